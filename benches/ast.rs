@@ -1,27 +1,25 @@
-#![feature(test)]
-
-extern crate bf;
-extern crate test;
-
 use bf::ast;
-use bf::test_helpers;
-use bf::traits::Interpretable;
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
 
-use test::Bencher;
+mod common;
 
-#[bench]
-fn parse_empty_program(b: &mut Bencher) {
-    b.iter(|| ast::parse_program(b"").unwrap())
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ast/parse");
+    group.bench_function("empty", |b| b.iter(|| ast::parse_program(b"").unwrap()));
+    for workload in &common::WORKLOADS {
+        group.throughput(Throughput::Bytes(workload.src.len() as u64));
+        group.bench_function(workload.name, |b| {
+            b.iter(|| ast::parse_program(workload.src).unwrap())
+        });
+    }
+    group.finish();
 }
 
-#[bench]
-fn parse_factor(b: &mut Bencher) {
-    b.iter(|| ast::parse_program(test_helpers::FACTOR_SRC).unwrap())
+fn interpret(c: &mut Criterion) {
+    common::bench_interpret(c, "ast/interpret", false, |program| {
+        Box::<ast::Program>::from(program)
+    });
 }
 
-#[bench]
-fn interpret_factor_million(b: &mut Bencher) {
-    let program = ast::parse_program(test_helpers::FACTOR_SRC).unwrap();
-
-    b.iter(|| program.interpret_memory(None, b"1000000\n").unwrap());
-}
+criterion_group!(benches, parse, interpret);
+criterion_main!(benches);
@@ -1,25 +1,27 @@
-#![feature(test)]
+use bf::bytecode::Encoding;
+use bf::traits::BytecodeCompilable;
+use criterion::{Criterion, criterion_group, criterion_main};
 
-extern crate bf;
-extern crate test;
+mod common;
 
-use bf::ast;
-use bf::test_helpers;
-use bf::traits::{BytecodeCompilable, Interpretable};
-
-use test::Bencher;
-
-#[bench]
-fn compile_factor(b: &mut Bencher) {
-    let program = ast::parse_program(test_helpers::FACTOR_SRC).unwrap();
-
-    b.iter(|| program.bytecode_compile());
+fn compile(c: &mut Criterion) {
+    for encoding in Encoding::ALL {
+        common::bench_compile(c, &format!("bytecode/{}/compile", encoding), |program| {
+            program.bytecode_compile_with(encoding)
+        });
+    }
 }
 
-#[bench]
-fn interpret_factor_million(b: &mut Bencher) {
-    let program = ast::parse_program(test_helpers::FACTOR_SRC).unwrap();
-    let program = program.bytecode_compile();
-
-    b.iter(|| program.interpret_memory(None, b"1000000\n").unwrap());
+fn interpret(c: &mut Criterion) {
+    for encoding in Encoding::ALL {
+        common::bench_interpret(
+            c,
+            &format!("bytecode/{}/interpret", encoding),
+            true,
+            |program| program.bytecode_compile_with(encoding),
+        );
+    }
 }
+
+criterion_group!(benches, compile, interpret);
+criterion_main!(benches);
@@ -0,0 +1,105 @@
+//! Workloads and measurement helpers shared by the benchmarks.
+
+// Each benchmark uses only some of the helpers.
+#![allow(dead_code)]
+
+use std::io::{self, Cursor};
+
+use bf::ast;
+use bf::counters::Counters;
+use bf::state::State;
+use bf::test_helpers;
+use bf::traits::*;
+use criterion::{Criterion, Throughput};
+
+/// A program and the input to run it on.
+pub struct Workload {
+    pub name: &'static str,
+    pub src: &'static [u8],
+    pub input: &'static [u8],
+    /// Whether the workload is too slow for the unoptimized interpreters.
+    pub heavy: bool,
+}
+
+pub const FACTOR: Workload = Workload {
+    name: "factor",
+    src: test_helpers::FACTOR_SRC,
+    input: b"1000000\n",
+    heavy: false,
+};
+
+pub const MANDELBROT: Workload = Workload {
+    name: "mandelbrot",
+    src: test_helpers::MANDELBROT_SRC,
+    input: b"",
+    heavy: true,
+};
+
+pub const WORKLOADS: [Workload; 2] = [FACTOR, MANDELBROT];
+
+/// The workloads that the given backend can get through in reasonable time.
+pub fn workloads(heavy: bool) -> impl Iterator<Item = &'static Workload> {
+    WORKLOADS
+        .iter()
+        .filter(move |workload| heavy || !workload.heavy)
+}
+
+pub fn parse(workload: &Workload) -> Box<ast::Program> {
+    ast::parse_program(workload.src).unwrap()
+}
+
+/// Benchmarks compiling each workload, with throughput in source bytes.
+pub fn bench_compile<P, F>(c: &mut Criterion, group: &str, compile: F)
+where
+    F: Fn(&ast::Program) -> P,
+{
+    let mut group = c.benchmark_group(group);
+    for workload in &WORKLOADS {
+        let program = parse(workload);
+        group.throughput(Throughput::Bytes(workload.src.len() as u64));
+        group.bench_function(workload.name, |b| b.iter(|| compile(&program)));
+    }
+    group.finish();
+}
+
+/// Benchmarks running each workload, with throughput in peephole instructions executed, so
+/// that the backends can be compared.
+pub fn bench_interpret<P, F>(c: &mut Criterion, group: &str, heavy: bool, compile: F)
+where
+    P: Interpretable,
+    F: Fn(&ast::Program) -> P,
+{
+    let mut group = c.benchmark_group(group);
+    for workload in workloads(heavy) {
+        let program = compile(&parse(workload));
+        group.throughput(Throughput::Elements(instructions(workload)));
+        if workload.heavy {
+            group.sample_size(10);
+        }
+        group.bench_function(workload.name, |b| {
+            let mut state = State::new();
+            b.iter(|| {
+                state.reset();
+                program
+                    .interpret_in(&mut state, Cursor::new(workload.input), io::sink())
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// The number of peephole instructions that running the workload executes.
+fn instructions(workload: &Workload) -> u64 {
+    let mut counters = Counters::new();
+    parse(workload)
+        .peephole_compile()
+        .interpret_counting(
+            &mut State::new(),
+            Cursor::new(workload.input),
+            io::sink(),
+            &mut counters,
+        )
+        .unwrap();
+    counters.instructions()
+}
@@ -1,24 +1,22 @@
-#![feature(test)]
-extern crate bf;
-extern crate test;
+use criterion::{Criterion, criterion_group, criterion_main};
 
 #[cfg(feature = "cranelift")]
-mod cranelift_only {
-    use bf::ast;
-    use bf::test_helpers;
-    use bf::traits::{CraneliftCompilable, Interpretable};
-    use test::Bencher;
+mod common;
 
-    #[bench]
-    fn compile_factor(b: &mut Bencher) {
-        let program = ast::parse_program(test_helpers::FACTOR_SRC).unwrap();
-        b.iter(|| program.cranelift_compile());
-    }
+#[cfg(feature = "cranelift")]
+fn cranelift(c: &mut Criterion) {
+    use bf::traits::CraneliftCompilable;
 
-    #[bench]
-    fn run_factor_million(b: &mut Bencher) {
-        let program = ast::parse_program(test_helpers::FACTOR_SRC).unwrap();
-        let program = program.cranelift_compile();
-        b.iter(|| program.interpret_memory(None, b"1000000\n").unwrap());
-    }
+    common::bench_compile(c, "cranelift/compile", |program| {
+        program.cranelift_compile()
+    });
+    common::bench_interpret(c, "cranelift/run", true, |program| {
+        program.cranelift_compile()
+    });
 }
+
+#[cfg(not(feature = "cranelift"))]
+fn cranelift(_c: &mut Criterion) {}
+
+criterion_group!(benches, cranelift);
+criterion_main!(benches);
@@ -0,0 +1,17 @@
+use bf::traits::FusedCompilable;
+use criterion::{Criterion, criterion_group, criterion_main};
+
+mod common;
+
+fn compile(c: &mut Criterion) {
+    common::bench_compile(c, "fused/compile", |program| program.fused_compile());
+}
+
+fn interpret(c: &mut Criterion) {
+    common::bench_interpret(c, "fused/interpret", true, |program| {
+        program.fused_compile()
+    });
+}
+
+criterion_group!(benches, compile, interpret);
+criterion_main!(benches);
@@ -1,44 +1,24 @@
-#![feature(test)]
-
-extern crate bf;
-extern crate test;
+use criterion::{Criterion, criterion_group, criterion_main};
 
 #[cfg(feature = "jit")]
-mod jit_only {
-    use bf::ast;
-
-    use bf::test_helpers;
-    use bf::traits::{Interpretable, JitCompilable};
-
-    use test::Bencher;
-
-    #[bench]
-    fn compile_factor(b: &mut Bencher) {
-        let program = ast::parse_program(test_helpers::FACTOR_SRC).unwrap();
-
-        b.iter(|| program.jit_compile(true));
-    }
-
-    #[bench]
-    fn compile_factor_unchecked(b: &mut Bencher) {
-        let program = ast::parse_program(test_helpers::FACTOR_SRC).unwrap();
+mod common;
 
-        b.iter(|| program.jit_compile(false));
-    }
-
-    #[bench]
-    fn run_factor_million(b: &mut Bencher) {
-        let program = ast::parse_program(test_helpers::FACTOR_SRC).unwrap();
-        let program = program.jit_compile(true);
-
-        b.iter(|| program.interpret_memory(None, b"1000000\n").unwrap());
-    }
+#[cfg(feature = "jit")]
+fn jit(c: &mut Criterion) {
+    use bf::traits::JitCompilable;
+
+    common::bench_compile(c, "jit/compile", |program| program.jit_compile(true));
+    common::bench_compile(c, "jit/compile_unchecked", |program| {
+        program.jit_compile(false)
+    });
+    common::bench_interpret(c, "jit/run", true, |program| program.jit_compile(true));
+    common::bench_interpret(c, "jit/run_unchecked", true, |program| {
+        program.jit_compile(false)
+    });
+}
 
-    #[bench]
-    fn run_factor_million_unchecked(b: &mut Bencher) {
-        let program = ast::parse_program(test_helpers::FACTOR_SRC).unwrap();
-        let program = program.jit_compile(false);
+#[cfg(not(feature = "jit"))]
+fn jit(_c: &mut Criterion) {}
 
-        b.iter(|| program.interpret_memory(None, b"1000000\n").unwrap());
-    }
-}
+criterion_group!(benches, jit);
+criterion_main!(benches);
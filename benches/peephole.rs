@@ -1,25 +1,17 @@
-#![feature(test)]
+use bf::traits::PeepholeCompilable;
+use criterion::{Criterion, criterion_group, criterion_main};
 
-extern crate bf;
-extern crate test;
+mod common;
 
-use bf::ast;
-use bf::test_helpers;
-use bf::traits::{Interpretable, PeepholeCompilable};
-
-use test::Bencher;
-
-#[bench]
-fn compile_factor(b: &mut Bencher) {
-    let program = ast::parse_program(test_helpers::FACTOR_SRC).unwrap();
-
-    b.iter(|| program.peephole_compile());
+fn compile(c: &mut Criterion) {
+    common::bench_compile(c, "peephole/compile", |program| program.peephole_compile());
 }
 
-#[bench]
-fn interpret_factor_million(b: &mut Bencher) {
-    let program = ast::parse_program(test_helpers::FACTOR_SRC).unwrap();
-    let program = program.peephole_compile();
-
-    b.iter(|| program.interpret_memory(None, b"1000000\n").unwrap());
+fn interpret(c: &mut Criterion) {
+    common::bench_interpret(c, "peephole/interpret", true, |program| {
+        program.peephole_compile()
+    });
 }
+
+criterion_group!(benches, compile, interpret);
+criterion_main!(benches);
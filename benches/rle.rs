@@ -1,27 +1,31 @@
-#![feature(test)]
+use bf::traits::RleCompilable;
+use bf::{ast, rle};
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
 
-extern crate bf;
-extern crate test;
+mod common;
 
-use bf::ast;
-use bf::test_helpers;
-use bf::traits::{Interpretable, RleCompilable};
-
-use test::Bencher;
-
-#[bench]
-fn compile_factor(b: &mut Bencher) {
-    let program = ast::parse_program(test_helpers::FACTOR_SRC).unwrap();
-
-    b.iter(|| {
-        program.rle_compile();
-    });
+fn compile(c: &mut Criterion) {
+    common::bench_compile(c, "rle/compile", |program| program.rle_compile());
 }
 
-#[bench]
-fn interpret_factor_million(b: &mut Bencher) {
-    let program = ast::parse_program(test_helpers::FACTOR_SRC).unwrap();
-    let program = program.rle_compile();
+/// Compares parsing to an AST and then encoding with parsing straight to run-length encoding.
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rle/parse");
+    for workload in &common::WORKLOADS {
+        group.throughput(Throughput::Bytes(workload.src.len() as u64));
+        group.bench_function(format!("{}/via-ast", workload.name), |b| {
+            b.iter(|| ast::parse_program(workload.src).unwrap().rle_compile())
+        });
+        group.bench_function(format!("{}/direct", workload.name), |b| {
+            b.iter(|| rle::parse_program(workload.src).unwrap())
+        });
+    }
+    group.finish();
+}
 
-    b.iter(|| program.interpret_memory(None, b"1000000\n").unwrap());
+fn interpret(c: &mut Criterion) {
+    common::bench_interpret(c, "rle/interpret", false, |program| program.rle_compile());
 }
+
+criterion_group!(benches, compile, parse, interpret);
+criterion_main!(benches);
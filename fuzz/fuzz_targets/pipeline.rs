@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// The first zero byte separates the program from its input.
+fuzz_target!(|data: &[u8]| {
+    let split = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    let (src, input) = data.split_at(split);
+    bf::fuzz::fuzz_pipeline(src, input.get(1..).unwrap_or(&[]));
+});
@@ -0,0 +1,56 @@
+/* C interface to the bf library. Build with:
+ *
+ *     cargo rustc --lib --release --features ffi --crate-type cdylib
+ */
+
+#ifndef BF_H
+#define BF_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Status codes. These values are stable. */
+#define BF_OK                0
+#define BF_UNMATCHED_BEGIN   1
+#define BF_UNMATCHED_END     2
+#define BF_POINTER_UNDERFLOW 3
+#define BF_POINTER_OVERFLOW  4
+#define BF_INVALID_ARGUMENT  5
+#define BF_INTERNAL_ERROR    6
+#define BF_NESTING_TOO_DEEP  7
+#define BF_STEP_LIMIT        8
+#define BF_OUTPUT_LIMIT      9
+#define BF_MEMORY_LIMIT      10
+#define BF_TIME_LIMIT        11
+#define BF_INTERRUPTED       12
+#define BF_CELL_OVERFLOW     13
+
+/* A compiled program. */
+typedef struct BfProgram BfProgram;
+
+/* Reads one byte, returning it, or a negative number at end of input. */
+typedef int (*bf_read_fn)(void *context);
+
+/* Writes one byte. */
+typedef void (*bf_write_fn)(void *context, uint8_t byte);
+
+/* Compiles `len` bytes of source, storing the program in `*out` (null on failure). */
+int bf_compile(const uint8_t *src, size_t len, BfProgram **out);
+
+/* Runs a program with `memory_size` bytes of memory. Null callbacks mean empty input and
+ * discarded output. */
+int bf_run(const BfProgram *program, size_t memory_size,
+           bf_read_fn read, bf_write_fn write, void *context);
+
+/* Frees a program. Does nothing if `program` is null. */
+void bf_free(BfProgram *program);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* BF_H */
@@ -2,6 +2,16 @@
 
 BIG_PRIME=179424691
 
+# With --json, print the timings of bfi's own backends as JSON, one object per line, instead.
+if [ "$1" = "--json" ]; then
+    rustup run nightly cargo build --release --features=jit
+    target/release/bfi bench --json bf/mandelbrot.bf
+    target/release/bfi bench --json bf/mandelbrot-quiet.bf
+    echo "$BIG_PRIME" > target/big-prime.in
+    target/release/bfi bench --json -i target/big-prime.in bf/factor.bf
+    exit 0
+fi
+
 make -C cpp
 
 rustup run nightly cargo build --release --features=jit
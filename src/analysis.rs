@@ -0,0 +1,716 @@
+//! Bounds-checking analysis.
+//!
+//! This is an abstract interpretation of the pointer position, which can prove that some pointer
+//! moves stay within memory. The [JIT](../jit/index.html) uses it to omit bounds checks from the
+//! generated code, and the [peephole](../peephole/index.html) and
+//! [bytecode](../bytecode/index.html) interpreters use it, via [`prove`](fn.prove.html) and
+//! [`prove_bytecode`](fn.prove_bytecode.html), to skip range checks in
+//! [`State`](../state/struct.State.html).
+
+use std::cmp;
+use std::collections::HashMap;
+use std::mem;
+
+use crate::bytecode;
+use crate::common::{Count, Instruction};
+use crate::peephole::{Program, Statement};
+use crate::traits::IntoUsize;
+
+/// Interface for bounds checking analysis.
+///
+/// We use two implementations of this trait to specialize the JIT compiler for checked versus
+/// unchecked mode. The [impl for checked mode](struct.AbstractInterpreter.html) actually does the
+/// analysis, whereas the [impl for unchecked mode](struct.NoAnalysis.html) is all no-ops.
+pub trait BoundsAnalysis {
+    /// Creates a new analyzer for the given program.
+    fn new(program: &Program) -> Self;
+
+    /// Moves the pointer the given distance to the left.
+    ///
+    /// Returns whether we can prove that this move will not underflow.
+    fn move_left(&mut self, count: Count) -> bool;
+
+    /// Moves the pointer the given distance to the right.
+    ///
+    /// Returns whether we can prove that this move will not overflow.
+    fn move_right(&mut self, count: Count) -> bool;
+
+    /// Checks whether the pointer can safely move the given distance to the left.
+    fn check_left(&self, count: Count) -> bool;
+
+    /// Checks whether the pointer can safely move the given distance to the right.
+    fn check_right(&self, count: Count) -> bool;
+
+    /// Records that a check showed that the pointer can move the given distance to the left,
+    /// without moving it.
+    fn assume_left(&mut self, count: Count);
+
+    /// Records that a check showed that the pointer can move the given distance to the right,
+    /// without moving it.
+    fn assume_right(&mut self, count: Count);
+
+    /// Updates the abstraction for a `FindZeroLeft` scan with the given stride.
+    ///
+    /// Every step of the scan is checked, so this only needs to account for the pointer moving
+    /// an arbitrary distance to the left.
+    fn find_zero_left(&mut self, skip: Count);
+
+    /// Updates the abstraction for a `FindZeroRight` scan with the given stride.
+    ///
+    /// Every step of the scan is checked, so this only needs to account for the pointer moving
+    /// an arbitrary distance to the right.
+    fn find_zero_right(&mut self, skip: Count);
+
+    /// Updates the abstraction upon entering a loop.
+    fn enter_loop(&mut self, body: &[Statement]);
+
+    /// Updates the abstraction upon leaving a loop.
+    fn leave_loop(&mut self);
+}
+
+/// An abstraction of the pointer position.
+///
+/// We take the pointer to start at address 0, so we can track an interval of addresses that it
+/// may occupy. Because the size of memory isn’t known until run time, proving that a move to
+/// the right stays in bounds relies on earlier checks: every check that succeeds shows that
+/// memory is at least a given size, and we remember the number of cells known to be available
+/// to the right of the pointer as a result.
+///
+/// On a state whose pointer was [set](../state/struct.State.html#method.set_pointer), the pointer
+/// really starts further right, and the proofs still hold. That only leaves more room on the left
+/// than the lower bound says, and memory is then bigger than the proven size by at least as much as
+/// the pointer is past the upper bound.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct Interval {
+    /// The least address the pointer may have.
+    low: usize,
+    /// The greatest address the pointer may have, or `None` if unbounded.
+    high: Option<usize>,
+    /// The number of cells that exist beyond the pointer, as proven by earlier checks.
+    right_mark: usize,
+    /// The number of cells that memory is proven to have.
+    proven_size: usize,
+}
+
+impl Interval {
+    /// The abstraction of the pointer at program start.
+    pub fn start() -> Self {
+        Interval {
+            low: 0,
+            high: Some(0),
+            right_mark: 0,
+            proven_size: 0,
+        }
+    }
+
+    /// The greatest address the pointer may have, or `None` if unbounded.
+    pub fn high(self) -> Option<usize> {
+        self.high
+    }
+
+    /// Can we prove that a move of the given distance to the left stays in bounds?
+    pub fn proves_left(self, count: usize) -> bool {
+        count <= self.low
+    }
+
+    /// Can we prove that a move of the given distance to the right stays in bounds?
+    pub fn proves_right(self, count: usize) -> bool {
+        count <= self.right_mark
+    }
+
+    /// Can we prove that a move of the given distance to the left goes out of bounds?
+    pub fn disproves_left(self, count: usize) -> bool {
+        self.high.is_some_and(|high| high < count)
+    }
+
+    /// Moves the pointer to the left, returning whether the move was proved in bounds.
+    ///
+    /// If it wasn’t proved, we assume that a check is emitted, so execution only continues if
+    /// the move was in bounds.
+    pub fn move_left(&mut self, count: usize) -> bool {
+        let proved = self.proves_left(count);
+
+        self.low = self.low.saturating_sub(count);
+        self.high = self.high.map(|high| high.saturating_sub(count));
+        self.right_mark = self.right_mark.saturating_add(count);
+        self.normalize();
+
+        proved
+    }
+
+    /// Moves the pointer to the right, returning whether the move was proved in bounds.
+    ///
+    /// If it wasn’t proved, we assume that a check is emitted, so execution only continues if
+    /// the move was in bounds.
+    pub fn move_right(&mut self, count: usize) -> bool {
+        let proved = self.proves_right(count);
+
+        self.low = self.low.saturating_add(count);
+        self.high = self.high.and_then(|high| high.checked_add(count));
+
+        if proved {
+            self.right_mark -= count;
+        } else {
+            self.right_mark = 0;
+            self.proven_size = cmp::max(self.proven_size, self.low.saturating_add(1));
+        }
+
+        self.normalize();
+
+        proved
+    }
+
+    /// Records that a check showed that a move of the given distance to the left would stay in
+    /// bounds.
+    pub fn assume_left(&mut self, count: usize) {
+        self.low = cmp::max(self.low, count);
+        if self.high.is_some_and(|high| high < self.low) {
+            self.high = Some(self.low);
+        }
+        self.normalize();
+    }
+
+    /// Records that a check showed that a move of the given distance to the right would stay in
+    /// bounds.
+    pub fn assume_right(&mut self, count: usize) {
+        self.right_mark = cmp::max(self.right_mark, count);
+        self.proven_size = cmp::max(
+            self.proven_size,
+            self.low.saturating_add(count).saturating_add(1),
+        );
+    }
+
+    /// Scans to the left, by checked steps of the given size, for a zero.
+    pub fn find_zero_left(&mut self, skip: usize) {
+        let mut after = *self;
+        after.move_left(skip);
+        after.low = 0;
+        *self = self.join(after);
+    }
+
+    /// Scans to the right, by checked steps of the given size, for a zero.
+    pub fn find_zero_right(&mut self, skip: usize) {
+        let mut after = *self;
+        after.move_right(skip);
+        after.high = None;
+        after.right_mark = 0;
+        *self = self.join(after);
+    }
+
+    /// Switches to another tape, where the pointer may be anywhere.
+    ///
+    /// Every tape has the same size, so the proven size carries over.
+    pub fn switch_tape(&mut self) {
+        self.low = 0;
+        self.high = None;
+        self.right_mark = 0;
+    }
+
+    /// The least upper bound of two abstractions, as when control flow merges.
+    pub fn join(self, other: Self) -> Self {
+        let high = match (self.high, other.high) {
+            (Some(a), Some(b)) => Some(cmp::max(a, b)),
+            _ => None,
+        };
+
+        Interval {
+            low: cmp::min(self.low, other.low),
+            high,
+            right_mark: cmp::min(self.right_mark, other.right_mark),
+            proven_size: cmp::min(self.proven_size, other.proven_size),
+        }
+    }
+
+    /// Extrapolates from `self` to `next`, to ensure that loop analysis terminates.
+    ///
+    /// Any bound that got weaker from one iteration to the next goes directly to its weakest
+    /// value, so each bound can change at most once.
+    pub fn widen(self, next: Self) -> Self {
+        let next = self.join(next);
+
+        Interval {
+            low: if next.low < self.low { 0 } else { self.low },
+            high: if next.high != self.high {
+                None
+            } else {
+                self.high
+            },
+            right_mark: if next.right_mark < self.right_mark {
+                0
+            } else {
+                self.right_mark
+            },
+            proven_size: if next.proven_size < self.proven_size {
+                0
+            } else {
+                self.proven_size
+            },
+        }
+    }
+
+    /// Re-derives the right mark from the upper bound and the proven size.
+    fn normalize(&mut self) {
+        if let Some(high) = self.high {
+            let from_size = self.proven_size.saturating_sub(high).saturating_sub(1);
+            self.right_mark = cmp::max(self.right_mark, from_size);
+        }
+    }
+}
+
+/// An index to a loop.
+///
+/// This is represented as the address of the first instruction of the loop body.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+struct LoopIndex(usize);
+
+impl LoopIndex {
+    /// Gets the loop index from a loop body.
+    fn from_loop_body<T>(body: &[T]) -> Self {
+        LoopIndex(body.as_ptr() as usize)
+    }
+}
+
+/// Abstract interpreter that tracks an [`Interval`](struct.Interval.html) abstraction of the
+/// pointer position.
+///
+/// This can be used to prove some bounds checks unnecessary. Loops are handled by iterating the
+/// analysis of the loop body, with widening, until it reaches a fixed point.
+#[derive(Debug)]
+pub struct AbstractInterpreter {
+    /// The current abstraction of the pointer.
+    current: Interval,
+    /// The loop invariants to restore when leaving a loop.
+    loop_stack: Vec<Interval>,
+    /// Cached loop invariants, by loop and entry abstraction.
+    invariants: HashMap<(LoopIndex, Interval), Interval>,
+}
+
+impl AbstractInterpreter {
+    /// Updates the abstraction for a single non-loop instruction.
+    ///
+    /// Returns whether any bounds check the instruction requires is proved unnecessary.
+    fn step(&mut self, instruction: &Instruction) -> bool {
+        use crate::common::Instruction::*;
+
+        match *instruction {
+            Right(count) => self.current.move_right(count.into_usize()),
+            Left(count) => self.current.move_left(count.into_usize()),
+            OffsetAddRight(offset) => self.current.proves_right(offset.into_usize()),
+            OffsetAddLeft(offset) => self.current.proves_left(offset.into_usize()),
+            MulAddRight(offset, _) => self.current.proves_right(offset.into_usize()),
+            MulAddLeft(offset, _) => self.current.proves_left(offset.into_usize()),
+            AddAt(offset, _) | SetAt(offset, _) if offset < 0 => {
+                self.current.proves_left(offset.unsigned_abs())
+            }
+            AddAt(offset, _) | SetAt(offset, _) => self.current.proves_right(offset.unsigned_abs()),
+            FindZeroRight(skip) => {
+                self.current.find_zero_right(skip.into_usize());
+                false
+            }
+            FindZeroLeft(skip) => {
+                self.current.find_zero_left(skip.into_usize());
+                false
+            }
+            SwitchTape(_) => {
+                self.current.switch_tape();
+                false
+            }
+            Add(_) | In | Out | WriteStr(_) | SetZero | SetConst(_) => false,
+            JumpZero(_) | JumpNotZero(_) => panic!("unexpected jump instruction"),
+        }
+    }
+
+    /// Computes the invariant that holds at the head of a loop, given the abstraction on entry
+    /// to the loop and a function that analyzes the loop body.
+    ///
+    /// This is also the abstraction upon leaving the loop, since the loop can only exit from its
+    /// head.
+    fn loop_invariant<F>(&mut self, index: LoopIndex, entry: Interval, mut body: F) -> Interval
+    where
+        F: FnMut(&mut Self, Interval) -> Interval,
+    {
+        let key = (index, entry);
+        if let Some(&invariant) = self.invariants.get(&key) {
+            return invariant;
+        }
+
+        let mut invariant = entry;
+        loop {
+            let after = body(self, invariant);
+            let next = invariant.widen(after);
+            if next == invariant {
+                break;
+            }
+            invariant = next;
+        }
+
+        self.invariants.insert(key, invariant);
+        invariant
+    }
+
+    /// Analyzes a sequence of statements, starting from the given abstraction.
+    fn transfer(&mut self, entry: Interval, body: &[Statement]) -> Interval {
+        let saved = mem::replace(&mut self.current, entry);
+
+        for statement in body {
+            match *statement {
+                Statement::Instr(ref instruction) => {
+                    self.step(instruction);
+                }
+
+                Statement::Loop(ref body) => {
+                    let current = self.current;
+                    self.current = self.loop_invariant(
+                        LoopIndex::from_loop_body(body),
+                        current,
+                        |this, entry| this.transfer(entry, body),
+                    );
+                }
+            }
+        }
+
+        mem::replace(&mut self.current, saved)
+    }
+
+    /// Analyzes the bytecode from address `begin` up to (but not including) `end`, starting from
+    /// the given abstraction.
+    fn transfer_bytecode(
+        &mut self,
+        entry: Interval,
+        program: &[Instruction],
+        begin: usize,
+        end: usize,
+    ) -> Interval {
+        let saved = mem::replace(&mut self.current, entry);
+        let mut pc = begin;
+
+        while pc < end {
+            match program[pc] {
+                Instruction::JumpZero(target) => {
+                    let target = target.into_usize();
+                    let current = self.current;
+                    self.current = self.bytecode_loop_invariant(current, program, pc, target);
+                    pc = target;
+                }
+
+                Instruction::JumpNotZero(_) => panic!("unmatched jump instruction"),
+
+                ref instruction => {
+                    self.step(instruction);
+                }
+            }
+
+            pc += 1;
+        }
+
+        mem::replace(&mut self.current, saved)
+    }
+
+    /// Computes the invariant of the bytecode loop from the `JumpZero` at `begin` to the
+    /// `JumpNotZero` at `end`.
+    fn bytecode_loop_invariant(
+        &mut self,
+        entry: Interval,
+        program: &[Instruction],
+        begin: usize,
+        end: usize,
+    ) -> Interval {
+        let index = LoopIndex::from_loop_body(&program[begin + 1..end]);
+        self.loop_invariant(index, entry, |this, entry| {
+            this.transfer_bytecode(entry, program, begin + 1, end)
+        })
+    }
+}
+
+impl BoundsAnalysis for AbstractInterpreter {
+    fn new(_program: &Program) -> Self {
+        AbstractInterpreter {
+            current: Interval::start(),
+            loop_stack: Vec::new(),
+            invariants: HashMap::new(),
+        }
+    }
+
+    fn move_left(&mut self, count: Count) -> bool {
+        self.current.move_left(count.into_usize())
+    }
+
+    fn move_right(&mut self, count: Count) -> bool {
+        self.current.move_right(count.into_usize())
+    }
+
+    fn check_left(&self, count: Count) -> bool {
+        self.current.proves_left(count.into_usize())
+    }
+
+    fn check_right(&self, count: Count) -> bool {
+        self.current.proves_right(count.into_usize())
+    }
+
+    fn assume_left(&mut self, count: Count) {
+        self.current.assume_left(count.into_usize());
+    }
+
+    fn assume_right(&mut self, count: Count) {
+        self.current.assume_right(count.into_usize());
+    }
+
+    fn find_zero_left(&mut self, skip: Count) {
+        self.current.find_zero_left(skip.into_usize());
+    }
+
+    fn find_zero_right(&mut self, skip: Count) {
+        self.current.find_zero_right(skip.into_usize());
+    }
+
+    fn enter_loop(&mut self, body: &[Statement]) {
+        let current = self.current;
+        let invariant =
+            self.loop_invariant(LoopIndex::from_loop_body(body), current, |this, entry| {
+                this.transfer(entry, body)
+            });
+        self.current = invariant;
+        self.loop_stack.push(invariant);
+    }
+
+    fn leave_loop(&mut self) {
+        self.current = self
+            .loop_stack
+            .pop()
+            .expect("got exit_loop without matching enter_loop");
+    }
+}
+
+/// Whether the bounds checks of a peephole statement were proved unnecessary.
+///
+/// A slice of these has the same shape as the program it was computed for, so interpreters can
+/// walk the two together.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Proof {
+    /// For a non-loop statement, whether its bounds check (if any) was proved unnecessary.
+    Instr(bool),
+    /// The proofs for the body of a loop.
+    Loop(Box<[Proof]>),
+}
+
+/// Determines which bounds checks in a peephole program are unnecessary.
+pub fn prove(program: &Program) -> Box<[Proof]> {
+    let mut analysis = AbstractInterpreter::new(program);
+    prove_statements(&mut analysis, program)
+}
+
+fn prove_statements(analysis: &mut AbstractInterpreter, program: &[Statement]) -> Box<[Proof]> {
+    let mut result = Vec::with_capacity(program.len());
+
+    for statement in program {
+        match *statement {
+            Statement::Instr(ref instruction) => {
+                result.push(Proof::Instr(analysis.step(instruction)))
+            }
+            Statement::Loop(ref body) => {
+                analysis.enter_loop(body);
+                result.push(Proof::Loop(prove_statements(analysis, body)));
+                analysis.leave_loop();
+            }
+        }
+    }
+
+    result.into_boxed_slice()
+}
+
+/// Determines which bounds checks in a bytecode program are unnecessary.
+///
+/// The result is indexed by instruction address.
+pub fn prove_bytecode(program: &bytecode::Program) -> Box<[bool]> {
+    let mut analysis = AbstractInterpreter::new(&[]);
+    let mut result = Vec::with_capacity(program.len());
+
+    for (pc, instruction) in program.iter().enumerate() {
+        match *instruction {
+            Instruction::JumpZero(end) => {
+                let current = analysis.current;
+                let invariant =
+                    analysis.bytecode_loop_invariant(current, program, pc, end.into_usize());
+                analysis.current = invariant;
+                analysis.loop_stack.push(invariant);
+                result.push(false);
+            }
+            Instruction::JumpNotZero(_) => {
+                analysis.leave_loop();
+                result.push(false);
+            }
+            ref instruction => result.push(analysis.step(instruction)),
+        }
+    }
+
+    result.into_boxed_slice()
+}
+
+/// No-op implementation of `BoundsAnalysis`.
+///
+/// Tracks no information, and returns `false` (not proved) for moves.
+pub struct NoAnalysis;
+
+impl BoundsAnalysis for NoAnalysis {
+    fn new(_program: &Program) -> Self {
+        NoAnalysis
+    }
+    fn move_left(&mut self, _count: Count) -> bool {
+        false
+    }
+    fn move_right(&mut self, _count: Count) -> bool {
+        false
+    }
+    fn check_left(&self, _count: Count) -> bool {
+        false
+    }
+    fn check_right(&self, _count: Count) -> bool {
+        false
+    }
+    fn assume_left(&mut self, _count: Count) {}
+    fn assume_right(&mut self, _count: Count) {}
+    fn find_zero_left(&mut self, _skip: Count) {}
+    fn find_zero_right(&mut self, _skip: Count) {}
+    fn enter_loop(&mut self, _body: &[Statement]) {}
+    fn leave_loop(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moving_back_left_is_proved() {
+        let mut interval = Interval::start();
+        assert!(!interval.move_right(5));
+        assert!(interval.move_left(3));
+        assert!(interval.move_right(3));
+        assert!(!interval.move_left(6));
+    }
+
+    #[test]
+    fn earlier_checks_prove_later_moves() {
+        let mut interval = Interval::start();
+        assert!(!interval.move_right(10));
+        assert!(interval.move_left(10));
+        assert!(interval.move_right(4));
+        assert!(interval.move_right(6));
+        assert!(!interval.move_right(1));
+    }
+
+    #[test]
+    fn assumed_checks_prove_later_moves() {
+        let mut interval = Interval::start();
+        interval.assume_right(6);
+        assert!(interval.move_right(2));
+        assert!(interval.move_right(4));
+        assert!(!interval.move_right(1));
+        interval.assume_left(7);
+        assert!(interval.move_left(3));
+        assert!(interval.move_left(4));
+        assert!(!interval.move_left(1));
+    }
+
+    #[test]
+    fn find_zero_right_keeps_left_bound() {
+        let mut interval = Interval::start();
+        interval.move_right(4);
+        interval.find_zero_right(1);
+        assert!(interval.proves_left(4));
+        assert!(!interval.proves_right(1));
+    }
+
+    #[test]
+    fn find_zero_left_keeps_right_bound() {
+        let mut interval = Interval::start();
+        interval.move_right(4);
+        interval.move_left(2);
+        interval.find_zero_left(1);
+        assert!(interval.proves_right(2));
+        assert!(!interval.proves_left(1));
+    }
+
+    #[test]
+    fn balanced_loop_keeps_bounds() {
+        let program = peephole("[->>>>>+<<<<<-]");
+        let mut analysis = AbstractInterpreter::new(&program);
+
+        analysis.move_right(5);
+        analysis.move_left(5);
+
+        if let Statement::Loop(ref body) = program[0] {
+            analysis.enter_loop(body);
+            assert!(analysis.check_right(5));
+            analysis.leave_loop();
+        } else {
+            panic!("expected a loop");
+        }
+
+        assert!(analysis.check_right(5));
+    }
+
+    #[test]
+    fn right_moving_loop_widens() {
+        let program = peephole("[>+]");
+        let mut analysis = AbstractInterpreter::new(&program);
+
+        analysis.move_right(3);
+        analysis.move_left(3);
+
+        if let Statement::Loop(ref body) = program[0] {
+            analysis.enter_loop(body);
+            assert!(!analysis.check_right(1));
+            analysis.leave_loop();
+        } else {
+            panic!("expected a loop");
+        }
+
+        assert!(!analysis.check_right(1));
+    }
+
+    #[test]
+    fn prove_matches_program_shape() {
+        let program = peephole(">>[-<+>-]<<");
+        let proofs = prove(&program);
+
+        assert_eq!(
+            &*proofs,
+            &[
+                Proof::Instr(false),
+                Proof::Loop(
+                    vec![
+                        Proof::Instr(false),
+                        Proof::Instr(true),
+                        Proof::Instr(false),
+                        Proof::Instr(true),
+                        Proof::Instr(false),
+                    ]
+                    .into_boxed_slice()
+                ),
+                Proof::Instr(true),
+            ]
+        );
+    }
+
+    #[test]
+    fn prove_bytecode_agrees_with_prove() {
+        let program = peephole(">>[-<+>-]<<");
+        let bytecode = crate::bytecode::compile(&program);
+
+        assert_eq!(
+            &*prove_bytecode(&bytecode),
+            &[false, false, false, true, false, true, false, false, true]
+        );
+    }
+
+    /// Compiles without dead code elimination, which would remove the loops that these programs
+    /// start with, or folding moves, which would remove the moves that they check.
+    fn peephole(src: &str) -> Box<Program> {
+        let program = crate::ast::parse_program(src.as_bytes()).unwrap();
+        let program = crate::rle::compile(&program);
+        let mut passes = crate::pass::PassManager::standard();
+        passes.remove("dead-code");
+        passes.remove("fold-moves");
+        passes.compile(&program).unwrap()
+    }
+}
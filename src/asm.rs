@@ -0,0 +1,656 @@
+//! Textual x86-64 assembly, for reading what a program compiles to or for building it without
+//! the JIT.
+//!
+//! [`emit`](fn.emit.html) renders the peephole AST with the lowering that the
+//! [JIT](../jit/index.html) uses: the same registers, with the pointer in `r12` and the ends of
+//! the memory in `r13` and `r14`, the same instruction selection, and the same bounds checks
+//! before each move. It keeps each cell in memory rather than caching it in a register, and
+//! checks every move rather than only those that it can't prove safe. The output is a whole
+//! program for Linux, with a small runtime that does the I/O with system calls, buffering the
+//! output, and stops with a message and status 3 if the pointer leaves the memory. At the end
+//! of input, `,` stores 0.
+//!
+//! Either assembler will do:
+//!
+//! ```text
+//! bfi --emit-asm=gas hello.b > hello.s && as hello.s -o hello.o && ld hello.o -o hello
+//! bfi --emit-asm=nasm hello.b > hello.asm && nasm -f elf64 hello.asm && ld hello.o -o hello
+//! ```
+//!
+//! ```
+//! use bf::asm::{self, Syntax};
+//! use bf::ast;
+//! use bf::traits::*;
+//!
+//! let program = ast::parse_program(b"+[->+<]").unwrap().peephole_compile();
+//! let text = asm::emit(&program, Syntax::Nasm, 30_000);
+//! assert!(text.contains("global _start"));
+//! assert!(text.contains("resb 30000"));
+//! ```
+
+use std::fmt::{self, Write};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::common::{Error, Instruction};
+use crate::peephole;
+
+/// The size of the output buffer, in bytes.
+const OUTPUT_BUFFER: usize = 4096;
+
+/// The assembler that the text is for. Both take Intel syntax.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub enum Syntax {
+    /// The GNU assembler, `as`, with `.intel_syntax noprefix`.
+    #[default]
+    Gas,
+    /// The Netwide Assembler, `nasm`.
+    Nasm,
+}
+
+impl Syntax {
+    /// All the syntaxes.
+    pub const ALL: [Syntax; 2] = [Syntax::Gas, Syntax::Nasm];
+
+    /// The name of the syntax, as accepted by `from_str`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Syntax::Gas => "gas",
+            Syntax::Nasm => "nasm",
+        }
+    }
+
+    /// What comes before a memory operand of the given size, such as `byte`.
+    fn size(self, size: &str) -> String {
+        match self {
+            Syntax::Gas => format!("{} ptr", size),
+            Syntax::Nasm => size.to_owned(),
+        }
+    }
+
+    /// The address of a label, relative to the instruction pointer.
+    fn rel(self, label: &str) -> String {
+        match self {
+            Syntax::Gas => format!("[rip + {}]", label),
+            Syntax::Nasm => format!("[rel {}]", label),
+        }
+    }
+
+    fn comment(self) -> &'static str {
+        match self {
+            Syntax::Gas => "#",
+            Syntax::Nasm => ";",
+        }
+    }
+}
+
+impl fmt::Display for Syntax {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Syntax {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Syntax::ALL
+            .iter()
+            .copied()
+            .find(|syntax| syntax.name() == s)
+            .ok_or_else(|| format!("unknown assembly syntax: {}", s))
+    }
+}
+
+/// Renders the program as assembly for the given assembler, as a program whose memory is
+/// `memory_size` cells.
+///
+/// # Panics
+///
+/// Panics if the program contains jump instructions, which the peephole AST doesn't.
+pub fn emit(program: &peephole::Program, syntax: Syntax, memory_size: usize) -> String {
+    let mut emitter = Emitter {
+        syntax,
+        text: String::new(),
+        labels: 0,
+        strings: Vec::new(),
+    };
+    emitter.emit_program(program, memory_size);
+    emitter.text
+}
+
+/// The text so far, and what it still needs.
+struct Emitter {
+    syntax: Syntax,
+    text: String,
+    /// The number of labels made so far.
+    labels: usize,
+    /// The bytes of each `WriteStr`, which go in read-only data at the end.
+    strings: Vec<Arc<[u8]>>,
+}
+
+impl Emitter {
+    fn emit_program(&mut self, program: &peephole::Program, memory_size: usize) {
+        match self.syntax {
+            Syntax::Gas => self.line(".intel_syntax noprefix"),
+            Syntax::Nasm => self.line("bits 64"),
+        }
+        self.blank();
+        self.section(".text");
+        self.global("_start");
+        self.label("_start");
+        self.op(&format!("lea r12, {}", self.syntax.rel("bf_memory")));
+        self.op("mov r13, r12");
+        self.op(&format!("mov r14, {}", memory_size));
+        self.op("add r14, r12");
+        self.op("xor r15d, r15d");
+        self.blank();
+
+        self.compile(program);
+
+        self.blank();
+        self.op("call bf_flush");
+        self.op("xor edi, edi");
+        self.op("mov eax, 60");
+        self.op("syscall");
+
+        self.emit_runtime();
+        self.emit_data(memory_size);
+    }
+
+    fn compile(&mut self, program: &[peephole::Statement]) {
+        for stm in program {
+            self.compile_statement(stm);
+        }
+    }
+
+    fn compile_statement(&mut self, stm: &peephole::Statement) {
+        use crate::common::Instruction::*;
+        use peephole::Statement::*;
+
+        let instruction = match *stm {
+            Instr(ref instruction) => instruction,
+            Loop(ref body) => {
+                let begin = self.new_label();
+                let end = self.new_label();
+                self.op(&format!("jmp {}", end));
+                self.label(&begin);
+                self.compile(body);
+                self.label(&end);
+                let cell = self.cell("");
+                self.op(&format!("cmp {}, 0", cell));
+                self.op(&format!("jnz {}", begin));
+                return;
+            }
+        };
+
+        self.comment(&describe(instruction));
+        let cell = self.cell("");
+        match *instruction {
+            Right(count) => {
+                self.load_pos_offset(count as u64);
+                self.op("add r12, rax");
+            }
+
+            Left(count) => {
+                self.load_neg_offset(count as u64);
+                self.op("sub r12, rax");
+            }
+
+            Add(count) => self.op(&format!("add {}, {}", cell, count)),
+
+            In => self.op("call bf_read"),
+
+            Out => {
+                self.op(&format!("movzx edi, {}", cell));
+                self.op("call bf_write");
+            }
+
+            WriteStr(ref bytes) => {
+                let label = format!("bf_str_{}", self.strings.len());
+                self.strings.push(bytes.clone());
+                self.op(&format!("lea rsi, {}", self.syntax.rel(&label)));
+                self.op(&format!("mov rdx, {}", bytes.len()));
+                self.op("call bf_write_str");
+            }
+
+            // The program runs with a single tape, where switching does nothing.
+            SwitchTape(_) => (),
+
+            SetZero => self.op(&format!("mov {}, 0", cell)),
+
+            SetConst(value) => self.op(&format!("mov {}, {}", cell, value)),
+
+            FindZeroRight(1) => self.scan("bf_find_zero_right"),
+
+            FindZeroLeft(1) => self.scan("bf_find_zero_left"),
+
+            FindZeroRight(skip) => self.find_zero(skip as u64, true),
+
+            FindZeroLeft(skip) => self.find_zero(skip as u64, false),
+
+            OffsetAddRight(offset) => self.offset_add(offset as u64, true),
+
+            OffsetAddLeft(offset) => self.offset_add(offset as u64, false),
+
+            MulAddRight(offset, factor) => self.mul_add(offset as isize, factor),
+
+            MulAddLeft(offset, factor) => self.mul_add(-(offset as isize), factor),
+
+            AddAt(offset, value) => {
+                let target = self.check_offset(offset);
+                self.op(&format!("add {}, {}", target, value));
+            }
+
+            SetAt(offset, value) => {
+                let target = self.check_offset(offset);
+                self.op(&format!("mov {}, {}", target, value));
+            }
+
+            JumpZero(_) | JumpNotZero(_) => panic!("unexpected jump instruction"),
+        }
+    }
+
+    /// Calls a runtime function that scans by single steps, unless the cell is already zero.
+    fn scan(&mut self, function: &str) {
+        let skip = self.new_label();
+        let cell = self.cell("");
+        self.op(&format!("cmp {}, 0", cell));
+        self.op(&format!("jz {}", skip));
+        self.op(&format!("call {}", function));
+        self.label(&skip);
+    }
+
+    /// Moves by steps of `skip` until the cell is zero.
+    fn find_zero(&mut self, skip: u64, right: bool) {
+        let begin = self.new_label();
+        let end = self.new_label();
+        self.op(&format!("jmp {}", end));
+        self.label(&begin);
+        if right {
+            self.load_pos_offset(skip);
+            self.op("add r12, rax");
+        } else {
+            self.load_neg_offset(skip);
+            self.op("sub r12, rax");
+        }
+        self.label(&end);
+        let cell = self.cell("");
+        self.op(&format!("cmp {}, 0", cell));
+        self.op(&format!("jnz {}", begin));
+    }
+
+    /// Moves the cell at the pointer to the cell at `offset` from it, unless it is zero.
+    fn offset_add(&mut self, offset: u64, right: bool) {
+        let skip = self.new_label();
+        let cell = self.cell("");
+        self.op(&format!("cmp {}, 0", cell));
+        self.op(&format!("jz {}", skip));
+        if right {
+            self.load_pos_offset(offset);
+        } else {
+            self.load_neg_offset(offset);
+        }
+        self.op(&format!("mov cl, {}", cell));
+        self.op(&format!("mov {}, 0", cell));
+        if !right {
+            self.op("neg rax");
+        }
+        let target = self.cell(" + rax");
+        self.op(&format!("add {}, cl", target));
+        self.label(&skip);
+    }
+
+    /// Adds the cell at the pointer times `factor` to the cell at `offset` from it, unless the
+    /// cell is zero.
+    fn mul_add(&mut self, offset: isize, factor: u8) {
+        let skip = self.new_label();
+        let cell = self.cell("");
+        self.op(&format!("movzx ecx, {}", cell));
+        self.op("test ecx, ecx");
+        self.op(&format!("jz {}", skip));
+        let target = self.check_offset(offset);
+        self.multiply_ecx(factor);
+        self.op(&format!("add {}, cl", target));
+        self.label(&skip);
+    }
+
+    /// Multiplies `ecx` by `factor`, modulo 256 in `cl`, as the JIT does.
+    fn multiply_ecx(&mut self, factor: u8) {
+        match factor {
+            1 => (),
+            255 => self.op("neg ecx"),
+            3 => self.op("lea ecx, [rcx + rcx * 2]"),
+            5 => self.op("lea ecx, [rcx + rcx * 4]"),
+            9 => self.op("lea ecx, [rcx + rcx * 8]"),
+            _ if factor.is_power_of_two() => {
+                self.op(&format!("shl ecx, {}", factor.trailing_zeros()))
+            }
+            _ => self.op(&format!("imul ecx, ecx, {}", factor)),
+        }
+    }
+
+    /// Checks the offset of an `AddAt`, `SetAt`, or `MulAdd`, and returns the operand for the
+    /// cell there, with the offset as a displacement if it fits in one, and otherwise in `rax`.
+    fn check_offset(&mut self, offset: isize) -> String {
+        let distance = offset.unsigned_abs() as u64;
+        if offset < 0 {
+            self.load_neg_offset(distance);
+        } else {
+            self.load_pos_offset(distance);
+        }
+        match i32::try_from(offset) {
+            Ok(disp) if disp < 0 => self.cell(&format!(" - {}", disp.unsigned_abs())),
+            Ok(disp) => self.cell(&format!(" + {}", disp)),
+            Err(_) => {
+                if offset < 0 {
+                    self.op("neg rax");
+                }
+                self.cell(" + rax")
+            }
+        }
+    }
+
+    /// Loads `offset` into `rax`, and checks that the pointer can move that far right. The check
+    /// uses `rdx`, leaving `rcx` for the cell's value.
+    fn load_pos_offset(&mut self, offset: u64) {
+        self.op(&format!("mov rax, {}", offset));
+        self.op("mov rdx, r14");
+        self.op("sub rdx, r12");
+        self.op("cmp rdx, rax");
+        self.op("jle bf_overflow");
+    }
+
+    /// Loads `offset` into `rax`, and checks that the pointer can move that far left.
+    fn load_neg_offset(&mut self, offset: u64) {
+        self.op(&format!("mov rax, {}", offset));
+        self.op("mov rdx, r12");
+        self.op("sub rdx, r13");
+        self.op("cmp rdx, rax");
+        self.op("jl bf_underflow");
+    }
+
+    /// The functions that the program calls, which keep `r12` to `r15` as they find them,
+    /// except those that move the pointer. `r15` holds the number of bytes in the output buffer.
+    fn emit_runtime(&mut self) {
+        let byte = self.syntax.size("byte");
+        let buffer = self.syntax.rel("bf_output");
+
+        self.blank();
+        self.comment("Reads a byte into the cell, or stores 0 at the end of input.");
+        self.label("bf_read");
+        self.op("call bf_flush");
+        self.op("xor eax, eax");
+        self.op("xor edi, edi");
+        self.op("mov rsi, r12");
+        self.op("mov edx, 1");
+        self.op("syscall");
+        self.op("cmp rax, 1");
+        self.op("je bf_read_done");
+        self.op(&format!("mov {} [r12], 0", byte));
+        self.label("bf_read_done");
+        self.op("ret");
+
+        self.blank();
+        self.comment("Writes the byte in dil.");
+        self.label("bf_write");
+        self.op(&format!("lea rax, {}", buffer));
+        self.op(&format!("mov {} [rax + r15], dil", byte));
+        self.op("inc r15");
+        self.op(&format!("cmp r15, {}", OUTPUT_BUFFER));
+        self.op("je bf_flush");
+        self.op("ret");
+
+        self.blank();
+        self.comment("Writes the rdx bytes at rsi.");
+        self.label("bf_write_str");
+        self.op("push rbx");
+        self.op("push rbp");
+        self.op("mov rbx, rsi");
+        self.op("mov rbp, rdx");
+        self.label("bf_write_str_next");
+        self.op(&format!("movzx edi, {} [rbx]", byte));
+        self.op("call bf_write");
+        self.op("inc rbx");
+        self.op("dec rbp");
+        self.op("jnz bf_write_str_next");
+        self.op("pop rbp");
+        self.op("pop rbx");
+        self.op("ret");
+
+        self.blank();
+        self.comment("Writes out the output buffer. Output that can't be written is dropped.");
+        self.label("bf_flush");
+        self.op(&format!("lea rsi, {}", buffer));
+        self.label("bf_flush_next");
+        self.op("test r15, r15");
+        self.op("jz bf_flush_done");
+        self.op("mov eax, 1");
+        self.op("mov edi, 1");
+        self.op("mov rdx, r15");
+        self.op("syscall");
+        self.op("test rax, rax");
+        self.op("jle bf_flush_done");
+        self.op("add rsi, rax");
+        self.op("sub r15, rax");
+        self.op("jmp bf_flush_next");
+        self.label("bf_flush_done");
+        self.op("xor r15d, r15d");
+        self.op("ret");
+
+        self.blank();
+        self.comment("Moves the pointer right to the nearest zero.");
+        self.label("bf_find_zero_right");
+        self.op("mov rdi, r12");
+        self.op("mov rcx, r14");
+        self.op("sub rcx, r12");
+        self.op("xor eax, eax");
+        self.op("repne scasb");
+        self.op("jne bf_overflow");
+        self.op("lea r12, [rdi - 1]");
+        self.op("ret");
+
+        self.blank();
+        self.comment("Moves the pointer left to the nearest zero.");
+        self.label("bf_find_zero_left");
+        self.op("mov rdi, r12");
+        self.op("mov rcx, r12");
+        self.op("sub rcx, r13");
+        self.op("inc rcx");
+        self.op("xor eax, eax");
+        self.op("std");
+        self.op("repne scasb");
+        self.op("cld");
+        self.op("jne bf_underflow");
+        self.op("lea r12, [rdi + 1]");
+        self.op("ret");
+
+        self.blank();
+        self.comment("Stops the program with the message at rsi, rdx bytes long.");
+        for (label, error) in [
+            ("bf_underflow", Error::PointerUnderflow),
+            ("bf_overflow", Error::PointerOverflow),
+        ] {
+            self.label(label);
+            self.op(&format!(
+                "lea rsi, {}",
+                self.syntax.rel(&format!("{}_message", label))
+            ));
+            self.op(&format!("mov edx, {}", message(error).len()));
+            self.op("jmp bf_fail");
+        }
+        self.label("bf_fail");
+        self.op("push rsi");
+        self.op("push rdx");
+        self.op("call bf_flush");
+        self.op("pop rdx");
+        self.op("pop rsi");
+        self.op("mov eax, 1");
+        self.op("mov edi, 2");
+        self.op("syscall");
+        self.op("mov edi, 3");
+        self.op("mov eax, 60");
+        self.op("syscall");
+    }
+
+    fn emit_data(&mut self, memory_size: usize) {
+        self.blank();
+        self.section(".rodata");
+        for (label, error) in [
+            ("bf_underflow_message", Error::PointerUnderflow),
+            ("bf_overflow_message", Error::PointerOverflow),
+        ] {
+            self.label(label);
+            self.bytes(message(error).as_bytes());
+        }
+        for (index, bytes) in std::mem::take(&mut self.strings).into_iter().enumerate() {
+            self.label(&format!("bf_str_{}", index));
+            self.bytes(&bytes);
+        }
+
+        self.blank();
+        self.section(".bss");
+        self.label("bf_memory");
+        self.reserve(memory_size);
+        self.label("bf_output");
+        self.reserve(OUTPUT_BUFFER);
+
+        // The stack needn't be executable.
+        self.blank();
+        match self.syntax {
+            Syntax::Gas => self.line(".section .note.GNU-stack,\"\",@progbits"),
+            Syntax::Nasm => self.line("section .note.GNU-stack noalloc noexec nowrite progbits"),
+        }
+    }
+
+    /// The operand for the cell at the pointer, followed by `displacement`.
+    fn cell(&self, displacement: &str) -> String {
+        format!("{} [r12{}]", self.syntax.size("byte"), displacement)
+    }
+
+    fn new_label(&mut self) -> String {
+        self.labels += 1;
+        format!("bf_{}", self.labels - 1)
+    }
+
+    fn line(&mut self, line: &str) {
+        self.text.push_str(line);
+        self.text.push('\n');
+    }
+
+    fn blank(&mut self) {
+        self.text.push('\n');
+    }
+
+    fn op(&mut self, op: &str) {
+        let _ = writeln!(self.text, "    {}", op);
+    }
+
+    fn label(&mut self, label: &str) {
+        let _ = writeln!(self.text, "{}:", label);
+    }
+
+    fn comment(&mut self, text: &str) {
+        let _ = writeln!(self.text, "    {} {}", self.syntax.comment(), text);
+    }
+
+    fn section(&mut self, name: &str) {
+        match self.syntax {
+            Syntax::Gas => self.line(&format!(".section {}", name)),
+            Syntax::Nasm => self.line(&format!("section {}", name)),
+        }
+    }
+
+    fn global(&mut self, name: &str) {
+        match self.syntax {
+            Syntax::Gas => self.line(&format!(".globl {}", name)),
+            Syntax::Nasm => self.line(&format!("global {}", name)),
+        }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        let directive = match self.syntax {
+            Syntax::Gas => ".byte",
+            Syntax::Nasm => "db",
+        };
+        for chunk in bytes.chunks(16) {
+            let values: Vec<_> = chunk.iter().map(u8::to_string).collect();
+            self.op(&format!("{} {}", directive, values.join(", ")));
+        }
+    }
+
+    fn reserve(&mut self, size: usize) {
+        match self.syntax {
+            Syntax::Gas => self.op(&format!(".skip {}", size)),
+            Syntax::Nasm => self.op(&format!("resb {}", size)),
+        }
+    }
+}
+
+/// The instruction as its comment shows it.
+fn describe(instruction: &Instruction) -> String {
+    match instruction {
+        // The bytes can be anything, including a newline.
+        Instruction::WriteStr(bytes) => format!("WriteStr({} bytes)", bytes.len()),
+        instruction => format!("{:?}", instruction),
+    }
+}
+
+/// What the program writes to stderr when it stops with `error`, as `bfi` words it.
+fn message(error: Error) -> String {
+    format!("runtime error: {}.\n", error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast;
+    use crate::traits::*;
+
+    fn emit_src(src: &[u8], syntax: Syntax) -> String {
+        let program = ast::parse_program(src).unwrap().peephole_compile();
+        emit(&program, syntax, 100)
+    }
+
+    #[test]
+    fn lowers_like_the_jit() {
+        let text = emit_src(b"+>[-]<[->+++<]", Syntax::Gas);
+        assert!(text.starts_with(".intel_syntax noprefix\n"));
+        for line in [
+            "    # Add(1)",
+            "    add byte ptr [r12], 1",
+            "    jle bf_overflow",
+            "    mov byte ptr [r12 + 1], 0",
+            "    # MulAddRight(1, 3)",
+            "    lea ecx, [rcx + rcx * 2]",
+            "    add byte ptr [r12 + 1], cl",
+            "    mov byte ptr [r12], 0",
+            "bf_memory:",
+            "    .skip 100",
+        ] {
+            assert!(text.lines().any(|text| text == line), "{}:\n{}", line, text);
+        }
+    }
+
+    #[test]
+    fn renders_for_each_assembler() {
+        let src = b"++++++++[>++++++++<-]>+.,[.,]<<[<]";
+        let gas = emit_src(src, Syntax::Gas);
+        let nasm = emit_src(src, Syntax::Nasm);
+        assert_eq!(gas.lines().count(), nasm.lines().count());
+        assert!(gas.contains("lea r12, [rip + bf_memory]"));
+        assert!(nasm.contains("lea r12, [rel bf_memory]"));
+        assert!(nasm.contains("    movzx edi, byte [r12]"));
+        assert!(nasm.contains("    resb 100"));
+        assert!(!nasm.contains("ptr") && !nasm.contains('#'), "{}", nasm);
+        assert_eq!("nasm".parse(), Ok(Syntax::Nasm));
+        assert!("masm".parse::<Syntax>().is_err());
+    }
+
+    #[test]
+    fn keeps_strings_as_data() {
+        let text = emit_src(b"++++++++++.+.", Syntax::Nasm);
+        assert!(text.contains("    ; WriteStr(2 bytes)"), "{}", text);
+        assert!(text.contains("bf_str_0:\n    db 10, 11\n"), "{}", text);
+    }
+}
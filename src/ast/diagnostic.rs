@@ -0,0 +1,125 @@
+use std::fmt;
+
+use crate::common::Error;
+
+/// A place in Brainfuck source.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Position {
+    /// The number of bytes before the place.
+    pub offset: usize,
+    /// The line, counting from 1.
+    pub line: usize,
+    /// The column, counting characters from 1.
+    pub column: usize,
+}
+
+impl Position {
+    /// The position of the byte at `offset` in `src`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` is past the end of `src`.
+    pub fn new(src: &[u8], offset: usize) -> Self {
+        let before = &src[..offset];
+        let line_start = line_start(src, offset);
+        Position {
+            offset,
+            line: before.iter().filter(|&&byte| byte == b'\n').count() + 1,
+            column: count_chars(&before[line_start..]) + 1,
+        }
+    }
+
+    /// Shows the line of `src` with the place, which must be in `src`, and a caret under it.
+    pub fn snippet(&self, src: &[u8]) -> String {
+        let offset = self.offset;
+        let start = line_start(src, offset);
+        let end = src[offset..]
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .map_or(src.len(), |len| offset + len);
+
+        let line = String::from_utf8_lossy(&src[start..end]);
+        let line = line.trim_end_matches('\r');
+        // Tabs stay tabs, so that the caret lines up however wide they are.
+        let indent: String = String::from_utf8_lossy(&src[start..offset])
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        format!("{}\n{}^", line, indent)
+    }
+}
+
+/// A syntax error, with where it is in the source.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ParseError {
+    /// What is wrong.
+    pub error: Error,
+    /// The unmatched bracket.
+    pub position: Position,
+}
+
+impl ParseError {
+    /// Shows the line of `src` with the error, which must be the source that was parsed, and a
+    /// caret under the unmatched bracket.
+    pub fn snippet(&self, src: &[u8]) -> String {
+        self.position.snippet(src)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {}",
+            self.error, self.position.line, self.position.column
+        )
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(error: ParseError) -> Self {
+        error.error
+    }
+}
+
+fn line_start(src: &[u8], offset: usize) -> usize {
+    src[..offset]
+        .iter()
+        .rposition(|&byte| byte == b'\n')
+        .map_or(0, |newline| newline + 1)
+}
+
+/// The number of UTF-8 characters in `bytes`, counting each invalid byte as one.
+fn count_chars(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|&&byte| byte & 0xC0 != 0x80).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_lines_and_characters() {
+        let src = "+\n—[+\n".as_bytes();
+        assert_eq!(
+            Position::new(src, 5),
+            Position {
+                offset: 5,
+                line: 2,
+                column: 2,
+            }
+        );
+        assert_eq!(Position::new(src, 0).column, 1);
+    }
+
+    #[test]
+    fn snippet_points_at_the_bracket() {
+        let src = b"+\n\t-- ]]\n.";
+        let error = ParseError {
+            error: Error::UnmatchedEnd,
+            position: Position::new(src, 7),
+        };
+        assert_eq!(error.to_string(), "unmatched ‘]’ at line 2, column 6");
+        assert_eq!(error.snippet(src), "\t-- ]]\n\t    ^");
+    }
+}
@@ -2,66 +2,117 @@ use std::io::{Read, Write};
 
 use super::*;
 use crate::common::BfResult;
+use crate::counters::{self, CountingInterpretable, Counters, Hooks, Kind};
+use crate::sandbox::{self, SandboxConfig, SandboxInterpretable};
 use crate::state::State;
 use crate::traits::Interpretable;
 
 impl Interpretable for Program {
-    fn interpret_state<R: Read, W: Write>(
+    fn interpret_in<R: Read, W: Write>(
         &self,
-        mut state: State,
+        state: &mut State,
         mut input: R,
         mut output: W,
     ) -> BfResult<()> {
-        interpret(self, &mut state, &mut input, &mut output)
+        interpret(self, state, &mut input, &mut output, &mut ())
     }
 }
 
-fn interpret<R, W>(
+impl CountingInterpretable for Program {
+    fn interpret_counting<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        input: R,
+        output: W,
+        counters: &mut Counters,
+    ) -> BfResult<()> {
+        counters::count_io(input, output, counters, |input, output, counters| {
+            interpret(self, state, input, output, counters)
+        })
+    }
+}
+
+impl SandboxInterpretable for Program {
+    fn interpret_sandboxed<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        input: R,
+        output: W,
+        config: &SandboxConfig,
+    ) -> BfResult<()> {
+        sandbox::run(
+            state,
+            input,
+            output,
+            config,
+            |state, input, output, hooks| interpret(self, state, input, output, hooks),
+        )
+    }
+}
+
+fn interpret<R, W, H>(
     instructions: &Program,
     state: &mut State,
     input: &mut R,
     output: &mut W,
+    hooks: &mut H,
 ) -> BfResult<()>
 where
     R: Read,
     W: Write,
+    H: Hooks,
 {
     for instruction in instructions {
-        interpret_instruction(instruction, state, input, output)?;
+        interpret_instruction(instruction, state, input, output, hooks)?;
     }
 
     Ok(())
 }
 
 #[inline]
-fn interpret_instruction<R, W>(
+fn interpret_instruction<R, W, H>(
     instruction: &Statement,
     state: &mut State,
     input: &mut R,
     output: &mut W,
+    hooks: &mut H,
 ) -> BfResult<()>
 where
     R: Read,
     W: Write,
+    H: Hooks,
 {
     use super::Command::*;
     use super::Statement::*;
 
+    match *instruction {
+        Cmd(Left) => hooks.instruction(Kind::Left),
+        Cmd(Right) => hooks.instruction(Kind::Right),
+        Cmd(Up) | Cmd(Down) => hooks.instruction(Kind::Add),
+        Cmd(In) => hooks.instruction(Kind::In),
+        Cmd(Out) => hooks.instruction(Kind::Out),
+        Cmd(SwitchTape) => hooks.instruction(Kind::SwitchTape),
+        Cmd(Begin) | Cmd(End) | Loop(_) => (),
+    }
+
     match *instruction {
         Cmd(Left) => state.left(1usize)?,
         Cmd(Right) => state.right(1usize)?,
-        Cmd(Up) => state.up(1),
-        Cmd(Down) => state.down(1),
+        Cmd(Up) => state.up(1u8)?,
+        Cmd(Down) => state.down(1u8)?,
         Cmd(In) => state.read(input),
         Cmd(Out) => state.write(output),
+        Cmd(SwitchTape) => state.switch_tape(1usize),
         Cmd(Begin) | Cmd(End) => panic!("Invalid instruction: Begin or End"),
         Loop(ref program) => {
             while state.load() != 0 {
-                interpret(program, state, input, output)?;
+                hooks.loop_iteration()?;
+                interpret(program, state, input, output, hooks)?;
             }
         }
     }
 
+    hooks.pointer(state.pointer());
     Ok(())
 }
 
@@ -104,6 +155,12 @@ mod tests {
         assert_interpret(prog, &[8, 255, 18, 0], &[9, 0, 19]);
     }
 
+    #[test]
+    fn multi_tape() {
+        let program = parse_multi_tape(MULTI_TAPE_SRC);
+        assert_interpret_tapes(&*program, 2, b"", b"ABB");
+    }
+
     #[test]
     fn hello_world() {
         assert_parse_interpret(HELLO_WORLD_SRC, "", "Hello, World!");
@@ -6,14 +6,28 @@
 //! In this module, BF programs are represented by the [`Program`](type.Program.html)
 //! type, which is an array of [`Instruction`](enum.Instruction.html)s. `Instruction`s
 //! correspond directly to Brainfuck commands, except that loops are represented as subtrees
-//! rather than with begin and end markers.
+//! rather than with begin and end markers. A [`Visitor`](trait.Visitor.html) traverses a program,
+//! and a [`Folder`](trait.Folder.html) rewrites one.
+//!
+//! [`parse_with_positions`](fn.parse_with_positions.html) reports the line and column of a syntax
+//! error, and its [`ParseError`](struct.ParseError.html) can show the line with a caret under
+//! the unmatched bracket. [`parse_program_recovering`](fn.parse_program_recovering.html) reports
+//! every unmatched bracket at once.
 
+mod diagnostic;
 mod interpreter;
 mod parser;
+mod visit;
 
-pub use self::parser::parse_program;
+pub use self::diagnostic::{ParseError, Position};
+pub use self::parser::{
+    parse_program, parse_program_recovering, parse_program_with, parse_with_positions, ParseOptions,
+    DEFAULT_MAX_DEPTH,
+};
+pub use self::visit::{fold_program, fold_statement, walk_program, walk_statement, Folder, Visitor};
 
 use crate::common::Command;
+use crate::traits::Compile;
 
 /// A BF program is represented as a slice of statements. The slice will
 /// typically be boxed.
@@ -21,6 +35,7 @@ pub type Program = [Statement];
 
 /// An unoptimized BF statement.
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Statement {
     /// A non-loop command.
     ///
@@ -31,3 +46,5 @@ pub enum Statement {
     /// A loop surrounding a sequence of instructions.
     Loop(Box<[Statement]>),
 }
+
+impl Compile for Program {}
@@ -1,91 +1,190 @@
+use std::mem;
+
 use super::*;
+use crate::ast::{ParseError, Position};
 use crate::common::{BfResult, Error};
+use crate::instrument;
+use crate::traits::ToSource;
 
 /// Parses Brainfuck concrete syntax into an abstract syntax tree.
 ///
+/// Loops may nest at most [`DEFAULT_MAX_DEPTH`](constant.DEFAULT_MAX_DEPTH.html) deep.
+///
 /// # Errors
 ///
-/// Unmatched square brackets will result in an `Err` return. See
+/// Unmatched square brackets, or loops nested too deeply, will result in an `Err` return. See
 /// [`common::Error`](../common/enum.Error.html).
 pub fn parse_program(input: &[u8]) -> BfResult<Box<Program>> {
-    let (program, rest) = parse_instructions(input)?;
-    if rest.is_empty() {
-        Ok(program)
-    } else {
-        Err(Error::UnmatchedEnd)
+    parse_program_with(input, &ParseOptions::default())
+}
+
+/// How deeply loops may nest with the default [`ParseOptions`](struct.ParseOptions.html).
+///
+/// The parser itself keeps a stack of open loops, but dropping the syntax tree, the compilers,
+/// the bounds analysis, and the AST and RLE interpreters all recurse on nested loops. This
+/// leaves plenty of room for them on a 2 MiB thread stack, even in a debug build.
+pub const DEFAULT_MAX_DEPTH: usize = 500;
+
+/// Options for the parser, which turn on extensions to Brainfuck.
+///
+/// With the default options, the parser accepts plain Brainfuck, and the commands of the
+/// extensions are comments.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ParseOptions {
+    /// Recognize `@` as the multi-tape extension's
+    /// [`SwitchTape`](../common/enum.Command.html#variant.SwitchTape) command.
+    pub multi_tape: bool,
+    /// The deepest that loops may nest, which is
+    /// [`DEFAULT_MAX_DEPTH`](constant.DEFAULT_MAX_DEPTH.html) by default, or `None` for no
+    /// limit. The passes after parsing recurse on nested loops, so without a limit a deep
+    /// enough program overflows the stack.
+    pub max_depth: Option<usize>,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            multi_tape: false,
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+        }
     }
 }
 
-/// The type returned by a parser.
+/// Parses Brainfuck concrete syntax, with the given extensions, into an abstract syntax tree.
 ///
-/// A successful parse returns `Ok` of a pair of the result value and a slice of the
-/// remaining input. A failed parse returns `Err`.
-type Parser<'a, R> = BfResult<(R, &'a [u8])>;
+/// # Errors
+///
+/// The same as [`parse_program`](fn.parse_program.html), and
+/// [`NestingTooDeep`](../common/enum.Error.html#variant.NestingTooDeep) if loops nest more
+/// deeply than `options.max_depth`.
+pub fn parse_program_with(input: &[u8], options: &ParseOptions) -> BfResult<Box<Program>> {
+    parse(input, options).map_err(|(error, _)| error)
+}
 
-fn parse_instruction<'a>(mut input: &'a [u8]) -> Parser<Option<Statement>> {
-    use crate::common::Command::*;
+/// Parses Brainfuck concrete syntax, with the given extensions, reporting where in the source
+/// any error is.
+///
+/// # Errors
+///
+/// The same as [`parse_program_with`](fn.parse_program_with.html), along with the
+/// [position](struct.Position.html) of the bracket that is unmatched or nested too deeply. If
+/// there are several unmatched brackets, it is the first `]` without a `[`, or else the last `[`
+/// without a `]`.
+pub fn parse_with_positions(
+    input: &[u8],
+    options: &ParseOptions,
+) -> Result<Box<Program>, ParseError> {
+    parse(input, options).map_err(|(error, offset)| ParseError {
+        error,
+        position: Position::new(input, offset),
+    })
+}
 
-    let ok = |cmd, inp: &'a [u8]| Ok((Some(Statement::Cmd(cmd)), inp));
-
-    loop {
-        if let Some((&c, next_input)) = input.split_first() {
-            input = next_input;
-            match c {
-                b'<' => return ok(Left, input),
-                b'>' => return ok(Right, input),
-                b'+' => return ok(Up, input),
-                b'-' => return ok(Down, input),
-                b',' => return ok(In, input),
-                b'.' => return ok(Out, input),
-                b']' => return Err(Error::UnmatchedEnd),
-
-                b'[' => match parse_instructions(input) {
-                    Err(e) => return Err(e),
-                    Ok((program, next_input)) => {
-                        input = next_input;
-                        loop {
-                            match input.split_first() {
-                                Some((&b']', next_input)) => {
-                                    return Ok((Some(Statement::Loop(program)), next_input))
-                                }
-                                Some((_, next_input)) => input = next_input,
-                                None => return Err(Error::UnmatchedBegin),
-                            }
-                        }
-                    }
-                },
-                _ => {
-                    // pass
-                }
-            }
-        } else {
-            return Ok((None, input));
+/// Parses Brainfuck concrete syntax, with the given extensions, reporting every syntax error
+/// instead of stopping at the first, so that an editor can show them all at once.
+///
+/// # Errors
+///
+/// Each unmatched bracket is an error, in the order they appear in the source. If the brackets
+/// all match but nest too deeply, that is the only error.
+pub fn parse_program_recovering(
+    input: &[u8],
+    options: &ParseOptions,
+) -> Result<Box<Program>, Vec<ParseError>> {
+    let (ends, begins) = unmatched_brackets(input);
+    if ends.is_empty() && begins.is_empty() {
+        return parse_with_positions(input, options).map_err(|error| vec![error]);
+    }
+
+    let mut errors: Vec<ParseError> = ends
+        .into_iter()
+        .map(|offset| (Error::UnmatchedEnd, offset))
+        .chain(
+            begins
+                .into_iter()
+                .map(|offset| (Error::UnmatchedBegin, offset)),
+        )
+        .map(|(error, offset)| ParseError {
+            error,
+            position: Position::new(input, offset),
+        })
+        .collect();
+    errors.sort_by_key(|error| error.position.offset);
+    Err(errors)
+}
+
+/// The offsets of the unmatched `]`s and the unmatched `[`s in `input`.
+fn unmatched_brackets(input: &[u8]) -> (Vec<usize>, Vec<usize>) {
+    let mut ends = Vec::new();
+    let mut open = Vec::new();
+    for (offset, &byte) in input.iter().enumerate() {
+        match byte {
+            b'[' => open.push(offset),
+            b']' if open.pop().is_none() => ends.push(offset),
+            _ => (),
         }
     }
+    (ends, open)
 }
 
-fn parse_instructions(mut input: &[u8]) -> Parser<Box<Program>> {
-    let mut instructions = Vec::new();
+/// Parses the program, or returns the first error and the offset of the bracket that caused it.
+fn parse(input: &[u8], options: &ParseOptions) -> Result<Box<Program>, (Error, usize)> {
+    instrument::pass("parse", input, |input| parse_commands(input, options))
+}
 
-    loop {
-        match parse_instruction(input) {
-            Ok((Some(instruction), next_input)) => {
-                instructions.push(instruction);
-                input = next_input;
-            }
+fn parse_commands(input: &[u8], options: &ParseOptions) -> Result<Box<Program>, (Error, usize)> {
+    use crate::common::Command::*;
+
+    // The loops that are open, with the offset of each `[` and the statements before it.
+    let mut open: Vec<(usize, Vec<Statement>)> = Vec::new();
+    let mut current = Vec::new();
 
-            Ok((None, next_input)) => {
-                input = next_input;
-                break;
+    for (offset, &byte) in input.iter().enumerate() {
+        let command = match byte {
+            b'<' => Left,
+            b'>' => Right,
+            b'+' => Up,
+            b'-' => Down,
+            b',' => In,
+            b'.' => Out,
+            b'@' if options.multi_tape => SwitchTape,
+            b'[' => {
+                if options.max_depth.is_some_and(|max| open.len() >= max) {
+                    return Err((Error::NestingTooDeep, offset));
+                }
+                open.push((offset, mem::take(&mut current)));
+                continue;
+            }
+            b']' => {
+                let (_, outer) = open.pop().ok_or((Error::UnmatchedEnd, offset))?;
+                let body = mem::replace(&mut current, outer);
+                current.push(Statement::Loop(body.into_boxed_slice()));
+                continue;
             }
+            _ => continue,
+        };
+        current.push(Statement::Cmd(command));
+    }
 
-            Err(e @ Error::UnmatchedBegin) => return Err(e),
+    match open.last() {
+        Some(&(offset, _)) => Err((Error::UnmatchedBegin, offset)),
+        None => Ok(current.into_boxed_slice()),
+    }
+}
 
-            _ => break,
+impl ToSource for Program {
+    fn write_source(&self, out: &mut String) {
+        for statement in self {
+            match *statement {
+                Statement::Cmd(command) => out.push(command.symbol() as char),
+                Statement::Loop(ref body) => {
+                    out.push('[');
+                    body.write_source(out);
+                    out.push(']');
+                }
+            }
         }
     }
-
-    Ok((instructions.into_boxed_slice(), input))
 }
 
 #[cfg(test)]
@@ -104,6 +203,12 @@ mod tests {
         assert_parse(".", &[Cmd(Out)]);
     }
 
+    #[test]
+    fn source_drops_comments() {
+        let program = parse_program(b"+[->, comment .<]").unwrap();
+        assert_eq!(program.to_source(), "+[->,.<]");
+    }
+
     #[test]
     fn multiple_instructions_parse() {
         assert_parse(
@@ -166,6 +271,20 @@ mod tests {
         assert_parse("hello", &[]);
     }
 
+    #[test]
+    fn switch_tape_is_opt_in() {
+        assert_parse("@+", &[Cmd(Up)]);
+
+        let options = ParseOptions {
+            multi_tape: true,
+            ..ParseOptions::default()
+        };
+        assert_eq!(
+            parse_program_with(b"@[@]", &options),
+            Ok(vec![Cmd(SwitchTape), mk_loop(vec![Cmd(SwitchTape)])].into_boxed_slice())
+        );
+    }
+
     #[test]
     fn left_bracket_without_right_is_error() {
         assert_parse_error("[", Error::UnmatchedBegin);
@@ -178,6 +297,109 @@ mod tests {
         assert_parse_error(".[.].]", Error::UnmatchedEnd);
     }
 
+    #[test]
+    fn errors_have_positions() {
+        let options = ParseOptions::default();
+        let error = parse_with_positions(b"+[\n[-]]]", &options).unwrap_err();
+        assert_eq!(error.to_string(), "unmatched ‘]’ at line 2, column 5");
+        assert_eq!(error.snippet(b"+[\n[-]]]"), "[-]]]\n    ^");
+
+        let error = parse_with_positions(b"[[]\n[", &options).unwrap_err();
+        assert_eq!(error.to_string(), "unmatched ‘[’ at line 2, column 1");
+        assert!(parse_with_positions(b"[.]", &options).is_ok());
+    }
+
+    #[test]
+    fn recovering_finds_every_error() {
+        let options = ParseOptions::default();
+        let errors = parse_program_recovering(b"][[]\n[.]]]+[", &options).unwrap_err();
+        let found: Vec<_> = errors
+            .iter()
+            .map(|error| (error.error, error.position.line, error.position.column))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (Error::UnmatchedEnd, 1, 1),
+                (Error::UnmatchedEnd, 2, 5),
+                (Error::UnmatchedBegin, 2, 7),
+            ]
+        );
+
+        assert_eq!(
+            parse_program_recovering(b"+[-]", &options),
+            Ok(parse_program(b"+[-]").unwrap())
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serializes_to_json() {
+        let program = parse_program(b"+[>.]").unwrap();
+        assert_eq!(
+            serde_json::to_string(&program).unwrap(),
+            r#"[{"Cmd":"Up"},{"Loop":[{"Cmd":"Right"},{"Cmd":"Out"}]}]"#
+        );
+    }
+
+    #[test]
+    fn deep_nesting_parses() {
+        let depth = 100_000;
+        let src = format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert_eq!(parse_program(src.as_bytes()), Err(Error::NestingTooDeep));
+
+        let options = ParseOptions {
+            max_depth: None,
+            ..ParseOptions::default()
+        };
+        let mut program = parse_program_with(src.as_bytes(), &options).unwrap();
+        let mut found = 0;
+        // Take the tree apart as we go, since dropping it whole would recurse.
+        while let [Loop(body)] = &mut *program {
+            program = std::mem::take(body);
+            found += 1;
+        }
+        assert_eq!(found, depth);
+    }
+
+    #[test]
+    fn default_depth_runs_everywhere() {
+        use crate::traits::Interpretable;
+
+        let depth = DEFAULT_MAX_DEPTH;
+        let src = format!("+{}-{}.", "[".repeat(depth), "]".repeat(depth));
+        let program = parse_program(src.as_bytes()).unwrap();
+        let too_deep = format!("[{}]", src);
+        assert_eq!(parse_program(too_deep.as_bytes()), Err(Error::NestingTooDeep));
+
+        let rle = crate::rle::compile(&program);
+        let peephole = crate::peephole::compile(&rle);
+        let bytecode = crate::bytecode::compile(&peephole);
+        assert_eq!(program.interpret_memory(None, b""), Ok(vec![0]));
+        assert_eq!(rle.interpret_memory(None, b""), Ok(vec![0]));
+        assert_eq!(peephole.interpret_memory(None, b""), Ok(vec![0]));
+        assert_eq!(bytecode.interpret_memory(None, b""), Ok(vec![0]));
+    }
+
+    #[test]
+    fn nesting_limit_is_an_error() {
+        let options = ParseOptions {
+            max_depth: Some(2),
+            ..ParseOptions::default()
+        };
+        assert!(parse_program_with(b"[[]][[]]", &options).is_ok());
+        let error = parse_with_positions(b"[[]]\n[[[]]]", &options).unwrap_err();
+        assert_eq!(error.error, Error::NestingTooDeep);
+        assert_eq!(
+            error.to_string(),
+            "loops nested too deeply at line 2, column 3"
+        );
+        assert_eq!(
+            parse_program_recovering(b"[[[]]]]", &options).unwrap_err()[0].error,
+            Error::UnmatchedEnd
+        );
+    }
+
     fn assert_parse(input: &str, program: &[Statement]) {
         assert_eq!(
             parse_program(input.as_bytes()),
@@ -0,0 +1,146 @@
+use super::*;
+
+/// Traverses an AST without changing it.
+///
+/// Every method has a default that visits the children, so an implementation only overrides
+/// the methods for what it looks at. An override that still wants the children visited calls
+/// [`walk_program`](fn.walk_program.html) or [`walk_statement`](fn.walk_statement.html).
+pub trait Visitor {
+    /// Visits each statement of a program or loop body, in order.
+    fn visit_program(&mut self, program: &Program) {
+        walk_program(self, program)
+    }
+
+    /// Visits a statement, dispatching on its form.
+    fn visit_statement(&mut self, statement: &Statement) {
+        walk_statement(self, statement)
+    }
+
+    /// Visits a non-loop command.
+    fn visit_command(&mut self, _command: Command) {}
+
+    /// Visits a loop, whose body is visited by default.
+    fn visit_loop(&mut self, body: &Program) {
+        self.visit_program(body)
+    }
+}
+
+/// Visits each statement of `program` with `visitor`.
+pub fn walk_program<V: Visitor + ?Sized>(visitor: &mut V, program: &Program) {
+    for statement in program {
+        visitor.visit_statement(statement);
+    }
+}
+
+/// Passes `statement` to the `visitor` method for its form.
+pub fn walk_statement<V: Visitor + ?Sized>(visitor: &mut V, statement: &Statement) {
+    match *statement {
+        Statement::Cmd(command) => visitor.visit_command(command),
+        Statement::Loop(ref body) => visitor.visit_loop(body),
+    }
+}
+
+/// Rewrites an AST, statement by statement.
+///
+/// Every method has a default that rebuilds the statement from its rewritten children, so an
+/// implementation only overrides the methods for what it changes. A statement that folds to
+/// `None` is removed. An override that still wants the children rewritten calls
+/// [`fold_program`](fn.fold_program.html) or [`fold_statement`](fn.fold_statement.html).
+pub trait Folder {
+    /// Rewrites each statement of a program or loop body, in order.
+    fn fold_program(&mut self, program: Box<Program>) -> Box<Program> {
+        fold_program(self, program)
+    }
+
+    /// Rewrites a statement, dispatching on its form.
+    fn fold_statement(&mut self, statement: Statement) -> Option<Statement> {
+        fold_statement(self, statement)
+    }
+
+    /// Rewrites a non-loop command.
+    fn fold_command(&mut self, command: Command) -> Option<Statement> {
+        Some(Statement::Cmd(command))
+    }
+
+    /// Rewrites a loop, whose body is rewritten by default.
+    fn fold_loop(&mut self, body: Box<Program>) -> Option<Statement> {
+        Some(Statement::Loop(self.fold_program(body)))
+    }
+}
+
+/// Rewrites each statement of `program` with `folder`, dropping those that fold to `None`.
+pub fn fold_program<F: Folder + ?Sized>(folder: &mut F, program: Box<Program>) -> Box<Program> {
+    program
+        .into_vec()
+        .into_iter()
+        .filter_map(|statement| folder.fold_statement(statement))
+        .collect()
+}
+
+/// Passes `statement` to the `folder` method for its form.
+pub fn fold_statement<F: Folder + ?Sized>(
+    folder: &mut F,
+    statement: Statement,
+) -> Option<Statement> {
+    match statement {
+        Statement::Cmd(command) => folder.fold_command(command),
+        Statement::Loop(body) => folder.fold_loop(body),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    #[derive(Default)]
+    struct Depth {
+        current: usize,
+        deepest: usize,
+        commands: usize,
+    }
+
+    impl Visitor for Depth {
+        fn visit_command(&mut self, _command: Command) {
+            self.commands += 1;
+        }
+
+        fn visit_loop(&mut self, body: &Program) {
+            self.current += 1;
+            self.deepest = self.deepest.max(self.current);
+            walk_program(self, body);
+            self.current -= 1;
+        }
+    }
+
+    #[test]
+    fn visits_every_command() {
+        let program = parse_program(b"+[>[-]<,]..").unwrap();
+        let mut depth = Depth::default();
+        depth.visit_program(&program);
+        assert_eq!((depth.deepest, depth.commands), (2, 7));
+    }
+
+    struct NoOutput;
+
+    impl Folder for NoOutput {
+        fn fold_command(&mut self, command: Command) -> Option<Statement> {
+            match command {
+                Command::Out => None,
+                _ => Some(Statement::Cmd(command)),
+            }
+        }
+    }
+
+    #[test]
+    fn folds_nested_commands() {
+        let program = parse_program(b"+.[.>[.-]<]").unwrap();
+        let program = NoOutput.fold_program(program);
+        assert_eq!(program, parse_program(b"+[>[-]<]").unwrap());
+        assert_interpret(
+            &*NoOutput.fold_program(parse_program(HELLO_WORLD_SRC).unwrap()),
+            b"",
+            b"",
+        );
+    }
+}
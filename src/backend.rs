@@ -0,0 +1,397 @@
+//! Choosing a backend at run time.
+//!
+//! Each backend has its own compiler trait and its own program type, so code that lets its user
+//! pick one would otherwise need a `match` over all of them, which then misses any backend added
+//! later. A [`Backend`](enum.Backend.html) names one of the backends enabled in this build, and
+//! [`Backend::all`](enum.Backend.html#method.all) lists them, so a program can offer every
+//! backend by name and then [compile and run](enum.Backend.html#method.compile_and_run) with
+//! whichever was chosen, or [compile](enum.Backend.html#method.compile) to a
+//! [`DynProgram`](../traits/trait.DynProgram.html) to run later.
+//!
+//! ```
+//! use bf::ast::ParseOptions;
+//! use bf::backend::Backend;
+//! use bf::state::State;
+//!
+//! let backend: Backend = "fused".parse().unwrap();
+//! let mut output = Vec::new();
+//! backend
+//!     .compile_and_run(
+//!         b"++++++[>++++++++<-]>+.",
+//!         &ParseOptions::default(),
+//!         &mut State::new(),
+//!         &b""[..],
+//!         &mut output,
+//!     )
+//!     .unwrap();
+//! assert_eq!(output, b"1");
+//! ```
+//!
+//! The optimizing compilers assume that cells wrap, so a backend compiles a program for cells
+//! that [overflow](../semantics/enum.Overflow.html) some other way with
+//! [`compile_for`](enum.Backend.html#method.compile_for), which `interpret_in` uses for the
+//! state it is given, which may also make them [signed](../state/struct.State.html#method.signed).
+//! Only the Cranelift and LLVM backends don't support such cells.
+//!
+//! In `bfi`, pass `--backend NAME` to choose a backend by its name.
+
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use crate::ast::{self, ParseOptions};
+use crate::bytecode::Encoding;
+use crate::common::BfResult;
+use crate::instrument;
+use crate::semantics::Overflow;
+use crate::state::State;
+use crate::traits::*;
+use crate::{bytecode, fused, peephole, rle};
+
+/// The backends that can run a program.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Backend {
+    /// The unoptimized AST interpreter, which is the reference.
+    Ast,
+    /// The run-length encoded AST interpreter.
+    Rle,
+    /// The peephole-optimized AST interpreter.
+    Peephole,
+    /// A bytecode interpreter.
+    Bytecode(Encoding),
+    /// The superinstruction interpreter.
+    Fused,
+    /// The dynasm JIT, with bounds checks.
+    #[cfg(feature = "jit")]
+    Jit,
+    /// The Cranelift JIT.
+    #[cfg(feature = "cranelift")]
+    Cranelift,
+    /// The LLVM JIT.
+    #[cfg(feature = "llvm")]
+    Llvm,
+}
+
+impl Backend {
+    /// All backends enabled in this build, starting with the reference.
+    pub fn all() -> Vec<Backend> {
+        let mut result = vec![Backend::Ast, Backend::Rle, Backend::Peephole];
+        result.extend(
+            Encoding::ALL
+                .iter()
+                .map(|&encoding| Backend::Bytecode(encoding)),
+        );
+        result.push(Backend::Fused);
+        #[cfg(feature = "jit")]
+        result.push(Backend::Jit);
+        #[cfg(feature = "cranelift")]
+        result.push(Backend::Cranelift);
+        #[cfg(feature = "llvm")]
+        result.push(Backend::Llvm);
+        result
+    }
+
+    /// The name of the backend, as accepted by `from_str`. Bytecode interpreters are named by
+    /// their encodings, except that the boxed one is `bytecode`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Ast => "ast",
+            Backend::Rle => "rle",
+            Backend::Peephole => "peephole",
+            Backend::Bytecode(Encoding::Boxed) => "bytecode",
+            Backend::Bytecode(encoding) => encoding.name(),
+            Backend::Fused => "fused",
+            #[cfg(feature = "jit")]
+            Backend::Jit => "jit",
+            #[cfg(feature = "cranelift")]
+            Backend::Cranelift => "cranelift",
+            #[cfg(feature = "llvm")]
+            Backend::Llvm => "llvm",
+        }
+    }
+
+    /// Whether the backend compiles to native code, which supports only one tape and a memory
+    /// that doesn't grow.
+    pub fn is_native(self) -> bool {
+        match self {
+            #[cfg(feature = "jit")]
+            Backend::Jit => true,
+            #[cfg(feature = "cranelift")]
+            Backend::Cranelift => true,
+            #[cfg(feature = "llvm")]
+            Backend::Llvm => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the backend can run programs whose cells overflow as `overflow` says. The
+    /// Cranelift and LLVM backends support only wrapping cells.
+    pub fn supports_overflow(self, overflow: Overflow) -> bool {
+        let wrapping_only = match self {
+            #[cfg(feature = "cranelift")]
+            Backend::Cranelift => true,
+            #[cfg(feature = "llvm")]
+            Backend::Llvm => true,
+            _ => false,
+        };
+        !wrapping_only || overflow == Overflow::Wrap
+    }
+
+    /// Compiles the program for this backend, for cells that wrap. The LLVM backend compiles
+    /// only as far as the peephole AST, and does the rest each time the program runs.
+    pub fn compile(self, program: &ast::Program) -> Box<dyn DynProgram> {
+        match self {
+            Backend::Ast => Box::new(program.to_vec().into_boxed_slice()),
+            Backend::Rle => Box::new(program.rle_compile()),
+            Backend::Peephole => Box::new(program.peephole_compile()),
+            Backend::Bytecode(encoding) => Box::new(program.bytecode_compile_with(encoding)),
+            Backend::Fused => Box::new(program.fused_compile()),
+            #[cfg(feature = "jit")]
+            Backend::Jit => Box::new(program.jit_compile(true)),
+            #[cfg(feature = "cranelift")]
+            Backend::Cranelift => Box::new(program.cranelift_compile()),
+            #[cfg(feature = "llvm")]
+            Backend::Llvm => Box::new(LlvmProgram(program.peephole_compile())),
+        }
+    }
+
+    /// Compiles the program for this backend, for cells that overflow as `overflow` says and
+    /// are `signed` or not, which the compiled program then expects of the states it runs in.
+    ///
+    /// For cells that don't wrap, this compiles without the optimizations, which rewrite code
+    /// with wrapping arithmetic, using [`rle::compile_for`](../rle/fn.compile_for.html) and
+    /// [`peephole::compile_for`](../peephole/fn.compile_for.html).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the backend doesn't [support](#method.supports_overflow) `overflow`.
+    #[cfg_attr(not(feature = "jit"), allow(unused_variables))]
+    pub fn compile_for(
+        self,
+        program: &ast::Program,
+        overflow: Overflow,
+        signed: bool,
+    ) -> Box<dyn DynProgram> {
+        assert!(
+            self.supports_overflow(overflow),
+            "the {} backend supports only wrapping cells",
+            self
+        );
+        if overflow == Overflow::Wrap {
+            return self.compile(program);
+        }
+
+        let peephole = || peephole::compile_for(&rle::compile_for(program, overflow), overflow);
+        match self {
+            Backend::Ast => Box::new(program.to_vec().into_boxed_slice()),
+            Backend::Rle => Box::new(rle::compile_for(program, overflow)),
+            Backend::Peephole => Box::new(peephole()),
+            Backend::Bytecode(encoding) => {
+                Box::new(bytecode::encode(&bytecode::compile(&peephole()), encoding))
+            }
+            Backend::Fused => Box::new(fused::compile(&bytecode::compile(&peephole()))),
+            #[cfg(feature = "jit")]
+            Backend::Jit => Box::new(crate::jit::compile_for(&peephole(), true, overflow, signed)),
+            #[allow(unreachable_patterns)]
+            _ => unreachable!("the backend supports the overflow"),
+        }
+    }
+
+    /// Compiles the program for this backend as [`compile_for`](#method.compile_for) does, but
+    /// without assuming that memory starts out zeroed, for states whose cells were filled in
+    /// beforehand. See [`peephole::compile_prefilled`](../peephole/fn.compile_prefilled.html).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the backend doesn't [support](#method.supports_overflow) `overflow`.
+    pub fn compile_prefilled(
+        self,
+        program: &ast::Program,
+        overflow: Overflow,
+        signed: bool,
+    ) -> Box<dyn DynProgram> {
+        // Only the full compiler for wrapping cells relies on zeroed memory.
+        if overflow != Overflow::Wrap {
+            return self.compile_for(program, overflow, signed);
+        }
+
+        let peephole = || peephole::compile_prefilled(&rle::compile(program));
+        match self {
+            Backend::Ast => Box::new(program.to_vec().into_boxed_slice()),
+            Backend::Rle => Box::new(program.rle_compile()),
+            Backend::Peephole => Box::new(peephole()),
+            Backend::Bytecode(encoding) => {
+                Box::new(bytecode::encode(&bytecode::compile(&peephole()), encoding))
+            }
+            Backend::Fused => Box::new(fused::compile(&bytecode::compile(&peephole()))),
+            #[cfg(feature = "jit")]
+            Backend::Jit => Box::new(crate::jit::compile(&peephole(), true)),
+            #[cfg(feature = "cranelift")]
+            Backend::Cranelift => Box::new(crate::cranelift::compiler::compile(&peephole())),
+            #[cfg(feature = "llvm")]
+            Backend::Llvm => Box::new(LlvmProgram(peephole())),
+        }
+    }
+
+    /// Compiles the program for this backend and the given state's
+    /// [`overflow`](../state/struct.State.html#method.overflow) and
+    /// [signedness](../state/struct.State.html#method.signed), and runs it against the state.
+    /// If the state's memory isn't all zero, this compiles as
+    /// [`compile_prefilled`](#method.compile_prefilled) does.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the backend [is native](#method.is_native) and the state has more than one
+    /// tape, or if the backend doesn't [support](#method.supports_overflow) the state's
+    /// `overflow`.
+    pub fn interpret_in<R: Read, W: Write>(
+        self,
+        program: &ast::Program,
+        state: &mut State,
+        mut input: R,
+        mut output: W,
+    ) -> BfResult<()> {
+        match self {
+            Backend::Ast => {
+                instrument::run(self.name(), || program.interpret_in(state, input, output))
+            }
+            backend => {
+                let program = if state.is_zeroed() {
+                    backend.compile_for(program, state.overflow(), state.signed())
+                } else {
+                    backend.compile_prefilled(program, state.overflow(), state.signed())
+                };
+                instrument::run(self.name(), || {
+                    program.run_in(state, &mut input, &mut output)
+                })
+            }
+        }
+    }
+
+    /// Parses `src`, compiles it for this backend, and runs it against the given state.
+    ///
+    /// # Errors
+    ///
+    /// The syntax error if `src` doesn't parse, or else the error that the program stopped with.
+    pub fn compile_and_run<R: Read, W: Write>(
+        self,
+        src: &[u8],
+        options: &ParseOptions,
+        state: &mut State,
+        input: R,
+        output: W,
+    ) -> BfResult<()> {
+        let program = ast::parse_program_with(src, options)?;
+        self.interpret_in(&program, state, input, output)
+    }
+}
+
+/// A program for the LLVM backend, which compiles it each time it runs.
+#[cfg(feature = "llvm")]
+struct LlvmProgram(Box<crate::peephole::Program>);
+
+#[cfg(feature = "llvm")]
+impl DynProgram for LlvmProgram {
+    fn run_in(
+        &self,
+        state: &mut State,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> BfResult<()> {
+        self.0.llvm_run_in(state, input, output)
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Backend::Bytecode(encoding) => write!(f, "bytecode ({})", encoding),
+            backend => f.write_str(backend.name()),
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Backend::all()
+            .into_iter()
+            .find(|backend| backend.name() == s)
+            .ok_or_else(|| format!("unknown backend: {}", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Error;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn names_round_trip() {
+        for backend in Backend::all() {
+            assert_eq!(backend.name().parse(), Ok(backend));
+        }
+        assert_eq!("packed".parse(), Ok(Backend::Bytecode(Encoding::Packed)));
+        assert!("boxed".parse::<Backend>().is_err());
+        assert_eq!(
+            Backend::Bytecode(Encoding::Boxed).to_string(),
+            "bytecode (boxed)"
+        );
+    }
+
+    #[test]
+    fn every_backend_compiles_and_runs() {
+        for backend in Backend::all() {
+            let mut output = Vec::new();
+            let result = backend.compile_and_run(
+                FACTOR_SRC,
+                &ParseOptions::default(),
+                &mut State::new(),
+                &b"100\n"[..],
+                &mut output,
+            );
+            assert_eq!(result, Ok(()), "{}", backend);
+            assert_eq!(output, b"100: 2 2 5 5\n", "{}", backend);
+        }
+    }
+
+    #[test]
+    fn every_backend_starts_at_the_pointer_of_the_state() {
+        // The moves back to the right are proved from the checked moves before them, wherever
+        // the pointer started.
+        let program = ast::parse_program(b">>+<<<<<<<+>>>>>>>+>").unwrap();
+        for backend in Backend::all() {
+            let mut state = State::with_capacity(8);
+            state.set_pointer(5).unwrap();
+            let result = backend.interpret_in(&program, &mut state, &b""[..], Vec::new());
+            assert_eq!(result, Err(Error::PointerOverflow), "{}", backend);
+            assert_eq!(state.cells(), [1, 0, 0, 0, 0, 0, 0, 2], "{}", backend);
+        }
+    }
+
+    #[test]
+    fn compiles_to_programs_of_every_form() {
+        let program = ast::parse_program(FACTOR_SRC).unwrap();
+        let programs: Vec<_> = Backend::all()
+            .into_iter()
+            .map(|backend| backend.compile(&program))
+            .collect();
+        for program in &programs {
+            assert_interpret(program, b"100\n", b"100: 2 2 5 5\n");
+        }
+    }
+
+    #[test]
+    fn reports_syntax_errors() {
+        let result = Backend::Ast.compile_and_run(
+            b"[",
+            &ParseOptions::default(),
+            &mut State::new(),
+            &b""[..],
+            Vec::new(),
+        );
+        assert_eq!(result, Err(Error::UnmatchedBegin));
+    }
+}
@@ -0,0 +1,119 @@
+//! Running one program over many inputs in parallel.
+//!
+//! The [`RunBatch`](trait.RunBatch.html) trait is implemented for every program form that can be
+//! shared between threads. It spreads the inputs over one worker thread per available CPU. Each
+//! worker allocates one memory and [resets](../state/struct.State.html#method.reset) it between
+//! inputs. Results come back in the order of the inputs.
+
+use std::sync::Mutex;
+use std::thread;
+
+use crate::common::BfResult;
+use crate::state::{State, DEFAULT_CAPACITY};
+use crate::traits::Interpretable;
+
+/// Program forms that can be run over many inputs in parallel.
+pub trait RunBatch: Interpretable + Sync {
+    /// Runs the program on each input with the default memory size, returning each output.
+    fn run_batch<I>(&self, inputs: I) -> Vec<BfResult<Vec<u8>>>
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+    {
+        self.run_batch_sized(None, inputs)
+    }
+
+    /// Runs the program on each input with memory of the given size, or the default size if
+    /// `None`, returning each output.
+    fn run_batch_sized<I>(&self, size: Option<usize>, inputs: I) -> Vec<BfResult<Vec<u8>>>
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+    {
+        let inputs: Vec<Vec<u8>> = inputs.into_iter().collect();
+        let size = size.unwrap_or(DEFAULT_CAPACITY);
+        let workers = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            .min(inputs.len());
+
+        let mut results: Vec<Option<BfResult<Vec<u8>>>> = vec![None; inputs.len()];
+
+        if workers <= 1 {
+            let mut state = State::with_capacity(size);
+            for (input, result) in inputs.iter().zip(&mut results) {
+                *result = Some(run_one(self, &mut state, input));
+            }
+        } else {
+            let jobs = Mutex::new(inputs.iter().zip(results.iter_mut()));
+
+            thread::scope(|scope| {
+                for _ in 0..workers {
+                    scope.spawn(|| {
+                        let mut state = State::with_capacity(size);
+                        loop {
+                            let job = jobs.lock().unwrap().next();
+                            match job {
+                                Some((input, result)) => {
+                                    *result = Some(run_one(self, &mut state, input));
+                                }
+                                None => break,
+                            }
+                        }
+                    });
+                }
+            });
+        }
+
+        results.into_iter().map(Option::unwrap).collect()
+    }
+}
+
+impl<P: Interpretable + Sync + ?Sized> RunBatch for P {}
+
+fn run_one<P>(program: &P, state: &mut State, input: &[u8]) -> BfResult<Vec<u8>>
+where
+    P: Interpretable + ?Sized,
+{
+    let mut output = Vec::new();
+    state.reset();
+    program.interpret_in(state, input, &mut output)?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast;
+    use crate::common::Error;
+    use crate::test_helpers::*;
+    use crate::traits::*;
+
+    #[test]
+    fn results_are_in_input_order() {
+        let program = ast::parse_program(FACTOR_SRC).unwrap().bytecode_compile();
+        let inputs = (2..50).map(|n| format!("{}\n", n).into_bytes());
+        let results = program.run_batch(inputs);
+
+        assert_eq!(results.len(), 48);
+        for (n, result) in (2..50).zip(results) {
+            let output = String::from_utf8(result.unwrap()).unwrap();
+            assert!(output.starts_with(&format!("{}:", n)));
+        }
+    }
+
+    #[test]
+    fn errors_are_per_input() {
+        let program = ast::parse_program(b",[>,]").unwrap().peephole_compile();
+        let inputs = vec![b"ab".to_vec(), b"abcd".to_vec(), Vec::new()];
+
+        assert_eq!(
+            program.run_batch_sized(Some(3), inputs),
+            vec![Ok(Vec::new()), Err(Error::PointerOverflow), Ok(Vec::new())]
+        );
+    }
+
+    #[test]
+    fn empty_batch() {
+        let program = ast::parse_program(b"+").unwrap();
+        assert_eq!(program.run_batch(Vec::new()), Vec::new());
+    }
+}
@@ -3,42 +3,375 @@
 //! ```
 //! USAGE:
 //!     bfi [FLAGS] [OPTIONS] [--] [FILE]...
+//!     bfi <SUBCOMMAND>
+//!
+//! SUBCOMMANDS:
+//!     run        Run a program (the default)
+//!     pipe       Run programs with each one's output as the next one's input
+//!     serve      Run a program for each connection to a TCP address
+//!     compile    Compile a program and print the result
+//!     disasm     Print a program's bytecode with addresses
+//!     debug      Step through a program, reading commands from stdin
+//!     bench      Time a program under every enabled backend
+//!     profile    Report which statements of a program take the most time
+//!     fmt        Reformat a program's source
+//!     lint       Report definite bugs in a program
+//!     analyze    Report which loops provably stop, and bound the memory used
+//!     gen        Write a program that prints the given text
 //!
 //! FLAGS:
+//!         --append       Append to the output file instead of truncating it
 //!         --ast          Interpret the unoptimized AST
+//!         --auto-size    Start with a small memory and grow it as needed
 //!         --byte         Compile AST to bytecode
+//!         --dump-on-interrupt
+//!                        On Ctrl-C, stop and print the pointer and nearby cells to stderr
+//!         --fused        Compile AST to bytecode with superinstructions
+//!         --packed       Compile AST to packed 32-bit bytecode
+//!         --threaded     Compile AST to direct-threaded bytecode
 //!     -h, --help         Prints help information
+//!         --gdb-jit      Tell GDB or LLDB where the JIT's code for each loop is
+//!         --heatmap      Print how often each cell was read and written to stderr
 //!         --jit          JIT to native x64 (default)
 //!         --llvm         JIT using LLVM
+//!         --macros       Expand macros, repetitions, and `%include`s before parsing
 //!         --peep         Interpret the peephole-optimized AST
+//!         --perf-map     Write /tmp/perf-PID.map naming the JIT's code for each loop
 //!         --rle          Interpret the run-length encoded the AST
+//!         --signed       Make cells hold -128 to 127 instead of 0 to 255
+//!         --stats        Print execution statistics to stderr after the run
 //!     -u, --unchecked    Omit memory bounds checks in JIT
+//!         --verify       Check that all backends agree on the program
+//!     -v, --verbose      Print the size and time of each pass to stderr
 //!     -V, --version      Prints version information
 //!
 //! OPTIONS:
+//!         --backend <NAME>    Run with the named backend instead of choosing one by flag
+//!                             [possible values: ast, rle, peephole, bytecode, packed, threaded,
+//!                             fused, jit, cranelift, llvm (as enabled)]
+//!         --cache <DIR>       Keep the compiled bytecode in DIR, and reuse it for unchanged
+//!                             programs
+//!         --dialect <NAME>    The language the program is written in (default brainfuck)
+//!                             [possible values: brainfuck, ook, blub]
+//!         --eof <BEHAVIOR>    What `,` stores at the end of input (default zero)
+//!                             [possible values: zero, unchanged, minus-one]
 //!     -e, --expr <CODE>...    BF code to execute
+//!         --emit-asm[=<SYNTAX>]
+//!                             Print the program as x86-64 assembly instead of running it
+//!                             [possible values: gas, nasm]
+//!         --emit-elf <FILE>   Write the program to FILE as a tiny x86-64 Linux executable
+//!         --emit-exe <FILE>   Write the program to FILE as a tiny x86-64 Windows executable
+//!         --exit-cell[=<N>]   Exit with the final value of cell N (default 0)
+//!     -i, --input <FILE>      Read program input from FILE instead of stdin
+//!         --max-depth <N>     Reject programs whose loops nest more than N deep (default 500)
+//!         --max-output <BYTES>
+//!                             Stop the program once it writes more than BYTES bytes
+//!     -o, --output <FILE>     Write program output to FILE instead of stdout
+//!         --overflow <BEHAVIOR>
+//!                             What `+` and `-` do past 255 or below 0 (default wrap)
+//!                             [possible values: wrap, saturate, trap]
+//!         --record <FILE>     Save the run's input and output to FILE
+//!         --replay <FILE>     Take the input from a run saved with --record
+//!         --trace <FILE>      Write a JSON Lines record of each instruction to FILE
+//!         --trace-every <N>   Trace only every Nth instruction (default 1)
+//!         --trace-limit <N>   Stop tracing after N records
+//!         --profile <NAME>    The conventions the program expects (default default)
+//!                             [possible values: default, dbfi]
+//!         --random-input[=<SEED>]
+//!                             Make `,` read random bytes chosen by SEED (default from the clock)
 //!     -s, --size <SIZE>       Memory size in bytes (default 30,000)
+//!         --tape-alloc <STRATEGY>
+//!                             How to allocate the memory (default heap, or lazy from 64 MiB)
+//!                             [possible values: heap, lazy, huge-pages]
+//!         --tapes <N>         Enable `@` to switch between N tapes
 //!
 //! ARGS:
 //!     <FILE>...    The source file(s) to interpret
 //! ```
 //!
+//! Without a subcommand, `bfi` takes the same flags as `bfi run`. Run `bfi help <SUBCOMMAND>`
+//! for the options of the other subcommands.
+//!
+//! With `--stats`, `bfi` reports the compile and run times, and for the AST, RLE, peephole, and
+//! (boxed) bytecode interpreters and the JIT, the [counters](../bf/counters/index.html) for the
+//! run. With `--llvm`, the run time includes LLVM's compilation. The peak cell is the highest cell
+//! that the program used, so it needs a `--size` of at least one more than that. The counters
+//! include the bytes that the program read and wrote.
+//!
+//! With `--max-output BYTES`, `bfi` writes at most BYTES bytes of output, and stops the program
+//! with an error if it tries to write more, so that a runaway program can't fill the disk. It
+//! runs the program in a [sandbox](../bf/sandbox/index.html), which only the AST, RLE, peephole,
+//! and (boxed) bytecode interpreters and the checked JIT support.
+//!
+//! With `--dump-on-interrupt`, Ctrl-C doesn't kill `bfi` at once: the program stops at the
+//! start of its next loop iteration, through the sandbox's
+//! [interrupt](../bf/sandbox/struct.Interrupt.html), and `bfi` prints to stderr where the
+//! pointer was, the cells around it, and how many instructions had run, before exiting with
+//! status 130. The AST, RLE, peephole, and (boxed) bytecode interpreters count the instructions;
+//! the JIT runs its checked code in a sandbox, which doesn't. A second Ctrl-C exits at once, for a
+//! program that is waiting for input or isn't in a loop.
+//!
+//! With `--exit-cell=N`, `bfi` exits with the value that cell N of the current tape holds when the
+//! program finishes, or cell 0 with just `--exit-cell`, so that a shell script or a test harness
+//! can check a program's result by its exit status. A run that fails still exits with its own
+//! status, as without the option. LLVM doesn't give back the memory, so it doesn't support
+//! `--exit-cell`.
+//!
+//! With `--auto-size`, the memory starts at 256 cells and doubles whenever the pointer would
+//! move past the end, up to 1 GiB, so that a program gets as much memory as it needs without
+//! choosing a `--size`. Only the interpreters can grow the memory.
+//!
+//! With `--tape-alloc lazy`, the memory is [mapped](../bf/state/enum.TapeAlloc.html) from the
+//! operating system and committed a page at a time as the program touches it, so that a
+//! `--size` of a gigabyte costs only what the program uses, and `--tape-alloc huge-pages` also
+//! asks Linux to back it with huge pages. A `--size` of 64 MiB or more is lazy by default.
+//!
+//! With `--tapes N`, `@` switches to the next of N tapes, each with its own pointer, in the
+//! [multi-tape extension](../bf/common/enum.Command.html#variant.SwitchTape). Only the
+//! interpreters support more than one tape.
+//!
+//! With `--profile dbfi`, `bfi` follows the [conventions](../bf/semantics/index.html) of
+//! self-interpreters such as dbfi: a `!` in the source ends the program, and the rest of the
+//! source is the start of its input, and `,` leaves the cell unchanged at the end of input.
+//! `--eof` overrides the profile's end-of-input behavior.
+//!
+//! With `--overflow saturate`, a cell stays at 255 or 0 instead of wrapping around, and with
+//! `--overflow trap`, going past 255 or below 0 is a runtime error, as some dialects and teaching
+//! settings require. Cells that don't wrap turn off the optimizations, which assume wrapping.
+//! Cranelift and LLVM don't support them, and the JIT supports them only without `--stats`,
+//! `--max-output`, or `--dump-on-interrupt`.
+//!
+//! With `--signed`, cells hold -128 to 127, so that `--overflow` stops them at 127 or -128 in
+//! place of 255 or 0, and `--trace`, `--dump-on-interrupt`, and `bfi debug --signed` show
+//! negative cells as such. A signed cell is the same byte as an unsigned one, so only this
+//! changes, and `--exit-cell` still exits with the byte.
+//!
+//! `bfi --record FILE` saves everything that the program reads and writes to a
+//! [replay file](../bf/replay/index.html). `bfi --replay FILE` runs the program with the input
+//! from the file instead of `--input` or stdin, which makes an interactive run repeatable, and
+//! warns if the output is different from the recorded output.
+//!
+//! `bfi --random-input=SEED` gives the program an endless [random
+//! input](../bf/random/index.html) instead of stdin, with bytes that SEED chooses, so that a
+//! program that makes random choices does the same thing every time it runs with the same seed.
+//! With just `--random-input`, `bfi` takes a seed from the clock and prints it to stderr, so that
+//! a run can still be repeated. Any input in the source comes first.
+//!
+//! `bfi --cache DIR` keeps the program's bytecode in a [cache](../bf/cache/index.html) in DIR,
+//! and the next run of the same program loads it instead of compiling again. It serves the
+//! peephole, bytecode, fused, and JIT passes, which all start from the bytecode or from the
+//! peephole AST rebuilt from it. With `--stats`, `bfi` also reports whether the program was in
+//! the cache, or was there only from another version of `bfi`.
+//!
+//! `bfi --trace FILE` runs the program's bytecode and writes a [JSON Lines
+//! trace](../bf/trace/index.html) of it to FILE, with a record for every `--trace-every`th
+//! instruction, up to `--trace-limit` records.
+//!
+//! `bfi --heatmap` runs the program's bytecode and then prints a [heat
+//! map](../bf/heatmap/index.html) of the tape to stderr, showing how often each cell was read
+//! and written. It is as wide as `COLUMNS`, or else 80 characters.
+//!
+//! `bfi debug` runs the program under the [debugger](../bf/debugger/index.html), one command
+//! of the source per step, reading debugger commands from stdin; type `help` for the list.
+//! The program's input comes from `--input FILE`, or is empty. After each command it prints the
+//! next command of the source with its line and column, the cells around the pointer, and any
+//! new output. If the `tui` feature is enabled, `bfi debug --tui` instead redraws the terminal
+//! after each command, with the source and the next command highlighted, the tape centered on
+//! the pointer, and the output so far. It takes the terminal size from `COLUMNS` and `LINES`,
+//! or else assumes 80 by 24.
+//!
+//! `bfi bench` compiles and runs the program on each backend in turn, after `--warmup` untimed
+//! runs, and prints a table of the median compile time and the median and fastest of
+//! `--iterations` timed runs. The input comes from `--input FILE`, or is empty; the output is
+//! discarded. With `--json`, it instead prints a JSON object, so that results can be compared
+//! across releases: the program, the number of peephole instructions that it executes, and for
+//! each backend, the median compile time and the median and fastest run times in nanoseconds,
+//! the instructions executed per second of median run time, and the error that stopped the
+//! program. The compile time is `null` for the AST, which isn't compiled, and for LLVM, which
+//! compiles as it runs; the error is `null` if the program finished.
+//!
+//! `bfi pipe A B ...` runs each of the programs in the files, or each `-e` expression, at once on
+//! its own thread and with its own memory, and streams each one's output into the next one's
+//! input, so that the programs work as a [pipeline](../bf/pipeline/index.html) of filters, as
+//! `bfi A | bfi B` would. The first program reads `--input FILE` or stdin, and the last one
+//! writes to stdout. They run on the peephole interpreter, or the backend named by `--backend`.
+//! Each program that fails is reported with its file, and then `bfi` exits with status 3.
+//!
+//! `bfi serve --listen ADDR FILE` accepts TCP connections on ADDR, such as `127.0.0.1:7000`, and
+//! runs the program once for each, with what the client sends as the input and the output sent
+//! back, so that a program can be a network service for a demo or a CTF. It runs at most
+//! `--workers` programs at once, 8 by default, and further connections wait until a run ends.
+//! The output is sent whenever the program waits for input, and when it stops. The program is
+//! compiled once, and each run gets a fresh memory and runs in a
+//! [sandbox](../bf/sandbox/index.html): it stops after `--timeout` seconds, 10 by default, and
+//! after `--max-steps` loop iterations or `--max-output` bytes if those are given. A client
+//! that keeps the program waiting for input past the timeout gets the end of input instead, and
+//! one that stops reading the output for that long ends the run. The connection closes when the
+//! program stops, and `bfi` prints how each run ended to stderr.
+//!
+//! `bfi profile` runs the program's peephole code under the [profiler](../bf/profile/index.html),
+//! and then prints to stderr the `--top` statements that took the most time of their own, with
+//! their share of the run, their own time and the time including the statements inside them,
+//! how often they ran, and the loop that they are in. The input comes from `--input FILE`, or is
+//! empty, and the output goes to stdout. With the `jit` feature, `bfi profile --jit` times only
+//! the loops, in JIT-compiled code, which is closer to how fast the program runs.
+//!
+//! If the `tracing` feature is enabled, `bfi -v` prints to stderr how long each compilation pass
+//! took, with the size of the program before and after it, followed by the time of the run. The
+//! lines come from the library's [`tracing`](https://docs.rs/tracing) events, and the passes
+//! inside a larger one are indented under it, after it finishes.
+//!
+//! `bfi compile --cfg dot` prints the control-flow graph of the form chosen by `--emit` as
+//! Graphviz DOT, and `--cfg json` prints it as JSON; both include counts of the blocks, edges,
+//! and loops.
+//!
+//! With `--macros`, the source goes through the [macro preprocessor](../bf/preprocess/index.html)
+//! before parsing, so that `+{40}` stands for forty `+`s and `$NAME` for the body of a
+//! `%define NAME` line, and a line `%include "FILE"` for the contents of FILE. Relative includes
+//! are found in the directory of the file that includes them, which for `-e` is the current
+//! directory and for several files is that of the first. Syntax errors in the expanded program
+//! point to the file and the place in it that the bracket came from.
+//!
+//! Syntax errors give the line and column of each unmatched bracket, counting from the start of
+//! the file it is in, and show that line with a caret under the bracket.
+//!
+//! `bfi --emit-asm` prints the program as x86-64 assembly for Linux instead of running it, with
+//! the [lowering](../bf/asm/index.html) that the JIT uses and a small runtime for the I/O, in
+//! the syntax of the GNU assembler, or of NASM with `--emit-asm=nasm`. The program's memory is
+//! `--size` cells, which wrap, and `,` stores 0 at the end of input.
+//!
+//! `bfi --emit-elf FILE` writes the program to FILE as a [tiny executable](../bf/elf/index.html)
+//! for x86-64 Linux instead of running it, with the same memory and conventions. It needs no
+//! assembler or linker, and programs come to around a kilobyte.
+//! `bfi --emit-exe FILE` does the same for x86-64 Windows, writing a [PE
+//! executable](../bf/pe/index.html) that calls `ReadFile` and `WriteFile`, with a `--size` of at
+//! most 2 GiB.
+//!
+//! If the `serde` feature is enabled, `bfi --dump-json STAGE` prints the program compiled to
+//! `STAGE`, which is one of the forms of `--emit` other than `bf`, as JSON instead of running
+//! it. Each instruction is an object with its name as the key, as in `{"Right":2}`, except
+//! that instructions without operands are just their names.
+//!
+//! `bfi compile --stats` prints statistics about the form chosen by `--emit` as JSON: the
+//! number of instructions of each kind, the code size, the loop count and depth, and how many
+//! cells the program can reach, or `null` if that isn't known without running it.
+//!
+//! `bfi fmt` prints the program's commands indented by loop depth, with `--indent` spaces per
+//! level and lines of up to `--width` characters, or on a single line with `--minify`. Comments
+//! are dropped unless `--comments` is given.
+//!
+//! `bfi compile --emit bf` prints the peephole-optimized program back as plain Brainfuck, with
+//! each optimized instruction expanded to the commands it stands for, so that the optimized
+//! program can run on other Brainfuck implementations.
+//!
 //! See [the library crate documentation](../bf/index.html) for more.
 extern crate bf;
 extern crate clap;
+use bf::asm::{self, Syntax};
 use bf::ast;
+use bf::bytecode::{self, Encoding};
+use bf::cache::Cache;
+use bf::common::{BfResult, Error};
+use bf::conformance::{self, Backend};
+use bf::counters::Counters;
+use bf::debugger::{self, Console, Debugger, Stop};
+use bf::dialects::Dialect;
+use bf::elf;
+use bf::format::{self, FormatOptions};
+use bf::fused;
+use bf::r#gen;
+use bf::graph::{write_json_string, ToGraph};
+use bf::lint;
+use bf::pe;
+use bf::peephole;
+use bf::pipeline;
+use bf::preprocess::{self, Expansion};
+use bf::profile::{self, Profile};
+use bf::random::RandomInput;
+use bf::replay::{Record, Recording};
+use bf::rle;
+use bf::sandbox::{Interrupt, SandboxConfig};
+use bf::semantics::{Eof, Overflow, Semantics};
+use bf::state::{State, TapeAlloc, DEFAULT_CAPACITY, INITIAL_GROWING_CAPACITY};
+use bf::stats::Stats;
+use bf::termination::{self, Termination};
+use bf::trace::TraceOptions;
 use bf::traits::*;
-use clap::Parser;
-use std::fs::File;
-use std::io::Read;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::cell::RefCell;
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, stdin, stdout, BufReader, BufWriter, Cursor, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
 use std::process::exit;
+use std::sync::atomic::AtomicBool;
+#[cfg(feature = "tracing")]
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+#[cfg(feature = "tracing")]
+use tracing::field::{Field, Visit};
+#[cfg(feature = "tracing")]
+use tracing::span;
+
+/// The most memory that `--auto-size` grows to, 1 GiB.
+const AUTO_SIZE_LIMIT: usize = 1 << 30;
+
+/// The smallest `--size` that is mapped lazily without `--tape-alloc`, 64 MiB.
+const LAZY_TAPE_SIZE: usize = 1 << 26;
+
+/// The flag that Ctrl-C raises for `--dump-on-interrupt`.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Stops the program at its next loop iteration once Ctrl-C raises it.
+const INTERRUPT: Interrupt = Interrupt::new(&INTERRUPTED);
+
+/// The cells around the pointer that `--dump-on-interrupt` prints.
+const DUMP_CELLS: usize = 9;
 
 #[derive(Debug, Clone, Parser)]
 #[clap(version = env!("CARGO_PKG_VERSION"))]
 #[clap(author = "Jesse A. Tov <jesse.tov@gmail.com>")]
 #[clap(name = "bfi")]
 #[clap(about = "A brainfuck interpreter")]
-struct NewOptions {
+#[clap(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+    /// With no subcommand, the arguments are the same as for `run`.
+    #[clap(flatten)]
+    run: RunOptions,
+}
+#[derive(Debug, Clone, Subcommand)]
+enum Command {
+    /// Run a program (the default)
+    Run(Box<RunOptions>),
+    /// Run programs with each one's output as the next one's input
+    Pipe(PipeOptions),
+    /// Run a program for each connection to a TCP address
+    Serve(ServeOptions),
+    /// Compile a program and print the result
+    Compile(CompileOptions),
+    /// Print a program's bytecode with addresses
+    Disasm(DisasmOptions),
+    /// Step through a program, reading commands from stdin
+    Debug(DebugOptions),
+    /// Time a program under every enabled backend
+    Bench(BenchOptions),
+    /// Report which statements of a program take the most time
+    Profile(ProfileOptions),
+    /// Reformat a program's source
+    Fmt(FmtOptions),
+    /// Report definite bugs in a program
+    Lint(LintOptions),
+    /// Report which loops provably stop, and bound the memory used
+    Analyze(AnalyzeOptions),
+    /// Write a program that prints the given text
+    Gen(GenOptions),
+}
+#[derive(Debug, Clone, Args)]
+struct SourceOptions {
     #[clap(
         help = "The source file(s) to interpret",
         conflicts_with = "expressions"
@@ -51,6 +384,59 @@ struct NewOptions {
         conflicts_with = "files"
     )]
     expressions: Option<Vec<String>>,
+    #[clap(
+        long = "dialect",
+        value_name = "NAME",
+        value_parser = clap::builder::PossibleValuesParser::new(Dialect::NAMES),
+        help = "The language the program is written in (default brainfuck)"
+    )]
+    dialect: Option<String>,
+    #[clap(
+        long = "tapes",
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Enable `@` to switch between N tapes"
+    )]
+    tapes: Option<u32>,
+    #[clap(
+        long = "max-depth",
+        value_name = "N",
+        default_value_t = ast::DEFAULT_MAX_DEPTH,
+        help = "Reject programs whose loops nest more than N deep (default 500)"
+    )]
+    max_depth: usize,
+    #[clap(
+        long = "macros",
+        help = "Expand macros, repetitions, and `%include`s before parsing",
+        conflicts_with = "dialect"
+    )]
+    macros: bool,
+    #[clap(
+        long = "profile",
+        value_name = "NAME",
+        value_parser = clap::builder::PossibleValuesParser::new(Semantics::PROFILES),
+        help = "The conventions the program expects (default default)"
+    )]
+    profile: Option<String>,
+}
+impl SourceOptions {
+    /// The conventions of the chosen profile.
+    fn semantics(&self) -> Semantics {
+        match self.profile {
+            Some(ref name) => Semantics::by_name(name).expect("profile names are checked by clap"),
+            None => Semantics::default(),
+        }
+    }
+
+    /// The number of tapes, which is one unless the multi-tape extension is enabled.
+    fn tapes(&self) -> usize {
+        self.tapes.map_or(1, |tapes| tapes as usize)
+    }
+}
+#[derive(Debug, Clone, Args)]
+struct RunOptions {
+    #[clap(flatten)]
+    source: SourceOptions,
     #[clap(
         short = 's',
         long = "size",
@@ -58,12 +444,213 @@ struct NewOptions {
         help = "Memory size in bytes (default 30,000)"
     )]
     memory_size: usize,
+    #[clap(
+        long = "auto-size",
+        help = "Start with a small memory and grow it as needed",
+        conflicts_with_all = &["memory_size", "verify"]
+    )]
+    auto_size: bool,
+    #[clap(
+        long = "tape-alloc",
+        value_name = "STRATEGY",
+        value_parser = clap::builder::PossibleValuesParser::new(TapeAlloc::ALL.map(TapeAlloc::name)),
+        help = "How to allocate the memory (default heap, or lazy from 64 MiB)",
+        conflicts_with = "auto_size"
+    )]
+    tape_alloc: Option<String>,
+    #[clap(
+        long = "eof",
+        value_name = "BEHAVIOR",
+        value_parser = clap::builder::PossibleValuesParser::new(Eof::ALL.map(Eof::name)),
+        help = "What `,` stores at the end of input (default zero)"
+    )]
+    eof: Option<String>,
+    #[clap(
+        long = "overflow",
+        value_name = "BEHAVIOR",
+        value_parser = clap::builder::PossibleValuesParser::new(Overflow::ALL.map(Overflow::name)),
+        help = "What `+` and `-` do past 255 or below 0 (default wrap)",
+        conflicts_with_all = &["verify", "trace", "heatmap", "cache"]
+    )]
+    overflow: Option<String>,
+    #[clap(long = "signed", help = "Make cells hold -128 to 127 instead of 0 to 255")]
+    signed: bool,
     #[clap(
         short = 'u',
         long = "unchecked",
         help = "Omit memory bounds checks in JIT"
     )]
     unchecked: bool,
+    #[clap(
+        long = "perf-map",
+        help = "Write /tmp/perf-PID.map naming the JIT's code for each loop"
+    )]
+    perf_map: bool,
+    #[clap(
+        long = "gdb-jit",
+        help = "Tell GDB or LLDB where the JIT's code for each loop is"
+    )]
+    gdb_jit: bool,
+    #[clap(
+        short = 'i',
+        long = "input",
+        value_name = "FILE",
+        help = "Read program input from FILE instead of stdin"
+    )]
+    input: Option<String>,
+    #[clap(
+        short = 'o',
+        long = "output",
+        value_name = "FILE",
+        help = "Write program output to FILE instead of stdout"
+    )]
+    output: Option<String>,
+    #[clap(
+        long = "append",
+        requires = "output",
+        help = "Append to the output file instead of truncating it"
+    )]
+    append: bool,
+    #[clap(
+        long = "record",
+        value_name = "FILE",
+        help = "Save the run's input and output to FILE",
+        conflicts_with = "replay"
+    )]
+    record: Option<String>,
+    #[clap(
+        long = "replay",
+        value_name = "FILE",
+        help = "Take the input from a run saved with --record",
+        conflicts_with = "input"
+    )]
+    replay: Option<String>,
+    #[clap(
+        long = "random-input",
+        value_name = "SEED",
+        num_args = 0..=1,
+        require_equals = true,
+        help = "Make `,` read random bytes chosen by SEED (default from the clock)",
+        conflicts_with_all = &["input", "replay"]
+    )]
+    random_input: Option<Option<u64>>,
+    #[clap(
+        long = "trace",
+        value_name = "FILE",
+        help = "Write a JSON Lines record of each instruction to FILE",
+        conflicts_with_all = &["verify", "stats", "pass", "unchecked"]
+    )]
+    trace: Option<String>,
+    #[clap(
+        long = "trace-every",
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "trace",
+        help = "Trace only every Nth instruction (default 1)"
+    )]
+    trace_every: u64,
+    #[clap(
+        long = "trace-limit",
+        value_name = "N",
+        requires = "trace",
+        help = "Stop tracing after N records"
+    )]
+    trace_limit: Option<u64>,
+    #[clap(
+        long = "heatmap",
+        help = "Print how often each cell was read and written to stderr",
+        conflicts_with_all = &["verify", "stats", "pass", "unchecked", "trace"]
+    )]
+    heatmap: bool,
+    #[clap(
+        long = "verify",
+        help = "Check that all backends agree on the program",
+        conflicts_with_all = &["pass", "unchecked", "tapes", "record", "replay"]
+    )]
+    verify: bool,
+    #[clap(
+        long = "stats",
+        help = "Print execution statistics to stderr after the run",
+        conflicts_with = "verify"
+    )]
+    stats: bool,
+    #[cfg(feature = "tracing")]
+    #[clap(
+        short = 'v',
+        long = "verbose",
+        help = "Print the size and time of each pass to stderr"
+    )]
+    verbose: bool,
+    #[clap(
+        long = "max-output",
+        value_name = "BYTES",
+        help = "Stop the program once it writes more than BYTES bytes",
+        conflicts_with_all = &["verify", "stats", "trace", "heatmap", "unchecked"]
+    )]
+    max_output: Option<u64>,
+    #[clap(
+        long = "dump-on-interrupt",
+        help = "On Ctrl-C, stop and print the pointer and nearby cells to stderr",
+        conflicts_with_all = &["verify", "trace", "heatmap", "unchecked"]
+    )]
+    dump_on_interrupt: bool,
+    #[clap(
+        long = "exit-cell",
+        value_name = "N",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "0",
+        help = "Exit with the final value of cell N (default 0)",
+        conflicts_with_all = &["verify", "trace", "heatmap"]
+    )]
+    exit_cell: Option<usize>,
+    #[clap(
+        long = "cache",
+        value_name = "DIR",
+        help = "Keep the compiled bytecode in DIR, and reuse it for unchanged programs",
+        conflicts_with_all = &["verify", "trace", "heatmap"]
+    )]
+    cache: Option<String>,
+    #[clap(
+        long = "emit-asm",
+        value_name = "SYNTAX",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "gas",
+        value_parser = clap::builder::PossibleValuesParser::new(Syntax::ALL.map(Syntax::name)),
+        help = "Print the program as x86-64 assembly instead of running it",
+        conflicts_with_all = &["verify", "stats", "auto_size"]
+    )]
+    emit_asm: Option<String>,
+    #[clap(
+        long = "emit-elf",
+        value_name = "FILE",
+        help = "Write the program to FILE as a tiny x86-64 Linux executable",
+        conflicts_with_all = &["verify", "stats", "auto_size", "emit_asm"]
+    )]
+    emit_elf: Option<String>,
+    #[clap(
+        long = "emit-exe",
+        value_name = "FILE",
+        help = "Write the program to FILE as a tiny x86-64 Windows executable",
+        conflicts_with_all = &["verify", "stats", "auto_size", "emit_asm", "emit_elf"]
+    )]
+    emit_exe: Option<String>,
+    #[cfg(feature = "serde")]
+    #[clap(
+        long = "dump-json",
+        value_enum,
+        value_name = "STAGE",
+        help = "Print the program compiled to STAGE as JSON instead of running it",
+        conflicts_with_all = &["verify", "stats"]
+    )]
+    dump_json: Option<Emit>,
+    #[clap(flatten)]
+    pass: PassOptions,
+}
+#[derive(Debug, Clone, Args)]
+struct PassOptions {
     #[clap(long = "ast", help = "Interpret the unoptimized AST", group = "pass")]
     ast: bool,
     #[clap(
@@ -74,6 +661,24 @@ struct NewOptions {
     rle: bool,
     #[clap(long = "byte", help = "Compile AST to bytecode", group = "pass")]
     bytecode: bool,
+    #[clap(
+        long = "fused",
+        help = "Compile AST to bytecode with superinstructions",
+        group = "pass"
+    )]
+    fused: bool,
+    #[clap(
+        long = "packed",
+        help = "Compile AST to packed 32-bit bytecode",
+        group = "pass"
+    )]
+    packed: bool,
+    #[clap(
+        long = "threaded",
+        help = "Compile AST to direct-threaded bytecode",
+        group = "pass"
+    )]
+    threaded: bool,
     #[clap(
         long = "peep",
         help = "Interpret the peephole-optimized AST",
@@ -88,100 +693,548 @@ struct NewOptions {
     #[cfg(feature = "cranelift")]
     #[clap(long = "cranelift", help = "JIT using Cranelift", group = "pass")]
     cranelift: bool,
+    #[clap(
+        long = "backend",
+        value_name = "NAME",
+        help = "Run with the named backend instead of choosing one by flag",
+        value_parser = clap::builder::PossibleValuesParser::new(Backend::all().into_iter().map(Backend::name)),
+        group = "pass"
+    )]
+    backend: Option<String>,
+}
+#[derive(Debug, Clone, Args)]
+struct CompileOptions {
+    #[clap(flatten)]
+    source: SourceOptions,
+    #[clap(
+        long = "emit",
+        value_enum,
+        default_value_t = Emit::Peephole,
+        help = "The form to print"
+    )]
+    emit: Emit,
+    #[clap(
+        long = "cfg",
+        value_enum,
+        value_name = "FORMAT",
+        help = "Print the control-flow graph of the form instead"
+    )]
+    cfg: Option<CfgFormat>,
+    #[clap(
+        long = "stats",
+        conflicts_with = "cfg",
+        help = "Print statistics about the form as JSON instead"
+    )]
+    stats: bool,
+}
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum CfgFormat {
+    Dot,
+    Json,
+}
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Emit {
+    Ast,
+    Rle,
+    Peephole,
+    Bytecode,
+    Fused,
+    Bf,
+}
+#[derive(Debug, Clone, Args)]
+struct DisasmOptions {
+    #[clap(flatten)]
+    source: SourceOptions,
+    #[clap(long = "fused", help = "Show bytecode with superinstructions")]
+    fused: bool,
+}
+#[derive(Debug, Clone, Args)]
+struct DebugOptions {
+    #[clap(flatten)]
+    source: SourceOptions,
+    #[clap(
+        short = 's',
+        long = "size",
+        default_value_t = 30000,
+        help = "Memory size in bytes (default 30,000)"
+    )]
+    memory_size: usize,
+    #[clap(
+        long = "eof",
+        value_name = "BEHAVIOR",
+        value_parser = clap::builder::PossibleValuesParser::new(Eof::ALL.map(Eof::name)),
+        help = "What `,` stores at the end of input (default zero)"
+    )]
+    eof: Option<String>,
+    #[clap(
+        short = 'i',
+        long = "input",
+        value_name = "FILE",
+        help = "Read program input from FILE (default empty)"
+    )]
+    input: Option<String>,
+    #[clap(long = "signed", help = "Show cells as -128 to 127 instead of 0 to 255")]
+    signed: bool,
+    #[cfg(feature = "tui")]
+    #[clap(long = "tui", help = "Show the source, tape, and output full screen")]
+    tui: bool,
+}
+#[derive(Debug, Clone, Args)]
+struct FmtOptions {
+    #[clap(flatten)]
+    source: SourceOptions,
+    #[clap(long = "minify", help = "Write the program on a single line")]
+    minify: bool,
+    #[clap(
+        long = "indent",
+        default_value_t = 2,
+        conflicts_with = "minify",
+        help = "Spaces to indent each loop level"
+    )]
+    indent: usize,
+    #[clap(
+        long = "width",
+        default_value_t = 80,
+        conflicts_with = "minify",
+        help = "The longest line of commands, or 0 for no limit"
+    )]
+    width: usize,
+    #[clap(long = "comments", help = "Keep comments")]
+    comments: bool,
+}
+#[derive(Debug, Clone, Args)]
+struct LintOptions {
+    #[clap(flatten)]
+    source: SourceOptions,
+}
+#[derive(Debug, Clone, Args)]
+struct AnalyzeOptions {
+    #[clap(flatten)]
+    source: SourceOptions,
+}
+#[derive(Debug, Clone, Args)]
+struct GenOptions {
+    #[clap(
+        long = "text",
+        value_name = "TEXT",
+        help = "The text for the program to print"
+    )]
+    text: String,
+}
+#[derive(Debug, Clone, Args)]
+struct ProfileOptions {
+    #[clap(flatten)]
+    source: SourceOptions,
+    #[clap(
+        short = 's',
+        long = "size",
+        default_value_t = 30000,
+        help = "Memory size in bytes (default 30,000)"
+    )]
+    memory_size: usize,
+    #[clap(
+        long = "eof",
+        value_name = "BEHAVIOR",
+        value_parser = clap::builder::PossibleValuesParser::new(Eof::ALL.map(Eof::name)),
+        help = "What `,` stores at the end of input (default zero)"
+    )]
+    eof: Option<String>,
+    #[clap(
+        short = 'i',
+        long = "input",
+        value_name = "FILE",
+        help = "Read program input from FILE (default empty)"
+    )]
+    input: Option<String>,
+    #[clap(
+        long = "top",
+        value_name = "N",
+        default_value_t = 20,
+        help = "The number of statements to report"
+    )]
+    top: usize,
+    #[cfg(feature = "jit")]
+    #[clap(long = "jit", help = "Time only the loops, in JIT-compiled code")]
+    jit: bool,
+}
+#[derive(Debug, Clone, Args)]
+struct PipeOptions {
+    #[clap(flatten)]
+    source: SourceOptions,
+    #[clap(
+        short = 's',
+        long = "size",
+        default_value_t = 30000,
+        help = "Memory size in bytes for each program (default 30,000)"
+    )]
+    memory_size: usize,
+    #[clap(
+        long = "eof",
+        value_name = "BEHAVIOR",
+        value_parser = clap::builder::PossibleValuesParser::new(Eof::ALL.map(Eof::name)),
+        help = "What `,` stores at the end of input (default zero)"
+    )]
+    eof: Option<String>,
+    #[clap(
+        short = 'i',
+        long = "input",
+        value_name = "FILE",
+        help = "Read the first program's input from FILE instead of stdin"
+    )]
+    input: Option<String>,
+    #[clap(
+        long = "backend",
+        value_name = "NAME",
+        help = "Run each program with the named backend (default peephole)",
+        value_parser = clap::builder::PossibleValuesParser::new(Backend::all().into_iter().map(Backend::name))
+    )]
+    backend: Option<String>,
+}
+#[derive(Debug, Clone, Args)]
+struct ServeOptions {
+    #[clap(flatten)]
+    source: SourceOptions,
+    #[clap(
+        long = "listen",
+        value_name = "ADDR",
+        help = "The address to accept connections on, such as 127.0.0.1:7000"
+    )]
+    listen: String,
+    #[clap(
+        short = 's',
+        long = "size",
+        default_value_t = 30000,
+        help = "Memory size in bytes for each run (default 30,000)"
+    )]
+    memory_size: usize,
+    #[clap(
+        long = "eof",
+        value_name = "BEHAVIOR",
+        value_parser = clap::builder::PossibleValuesParser::new(Eof::ALL.map(Eof::name)),
+        help = "What `,` stores at the end of input (default zero)"
+    )]
+    eof: Option<String>,
+    #[clap(
+        long = "timeout",
+        value_name = "SECONDS",
+        default_value_t = 10,
+        help = "Stop each run after SECONDS seconds, or 0 for no limit (default 10)"
+    )]
+    timeout: u64,
+    #[clap(
+        long = "max-steps",
+        value_name = "N",
+        help = "Stop each run after N loop iterations"
+    )]
+    max_steps: Option<u64>,
+    #[clap(
+        long = "max-output",
+        value_name = "BYTES",
+        help = "Stop each run once it writes more than BYTES bytes"
+    )]
+    max_output: Option<u64>,
+    #[clap(
+        long = "workers",
+        value_name = "N",
+        default_value_t = 8,
+        help = "Run at most N connections at once (default 8)"
+    )]
+    workers: usize,
+}
+#[derive(Debug, Clone, Args)]
+struct BenchOptions {
+    #[clap(flatten)]
+    source: SourceOptions,
+    #[clap(
+        short = 's',
+        long = "size",
+        default_value_t = 30000,
+        help = "Memory size in bytes (default 30,000)"
+    )]
+    memory_size: usize,
+    #[clap(
+        long = "eof",
+        value_name = "BEHAVIOR",
+        value_parser = clap::builder::PossibleValuesParser::new(Eof::ALL.map(Eof::name)),
+        help = "What `,` stores at the end of input (default zero)"
+    )]
+    eof: Option<String>,
+    #[clap(
+        short = 'i',
+        long = "input",
+        value_name = "FILE",
+        help = "Read program input from FILE (default empty)"
+    )]
+    input: Option<String>,
+    #[clap(
+        long = "warmup",
+        default_value_t = 1,
+        help = "Untimed runs before timing each backend"
+    )]
+    warmup: usize,
+    #[clap(
+        short = 'n',
+        long = "iterations",
+        default_value_t = 5,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Timed runs for each backend"
+    )]
+    iterations: u64,
+    #[clap(long = "json", help = "Print the timings as JSON")]
+    json: bool,
 }
 #[derive(Debug, Clone)]
 struct Options {
-    program_text: Vec<u8>,
     memory_size: Option<usize>,
-    compiler_pass: Pass,
+    /// Whether the memory grows from a small size instead.
+    auto_size: bool,
+    /// How to allocate the memory, unless it grows.
+    tape_alloc: TapeAlloc,
+    tapes: usize,
+    semantics: Semantics,
+    /// Input from the source, which comes before the input proper.
+    source_input: Vec<u8>,
+    compiler_pass: Backend,
     unchecked: bool,
+    /// Whether to tell `perf` where the JIT put each loop.
+    perf_map: bool,
+    /// Whether to tell a debugger where the JIT put each loop.
+    gdb_jit: bool,
+    input: Option<String>,
+    output: Option<String>,
+    append: bool,
+    /// Where to save the run's input and output.
+    record: Option<String>,
+    /// The run to replay.
+    replay: Option<Recording>,
+    /// The seed of the random input, if the input is random.
+    random_input: Option<u64>,
+    stats: bool,
+    /// The most bytes that the program may write.
+    max_output: Option<u64>,
+    /// Whether Ctrl-C stops the program and prints its state.
+    dump_on_interrupt: bool,
+    /// The cell whose final value is the exit status.
+    exit_cell: Option<usize>,
 }
 impl Options {
-    fn new(options: &NewOptions) -> Options {
-        let compiler_pass = Pass::new(options);
+    fn new(options: &RunOptions, source_input: Vec<u8>) -> Options {
+        let compiler_pass = backend(&options.pass);
         Options {
-            program_text: Vec::new(),
             memory_size: Some(options.memory_size),
+            auto_size: options.auto_size,
+            tape_alloc: match options.tape_alloc {
+                Some(ref alloc) => alloc.parse().expect("tape allocations are checked by clap"),
+                None if options.memory_size >= LAZY_TAPE_SIZE => TapeAlloc::Lazy,
+                None => TapeAlloc::Heap,
+            },
+            tapes: options.source.tapes(),
+            semantics: Semantics {
+                overflow: match options.overflow {
+                    Some(ref overflow) => overflow
+                        .parse()
+                        .expect("overflow behaviors are checked by clap"),
+                    None => Overflow::default(),
+                },
+                signed: options.signed,
+                ..semantics(&options.source, &options.eof)
+            },
+            source_input,
             compiler_pass,
             unchecked: options.unchecked,
+            perf_map: options.perf_map,
+            gdb_jit: options.gdb_jit,
+            input: options.input.clone(),
+            output: options.output.clone(),
+            append: options.append,
+            record: options.record.clone(),
+            replay: options.replay.as_ref().map(|path| load_recording(path)),
+            random_input: options
+                .random_input
+                .map(|seed| seed.unwrap_or_else(clock_seed)),
+            stats: options.stats,
+            max_output: options.max_output,
+            dump_on_interrupt: options.dump_on_interrupt,
+            exit_cell: options.exit_cell,
         }
     }
 }
-#[derive(Debug, Clone, Copy)]
-enum Pass {
-    Ast,
-    Rle,
-    Bytecode,
-    Peephole,
-    Jit,
+/// The backend chosen by `--backend` or by one of the pass flags, or else the peephole pass.
+fn backend(options: &PassOptions) -> Backend {
+    if let Some(ref name) = options.backend {
+        return name.parse().expect("backend names are checked by clap");
+    }
+    if options.ast {
+        return Backend::Ast;
+    }
+    if options.rle {
+        return Backend::Rle;
+    }
+    if options.bytecode {
+        return Backend::Bytecode(Encoding::Boxed);
+    }
+    if options.fused {
+        return Backend::Fused;
+    }
+    if options.packed {
+        return Backend::Bytecode(Encoding::Packed);
+    }
+    if options.threaded {
+        return Backend::Bytecode(Encoding::Threaded);
+    }
+    if options.peephole {
+        return Backend::Peephole;
+    }
+    if options.jit {
+        #[cfg(feature = "jit")]
+        return Backend::Jit;
+        #[cfg(not(feature = "jit"))]
+        error_exit(1, "error: jit feature not enabled.");
+    }
     #[cfg(feature = "llvm")]
-    Llvm,
+    if options.llvm {
+        return Backend::Llvm;
+    }
     #[cfg(feature = "cranelift")]
-    Cranelift,
+    if options.cranelift {
+        return Backend::Cranelift;
+    }
+    Backend::Peephole
 }
-impl Pass {
-    fn new(options: &NewOptions) -> Pass {
-        if options.ast {
-            return Pass::Ast;
-        }
-        if options.rle {
-            return Pass::Rle;
-        }
-        if options.bytecode {
-            return Pass::Bytecode;
-        }
-        if options.peephole {
-            return Pass::Peephole;
-        }
-        if options.jit {
-            return Pass::Jit;
-        }
-        #[cfg(feature = "llvm")]
-        if options.llvm {
-            return Pass::Llvm;
-        }
-        #[cfg(feature = "cranelift")]
-        if options.cranelift {
-            return Pass::Cranelift;
-        }
-        Pass::Peephole
+
+/// Whether the backend can run in a sandbox, for `--max-output` and `--dump-on-interrupt`.
+fn can_sandbox(backend: Backend) -> bool {
+    match backend {
+        Backend::Ast | Backend::Rle | Backend::Peephole => true,
+        Backend::Bytecode(encoding) => encoding == Encoding::Boxed,
+        #[cfg(feature = "jit")]
+        Backend::Jit => true,
+        _ => false,
     }
 }
+
 fn main() {
-    let result = NewOptions::parse();
+    let cli = Cli::parse();
+    match cli.command {
+        None => run(&cli.run),
+        Some(Command::Run(ref options)) => run(options),
+        Some(Command::Pipe(ref options)) => pipe(options),
+        Some(Command::Serve(ref options)) => serve(options),
+        Some(Command::Compile(ref options)) => compile(options),
+        Some(Command::Disasm(ref options)) => disasm(options),
+        Some(Command::Debug(ref options)) => debug(options),
+        Some(Command::Bench(ref options)) => bench(options),
+        Some(Command::Profile(ref options)) => run_profile(options),
+        Some(Command::Fmt(ref options)) => fmt(options),
+        Some(Command::Lint(ref options)) => lint(options),
+        Some(Command::Analyze(ref options)) => analyze(options),
+        Some(Command::Gen(ref options)) => generate(options),
+    }
+}
+
+fn run(result: &RunOptions) {
     if result.memory_size == 0 {
         error_exit(1, "error: memory size must be at least 1.");
     }
-    let mut options = Options::new(&result);
-    if let Option::Some(exprs) = result.expressions {
-        for e in exprs {
-            options.program_text.extend(e.as_bytes());
-        }
-    } else if let Option::Some(files) = result.files {
-        for f in files {
-            let mut file =
-                File::open(f.clone()).unwrap_or_else(|e| error_exit(1, &format!("{}: {}", e, f)));
-            file.read_to_end(&mut options.program_text)
-                .unwrap_or_else(|e| error_exit(1, &format!("{}: {}", e, f)));
-        }
-    } else {
-        error_exit(1, "error: no program given.");
+    #[cfg(feature = "tracing")]
+    if result.verbose {
+        let _ = tracing::subscriber::set_global_default(Breakdown::default());
+    }
+    let start = Instant::now();
+    let (program, source_input) = parse_with_input(&result.source);
+    let options = Options::new(result, source_input);
+    if let Some(ref syntax) = result.emit_asm {
+        let syntax = syntax.parse().expect("assembly syntaxes are checked by clap");
+        emit_asm(&program, &options, syntax);
+        return;
+    }
+    if let Some(ref path) = result.emit_elf {
+        emit_elf(&program, &options, path);
+        return;
+    }
+    if let Some(ref path) = result.emit_exe {
+        emit_exe(&program, &options, path);
+        return;
+    }
+    #[cfg(feature = "serde")]
+    if let Some(stage) = result.dump_json {
+        dump_json(&program, stage);
+        return;
+    }
+    if result.verify {
+        verify(&program, &options);
+        return;
+    }
+    if let Some(ref path) = result.trace {
+        let trace_options = TraceOptions {
+            every: result.trace_every,
+            limit: result.trace_limit,
+        };
+        trace(&program, &options, path, &trace_options);
+        return;
+    }
+    if result.heatmap {
+        heatmap(&program, &options);
+        return;
+    }
+    if options.tapes > 1 && options.compiler_pass.is_native() {
+        error_exit(2, "error: native backends support only one tape.");
+    }
+    if options.auto_size && options.compiler_pass.is_native() {
+        error_exit(2, "error: native backends can't grow the memory for --auto-size.");
+    }
+    if options.max_output.is_some() && !can_sandbox(options.compiler_pass) {
+        error_exit(2, "error: --max-output needs the AST, RLE, peephole, bytecode, or JIT pass.");
+    }
+    if let Some(cell) = options.exit_cell {
+        if options.compiler_pass.name() == "llvm" {
+            error_exit(2, "error: LLVM doesn't support --exit-cell.");
+        }
+        if !options.auto_size && cell >= result.memory_size {
+            error_exit(1, "error: --exit-cell must be less than the memory size.");
+        }
+    }
+    if options.dump_on_interrupt && !can_sandbox(options.compiler_pass) {
+        error_exit(2, "error: --dump-on-interrupt needs the AST, RLE, peephole, bytecode, or JIT pass.");
+    }
+    let overflow = options.semantics.overflow;
+    if !options.compiler_pass.supports_overflow(overflow) {
+        error_exit(2, "error: Cranelift and LLVM support only --overflow wrap.");
+    }
+    let sandboxed = options.max_output.is_some() || options.dump_on_interrupt;
+    if overflow != Overflow::Wrap
+        && options.compiler_pass.name() == "jit"
+        && (options.stats || sandboxed)
+    {
+        error_exit(2, "error: the JIT supports --overflow only without --stats, --max-output, or --dump-on-interrupt.");
+    }
+    if options.perf_map && options.compiler_pass.name() != "jit" {
+        error_exit(2, "error: --perf-map needs the JIT pass.");
+    }
+    if options.gdb_jit && options.compiler_pass.name() != "jit" {
+        error_exit(2, "error: --gdb-jit needs the JIT pass.");
+    }
+    if options.dump_on_interrupt {
+        catch_interrupts();
+    }
+    if let Some(ref dir) = result.cache {
+        run_cached(&program, &options, dir, start);
+        return;
     }
-    let program = parse(&options);
     match options.compiler_pass {
-        Pass::Ast => {
-            interpret(&*program, &options);
+        Backend::Ast => {
+            interpret_counting(&*program, &options, start);
         }
 
-        Pass::Rle => {
-            let program = program.rle_compile();
-            interpret(&*program, &options);
+        Backend::Rle => {
+            let program = rle::compile_for(&program, overflow);
+            interpret_counting(&*program, &options, start);
         }
 
-        Pass::Peephole => {
+        Backend::Peephole => {
             if !options.unchecked {
-                let program = program.peephole_compile();
-                interpret(&*program, &options);
+                let program = peephole_for(&program, overflow);
+                interpret_counting(&*program, &options, start);
             } else {
                 error_exit(
                     2,
@@ -189,47 +1242,1541 @@ fn main() {
                 );
             }
         }
-        Pass::Bytecode => {
-            let program = program.bytecode_compile();
-            interpret(&*program, &options);
+        Backend::Bytecode(Encoding::Boxed) => {
+            let program = bytecode::compile(&peephole_for(&program, overflow));
+            interpret_counting(&*program, &options, start);
+        }
+
+        #[cfg(feature = "jit")]
+        Backend::Jit => run_jit(&*peephole_for(&program, overflow), &options, start),
+
+        #[cfg(feature = "llvm")]
+        Backend::Llvm => {
+            if options.semantics.eof != Eof::Zero {
+                error_exit(2, "error: LLVM supports only --eof zero.");
+            }
+            let compile_time = start.elapsed();
+            let start = Instant::now();
+            let result = with_io(&options, |input, output| {
+                program.llvm_run_with(options.memory_size, input, output)
+            });
+            finish(result, &options, compile_time, start.elapsed(), None);
+        }
+
+        backend => {
+            let program = backend.compile_for(&program, overflow, options.semantics.signed);
+            interpret(&program, &options, start);
+        }
+    }
+}
+
+/// Compiles a program to the peephole AST for cells that overflow as `overflow` says.
+fn peephole_for(program: &ast::Program, overflow: Overflow) -> Box<peephole::Program> {
+    peephole::compile_for(&rle::compile_for(program, overflow), overflow)
+}
+
+/// Compiles a program with the JIT and runs it, counting what it does if we are reporting
+/// statistics, or in a sandbox if the output is limited or Ctrl-C stops it.
+#[cfg(feature = "jit")]
+fn run_jit<P: JitCompilable + ?Sized>(program: &P, options: &Options, start: Instant) {
+    if options.max_output.is_some() || options.dump_on_interrupt {
+        let mut program = program.jit_compile_sandboxed();
+        describe_code(&mut program, options);
+        interpret_sandboxed(&program, options, start);
+    } else if options.stats {
+        let mut program = program.jit_compile_counting(!options.unchecked);
+        describe_code(&mut program, options);
+        interpret_counting(&program, options, start);
+    } else {
+        let Semantics {
+            overflow, signed, ..
+        } = options.semantics;
+        let mut program = program.with_peephole(|program| {
+            bf::jit::compile_for(program, !options.unchecked, overflow, signed)
+        });
+        describe_code(&mut program, options);
+        interpret(&program, options, start);
+    }
+}
+
+/// Runs a program from the bytecode in the cache in `dir`, compiling and saving it if it isn't
+/// there, for `--cache`.
+fn run_cached(program: &ast::Program, options: &Options, dir: &str, start: Instant) {
+    let backend = options.compiler_pass;
+    let supported = matches!(
+        backend,
+        Backend::Peephole | Backend::Bytecode(_) | Backend::Fused
+    ) || backend.name() == "jit";
+    if !supported {
+        error_exit(2, "error: --cache needs the peephole, bytecode, fused, or JIT pass.");
+    }
+    if options.unchecked && backend == Backend::Peephole {
+        error_exit(2, "unchecked can not be used with the default pass (peephole)");
+    }
+
+    let mut cache = Cache::new(dir);
+    let bytecode = cache.compile_ast(program);
+    if options.stats {
+        let stats = cache.stats();
+        let found = match (stats.hits, stats.stale) {
+            (0, 0) => "miss",
+            (0, _) => "stale",
+            _ => "hit",
+        };
+        let saved = if stats.write_errors > 0 {
+            " (not saved)"
+        } else {
+            ""
+        };
+        eprintln!("cache:           {}{}", found, saved);
+    }
+    let lift = || bytecode::lift(&bytecode).expect("compiled bytecode lifts");
+
+    match backend {
+        Backend::Peephole => interpret_counting(&*lift(), options, start),
+        Backend::Bytecode(Encoding::Boxed) => interpret_counting(&*bytecode, options, start),
+        Backend::Bytecode(encoding) => {
+            interpret(&bytecode::encode(&bytecode, encoding), options, start)
+        }
+        Backend::Fused => interpret(&*fused::compile(&bytecode), options, start),
+        #[cfg(feature = "jit")]
+        Backend::Jit => run_jit(&*lift(), options, start),
+        _ => unreachable!("the backend was checked"),
+    }
+}
+
+/// Prints the program as assembly.
+fn emit_asm(program: &ast::Program, options: &Options, syntax: Syntax) {
+    check_emitted(options, "--emit-asm");
+    let memory_size = options.memory_size.unwrap_or(DEFAULT_CAPACITY);
+    print!("{}", asm::emit(&program.peephole_compile(), syntax, memory_size));
+}
+
+/// Writes the program to `path` as a Linux executable.
+fn emit_elf(program: &ast::Program, options: &Options, path: &str) {
+    check_emitted(options, "--emit-elf");
+    let memory_size = options.memory_size.unwrap_or(DEFAULT_CAPACITY);
+    write_executable(path, &elf::emit(&program.peephole_compile(), memory_size));
+}
+
+/// Writes the program to `path` as a Windows executable.
+fn emit_exe(program: &ast::Program, options: &Options, path: &str) {
+    check_emitted(options, "--emit-exe");
+    let memory_size = options.memory_size.unwrap_or(DEFAULT_CAPACITY);
+    if memory_size > pe::MAX_MEMORY_SIZE {
+        error_exit(2, "error: --emit-exe supports a --size of at most 2 GiB.");
+    }
+    write_executable(path, &pe::emit(&program.peephole_compile(), memory_size));
+}
+
+/// Writes `executable` to `path`, where it can be run.
+fn write_executable(path: &str, executable: &[u8]) {
+    let write = || -> io::Result<()> {
+        let mut file = OpenOptions::new();
+        file.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut file, 0o755);
+        file.open(path)?.write_all(executable)
+    };
+    write().unwrap_or_else(|e| error_exit(1, &format!("{}: {}", e, path)));
+}
+
+/// Exits if the program needs more than the runtime of an emitted program does, as `flag` says.
+fn check_emitted(options: &Options, flag: &str) {
+    if options.tapes > 1 {
+        error_exit(2, &format!("error: {} supports only one tape.", flag));
+    }
+    if options.semantics.overflow != Overflow::Wrap {
+        error_exit(2, &format!("error: {} supports only --overflow wrap.", flag));
+    }
+    if options.semantics.eof != Eof::Zero {
+        error_exit(2, &format!("error: {} supports only --eof zero.", flag));
+    }
+}
+
+#[cfg(feature = "serde")]
+fn dump_json(program: &ast::Program, stage: Emit) {
+    let json = match stage {
+        Emit::Ast => serde_json::to_string(program),
+        Emit::Rle => serde_json::to_string(&*program.rle_compile()),
+        Emit::Peephole => serde_json::to_string(&*program.peephole_compile()),
+        Emit::Bytecode => serde_json::to_string(&*program.bytecode_compile()),
+        Emit::Fused => serde_json::to_string(&*program.fused_compile()),
+        Emit::Bf => error_exit(1, "error: --dump-json needs a program form, not bf."),
+    };
+    println!("{}", json.expect("programs serialize to JSON"));
+}
+
+fn trace(program: &ast::Program, options: &Options, path: &str, trace_options: &TraceOptions) {
+    let program = program.bytecode_compile();
+    let sink = File::create(path).unwrap_or_else(|e| error_exit(1, &format!("{}: {}", e, path)));
+    let result = with_io(options, |input, output| {
+        bf::trace::trace(
+            program,
+            new_state(options),
+            input,
+            output,
+            BufWriter::new(sink),
+            trace_options,
+        )
+    });
+    result
+        .unwrap_or_else(|e| error_exit(1, &format!("{}: {}", e, path)))
+        .unwrap_or_else(|e| error_exit(3, &format!("runtime error: {}.", e)))
+}
+
+fn heatmap(program: &ast::Program, options: &Options) {
+    let program = program.bytecode_compile();
+    let mut state = new_state(options);
+    state.track_heat();
+    let (heatmap, result) = with_io(options, |input, output| {
+        let mut debugger = Debugger::new(program, state, input, output);
+        let result = loop {
+            match debugger.run() {
+                Stop::Halted => break Ok(()),
+                Stop::Error(error) => break Err(error),
+                // There are no breakpoints or watchpoints.
+                _ => {}
+            }
+        };
+        let heatmap = debugger.state().heatmap().expect("the state tracks heat");
+        (heatmap.render(terminal_size("COLUMNS", 80)), result)
+    });
+    eprint!("{}", heatmap);
+    result.unwrap_or_else(|e| error_exit(3, &format!("runtime error: {}.", e)))
+}
+
+fn compile(options: &CompileOptions) {
+    let program = parse(&options.source);
+    if let Some(format) = options.cfg {
+        let graph = match options.emit {
+            Emit::Ast => program.to_graph(),
+            Emit::Rle => program.rle_compile().to_graph(),
+            Emit::Peephole => program.peephole_compile().to_graph(),
+            Emit::Bytecode => program.bytecode_compile().to_graph(),
+            Emit::Fused => program.fused_compile().to_graph(),
+            Emit::Bf => error_exit(1, "error: --cfg needs a program form, not bf."),
+        };
+        match format {
+            CfgFormat::Dot => print!("{}", graph.to_dot()),
+            CfgFormat::Json => println!("{}", graph.to_json()),
+        }
+        return;
+    }
+
+    if options.stats {
+        let stats = match options.emit {
+            Emit::Ast => Stats::from(&*program),
+            Emit::Rle => Stats::from(&*program.rle_compile()),
+            Emit::Peephole => Stats::from(&*program.peephole_compile()),
+            Emit::Bytecode => Stats::from(&*program.bytecode_compile()),
+            Emit::Fused => Stats::from(&*program.fused_compile()),
+            Emit::Bf => error_exit(1, "error: --stats needs a program form, not bf."),
+        };
+        println!("{}", stats.to_json());
+        return;
+    }
+
+    match options.emit {
+        Emit::Ast => println!("{:#?}", program),
+        Emit::Rle => println!("{:#?}", program.rle_compile()),
+        Emit::Peephole => println!("{:#?}", program.peephole_compile()),
+        Emit::Bytecode => println!("{:#?}", program.bytecode_compile()),
+        Emit::Fused => println!("{:#?}", program.fused_compile()),
+        Emit::Bf => println!("{}", program.peephole_compile().to_source()),
+    }
+}
+
+fn disasm(options: &DisasmOptions) {
+    let program = parse(&options.source);
+    if options.fused {
+        for (pc, instruction) in program.fused_compile().iter().enumerate() {
+            println!("{:6}  {:?}", pc, instruction);
+        }
+    } else {
+        for (pc, instruction) in program.bytecode_compile().iter().enumerate() {
+            println!("{:6}  {:?}", pc, instruction);
+        }
+    }
+}
+
+fn debug(options: &DebugOptions) {
+    if options.memory_size == 0 {
+        error_exit(1, "error: memory size must be at least 1.");
+    }
+    // Parsing the usual way reports any syntax errors as `run` does.
+    let (_, mut input) = parse_with_input(&options.source);
+    let source = read_source(&options.source);
+    let (source, _) = options.source.semantics().split_source(&source);
+    let source = match options.source.dialect {
+        Some(ref name) => Dialect::by_name(name)
+            .expect("dialect names are checked by clap")
+            .translate(source),
+        None if options.source.macros => {
+            let dir = include_dir(&options.source);
+            expand_macros(source, &[], dir).source().to_vec()
+        }
+        None => source.to_vec(),
+    };
+    let parse_options = ast::ParseOptions {
+        multi_tape: options.source.tapes.is_some(),
+        max_depth: Some(options.source.max_depth),
+    };
+    let (program, map) =
+        debugger::compile_source(&source, &parse_options).expect("the source parsed already");
+
+    if let Some(ref path) = options.input {
+        File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut input))
+            .unwrap_or_else(|e| error_exit(1, &format!("{}: {}", e, path)));
+    }
+    let mut state = State::with_tapes(options.memory_size, options.source.tapes());
+    state.set_eof(semantics(&options.source, &options.eof).eof);
+    state.set_signed(options.signed);
+    let mut debugger = Debugger::new(program, state, Cursor::new(input), Vec::new());
+
+    let console = Console::new().with_source(source, map);
+    #[cfg(feature = "tui")]
+    let console = if options.tui {
+        console.full_screen(terminal_size("COLUMNS", 80), terminal_size("LINES", 24))
+    } else {
+        console
+    };
+    let mut console = console;
+    console
+        .run(&mut debugger, stdin().lock(), stdout().lock())
+        .unwrap_or_else(|e| error_exit(1, &format!("error: {}", e)));
+}
+
+/// The terminal's width or height from the environment variable `name`, or else `default`.
+fn terminal_size(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+fn fmt(options: &FmtOptions) {
+    let format_options = FormatOptions {
+        indent: if options.minify {
+            None
+        } else {
+            Some(options.indent)
+        },
+        width: if options.minify { 0 } else { options.width },
+        comments: options.comments,
+        multi_tape: options.source.tapes.is_some(),
+    };
+    let (mut source, files) = read_source_files(&options.source);
+    if let Some(ref name) = options.source.dialect {
+        let dialect = Dialect::by_name(name).expect("dialect names are checked by clap");
+        source = dialect.translate(&source);
+    }
+    if options.source.macros {
+        source = expand_macros(&source, &files, include_dir(&options.source))
+            .source()
+            .to_vec();
+    }
+    let result = format::format(&source, &format_options)
+        .unwrap_or_else(|e| error_exit(2, &format!("syntax error: {}.", e)));
+    stdout()
+        .write_all(&result)
+        .unwrap_or_else(|e| error_exit(1, &format!("error: {}", e)));
+}
+
+/// Reads the program's Brainfuck source, after translating any dialect and expanding any
+/// macros, for the analyses that report positions in it. Returns the source, the name of each
+/// source file and the offset where it starts, if the source is as read, and the parse options.
+///
+/// Parsing the usual way first reports any syntax errors as `run` does.
+fn analysis_source(options: &SourceOptions) -> (Vec<u8>, Vec<(&str, usize)>, ast::ParseOptions) {
+    parse(options);
+    let (source, files) = read_source_files(options);
+    let (source, _) = options.semantics().split_source(&source);
+    let (source, files) = match options.dialect {
+        Some(ref name) => (
+            Dialect::by_name(name)
+                .expect("dialect names are checked by clap")
+                .translate(source),
+            Vec::new(),
+        ),
+        None if options.macros => {
+            let dir = include_dir(options);
+            (
+                expand_macros(source, &files, dir).source().to_vec(),
+                Vec::new(),
+            )
+        }
+        None => (source.to_vec(), files),
+    };
+    let parse_options = ast::ParseOptions {
+        multi_tape: options.tapes.is_some(),
+        max_depth: Some(options.max_depth),
+    };
+    (source, files, parse_options)
+}
+
+/// Prints the definite bugs in the program, exiting with status 1 if there are any.
+fn lint(options: &LintOptions) {
+    let (source, files, parse_options) = analysis_source(&options.source);
+    let lints = lint::lint(&source, &parse_options).expect("the source parsed already");
+    for found in &lints {
+        let offset = found.position.offset;
+        println!(
+            "{}",
+            source_error("warning", &source, &files, offset, |position| {
+                lint::Lint { position, ..*found }.to_string()
+            })
+        );
+    }
+    if !lints.is_empty() {
+        exit(1);
+    }
+}
+
+/// Prints whether each loop of the program can be shown to stop, and a bound on its memory.
+fn analyze(options: &AnalyzeOptions) {
+    let (source, files, parse_options) = analysis_source(&options.source);
+    let report = termination::analyze(&source, &parse_options);
+    let report = report.expect("the source parsed already");
+    for found in &report.loops {
+        let (name, _, position) = locate(&source, &files, found.position.offset);
+        if let Some(name) = name {
+            print!("{}: ", name);
+        }
+        println!(
+            "line {}, column {}: {}",
+            position.line, position.column, found.termination
+        );
+    }
+    println!(
+        "loops: {} ({} {}, {} {}, {} {})",
+        report.loops.len(),
+        report.count(Termination::Terminates),
+        Termination::Terminates,
+        report.count(Termination::BoundedByInput),
+        Termination::BoundedByInput,
+        report.count(Termination::Unknown),
+        Termination::Unknown
+    );
+    match report.memory {
+        Some(cells) => println!("cells used: at most {}", cells),
+        None => println!("cells used: no bound found"),
+    }
+}
+
+fn generate(options: &GenOptions) {
+    println!("{}", r#gen::print_text(options.text.as_bytes()));
+}
+
+fn pipe(options: &PipeOptions) {
+    if options.memory_size == 0 {
+        error_exit(1, "error: memory size must be at least 1.");
+    }
+    let backend = options.backend.as_ref().map_or(Backend::Peephole, |name| {
+        name.parse().expect("backend names are checked by clap")
+    });
+    let tapes = options.source.tapes();
+    if tapes > 1 && backend.is_native() {
+        error_exit(2, "error: native backends support only one tape.");
+    }
+    if backend.name() == "llvm" {
+        error_exit(2, "error: LLVM can't run in a pipe.");
+    }
+
+    let stages = pipe_stages(&options.source);
+    let mut source_input = Vec::new();
+    let mut programs = Vec::new();
+    for (index, (name, source)) in stages.iter().enumerate() {
+        let (program, input) = parse_with_input(source);
+        if index == 0 {
+            source_input = input;
+        } else if !input.is_empty() {
+            error_exit(1, &format!("error: {}: only the first program can carry input.", name));
+        }
+        programs.push(program);
+    }
+    let programs: Vec<_> = programs.iter().map(|program| &**program).collect();
+
+    let input: Box<dyn Read + Send> = match options.input {
+        Some(ref path) => {
+            let file = File::open(path)
+                .unwrap_or_else(|e| error_exit(1, &format!("{}: {}", e, path)));
+            Box::new(Cursor::new(source_input).chain(file))
+        }
+        None => Box::new(Cursor::new(source_input).chain(stdin())),
+    };
+    let mut state = State::with_tapes(options.memory_size, tapes);
+    state.set_eof(semantics(&options.source, &options.eof).eof);
+
+    let results = pipeline::run(backend, &programs, &state, input, stdout())
+        .unwrap_or_else(|e| error_exit(1, &format!("error: can't create a pipe: {}.", e)));
+    let mut failed = false;
+    for ((name, _), result) in stages.iter().zip(results) {
+        if let Err(e) = result {
+            eprintln!("bfi: {}: runtime error: {}.", name, e);
+            failed = true;
+        }
+    }
+    if failed {
+        exit(3);
+    }
+}
+
+/// The programs of `bfi pipe`, each named by its file, or by its place among the `-e`
+/// expressions, with the options that read just that program.
+fn pipe_stages(options: &SourceOptions) -> Vec<(String, SourceOptions)> {
+    let stage = |files, expressions| SourceOptions {
+        files,
+        expressions,
+        ..options.clone()
+    };
+    if let Some(ref exprs) = options.expressions {
+        exprs
+            .iter()
+            .enumerate()
+            .map(|(index, e)| {
+                let name = format!("expression {}", index + 1);
+                (name, stage(None, Some(vec![e.clone()])))
+            })
+            .collect()
+    } else if let Some(ref files) = options.files {
+        files
+            .iter()
+            .map(|f| (f.clone(), stage(Some(vec![f.clone()]), None)))
+            .collect()
+    } else {
+        error_exit(1, "error: no program given.");
+    }
+}
+
+fn serve(options: &ServeOptions) {
+    if options.memory_size == 0 {
+        error_exit(1, "error: memory size must be at least 1.");
+    }
+    if options.workers == 0 {
+        error_exit(1, "error: there must be at least 1 worker.");
+    }
+    let (program, source_input) = parse_with_input(&options.source);
+    let program = program.peephole_compile();
+    let mut state = State::with_tapes(options.memory_size, options.source.tapes());
+    state.set_eof(semantics(&options.source, &options.eof).eof);
+    let config = SandboxConfig {
+        max_steps: options.max_steps,
+        max_output_bytes: options.max_output,
+        wall_clock_limit: (options.timeout > 0).then(|| Duration::from_secs(options.timeout)),
+        ..SandboxConfig::default()
+    };
+
+    let listener = TcpListener::bind(&options.listen)
+        .unwrap_or_else(|e| error_exit(1, &format!("{}: {}", e, options.listen)));
+    match listener.local_addr() {
+        Ok(address) => eprintln!("bfi: listening on {}.", address),
+        Err(_) => eprintln!("bfi: listening on {}.", options.listen),
+    }
+
+    // Each worker accepts its own connections, so the rest wait in the listener's backlog while
+    // every worker is busy.
+    let (program, state, source_input) = (&*program, &state, &source_input[..]);
+    let (listener, config) = (&listener, &config);
+    thread::scope(|scope| {
+        for _ in 0..options.workers {
+            scope.spawn(move || {
+                // The flag that stops this worker's runs, which lasts as long as the server.
+                let stop = Interrupt::new(Box::leak(Box::new(AtomicBool::new(false))));
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => {
+                            serve_connection(program, state, source_input, config, stop, stream)
+                        }
+                        Err(e) => eprintln!("bfi: can't accept a connection: {}.", e),
+                    }
+                }
+            });
+        }
+    });
+}
+
+/// The input and output of one `bfi serve` run. The output is buffered, and sent before each
+/// read, so that the client sees a prompt before the program waits for the answer.
+///
+/// Sending the output is the end of the run once it fails, such as when a client stops reading
+/// until the write times out: the connection raises `stop`, so that the program stops at its
+/// next step, and every later read and write fails at once instead of waiting again.
+struct Connection<'a> {
+    input: RefCell<io::Chain<Cursor<&'a [u8]>, BufReader<TcpStream>>>,
+    output: RefCell<BufWriter<&'a TcpStream>>,
+    stop: Interrupt,
+    /// Why sending the output failed, if it did.
+    error: RefCell<Option<io::Error>>,
+}
+
+impl Connection<'_> {
+    /// Writes or flushes the output with `f`, ending the run if that fails.
+    fn send<T>(
+        &self,
+        f: impl FnOnce(&mut BufWriter<&TcpStream>) -> io::Result<T>,
+    ) -> io::Result<T> {
+        if self.error.borrow().is_some() {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        f(&mut self.output.borrow_mut()).map_err(|e| {
+            self.stop.raise();
+            let kind = e.kind();
+            *self.error.borrow_mut() = Some(e);
+            kind.into()
+        })
+    }
+}
+
+impl Read for &Connection<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.send(|output| output.flush())?;
+        self.input.borrow_mut().read(buf)
+    }
+}
+
+impl Write for &Connection<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.send(|output| output.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send(|output| output.flush())
+    }
+}
+
+/// Runs the program for one connection of `bfi serve`, with the input that the client sends and
+/// the output sent back, and reports how the run ended.
+fn serve_connection(
+    program: &peephole::Program,
+    state: &State,
+    source_input: &[u8],
+    config: &SandboxConfig,
+    stop: Interrupt,
+    stream: TcpStream,
+) {
+    let peer = match stream.peer_addr() {
+        Ok(address) => address.to_string(),
+        Err(_) => "unknown client".to_owned(),
+    };
+    let reader = match stream.try_clone() {
+        Ok(reader) => reader,
+        Err(e) => {
+            eprintln!("bfi: {}: {}.", peer, e);
+            return;
+        }
+    };
+    // A read that times out is the end of input, so the run stops at its next step, and a write
+    // that times out is the end of the run.
+    let _ = reader.set_read_timeout(config.wall_clock_limit);
+    let _ = stream.set_write_timeout(config.wall_clock_limit);
+    stop.clear();
+    let config = SandboxConfig {
+        interrupt: Some(stop),
+        ..*config
+    };
+    let connection = Connection {
+        input: RefCell::new(Cursor::new(source_input).chain(BufReader::new(reader))),
+        output: RefCell::new(BufWriter::new(&stream)),
+        stop,
+        error: RefCell::new(None),
+    };
+
+    let start = Instant::now();
+    let result = program.interpret_sandboxed(&mut state.clone(), &connection, &connection, &config);
+    let _ = (&connection).flush();
+    // Output that couldn't be sent stays in the buffer, which mustn't try again when it drops.
+    let _ = connection.output.into_inner().into_parts();
+    match (connection.error.into_inner(), result) {
+        (Some(e), _) => eprintln!("bfi: {}: can't send the output: {}.", peer, e),
+        (None, Ok(())) => eprintln!("bfi: {}: finished in {:?}.", peer, start.elapsed()),
+        (None, Err(e)) => eprintln!("bfi: {}: runtime error: {}.", peer, e),
+    }
+}
+
+fn run_profile(options: &ProfileOptions) {
+    if options.memory_size == 0 {
+        error_exit(1, "error: memory size must be at least 1.");
+    }
+    let (program, mut input) = parse_with_input(&options.source);
+    if let Some(ref path) = options.input {
+        File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut input))
+            .unwrap_or_else(|e| error_exit(1, &format!("{}: {}", e, path)));
+    }
+
+    let program = program.peephole_compile();
+    let mut profile = Profile::new(&program);
+    let mut state = State::with_tapes(options.memory_size, options.source.tapes());
+    state.set_eof(semantics(&options.source, &options.eof).eof);
+    let input = Cursor::new(&input);
+    let stdout = stdout();
+    let mut output = BufWriter::new(stdout.lock());
+
+    #[cfg(feature = "jit")]
+    let result = if options.jit {
+        if options.source.tapes() > 1 {
+            error_exit(2, "error: native backends support only one tape.");
+        }
+        profile::profile_jit(&program, &mut state, input, &mut output, &mut profile)
+    } else {
+        profile::profile(&program, &mut state, input, &mut output, &mut profile)
+    };
+    #[cfg(not(feature = "jit"))]
+    let result = profile::profile(&program, &mut state, input, &mut output, &mut profile);
+    let _ = output.flush();
+
+    let total = profile.total().as_secs_f64().max(f64::MIN_POSITIVE);
+    let entries = profile.entries();
+    eprintln!("total time: {:.2?}", profile.total());
+    eprintln!(
+        "{:>7} {:>10} {:>10} {:>12}  statement (in loop)",
+        "self", "self time", "total", "hits"
+    );
+    for index in profile.hottest().into_iter().take(options.top) {
+        let entry = &entries[index];
+        let share = 100.0 * entry.self_time.as_secs_f64() / total;
+        eprint!(
+            "{:>6.2}% {:>10} {:>10} {:>12}  {}",
+            share,
+            format!("{:.2?}", entry.self_time),
+            format!("{:.2?}", entry.time),
+            entry.hits,
+            entry.label
+        );
+        match profile.parent(index) {
+            Some(parent) => eprintln!(" (in {})", entries[parent].label),
+            None => eprintln!(),
         }
+    }
+
+    result.unwrap_or_else(|e| error_exit(3, &format!("runtime error: {}.", e)))
+}
+
+/// The timings of one backend.
+struct Timings {
+    name: String,
+    /// The median compile time, if compiling is separate from running.
+    compile: Option<Duration>,
+    /// The timed runs, sorted.
+    runs: Vec<Duration>,
+    result: BfResult<()>,
+}
+
+impl Timings {
+    fn median_run(&self) -> Duration {
+        self.runs[self.runs.len() / 2]
+    }
+}
+
+fn bench(options: &BenchOptions) {
+    if options.memory_size == 0 {
+        error_exit(1, "error: memory size must be at least 1.");
+    }
+    let (program, mut input) = parse_with_input(&options.source);
+    if let Some(ref path) = options.input {
+        File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut input))
+            .unwrap_or_else(|e| error_exit(1, &format!("{}: {}", e, path)));
+    }
 
-        Pass::Jit => {
+    let mut table = Vec::new();
+    for backend in Backend::all() {
+        let name = backend.to_string();
+        let timings = match backend {
+            Backend::Ast => time_runs(name, None, &*program, options, &input),
+            Backend::Rle => time_backend(name, || program.rle_compile(), options, &input),
+            Backend::Peephole => {
+                time_backend(name, || program.peephole_compile(), options, &input)
+            }
+            Backend::Bytecode(encoding) => time_backend(
+                name,
+                || program.bytecode_compile_with(encoding),
+                options,
+                &input,
+            ),
+            Backend::Fused => time_backend(name, || program.fused_compile(), options, &input),
             #[cfg(feature = "jit")]
-            {
-                let program = program.jit_compile(!options.unchecked);
-                interpret(&program, &options);
+            Backend::Jit if options.source.tapes() > 1 => continue,
+            #[cfg(feature = "jit")]
+            Backend::Jit => time_backend(name, || program.jit_compile(true), options, &input),
+            #[cfg(feature = "cranelift")]
+            Backend::Cranelift if options.source.tapes() > 1 => continue,
+            #[cfg(feature = "cranelift")]
+            Backend::Cranelift => {
+                time_backend(name, || program.cranelift_compile(), options, &input)
+            }
+            // LLVM compiles as it runs, so it is timed on its own below.
+            #[cfg(feature = "llvm")]
+            Backend::Llvm => continue,
+        };
+        table.push(timings);
+    }
+
+    #[cfg(feature = "llvm")]
+    if options.source.tapes() == 1 && semantics(&options.source, &options.eof).eof == Eof::Zero {
+        table.push(time_llvm(&program, options, &input));
+    }
+
+    if options.json {
+        let instructions = count_instructions(&program, options, &input);
+        println!("{}", timings_json(&table, instructions, options));
+    } else {
+        print_timings(&table, options);
+    }
+}
+
+/// Times compiling with `compile`, and then running the result.
+fn time_backend<P, F>(name: String, compile: F, options: &BenchOptions, input: &[u8]) -> Timings
+where
+    P: Interpretable,
+    F: Fn() -> P,
+{
+    let mut compiles = Vec::new();
+    let mut program = None;
+    for _ in 0..options.iterations {
+        let start = Instant::now();
+        let compiled = compile();
+        compiles.push(start.elapsed());
+        program = Some(compiled);
+    }
+    compiles.sort();
+
+    let program = program.expect("at least one iteration");
+    time_runs(name, Some(compiles[compiles.len() / 2]), &program, options, input)
+}
+
+/// Times running an already compiled program.
+fn time_runs<P: Interpretable + ?Sized>(
+    name: String,
+    compile: Option<Duration>,
+    program: &P,
+    options: &BenchOptions,
+    input: &[u8],
+) -> Timings {
+    let mut state = State::with_tapes(options.memory_size, options.source.tapes());
+    state.set_eof(semantics(&options.source, &options.eof).eof);
+    let mut result = Ok(());
+
+    for _ in 0..options.warmup {
+        state.reset();
+        let _ = program.interpret_in(&mut state, Cursor::new(input), io::sink());
+    }
+
+    let mut runs = Vec::new();
+    for _ in 0..options.iterations {
+        state.reset();
+        let start = Instant::now();
+        result = program.interpret_in(&mut state, Cursor::new(input), io::sink());
+        runs.push(start.elapsed());
+    }
+    runs.sort();
+
+    Timings {
+        name,
+        compile,
+        runs,
+        result,
+    }
+}
+
+/// Times LLVM, which compiles as part of each run.
+#[cfg(feature = "llvm")]
+fn time_llvm(program: &ast::Program, options: &BenchOptions, input: &[u8]) -> Timings {
+    let size = Some(options.memory_size);
+    let mut result = Ok(());
+
+    for _ in 0..options.warmup {
+        let _ = program.llvm_run_with(size, Cursor::new(input), io::sink());
+    }
+
+    let mut runs = Vec::new();
+    for _ in 0..options.iterations {
+        let start = Instant::now();
+        result = program.llvm_run_with(size, Cursor::new(input), io::sink());
+        runs.push(start.elapsed());
+    }
+    runs.sort();
+
+    Timings {
+        name: "llvm (with compile)".to_owned(),
+        compile: None,
+        runs,
+        result,
+    }
+}
+
+fn print_timings(table: &[Timings], options: &BenchOptions) {
+    let reference = table[0].median_run();
+
+    println!(
+        "{} warmup, {} timed runs per backend",
+        options.warmup, options.iterations
+    );
+    println!(
+        "{:20} {:>12} {:>12} {:>12} {:>9}",
+        "backend", "compile", "run (median)", "run (min)", "vs ast"
+    );
+    for timings in table {
+        let compile = match timings.compile {
+            Some(compile) => format!("{:.2?}", compile),
+            None => "-".to_owned(),
+        };
+        let median = timings.median_run();
+        let speedup = reference.as_secs_f64() / median.as_secs_f64().max(f64::MIN_POSITIVE);
+        print!(
+            "{:20} {:>12} {:>12} {:>12} {:>8.2}x",
+            timings.name,
+            compile,
+            format!("{:.2?}", median),
+            format!("{:.2?}", timings.runs[0]),
+            speedup
+        );
+        match timings.result {
+            Ok(()) => println!(),
+            Err(e) => println!("  ({})", e),
+        }
+    }
+}
+
+/// The number of peephole instructions that running the program executes, which is the same for
+/// every backend, for comparing their speeds.
+fn count_instructions(program: &ast::Program, options: &BenchOptions, input: &[u8]) -> u64 {
+    let mut state = State::with_tapes(options.memory_size, options.source.tapes());
+    state.set_eof(semantics(&options.source, &options.eof).eof);
+    let mut counters = Counters::new();
+    let _ = program.peephole_compile().interpret_counting(
+        &mut state,
+        Cursor::new(input),
+        io::sink(),
+        &mut counters,
+    );
+    counters.instructions()
+}
+
+fn timings_json(table: &[Timings], instructions: u64, options: &BenchOptions) -> String {
+    let program = match options.source.files {
+        Some(ref files) => files.join(" "),
+        None => "-e".to_owned(),
+    };
+
+    let mut result = String::from("{\"program\":");
+    write_json_string(&mut result, &program);
+    let _ = write!(
+        result,
+        ",\"warmup\":{},\"iterations\":{},\"instructions\":{},\"backends\":[",
+        options.warmup, options.iterations, instructions
+    );
+    for (index, timings) in table.iter().enumerate() {
+        if index > 0 {
+            result.push(',');
+        }
+        let median = timings.median_run();
+        result.push_str("{\"backend\":");
+        write_json_string(&mut result, &timings.name);
+        match timings.compile {
+            Some(compile) => {
+                let _ = write!(result, ",\"compile_ns\":{}", compile.as_nanos());
             }
-            #[cfg(not(feature = "jit"))]
-            {
-                error_exit(1, "error: jit feature not enabled.");
+            None => result.push_str(",\"compile_ns\":null"),
+        }
+        let _ = write!(
+            result,
+            ",\"run_median_ns\":{},\"run_min_ns\":{},\"instructions_per_sec\":{:.0},\"error\":",
+            median.as_nanos(),
+            timings.runs[0].as_nanos(),
+            instructions as f64 / median.as_secs_f64().max(f64::MIN_POSITIVE)
+        );
+        match timings.result {
+            Ok(()) => result.push_str("null}"),
+            Err(e) => {
+                write_json_string(&mut result, &e.to_string());
+                result.push('}');
             }
         }
+    }
+    result.push_str("]}");
+    result
+}
 
-        #[cfg(feature = "llvm")]
-        Pass::Llvm => {
-            program
-                .llvm_run(options.memory_size)
-                .unwrap_or_else(|e| error_exit(3, &format!("runtime error: {}.", e)));
+fn read_source(options: &SourceOptions) -> Vec<u8> {
+    read_source_files(options).0
+}
+
+/// Reads the program, also returning the name of each source file and the offset where it
+/// starts in the program.
+fn read_source_files(options: &SourceOptions) -> (Vec<u8>, Vec<(&str, usize)>) {
+    let mut program_text = Vec::new();
+    let mut starts = Vec::new();
+    if let Option::Some(ref exprs) = options.expressions {
+        for e in exprs {
+            program_text.extend(e.as_bytes());
+        }
+    } else if let Option::Some(ref files) = options.files {
+        for f in files {
+            starts.push((f.as_str(), program_text.len()));
+            let mut file =
+                File::open(f.clone()).unwrap_or_else(|e| error_exit(1, &format!("{}: {}", e, f)));
+            file.read_to_end(&mut program_text)
+                .unwrap_or_else(|e| error_exit(1, &format!("{}: {}", e, f)));
+        }
+    } else {
+        error_exit(1, "error: no program given.");
+    }
+    (program_text, starts)
+}
+
+fn parse(options: &SourceOptions) -> Box<ast::Program> {
+    parse_with_input(options).0
+}
+
+/// Parses the program, also returning any input that the source contains.
+fn parse_with_input(options: &SourceOptions) -> (Box<ast::Program>, Vec<u8>) {
+    let (source, files) = read_source_files(options);
+    let (original, input) = options.semantics().split_source(&source);
+    let expansion = options
+        .macros
+        .then(|| expand_macros(original, &files, include_dir(options)));
+    let source = expansion.as_ref().map_or(original, Expansion::source);
+    let parse_options = ast::ParseOptions {
+        multi_tape: options.tapes.is_some(),
+        max_depth: Some(options.max_depth),
+    };
+    let program = match options.dialect {
+        Some(ref name) => Dialect::by_name(name)
+            .expect("dialect names are checked by clap")
+            .parse(source)
+            .unwrap_or_else(|e| error_exit(2, &format!("syntax error: {}.", e))),
+        None => ast::parse_program_recovering(source, &parse_options).unwrap_or_else(|errors| {
+            let messages: Vec<_> = errors
+                .into_iter()
+                .map(|e| match expansion {
+                    Some(ref expansion) => expanded_syntax_error(original, &files, expansion, e),
+                    None => syntax_error(original, &files, e),
+                })
+                .collect();
+            error_exit(2, &messages.join("\nbfi: "))
+        }),
+    };
+    (program, input.to_vec())
+}
+
+/// Where relative includes in the source are found: the directory of the first source file, or
+/// else the current directory.
+fn include_dir(options: &SourceOptions) -> &Path {
+    options
+        .files
+        .as_ref()
+        .and_then(|files| files.first())
+        .and_then(|file| Path::new(file).parent())
+        .unwrap_or(Path::new(""))
+}
+
+/// Expands the macros and includes in the source, or exits with the error.
+fn expand_macros(source: &[u8], files: &[(&str, usize)], dir: &Path) -> Expansion {
+    preprocess::expand_in(source, dir).unwrap_or_else(|error| {
+        let describe = |text: &[u8], files: &[(&str, usize)]| {
+            let offset = error.position.offset;
+            source_error("preprocessor error", text, files, offset, |position| {
+                let error = preprocess::PreprocessError {
+                    position,
+                    ..error.clone()
+                };
+                error.to_string()
+            })
+        };
+        let message = match error.file {
+            Some(ref file) => describe(&file.text, &[(&file.path.to_string_lossy(), 0)]),
+            None => describe(source, files),
+        };
+        error_exit(2, &message)
+    })
+}
+
+/// Describes a syntax error in the expanded source at the place that it came from, in the
+/// source or in an included file.
+fn expanded_syntax_error(
+    original: &[u8],
+    files: &[(&str, usize)],
+    expansion: &Expansion,
+    error: ast::ParseError,
+) -> String {
+    let file = expansion.origin(error.position.offset).file;
+    let error = expansion.original_error(original, error);
+    match file {
+        Some(file) => {
+            let file = &expansion.included()[file];
+            syntax_error(&file.text, &[(&file.path.to_string_lossy(), 0)], error)
         }
+        None => syntax_error(original, files, error),
+    }
+}
+
+/// Describes a syntax error, with the position counted from the start of the file that it is
+/// in, if the source came from files.
+fn syntax_error(source: &[u8], files: &[(&str, usize)], error: ast::ParseError) -> String {
+    let offset = error.position.offset;
+    source_error("syntax error", source, files, offset, |position| {
+        ast::ParseError { position, ..error }.to_string()
+    })
+}
+
+/// Describes an error at `offset` in the source, with the line that it is on, given how to
+/// describe it at a position. The position counts from the start of the file that it is in, if
+/// the source came from files.
+fn source_error<F>(
+    label: &str,
+    source: &[u8],
+    files: &[(&str, usize)],
+    offset: usize,
+    describe: F,
+) -> String
+where
+    F: Fn(ast::Position) -> String,
+{
+    match locate(source, files, offset) {
+        (Some(name), file, position) => format!(
+            "{} in {}: {}.\n{}",
+            label,
+            name,
+            describe(position),
+            position.snippet(file)
+        ),
+        (None, _, position) => format!(
+            "{}: {}.\n{}",
+            label,
+            describe(position),
+            position.snippet(source)
+        ),
+    }
+}
 
-        #[cfg(feature = "cranelift")]
-        Pass::Cranelift => {
-            let program = program.cranelift_compile();
-            interpret(&program, &options);
+/// Finds the place at `offset` in the source: the name and text of the file that it is in, if
+/// the source came from files, and the position counted from the start of that file.
+fn locate<'a>(
+    source: &'a [u8],
+    files: &[(&'a str, usize)],
+    offset: usize,
+) -> (Option<&'a str>, &'a [u8], ast::Position) {
+    match files.iter().rev().find(|&&(_, start)| start <= offset) {
+        Some(&(name, start)) => {
+            let end = files
+                .iter()
+                .map(|&(_, start)| start)
+                .find(|&next| next > offset)
+                .unwrap_or(source.len());
+            let file = &source[start..end];
+            (Some(name), file, ast::Position::new(file, offset - start))
         }
+        None => (None, source, ast::Position::new(source, offset)),
     }
 }
 
-fn parse(options: &Options) -> Box<ast::Program> {
-    ast::parse_program(&options.program_text)
-        .unwrap_or_else(|e| error_exit(2, &format!("syntax error: {}.", e)))
+/// The conventions of the chosen profile, with any `--eof` override.
+fn semantics(source: &SourceOptions, eof: &Option<String>) -> Semantics {
+    let mut result = source.semantics();
+    if let Some(ref eof) = *eof {
+        result.eof = eof.parse().expect("EOF behaviors are checked by clap");
+    }
+    result
 }
 
-fn interpret<P: Interpretable + ?Sized>(program: &P, options: &Options) {
-    program
-        .interpret_stdin(options.memory_size)
-        .unwrap_or_else(|e| error_exit(3, &format!("runtime error: {}.", e)))
+/// Tells `perf` where the JIT put the code for each loop, for `--perf-map`, and a debugger, for
+/// `--gdb-jit`.
+#[cfg(feature = "jit")]
+fn describe_code(program: &mut bf::jit::Program, options: &Options) {
+    if options.gdb_jit {
+        program.register_with_debugger();
+    }
+    if options.perf_map
+        && let Err(e) = program.write_perf_map()
+    {
+        error_exit(1, &format!("{}: /tmp/perf-{}.map", e, std::process::id()));
+    }
+}
+
+/// Runs a program that was compiled since `start`.
+fn interpret<P: Interpretable + ?Sized>(program: &P, options: &Options, start: Instant) {
+    let compile_time = start.elapsed();
+    let mut state = new_state(options);
+
+    let start = Instant::now();
+    let result = with_io(options, |input, output| {
+        program.interpret_in(&mut state, input, output)
+    });
+    finish(result, options, compile_time, start.elapsed(), None);
+    exit_with_cell(&state, options);
+}
+
+/// Runs a program that was compiled since `start`, counting what it does if we are reporting
+/// statistics or Ctrl-C stops it, or in a sandbox if the output is limited.
+fn interpret_counting<P: CountingInterpretable + SandboxInterpretable + ?Sized>(
+    program: &P,
+    options: &Options,
+    start: Instant,
+) {
+    if options.max_output.is_some() {
+        return interpret_sandboxed(program, options, start);
+    }
+    if !options.stats && !options.dump_on_interrupt {
+        return interpret(program, options, start);
+    }
+
+    let compile_time = start.elapsed();
+    let mut state = new_state(options);
+    let mut counters = Counters::new();
+    if options.dump_on_interrupt {
+        counters.set_interrupt(INTERRUPT);
+    }
+
+    let start = Instant::now();
+    let result = with_io(options, |input, output| {
+        program.interpret_counting(&mut state, input, output, &mut counters)
+    });
+    dump_interrupted(&result, &state, Some(&counters));
+    finish(result, options, compile_time, start.elapsed(), Some(&counters));
+    exit_with_cell(&state, options);
+}
+
+/// Runs a program that was compiled since `start` in a sandbox that limits its output, or that
+/// Ctrl-C stops.
+fn interpret_sandboxed<P: SandboxInterpretable + ?Sized>(
+    program: &P,
+    options: &Options,
+    start: Instant,
+) {
+    let compile_time = start.elapsed();
+    let mut state = new_state(options);
+    let config = SandboxConfig {
+        max_output_bytes: options.max_output,
+        interrupt: options.dump_on_interrupt.then_some(INTERRUPT),
+        ..SandboxConfig::default()
+    };
+
+    let start = Instant::now();
+    let result = with_io(options, |input, output| {
+        program.interpret_sandboxed(&mut state, input, output, &config)
+    });
+    dump_interrupted(&result, &state, None);
+    finish(result, options, compile_time, start.elapsed(), None);
+    exit_with_cell(&state, options);
+}
+
+/// Exits with the final value of the cell chosen by `--exit-cell`, if there is one. A cell past
+/// the end of a memory that didn't grow that far was never changed, so it is zero.
+fn exit_with_cell(state: &State, options: &Options) {
+    if let Some(cell) = options.exit_cell {
+        exit(state.cells().get(cell).map_or(0, |&value| value.into()));
+    }
+}
+
+/// Makes Ctrl-C raise the interrupt, for `--dump-on-interrupt`, or exit if it already has.
+fn catch_interrupts() {
+    let handler = || {
+        if INTERRUPT.is_raised() {
+            exit(130);
+        }
+        INTERRUPT.raise();
+    };
+    if let Err(e) = ctrlc::set_handler(handler) {
+        error_exit(1, &format!("error: can't catch Ctrl-C: {}.", e));
+    }
+}
+
+/// Prints where a program was when Ctrl-C stopped it: the pointer, the cells around it, and the
+/// instructions executed, if they were counted.
+fn dump_interrupted(result: &BfResult<()>, state: &State, counters: Option<&Counters>) {
+    if *result != Err(Error::Interrupted) {
+        return;
+    }
+    let memory = state.cells();
+    let pointer = state.pointer();
+    let first = pointer
+        .saturating_sub(DUMP_CELLS / 2)
+        .min(memory.len().saturating_sub(DUMP_CELLS));
+    let last = memory.len().min(first + DUMP_CELLS);
+    let cells: Vec<_> = (first..last)
+        .map(|address| {
+            let value = state.cell_value(memory[address]);
+            if address == pointer {
+                format!("[{}]", value)
+            } else {
+                value.to_string()
+            }
+        })
+        .collect();
+
+    let _ = stdout().flush();
+    if state.tapes() > 1 {
+        eprintln!("pointer:         {} (tape {})", pointer, state.tape());
+    } else {
+        eprintln!("pointer:         {}", pointer);
+    }
+    let label = format!("cells {}-{}:", first, last - 1);
+    eprintln!("{:17}{}", label, cells.join(" "));
+    match counters {
+        Some(counters) => eprintln!("instructions:    {}", counters.instructions()),
+        None => eprintln!("instructions:    (not counted by this backend)"),
+    }
+}
+
+fn new_state(options: &Options) -> State {
+    let mut state = if options.auto_size {
+        let mut state = State::with_tapes(INITIAL_GROWING_CAPACITY, options.tapes);
+        state.set_limit(AUTO_SIZE_LIMIT);
+        state
+    } else {
+        let memory_size = options.memory_size.unwrap_or(DEFAULT_CAPACITY);
+        State::with_tape_alloc(memory_size, options.tapes, options.tape_alloc)
+    };
+    state.set_eof(options.semantics.eof);
+    state.set_overflow(options.semantics.overflow);
+    state.set_signed(options.semantics.signed);
+    state
+}
+
+/// Reports statistics if requested, and then exits if the run failed.
+fn finish(
+    result: BfResult<()>,
+    options: &Options,
+    compile_time: Duration,
+    run_time: Duration,
+    counters: Option<&Counters>,
+) {
+    #[cfg(feature = "tracing")]
+    tracing::info!(
+        target: "bf",
+        backend = options.compiler_pass.name(),
+        micros = run_time.as_micros() as u64,
+        ok = result.is_ok()
+    );
+    if options.stats {
+        eprintln!("compile time:    {:?}", compile_time);
+        eprintln!("run time:        {:?}", run_time);
+        match counters {
+            Some(counters) => {
+                eprintln!("instructions:    {}", counters.instructions());
+                for (kind, count) in counters.counts() {
+                    eprintln!("  {:15}{}", kind.name(), count);
+                }
+                eprintln!("loop iterations: {}", counters.loop_iterations());
+                eprintln!("peak pointer:    {}", counters.peak_pointer());
+                eprintln!("peak cell:       {}", counters.peak_cell());
+                eprintln!("bytes read:      {}", counters.bytes_read());
+                eprintln!("bytes written:   {}", counters.bytes_written());
+            }
+            None => eprintln!("instructions:    (not counted by this backend)"),
+        }
+    }
+
+    match result {
+        Ok(()) => (),
+        Err(Error::Interrupted) => error_exit(130, "interrupted."),
+        Err(e) => error_exit(3, &format!("runtime error: {}.", e)),
+    }
+}
+
+/// Prints the library's events for `-v`, one line for each pass and run, indented by the spans
+/// around it.
+#[cfg(feature = "tracing")]
+#[derive(Default)]
+struct Breakdown {
+    /// The last span ID given out.
+    spans: AtomicU64,
+    /// The number of spans entered.
+    depth: AtomicUsize,
+}
+
+#[cfg(feature = "tracing")]
+impl tracing::Subscriber for Breakdown {
+    fn enabled(&self, metadata: &tracing::Metadata) -> bool {
+        metadata.target() == "bf"
+    }
+
+    fn new_span(&self, _: &span::Attributes) -> span::Id {
+        span::Id::from_u64(self.spans.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+
+    fn record(&self, _: &span::Id, _: &span::Record) {}
+
+    fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+    fn event(&self, event: &tracing::Event) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let indent = "  ".repeat(self.depth.load(Ordering::Relaxed).saturating_sub(1));
+        let time = Duration::from_micros(fields.micros);
+        match fields.pass {
+            Some(pass) => eprintln!(
+                "{}{:<width$} {:>8} -> {:<8} {:?}",
+                indent,
+                pass,
+                fields.size_in,
+                fields.size_out,
+                time,
+                width = 20usize.saturating_sub(indent.len())
+            ),
+            None => eprintln!(
+                "{}{:<width$} {:20} {:?}{}",
+                indent,
+                format!("run ({})", fields.backend),
+                "",
+                time,
+                if fields.ok { "" } else { " (error)" },
+                width = 20usize.saturating_sub(indent.len())
+            ),
+        }
+    }
+
+    fn enter(&self, _: &span::Id) {
+        self.depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn exit(&self, _: &span::Id) {
+        self.depth.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The fields of one of the library's events.
+#[cfg(feature = "tracing")]
+#[derive(Default)]
+struct Fields {
+    /// The pass, or `None` for a run.
+    pass: Option<String>,
+    backend: String,
+    size_in: u64,
+    size_out: u64,
+    micros: u64,
+    ok: bool,
+}
+
+#[cfg(feature = "tracing")]
+impl Visit for Fields {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "size_in" => self.size_in = value,
+            "size_out" => self.size_out = value,
+            "micros" => self.micros = value,
+            _ => (),
+        }
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        if field.name() == "ok" {
+            self.ok = value;
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "pass" => self.pass = Some(value.to_owned()),
+            "backend" => self.backend = value.to_owned(),
+            _ => (),
+        }
+    }
+
+    fn record_debug(&mut self, _: &Field, _: &dyn std::fmt::Debug) {}
+}
+
+/// Calls `run` with the program's input and output, saving them if `--record` was given, and
+/// checking the output against the recording if `--replay` was.
+fn with_io<T, F>(options: &Options, run: F) -> T
+where
+    F: FnOnce(&mut dyn Read, &mut dyn Write) -> T,
+{
+    let (mut input, mut output) = (open_input(options), open_output(options));
+    if options.record.is_none() && options.replay.is_none() {
+        return run(&mut input, &mut output);
+    }
+
+    let (mut input, mut output) = (Record::new(input), Record::new(output));
+    let result = run(&mut input, &mut output);
+    let _ = output.flush();
+    let recording = Recording::new(input.into_bytes(), output.into_bytes());
+
+    if let Some(ref path) = options.record {
+        File::create(path)
+            .and_then(|file| recording.write_to(BufWriter::new(file)))
+            .unwrap_or_else(|e| error_exit(1, &format!("{}: {}", e, path)));
+    }
+    if let Some(ref replay) = options.replay
+        && let Some(offset) = replay.first_difference(&recording.output)
+    {
+        eprintln!(
+            "bfi: warning: the output differs from the recording at byte {}.",
+            offset
+        );
+    }
+    result
+}
+
+/// A seed for `--random-input` from the clock, which is printed so that the run can be repeated.
+fn clock_seed() -> u64 {
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos() as u64);
+    eprintln!("bfi: random input seed {}.", seed);
+    seed
+}
+
+fn load_recording(path: &str) -> Recording {
+    File::open(path)
+        .and_then(Recording::read_from)
+        .unwrap_or_else(|e| error_exit(1, &format!("{}: {}", e, path)))
+}
+
+fn open_input(options: &Options) -> Box<dyn Read> {
+    if let Some(ref recording) = options.replay {
+        return Box::new(Cursor::new(recording.input.clone()));
+    }
+    let source_input = Cursor::new(options.source_input.clone());
+    if let Some(seed) = options.random_input {
+        return Box::new(source_input.chain(RandomInput::new(seed)));
+    }
+    match options.input {
+        Some(ref path) => {
+            let file = File::open(path)
+                .unwrap_or_else(|e| error_exit(1, &format!("{}: {}", e, path)));
+            Box::new(source_input.chain(file))
+        }
+        None => Box::new(source_input.chain(stdin())),
+    }
+}
+
+fn open_output(options: &Options) -> Box<dyn Write> {
+    match options.output {
+        Some(ref path) => {
+            let file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .append(options.append)
+                .truncate(!options.append)
+                .open(path)
+                .unwrap_or_else(|e| error_exit(1, &format!("{}: {}", e, path)));
+            Box::new(BufWriter::new(file))
+        }
+        None => Box::new(stdout()),
+    }
+}
+
+fn verify(program: &ast::Program, options: &Options) {
+    let mut input = Vec::new();
+    open_input(options)
+        .read_to_end(&mut input)
+        .unwrap_or_else(|e| error_exit(1, &format!("{}: input", e)));
+
+    let outcome = conformance::check_in(program, &new_state(options), &input)
+        .unwrap_or_else(|m| error_exit(4, &format!("verification failed: {}.", m)));
+
+    let mut output = open_output(options);
+    output
+        .write_all(&outcome.output)
+        .and_then(|()| output.flush())
+        .unwrap_or_else(|e| error_exit(1, &format!("{}: output", e)));
+    outcome
+        .result
+        .unwrap_or_else(|e| error_exit(3, &format!("runtime error: {}.", e)));
 }
 
 fn error_exit(code: i32, msg: &str) -> ! {
@@ -1,5 +1,7 @@
 use super::*;
+use crate::instrument;
 use crate::peephole;
+use crate::traits::{Compile, CompileTo, Stage};
 
 use crate::common::{Count, Instruction};
 
@@ -14,13 +16,23 @@ pub trait BytecodeCompilable {
     fn bytecode_compile(&self) -> Box<Program> {
         self.with_peephole(compile)
     }
+
+    /// Compile the given program to bytecode in the given encoding.
+    fn bytecode_compile_with(&self, encoding: Encoding) -> Encoded {
+        self.with_peephole(|program| encode(&compile(program), encoding))
+    }
 }
 
 /// Compiles peephole-optimized AST to a bytecode program.
 pub fn compile(src: &[peephole::Statement]) -> Box<Program> {
-    let mut compiler = Compiler::new();
-    compiler.compile(src);
-    compiler.into_program()
+    debug_assert_eq!(peephole::verify(src), Ok(()));
+    let program = instrument::pass("bytecode", src, |src| {
+        let mut compiler = Compiler::new();
+        compiler.compile(src);
+        compiler.into_program()
+    });
+    debug_assert_eq!(verify(&program), Ok(()));
+    program
 }
 
 pub struct Compiler {
@@ -40,7 +52,7 @@ impl Compiler {
 
         for instruction in src {
             match *instruction {
-                Src::Instr(instruction) => self.issue(instruction),
+                Src::Instr(ref instruction) => self.issue(instruction.clone()),
                 Src::Loop(ref body) => {
                     let begin_pc = self.instructions.len();
                     self.issue(Obj::JumpZero(0));
@@ -57,7 +69,15 @@ impl Compiler {
         self.instructions.into_boxed_slice()
     }
 
+    /// Issues an instruction, folding it into a `SetZero` or `SetConst` just before it when the
+    /// two store a constant.
     fn issue(&mut self, instruction: Instruction) {
+        if let Some(previous) = self.instructions.last_mut()
+            && let Some(folded) = peephole::set_const_peephole(previous, &instruction)
+        {
+            *previous = folded;
+            return;
+        }
         self.instructions.push(instruction);
     }
 }
@@ -86,3 +106,15 @@ impl<T: peephole::PeepholeCompilable + ?Sized> BytecodeCompilable for T {
         k(&self.peephole_compile())
     }
 }
+
+impl Stage for Program {
+    type Compiled = Box<Program>;
+}
+
+impl Compile for Program {}
+
+impl<T: BytecodeCompilable + ?Sized> CompileTo<Program> for T {
+    fn compile_to(&self) -> Box<Program> {
+        self.bytecode_compile()
+    }
+}
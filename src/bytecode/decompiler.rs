@@ -0,0 +1,120 @@
+use super::*;
+use crate::ast;
+use crate::common::Instruction;
+use crate::peephole;
+use crate::traits::{IntoUsize, ToSource};
+
+/// Decompiles a bytecode program back to an unoptimized AST.
+///
+/// The loops come from the matching `JumpZero` and `JumpNotZero` pairs, which must nest, and
+/// each optimized instruction becomes the Brainfuck commands it stands for, as when
+/// [printing](../traits/trait.ToSource.html) a peephole program. The result can be compiled
+/// again like any other program.
+///
+/// # Errors
+///
+/// Returns a description of the first problem if the program doesn't
+/// [verify](fn.verify.html) or its loops don't nest.
+///
+/// # Panics
+///
+/// Panics if the program uses a multiply or `WriteStr` in a way that the compiler doesn't,
+/// as [`ToSource`](../traits/trait.ToSource.html) does.
+pub fn decompile(program: &Program) -> Result<Box<ast::Program>, String> {
+    let program = lift(program)?;
+    let source = program.to_source();
+    Ok(ast::parse_program(source.as_bytes()).expect("printed source has matched brackets"))
+}
+
+/// Rebuilds the loops of a bytecode program as a peephole AST, for the backends that start from
+/// one, such as the JIT.
+///
+/// # Errors
+///
+/// The same as [`decompile`](fn.decompile.html).
+pub fn lift(program: &Program) -> Result<Box<peephole::Program>, String> {
+    verify(program)?;
+    structure(program)
+}
+
+/// Rebuilds the loops of a verified bytecode program.
+fn structure(program: &Program) -> Result<Box<peephole::Program>, String> {
+    // The loops that are open, with the address of each `JumpZero` and the statements before it.
+    let mut open: Vec<(usize, Vec<peephole::Statement>)> = Vec::new();
+    let mut current = Vec::new();
+
+    for (pc, instruction) in program.iter().enumerate() {
+        match *instruction {
+            Instruction::JumpZero(_) => open.push((pc, std::mem::take(&mut current))),
+            Instruction::JumpNotZero(begin) => match open.pop() {
+                Some((start, outer)) if start == begin.into_usize() => {
+                    let body = std::mem::replace(&mut current, outer);
+                    current.push(peephole::Statement::Loop(body.into_boxed_slice()));
+                }
+                _ => return Err(format!("loop ending at {} overlaps another loop", pc)),
+            },
+            _ => current.push(peephole::Statement::Instr(instruction.clone())),
+        }
+    }
+
+    match open.last() {
+        Some(&(start, _)) => Err(format!("loop starting at {} overlaps another loop", start)),
+        None => Ok(current.into_boxed_slice()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Instruction::*;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn rebuilds_loops() {
+        let program = [
+            In,
+            JumpZero(5),
+            Right(1),
+            JumpZero(4),
+            Out,
+            JumpNotZero(3),
+            Add(255),
+        ];
+        assert!(decompile(&program).is_err());
+
+        let program = [
+            In,
+            JumpZero(6),
+            Right(1),
+            JumpZero(5),
+            Out,
+            JumpNotZero(3),
+            JumpNotZero(1),
+        ];
+        let expected = ast::parse_program(b",[>[.]]").unwrap();
+        assert_eq!(decompile(&program), Ok(expected));
+    }
+
+    #[test]
+    fn rejects_overlapping_loops() {
+        let program = [JumpZero(2), JumpZero(3), JumpNotZero(0), JumpNotZero(1)];
+        assert_eq!(verify(&program), Ok(()));
+        assert_eq!(
+            decompile(&program),
+            Err("loop ending at 2 overlaps another loop".to_owned())
+        );
+    }
+
+    #[test]
+    fn decompiled_programs_recompile() {
+        let program = ast::parse_program(FACTOR_SRC).unwrap();
+        let program = decompile(&program.bytecode_compile()).unwrap();
+        assert_interpret(&*program.bytecode_compile(), b"100\n", b"100: 2 2 5 5\n");
+    }
+
+    #[test]
+    fn lifted_programs_compile_to_the_same_bytecode() {
+        let program = ast::parse_program(FACTOR_SRC).unwrap().bytecode_compile();
+        assert_eq!(lift(&program).unwrap().bytecode_compile(), program);
+    }
+}
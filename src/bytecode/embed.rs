@@ -0,0 +1,216 @@
+//! Compiling Brainfuck to bytecode while the Rust program compiles.
+//!
+//! An application that ships Brainfuck programs as resources can embed them already compiled,
+//! with [`include_bf!`](../../macro.include_bf.html), so that it doesn't parse them each time it
+//! starts and a syntax error in one of them fails the build. The compiler here is a `const fn`,
+//! so it does less than [`bytecode_compile`](../trait.BytecodeCompilable.html): it
+//! run-length encodes the commands and turns `[-]` and `[+]` into `SetZero`, but it doesn't run
+//! the peephole optimizer.
+//!
+//! ```
+//! use bf::include_bf;
+//! use bf::traits::*;
+//!
+//! let program = include_bf!("../../bf/hello.bf");
+//! assert_eq!(program.interpret_memory(None, b"").unwrap(), b"Hello, World!");
+//! ```
+
+use std::mem;
+
+use crate::common::{Count, Instruction};
+
+/// Compiles a Brainfuck file to bytecode when the program that uses it compiles, and embeds the
+/// bytecode as a `&'static bytecode::Program`.
+///
+/// The path is relative to the file that uses the macro, as for `include_bytes!`. A syntax error
+/// in the Brainfuck is a compile error.
+#[macro_export]
+macro_rules! include_bf {
+    ($path:expr) => {{
+        const SRC: &[u8] = include_bytes!($path);
+        const LEN: usize = $crate::bytecode::embed::compiled_len(SRC);
+        static PROGRAM: [$crate::common::Instruction; LEN] =
+            $crate::bytecode::embed::compile_embedded(SRC);
+        &PROGRAM as &'static $crate::bytecode::Program
+    }};
+}
+
+/// The most that a `Count` holds, which is a cast only with the `u16count` or `u32count` feature.
+#[allow(clippy::unnecessary_cast)]
+const MAX_COUNT: usize = Count::MAX as usize;
+
+/// The unit that the scanner finds next in the source.
+///
+/// This mirrors the instructions that the scanner makes rather than holding them, because an
+/// `Instruction` may own bytes and a `const fn` can't drop one.
+#[derive(Clone, Copy)]
+enum Token {
+    Left(Count),
+    Right(Count),
+    Add(u8),
+    In,
+    Out,
+    SetZero,
+    Begin,
+    End,
+}
+
+/// The number of instructions that [`compile_embedded`](fn.compile_embedded.html) makes from
+/// `src`.
+///
+/// # Panics
+///
+/// Panics if the brackets in `src` don't match, which is a compile error in a constant.
+pub const fn compiled_len(src: &[u8]) -> usize {
+    let mut len = 0;
+    let mut depth = 0;
+    let mut offset = 0;
+    while let Some((token, next)) = scan(src, offset) {
+        match token {
+            Token::Begin => depth += 1,
+            Token::End if depth == 0 => panic!("unmatched ‘]’"),
+            Token::End => depth -= 1,
+            _ => {}
+        }
+        len += 1;
+        offset = next;
+    }
+    assert!(depth == 0, "unmatched ‘[’");
+    assert!(len < MAX_COUNT, "program too long");
+    len
+}
+
+/// Compiles `src` to `LEN` instructions of bytecode, where `LEN` must be
+/// [`compiled_len(src)`](fn.compiled_len.html).
+pub const fn compile_embedded<const LEN: usize>(src: &[u8]) -> [Instruction; LEN] {
+    let mut program = [const { Instruction::SetZero }; LEN];
+    // The addresses of the `JumpZero`s of the loops that are open.
+    let mut open = [0; LEN];
+    let mut depth = 0;
+    let mut len = 0;
+    let mut offset = 0;
+    while let Some((token, next)) = scan(src, offset) {
+        let instruction = match token {
+            Token::Left(count) => Instruction::Left(count),
+            Token::Right(count) => Instruction::Right(count),
+            Token::Add(amount) => Instruction::Add(amount),
+            Token::In => Instruction::In,
+            Token::Out => Instruction::Out,
+            Token::SetZero => Instruction::SetZero,
+            Token::Begin => {
+                open[depth] = len;
+                depth += 1;
+                // The target is filled in at the end of the loop.
+                Instruction::JumpZero(0)
+            }
+            Token::End => {
+                depth -= 1;
+                let begin = open[depth];
+                store(&mut program, begin, Instruction::JumpZero(len as Count));
+                Instruction::JumpNotZero(begin as Count)
+            }
+        };
+        store(&mut program, len, instruction);
+        len += 1;
+        offset = next;
+    }
+    assert!(len == LEN, "wrong length for the program");
+    program
+}
+
+/// Replaces the instruction at `index` without dropping the old one, which a `const fn` can't
+/// do. None of the instructions here own anything, so nothing leaks.
+const fn store(program: &mut [Instruction], index: usize, instruction: Instruction) {
+    mem::forget(mem::replace(&mut program[index], instruction));
+}
+
+/// The next token in `src` at or after `offset`, and the offset after it.
+const fn scan(src: &[u8], mut offset: usize) -> Option<(Token, usize)> {
+    while offset < src.len() {
+        let byte = src[offset];
+        let token = match byte {
+            b'<' | b'>' | b'+' | b'-' => {
+                let count = run_length(src, offset);
+                offset += count;
+                let token = match byte {
+                    b'<' => Token::Left(count as Count),
+                    b'>' => Token::Right(count as Count),
+                    b'+' => Token::Add(count as u8),
+                    _ => Token::Add((count as u8).wrapping_neg()),
+                };
+                return Some((token, offset));
+            }
+            b',' => Token::In,
+            b'.' => Token::Out,
+            b'[' if offset + 2 < src.len()
+                && (src[offset + 1] == b'-' || src[offset + 1] == b'+')
+                && src[offset + 2] == b']' =>
+            {
+                return Some((Token::SetZero, offset + 3));
+            }
+            b'[' => Token::Begin,
+            b']' => Token::End,
+            _ => {
+                offset += 1;
+                continue;
+            }
+        };
+        return Some((token, offset + 1));
+    }
+    None
+}
+
+/// The number of times that the byte at `offset` repeats from there, up to the most that a
+/// `Count` holds.
+const fn run_length(src: &[u8], offset: usize) -> usize {
+    let mut end = offset + 1;
+    while end < src.len() && src[end] == src[offset] && end - offset < MAX_COUNT {
+        end += 1;
+    }
+    end - offset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode;
+    use crate::test_helpers::*;
+    use crate::traits::*;
+
+    #[test]
+    fn compiles_at_build_time() {
+        use crate::common::Instruction::*;
+
+        const SRC: &[u8] = b"++ [->+<] [-] >>,---.";
+        const LEN: usize = compiled_len(SRC);
+        const PROGRAM: [Instruction; LEN] = compile_embedded(SRC);
+        assert_eq!(
+            PROGRAM,
+            [
+                Add(2),
+                JumpZero(6),
+                Add(255),
+                Right(1),
+                Add(1),
+                Left(1),
+                JumpNotZero(1),
+                SetZero,
+                Right(2),
+                In,
+                Add(253),
+                Out,
+            ]
+        );
+        assert!(bytecode::verify(&PROGRAM).is_ok());
+    }
+
+    #[test]
+    fn runs_like_the_runtime_compiler() {
+        let program = include_bf!("../../bf/factor.bf");
+        assert_interpret(program, b"100\n", b"100: 2 2 5 5\n");
+        let compiled = crate::ast::parse_program(FACTOR_SRC)
+            .unwrap()
+            .bytecode_compile();
+        assert!(program.len() > compiled.len());
+    }
+}
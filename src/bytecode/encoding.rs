@@ -0,0 +1,136 @@
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use super::*;
+use crate::common::BfResult;
+use crate::state::State;
+use crate::traits::Interpretable;
+
+/// The ways a bytecode program can be encoded for interpretation.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub enum Encoding {
+    /// A boxed slice of [`Instruction`](../common/enum.Instruction.html)s.
+    #[default]
+    Boxed,
+    /// The [packed 32-bit encoding](packed/index.html).
+    Packed,
+    /// Instructions with [pre-resolved jumps](threaded/index.html).
+    Threaded,
+}
+
+/// A bytecode program in one of the available encodings.
+pub enum Encoded {
+    Boxed(Box<Program>),
+    Packed(packed::Program),
+    Threaded(threaded::Program),
+}
+
+impl Encoding {
+    /// All the encodings.
+    pub const ALL: [Encoding; 3] = [Encoding::Boxed, Encoding::Packed, Encoding::Threaded];
+
+    /// The name of the encoding, as accepted by `from_str`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Boxed => "boxed",
+            Encoding::Packed => "packed",
+            Encoding::Threaded => "threaded",
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Encoding::ALL
+            .iter()
+            .copied()
+            .find(|encoding| encoding.name() == s)
+            .ok_or_else(|| format!("unknown bytecode encoding: {}", s))
+    }
+}
+
+impl Encoded {
+    /// The encoding of this program.
+    pub fn encoding(&self) -> Encoding {
+        match *self {
+            Encoded::Boxed(_) => Encoding::Boxed,
+            Encoded::Packed(_) => Encoding::Packed,
+            Encoded::Threaded(_) => Encoding::Threaded,
+        }
+    }
+}
+
+/// Encodes a bytecode program using the given encoding.
+pub fn encode(src: &Program, encoding: Encoding) -> Encoded {
+    match encoding {
+        Encoding::Boxed => Encoded::Boxed(src.into()),
+        Encoding::Packed => Encoded::Packed(packed::compile(src)),
+        Encoding::Threaded => Encoded::Threaded(threaded::compile(src)),
+    }
+}
+
+impl Interpretable for Encoded {
+    fn interpret_in<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        input: R,
+        output: W,
+    ) -> BfResult<()> {
+        match *self {
+            Encoded::Boxed(ref program) => program.interpret_in(state, input, output),
+            Encoded::Packed(ref program) => program.interpret_in(state, input, output),
+            Encoded::Threaded(ref program) => program.interpret_in(state, input, output),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn names_round_trip() {
+        for encoding in Encoding::ALL {
+            assert_eq!(encoding.name().parse(), Ok(encoding));
+        }
+        assert!("flat".parse::<Encoding>().is_err());
+    }
+
+    #[test]
+    fn hello_world() {
+        for encoding in Encoding::ALL {
+            let program = crate::ast::parse_program(HELLO_WORLD_SRC).unwrap();
+            let program = program.bytecode_compile_with(encoding);
+            assert_eq!(program.encoding(), encoding);
+            assert_interpret(&program, b"", b"Hello, World!");
+        }
+    }
+
+    #[test]
+    fn multi_tape() {
+        for encoding in Encoding::ALL {
+            let program = parse_multi_tape(MULTI_TAPE_SRC);
+            let program = program.bytecode_compile_with(encoding);
+            assert_interpret_tapes(&program, 2, b"", b"ABB");
+        }
+    }
+
+    #[test]
+    fn factoring() {
+        for encoding in Encoding::ALL {
+            let program = crate::ast::parse_program(FACTOR_SRC).unwrap();
+            let program = program.bytecode_compile_with(encoding);
+            assert_interpret(&program, b"100\n", b"100: 2 2 5 5\n");
+        }
+    }
+}
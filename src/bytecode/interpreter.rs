@@ -1,62 +1,196 @@
 use std::io::{Read, Write};
 
 use super::*;
+use crate::analysis;
+use crate::counters::{self, CountingInterpretable, Counters, Hooks, Kind};
+use crate::sandbox::{self, SandboxConfig, SandboxInterpretable};
 use crate::state::State;
-use crate::traits::Interpretable;
+use crate::traits::{Interpretable, IntoUsize};
 use common::BfResult;
 
 impl Interpretable for Program {
-    fn interpret_state<R: Read, W: Write>(
+    fn interpret_in<R: Read, W: Write>(
         &self,
-        mut state: State,
+        state: &mut State,
         mut input: R,
         mut output: W,
     ) -> BfResult<()> {
-        interpret(self, &mut state, &mut input, &mut output)
+        let proofs = analysis::prove_bytecode(self);
+        specialize(self, &proofs, state, &mut input, &mut output, &mut ())
     }
 }
 
-fn interpret<R, W>(
+impl CountingInterpretable for Program {
+    fn interpret_counting<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        input: R,
+        output: W,
+        counters: &mut Counters,
+    ) -> BfResult<()> {
+        let proofs = analysis::prove_bytecode(self);
+        counters::count_io(input, output, counters, |input, output, counters| {
+            specialize(self, &proofs, state, input, output, counters)
+        })
+    }
+}
+
+impl SandboxInterpretable for Program {
+    fn interpret_sandboxed<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        input: R,
+        output: W,
+        config: &SandboxConfig,
+    ) -> BfResult<()> {
+        let proofs = analysis::prove_bytecode(self);
+        sandbox::run(
+            state,
+            input,
+            output,
+            config,
+            |state, input, output, hooks| specialize(self, &proofs, state, input, output, hooks),
+        )
+    }
+}
+
+impl Interpretable for Certified {
+    fn interpret_in<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        mut input: R,
+        mut output: W,
+    ) -> BfResult<()> {
+        specialize(self, self.proofs(), state, &mut input, &mut output, &mut ())
+    }
+}
+
+impl CountingInterpretable for Certified {
+    fn interpret_counting<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        input: R,
+        output: W,
+        counters: &mut Counters,
+    ) -> BfResult<()> {
+        counters::count_io(input, output, counters, |input, output, counters| {
+            specialize(self, self.proofs(), state, input, output, counters)
+        })
+    }
+}
+
+impl SandboxInterpretable for Certified {
+    fn interpret_sandboxed<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        input: R,
+        output: W,
+        config: &SandboxConfig,
+    ) -> BfResult<()> {
+        sandbox::run(
+            state,
+            input,
+            output,
+            config,
+            |state, input, output, hooks| {
+                specialize(self, self.proofs(), state, input, output, hooks)
+            },
+        )
+    }
+}
+
+/// Runs `interpret` specialized for how the state's cells overflow, so that the loop doesn't
+/// check that for every instruction.
+///
+/// The other settings don't need parameters. Bounds checks are already skipped per
+/// instruction, from the proofs; cells are always bytes, and signedness matters only to the
+/// overflow check that wrapping skips; and the end-of-input policy is consulted only by `In`,
+/// next to a read that costs far more.
+fn specialize<R, W, H>(
+    instructions: &Program,
+    proofs: &[bool],
+    state: &mut State,
+    input: &mut R,
+    output: &mut W,
+    hooks: &mut H,
+) -> BfResult<()>
+where
+    R: Read,
+    W: Write,
+    H: Hooks,
+{
+    if state.wraps() {
+        interpret::<_, _, _, true>(instructions, proofs, state, input, output, hooks)
+    } else {
+        interpret::<_, _, _, false>(instructions, proofs, state, input, output, hooks)
+    }
+}
+
+fn interpret<R, W, H, const WRAP: bool>(
     instructions: &Program,
+    proofs: &[bool],
     state: &mut State,
     input: &mut R,
     output: &mut W,
+    hooks: &mut H,
 ) -> BfResult<()>
 where
     R: Read,
     W: Write,
+    H: Hooks,
 {
     use common::Instruction::*;
 
+    // There is a proof for each instruction, and saying so here lets the loop look them up
+    // without a bounds check, since `pc` is always in range of the instructions.
+    let proofs = &proofs[..instructions.len()];
     let mut pc = 0;
 
     while pc < instructions.len() {
+        hooks.instruction(Kind::of(&instructions[pc]));
+
         match instructions[pc] {
+            Left(count) if proofs[pc] => state.left_unchecked(count),
             Left(count) => state.left(count)?,
+            Right(count) if proofs[pc] => state.right_unchecked(count),
             Right(count) => state.right(count)?,
-            Add(count) => state.up(count),
+            Add(count) if WRAP => state.add_wrapping(count),
+            Add(count) => state.add(count)?,
             In => state.read(input),
             Out => state.write(output),
+            WriteStr(ref bytes) => {
+                let _ = output.write_all(bytes);
+            }
 
             JumpZero(address) => {
                 if state.load() == 0 {
                     pc = address;
+                } else {
+                    hooks.loop_iteration()?;
                 }
             }
 
             JumpNotZero(address) => {
                 if state.load() != 0 {
                     pc = address;
+                    hooks.loop_iteration()?;
                 }
             }
 
             SetZero => state.store(0),
 
+            SetConst(value) => state.store(value),
+
             OffsetAddRight(offset) => {
                 if state.load() != 0 {
                     let value = state.load();
                     state.store(0);
-                    state.up_pos_offset(offset, value)?;
+                    if proofs[pc] {
+                        state.up_pos_offset_unchecked(offset, value);
+                    } else {
+                        state.up_pos_offset(offset, value)?;
+                    }
+                    hooks.cell(state.pointer() + offset.into_usize());
                 }
             }
 
@@ -64,23 +198,64 @@ where
                 if state.load() != 0 {
                     let value = state.load();
                     state.store(0);
-                    state.up_neg_offset(offset, value)?;
+                    if proofs[pc] {
+                        state.up_neg_offset_unchecked(offset, value);
+                    } else {
+                        state.up_neg_offset(offset, value)?;
+                    }
+                }
+            }
+
+            MulAddRight(offset, factor) => {
+                let value = state.load();
+                if value != 0 {
+                    let value = value.wrapping_mul(factor);
+                    if proofs[pc] {
+                        state.up_pos_offset_unchecked(offset, value);
+                    } else {
+                        state.up_pos_offset(offset, value)?;
+                    }
+                    hooks.cell(state.pointer() + offset.into_usize());
                 }
             }
 
-            FindZeroRight(offset) => {
-                while state.load() != 0 {
-                    state.right(offset)?;
+            MulAddLeft(offset, factor) => {
+                let value = state.load();
+                if value != 0 {
+                    let value = value.wrapping_mul(factor);
+                    if proofs[pc] {
+                        state.up_neg_offset_unchecked(offset, value);
+                    } else {
+                        state.up_neg_offset(offset, value)?;
+                    }
                 }
             }
 
-            FindZeroLeft(offset) => {
-                while state.load() != 0 {
-                    state.left(offset)?;
+            AddAt(offset, value) => {
+                if proofs[pc] {
+                    state.up_at_unchecked(offset, value);
+                } else {
+                    state.up_at(offset, value)?;
                 }
+                hooks.cell(state.pointer().wrapping_add_signed(offset));
             }
+            SetAt(offset, value) => {
+                if proofs[pc] {
+                    state.store_at_unchecked(offset, value);
+                } else {
+                    state.store_at(offset, value)?;
+                }
+                hooks.cell(state.pointer().wrapping_add_signed(offset));
+            }
+
+            FindZeroRight(offset) => state.find_zero_right(offset)?,
+
+            FindZeroLeft(offset) => state.find_zero_left(offset)?,
+
+            SwitchTape(count) => state.switch_tape(count),
         }
 
+        hooks.pointer(state.pointer());
         pc += 1;
     }
 
@@ -104,6 +279,39 @@ mod tests {
         assert_parse_interpret(FACTOR_SRC, "100\n", "100: 2 2 5 5\n");
     }
 
+    #[test]
+    fn folds_stores_from_unfolded_programs() {
+        use crate::common::Instruction::*;
+
+        let mut passes = crate::pass::PassManager::standard();
+        passes.remove("set-const");
+        let program = crate::ast::parse_program(b",[-]+++.").unwrap();
+        let program = passes.compile(&crate::rle::compile(&program)).unwrap();
+        let program = crate::bytecode::compile(&program);
+        assert_eq!(&*program, &[In, SetConst(3), Out]);
+        assert_interpret(&*program, b"A", b"\x03");
+    }
+
+    #[test]
+    fn adds_as_the_cells_overflow() {
+        use crate::semantics::Overflow;
+        use crate::state::State;
+        use crate::traits::Interpretable;
+
+        let program = crate::ast::parse_program(b"--.").unwrap();
+        let program = crate::rle::compile_for(&program, Overflow::Saturate);
+        let program = crate::bytecode::compile(&crate::peephole::compile(&program));
+        for (overflow, expected) in [(Overflow::Wrap, 254), (Overflow::Saturate, 0)] {
+            let mut state = State::with_capacity(10);
+            state.set_overflow(overflow);
+            let mut output = Vec::new();
+            program
+                .interpret_in(&mut state, &b""[..], &mut output)
+                .unwrap();
+            assert_eq!(output, [expected]);
+        }
+    }
+
     fn assert_parse_interpret(program: &[u8], input: &str, output: &str) {
         let program = crate::ast::parse_program(program).unwrap();
         let program = crate::rle::compile(&program);
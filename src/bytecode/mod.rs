@@ -14,13 +14,41 @@
 //! Flattening is not necessary for interpretation, but it might
 //! perform better because of the cache. So far, it appears
 //! to perform worse than the peephole-optimized AST.
+//!
+//! The [`verify`](fn.verify.html) function checks that a program's jumps are matched. In debug
+//! builds, the compiler checks both its input and its output. [`certify`](fn.certify.html)
+//! also proves the program's bounds checks once, giving a [`Certified`](struct.Certified.html)
+//! program that runs without proving them again.
+//!
+//! [`decompile`](fn.decompile.html) goes the other way, from bytecode back to an unoptimized
+//! AST, so that bytecode can be inspected and compiled again, and [`lift`](fn.lift.html) rebuilds
+//! just its loops, giving the peephole AST that the JIT compiles.
+//!
+//! The [`embed`](embed/index.html) submodule compiles Brainfuck to bytecode while the Rust
+//! program that uses it compiles, with the [`include_bf!`](../macro.include_bf.html) macro.
+//!
+//! The [`packed`](packed/index.html) submodule provides a more compact encoding of bytecode
+//! programs, with its own interpreter. The [`threaded`](threaded/index.html) submodule provides
+//! an interpreter that resolves jumps to pointers ahead of time and fetches instructions without
+//! bounds checks. To choose among these at run time, pass an [`Encoding`](enum.Encoding.html) to
+//! [`bytecode_compile_with`](trait.BytecodeCompilable.html#method.bytecode_compile_with), which
+//! returns an [`Encoded`](enum.Encoded.html) program that dispatches to the right interpreter.
 
 use crate::common;
 
 mod compiler;
+mod decompiler;
+pub mod embed;
+mod encoding;
 mod interpreter;
+pub mod packed;
+pub mod threaded;
+mod verifier;
 
-pub use self::compiler::{compile, BytecodeCompilable};
+pub use self::compiler::{compile, usize_to_count, BytecodeCompilable};
+pub use self::decompiler::{decompile, lift};
+pub use self::encoding::{encode, Encoded, Encoding};
+pub use self::verifier::{certify, verify, Certified};
 
 /// A program is a bytecode sequence of instructions.
 pub type Program = [common::Instruction];
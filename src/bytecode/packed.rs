@@ -0,0 +1,480 @@
+//! A packed, 32-bit encoding of bytecode.
+//!
+//! A `common::Instruction` is as large as its biggest variant, which is two words on a 64-bit
+//! machine. In this encoding, each instruction is usually a single 32-bit word, with an opcode
+//! in the low byte and the operand in the upper 24 bits. Operands that don't fit in 24 bits are
+//! escaped: the opcode gets the [`ESCAPE`](constant.ESCAPE.html) bit, and the operand follows in
+//! the next two words, low half first. Programs that are several times smaller fit much better
+//! in the cache. The bytes that `WriteStr` instructions write go in a separate table, and their
+//! operands are indices into it.
+//!
+//! Jump operands are word addresses, and unlike in the unpacked bytecode, they give the address
+//! just *after* the matching jump, so that the interpreter doesn't have to decode the matching
+//! instruction to skip over it.
+
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+use super::*;
+use crate::common::{BfResult, Count, Instruction};
+use crate::state::State;
+use crate::traits::{CompileTo, Interpretable, IntoUsize, Stage};
+
+/// Program forms that can be compiled to packed bytecode.
+pub trait PackedCompilable {
+    /// Compile the given program into bytecode to prepare for packing.
+    fn with_bytecode<F, R>(&self, k: F) -> R
+    where
+        F: FnOnce(&super::Program) -> R;
+
+    /// Compile the given program to packed bytecode.
+    fn packed_compile(&self) -> Program {
+        self.with_bytecode(compile)
+    }
+}
+
+/// A packed bytecode program.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Program {
+    code: Box<[u32]>,
+    /// The bytes of each `WriteStr`, whose operand is an index into this table.
+    strings: Box<[Arc<[u8]>]>,
+}
+
+/// Opcode bit indicating that the operand follows in the next two words.
+pub const ESCAPE: u32 = 0x80;
+
+/// The largest operand that fits in the same word as its opcode.
+pub const MAX_INLINE_OPERAND: u64 = (1 << 24) - 1;
+
+const LEFT: u32 = 0;
+const RIGHT: u32 = 1;
+const ADD: u32 = 2;
+const IN: u32 = 3;
+const OUT: u32 = 4;
+const JUMP_ZERO: u32 = 5;
+const JUMP_NOT_ZERO: u32 = 6;
+const SET_ZERO: u32 = 7;
+const OFFSET_ADD_RIGHT: u32 = 8;
+const OFFSET_ADD_LEFT: u32 = 9;
+const FIND_ZERO_RIGHT: u32 = 10;
+const FIND_ZERO_LEFT: u32 = 11;
+const SWITCH_TAPE: u32 = 12;
+const SET_CONST: u32 = 13;
+const MUL_ADD_RIGHT: u32 = 14;
+const MUL_ADD_LEFT: u32 = 15;
+const WRITE_STR: u32 = 16;
+const ADD_AT: u32 = 17;
+const SET_AT: u32 = 18;
+
+impl Program {
+    /// The encoded words.
+    pub fn as_words(&self) -> &[u32] {
+        &self.code
+    }
+
+    /// Decodes the packed program back to ordinary bytecode.
+    pub fn decode(&self) -> Box<super::Program> {
+        let mut instructions = Vec::new();
+        // For each word address, the corresponding instruction address.
+        let mut addresses = vec![0; self.code.len() + 1];
+
+        let mut pc = 0;
+        while pc < self.code.len() {
+            let (opcode, operand, next) = self.fetch(pc);
+            addresses[pc] = instructions.len();
+            instructions.push((opcode, operand));
+            pc = next;
+        }
+        addresses[pc] = instructions.len();
+
+        let count = |operand: u64| operand as Count;
+        // Jump operands point just past the matching jump.
+        let target = |operand: u64| usize_to_count(addresses[operand as usize] - 1);
+
+        instructions
+            .into_iter()
+            .map(|(opcode, operand)| match opcode {
+                LEFT => Instruction::Left(count(operand)),
+                RIGHT => Instruction::Right(count(operand)),
+                ADD => Instruction::Add(operand as u8),
+                IN => Instruction::In,
+                OUT => Instruction::Out,
+                JUMP_ZERO => Instruction::JumpZero(target(operand)),
+                JUMP_NOT_ZERO => Instruction::JumpNotZero(target(operand)),
+                SET_ZERO => Instruction::SetZero,
+                OFFSET_ADD_RIGHT => Instruction::OffsetAddRight(count(operand)),
+                OFFSET_ADD_LEFT => Instruction::OffsetAddLeft(count(operand)),
+                FIND_ZERO_RIGHT => Instruction::FindZeroRight(count(operand)),
+                FIND_ZERO_LEFT => Instruction::FindZeroLeft(count(operand)),
+                SWITCH_TAPE => Instruction::SwitchTape(count(operand)),
+                SET_CONST => Instruction::SetConst(operand as u8),
+                MUL_ADD_RIGHT => Instruction::MulAddRight(count(operand >> 8), operand as u8),
+                MUL_ADD_LEFT => Instruction::MulAddLeft(count(operand >> 8), operand as u8),
+                WRITE_STR => Instruction::WriteStr(self.strings[operand as usize].clone()),
+                ADD_AT => Instruction::AddAt(signed(operand), operand as u8),
+                SET_AT => Instruction::SetAt(signed(operand), operand as u8),
+                _ => panic!("bad opcode: {}", opcode),
+            })
+            .collect::<Vec<_>>()
+            .into_boxed_slice()
+    }
+
+    /// Decodes the instruction at `pc`, returning its opcode, its operand, and the address of
+    /// the next instruction.
+    #[inline]
+    fn fetch(&self, pc: usize) -> (u32, u64, usize) {
+        let word = self.code[pc];
+        let opcode = word & 0xFF;
+
+        if opcode & ESCAPE == 0 {
+            (opcode, u64::from(word >> 8), pc + 1)
+        } else {
+            let low = u64::from(self.code[pc + 1]);
+            let high = u64::from(self.code[pc + 2]);
+            (opcode & !ESCAPE, low | high << 32, pc + 3)
+        }
+    }
+}
+
+/// Packs a bytecode program.
+pub fn compile(src: &super::Program) -> Program {
+    let mut strings = Vec::new();
+    let operands: Vec<u64> = src
+        .iter()
+        .map(|instruction| match *instruction {
+            Instruction::WriteStr(ref bytes) => {
+                strings.push(bytes.clone());
+                (strings.len() - 1) as u64
+            }
+            _ => operand(instruction),
+        })
+        .collect();
+
+    // Jump operands are word addresses, and whether an operand fits inline determines how many
+    // words each instruction takes. So we start by assuming that every jump fits, and then grow
+    // jumps that don't until the layout stops changing.
+    let mut sizes: Vec<usize> = src
+        .iter()
+        .zip(&operands)
+        .map(|(instruction, &operand)| match *instruction {
+            Instruction::JumpZero(_) | Instruction::JumpNotZero(_) => 1,
+            _ => encoded_size(operand),
+        })
+        .collect();
+
+    let addresses = loop {
+        let mut addresses = Vec::with_capacity(src.len() + 1);
+        let mut address = 0;
+        for &size in &sizes {
+            addresses.push(address);
+            address += size;
+        }
+        addresses.push(address);
+
+        let mut changed = false;
+        for (pc, instruction) in src.iter().enumerate() {
+            if let Some(target) = jump_target(instruction) {
+                let size = encoded_size(addresses[target + 1] as u64);
+                if size != sizes[pc] {
+                    sizes[pc] = size;
+                    changed = true;
+                }
+            }
+        }
+
+        if !changed {
+            break addresses;
+        }
+    };
+
+    let mut code = Vec::with_capacity(addresses[src.len()]);
+
+    for (instruction, &operand) in src.iter().zip(&operands) {
+        let operand = match jump_target(instruction) {
+            Some(target) => addresses[target + 1] as u64,
+            None => operand,
+        };
+
+        emit(&mut code, opcode(instruction), operand);
+    }
+
+    Program {
+        code: code.into_boxed_slice(),
+        strings: strings.into_boxed_slice(),
+    }
+}
+
+fn emit(code: &mut Vec<u32>, opcode: u32, operand: u64) {
+    if operand <= MAX_INLINE_OPERAND {
+        code.push(opcode | (operand as u32) << 8);
+    } else {
+        code.push(opcode | ESCAPE);
+        code.push(operand as u32);
+        code.push((operand >> 32) as u32);
+    }
+}
+
+fn encoded_size(operand: u64) -> usize {
+    if operand <= MAX_INLINE_OPERAND { 1 } else { 3 }
+}
+
+fn jump_target(instruction: &Instruction) -> Option<usize> {
+    match *instruction {
+        Instruction::JumpZero(target) | Instruction::JumpNotZero(target) => {
+            Some(target.into_usize())
+        }
+        _ => None,
+    }
+}
+
+fn opcode(instruction: &Instruction) -> u32 {
+    use crate::common::Instruction::*;
+
+    match *instruction {
+        Left(_) => LEFT,
+        Right(_) => RIGHT,
+        Add(_) => ADD,
+        In => IN,
+        Out => OUT,
+        JumpZero(_) => JUMP_ZERO,
+        JumpNotZero(_) => JUMP_NOT_ZERO,
+        SetZero => SET_ZERO,
+        OffsetAddRight(_) => OFFSET_ADD_RIGHT,
+        OffsetAddLeft(_) => OFFSET_ADD_LEFT,
+        FindZeroRight(_) => FIND_ZERO_RIGHT,
+        FindZeroLeft(_) => FIND_ZERO_LEFT,
+        SwitchTape(_) => SWITCH_TAPE,
+        SetConst(_) => SET_CONST,
+        MulAddRight(..) => MUL_ADD_RIGHT,
+        MulAddLeft(..) => MUL_ADD_LEFT,
+        WriteStr(_) => WRITE_STR,
+        AddAt(..) => ADD_AT,
+        SetAt(..) => SET_AT,
+    }
+}
+
+/// The operand of an instruction, except that `compile` numbers the `WriteStr` instructions
+/// itself.
+fn operand(instruction: &Instruction) -> u64 {
+    use crate::common::Instruction::*;
+
+    match *instruction {
+        Left(count)
+        | Right(count)
+        | JumpZero(count)
+        | JumpNotZero(count)
+        | OffsetAddRight(count)
+        | OffsetAddLeft(count)
+        | FindZeroRight(count)
+        | FindZeroLeft(count)
+        | SwitchTape(count) => count.into_usize() as u64,
+        Add(amount) | SetConst(amount) => u64::from(amount),
+        // The factor goes in the low byte, below the offset.
+        MulAddRight(offset, factor) | MulAddLeft(offset, factor) => {
+            (offset.into_usize() as u64) << 8 | u64::from(factor)
+        }
+        // Likewise, with the offset in zigzag form, so that small negative offsets stay small.
+        AddAt(offset, value) | SetAt(offset, value) => zigzag(offset) << 8 | u64::from(value),
+        In | Out | SetZero | WriteStr(_) => 0,
+    }
+}
+
+/// Encodes an offset so that offsets near zero, of either sign, are small.
+fn zigzag(offset: isize) -> u64 {
+    ((offset << 1) ^ (offset >> (isize::BITS - 1))) as u64
+}
+
+/// Decodes the offset from the operand of `AddAt` or `SetAt`.
+#[inline]
+fn signed(operand: u64) -> isize {
+    let zigzag = (operand >> 8) as isize;
+    (zigzag >> 1) ^ -(zigzag & 1)
+}
+
+impl Interpretable for Program {
+    fn interpret_in<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        mut input: R,
+        mut output: W,
+    ) -> BfResult<()> {
+        interpret(self, state, &mut input, &mut output)
+    }
+}
+
+fn interpret<R, W>(
+    program: &Program,
+    state: &mut State,
+    input: &mut R,
+    output: &mut W,
+) -> BfResult<()>
+where
+    R: Read,
+    W: Write,
+{
+    let mut pc = 0;
+
+    while pc < program.code.len() {
+        let (opcode, operand, next) = program.fetch(pc);
+        pc = next;
+
+        match opcode {
+            LEFT => state.left(operand as usize)?,
+            RIGHT => state.right(operand as usize)?,
+            ADD => state.add(operand as u8)?,
+            IN => state.read(input),
+            OUT => state.write(output),
+
+            ADD_AT => state.up_at(signed(operand), operand as u8)?,
+
+            SET_AT => state.store_at(signed(operand), operand as u8)?,
+
+            WRITE_STR => {
+                let _ = output.write_all(&program.strings[operand as usize]);
+            }
+
+            JUMP_ZERO => {
+                if state.load() == 0 {
+                    pc = operand as usize;
+                }
+            }
+
+            JUMP_NOT_ZERO => {
+                if state.load() != 0 {
+                    pc = operand as usize;
+                }
+            }
+
+            SET_ZERO => state.store(0),
+
+            OFFSET_ADD_RIGHT => {
+                if state.load() != 0 {
+                    let value = state.load();
+                    state.store(0);
+                    state.up_pos_offset(operand as usize, value)?;
+                }
+            }
+
+            OFFSET_ADD_LEFT => {
+                if state.load() != 0 {
+                    let value = state.load();
+                    state.store(0);
+                    state.up_neg_offset(operand as usize, value)?;
+                }
+            }
+
+            FIND_ZERO_RIGHT => state.find_zero_right(operand as usize)?,
+
+            FIND_ZERO_LEFT => state.find_zero_left(operand as usize)?,
+
+            SWITCH_TAPE => state.switch_tape(operand as usize),
+
+            SET_CONST => state.store(operand as u8),
+
+            MUL_ADD_RIGHT => {
+                let value = state.load();
+                if value != 0 {
+                    let value = value.wrapping_mul(operand as u8);
+                    state.up_pos_offset((operand >> 8) as usize, value)?;
+                }
+            }
+
+            MUL_ADD_LEFT => {
+                let value = state.load();
+                if value != 0 {
+                    let value = value.wrapping_mul(operand as u8);
+                    state.up_neg_offset((operand >> 8) as usize, value)?;
+                }
+            }
+
+            _ => panic!("bad opcode: {}", opcode),
+        }
+    }
+
+    Ok(())
+}
+
+impl PackedCompilable for super::Program {
+    fn with_bytecode<F, R>(&self, k: F) -> R
+    where
+        F: FnOnce(&super::Program) -> R,
+    {
+        k(self)
+    }
+}
+
+impl<T: BytecodeCompilable + ?Sized> PackedCompilable for T {
+    fn with_bytecode<F, R>(&self, k: F) -> R
+    where
+        F: FnOnce(&super::Program) -> R,
+    {
+        k(&self.bytecode_compile())
+    }
+}
+
+impl Stage for Program {
+    type Compiled = Program;
+}
+
+impl<T: PackedCompilable + ?Sized> CompileTo<Program> for T {
+    fn compile_to(&self) -> Program {
+        self.packed_compile()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn small_operands_are_inline() {
+        let program = compile(&[Instruction::Right(5), Instruction::Add(3)]);
+        assert_eq!(program.as_words(), &[5 << 8 | RIGHT, 3 << 8 | ADD]);
+    }
+
+    #[test]
+    fn big_operands_are_escaped() {
+        let program = compile(&[Instruction::Right(1 << 24)]);
+        assert_eq!(program.as_words(), &[RIGHT | ESCAPE, 1 << 24, 0]);
+    }
+
+    #[test]
+    fn decode_inverts_compile() {
+        let src = [
+            Instruction::Right(1 << 24),
+            Instruction::JumpZero(3),
+            Instruction::Left(1 << 30),
+            Instruction::JumpNotZero(1),
+            Instruction::SetConst(7),
+            Instruction::MulAddRight(1 << 20, 3),
+            Instruction::MulAddLeft(2, 255),
+            Instruction::WriteStr(b"hi".as_slice().into()),
+            Instruction::AddAt(-3, 4),
+            Instruction::SetAt(1 << 40, 5),
+            Instruction::Out,
+            Instruction::WriteStr(b"!".as_slice().into()),
+        ];
+        assert_eq!(&*compile(&src).decode(), &src);
+    }
+
+    #[test]
+    fn hello_world() {
+        assert_parse_interpret(HELLO_WORLD_SRC, "", "Hello, World!");
+    }
+
+    #[test]
+    fn factoring() {
+        assert_parse_interpret(FACTOR_SRC, "2\n", "2: 2\n");
+        assert_parse_interpret(FACTOR_SRC, "3\n", "3: 3\n");
+        assert_parse_interpret(FACTOR_SRC, "6\n", "6: 2 3\n");
+        assert_parse_interpret(FACTOR_SRC, "100\n", "100: 2 2 5 5\n");
+    }
+
+    fn assert_parse_interpret(program: &[u8], input: &str, output: &str) {
+        let program = crate::ast::parse_program(program).unwrap();
+        let program = program.packed_compile();
+        assert_interpret(&program, input.as_bytes(), output.as_bytes());
+    }
+}
@@ -0,0 +1,316 @@
+//! A direct-threaded bytecode interpreter.
+//!
+//! This is a middle ground between the [bytecode interpreter](../index.html) and the JIT, for
+//! platforms where the JIT isn't available. Compiling a bytecode program for this interpreter
+//! first verifies that its jumps are well formed, and then resolves each jump target to a
+//! pointer to the instruction to continue at. The interpreter can then follow jumps and fetch
+//! instructions through raw pointers, with no bounds checks on the program counter. Moves that
+//! the [bounds analysis](../../analysis/index.html) proves safe are also resolved to unchecked
+//! moves. A program that is already [certified](../fn.certify.html) can be compiled with
+//! [`compile_certified`](fn.compile_certified.html), which skips verifying and proving it again.
+//!
+//! In `bfi`, pass the `--threaded` flag to use this interpreter.
+
+use std::io::{Read, Write};
+use std::ptr;
+use std::sync::Arc;
+
+use super::*;
+use crate::common::{BfResult, Count, Instruction};
+use crate::state::State;
+use crate::traits::{CompileTo, Interpretable, IntoUsize, Stage};
+
+/// Program forms that can be compiled for the threaded interpreter.
+pub trait ThreadedCompilable {
+    /// Compile the given program into bytecode to prepare for threading.
+    fn with_bytecode<F, R>(&self, k: F) -> R
+    where
+        F: FnOnce(&super::Program) -> R;
+
+    /// Compile the given program for the threaded interpreter.
+    fn threaded_compile(&self) -> Program {
+        self.with_bytecode(compile)
+    }
+}
+
+/// A bytecode program with resolved jump targets.
+pub struct Program {
+    code: Box<[Op]>,
+}
+
+// Safety: The raw pointers in the code point into the code itself, which is owned by the
+// program and never mutated after construction.
+unsafe impl Send for Program {}
+unsafe impl Sync for Program {}
+
+/// A resolved instruction.
+#[derive(Clone, Debug)]
+enum Op {
+    Left(Count),
+    LeftProved(Count),
+    Right(Count),
+    RightProved(Count),
+    Add(u8),
+    In,
+    Out,
+    WriteStr(Arc<[u8]>),
+    /// Continues at the given instruction if the byte at the pointer is zero.
+    JumpZero(*const Op),
+    /// Continues at the given instruction if the byte at the pointer is non-zero.
+    JumpNotZero(*const Op),
+    SetZero,
+    SetConst(u8),
+    OffsetAddRight(Count),
+    OffsetAddLeft(Count),
+    FindZeroRight(Count),
+    FindZeroLeft(Count),
+    MulAddRight(Count, u8),
+    MulAddLeft(Count, u8),
+    AddAt(isize, u8),
+    SetAt(isize, u8),
+    SwitchTape(Count),
+}
+
+/// Compiles a bytecode program for the threaded interpreter.
+///
+/// # Panics
+///
+/// Panics if `src` doesn't pass [`verify`](../fn.verify.html).
+pub fn compile(src: &super::Program) -> Program {
+    match certify(src) {
+        Ok(certified) => compile_certified(&certified),
+        Err(message) => panic!("{}", message),
+    }
+}
+
+/// Compiles a [certified](../fn.certify.html) bytecode program for the threaded interpreter,
+/// using the checks and proofs that certifying it already did.
+pub fn compile_certified(src: &Certified) -> Program {
+    let mut code: Box<[Op]> = src
+        .iter()
+        .zip(src.proofs())
+        .map(|(instruction, &proved)| match *instruction {
+            Instruction::Left(count) if proved => Op::LeftProved(count),
+            Instruction::Left(count) => Op::Left(count),
+            Instruction::Right(count) if proved => Op::RightProved(count),
+            Instruction::Right(count) => Op::Right(count),
+            Instruction::Add(amount) => Op::Add(amount),
+            Instruction::In => Op::In,
+            Instruction::Out => Op::Out,
+            Instruction::WriteStr(ref bytes) => Op::WriteStr(bytes.clone()),
+            Instruction::JumpZero(_) => Op::JumpZero(ptr::null()),
+            Instruction::JumpNotZero(_) => Op::JumpNotZero(ptr::null()),
+            Instruction::SetZero => Op::SetZero,
+            Instruction::SetConst(value) => Op::SetConst(value),
+            Instruction::OffsetAddRight(offset) => Op::OffsetAddRight(offset),
+            Instruction::OffsetAddLeft(offset) => Op::OffsetAddLeft(offset),
+            Instruction::FindZeroRight(skip) => Op::FindZeroRight(skip),
+            Instruction::FindZeroLeft(skip) => Op::FindZeroLeft(skip),
+            Instruction::MulAddRight(offset, factor) => Op::MulAddRight(offset, factor),
+            Instruction::MulAddLeft(offset, factor) => Op::MulAddLeft(offset, factor),
+            Instruction::AddAt(offset, value) => Op::AddAt(offset, value),
+            Instruction::SetAt(offset, value) => Op::SetAt(offset, value),
+            Instruction::SwitchTape(count) => Op::SwitchTape(count),
+        })
+        .collect();
+
+    // Now that the code won't move, resolve each jump to a pointer to the instruction just past
+    // its matching jump.
+    let base = code.as_ptr();
+    for (op, instruction) in code.iter_mut().zip(src.iter()) {
+        match *instruction {
+            Instruction::JumpZero(end) => {
+                *op = Op::JumpZero(base.wrapping_add(end.into_usize() + 1));
+            }
+            Instruction::JumpNotZero(begin) => {
+                *op = Op::JumpNotZero(base.wrapping_add(begin.into_usize() + 1));
+            }
+            _ => (),
+        }
+    }
+
+    Program { code }
+}
+
+impl Interpretable for Program {
+    fn interpret_in<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        mut input: R,
+        mut output: W,
+    ) -> BfResult<()> {
+        interpret(self, state, &mut input, &mut output)
+    }
+}
+
+fn interpret<R, W>(
+    program: &Program,
+    state: &mut State,
+    input: &mut R,
+    output: &mut W,
+) -> BfResult<()>
+where
+    R: Read,
+    W: Write,
+{
+    let range = program.code.as_ptr_range();
+    let mut pc = range.start;
+
+    while pc != range.end {
+        // Safety: `compile` verified that every jump target is within the code or just past
+        // its end, so `pc` always points to an instruction here.
+        let op = unsafe { &*pc };
+        pc = pc.wrapping_add(1);
+
+        match *op {
+            Op::Left(count) => state.left(count)?,
+            Op::LeftProved(count) => state.left_unchecked(count),
+            Op::Right(count) => state.right(count)?,
+            Op::RightProved(count) => state.right_unchecked(count),
+            Op::Add(amount) => state.add(amount)?,
+            Op::In => state.read(input),
+            Op::Out => state.write(output),
+            Op::WriteStr(ref bytes) => {
+                let _ = output.write_all(bytes);
+            }
+
+            Op::JumpZero(target) => {
+                if state.load() == 0 {
+                    pc = target;
+                }
+            }
+
+            Op::JumpNotZero(target) => {
+                if state.load() != 0 {
+                    pc = target;
+                }
+            }
+
+            Op::SetZero => state.store(0),
+
+            Op::SetConst(value) => state.store(value),
+
+            Op::OffsetAddRight(offset) => {
+                let value = state.load();
+                if value != 0 {
+                    state.store(0);
+                    state.up_pos_offset(offset, value)?;
+                }
+            }
+
+            Op::OffsetAddLeft(offset) => {
+                let value = state.load();
+                if value != 0 {
+                    state.store(0);
+                    state.up_neg_offset(offset, value)?;
+                }
+            }
+
+            Op::FindZeroRight(skip) => state.find_zero_right(skip)?,
+
+            Op::FindZeroLeft(skip) => state.find_zero_left(skip)?,
+
+            Op::MulAddRight(offset, factor) => {
+                let value = state.load();
+                if value != 0 {
+                    state.up_pos_offset(offset, value.wrapping_mul(factor))?;
+                }
+            }
+
+            Op::MulAddLeft(offset, factor) => {
+                let value = state.load();
+                if value != 0 {
+                    state.up_neg_offset(offset, value.wrapping_mul(factor))?;
+                }
+            }
+
+            Op::AddAt(offset, value) => state.up_at(offset, value)?,
+
+            Op::SetAt(offset, value) => state.store_at(offset, value)?,
+
+            Op::SwitchTape(count) => state.switch_tape(count),
+        }
+    }
+
+    Ok(())
+}
+
+impl ThreadedCompilable for super::Program {
+    fn with_bytecode<F, R>(&self, k: F) -> R
+    where
+        F: FnOnce(&super::Program) -> R,
+    {
+        k(self)
+    }
+}
+
+impl<T: BytecodeCompilable + ?Sized> ThreadedCompilable for T {
+    fn with_bytecode<F, R>(&self, k: F) -> R
+    where
+        F: FnOnce(&super::Program) -> R,
+    {
+        k(&self.bytecode_compile())
+    }
+}
+
+impl Stage for Program {
+    type Compiled = Program;
+}
+
+impl<T: ThreadedCompilable + ?Sized> CompileTo<Program> for T {
+    fn compile_to(&self) -> Program {
+        self.threaded_compile()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Error;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn hello_world() {
+        assert_parse_interpret(HELLO_WORLD_SRC, "", Ok("Hello, World!"));
+    }
+
+    #[test]
+    fn factoring() {
+        assert_parse_interpret(FACTOR_SRC, "2\n", Ok("2: 2\n"));
+        assert_parse_interpret(FACTOR_SRC, "3\n", Ok("3: 3\n"));
+        assert_parse_interpret(FACTOR_SRC, "6\n", Ok("6: 2 3\n"));
+        assert_parse_interpret(FACTOR_SRC, "100\n", Ok("100: 2 2 5 5\n"));
+    }
+
+    #[test]
+    fn loop_at_end_of_program() {
+        assert_parse_interpret(b"+++[>+<-]", "", Ok(""));
+        assert_parse_interpret(b"+++[>+.<-]", "", Ok("\x01\x02\x03"));
+    }
+
+    #[test]
+    fn move_left_once() {
+        assert_parse_interpret(b"<", "", Err(Error::PointerUnderflow));
+    }
+
+    #[test]
+    #[should_panic]
+    fn mismatched_jumps_are_rejected() {
+        compile(&[Instruction::JumpZero(1), Instruction::Out]);
+    }
+
+    #[test]
+    fn compiles_certified_programs() {
+        let program = crate::ast::parse_program(FACTOR_SRC)
+            .unwrap()
+            .bytecode_compile();
+        let program = compile_certified(&certify(&program).unwrap());
+        assert_interpret(&program, b"6\n", b"6: 2 3\n");
+    }
+
+    fn assert_parse_interpret(program: &[u8], input: &str, output: BfResult<&str>) {
+        let program = crate::ast::parse_program(program).unwrap();
+        let program = program.threaded_compile();
+        assert_interpret_result(&program, input.as_bytes(), output.map(|s| s.as_bytes()));
+    }
+}
@@ -0,0 +1,128 @@
+use std::ops::Deref;
+
+use super::*;
+use crate::analysis;
+use crate::common::Instruction;
+use crate::traits::IntoUsize;
+
+/// A bytecode program that [`verify`](fn.verify.html) accepted, along with the moves and offsets
+/// that the [bounds analysis](../analysis/index.html) proved to stay in range.
+///
+/// Interpreting a plain program proves its bounds checks each time it runs. A certified program
+/// has them proved once, by [`certify`](fn.certify.html), so running it again goes straight to
+/// the unchecked moves.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Certified {
+    program: Box<Program>,
+    proofs: Box<[bool]>,
+}
+
+/// Checks the invariants of a bytecode program.
+///
+/// Every `JumpZero` must refer to a later `JumpNotZero` that refers back to it, and vice versa,
+/// and moves, offsets, and find-zero skips must be non-zero.
+pub fn verify(program: &Program) -> Result<(), String> {
+    for (pc, instruction) in program.iter().enumerate() {
+        match *instruction {
+            Instruction::JumpZero(end) => match program.get(end.into_usize()) {
+                Some(&Instruction::JumpNotZero(begin))
+                    if pc < end.into_usize() && begin.into_usize() == pc => {}
+                _ => return Err(format!("JumpZero at {} has no matching JumpNotZero", pc)),
+            },
+            Instruction::JumpNotZero(begin) => match program.get(begin.into_usize()) {
+                Some(&Instruction::JumpZero(end)) if end.into_usize() == pc => {}
+                _ => return Err(format!("JumpNotZero at {} has no matching JumpZero", pc)),
+            },
+            _ => instruction
+                .check_count()
+                .map_err(|message| format!("{} at {}", message, pc))?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Verifies a bytecode program and proves which of its bounds checks can be skipped.
+///
+/// # Errors
+///
+/// Returns the message from [`verify`](fn.verify.html) if the program is malformed.
+pub fn certify(program: &Program) -> Result<Certified, String> {
+    verify(program)?;
+    Ok(Certified {
+        program: program.into(),
+        proofs: analysis::prove_bytecode(program),
+    })
+}
+
+impl Certified {
+    /// Which instructions, by address, need no bounds check.
+    pub fn proofs(&self) -> &[bool] {
+        &self.proofs
+    }
+}
+
+impl Deref for Certified {
+    type Target = Program;
+
+    fn deref(&self) -> &Program {
+        &self.program
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Instruction::*;
+    use crate::test_helpers::RandomProgram;
+    use quickcheck::quickcheck;
+
+    #[test]
+    fn accepts_compiler_output() {
+        let program = crate::ast::parse_program(crate::test_helpers::FACTOR_SRC).unwrap();
+        assert_eq!(verify(&program.bytecode_compile()), Ok(()));
+    }
+
+    #[test]
+    fn accepts_compiler_output_on_random_programs() {
+        fn prop(program: RandomProgram) -> bool {
+            verify(&program.0.bytecode_compile()).is_ok()
+        }
+
+        quickcheck(prop as fn(RandomProgram) -> bool);
+    }
+
+    #[test]
+    fn rejects_unmatched_jumps() {
+        assert!(verify(&[JumpZero(1), Out]).is_err());
+        assert!(verify(&[JumpZero(5)]).is_err());
+        assert!(verify(&[JumpNotZero(0)]).is_err());
+        assert!(verify(&[JumpZero(1), JumpNotZero(0)]).is_ok());
+        assert!(verify(&[JumpNotZero(1), JumpZero(0)]).is_err());
+    }
+
+    #[test]
+    fn certifies_what_it_verifies() {
+        let program = crate::ast::parse_program(b"++[>+<-]>.")
+            .unwrap()
+            .bytecode_compile();
+        let certified = certify(&program).unwrap();
+        assert_eq!(&*certified, &*program);
+        assert_eq!(
+            certified.proofs(),
+            &*crate::analysis::prove_bytecode(&program)
+        );
+        crate::test_helpers::assert_interpret(&certified, b"", b"\x02");
+
+        assert!(certify(&[JumpZero(5)]).is_err());
+    }
+
+    #[test]
+    fn rejects_zero_counts() {
+        assert_eq!(
+            verify(&[Out, Right(0)]),
+            Err("Right(0) has a zero count at 1".to_owned())
+        );
+        assert!(verify(&[WriteStr(b"".as_slice().into())]).is_err());
+    }
+}
@@ -0,0 +1,498 @@
+//! Saving compiled programs on disk, so that compiling an unchanged program again is a lookup.
+//!
+//! A [`Cache`](struct.Cache.html) keeps one file in its directory for each program, holding the
+//! program's bytecode, named by a hash of the source and the parse options. Asking the cache for
+//! a program that it has skips the whole pipeline, from parsing to bytecode; for any other, it
+//! compiles the program and saves the result. The bytecode also serves the backends that start
+//! from it, such as the [fused](../fused/index.html) interpreter, and those that start from the
+//! peephole AST, which [`lift`](../bytecode/fn.lift.html) rebuilds, such as the JIT. Machine code
+//! isn't saved, because it refers to the run-time system at addresses that change from one run
+//! to the next.
+//!
+//! Each file starts with the version of this crate and the size of a
+//! [`Count`](../common/type.Count.html), and the cache ignores, and later replaces, files from
+//! any other version, since the compiler's output may have changed. It also keeps the whole
+//! source, so that two programs with the same hash never share an entry, and it counts what it
+//! found in its [statistics](struct.CacheStats.html). The cache is only an optimization: a file
+//! that can't be read just means compiling again, and one that can't be written is skipped.
+//!
+//! In `bfi`, pass `--cache DIR` to keep a cache in DIR.
+//!
+//! ```
+//! use bf::ast::ParseOptions;
+//! use bf::cache::Cache;
+//! use bf::traits::*;
+//!
+//! let dir = std::env::temp_dir().join(format!("bf-cache-doc-{}", std::process::id()));
+//! let mut cache = Cache::new(&dir);
+//! let src = b"++++++[>++++++++<-]>+.";
+//! let first = cache.compile(src, &ParseOptions::default()).unwrap();
+//! let second = cache.compile(src, &ParseOptions::default()).unwrap();
+//! assert_eq!(first, second);
+//! assert_eq!((cache.stats().misses, cache.stats().hits), (1, 1));
+//! assert_eq!(second.interpret_memory(None, b"").unwrap(), b"1");
+//! # std::fs::remove_dir_all(&dir).unwrap();
+//! ```
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::ast::{self, ParseOptions};
+use crate::bytecode::{self, Program};
+use crate::common::{BfResult, Count, Instruction};
+use crate::traits::{BytecodeCompilable, IntoUsize, ToSource};
+
+/// The bytes that every cache file starts with.
+const MAGIC: &[u8] = b"bf-cache\n";
+
+/// The extension of cache files.
+const EXTENSION: &str = "bfc";
+
+/// What a cache found, since it was created.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CacheStats {
+    /// The programs that were in the cache.
+    pub hits: u64,
+    /// The programs that weren't, and were compiled.
+    pub misses: u64,
+    /// The misses where the program's file was from another version of this crate, or couldn't
+    /// be read.
+    pub stale: u64,
+    /// The compiled programs that couldn't be saved.
+    pub write_errors: u64,
+}
+
+/// A directory of compiled programs.
+#[derive(Clone, Debug)]
+pub struct Cache {
+    dir: PathBuf,
+    /// The version that the files must have, which tests can change.
+    version: &'static str,
+    stats: CacheStats,
+}
+
+impl Cache {
+    /// A cache that keeps its files in `dir`, which is created when the first file is saved.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Cache {
+            dir: dir.into(),
+            version: env!("CARGO_PKG_VERSION"),
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// The directory that the cache keeps its files in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// What the cache has found so far.
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Parses and compiles Brainfuck source to bytecode, or loads the bytecode saved the last time
+    /// the same source was compiled with the same options.
+    ///
+    /// # Errors
+    ///
+    /// The same as [`parse_program_with`](../ast/fn.parse_program_with.html). Programs that don't
+    /// parse aren't saved.
+    pub fn compile(&mut self, src: &[u8], options: &ParseOptions) -> BfResult<Box<Program>> {
+        let mut key = vec![b's', options.multi_tape as u8];
+        key.extend_from_slice(
+            &options
+                .max_depth
+                .map_or(u64::MAX, |depth| depth as u64)
+                .to_le_bytes(),
+        );
+        key.extend_from_slice(src);
+        self.get_or_compile(&key, || {
+            ast::parse_program_with(src, options).map(|program| program.bytecode_compile())
+        })
+    }
+
+    /// Compiles a parsed program to bytecode, or loads the bytecode saved the last time the same
+    /// program was compiled. This serves programs that don't come straight from Brainfuck
+    /// source, such as those in [dialects](../dialects/index.html), at the cost of printing the
+    /// program to find it in the cache.
+    pub fn compile_ast(&mut self, program: &ast::Program) -> Box<Program> {
+        let mut key = vec![b'a'];
+        key.extend_from_slice(program.to_source().as_bytes());
+        self.get_or_compile(&key, || Ok(program.bytecode_compile()))
+            .expect("compiling a parsed program doesn't fail")
+    }
+
+    /// Removes every file that the cache has saved, returning how many there were.
+    ///
+    /// # Errors
+    ///
+    /// Any error listing the directory or removing a file, other than that the directory doesn't
+    /// exist.
+    pub fn clear(&self) -> io::Result<usize> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(error) => return Err(error),
+        };
+
+        let mut removed = 0;
+        for entry in entries {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == EXTENSION)
+            {
+                fs::remove_file(path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn get_or_compile<F>(&mut self, key: &[u8], compile: F) -> BfResult<Box<Program>>
+    where
+        F: FnOnce() -> BfResult<Box<Program>>,
+    {
+        let path = self.dir.join(format!("{:016x}.{}", hash(key), EXTENSION));
+        match fs::read(&path) {
+            Ok(bytes) => match self.decode(&bytes, key) {
+                Entry::Found(program) => {
+                    self.stats.hits += 1;
+                    return Ok(program);
+                }
+                Entry::OtherKey => (),
+                Entry::Stale => self.stats.stale += 1,
+            },
+            Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+            Err(_) => self.stats.stale += 1,
+        }
+
+        self.stats.misses += 1;
+        let program = compile()?;
+        if self.save(&path, key, &program).is_err() {
+            self.stats.write_errors += 1;
+        }
+        Ok(program)
+    }
+
+    /// Writes an entry to a temporary file and then moves it into place, so that a reader never
+    /// sees half of one.
+    fn save(&self, path: &Path, key: &[u8], program: &Program) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let temporary = path.with_extension(format!("{}.tmp", process::id()));
+        fs::write(&temporary, self.encode(key, program))?;
+        fs::rename(&temporary, path).inspect_err(|_| {
+            let _ = fs::remove_file(&temporary);
+        })
+    }
+
+    fn header(&self) -> Vec<u8> {
+        let mut result = MAGIC.to_vec();
+        result.push(self.version.len() as u8);
+        result.extend_from_slice(self.version.as_bytes());
+        result.push(std::mem::size_of::<Count>() as u8);
+        result
+    }
+
+    fn encode(&self, key: &[u8], program: &Program) -> Vec<u8> {
+        let mut result = self.header();
+        write_bytes(&mut result, key);
+        write_u64(&mut result, program.len());
+        for instruction in program {
+            encode_instruction(&mut result, instruction);
+        }
+        result
+    }
+
+    fn decode(&self, bytes: &[u8], key: &[u8]) -> Entry {
+        let Some(rest) = bytes.strip_prefix(&self.header()[..]) else {
+            return Entry::Stale;
+        };
+        let mut reader = Reader(rest);
+        match reader.bytes() {
+            Some(stored) if stored == key => (),
+            Some(_) => return Entry::OtherKey,
+            None => return Entry::Stale,
+        }
+        let program = reader.u64().and_then(|len| {
+            let mut program = Vec::new();
+            for _ in 0..len {
+                program.push(decode_instruction(&mut reader)?);
+            }
+            Some(program.into_boxed_slice())
+        });
+        match program {
+            Some(program) if reader.0.is_empty() && bytecode::verify(&program).is_ok() => {
+                Entry::Found(program)
+            }
+            _ => Entry::Stale,
+        }
+    }
+}
+
+/// What a cache file holds.
+enum Entry {
+    /// The program that was asked for.
+    Found(Box<Program>),
+    /// Another program whose key has the same hash.
+    OtherKey,
+    /// Something from another version, or that isn't a whole entry.
+    Stale,
+}
+
+/// The 64-bit FNV-1a hash, which doesn't change between builds, unlike the standard library's.
+fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn write_u64<N: IntoUsize>(out: &mut Vec<u8>, value: N) {
+    out.extend_from_slice(&(value.into_usize() as u64).to_le_bytes());
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_u64(out, bytes.len());
+    out.extend_from_slice(bytes);
+}
+
+fn encode_instruction(out: &mut Vec<u8>, instruction: &Instruction) {
+    use crate::common::Instruction::*;
+
+    let (opcode, count, byte, offset, string): (u8, _, _, _, &[u8]) = match *instruction {
+        Left(count) => (0, Some(count), None, None, &[]),
+        Right(count) => (1, Some(count), None, None, &[]),
+        Add(value) => (2, None, Some(value), None, &[]),
+        In => (3, None, None, None, &[]),
+        Out => (4, None, None, None, &[]),
+        JumpZero(count) => (5, Some(count), None, None, &[]),
+        JumpNotZero(count) => (6, Some(count), None, None, &[]),
+        SetZero => (7, None, None, None, &[]),
+        SetConst(value) => (8, None, Some(value), None, &[]),
+        OffsetAddRight(count) => (9, Some(count), None, None, &[]),
+        OffsetAddLeft(count) => (10, Some(count), None, None, &[]),
+        FindZeroRight(count) => (11, Some(count), None, None, &[]),
+        FindZeroLeft(count) => (12, Some(count), None, None, &[]),
+        MulAddRight(count, factor) => (13, Some(count), Some(factor), None, &[]),
+        MulAddLeft(count, factor) => (14, Some(count), Some(factor), None, &[]),
+        AddAt(offset, value) => (15, None, Some(value), Some(offset), &[]),
+        SetAt(offset, value) => (16, None, Some(value), Some(offset), &[]),
+        WriteStr(ref bytes) => (17, None, None, None, bytes),
+        SwitchTape(count) => (18, Some(count), None, None, &[]),
+    };
+
+    out.push(opcode);
+    if let Some(count) = count {
+        write_u64(out, count);
+    }
+    if let Some(offset) = offset {
+        out.extend_from_slice(&(offset as i64).to_le_bytes());
+    }
+    if let Some(byte) = byte {
+        out.push(byte);
+    }
+    if opcode == 17 {
+        write_bytes(out, string);
+    }
+}
+
+fn decode_instruction(reader: &mut Reader) -> Option<Instruction> {
+    use crate::common::Instruction::*;
+
+    let instruction = match reader.u8()? {
+        0 => Left(reader.count()?),
+        1 => Right(reader.count()?),
+        2 => Add(reader.u8()?),
+        3 => In,
+        4 => Out,
+        5 => JumpZero(reader.count()?),
+        6 => JumpNotZero(reader.count()?),
+        7 => SetZero,
+        8 => SetConst(reader.u8()?),
+        9 => OffsetAddRight(reader.count()?),
+        10 => OffsetAddLeft(reader.count()?),
+        11 => FindZeroRight(reader.count()?),
+        12 => FindZeroLeft(reader.count()?),
+        13 => MulAddRight(reader.count()?, reader.u8()?),
+        14 => MulAddLeft(reader.count()?, reader.u8()?),
+        15 => AddAt(reader.offset()?, reader.u8()?),
+        16 => SetAt(reader.offset()?, reader.u8()?),
+        17 => WriteStr(reader.bytes()?.into()),
+        18 => SwitchTape(reader.count()?),
+        _ => return None,
+    };
+    Some(instruction)
+}
+
+/// Reads the parts of a cache file, or `None` if the file ends first.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (result, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(result)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let bytes = self.take(8)?;
+        Some(u64::from_le_bytes(bytes.try_into().expect("took 8 bytes")))
+    }
+
+    fn count(&mut self) -> Option<Count> {
+        Count::try_from(self.u64()?).ok()
+    }
+
+    fn offset(&mut self) -> Option<isize> {
+        isize::try_from(self.u64()? as i64).ok()
+    }
+
+    fn bytes(&mut self) -> Option<&'a [u8]> {
+        let len = usize::try_from(self.u64()?).ok()?;
+        self.take(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    /// A cache in a new directory, which is removed when the test ends.
+    struct TestCache {
+        cache: Cache,
+    }
+
+    impl TestCache {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("bf-cache-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            TestCache {
+                cache: Cache::new(dir),
+            }
+        }
+
+        fn files(&self) -> Vec<PathBuf> {
+            fs::read_dir(self.cache.dir())
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .collect()
+        }
+    }
+
+    impl Drop for TestCache {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(self.cache.dir());
+        }
+    }
+
+    fn stats(hits: u64, misses: u64, stale: u64) -> CacheStats {
+        CacheStats {
+            hits,
+            misses,
+            stale,
+            write_errors: 0,
+        }
+    }
+
+    #[test]
+    fn hits_after_the_first_compile() {
+        let mut test = TestCache::new("hits");
+        let options = ParseOptions::default();
+        let expected = ast::parse_program(FACTOR_SRC).unwrap().bytecode_compile();
+
+        assert_eq!(
+            test.cache.compile(FACTOR_SRC, &options),
+            Ok(expected.clone())
+        );
+        assert_eq!(
+            test.cache.compile(FACTOR_SRC, &options),
+            Ok(expected.clone())
+        );
+        assert_eq!(test.cache.stats(), stats(1, 1, 0));
+        assert_eq!(test.files().len(), 1);
+
+        // The same program from the AST, and with other options, has entries of its own.
+        let program = ast::parse_program(FACTOR_SRC).unwrap();
+        assert_eq!(test.cache.compile_ast(&program), expected);
+        let options = ParseOptions {
+            max_depth: Some(100),
+            ..options
+        };
+        assert_eq!(test.cache.compile(FACTOR_SRC, &options), Ok(expected));
+        assert_eq!(test.cache.stats(), stats(1, 3, 0));
+        assert_eq!(test.files().len(), 3);
+        assert_eq!(test.cache.clear().unwrap(), 3);
+        assert!(test.files().is_empty());
+    }
+
+    #[test]
+    fn round_trips_every_instruction() {
+        use crate::common::Instruction::*;
+
+        let mut test = TestCache::new("instructions");
+        let program: Box<Program> = Box::new([
+            Left(1),
+            Right(2),
+            Add(3),
+            In,
+            Out,
+            JumpZero(7),
+            SetZero,
+            JumpNotZero(5),
+            SetConst(4),
+            OffsetAddRight(1),
+            OffsetAddLeft(2),
+            FindZeroRight(3),
+            FindZeroLeft(1),
+            MulAddRight(2, 3),
+            MulAddLeft(1, 5),
+            AddAt(-2, 1),
+            SetAt(3, 9),
+            WriteStr(b"hi".as_slice().into()),
+            SwitchTape(1),
+        ]);
+        let saved = test.cache.get_or_compile(b"key", || Ok(program.clone()));
+        let loaded = test.cache.get_or_compile(b"key", || unreachable!());
+        assert_eq!((saved, loaded), (Ok(program.clone()), Ok(program)));
+        assert_eq!(test.cache.stats(), stats(1, 1, 0));
+    }
+
+    #[test]
+    fn replaces_stale_entries() {
+        let mut test = TestCache::new("stale");
+        let options = ParseOptions::default();
+        test.cache.compile(HELLO_WORLD_SRC, &options).unwrap();
+
+        test.cache.version = "0.0.0";
+        test.cache.compile(HELLO_WORLD_SRC, &options).unwrap();
+        test.cache.compile(HELLO_WORLD_SRC, &options).unwrap();
+        assert_eq!(test.cache.stats(), stats(1, 2, 1));
+
+        let file = test.files().pop().unwrap();
+        let mut bytes = fs::read(&file).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        fs::write(&file, bytes).unwrap();
+        let program = test.cache.compile(HELLO_WORLD_SRC, &options).unwrap();
+        assert_interpret(&*program, b"", b"Hello, World!");
+        assert_eq!(test.cache.stats(), stats(1, 3, 2));
+    }
+
+    #[test]
+    fn does_not_save_syntax_errors() {
+        let mut test = TestCache::new("errors");
+        assert!(test.cache.compile(b"[", &ParseOptions::default()).is_err());
+        assert_eq!(test.cache.stats(), stats(0, 1, 0));
+        assert_eq!(test.cache.clear().unwrap(), 0);
+    }
+}
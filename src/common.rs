@@ -3,6 +3,7 @@
 //! This includes error handling and the basic definition of Brainfuck commands.
 
 use std::fmt;
+use std::sync::Arc;
 
 /// The result type for Brainfuck operations that can fail.
 ///
@@ -17,11 +18,28 @@ pub enum Error {
     UnmatchedBegin,
     /// Unmatched ‘]’ (syntax error)
     UnmatchedEnd,
+    /// Loops nested more deeply than the parser allows (syntax error)
+    NestingTooDeep,
     /// If execution continues, the pointer will go below 0 (run-time error)
     PointerUnderflow,
     /// If execution continues, the pointer will go beyond the high end of the
     /// memory (run-time error)
     PointerOverflow,
+    /// The program ran more loop iterations than its [sandbox](../sandbox/index.html) allows
+    /// (run-time error)
+    StepLimit,
+    /// The program wrote more output than its sandbox allows (run-time error)
+    OutputLimit,
+    /// The program needs more memory than its sandbox allows (run-time error)
+    MemoryLimit,
+    /// The program ran for longer than its sandbox allows (run-time error)
+    TimeLimit,
+    /// The program was stopped by raising its sandbox's
+    /// [interrupt](../sandbox/struct.Interrupt.html) (run-time error)
+    Interrupted,
+    /// A `+` or `-` would have taken a cell past 255 or below 0, in a state whose cells
+    /// [trap](../semantics/enum.Overflow.html) (run-time error)
+    CellOverflow,
 }
 
 impl fmt::Display for Error {
@@ -31,14 +49,22 @@ impl fmt::Display for Error {
         match *self {
             UnmatchedBegin => write!(f, "unmatched ‘[’"),
             UnmatchedEnd => write!(f, "unmatched ‘]’"),
+            NestingTooDeep => write!(f, "loops nested too deeply"),
             PointerUnderflow => write!(f, "pointer underflow"),
             PointerOverflow => write!(f, "pointer overflow"),
+            StepLimit => write!(f, "step limit exceeded"),
+            OutputLimit => write!(f, "output limit exceeded"),
+            MemoryLimit => write!(f, "memory limit exceeded"),
+            TimeLimit => write!(f, "time limit exceeded"),
+            Interrupted => write!(f, "interrupted"),
+            CellOverflow => write!(f, "cell overflow"),
         }
     }
 }
 
-/// The eight Brainfuck commands.
+/// The eight Brainfuck commands, and the commands of opt-in extensions.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 #[repr(u8)]
 pub enum Command {
     /// `>`: Increment the data pointer.
@@ -57,6 +83,31 @@ pub enum Command {
     Begin,
     /// `]`: End a loop, which repeats if the byte at the pointer is non-zero.
     End,
+    /// `@`: Switch to the next tape, wrapping around to the first after the last.
+    ///
+    /// This is part of the multi-tape extension, which the parser recognizes only when
+    /// [asked to](../ast/struct.ParseOptions.html). Each tape has its own pointer. With one tape,
+    /// switching does nothing.
+    SwitchTape,
+}
+
+impl Command {
+    /// The character for the command in Brainfuck concrete syntax.
+    pub fn symbol(self) -> u8 {
+        use self::Command::*;
+
+        match self {
+            Right => b'>',
+            Left => b'<',
+            Up => b'+',
+            Down => b'-',
+            In => b',',
+            Out => b'.',
+            Begin => b'[',
+            End => b']',
+            SwitchTape => b'@',
+        }
+    }
 }
 
 #[cfg(not(any(feature = "u16count", feature = "u32count")))]
@@ -89,8 +140,9 @@ pub type Count = u32;
 ///
 /// Unlike in the earlier passes, the loop instructions
 /// do not include a boxed slice of instructions as a
-/// subtree. Note that this type is `Copy`.
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+/// subtree.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Instruction {
     /// Decrease the pointer by the specified offset.
     Left(Count),
@@ -114,6 +166,10 @@ pub enum Instruction {
     ///
     /// Equivalent to the concrete Braincode loop `[-]`.
     SetZero,
+    /// Set the current byte value to the specified value.
+    ///
+    /// `SetConst(3)` is equivalent to the concrete Brainfuck sequence `[-]+++`.
+    SetConst(u8),
     /// Add the byte at the pointer to the byte at the specified offset and zero the byte at the
     /// pointer.
     ///
@@ -132,4 +188,65 @@ pub enum Instruction {
     ///
     /// `FindZeroLeft(3)` is equivalent to the concrete Brainfuck loop `[<<<]`.
     FindZeroLeft(Count),
+    /// Add the byte at the pointer times the given factor to the byte at the specified offset,
+    /// unless the byte at the pointer is zero.
+    ///
+    /// The concrete Brainfuck loop `[->>+++<<]` is equivalent to `MulAddRight(2, 3)` followed by
+    /// `SetZero`.
+    MulAddRight(Count, u8),
+    /// Add the byte at the pointer times the given factor to the byte at the specified offset,
+    /// unless the byte at the pointer is zero.
+    ///
+    /// The concrete Brainfuck loop `[-<<+++>>]` is equivalent to `MulAddLeft(2, 3)` followed by
+    /// `SetZero`.
+    MulAddLeft(Count, u8),
+    /// Add the specified value to the byte at the specified offset from the pointer, without
+    /// moving the pointer.
+    ///
+    /// `AddAt(2, 3)` is equivalent to the concrete Brainfuck sequence `>>+++<<`, and
+    /// `AddAt(-1, 255)` to `<->`.
+    AddAt(isize, u8),
+    /// Set the byte at the specified offset from the pointer to the specified value, without
+    /// moving the pointer.
+    ///
+    /// `SetAt(1, 3)` is equivalent to the concrete Brainfuck sequence `>[-]+++<`.
+    SetAt(isize, u8),
+    /// Write the given bytes of output.
+    ///
+    /// This is what a run of `Out` instructions becomes when the bytes they write are known at
+    /// compile time, so `SetConst(104) Out SetConst(105) Out` writes the same as `WriteStr(b"hi")`.
+    /// The bytes are shared, so cloning the instruction doesn't copy them.
+    WriteStr(Arc<[u8]>),
+    /// Switches forward by the specified number of tapes.
+    ///
+    /// `SwitchTape(2)` is equivalent to the multi-tape command sequence `@@`.
+    SwitchTape(Count),
+}
+
+impl Instruction {
+    /// Checks that the instruction's count operand is non-zero, for the instructions where a
+    /// count of zero makes no sense. Also checks that offsets from the pointer aren't zero and that
+    /// `WriteStr` writes something. Jump targets may be zero.
+    pub(crate) fn check_count(&self) -> Result<(), String> {
+        use self::Instruction::*;
+
+        match *self {
+            Left(count)
+            | Right(count)
+            | OffsetAddRight(count)
+            | OffsetAddLeft(count)
+            | FindZeroRight(count)
+            | FindZeroLeft(count)
+            | MulAddRight(count, _)
+            | MulAddLeft(count, _)
+            | SwitchTape(count)
+                if count == 0 =>
+            {
+                Err(format!("{:?} has a zero count", self))
+            }
+            AddAt(0, _) | SetAt(0, _) => Err(format!("{:?} has a zero offset", self)),
+            WriteStr(ref bytes) if bytes.is_empty() => Err(format!("{:?} is empty", self)),
+            _ => Ok(()),
+        }
+    }
 }
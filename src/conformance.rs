@@ -0,0 +1,203 @@
+//! Differential testing across backends.
+//!
+//! Every backend should give the same observable behavior for the same program and input: the
+//! same output, and the same result. This module runs a program through each of the backends
+//! enabled in this build and compares what they do against the unoptimized AST interpreter,
+//! which serves as the reference.
+//!
+//! In `bfi`, pass the `--verify` flag to check a program this way.
+
+use std::fmt;
+use std::io::Cursor;
+
+use crate::ast;
+use crate::common::BfResult;
+use crate::state::State;
+
+pub use crate::backend::Backend;
+
+impl Backend {
+    /// Runs the program on this backend with the given memory size and input.
+    pub fn run(self, program: &ast::Program, size: Option<usize>, input: &[u8]) -> Outcome {
+        let mut state = size.map(State::with_capacity).unwrap_or_default();
+        self.run_in(program, &mut state, input)
+    }
+
+    /// Runs the program on this backend against the given state, with the given input.
+    pub fn run_in(self, program: &ast::Program, state: &mut State, input: &[u8]) -> Outcome {
+        let mut output = Cursor::new(Vec::new());
+        let result = self.interpret_in(program, state, Cursor::new(input), &mut output);
+        Outcome {
+            output: output.into_inner(),
+            result,
+        }
+    }
+}
+
+/// The observable behavior of running a program.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Outcome {
+    /// Everything the program wrote, including before any error.
+    pub output: Vec<u8>,
+    /// Whether the program finished or stopped with an error.
+    pub result: BfResult<()>,
+}
+
+/// A backend whose behavior differs from the reference.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Mismatch {
+    /// The backend that disagreed.
+    pub backend: Backend,
+    /// What the reference backend did.
+    pub expected: Outcome,
+    /// What the disagreeing backend did.
+    pub actual: Outcome,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} disagrees with {}: ", self.backend, Backend::Ast)?;
+
+        if self.expected.result != self.actual.result {
+            write!(
+                f,
+                "expected result {:?}, got {:?}",
+                self.expected.result, self.actual.result
+            )
+        } else {
+            let common = self
+                .expected
+                .output
+                .iter()
+                .zip(&self.actual.output)
+                .take_while(|(a, b)| a == b)
+                .count();
+            write!(
+                f,
+                "output differs at byte {} (expected {} bytes, got {})",
+                common,
+                self.expected.output.len(),
+                self.actual.output.len()
+            )
+        }
+    }
+}
+
+/// Runs the program on every backend, returning the common outcome if they all agree.
+pub fn check(
+    program: &ast::Program,
+    size: Option<usize>,
+    input: &[u8],
+) -> Result<Outcome, Mismatch> {
+    let state = size.map(State::with_capacity).unwrap_or_default();
+    check_in(program, &state, input)
+}
+
+/// Runs the program on every backend, each starting from a copy of `state`, returning the
+/// common outcome if they all agree.
+pub fn check_in(program: &ast::Program, state: &State, input: &[u8]) -> Result<Outcome, Mismatch> {
+    let expected = Backend::Ast.run_in(program, &mut state.clone(), input);
+
+    for backend in Backend::all().into_iter().skip(1) {
+        let actual = backend.run_in(program, &mut state.clone(), input);
+        if actual != expected {
+            return Err(Mismatch {
+                backend,
+                expected,
+                actual,
+            });
+        }
+    }
+
+    Ok(expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Error;
+    use crate::test_helpers::*;
+    use quickcheck::{quickcheck, TestResult};
+
+    #[test]
+    fn hello_world() {
+        assert_agree(HELLO_WORLD_SRC, b"", b"Hello, World!", Ok(()));
+    }
+
+    #[test]
+    fn factoring() {
+        assert_agree(FACTOR_SRC, b"100\n", b"100: 2 2 5 5\n", Ok(()));
+    }
+
+    #[test]
+    fn errors_keep_earlier_output() {
+        assert_agree(b"+.<", b"", b"\x01", Err(Error::PointerUnderflow));
+        assert_agree(b"+[>+]", b"", b"", Err(Error::PointerOverflow));
+        assert_agree(b"+>+>+[<]", b"", b"", Err(Error::PointerUnderflow));
+    }
+
+    #[test]
+    fn backends_leave_the_same_state() {
+        let program = ast::parse_program(b"++[->+>+++<<]>>>+<<").unwrap();
+        for backend in Backend::all() {
+            let mut state = State::with_capacity(10);
+            assert_eq!(backend.run_in(&program, &mut state, b"").result, Ok(()));
+            assert_eq!(state.cells()[..4], [0, 2, 6, 1], "{}", backend);
+            assert_eq!(state.pointer(), 1, "{}", backend);
+        }
+    }
+
+    #[test]
+    fn prefilled_states_agree() {
+        let program = ast::parse_program(b"[->+<>.<]").unwrap();
+        let state = State::from_cells(vec![3, 4]);
+        assert_eq!(check_in(&program, &state, b"").unwrap().output, [5, 6, 7]);
+        for backend in Backend::all() {
+            let mut state = state.clone();
+            assert_eq!(backend.run_in(&program, &mut state, b"").result, Ok(()));
+            assert_eq!(state.cells(), [0, 7], "{}", backend);
+        }
+    }
+
+    #[test]
+    fn mismatch_describes_output() {
+        let mismatch = Mismatch {
+            backend: Backend::Fused,
+            expected: Outcome {
+                output: b"abc".to_vec(),
+                result: Ok(()),
+            },
+            actual: Outcome {
+                output: b"abd".to_vec(),
+                result: Ok(()),
+            },
+        };
+        assert_eq!(
+            mismatch.to_string(),
+            "fused disagrees with ast: output differs at byte 2 (expected 3 bytes, got 3)"
+        );
+    }
+
+    #[test]
+    fn random_programs_agree() {
+        fn prop(program: RandomProgram, input: Vec<u8>) -> TestResult {
+            if !halts_within(&program.0, 32, &input, 10_000) {
+                return TestResult::discard();
+            }
+
+            match check(&program.0, Some(32), &input) {
+                Ok(_) => TestResult::passed(),
+                Err(mismatch) => TestResult::error(mismatch.to_string()),
+            }
+        }
+
+        quickcheck(prop as fn(RandomProgram, Vec<u8>) -> TestResult);
+    }
+
+    fn assert_agree(src: &[u8], input: &[u8], output: &[u8], result: BfResult<()>) {
+        let program = ast::parse_program(src).unwrap();
+        let outcome = check(&program, Some(100), input).unwrap_or_else(|m| panic!("{}", m));
+        assert_eq!(outcome.output, output);
+        assert_eq!(outcome.result, result);
+    }
+}
@@ -0,0 +1,521 @@
+//! Counting what a program does as it runs.
+//!
+//! A [`Counters`](struct.Counters.html) records how many instructions of each
+//! [`Kind`](enum.Kind.html) a run executed, how many loop iterations it started, the highest
+//! position the pointer reached, the highest cell that the program used, which says how much memory
+//! it needs, and how many bytes it read and wrote. The AST, RLE, peephole, and bytecode
+//! interpreters take counting hooks that compile away when they aren't counting; the JIT instead
+//! compiles counting code when asked to, and keeps the counters in its
+//! [run-time system](../rts/index.html) state.
+//!
+//! Loops in the tree-shaped IRs don't execute jump instructions, so only the bytecode
+//! interpreter counts `JumpZero` and `JumpNotZero`. Every backend counts loop iterations the
+//! same way: once each time a loop body starts.
+//!
+//! The interpreters also stop a counting run with `Interrupted` at the start of a loop body once
+//! its [interrupt](../sandbox/struct.Interrupt.html), if it has one, is raised, keeping the counts
+//! so far. JIT-compiled counting code doesn't check it.
+//!
+//! In `bfi`, pass the `--stats` flag to print the counters after a run.
+
+use std::io::{self, Read, Write};
+
+use crate::common::{BfResult, Instruction};
+use crate::sandbox::Interrupt;
+use crate::state::State;
+use crate::traits::Interpretable;
+
+/// Program forms that can be interpreted while counting what they do.
+pub trait CountingInterpretable: Interpretable {
+    /// Interprets a program against the given state, adding what it does to `counters`.
+    fn interpret_counting<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        input: R,
+        output: W,
+        counters: &mut Counters,
+    ) -> BfResult<()>;
+}
+
+impl<P: CountingInterpretable + ?Sized> CountingInterpretable for Box<P> {
+    fn interpret_counting<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        input: R,
+        output: W,
+        counters: &mut Counters,
+    ) -> BfResult<()> {
+        (**self).interpret_counting(state, input, output, counters)
+    }
+}
+
+/// The kinds of instruction that are counted, one for each
+/// [`Instruction`](../common/enum.Instruction.html) variant.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Kind {
+    Left,
+    Right,
+    Add,
+    In,
+    Out,
+    JumpZero,
+    JumpNotZero,
+    SetZero,
+    SetConst,
+    OffsetAddRight,
+    OffsetAddLeft,
+    FindZeroRight,
+    FindZeroLeft,
+    MulAddRight,
+    MulAddLeft,
+    AddAt,
+    SetAt,
+    WriteStr,
+    SwitchTape,
+}
+
+/// The number of instruction kinds.
+pub const KINDS: usize = 19;
+
+impl Kind {
+    /// All the kinds, in the order of the `Instruction` variants.
+    pub const ALL: [Kind; KINDS] = [
+        Kind::Left,
+        Kind::Right,
+        Kind::Add,
+        Kind::In,
+        Kind::Out,
+        Kind::JumpZero,
+        Kind::JumpNotZero,
+        Kind::SetZero,
+        Kind::SetConst,
+        Kind::OffsetAddRight,
+        Kind::OffsetAddLeft,
+        Kind::FindZeroRight,
+        Kind::FindZeroLeft,
+        Kind::MulAddRight,
+        Kind::MulAddLeft,
+        Kind::AddAt,
+        Kind::SetAt,
+        Kind::WriteStr,
+        Kind::SwitchTape,
+    ];
+
+    /// The kind of the given instruction.
+    pub fn of(instruction: &Instruction) -> Kind {
+        match instruction {
+            Instruction::Left(_) => Kind::Left,
+            Instruction::Right(_) => Kind::Right,
+            Instruction::Add(_) => Kind::Add,
+            Instruction::In => Kind::In,
+            Instruction::Out => Kind::Out,
+            Instruction::JumpZero(_) => Kind::JumpZero,
+            Instruction::JumpNotZero(_) => Kind::JumpNotZero,
+            Instruction::SetZero => Kind::SetZero,
+            Instruction::SetConst(_) => Kind::SetConst,
+            Instruction::OffsetAddRight(_) => Kind::OffsetAddRight,
+            Instruction::OffsetAddLeft(_) => Kind::OffsetAddLeft,
+            Instruction::FindZeroRight(_) => Kind::FindZeroRight,
+            Instruction::FindZeroLeft(_) => Kind::FindZeroLeft,
+            Instruction::MulAddRight(..) => Kind::MulAddRight,
+            Instruction::MulAddLeft(..) => Kind::MulAddLeft,
+            Instruction::AddAt(..) => Kind::AddAt,
+            Instruction::SetAt(..) => Kind::SetAt,
+            Instruction::WriteStr(_) => Kind::WriteStr,
+            Instruction::SwitchTape(_) => Kind::SwitchTape,
+        }
+    }
+
+    /// The name of the kind, which is the name of its `Instruction` variant.
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Left => "Left",
+            Kind::Right => "Right",
+            Kind::Add => "Add",
+            Kind::In => "In",
+            Kind::Out => "Out",
+            Kind::JumpZero => "JumpZero",
+            Kind::JumpNotZero => "JumpNotZero",
+            Kind::SetZero => "SetZero",
+            Kind::SetConst => "SetConst",
+            Kind::OffsetAddRight => "OffsetAddRight",
+            Kind::OffsetAddLeft => "OffsetAddLeft",
+            Kind::FindZeroRight => "FindZeroRight",
+            Kind::FindZeroLeft => "FindZeroLeft",
+            Kind::MulAddRight => "MulAddRight",
+            Kind::MulAddLeft => "MulAddLeft",
+            Kind::AddAt => "AddAt",
+            Kind::SetAt => "SetAt",
+            Kind::WriteStr => "WriteStr",
+            Kind::SwitchTape => "SwitchTape",
+        }
+    }
+}
+
+/// What a program did while it ran.
+///
+/// The layout is `repr(C)` because JIT-compiled code updates the fields in place.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[repr(C)]
+pub struct Counters {
+    pub(crate) instructions: [u64; KINDS],
+    pub(crate) loop_iterations: u64,
+    pub(crate) peak_pointer: u64,
+    pub(crate) peak_cell: u64,
+    pub(crate) bytes_read: u64,
+    pub(crate) bytes_written: u64,
+    interrupt: Option<Interrupt>,
+}
+
+impl Counters {
+    /// Creates counters that are all zero.
+    pub fn new() -> Self {
+        Counters::default()
+    }
+
+    /// Stops the interpreters that count with these counters at the next loop iteration once
+    /// `interrupt` is raised.
+    pub fn set_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt = Some(interrupt);
+    }
+
+    /// The number of instructions of the given kind that were executed.
+    pub fn count(&self, kind: Kind) -> u64 {
+        self.instructions[kind as usize]
+    }
+
+    /// The number of instructions of each kind that were executed, leaving out kinds that
+    /// never ran.
+    pub fn counts(&self) -> impl Iterator<Item = (Kind, u64)> + '_ {
+        Kind::ALL
+            .iter()
+            .map(move |&kind| (kind, self.count(kind)))
+            .filter(|&(_, count)| count != 0)
+    }
+
+    /// The total number of instructions that were executed.
+    pub fn instructions(&self) -> u64 {
+        self.instructions.iter().sum()
+    }
+
+    /// The number of times that a loop body started.
+    pub fn loop_iterations(&self) -> u64 {
+        self.loop_iterations
+    }
+
+    /// The highest position that the pointer reached.
+    pub fn peak_pointer(&self) -> usize {
+        self.peak_pointer as usize
+    }
+
+    /// The highest cell that the pointer reached or that an instruction changed through an
+    /// offset from the pointer.
+    ///
+    /// The program needs a tape of at least one more cell than this.
+    pub fn peak_cell(&self) -> usize {
+        self.peak_cell as usize
+    }
+
+    /// The number of bytes that the program read. Reads at the end of input don't count.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// The number of bytes that the program wrote.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Adds the counts from another run, keeping the higher peaks.
+    pub fn add(&mut self, other: &Counters) {
+        for (count, other) in self.instructions.iter_mut().zip(&other.instructions) {
+            *count += other;
+        }
+        self.loop_iterations += other.loop_iterations;
+        self.peak_pointer = self.peak_pointer.max(other.peak_pointer);
+        self.peak_cell = self.peak_cell.max(other.peak_cell);
+        self.bytes_read += other.bytes_read;
+        self.bytes_written += other.bytes_written;
+    }
+}
+
+/// Passes reads or writes through, counting the bytes.
+pub(crate) struct Metered<T> {
+    inner: T,
+    bytes: u64,
+}
+
+impl<R: Read> Read for Metered<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.bytes += len as u64;
+        Ok(len)
+    }
+}
+
+impl<W: Write> Write for Metered<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.bytes += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Calls `run` with the input and output metered, adding the bytes read and written to
+/// `counters`.
+pub(crate) fn count_io<R, W, F>(
+    input: R,
+    output: W,
+    counters: &mut Counters,
+    run: F,
+) -> BfResult<()>
+where
+    R: Read,
+    W: Write,
+    F: FnOnce(&mut Metered<R>, &mut Metered<W>, &mut Counters) -> BfResult<()>,
+{
+    let mut input = Metered {
+        inner: input,
+        bytes: 0,
+    };
+    let mut output = Metered {
+        inner: output,
+        bytes: 0,
+    };
+    let result = run(&mut input, &mut output, counters);
+    counters.bytes_read += input.bytes;
+    counters.bytes_written += output.bytes;
+    result
+}
+
+/// The hooks that the interpreters call as they run.
+///
+/// The interpreters are generic in their hooks, so that with `()` as the hooks they compile to
+/// the same code as if they had none.
+pub(crate) trait Hooks {
+    /// Called before each instruction executes.
+    fn instruction(&mut self, kind: Kind);
+
+    /// Called after each instruction executes, with the pointer's position.
+    fn pointer(&mut self, pointer: usize);
+
+    /// Called after an instruction changes a cell at an offset from the pointer, with the cell's
+    /// address. Only cells to the right of the pointer need to be reported.
+    fn cell(&mut self, address: usize);
+
+    /// Called each time that a loop body starts. An error stops the run.
+    fn loop_iteration(&mut self) -> BfResult<()>;
+}
+
+impl Hooks for () {
+    #[inline(always)]
+    fn instruction(&mut self, _kind: Kind) {}
+
+    #[inline(always)]
+    fn pointer(&mut self, _pointer: usize) {}
+
+    #[inline(always)]
+    fn cell(&mut self, _address: usize) {}
+
+    #[inline(always)]
+    fn loop_iteration(&mut self) -> BfResult<()> {
+        Ok(())
+    }
+}
+
+impl Hooks for Counters {
+    #[inline]
+    fn instruction(&mut self, kind: Kind) {
+        self.instructions[kind as usize] += 1;
+    }
+
+    #[inline]
+    fn pointer(&mut self, pointer: usize) {
+        self.peak_pointer = self.peak_pointer.max(pointer as u64);
+        self.cell(pointer);
+    }
+
+    #[inline]
+    fn cell(&mut self, address: usize) {
+        self.peak_cell = self.peak_cell.max(address as u64);
+    }
+
+    #[inline]
+    fn loop_iteration(&mut self) -> BfResult<()> {
+        self.loop_iterations += 1;
+        match self.interrupt {
+            Some(interrupt) => interrupt.check(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+    use crate::traits::*;
+
+    #[test]
+    fn kinds_match_instructions() {
+        use crate::common::Instruction::*;
+
+        let instructions = [
+            Left(1),
+            Right(1),
+            Add(1),
+            In,
+            Out,
+            JumpZero(0),
+            JumpNotZero(0),
+            SetZero,
+            SetConst(1),
+            OffsetAddRight(1),
+            OffsetAddLeft(1),
+            FindZeroRight(1),
+            FindZeroLeft(1),
+            MulAddRight(1, 2),
+            MulAddLeft(1, 2),
+            AddAt(-1, 2),
+            SetAt(1, 2),
+            WriteStr(b"hi".as_slice().into()),
+            SwitchTape(1),
+        ];
+        for (instruction, kind) in instructions.iter().zip(Kind::ALL) {
+            assert_eq!(Kind::of(instruction), kind);
+            assert_eq!(
+                format!("{:?}", instruction).split('(').next(),
+                Some(kind.name())
+            );
+        }
+    }
+
+    #[test]
+    fn ast_counts_commands() {
+        let program = crate::ast::parse_program(b"++[>+<-]>.").unwrap();
+        let counters = count(&*program, b"");
+        assert_eq!(counters.count(Kind::Add), 2 + 2 * 2);
+        assert_eq!(counters.count(Kind::Right), 3);
+        assert_eq!(counters.count(Kind::Out), 1);
+        assert_eq!(counters.loop_iterations(), 2);
+        assert_eq!(counters.peak_pointer(), 1);
+    }
+
+    #[test]
+    fn bytecode_counts_jumps() {
+        let program = crate::ast::parse_program(b"++[>+<-.]").unwrap();
+        let counters = count(&*program.bytecode_compile(), b"");
+        assert_eq!(counters.count(Kind::JumpZero), 1);
+        assert_eq!(counters.count(Kind::JumpNotZero), 2);
+        assert_eq!(counters.loop_iterations(), 2);
+        assert_eq!(counters.instructions(), 1 + 1 + 3 * 2 + 2);
+    }
+
+    #[test]
+    fn peephole_and_bytecode_agree() {
+        let program = crate::ast::parse_program(FACTOR_SRC).unwrap();
+        let peephole = count(&*program.peephole_compile(), b"100\n");
+        let bytecode = count(&*program.bytecode_compile(), b"100\n");
+
+        for kind in Kind::ALL {
+            if kind != Kind::JumpZero && kind != Kind::JumpNotZero {
+                assert_eq!(peephole.count(kind), bytecode.count(kind), "{:?}", kind);
+            }
+        }
+        assert_eq!(peephole.loop_iterations(), bytecode.loop_iterations());
+        assert_eq!(peephole.peak_pointer(), bytecode.peak_pointer());
+        assert_eq!(peephole.peak_cell(), bytecode.peak_cell());
+    }
+
+    #[test]
+    fn peak_cell_counts_offsets() {
+        // `>+<` becomes `AddAt(1, 1)`, and the loop `MulAddRight(3, 2)`.
+        let program = crate::ast::parse_program(b">+<++[->>>++<<<]").unwrap();
+        for counters in [
+            count(&*program.peephole_compile(), b""),
+            count(&*program.bytecode_compile(), b""),
+        ] {
+            assert_eq!(counters.peak_pointer(), 0);
+            assert_eq!(counters.peak_cell(), 3);
+        }
+    }
+
+    #[test]
+    fn counting_keeps_behavior() {
+        let program = crate::ast::parse_program(FACTOR_SRC).unwrap();
+        let mut output = Vec::new();
+        let mut counters = Counters::new();
+        program
+            .rle_compile()
+            .interpret_counting(&mut State::new(), &b"100\n"[..], &mut output, &mut counters)
+            .unwrap();
+        assert_eq!(output, b"100: 2 2 5 5\n");
+        assert_eq!((counters.bytes_read(), counters.bytes_written()), (4, 13));
+    }
+
+    #[test]
+    fn errors_keep_counts() {
+        let program = crate::ast::parse_program(b"+>+>+[<]").unwrap();
+        let mut counters = Counters::new();
+        let result = program.peephole_compile().interpret_counting(
+            &mut State::new(),
+            &b""[..],
+            Vec::new(),
+            &mut counters,
+        );
+        assert_eq!(result, Err(crate::common::Error::PointerUnderflow));
+        assert_eq!(counters.count(Kind::FindZeroLeft), 1);
+        assert_eq!(counters.peak_pointer(), 2);
+    }
+
+    #[test]
+    fn stops_when_interrupted() {
+        use crate::common::Error;
+        use std::sync::atomic::AtomicBool;
+
+        static FLAG: AtomicBool = AtomicBool::new(true);
+        let program = crate::ast::parse_program(b"++>+[]").unwrap();
+        let mut counters = Counters::new();
+        counters.set_interrupt(Interrupt::new(&FLAG));
+        let result = program.bytecode_compile().interpret_counting(
+            &mut State::new(),
+            &b""[..],
+            Vec::new(),
+            &mut counters,
+        );
+        assert_eq!(result, Err(Error::Interrupted));
+        assert_eq!(counters.instructions(), 4);
+        assert_eq!(counters.loop_iterations(), 1);
+    }
+
+    #[cfg(feature = "jit")]
+    #[test]
+    fn jit_agrees_with_peephole() {
+        for (src, input) in [
+            (FACTOR_SRC, &b"100\n"[..]),
+            (HELLO_WORLD_SRC, b""),
+            (b"+[>+]", b""),
+            (b">+<++[->>>++<<<]++[->>+<<]", b""),
+        ] {
+            let program = crate::ast::parse_program(src).unwrap();
+            let expected = count(&*program.peephole_compile(), input);
+            let actual = count(&program.jit_compile_counting(true), input);
+            assert_eq!(actual, expected);
+        }
+    }
+
+    fn count<P: CountingInterpretable + ?Sized>(program: &P, input: &[u8]) -> Counters {
+        let mut counters = Counters::new();
+        let _ = program.interpret_counting(
+            &mut State::with_capacity(100),
+            input,
+            Vec::new(),
+            &mut counters,
+        );
+        counters
+    }
+}
@@ -1,10 +1,12 @@
 use crate::common::{BfResult, Error};
 use crate::peephole;
 use crate::rts::{self, RtsState};
+use crate::semantics::Overflow;
 use crate::state::State;
-use crate::traits::Interpretable;
+use crate::traits::{CompileTo, Interpretable, Stage};
 use std::io::{Read, Write};
 use std::mem;
+use std::sync::Arc;
 
 use cranelift_codegen::ir::condcodes::IntCC;
 use cranelift_codegen::ir::types;
@@ -18,6 +20,9 @@ pub struct Program {
     #[allow(dead_code)]
     module: JITModule,
     main_fn: *const u8,
+    /// The bytes that the code writes for `WriteStr`, which it points at.
+    #[allow(dead_code)]
+    strings: Vec<Arc<[u8]>>,
 }
 
 // Safety: The pointer is to JIT-compiled code that is owned by the module.
@@ -35,6 +40,16 @@ impl<T: peephole::PeepholeCompilable + ?Sized> CraneliftCompilable for T {
     }
 }
 
+impl Stage for Program {
+    type Compiled = Program;
+}
+
+impl<T: CraneliftCompilable + ?Sized> CompileTo<Program> for T {
+    fn compile_to(&self) -> Program {
+        self.cranelift_compile()
+    }
+}
+
 pub fn compile(program: &peephole::Program) -> Program {
     let mut flag_builder = settings::builder();
     flag_builder.set("use_colocated_libcalls", "false").unwrap();
@@ -52,6 +67,7 @@ pub fn compile(program: &peephole::Program) -> Program {
     // Symbols for runtime system calls
     module_builder.symbol("rts_read", rts::RtsState::read as *const u8);
     module_builder.symbol("rts_write", rts::RtsState::write as *const u8);
+    module_builder.symbol("rts_write_str", rts::RtsState::write_str as *const u8);
 
     let mut module = JITModule::new(module_builder);
 
@@ -73,7 +89,7 @@ pub fn compile(program: &peephole::Program) -> Program {
     ctx.func.signature = sig;
 
     let mut builder_context = FunctionBuilderContext::new();
-    {
+    let strings = {
         let mut builder = FunctionBuilder::new(&mut ctx.func, &mut builder_context);
         let entry_block = builder.create_block();
         builder.append_block_params_for_function_params(entry_block);
@@ -85,9 +101,16 @@ pub fn compile(program: &peephole::Program) -> Program {
         let mem_size = builder.block_params(entry_block)[1];
         let rts_ptr = builder.block_params(entry_block)[2];
 
-        // Variables
+        // Variables, with the pointer starting where the caller says.
         let ptr_var = builder.declare_var(ptr_type);
-        builder.def_var(ptr_var, mem_ptr);
+        let start = builder.ins().load(
+            ptr_type,
+            MemFlags::trusted(),
+            rts_ptr,
+            RtsState::pointer_offset(),
+        );
+        let ptr = builder.ins().iadd(mem_ptr, start);
+        builder.def_var(ptr_var, ptr);
 
         // We calculate mem_limit = mem_start + mem_size
         let mem_limit = builder.ins().iadd(mem_ptr, mem_size);
@@ -108,10 +131,21 @@ pub fn compile(program: &peephole::Program) -> Program {
             finish_block,
             underflow_block,
             overflow_block,
+            strings: Vec::new(),
         };
 
         compiler.compile(program);
 
+        // Tell the caller where the pointer ended up.
+        let ptr = compiler.builder.use_var(compiler.ptr_var);
+        let offset = compiler.builder.ins().isub(ptr, mem_ptr);
+        compiler.builder.ins().store(
+            MemFlags::trusted(),
+            offset,
+            rts_ptr,
+            RtsState::pointer_offset(),
+        );
+
         let okay = compiler.builder.ins().iconst(ptr_type, rts::OKAY as i64);
         let args = [BlockArg::Value(okay)];
         compiler.builder.ins().jump(compiler.finish_block, &args);
@@ -142,7 +176,8 @@ pub fn compile(program: &peephole::Program) -> Program {
         compiler.builder.seal_block(compiler.finish_block);
 
         compiler.builder.finalize();
-    }
+        compiler.strings
+    };
 
     module.define_function(func_id, &mut ctx).unwrap();
     module.finalize_definitions().unwrap();
@@ -152,6 +187,7 @@ pub fn compile(program: &peephole::Program) -> Program {
     Program {
         module,
         main_fn: code,
+        strings,
     }
 }
 
@@ -166,6 +202,7 @@ struct Compiler<'a> {
     finish_block: cranelift_codegen::ir::Block,
     underflow_block: cranelift_codegen::ir::Block,
     overflow_block: cranelift_codegen::ir::Block,
+    strings: Vec<Arc<[u8]>>,
 }
 
 impl<'a> Compiler<'a> {
@@ -221,20 +258,39 @@ impl<'a> Compiler<'a> {
                 let added = self.builder.ins().iadd_imm(val, *count as i64);
                 self.builder.ins().store(MemFlags::new(), added, ptr, 0);
             }
+            // Compiled code runs with a single tape, where switching does nothing.
+            Instr(SwitchTape(_)) => (),
             Instr(SetZero) => {
                 let ptr = self.builder.use_var(self.ptr_var);
                 let zero = self.builder.ins().iconst(types::I8, 0);
                 self.builder.ins().store(MemFlags::new(), zero, ptr, 0);
             }
+            Instr(SetConst(value)) => {
+                let ptr = self.builder.use_var(self.ptr_var);
+                let value = self.builder.ins().iconst(types::I8, *value as i64);
+                self.builder.ins().store(MemFlags::new(), value, ptr, 0);
+            }
             Instr(Out) => {
                 let ptr = self.builder.use_var(self.ptr_var);
                 let val = self.builder.ins().load(types::I8, MemFlags::new(), ptr, 0);
                 let val32 = self.builder.ins().uextend(types::I32, val);
                 self.call_rts("rts_write", &[self.rts_ptr, val32]);
             }
+            Instr(WriteStr(bytes)) => {
+                // The program keeps the bytes, so they outlive the compiled code.
+                self.strings.push(bytes.clone());
+                let start = self
+                    .builder
+                    .ins()
+                    .iconst(self.ptr_type, bytes.as_ptr() as i64);
+                let len = self.builder.ins().iconst(self.ptr_type, bytes.len() as i64);
+                self.call_rts("rts_write_str", &[self.rts_ptr, start, len]);
+            }
             Instr(In) => {
                 let ptr = self.builder.use_var(self.ptr_var);
-                let res = self.call_rts("rts_read", &[self.rts_ptr]);
+                let val = self.builder.ins().load(types::I8, MemFlags::new(), ptr, 0);
+                let val32 = self.builder.ins().uextend(types::I32, val);
+                let res = self.call_rts("rts_read", &[self.rts_ptr, val32]);
                 let res8 = self.builder.ins().ireduce(types::I8, res);
                 self.builder.ins().store(MemFlags::new(), res8, ptr, 0);
             }
@@ -377,12 +433,66 @@ impl<'a> Compiler<'a> {
                 self.builder.seal_block(body_block);
                 self.builder.seal_block(skip_block);
             }
+            Instr(MulAddRight(offset, factor)) => self.compile_mul_add(*offset as i64, *factor),
+            Instr(MulAddLeft(offset, factor)) => self.compile_mul_add(-(*offset as i64), *factor),
+            Instr(AddAt(offset, amount)) => {
+                let ptr = self.builder.use_var(self.ptr_var);
+                let target_ptr = self.builder.ins().iadd_imm(ptr, *offset as i64);
+                let val = self
+                    .builder
+                    .ins()
+                    .load(types::I8, MemFlags::new(), target_ptr, 0);
+                let added = self.builder.ins().iadd_imm(val, *amount as i64);
+                self.builder
+                    .ins()
+                    .store(MemFlags::new(), added, target_ptr, 0);
+            }
+            Instr(SetAt(offset, value)) => {
+                let ptr = self.builder.use_var(self.ptr_var);
+                let target_ptr = self.builder.ins().iadd_imm(ptr, *offset as i64);
+                let value = self.builder.ins().iconst(types::I8, *value as i64);
+                self.builder
+                    .ins()
+                    .store(MemFlags::new(), value, target_ptr, 0);
+            }
             _ => {
                 // Ignore unimplemented peephole instructions for now
             }
         }
     }
 
+    /// Adds the current byte times `factor` at `offset`, unless the current byte is zero.
+    fn compile_mul_add(&mut self, offset: i64, factor: u8) {
+        let skip_block = self.builder.create_block();
+        let body_block = self.builder.create_block();
+
+        let ptr = self.builder.use_var(self.ptr_var);
+        let val = self.builder.ins().load(types::I8, MemFlags::new(), ptr, 0);
+        let cond = self.builder.ins().icmp_imm(IntCC::NotEqual, val, 0);
+        let no_args: [BlockArg; 0] = [];
+        self.builder
+            .ins()
+            .brif(cond, body_block, &no_args, skip_block, &no_args);
+
+        self.builder.switch_to_block(body_block);
+        let ptr = self.builder.use_var(self.ptr_var);
+        let target_ptr = self.builder.ins().iadd_imm(ptr, offset);
+        let target_val = self
+            .builder
+            .ins()
+            .load(types::I8, MemFlags::new(), target_ptr, 0);
+        let product = self.builder.ins().imul_imm(val, factor as i64);
+        let new_val = self.builder.ins().iadd(target_val, product);
+        self.builder
+            .ins()
+            .store(MemFlags::new(), new_val, target_ptr, 0);
+        self.builder.ins().jump(skip_block, &[]);
+
+        self.builder.switch_to_block(skip_block);
+        self.builder.seal_block(body_block);
+        self.builder.seal_block(skip_block);
+    }
+
     fn call_rts(&mut self, name: &str, args: &[Value]) -> Value {
         let mut sig = self.module.make_signature();
         for arg in args {
@@ -427,19 +537,28 @@ type EntryFunction =
     extern "C" fn(memory: *mut u8, memory_size: u64, rts_state: *mut RtsState) -> u64;
 
 impl Interpretable for Program {
-    fn interpret_state<R: Read, W: Write>(
+    fn interpret_in<R: Read, W: Write>(
         &self,
-        mut state: State,
+        state: &mut State,
         mut input: R,
         mut output: W,
     ) -> BfResult<()> {
+        assert_eq!(state.tapes(), 1, "compiled code supports only one tape");
+        assert_eq!(
+            state.overflow(),
+            Overflow::Wrap,
+            "compiled code supports only wrapping cells"
+        );
+
         let mut rts_state = RtsState::new(&mut input, &mut output);
+        rts_state.set_eof(state.eof());
+        rts_state.set_pointer(state.pointer());
         let main_fn: EntryFunction = unsafe { mem::transmute(self.main_fn) };
 
         let result = main_fn(state.as_mut_ptr(), state.capacity() as u64, &mut rts_state);
 
         match result {
-            rts::OKAY => Ok(()),
+            rts::OKAY => state.set_pointer(rts_state.pointer()),
             rts::UNDERFLOW => Err(Error::PointerUnderflow),
             rts::OVERFLOW => Err(Error::PointerOverflow),
             _ => panic!("Unknown result code: {}", result),
@@ -0,0 +1,402 @@
+use std::fmt;
+
+use crate::state::State;
+
+/// A condition on the machine, for a conditional breakpoint.
+///
+/// Conditions are written in a small expression language over integers:
+///
+///  - `ptr` is the position of the pointer, `cell(e)` is the value of the cell at address `e`,
+///    which is negative for a byte of 128 or more if cells are
+///    [signed](../state/struct.State.html#method.signed), or 0 if that is past either end of
+///    memory, and `pc` is the address of the instruction that is about to run.
+///  - Numbers are decimal, such as `100`, or characters in single quotes, such as `'a'`.
+///  - `+` and `-` add and subtract, and `-` also negates.
+///  - `==`, `!=`, `<`, `<=`, `>` and `>=` compare, giving 1 if true and 0 if false.
+///  - `!`, `&&` and `||` are logical operators, which take any non-zero value as true.
+///  - Parentheses group.
+///
+/// The operators bind from loosest to tightest in the order `||`, `&&`, comparisons, `+` and
+/// `-`, and finally `!` and negation. The condition holds if its value isn't zero, so
+/// `cell(5) == 0 && ptr > 100` holds when cell 5 is zero and the pointer is past cell 100.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Condition {
+    expr: Expr,
+}
+
+/// An expression in a condition.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Expr {
+    /// A number.
+    Number(i64),
+    /// `ptr`: the position of the pointer.
+    Pointer,
+    /// `pc`: the address of the next instruction.
+    Pc,
+    /// `cell(e)`: the value of the cell at the given address.
+    Cell(Box<Expr>),
+    /// An operator applied to one operand.
+    Unary(UnaryOp, Box<Expr>),
+    /// An operator applied to two operands.
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+/// Operators with one operand.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnaryOp {
+    /// `-`
+    Negate,
+    /// `!`
+    Not,
+}
+
+/// Operators with two operands.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BinaryOp {
+    /// `+`
+    Add,
+    /// `-`
+    Subtract,
+    /// `==`
+    Equal,
+    /// `!=`
+    NotEqual,
+    /// `<`
+    Less,
+    /// `<=`
+    LessEqual,
+    /// `>`
+    Greater,
+    /// `>=`
+    GreaterEqual,
+    /// `&&`
+    And,
+    /// `||`
+    Or,
+}
+
+impl Condition {
+    /// Parses a condition.
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of the problem, with the column where it is, if the condition
+    /// isn't well formed.
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(src)?,
+            next: 0,
+        };
+        let expr = parser.or()?;
+        match parser.peek() {
+            None => Ok(Condition { expr }),
+            Some((column, token)) => Err(format!("unexpected {} at column {}", token, column)),
+        }
+    }
+
+    /// The expression.
+    pub fn expr(&self) -> &Expr {
+        &self.expr
+    }
+
+    /// Whether the condition holds on the given state, with the given instruction about to run.
+    pub fn holds(&self, state: &State, pc: usize) -> bool {
+        self.expr.eval(state, pc) != 0
+    }
+}
+
+impl Expr {
+    /// The value of the expression on the given state, with the given instruction about to run.
+    /// Arithmetic wraps around on overflow.
+    pub fn eval(&self, state: &State, pc: usize) -> i64 {
+        match *self {
+            Expr::Number(value) => value,
+            Expr::Pointer => state.pointer() as i64,
+            Expr::Pc => pc as i64,
+            Expr::Cell(ref address) => usize::try_from(address.eval(state, pc))
+                .ok()
+                .and_then(|address| state.as_bytes().get(address))
+                .map_or(0, |&value| i64::from(state.cell_value(value))),
+            Expr::Unary(op, ref operand) => {
+                let value = operand.eval(state, pc);
+                match op {
+                    UnaryOp::Negate => value.wrapping_neg(),
+                    UnaryOp::Not => i64::from(value == 0),
+                }
+            }
+            Expr::Binary(BinaryOp::And, ref left, ref right) => {
+                i64::from(left.eval(state, pc) != 0 && right.eval(state, pc) != 0)
+            }
+            Expr::Binary(BinaryOp::Or, ref left, ref right) => {
+                i64::from(left.eval(state, pc) != 0 || right.eval(state, pc) != 0)
+            }
+            Expr::Binary(op, ref left, ref right) => {
+                let (left, right) = (left.eval(state, pc), right.eval(state, pc));
+                match op {
+                    BinaryOp::Add => left.wrapping_add(right),
+                    BinaryOp::Subtract => left.wrapping_sub(right),
+                    BinaryOp::Equal => i64::from(left == right),
+                    BinaryOp::NotEqual => i64::from(left != right),
+                    BinaryOp::Less => i64::from(left < right),
+                    BinaryOp::LessEqual => i64::from(left <= right),
+                    BinaryOp::Greater => i64::from(left > right),
+                    BinaryOp::GreaterEqual => i64::from(left >= right),
+                    BinaryOp::And | BinaryOp::Or => unreachable!("handled above"),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Number(value) => write!(f, "number {}", value),
+            Token::Name(ref name) => write!(f, "`{}`", name),
+            Token::Symbol(symbol) => write!(f, "`{}`", symbol),
+        }
+    }
+}
+
+/// The symbols, with each before any that it starts with.
+const SYMBOLS: &[&str] = &[
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "+", "-", "(", ")",
+];
+
+/// Splits the source into tokens, each with the column it starts at.
+fn tokenize(src: &str) -> Result<Vec<(usize, Token)>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = src.chars().collect();
+    let mut index = 0;
+
+    while index < chars.len() {
+        let column = index + 1;
+        let c = chars[index];
+        if c.is_whitespace() {
+            index += 1;
+        } else if c.is_ascii_digit() {
+            let len = chars[index..]
+                .iter()
+                .take_while(|c| c.is_ascii_digit())
+                .count();
+            let digits: String = chars[index..index + len].iter().collect();
+            let value = digits
+                .parse()
+                .map_err(|_| format!("number too big at column {}", column))?;
+            tokens.push((column, Token::Number(value)));
+            index += len;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = chars[index..]
+                .iter()
+                .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+                .count();
+            let name = chars[index..index + len].iter().collect();
+            tokens.push((column, Token::Name(name)));
+            index += len;
+        } else if c == '\'' {
+            match chars.get(index + 1..index + 3) {
+                Some(&[value, '\'']) => {
+                    tokens.push((column, Token::Number(i64::from(u32::from(value)))));
+                    index += 3;
+                }
+                _ => return Err(format!("bad character literal at column {}", column)),
+            }
+        } else {
+            let rest: String = chars[index..].iter().take(2).collect();
+            let symbol = SYMBOLS
+                .iter()
+                .find(|symbol| rest.starts_with(**symbol))
+                .ok_or_else(|| format!("unexpected `{}` at column {}", c, column))?;
+            tokens.push((column, Token::Symbol(symbol)));
+            index += symbol.len();
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// A recursive-descent parser, with a method for each level of precedence.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&(usize, Token)> {
+        self.tokens.get(self.next)
+    }
+
+    /// Consumes the next token if it is the given symbol.
+    fn accept(&mut self, symbol: &str) -> bool {
+        match self.peek() {
+            Some(&(_, Token::Symbol(found))) if found == symbol => {
+                self.next += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.accept(symbol) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("`{}`", symbol)))
+        }
+    }
+
+    fn error(&self, expected: &str) -> String {
+        match self.peek() {
+            Some((column, token)) => format!(
+                "expected {} at column {}, found {}",
+                expected, column, token
+            ),
+            None => format!("expected {} at the end", expected),
+        }
+    }
+
+    /// Parses operands separated by the given operators, all at one level of precedence.
+    fn binary(
+        &mut self,
+        ops: &[(&str, BinaryOp)],
+        operand: fn(&mut Self) -> Result<Expr, String>,
+    ) -> Result<Expr, String> {
+        let mut left = operand(self)?;
+        'outer: loop {
+            for &(symbol, op) in ops {
+                if self.accept(symbol) {
+                    let right = operand(self)?;
+                    left = Expr::Binary(op, Box::new(left), Box::new(right));
+                    continue 'outer;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        self.binary(&[("||", BinaryOp::Or)], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        self.binary(&[("&&", BinaryOp::And)], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        self.binary(
+            &[
+                ("==", BinaryOp::Equal),
+                ("!=", BinaryOp::NotEqual),
+                ("<=", BinaryOp::LessEqual),
+                (">=", BinaryOp::GreaterEqual),
+                ("<", BinaryOp::Less),
+                (">", BinaryOp::Greater),
+            ],
+            Self::sum,
+        )
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        self.binary(
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Subtract)],
+            Self::unary,
+        )
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.accept("-") {
+            Ok(Expr::Unary(UnaryOp::Negate, Box::new(self.unary()?)))
+        } else if self.accept("!") {
+            Ok(Expr::Unary(UnaryOp::Not, Box::new(self.unary()?)))
+        } else {
+            self.atom()
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        let token = self.peek().map(|(_, token)| token.clone());
+        let expr = match token {
+            Some(Token::Number(value)) => Expr::Number(value),
+            Some(Token::Name(ref name)) if name == "ptr" => Expr::Pointer,
+            Some(Token::Name(ref name)) if name == "pc" => Expr::Pc,
+            Some(Token::Name(ref name)) if name == "cell" => {
+                self.next += 1;
+                self.expect("(")?;
+                let address = self.or()?;
+                self.expect(")")?;
+                return Ok(Expr::Cell(Box::new(address)));
+            }
+            Some(Token::Symbol("(")) => {
+                self.next += 1;
+                let expr = self.or()?;
+                self.expect(")")?;
+                return Ok(expr);
+            }
+            _ => return Err(self.error("a number, `ptr`, `pc`, `cell` or `(`")),
+        };
+        self.next += 1;
+        Ok(expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(src: &str, state: &State) -> i64 {
+        Condition::parse(src).unwrap().expr().eval(state, 7)
+    }
+
+    #[test]
+    fn evaluates_with_precedence() {
+        let mut state = State::with_capacity(10);
+        state.right(3usize).unwrap();
+        state.store(b'a');
+
+        assert_eq!(eval("1 + 2 - 4", &state), -1);
+        assert_eq!(eval("-(1 + 2) == 0 - 3", &state), 1);
+        assert_eq!(eval("cell(ptr) == 'a' && ptr + 1 > 3", &state), 1);
+        assert_eq!(eval("cell(2) || !cell(99)", &state), 1);
+        assert_eq!(eval("cell(-1) != 0 || pc < 7", &state), 0);
+        assert_eq!(eval("1 < 2 == 1", &state), 1);
+    }
+
+    #[test]
+    fn holds_if_not_zero() {
+        let state = State::with_capacity(200);
+        let condition = Condition::parse("cell(5) == 0 && ptr > 100").unwrap();
+        assert!(!condition.holds(&state, 0));
+        assert!(
+            Condition::parse("cell(5) == 0 && ptr < 100")
+                .unwrap()
+                .holds(&state, 0)
+        );
+    }
+
+    #[test]
+    fn reports_syntax_errors() {
+        assert_eq!(
+            Condition::parse("cell(1 == 2"),
+            Err("expected `)` at the end".to_owned())
+        );
+        assert_eq!(
+            Condition::parse("ptr > > 1"),
+            Err("expected a number, `ptr`, `pc`, `cell` or `(` at column 7, found `>`".to_owned())
+        );
+        assert_eq!(
+            Condition::parse("ptr = 1"),
+            Err("unexpected `=` at column 5".to_owned())
+        );
+        assert_eq!(
+            Condition::parse("ptr 1"),
+            Err("unexpected number 1 at column 5".to_owned())
+        );
+    }
+}
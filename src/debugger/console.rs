@@ -0,0 +1,544 @@
+use std::io::{self, BufRead, Read, Write};
+
+use super::source::{SourceMap, command};
+use super::{Condition, Debugger, Stop, Watchpoint};
+use crate::ast::Position;
+use crate::state::State;
+
+/// The number of cells that the line-based console shows around the pointer.
+const TAPE_CELLS: usize = 9;
+
+const HELP: &str = "\
+commands:
+  s, step [N]               run N instructions (default 1)
+  c, continue               run until the program stops
+  r, back [N]               go back N instructions (default 1)
+  b, break ADDR [if COND]   stop before the instruction at ADDR, if COND holds
+  d, delete ADDR            remove the breakpoint at ADDR
+  w, watch CELLS [= VALUE]  stop when an instruction writes CELLS, a cell or a range A..B
+  u, unwatch N              remove watchpoint N
+  h, help                   show this
+  q, quit                   stop debugging
+an empty line repeats the last command";
+
+/// An interactive front end for a [`Debugger`](struct.Debugger.html), which reads commands a
+/// line at a time and shows where the program is after each one.
+///
+/// By default it prints a line with the address of the next instruction, the cells around the
+/// pointer, and anything that the program wrote. With the `tui` feature,
+/// [`full_screen`](#method.full_screen) makes it redraw the terminal instead, with the source,
+/// the tape, and the output each in a pane.
+///
+/// Type `help` for the commands.
+#[derive(Debug, Default)]
+pub struct Console {
+    source: Option<(Vec<u8>, SourceMap)>,
+    #[cfg(feature = "tui")]
+    screen: Option<(usize, usize)>,
+    /// The last command, which an empty line repeats.
+    last: String,
+    /// How much of the output has been shown.
+    shown: usize,
+}
+
+impl Console {
+    /// A console that shows instructions by their addresses.
+    pub fn new() -> Self {
+        Console::default()
+    }
+
+    /// Shows the commands of the source instead, for a program from
+    /// [`compile_source`](fn.compile_source.html).
+    pub fn with_source(mut self, source: Vec<u8>, map: SourceMap) -> Self {
+        self.source = Some((source, map));
+        self
+    }
+
+    /// Redraws a terminal of the given size after each command, using ANSI escape codes.
+    #[cfg(feature = "tui")]
+    pub fn full_screen(mut self, width: usize, height: usize) -> Self {
+        self.screen = Some((width.max(20), height.max(12)));
+        self
+    }
+
+    /// Reads and runs commands until `quit` or the end of the commands.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from reading the commands or writing to `out`.
+    pub fn run<R: Read, C: BufRead, O: Write>(
+        &mut self,
+        debugger: &mut Debugger<R, Vec<u8>>,
+        commands: C,
+        mut out: O,
+    ) -> io::Result<()> {
+        self.show(debugger, "type `help` for the commands", &mut out)?;
+        for line in commands.lines() {
+            let line = line?;
+            let line = if line.trim().is_empty() {
+                self.last.clone()
+            } else {
+                line.trim().to_owned()
+            };
+            if matches!(line.as_str(), "q" | "quit") {
+                break;
+            }
+            let message = self
+                .execute(debugger, &line)
+                .unwrap_or_else(|message| format!("error: {}", message));
+            self.last = line;
+            self.show(debugger, &message, &mut out)?;
+        }
+        out.flush()
+    }
+
+    /// Runs a command, returning what to tell the user.
+    fn execute<R: Read>(
+        &mut self,
+        debugger: &mut Debugger<R, Vec<u8>>,
+        line: &str,
+    ) -> Result<String, String> {
+        let (name, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let rest = rest.trim();
+        match name {
+            "" => Ok(String::new()),
+            "s" | "step" => {
+                for _ in 0..count(rest)? {
+                    if let Some(stop) = debugger.step() {
+                        return Ok(describe(stop, debugger.state()));
+                    }
+                }
+                Ok(String::new())
+            }
+            "c" | "continue" => {
+                let stop = debugger.run();
+                Ok(describe(stop, debugger.state()))
+            }
+            "r" | "back" => {
+                for _ in 0..count(rest)? {
+                    if !debugger.step_back() {
+                        return Ok("cannot go back any further".to_owned());
+                    }
+                }
+                Ok(String::new())
+            }
+            "b" | "break" => {
+                let (address, condition) = match rest.split_once(" if ") {
+                    Some((address, condition)) => (address, Some(Condition::parse(condition)?)),
+                    None => (rest, None),
+                };
+                let address = number(address)?;
+                if address >= debugger.program().len() {
+                    return Err(format!("no instruction at {}", address));
+                }
+                match condition {
+                    Some(condition) => debugger.add_conditional_breakpoint(address, condition),
+                    None => debugger.add_breakpoint(address),
+                };
+                Ok(format!("breakpoint at {}", address))
+            }
+            "d" | "delete" => {
+                let address = number(rest)?;
+                if debugger.remove_breakpoint(address) {
+                    Ok(format!("removed the breakpoint at {}", address))
+                } else {
+                    Err(format!("no breakpoint at {}", address))
+                }
+            }
+            "w" | "watch" => {
+                let (cells, value) = match rest.split_once('=') {
+                    Some((cells, value)) => (cells.trim(), Some(value.trim())),
+                    None => (rest, None),
+                };
+                let cells = match cells.split_once("..") {
+                    Some((start, end)) => number(start)?..number(end)?,
+                    None => number(cells).map(|cell| cell..cell + 1)?,
+                };
+                let watchpoint = match value {
+                    Some(value) => {
+                        let value = byte(value, debugger.state())?;
+                        Watchpoint::value(cells, value)
+                    }
+                    None => Watchpoint::write(cells),
+                };
+                Ok(format!("watchpoint {}", debugger.watch(watchpoint)))
+            }
+            "u" | "unwatch" => {
+                let watchpoint = number(rest)?;
+                match debugger.unwatch(watchpoint) {
+                    Some(_) => Ok(format!("removed watchpoint {}", watchpoint)),
+                    None => Err(format!("no watchpoint {}", watchpoint)),
+                }
+            }
+            "h" | "help" => Ok(HELP.to_owned()),
+            _ => Err(format!("unknown command `{}`", name)),
+        }
+    }
+
+    fn show<R: Read, O: Write>(
+        &mut self,
+        debugger: &Debugger<R, Vec<u8>>,
+        message: &str,
+        out: &mut O,
+    ) -> io::Result<()> {
+        #[cfg(feature = "tui")]
+        if let Some((width, height)) = self.screen {
+            let screen = self.draw(debugger, message, width, height);
+            return write!(out, "{}", screen).and_then(|()| out.flush());
+        }
+
+        let output = &debugger.output()[self.shown..];
+        if !output.is_empty() {
+            out.write_all(output)?;
+            if !output.ends_with(b"\n") {
+                writeln!(out)?;
+            }
+            self.shown = debugger.output().len();
+        }
+        if !message.is_empty() {
+            writeln!(out, "{}", message)?;
+        }
+
+        let (start, cells) = tape(debugger, TAPE_CELLS);
+        let pointer = debugger.state().pointer();
+        let cells: Vec<_> = cells
+            .iter()
+            .enumerate()
+            .map(|(index, &cell)| {
+                let value = debugger.state().cell_value(cell);
+                if start + index == pointer {
+                    format!("[{}]", value)
+                } else {
+                    value.to_string()
+                }
+            })
+            .collect();
+        writeln!(
+            out,
+            "{}  ptr {}: {}",
+            self.location(debugger),
+            pointer,
+            cells.join(" ")
+        )?;
+        write!(out, "(bfi) ")?;
+        out.flush()
+    }
+
+    /// Where the program is, as the address and the instruction or command there.
+    fn location<R: Read>(&self, debugger: &Debugger<R, Vec<u8>>) -> String {
+        let pc = debugger.pc();
+        let instruction = match debugger.program().get(pc) {
+            Some(instruction) => instruction,
+            None => return format!("pc {} (end)", pc),
+        };
+        match self.source {
+            Some((ref source, ref map)) => {
+                let offset = map.offset(pc).expect("every instruction has a command");
+                let position = Position::new(source, offset);
+                format!(
+                    "pc {} `{}` at line {}, column {}",
+                    pc,
+                    command(instruction).unwrap_or('?'),
+                    position.line,
+                    position.column,
+                )
+            }
+            None => format!("pc {} {:?}", pc, instruction),
+        }
+    }
+
+    /// Draws the whole screen.
+    #[cfg(feature = "tui")]
+    fn draw<R: Read>(
+        &mut self,
+        debugger: &Debugger<R, Vec<u8>>,
+        message: &str,
+        width: usize,
+        height: usize,
+    ) -> String {
+        const CLEAR: &str = "\x1b[H\x1b[2J";
+
+        // The source takes what the tape, the output, and the status lines leave.
+        let output_height = height / 4;
+        let source_height = height - output_height - 9;
+        let mut screen = String::from(CLEAR);
+        let rule = |title: &str| format!("\x1b[1m{:─<width$}\x1b[0m\n", title, width = width);
+
+        screen.push_str(&rule("── program "));
+        for line in self.program_lines(debugger, source_height, width) {
+            screen.push_str(&line);
+            screen.push('\n');
+        }
+
+        screen.push_str(&rule("── tape "));
+        let (start, cells) = tape(debugger, width / 5);
+        let pointer = debugger.state().pointer();
+        let mut addresses = String::new();
+        let mut values = String::new();
+        for (index, &cell) in cells.iter().enumerate() {
+            let address = start + index;
+            let cell = debugger.state().cell_value(cell);
+            addresses.push_str(&format!("{:>5}", address));
+            if address == pointer {
+                values.push_str(&format!(" {}{:>4}{}", REVERSE, cell, RESET));
+            } else {
+                values.push_str(&format!("{:>5}", cell));
+            }
+        }
+        screen.push_str(&format!("{}\n{}\n", addresses, values));
+
+        screen.push_str(&rule("── output "));
+        let output = String::from_utf8_lossy(debugger.output());
+        let lines: Vec<_> = output.lines().collect();
+        let first = lines.len().saturating_sub(output_height);
+        for row in 0..output_height {
+            let line = lines.get(first + row).copied().unwrap_or("");
+            screen.push_str(&truncate(line, width));
+            screen.push('\n');
+        }
+        self.shown = debugger.output().len();
+
+        screen.push_str(&rule(""));
+        let status = format!("{}  ptr {}", self.location(debugger), pointer);
+        screen.push_str(&truncate(&status, width));
+        screen.push('\n');
+        let message = message.lines().last().unwrap_or("");
+        screen.push_str(&truncate(message, width));
+        screen.push_str("\n(bfi) ");
+        screen
+    }
+
+    /// The lines of the program pane, with the next instruction highlighted.
+    #[cfg(feature = "tui")]
+    fn program_lines<R: Read>(
+        &self,
+        debugger: &Debugger<R, Vec<u8>>,
+        height: usize,
+        width: usize,
+    ) -> Vec<String> {
+        let pc = debugger.pc();
+        let (source, map) = match self.source {
+            Some((ref source, ref map)) => (source, map),
+            None => {
+                // Without the source, show the bytecode as `bfi disasm` does.
+                let program = debugger.program();
+                let first = pc.saturating_sub(height / 2);
+                return (first..first + height)
+                    .map(|address| match program.get(address) {
+                        Some(instruction) if address == pc => {
+                            let line = format!("{:6}  {:?}", address, instruction);
+                            format!("{}{}{}", REVERSE, truncate(&line, width), RESET)
+                        }
+                        Some(instruction) => {
+                            truncate(&format!("{:6}  {:?}", address, instruction), width)
+                        }
+                        None => String::new(),
+                    })
+                    .collect();
+            }
+        };
+
+        let source = String::from_utf8_lossy(source);
+        let lines: Vec<Vec<char>> = source.lines().map(|line| line.chars().collect()).collect();
+        let current = map
+            .offset(pc)
+            .map(|offset| Position::new(source.as_bytes(), offset));
+        let current_line = current.map_or(lines.len(), |position| position.line - 1);
+        let first = current_line.saturating_sub(height / 2);
+
+        (first..first + height)
+            .map(|index| {
+                let line = match lines.get(index) {
+                    Some(line) => line,
+                    None => return String::new(),
+                };
+                match current {
+                    Some(position) if index == current_line && position.column <= line.len() => {
+                        let column = position.column - 1;
+                        // Scroll long lines so that the command is on the screen.
+                        let start = column.saturating_sub(width / 2);
+                        let end = line.len().min(start + width);
+                        let before: String = line[start..column].iter().collect();
+                        let after: String = line[column + 1..end.max(column + 1)].iter().collect();
+                        format!("{}{}{}{}{}", before, REVERSE, line[column], RESET, after)
+                    }
+                    _ => line.iter().take(width).collect(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "tui")]
+const REVERSE: &str = "\x1b[7m";
+
+#[cfg(feature = "tui")]
+const RESET: &str = "\x1b[0m";
+
+/// The first `width` characters of `line`.
+#[cfg(feature = "tui")]
+fn truncate(line: &str, width: usize) -> String {
+    line.chars().take(width).collect()
+}
+
+/// Up to `len` cells around the pointer, with the address of the first.
+fn tape<R: Read>(debugger: &Debugger<R, Vec<u8>>, len: usize) -> (usize, &[u8]) {
+    let memory = debugger.state().as_bytes();
+    let start = debugger
+        .state()
+        .pointer()
+        .saturating_sub(len / 2)
+        .min(memory.len().saturating_sub(len));
+    let end = memory.len().min(start + len);
+    (start, &memory[start..end])
+}
+
+fn describe(stop: Stop, state: &State) -> String {
+    match stop {
+        Stop::Halted => "the program halted".to_owned(),
+        Stop::Breakpoint(address) => format!("breakpoint at {}", address),
+        Stop::Watchpoint(hit) => format!(
+            "watchpoint {}: cell {} changed from {} to {}",
+            hit.watchpoint,
+            hit.cell,
+            state.cell_value(hit.old),
+            state.cell_value(hit.new)
+        ),
+        Stop::Error(error) => format!("the program failed: {}", error),
+    }
+}
+
+fn number(text: &str) -> Result<usize, String> {
+    text.trim()
+        .parse()
+        .map_err(|_| format!("expected a number, not `{}`", text.trim()))
+}
+
+/// The byte for a cell value, which is -128 to 127 if the state's cells are signed, and 0 to 255
+/// otherwise.
+fn byte(text: &str, state: &State) -> Result<u8, String> {
+    let value: i64 = text.parse().map_err(|_| "expected a byte value")?;
+    let byte = if state.signed() {
+        i8::try_from(value).map(|value| value as u8)
+    } else {
+        u8::try_from(value)
+    };
+    byte.map_err(|_| format!("{} doesn't fit in a cell", value))
+}
+
+/// The count for `step` and `back`, which is 1 if it is left out.
+fn count(text: &str) -> Result<usize, String> {
+    if text.is_empty() { Ok(1) } else { number(text) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::ast::ParseOptions;
+
+    fn session(src: &[u8], commands: &str) -> String {
+        session_in(src, State::new(), commands)
+    }
+
+    fn session_in(src: &[u8], state: State, commands: &str) -> String {
+        let (program, map) = compile_source(src, &ParseOptions::default()).unwrap();
+        let mut debugger = Debugger::new(program, state, &b"x"[..], Vec::new());
+        let mut console = Console::new().with_source(src.to_vec(), map);
+        let mut out = Vec::new();
+        console
+            .run(&mut debugger, commands.as_bytes(), &mut out)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn runs_commands() {
+        let out = session(
+            b"+\n,.[-]",
+            "s\n\nb 4\nc\nback 2\nw 0 = 0\nc\nd 4\nc\nq\nc\n",
+        );
+        let expected = "\
+type `help` for the commands
+pc 0 `+` at line 1, column 1  ptr 0: [0] 0 0 0 0 0 0 0 0
+(bfi) pc 1 `,` at line 2, column 1  ptr 0: [1] 0 0 0 0 0 0 0 0
+(bfi) pc 2 `.` at line 2, column 2  ptr 0: [120] 0 0 0 0 0 0 0 0
+(bfi) breakpoint at 4
+pc 2 `.` at line 2, column 2  ptr 0: [120] 0 0 0 0 0 0 0 0
+(bfi) x
+breakpoint at 4
+pc 4 `-` at line 2, column 4  ptr 0: [120] 0 0 0 0 0 0 0 0
+(bfi) pc 2 `.` at line 2, column 2  ptr 0: [120] 0 0 0 0 0 0 0 0
+(bfi) watchpoint 0
+pc 2 `.` at line 2, column 2  ptr 0: [120] 0 0 0 0 0 0 0 0
+(bfi) breakpoint at 4
+pc 4 `-` at line 2, column 4  ptr 0: [120] 0 0 0 0 0 0 0 0
+(bfi) removed the breakpoint at 4
+pc 4 `-` at line 2, column 4  ptr 0: [120] 0 0 0 0 0 0 0 0
+(bfi) watchpoint 0: cell 0 changed from 1 to 0
+pc 5 `]` at line 2, column 5  ptr 0: [0] 0 0 0 0 0 0 0 0
+(bfi) ";
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn shows_signed_cells() {
+        let mut state = State::new();
+        state.set_signed(true);
+        let out = session_in(b"-[-]", state, "w 0 = -3\nc\nw 0 = 200\nq\n");
+        let expected = "\
+type `help` for the commands
+pc 0 `-` at line 1, column 1  ptr 0: [0] 0 0 0 0 0 0 0 0
+(bfi) watchpoint 0
+pc 0 `-` at line 1, column 1  ptr 0: [0] 0 0 0 0 0 0 0 0
+(bfi) watchpoint 0: cell 0 changed from -2 to -3
+pc 3 `]` at line 1, column 4  ptr 0: [-3] 0 0 0 0 0 0 0 0
+(bfi) error: 200 doesn't fit in a cell
+pc 3 `]` at line 1, column 4  ptr 0: [-3] 0 0 0 0 0 0 0 0
+(bfi) ";
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn reports_mistakes() {
+        let out = session(b"+", "frob\nb 7\nd 0\nstep x\nback\nback\nq\n");
+        let messages: Vec<_> = out.lines().filter(|line| !line.contains("ptr")).collect();
+        assert_eq!(
+            messages,
+            [
+                "type `help` for the commands",
+                "(bfi) error: unknown command `frob`",
+                "(bfi) error: no instruction at 7",
+                "(bfi) error: no breakpoint at 0",
+                "(bfi) error: expected a number, not `x`",
+                "(bfi) cannot go back any further",
+                "(bfi) cannot go back any further",
+                "(bfi) ",
+            ]
+        );
+    }
+
+    #[cfg(feature = "tui")]
+    #[test]
+    fn draws_the_screen() {
+        let src = b"++>+.";
+        let (program, map) = compile_source(src, &ParseOptions::default()).unwrap();
+        let mut debugger = Debugger::new(program, State::new(), &b""[..], Vec::new());
+        let mut console = Console::new()
+            .with_source(src.to_vec(), map)
+            .full_screen(40, 16);
+        let mut out = Vec::new();
+        console
+            .run(&mut debugger, &b"s 3\nc\n"[..], &mut out)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let last = out.rsplit("\x1b[2J").next().unwrap();
+        assert!(last.contains("pc 5 (end)"));
+        assert!(last.contains("the program halted"));
+        assert!(last.contains("\u{1}"));
+
+        let screens: Vec<_> = out.split("\x1b[H\x1b[2J").skip(1).collect();
+        assert_eq!(screens.len(), 3);
+        assert!(screens[1].contains("++>\x1b[7m+\x1b[0m."));
+        assert_eq!(screens[1].lines().count(), 16);
+    }
+}
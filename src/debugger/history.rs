@@ -0,0 +1,239 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+use super::Debugger;
+use crate::state::State;
+
+/// How many instructions to run between snapshots.
+const SNAPSHOT_INTERVAL: u64 = 1024;
+
+/// How many snapshots to keep, which bounds how far back the debugger can go.
+const MAX_SNAPSHOTS: usize = 64;
+
+/// How many of the most recent instructions to remember.
+const JOURNAL_LEN: usize = 256;
+
+/// What a debugger remembers about the run so far, so that it can go back.
+///
+/// Going back restores the latest snapshot before the target and runs forward from it again.
+/// That gives the same result as the first time because the input that the program read is
+/// recorded and fed to it again. Output that was already written isn't written a second time.
+#[derive(Debug, Default)]
+pub(super) struct History {
+    snapshots: VecDeque<Snapshot>,
+    /// The addresses of the most recent instructions, oldest first.
+    journal: VecDeque<usize>,
+    /// Every byte read from the input so far.
+    input: Vec<u8>,
+    /// How many of the bytes in `input` the program has read at this point in the run.
+    read: usize,
+    /// How many bytes the program has written at this point in the run.
+    produced: u64,
+    /// How many bytes have actually been written to the output, which is more than `produced`
+    /// after going back.
+    written: u64,
+}
+
+#[derive(Debug)]
+struct Snapshot {
+    steps: u64,
+    pc: usize,
+    state: State,
+    read: usize,
+    produced: u64,
+}
+
+impl History {
+    /// Takes a snapshot if one is due, and records that the instruction at `pc` is about to run.
+    pub(super) fn before_step(&mut self, steps: u64, pc: usize, state: &State) {
+        let due = steps.is_multiple_of(SNAPSHOT_INTERVAL)
+            && self.snapshots.back().is_none_or(|last| last.steps < steps);
+        if due {
+            if self.snapshots.len() == MAX_SNAPSHOTS {
+                self.snapshots.pop_front();
+            }
+            self.snapshots.push_back(Snapshot {
+                steps,
+                pc,
+                state: state.clone(),
+                read: self.read,
+                produced: self.produced,
+            });
+        }
+
+        if self.journal.len() == JOURNAL_LEN {
+            self.journal.pop_front();
+        }
+        self.journal.push_back(pc);
+    }
+
+    /// Writes the given bytes, leaving out any that were written before going back.
+    pub(super) fn write<W: Write>(&mut self, output: &mut W, bytes: &[u8]) {
+        let skip = self
+            .written
+            .saturating_sub(self.produced)
+            .min(bytes.len() as u64) as usize;
+        let _ = output.write_all(&bytes[skip..]);
+        self.produced += bytes.len() as u64;
+        self.written = self.written.max(self.produced);
+    }
+
+    /// Reads through the record of the input, so that reading again after going back gives the
+    /// same bytes.
+    pub(super) fn reader<'a, R: Read>(&'a mut self, input: &'a mut R) -> RecordedInput<'a, R> {
+        RecordedInput {
+            history: self,
+            input,
+        }
+    }
+}
+
+/// Reads recorded input, and then new input, which it records.
+pub(super) struct RecordedInput<'a, R> {
+    history: &'a mut History,
+    input: &'a mut R,
+}
+
+impl<R: Read> Read for RecordedInput<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let history = &mut *self.history;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        if history.read == history.input.len() {
+            let len = self.input.read(&mut buf[..1])?;
+            if len == 0 {
+                return Ok(0);
+            }
+            history.input.push(buf[0]);
+        }
+        buf[0] = history.input[history.read];
+        history.read += 1;
+        Ok(1)
+    }
+}
+
+impl<R: Read, W: Write> Debugger<R, W> {
+    /// The number of instructions that have run.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// The addresses of the most recent instructions to run, oldest first. Going back forgets
+    /// them.
+    pub fn journal(&self) -> impl Iterator<Item = usize> + '_ {
+        self.history.journal.iter().copied()
+    }
+
+    /// Goes back to before the last instruction that ran, returning whether it could. If the
+    /// program failed, this goes back to before the instruction that failed.
+    ///
+    /// The debugger keeps snapshots of the state, from which it can go back a few tens of
+    /// thousands of instructions. A breakpoint at the address it goes back to doesn't stop the
+    /// next [`run`](struct.Debugger.html#method.run).
+    pub fn step_back(&mut self) -> bool {
+        let target = match self.error {
+            Some(_) => self.steps,
+            None if self.steps == 0 => return false,
+            None => self.steps - 1,
+        };
+        self.go_back_to(target)
+    }
+
+    /// Goes back to when the given number of instructions had run, returning whether it could.
+    fn go_back_to(&mut self, target: u64) -> bool {
+        let history = &mut self.history;
+        let snapshot = match history.snapshots.iter().rev().find(|s| s.steps <= target) {
+            Some(snapshot) => snapshot,
+            None => return false,
+        };
+
+        // The journal has an entry for every instruction that ran, and for one that failed.
+        let recorded = self.steps + u64::from(self.error.is_some());
+        let forgotten = usize::try_from(recorded - target).unwrap_or(usize::MAX);
+        let journal_len = history.journal.len().saturating_sub(forgotten);
+        history.journal.truncate(journal_len);
+
+        self.state.clone_from(&snapshot.state);
+        self.pc = snapshot.pc;
+        self.steps = snapshot.steps;
+        history.read = snapshot.read;
+        history.produced = snapshot.produced;
+        self.error = None;
+
+        while self.steps < target {
+            let instruction = self.program[self.pc].clone();
+            self.execute(&instruction)
+                .expect("instructions that ran before ran again");
+            self.pc += 1;
+            self.steps += 1;
+        }
+        self.at_breakpoint = true;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::ast;
+    use crate::test_helpers::*;
+    use crate::traits::*;
+
+    fn debugger(src: &[u8], input: &'static [u8]) -> Debugger<&'static [u8], Vec<u8>> {
+        let program = ast::parse_program(src).unwrap().bytecode_compile();
+        Debugger::new(program, State::new(), input, Vec::new())
+    }
+
+    #[test]
+    fn steps_back_one_instruction() {
+        let mut debugger = debugger(b",>,<.>.", b"ab");
+        while debugger.pc() < 4 {
+            debugger.step();
+        }
+        let (pc, state) = (debugger.pc(), debugger.state().clone());
+        debugger.step();
+        assert_eq!(debugger.output(), b"a");
+
+        assert!(debugger.step_back());
+        assert_eq!((debugger.pc(), debugger.state()), (pc, &state));
+        assert_eq!(debugger.journal().count(), 4);
+        assert_eq!(debugger.run(), Stop::Halted);
+        assert_eq!(debugger.output(), b"ab");
+    }
+
+    #[test]
+    fn replays_input_and_output() {
+        let mut debugger = debugger(FACTOR_SRC, b"100\n");
+        assert_eq!(debugger.run(), Stop::Halted);
+        let steps = debugger.steps();
+        assert!(steps > 3000, "{}", steps);
+
+        for _ in 0..2500 {
+            assert!(debugger.step_back());
+        }
+        assert_eq!(debugger.steps(), steps - 2500);
+        assert_eq!(debugger.run(), Stop::Halted);
+        assert_eq!(debugger.steps(), steps);
+        assert_eq!(debugger.output(), b"100: 2 2 5 5\n");
+
+        while debugger.step_back() {}
+        assert_eq!((debugger.steps(), debugger.pc()), (0, 0));
+        assert_eq!(debugger.journal().count(), 0);
+        assert_eq!(debugger.run(), Stop::Halted);
+        assert_eq!(debugger.output(), b"100: 2 2 5 5\n");
+    }
+
+    #[test]
+    fn steps_back_from_errors() {
+        // The loop becomes a single instruction, which clears the cell before it fails.
+        let mut debugger = debugger(b"+[-<+>]", b"");
+        assert_eq!(debugger.run(), Stop::Error(Error::PointerUnderflow));
+        assert_eq!(debugger.state().load(), 0);
+        assert!(debugger.step_back());
+        assert_eq!(debugger.state().load(), 1);
+        assert_eq!(debugger.journal().count(), 1);
+        assert_eq!(debugger.step(), Some(Stop::Error(Error::PointerUnderflow)));
+    }
+}
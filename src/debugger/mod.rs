@@ -0,0 +1,560 @@
+//! Runs a bytecode program one instruction at a time, stopping at breakpoints and watchpoints.
+//!
+//! A [`Debugger`](struct.Debugger.html) owns a [bytecode](../bytecode/index.html) program, its
+//! state, and its input and output. [`step`](struct.Debugger.html#method.step) runs a single
+//! instruction, and [`run`](struct.Debugger.html#method.run) runs until the program halts or
+//! fails, or until it reaches a breakpoint or sets off a watchpoint.
+//!
+//! A breakpoint is the address of an instruction, as shown by `bfi disasm`, and may have a
+//! [`Condition`](struct.Condition.html) such as `cell(5) == 0 && ptr > 100`, so that it stops
+//! the program only when the condition holds. A
+//! [`Watchpoint`](struct.Watchpoint.html) covers a range of cells, and stops the program when an
+//! instruction writes one of them, or only when a write gives one a particular value. Each
+//! instruction writes at most two cells, which the debugger works out before running it, so
+//! watching costs nothing for instructions that write no watched cell.
+//!
+//! [`step_back`](struct.Debugger.html#method.step_back) undoes the last instruction. The
+//! debugger keeps a snapshot of the state every thousand or so instructions and records the
+//! input that the program reads, so that going back means restoring a snapshot and running
+//! forward from it again, which gives the same result as the first time.
+//!
+//! A program compiled by [`compile_source`](fn.compile_source.html) has an instruction for each
+//! command of its source and a [`SourceMap`](struct.SourceMap.html) back to it, so that each
+//! step is one command. A [`Console`](struct.Console.html) reads debugger commands a line at a
+//! time, as `bfi debug` does, and with the `tui` feature can redraw the terminal with the source,
+//! the tape, and the output after each one.
+//!
+//! ```
+//! use bf::ast;
+//! use bf::debugger::{Debugger, Stop, Watchpoint};
+//! use bf::state::State;
+//! use bf::traits::*;
+//!
+//! let program = ast::parse_program(b"++[->+++<]>.").unwrap().bytecode_compile();
+//! let mut debugger = Debugger::new(program, State::new(), &b""[..], Vec::new());
+//! debugger.watch(Watchpoint::value(1..2, 6));
+//!
+//! match debugger.run() {
+//!     Stop::Watchpoint(hit) => assert_eq!((hit.cell, hit.old, hit.new), (1, 0, 6)),
+//!     other => panic!("unexpected stop: {:?}", other),
+//! }
+//! assert_eq!(debugger.run(), Stop::Halted);
+//! ```
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::ops::Range;
+
+use crate::bytecode;
+use crate::common::{BfResult, Error, Instruction};
+use crate::heatmap::Heatmap;
+use crate::state::State;
+use crate::traits::IntoUsize;
+
+use self::history::History;
+
+mod condition;
+mod console;
+mod history;
+mod source;
+
+pub use self::condition::{BinaryOp, Condition, Expr, UnaryOp};
+pub use self::console::Console;
+pub use self::source::{SourceMap, compile_source};
+
+/// Why a debugger stopped running the program.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Stop {
+    /// The program ran off its end.
+    Halted,
+    /// The program is about to run the instruction at this address, which has a breakpoint.
+    Breakpoint(usize),
+    /// The last instruction wrote a watched cell. If it set off more than one watchpoint, this
+    /// is the first.
+    Watchpoint(Hit),
+    /// The last instruction failed, and the program can't continue.
+    Error(Error),
+}
+
+/// A range of cells to watch, on whichever tape is current.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Watchpoint {
+    /// The addresses of the cells.
+    pub cells: Range<usize>,
+    /// The value that a write must store to set off the watchpoint, or `None` for any write.
+    pub value: Option<u8>,
+}
+
+impl Watchpoint {
+    /// Watches for any write to the given cells, even one that doesn't change them.
+    pub fn write(cells: Range<usize>) -> Self {
+        Watchpoint { cells, value: None }
+    }
+
+    /// Watches for a write that gives one of the given cells the given value.
+    pub fn value(cells: Range<usize>, value: u8) -> Self {
+        Watchpoint {
+            cells,
+            value: Some(value),
+        }
+    }
+
+    fn is_set_off_by(&self, cell: usize, new: u8) -> bool {
+        self.cells.contains(&cell) && self.value.is_none_or(|value| value == new)
+    }
+}
+
+/// A write that set off a watchpoint.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Hit {
+    /// The number of the watchpoint, as returned by [`watch`](struct.Debugger.html#method.watch).
+    pub watchpoint: usize,
+    /// The address of the cell.
+    pub cell: usize,
+    /// The value of the cell before the write.
+    pub old: u8,
+    /// The value of the cell after the write.
+    pub new: u8,
+}
+
+/// A bytecode program, paused between instructions.
+pub struct Debugger<R, W> {
+    program: Box<bytecode::Program>,
+    state: State,
+    input: R,
+    output: W,
+    pc: usize,
+    /// The breakpoints, by address, with any condition.
+    breakpoints: BTreeMap<usize, Option<Condition>>,
+    /// The watchpoints, by number, with `None` for those that were removed.
+    watchpoints: Vec<Option<Watchpoint>>,
+    /// The error that stopped the program, if it failed.
+    error: Option<Error>,
+    /// Whether `run` stopped at the breakpoint at `pc`, so that running again goes past it.
+    at_breakpoint: bool,
+    /// The number of instructions that have run.
+    steps: u64,
+    history: History,
+}
+
+impl<R: Read, W: Write> Debugger<R, W> {
+    /// Creates a debugger that is about to run the first instruction of the program.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the program doesn't [verify](../bytecode/fn.verify.html).
+    pub fn new(program: Box<bytecode::Program>, state: State, input: R, output: W) -> Self {
+        if let Err(message) = bytecode::verify(&program) {
+            panic!("cannot debug an invalid program: {}", message);
+        }
+
+        Debugger {
+            program,
+            state,
+            input,
+            output,
+            pc: 0,
+            breakpoints: BTreeMap::new(),
+            watchpoints: Vec::new(),
+            error: None,
+            at_breakpoint: false,
+            steps: 0,
+            history: History::default(),
+        }
+    }
+
+    /// The program.
+    pub fn program(&self) -> &bytecode::Program {
+        &self.program
+    }
+
+    /// The state of the machine.
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// The address of the next instruction to run.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// The output, which has everything that the program has written so far.
+    pub fn output(&self) -> &W {
+        &self.output
+    }
+
+    /// Sets a breakpoint at the given address, returning whether there wasn't one already. This
+    /// replaces any condition on a breakpoint that was there.
+    pub fn add_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.insert(address, None).is_none()
+    }
+
+    /// Sets a breakpoint at the given address that stops the program only if the condition
+    /// holds, returning whether there wasn't one already.
+    pub fn add_conditional_breakpoint(&mut self, address: usize, condition: Condition) -> bool {
+        self.breakpoints.insert(address, Some(condition)).is_none()
+    }
+
+    /// Removes the breakpoint at the given address, returning whether there was one.
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address).is_some()
+    }
+
+    /// Adds a watchpoint, returning its number.
+    pub fn watch(&mut self, watchpoint: Watchpoint) -> usize {
+        self.watchpoints.push(Some(watchpoint));
+        self.watchpoints.len() - 1
+    }
+
+    /// Removes the watchpoint with the given number, returning it if there was one.
+    pub fn unwatch(&mut self, number: usize) -> Option<Watchpoint> {
+        self.watchpoints.get_mut(number).and_then(Option::take)
+    }
+
+    /// Runs one instruction, returning why the program stopped, if it did. Breakpoints don't
+    /// stop a single step.
+    pub fn step(&mut self) -> Option<Stop> {
+        if let Some(error) = self.error {
+            return Some(Stop::Error(error));
+        }
+        let instruction = match self.program.get(self.pc) {
+            Some(instruction) => instruction.clone(),
+            None => return Some(Stop::Halted),
+        };
+
+        self.at_breakpoint = false;
+        self.history.before_step(self.steps, self.pc, &self.state);
+        let before = self.watched_writes(&instruction);
+        if let Err(error) = self.execute(&instruction) {
+            self.error = Some(error);
+            return Some(Stop::Error(error));
+        }
+        self.pc += 1;
+        self.steps += 1;
+
+        before
+            .into_iter()
+            .flatten()
+            .find_map(|(cell, old)| self.hit(cell, old))
+            .map(Stop::Watchpoint)
+    }
+
+    /// Runs until the program stops. If it last stopped at a breakpoint, it continues past it.
+    pub fn run(&mut self) -> Stop {
+        loop {
+            if !self.at_breakpoint && self.is_at_breakpoint() {
+                self.at_breakpoint = true;
+                return Stop::Breakpoint(self.pc);
+            }
+            if let Some(stop) = self.step() {
+                return stop;
+            }
+        }
+    }
+
+    /// Whether there is a breakpoint at `pc` whose condition, if any, holds.
+    fn is_at_breakpoint(&self) -> bool {
+        match self.breakpoints.get(&self.pc) {
+            Some(Some(condition)) => condition.holds(&self.state, self.pc),
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    /// The cells that the instruction will write and that are watched, with their values now.
+    fn watched_writes(&self, instruction: &Instruction) -> [Option<(usize, u8)>; 2] {
+        use crate::common::Instruction::*;
+
+        if self.watchpoints.iter().all(Option::is_none) {
+            return [None, None];
+        }
+
+        let pointer = self.state.pointer() as isize;
+        let current = self.state.load();
+        let offset = match *instruction {
+            OffsetAddRight(offset) if current != 0 => Some(offset.into_usize() as isize),
+            OffsetAddLeft(offset) if current != 0 => Some(-(offset.into_usize() as isize)),
+            MulAddRight(offset, _) if current != 0 => Some(offset.into_usize() as isize),
+            MulAddLeft(offset, _) if current != 0 => Some(-(offset.into_usize() as isize)),
+            AddAt(offset, _) | SetAt(offset, _) => Some(offset),
+            _ => None,
+        };
+        let writes_current = match *instruction {
+            Add(_) | In | SetZero | SetConst(_) => true,
+            OffsetAddRight(_) | OffsetAddLeft(_) => current != 0,
+            _ => false,
+        };
+
+        let memory = self.state.as_bytes();
+        let watched = |address: isize| {
+            let cell = usize::try_from(address).ok()?;
+            let old = *memory.get(cell)?;
+            let watched = self
+                .watchpoints
+                .iter()
+                .flatten()
+                .any(|watchpoint| watchpoint.cells.contains(&cell));
+            if watched { Some((cell, old)) } else { None }
+        };
+
+        [
+            if writes_current {
+                watched(pointer)
+            } else {
+                None
+            },
+            offset.and_then(|offset| watched(pointer + offset)),
+        ]
+    }
+
+    /// The first watchpoint that a write to the cell set off.
+    fn hit(&self, cell: usize, old: u8) -> Option<Hit> {
+        let new = self.state.as_bytes()[cell];
+        self.watchpoints
+            .iter()
+            .enumerate()
+            .find_map(|(number, watchpoint)| match *watchpoint {
+                Some(ref watchpoint) if watchpoint.is_set_off_by(cell, new) => Some(Hit {
+                    watchpoint: number,
+                    cell,
+                    old,
+                    new,
+                }),
+                _ => None,
+            })
+    }
+
+    fn execute(&mut self, instruction: &Instruction) -> BfResult<()> {
+        use crate::common::Instruction::*;
+
+        let state = &mut self.state;
+        let (pointer, value) = (state.pointer(), state.load());
+        match *instruction {
+            Left(count) => state.left(count)?,
+            Right(count) => state.right(count)?,
+            Add(count) => state.add(count)?,
+            In => state.read(&mut self.history.reader(&mut self.input)),
+            Out => self.history.write(&mut self.output, &[state.load()]),
+            WriteStr(ref bytes) => self.history.write(&mut self.output, bytes),
+            JumpZero(address) => {
+                if state.load() == 0 {
+                    self.pc = address.into_usize();
+                }
+            }
+            JumpNotZero(address) => {
+                if state.load() != 0 {
+                    self.pc = address.into_usize();
+                }
+            }
+            SetZero => state.store(0),
+            SetConst(value) => state.store(value),
+            OffsetAddRight(offset) => {
+                let value = state.load();
+                if value != 0 {
+                    state.store(0);
+                    state.up_pos_offset(offset, value)?;
+                }
+            }
+            OffsetAddLeft(offset) => {
+                let value = state.load();
+                if value != 0 {
+                    state.store(0);
+                    state.up_neg_offset(offset, value)?;
+                }
+            }
+            MulAddRight(offset, factor) => {
+                let value = state.load();
+                if value != 0 {
+                    state.up_pos_offset(offset, value.wrapping_mul(factor))?;
+                }
+            }
+            MulAddLeft(offset, factor) => {
+                let value = state.load();
+                if value != 0 {
+                    state.up_neg_offset(offset, value.wrapping_mul(factor))?;
+                }
+            }
+            AddAt(offset, value) => state.up_at(offset, value)?,
+            SetAt(offset, value) => state.store_at(offset, value)?,
+            FindZeroRight(skip) => state.find_zero_right(skip)?,
+            FindZeroLeft(skip) => state.find_zero_left(skip)?,
+            SwitchTape(count) => state.switch_tape(count),
+        }
+
+        let after = state.pointer();
+        if let Some(heat) = state.heatmap_mut() {
+            record_heat(heat, instruction, pointer, value, after);
+        }
+        Ok(())
+    }
+}
+
+/// Counts the cells that an instruction read and wrote, given the pointer and the cell before
+/// it ran, and the pointer after.
+fn record_heat(
+    heat: &mut Heatmap,
+    instruction: &Instruction,
+    pointer: usize,
+    value: u8,
+    after: usize,
+) {
+    use crate::common::Instruction::*;
+
+    let at = |offset: isize| pointer.wrapping_add_signed(offset);
+    match *instruction {
+        Left(_) | Right(_) | WriteStr(_) | SwitchTape(_) => {}
+        Add(_) => {
+            heat.read(pointer);
+            heat.write(pointer);
+        }
+        In | SetZero | SetConst(_) => heat.write(pointer),
+        Out | JumpZero(_) | JumpNotZero(_) => heat.read(pointer),
+        OffsetAddRight(offset)
+        | OffsetAddLeft(offset)
+        | MulAddRight(offset, _)
+        | MulAddLeft(offset, _) => {
+            heat.read(pointer);
+            if value != 0 {
+                let offset = offset.into_usize() as isize;
+                let target = match instruction {
+                    OffsetAddRight(_) | MulAddRight(..) => at(offset),
+                    _ => at(-offset),
+                };
+                // The multiplies leave the cell at the pointer for a store after them.
+                if let OffsetAddRight(_) | OffsetAddLeft(_) = instruction {
+                    heat.write(pointer);
+                }
+                heat.read(target);
+                heat.write(target);
+            }
+        }
+        AddAt(offset, _) => {
+            heat.read(at(offset));
+            heat.write(at(offset));
+        }
+        SetAt(offset, _) => heat.write(at(offset)),
+        FindZeroRight(skip) | FindZeroLeft(skip) => {
+            let skip = skip.into_usize();
+            let mut cell = pointer;
+            heat.read(cell);
+            while cell != after {
+                cell = if after > pointer {
+                    cell + skip
+                } else {
+                    cell - skip
+                };
+                heat.read(cell);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast;
+    use crate::test_helpers::*;
+    use crate::traits::*;
+
+    fn debugger(src: &[u8], input: &'static [u8]) -> Debugger<&'static [u8], Vec<u8>> {
+        let program = ast::parse_program(src).unwrap().bytecode_compile();
+        Debugger::new(program, State::new(), input, Vec::new())
+    }
+
+    #[test]
+    fn runs_like_the_interpreter() {
+        let mut debugger = debugger(FACTOR_SRC, b"100\n");
+        assert_eq!(debugger.run(), Stop::Halted);
+        assert_eq!(debugger.output(), b"100: 2 2 5 5\n");
+        assert_eq!(debugger.step(), Some(Stop::Halted));
+    }
+
+    #[test]
+    fn stops_at_breakpoints() {
+        let mut debugger = debugger(b"++[>+.<-]", b"");
+        let out = debugger
+            .program()
+            .iter()
+            .position(|instruction| *instruction == Instruction::Out)
+            .unwrap();
+        assert!(debugger.add_breakpoint(out));
+        assert!(!debugger.add_breakpoint(out));
+
+        assert_eq!(debugger.run(), Stop::Breakpoint(out));
+        assert_eq!(debugger.output(), b"");
+        assert_eq!(debugger.step(), None);
+        assert_eq!(debugger.pc(), out + 1);
+        assert_eq!(debugger.run(), Stop::Breakpoint(out));
+        assert_eq!(debugger.output(), &[1]);
+
+        assert!(debugger.remove_breakpoint(out));
+        assert_eq!(debugger.run(), Stop::Halted);
+        assert_eq!(debugger.output(), &[1, 2]);
+    }
+
+    #[test]
+    fn stops_at_breakpoints_when_conditions_hold() {
+        let mut debugger = debugger(b"+++++[.-]", b"");
+        let out = debugger
+            .program()
+            .iter()
+            .position(|instruction| *instruction == Instruction::Out)
+            .unwrap();
+        let condition = Condition::parse("cell(ptr) < 3 && pc > 0").unwrap();
+        assert!(debugger.add_conditional_breakpoint(out, condition));
+
+        assert_eq!(debugger.run(), Stop::Breakpoint(out));
+        assert_eq!(debugger.output(), &[5, 4, 3]);
+        assert_eq!(debugger.run(), Stop::Breakpoint(out));
+        assert_eq!(debugger.output(), &[5, 4, 3, 2]);
+        assert!(!debugger.add_breakpoint(out));
+        assert_eq!(debugger.run(), Stop::Halted);
+        assert_eq!(debugger.output(), &[5, 4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn watches_writes() {
+        let mut debugger = debugger(b">,<+>[-]", b"a");
+        let number = debugger.watch(Watchpoint::write(1..2));
+        let expected = Hit {
+            watchpoint: number,
+            cell: 1,
+            old: 0,
+            new: b'a',
+        };
+        assert_eq!(debugger.run(), Stop::Watchpoint(expected));
+        // The `+` writes another cell, and `[-]` becomes a single store.
+        assert_eq!(
+            debugger.run(),
+            Stop::Watchpoint(Hit {
+                old: b'a',
+                new: 0,
+                ..expected
+            })
+        );
+        assert_eq!(debugger.run(), Stop::Halted);
+    }
+
+    #[test]
+    fn watches_values() {
+        let mut debugger = debugger(b"++[->++<]>[-<+>]", b"");
+        let low = debugger.watch(Watchpoint::value(0..1, 2));
+        let both = debugger.watch(Watchpoint::value(0..2, 4));
+
+        let mut hits = Vec::new();
+        while let Stop::Watchpoint(hit) = debugger.run() {
+            hits.push((hit.watchpoint, hit.cell, hit.new));
+        }
+        assert_eq!(hits, [(low, 0, 2), (both, 1, 4), (both, 0, 4)]);
+
+        assert!(debugger.unwatch(both).is_some());
+        assert_eq!(debugger.unwatch(both), None);
+    }
+
+    #[test]
+    fn errors_stop_the_program() {
+        let mut debugger = debugger(b"+<", b"");
+        assert_eq!(debugger.run(), Stop::Error(Error::PointerUnderflow));
+        assert_eq!(debugger.step(), Some(Stop::Error(Error::PointerUnderflow)));
+    }
+}
@@ -0,0 +1,139 @@
+use crate::ast::ParseOptions;
+use crate::bytecode::{self, usize_to_count};
+use crate::common::{BfResult, Error, Instruction};
+use crate::traits::IntoUsize;
+
+/// Where in the source each instruction of a program from
+/// [`compile_source`](fn.compile_source.html) came from.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SourceMap {
+    offsets: Vec<usize>,
+}
+
+impl SourceMap {
+    /// The offset in the source of the command at the given address.
+    pub fn offset(&self, address: usize) -> Option<usize> {
+        self.offsets.get(address).copied()
+    }
+
+    /// The address of the first command at or after the given offset in the source.
+    pub fn address(&self, offset: usize) -> Option<usize> {
+        let address = self.offsets.partition_point(|&start| start < offset);
+        if address < self.offsets.len() {
+            Some(address)
+        } else {
+            None
+        }
+    }
+}
+
+/// Compiles Brainfuck source to bytecode without optimizing it, so that each instruction is one
+/// command of the source, and stepping a debugger through the program steps through the
+/// source.
+///
+/// # Errors
+///
+/// The same as [`ast::parse_program_with`](../ast/fn.parse_program_with.html).
+pub fn compile_source(
+    src: &[u8],
+    options: &ParseOptions,
+) -> BfResult<(Box<bytecode::Program>, SourceMap)> {
+    use crate::common::Instruction::*;
+
+    let mut program = Vec::new();
+    let mut offsets = Vec::new();
+    // The addresses of the `JumpZero`s of the loops that are open.
+    let mut open = Vec::new();
+
+    for (offset, &byte) in src.iter().enumerate() {
+        let instruction = match byte {
+            b'<' => Left(1),
+            b'>' => Right(1),
+            b'+' => Add(1),
+            b'-' => Add(u8::MAX),
+            b',' => In,
+            b'.' => Out,
+            b'@' if options.multi_tape => SwitchTape(1),
+            b'[' => {
+                if options.max_depth.is_some_and(|max| open.len() >= max) {
+                    return Err(Error::NestingTooDeep);
+                }
+                open.push(program.len());
+                // The target is filled in at the end of the loop.
+                JumpZero(0)
+            }
+            b']' => {
+                let begin = open.pop().ok_or(Error::UnmatchedEnd)?;
+                program[begin] = JumpZero(usize_to_count(program.len()));
+                JumpNotZero(usize_to_count(begin))
+            }
+            _ => continue,
+        };
+        program.push(instruction);
+        offsets.push(offset);
+    }
+
+    if !open.is_empty() {
+        return Err(Error::UnmatchedBegin);
+    }
+    debug_assert!(bytecode::verify(&program).is_ok());
+    Ok((program.into_boxed_slice(), SourceMap { offsets }))
+}
+
+/// The Brainfuck command that an instruction from [`compile_source`](fn.compile_source.html)
+/// stands for.
+pub(super) fn command(instruction: &Instruction) -> Option<char> {
+    use crate::common::Instruction::*;
+
+    match *instruction {
+        Left(count) if count.into_usize() == 1 => Some('<'),
+        Right(count) if count.into_usize() == 1 => Some('>'),
+        Add(1) => Some('+'),
+        Add(u8::MAX) => Some('-'),
+        In => Some(','),
+        Out => Some('.'),
+        JumpZero(_) => Some('['),
+        JumpNotZero(_) => Some(']'),
+        SwitchTape(count) if count.into_usize() == 1 => Some('@'),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use crate::ast::{self, ParseOptions};
+    use crate::test_helpers::*;
+    use crate::traits::ToSource;
+
+    #[test]
+    fn maps_instructions_to_commands() {
+        let src = b"+ loop: [->+<] .";
+        let (program, map) = compile_source(src, &ParseOptions::default()).unwrap();
+        assert_eq!(program.len(), 8);
+        assert_eq!(program[1], Instruction::JumpZero(6));
+        assert_eq!(program[6], Instruction::JumpNotZero(1));
+        assert_eq!(
+            (map.offset(1), map.offset(7), map.offset(8)),
+            (Some(8), Some(15), None)
+        );
+        assert_eq!(
+            (map.address(2), map.address(8), map.address(16)),
+            (Some(1), Some(1), None)
+        );
+
+        let errors = |src: &[u8]| compile_source(src, &ParseOptions::default()).unwrap_err();
+        assert_eq!(errors(b"[[]"), Error::UnmatchedBegin);
+        assert_eq!(errors(b"[]]"), Error::UnmatchedEnd);
+    }
+
+    #[test]
+    fn runs_like_the_source() {
+        let (program, _) = compile_source(FACTOR_SRC, &ParseOptions::default()).unwrap();
+        let mut debugger = Debugger::new(program, State::new(), &b"100\n"[..], Vec::new());
+        assert_eq!(debugger.run(), Stop::Halted);
+        assert_eq!(debugger.output(), b"100: 2 2 5 5\n");
+        let commands = ast::parse_program(FACTOR_SRC).unwrap().to_source();
+        assert_eq!(debugger.program().len(), commands.len());
+    }
+}
@@ -0,0 +1,269 @@
+//! Front ends for trivial Brainfuck substitutions, such as Ook!.
+//!
+//! A [`Dialect`](struct.Dialect.html) is a table of tokens, each standing for one of the eight
+//! commands. Translating a program looks for the longest token at each position, and as in
+//! Brainfuck, anything that isn't a token is a comment. Whitespace inside a token matches any
+//! run of whitespace, so the two words of an Ook! token can be split across lines. The
+//! translation is ordinary Brainfuck, so the whole pipeline works on it unchanged.
+//!
+//! In `bfi`, pass `--dialect ook` or `--dialect blub` to run a program written in a dialect.
+
+use crate::ast;
+use crate::common::{BfResult, Command};
+
+/// A table mapping tokens to Brainfuck commands.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Dialect {
+    /// Sorted longest first, so that the first match is the longest.
+    tokens: Vec<(Box<[u8]>, Command)>,
+}
+
+impl Dialect {
+    /// The names of the preset dialects, as accepted by
+    /// [`by_name`](struct.Dialect.html#method.by_name).
+    pub const NAMES: [&'static str; 3] = ["brainfuck", "ook", "blub"];
+
+    /// Creates a dialect with no tokens.
+    pub fn new() -> Self {
+        Dialect::default()
+    }
+
+    /// Looks up a preset dialect by name.
+    pub fn by_name(name: &str) -> Option<Dialect> {
+        match name {
+            "brainfuck" => Some(Dialect::brainfuck()),
+            "ook" => Some(Dialect::ook()),
+            "blub" => Some(Dialect::blub()),
+            _ => None,
+        }
+    }
+
+    /// Brainfuck itself.
+    pub fn brainfuck() -> Self {
+        let mut result = Dialect::new();
+        for command in COMMANDS {
+            result.add(&[command.symbol()], command);
+        }
+        result
+    }
+
+    /// [Ook!](https://esolangs.org/wiki/Ook!), in which tokens are pairs of `Ook.`, `Ook?`, and
+    /// `Ook!`.
+    pub fn ook() -> Self {
+        Dialect::pairs("Ook")
+    }
+
+    /// [Blub](https://esolangs.org/wiki/Blub), which is Ook! with `Blub` for `Ook`.
+    pub fn blub() -> Self {
+        Dialect::pairs("Blub")
+    }
+
+    /// The Ook!-style dialect where `word` replaces `Ook`.
+    fn pairs(word: &str) -> Self {
+        const PAIRS: [(&str, &str, Command); 8] = [
+            (".", "?", Command::Right),
+            ("?", ".", Command::Left),
+            (".", ".", Command::Up),
+            ("!", "!", Command::Down),
+            ("!", ".", Command::Out),
+            (".", "!", Command::In),
+            ("!", "?", Command::Begin),
+            ("?", "!", Command::End),
+        ];
+
+        let mut result = Dialect::new();
+        for (first, second, command) in PAIRS {
+            let token = format!("{}{} {}{}", word, first, word, second);
+            result.add(token.as_bytes(), command);
+        }
+        result
+    }
+
+    /// Adds a token for the given command, replacing any earlier meaning of the same token.
+    ///
+    /// Leading and trailing whitespace in the token is ignored.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the token is empty or all whitespace.
+    pub fn add(&mut self, token: &[u8], command: Command) -> &mut Self {
+        let token = token.trim_ascii();
+        assert!(!token.is_empty(), "dialect tokens cannot be empty");
+
+        self.tokens.retain(|(existing, _)| **existing != *token);
+        let index = self
+            .tokens
+            .iter()
+            .position(|(existing, _)| existing.len() < token.len())
+            .unwrap_or(self.tokens.len());
+        self.tokens.insert(index, (token.into(), command));
+        self
+    }
+
+    /// Translates a program in this dialect to Brainfuck, dropping the comments.
+    pub fn translate(&self, src: &[u8]) -> Vec<u8> {
+        let mut result = Vec::new();
+        let mut rest = src;
+
+        while !rest.is_empty() {
+            let found = self
+                .tokens
+                .iter()
+                .find_map(|(token, command)| Some((match_token(token, rest)?, *command)));
+
+            match found {
+                Some((len, command)) => {
+                    result.push(command.symbol());
+                    rest = &rest[len..];
+                }
+                None => rest = &rest[1..],
+            }
+        }
+
+        result
+    }
+
+    /// Translates a program in this dialect and parses the result.
+    ///
+    /// A dialect only produces the commands that it has tokens for, so a token for an extension
+    /// command, such as [`SwitchTape`](../common/enum.Command.html#variant.SwitchTape), enables
+    /// that extension.
+    ///
+    /// # Errors
+    ///
+    /// The same as [`ast::parse_program`](../ast/fn.parse_program.html).
+    pub fn parse(&self, src: &[u8]) -> BfResult<Box<ast::Program>> {
+        let options = ast::ParseOptions {
+            multi_tape: true,
+            ..ast::ParseOptions::default()
+        };
+        ast::parse_program_with(&self.translate(src), &options)
+    }
+}
+
+const COMMANDS: [Command; 8] = [
+    Command::Right,
+    Command::Left,
+    Command::Up,
+    Command::Down,
+    Command::In,
+    Command::Out,
+    Command::Begin,
+    Command::End,
+];
+
+/// If `src` starts with `token`, returns the length of the match.
+fn match_token(token: &[u8], src: &[u8]) -> Option<usize> {
+    let (mut i, mut j) = (0, 0);
+
+    while i < token.len() {
+        if token[i].is_ascii_whitespace() {
+            if !src.get(j)?.is_ascii_whitespace() {
+                return None;
+            }
+            while i < token.len() && token[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            while j < src.len() && src[j].is_ascii_whitespace() {
+                j += 1;
+            }
+        } else if src.get(j) == Some(&token[i]) {
+            i += 1;
+            j += 1;
+        } else {
+            return None;
+        }
+    }
+
+    Some(j)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn brainfuck_translates_to_itself() {
+        let dialect = Dialect::brainfuck();
+        assert_eq!(dialect.translate(b"+[>.<-] comment"), b"+[>.<-]");
+    }
+
+    #[test]
+    fn ook_hello_world() {
+        let src = to_ook(HELLO_WORLD_SRC);
+        let program = Dialect::ook().parse(src.as_bytes()).unwrap();
+        assert_interpret(&*program, b"", b"Hello, World!");
+    }
+
+    #[test]
+    fn ook_factoring_across_lines() {
+        let src = to_ook(FACTOR_SRC).replace(" Ook", "\nOok");
+        let program = Dialect::ook().parse(src.as_bytes()).unwrap();
+        assert_interpret(&*program, b"100\n", b"100: 2 2 5 5\n");
+    }
+
+    #[test]
+    fn blub_is_ook_with_blub() {
+        let src = to_ook(b"+++.").replace("Ook", "Blub");
+        assert_eq!(Dialect::blub().translate(src.as_bytes()), b"+++.");
+    }
+
+    #[test]
+    fn longest_token_wins() {
+        let mut dialect = Dialect::new();
+        dialect
+            .add(b"a", Command::Up)
+            .add(b"aa", Command::Down)
+            .add(b"  b ", Command::Out);
+        assert_eq!(dialect.translate(b"aaa b"), b"-+.");
+
+        dialect.add(b"aa", Command::Right);
+        assert_eq!(dialect.translate(b"aaa"), b">+");
+    }
+
+    #[test]
+    fn tokens_need_whitespace_between_words() {
+        assert_eq!(Dialect::ook().translate(b"Ook.Ook. Ook. Ook."), b"+");
+    }
+
+    #[test]
+    fn tokens_for_extensions() {
+        let mut dialect = Dialect::brainfuck();
+        dialect.add(b"tape", Command::SwitchTape);
+        let program = dialect.parse(b"+tape++ @").unwrap();
+        assert_eq!(program.len(), 4);
+    }
+
+    #[test]
+    fn unmatched_loops_are_errors() {
+        assert!(Dialect::ook().parse(b"Ook! Ook?").is_err());
+    }
+
+    #[test]
+    fn presets_by_name() {
+        for name in Dialect::NAMES {
+            assert!(Dialect::by_name(name).is_some());
+        }
+        assert_eq!(Dialect::by_name("ook"), Some(Dialect::ook()));
+        assert_eq!(Dialect::by_name("spoon"), None);
+    }
+
+    fn to_ook(src: &[u8]) -> String {
+        let words: Vec<&str> = src
+            .iter()
+            .filter_map(|&c| match c {
+                b'>' => Some("Ook. Ook?"),
+                b'<' => Some("Ook? Ook."),
+                b'+' => Some("Ook. Ook."),
+                b'-' => Some("Ook! Ook!"),
+                b'.' => Some("Ook! Ook."),
+                b',' => Some("Ook. Ook!"),
+                b'[' => Some("Ook! Ook?"),
+                b']' => Some("Ook? Ook!"),
+                _ => None,
+            })
+            .collect();
+        words.join(" ")
+    }
+}
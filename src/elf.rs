@@ -0,0 +1,288 @@
+//! Tiny static executables, written directly as ELF without an assembler or a linker.
+//!
+//! This backend is experimental. [`emit`](fn.emit.html) encodes the peephole AST as x86-64 machine
+//! code and wraps it in the smallest ELF file that Linux will run: a header, three program
+//! headers, and the code, with no sections, no symbols, and no libc. A small runtime does the I/O
+//! with raw `read` and `write` system calls, one byte at a time but for strings, and stops with a
+//! message and status 3 if the pointer leaves the memory. At the end of input, `,` stores 0.
+//! The memory is a segment of zeroed pages that takes no room in the file, so Hello World comes
+//! to under a kilobyte.
+//!
+//! The code keeps the pointer and the ends of the memory in the registers that the
+//! [JIT](../jit/index.html) and the [assembly emitter](../asm/index.html) use, but it favors size
+//! over speed: each move calls a runtime function that checks it, and scanning loops are not
+//! specialized.
+//!
+//! ```
+//! use bf::ast;
+//! use bf::elf;
+//! use bf::traits::*;
+//!
+//! let program = ast::parse_program(b",[.,]").unwrap().peephole_compile();
+//! let executable = elf::emit(&program, 30_000);
+//! assert_eq!(&executable[..4], b"\x7fELF");
+//! assert!(executable.len() < 1024);
+//! ```
+
+use crate::peephole;
+use crate::x64::{self, Code, Runtime};
+
+/// Where the file is loaded, and so where the code is.
+const BASE: u64 = 0x40_0000;
+
+const PAGE: u64 = 0x1000;
+
+const ELF_HEADER_SIZE: usize = 64;
+
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// The code, the memory, and the stack, which is marked as not executable.
+const PROGRAM_HEADERS: usize = 3;
+
+/// Where the code starts in the file.
+const CODE_OFFSET: usize = ELF_HEADER_SIZE + PROGRAM_HEADERS * PROGRAM_HEADER_SIZE;
+
+/// Renders the program as an executable for x86-64 Linux whose memory is `memory_size` cells.
+///
+/// # Panics
+///
+/// Panics if the program contains jump instructions, which the peephole AST doesn't.
+pub fn emit(program: &peephole::Program, memory_size: usize) -> Vec<u8> {
+    let mut code = Code::new();
+    let runtime = Runtime::new(&mut code);
+
+    // The addresses of the memory, which follows the file, are filled in once its length is known.
+    code.op(&[0x49, 0xBC]); // mov r12, memory
+    let memory_start = code.imm64(0);
+    code.op(&[0x4D, 0x89, 0xE5]); // mov r13, r12
+    code.op(&[0x49, 0xBE]); // mov r14, memory + memory_size
+    let memory_limit = code.imm64(0);
+
+    let strings = x64::compile(&mut code, &runtime, program);
+
+    code.op(&[0x31, 0xFF]); // xor edi, edi
+    exit(&mut code);
+    runtime.emit_moves(&mut code);
+    emit_io(&mut code, &runtime);
+    let fail = code.new_label();
+    runtime.emit_errors(&mut code, fail);
+    code.bind(fail);
+    code.op(&[0xB8, 1, 0, 0, 0]); // mov eax, 1
+    code.op(&[0xBF, 2, 0, 0, 0]); // mov edi, 2
+    code.op(&[0x0F, 0x05]); // syscall
+    code.op(&[0xBF, 3, 0, 0, 0]); // mov edi, 3
+    exit(&mut code);
+    for (label, bytes) in strings {
+        code.bind(label);
+        code.op(bytes);
+    }
+
+    let mut bytes = code.finish();
+    let file_size = (CODE_OFFSET + bytes.len()) as u64;
+    let memory = (BASE + file_size).div_ceil(PAGE) * PAGE;
+    bytes[memory_start..memory_start + 8].copy_from_slice(&memory.to_le_bytes());
+    let limit = memory + memory_size as u64;
+    bytes[memory_limit..memory_limit + 8].copy_from_slice(&limit.to_le_bytes());
+
+    let mut file = Vec::with_capacity(file_size as usize);
+    elf_header(&mut file);
+    // The file itself, headers and all, readable and executable.
+    program_header(&mut file, PT_LOAD, PF_R | PF_X, BASE, file_size, file_size);
+    // The memory, which is all zeros and so isn't in the file.
+    program_header(
+        &mut file,
+        PT_LOAD,
+        PF_R | PF_W,
+        memory,
+        0,
+        memory_size as u64,
+    );
+    program_header(&mut file, PT_GNU_STACK, PF_R | PF_W, 0, 0, 0);
+    debug_assert_eq!(file.len(), CODE_OFFSET);
+    file.extend_from_slice(&bytes);
+    file
+}
+
+/// Exits with the status in `edi`.
+fn exit(code: &mut Code) {
+    code.op(&[0xB8, 60, 0, 0, 0]); // mov eax, 60
+    code.op(&[0x0F, 0x05]); // syscall
+}
+
+/// Emits the runtime's I/O, with `read` and `write` system calls.
+fn emit_io(code: &mut Code, runtime: &Runtime) {
+    let done = code.new_label();
+    code.bind(runtime.read);
+    code.op(&[0x31, 0xC0]); // xor eax, eax
+    code.op(&[0x31, 0xFF]); // xor edi, edi
+    code.op(&[0x4C, 0x89, 0xE6]); // mov rsi, r12
+    code.op(&[0xBA, 1, 0, 0, 0]); // mov edx, 1
+    code.op(&[0x0F, 0x05]); // syscall
+    code.op(&[0x48, 0x83, 0xF8, 0x01]); // cmp rax, 1
+    code.jump(&[0x0F, 0x84], done); // je done
+    code.op(&[0x41, 0xC6, 0x04, 0x24, 0]); // mov byte [r12], 0
+    code.bind(done);
+    code.op(&[0xC3]); // ret
+
+    // Output that can't be written is dropped.
+    let done = code.new_label();
+    code.bind(runtime.out);
+    code.op(&[0x4C, 0x89, 0xE6]); // mov rsi, r12
+    code.op(&[0xBA, 1, 0, 0, 0]); // mov edx, 1
+    code.bind(runtime.write);
+    code.op(&[0xB8, 1, 0, 0, 0]); // mov eax, 1
+    code.op(&[0xBF, 1, 0, 0, 0]); // mov edi, 1
+    code.op(&[0x0F, 0x05]); // syscall
+    code.op(&[0x48, 0x85, 0xC0]); // test rax, rax
+    code.jump(&[0x0F, 0x8E], done); // jle done
+    code.op(&[0x48, 0x01, 0xC6]); // add rsi, rax
+    code.op(&[0x48, 0x29, 0xC2]); // sub rdx, rax
+    code.jump(&[0x0F, 0x85], runtime.write); // jnz bf_write
+    code.bind(done);
+    code.op(&[0xC3]); // ret
+}
+
+const PT_LOAD: u32 = 1;
+const PT_GNU_STACK: u32 = 0x6474_e551;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+fn elf_header(file: &mut Vec<u8>) {
+    // A 64-bit, little-endian, System V executable.
+    file.extend_from_slice(b"\x7fELF\x02\x01\x01\x00");
+    file.extend_from_slice(&[0; 8]);
+    file.extend_from_slice(&2u16.to_le_bytes()); // e_type: ET_EXEC
+    file.extend_from_slice(&0x3Eu16.to_le_bytes()); // e_machine: EM_X86_64
+    file.extend_from_slice(&1u32.to_le_bytes()); // e_version
+    file.extend_from_slice(&(BASE + CODE_OFFSET as u64).to_le_bytes()); // e_entry
+    file.extend_from_slice(&(ELF_HEADER_SIZE as u64).to_le_bytes()); // e_phoff
+    file.extend_from_slice(&0u64.to_le_bytes()); // e_shoff
+    file.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    file.extend_from_slice(&(ELF_HEADER_SIZE as u16).to_le_bytes()); // e_ehsize
+    file.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes()); // e_phentsize
+    file.extend_from_slice(&(PROGRAM_HEADERS as u16).to_le_bytes()); // e_phnum
+    file.extend_from_slice(&[0; 6]); // e_shentsize, e_shnum, e_shstrndx
+}
+
+/// Appends a program header for a segment at `address` whose first `file_size` bytes are the start
+/// of the file.
+fn program_header(
+    file: &mut Vec<u8>,
+    kind: u32,
+    flags: u32,
+    address: u64,
+    file_size: u64,
+    memory_size: u64,
+) {
+    let align = if kind == PT_LOAD { PAGE } else { 16 };
+    file.extend_from_slice(&kind.to_le_bytes());
+    file.extend_from_slice(&flags.to_le_bytes());
+    file.extend_from_slice(&0u64.to_le_bytes()); // p_offset
+    file.extend_from_slice(&address.to_le_bytes()); // p_vaddr
+    file.extend_from_slice(&address.to_le_bytes()); // p_paddr
+    file.extend_from_slice(&file_size.to_le_bytes());
+    file.extend_from_slice(&memory_size.to_le_bytes());
+    file.extend_from_slice(&align.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast;
+    use crate::test_helpers::*;
+    use crate::traits::*;
+
+    fn emit_src(src: &[u8], memory_size: usize) -> Vec<u8> {
+        let program = ast::parse_program(src).unwrap().peephole_compile();
+        emit(&program, memory_size)
+    }
+
+    fn u64_at(bytes: &[u8], at: usize) -> u64 {
+        u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn lays_out_a_minimal_executable() {
+        let file = emit_src(HELLO_WORLD_SRC, 30_000);
+        assert_eq!(&file[..4], b"\x7fELF");
+        assert_eq!(u64_at(&file, 24), BASE + CODE_OFFSET as u64);
+        // The code segment is the whole file, and the memory follows it on a page of its own.
+        let code = ELF_HEADER_SIZE;
+        let memory = code + PROGRAM_HEADER_SIZE;
+        assert_eq!(u64_at(&file, code + 32), file.len() as u64);
+        let address = u64_at(&file, memory + 16);
+        assert_eq!(address % PAGE, 0);
+        assert!(address >= BASE + file.len() as u64);
+        assert_eq!(u64_at(&file, memory + 32), 0);
+        assert_eq!(u64_at(&file, memory + 40), 30_000);
+        // The program starts by loading the ends of the memory.
+        assert_eq!(&file[CODE_OFFSET..CODE_OFFSET + 2], [0x49, 0xBC]);
+        assert_eq!(u64_at(&file, CODE_OFFSET + 2), address);
+        assert!(file.len() < 1024, "{} bytes", file.len());
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    fn run(src: &[u8], memory_size: usize, input: &[u8]) -> (Option<i32>, Vec<u8>, Vec<u8>) {
+        use std::io::Write;
+        use std::os::unix::fs::PermissionsExt;
+        use std::process::{Command, Stdio};
+
+        static RUNS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let run = RUNS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("bf-elf-{}-{}", std::process::id(), run));
+        std::fs::write(&path, emit_src(src, memory_size)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        // Another test's child can briefly hold the file open for writing.
+        let mut child = loop {
+            match Command::new(&path)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+            {
+                Err(e) if e.raw_os_error() == Some(libc::ETXTBSY) => std::thread::yield_now(),
+                result => break result.unwrap(),
+            }
+        };
+        child.stdin.take().unwrap().write_all(input).unwrap();
+        let output = child.wait_with_output().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        (output.status.code(), output.stdout, output.stderr)
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    #[test]
+    fn runs_like_the_interpreter() {
+        assert_eq!(
+            run(HELLO_WORLD_SRC, 30_000, b""),
+            (Some(0), b"Hello, World!".to_vec(), vec![])
+        );
+        assert_eq!(run(FACTOR_SRC, 30_000, b"100\n").1, b"100: 2 2 5 5\n");
+        assert_eq!(run(b",[.,]", 1, b"echo\n").1, b"echo\n");
+        // Each kind of move, including a scan and a multiplication, and the end of input.
+        let src = b"+>>+>>+<<<<[>>]<<[->+++>>++++<<<]>>>.<.<<<<[<]>.,.>>+<-<.";
+        let mut expected = Vec::new();
+        ast::parse_program(src)
+            .unwrap()
+            .interpret(None, &b""[..], &mut expected)
+            .unwrap();
+        assert_eq!(run(src, 100, b""), (Some(0), expected, vec![]));
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    #[test]
+    fn stops_when_the_pointer_leaves_the_memory() {
+        let underflow = (
+            Some(3),
+            vec![],
+            b"runtime error: pointer underflow.\n".to_vec(),
+        );
+        assert_eq!(run(b"<", 10, b""), underflow);
+        assert_eq!(run(b"+[<+]", 10, b""), underflow);
+        let (status, output, error) = run(b"+.>+[>+]", 10, b"");
+        assert_eq!((status, output), (Some(3), vec![1]));
+        assert_eq!(error, b"runtime error: pointer overflow.\n");
+        assert_eq!(run(b"+[->>>>>>>>>>+<<<<<<<<<<]", 10, b"").0, Some(3));
+    }
+}
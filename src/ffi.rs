@@ -0,0 +1,294 @@
+//! A C interface, for embedding the interpreter in other programs.
+//!
+//! Enabled with `--features=ffi`. To build a shared library:
+//!
+//! ```text
+//! cargo rustc --lib --release --features ffi --crate-type cdylib
+//! ```
+//!
+//! The declarations are in `include/bf.h` in the repository. A program is compiled once with
+//! [`bf_compile`](fn.bf_compile.html), run any number of times with [`bf_run`](fn.bf_run.html),
+//! and released with [`bf_free`](fn.bf_free.html). Programs run on the
+//! [peephole-optimized AST](../peephole/index.html).
+//!
+//! Functions that can fail return one of the `BF_*` status codes. Their values are stable.
+
+use std::io::{self, Read, Write};
+use std::os::raw::{c_int, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+
+use crate::common::Error;
+use crate::peephole;
+use crate::rle;
+use crate::state::State;
+use crate::traits::*;
+
+/// Success.
+pub const BF_OK: c_int = 0;
+/// Syntax error: unmatched `[`.
+pub const BF_UNMATCHED_BEGIN: c_int = 1;
+/// Syntax error: unmatched `]`.
+pub const BF_UNMATCHED_END: c_int = 2;
+/// Run-time error: the pointer went below the start of memory.
+pub const BF_POINTER_UNDERFLOW: c_int = 3;
+/// Run-time error: the pointer went past the end of memory.
+pub const BF_POINTER_OVERFLOW: c_int = 4;
+/// A required pointer argument was null, or the memory size was zero.
+pub const BF_INVALID_ARGUMENT: c_int = 5;
+/// The library panicked. This indicates a bug in the library.
+pub const BF_INTERNAL_ERROR: c_int = 6;
+/// Syntax error: loops nested more deeply than the parser allows.
+pub const BF_NESTING_TOO_DEEP: c_int = 7;
+/// Run-time error: the program ran more loop iterations than its sandbox allows.
+pub const BF_STEP_LIMIT: c_int = 8;
+/// Run-time error: the program wrote more output than its sandbox allows.
+pub const BF_OUTPUT_LIMIT: c_int = 9;
+/// Run-time error: the program needs more memory than its sandbox allows.
+pub const BF_MEMORY_LIMIT: c_int = 10;
+/// Run-time error: the program ran for longer than its sandbox allows.
+pub const BF_TIME_LIMIT: c_int = 11;
+/// Run-time error: the program was stopped by an interrupt.
+pub const BF_INTERRUPTED: c_int = 12;
+/// Run-time error: a cell would have gone past 255 or below 0.
+pub const BF_CELL_OVERFLOW: c_int = 13;
+
+/// Reads one byte, returning it, or a negative number at end of input.
+pub type BfReadFn = Option<unsafe extern "C" fn(context: *mut c_void) -> c_int>;
+
+/// Writes one byte.
+pub type BfWriteFn = Option<unsafe extern "C" fn(context: *mut c_void, byte: u8)>;
+
+/// A compiled program.
+pub struct BfProgram {
+    program: Box<peephole::Program>,
+}
+
+fn error_code(error: Error) -> c_int {
+    match error {
+        Error::UnmatchedBegin => BF_UNMATCHED_BEGIN,
+        Error::UnmatchedEnd => BF_UNMATCHED_END,
+        Error::NestingTooDeep => BF_NESTING_TOO_DEEP,
+        Error::PointerUnderflow => BF_POINTER_UNDERFLOW,
+        Error::PointerOverflow => BF_POINTER_OVERFLOW,
+        Error::StepLimit => BF_STEP_LIMIT,
+        Error::OutputLimit => BF_OUTPUT_LIMIT,
+        Error::MemoryLimit => BF_MEMORY_LIMIT,
+        Error::TimeLimit => BF_TIME_LIMIT,
+        Error::Interrupted => BF_INTERRUPTED,
+        Error::CellOverflow => BF_CELL_OVERFLOW,
+    }
+}
+
+fn catch<F: FnOnce() -> c_int>(f: F) -> c_int {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(BF_INTERNAL_ERROR)
+}
+
+/// Compiles the `len` bytes of source at `src`, storing the program in `*out`.
+///
+/// On failure, `*out` is set to null.
+///
+/// # Safety
+///
+/// `src` must point to `len` readable bytes, and `out` must be valid for writing.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bf_compile(src: *const u8, len: usize, out: *mut *mut BfProgram) -> c_int {
+    if out.is_null() || (src.is_null() && len != 0) {
+        return BF_INVALID_ARGUMENT;
+    }
+
+    unsafe { *out = std::ptr::null_mut() };
+
+    catch(|| {
+        let src = if len == 0 {
+            &[]
+        } else {
+            unsafe { slice::from_raw_parts(src, len) }
+        };
+
+        match rle::parse_program(src) {
+            Ok(program) => {
+                let program = Box::new(BfProgram {
+                    program: program.peephole_compile(),
+                });
+                unsafe { *out = Box::into_raw(program) };
+                BF_OK
+            }
+            Err(error) => error_code(error),
+        }
+    })
+}
+
+/// Runs a program with `memory_size` bytes of memory.
+///
+/// Input comes from `read` and output goes to `write`, each of which is passed `context`. If
+/// `read` is null, the input is empty, and if `write` is null, the output is discarded.
+///
+/// # Safety
+///
+/// `program` must have come from `bf_compile` and not yet have been freed, and the callbacks
+/// must be safe to call with `context`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bf_run(
+    program: *const BfProgram,
+    memory_size: usize,
+    read: BfReadFn,
+    write: BfWriteFn,
+    context: *mut c_void,
+) -> c_int {
+    if program.is_null() || memory_size == 0 {
+        return BF_INVALID_ARGUMENT;
+    }
+
+    catch(|| {
+        let program = unsafe { &*program };
+        let input = Callbacks {
+            read,
+            write,
+            context,
+        };
+        let output = Callbacks {
+            read,
+            write,
+            context,
+        };
+
+        match program
+            .program
+            .interpret_state(State::with_capacity(memory_size), input, output)
+        {
+            Ok(()) => BF_OK,
+            Err(error) => error_code(error),
+        }
+    })
+}
+
+/// Frees a program. Does nothing if `program` is null.
+///
+/// # Safety
+///
+/// `program` must be null or have come from `bf_compile`, and must not be used afterward.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bf_free(program: *mut BfProgram) {
+    if !program.is_null() {
+        drop(unsafe { Box::from_raw(program) });
+    }
+}
+
+/// Adapts the C callbacks to `Read` and `Write`.
+struct Callbacks {
+    read: BfReadFn,
+    write: BfWriteFn,
+    context: *mut c_void,
+}
+
+impl Read for Callbacks {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = match self.read {
+            Some(read) if !buf.is_empty() => read,
+            _ => return Ok(0),
+        };
+
+        match unsafe { read(self.context) } {
+            byte @ 0..=255 => {
+                buf[0] = byte as u8;
+                Ok(1)
+            }
+            _ => Ok(0),
+        }
+    }
+}
+
+impl Write for Callbacks {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(write) = self.write {
+            for &byte in buf {
+                unsafe { write(self.context, byte) };
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+    use std::ptr;
+
+    struct Buffers {
+        input: Vec<u8>,
+        output: Vec<u8>,
+    }
+
+    unsafe extern "C" fn read(context: *mut c_void) -> c_int {
+        let buffers = unsafe { &mut *(context as *mut Buffers) };
+        if buffers.input.is_empty() {
+            -1
+        } else {
+            c_int::from(buffers.input.remove(0))
+        }
+    }
+
+    unsafe extern "C" fn write(context: *mut c_void, byte: u8) {
+        let buffers = unsafe { &mut *(context as *mut Buffers) };
+        buffers.output.push(byte);
+    }
+
+    fn run(src: &[u8], input: &[u8]) -> (c_int, Vec<u8>) {
+        let mut program = ptr::null_mut();
+        let status = unsafe { bf_compile(src.as_ptr(), src.len(), &mut program) };
+        if status != BF_OK {
+            assert!(program.is_null());
+            return (status, Vec::new());
+        }
+
+        let mut buffers = Buffers {
+            input: input.to_vec(),
+            output: Vec::new(),
+        };
+        let context = &mut buffers as *mut Buffers as *mut c_void;
+        let status = unsafe { bf_run(program, 100, Some(read), Some(write), context) };
+        unsafe { bf_free(program) };
+
+        (status, buffers.output)
+    }
+
+    #[test]
+    fn factoring() {
+        assert_eq!(run(FACTOR_SRC, b"12\n"), (BF_OK, b"12: 2 2 3\n".to_vec()));
+    }
+
+    #[test]
+    fn echo_until_eof() {
+        assert_eq!(run(b",[.,]", b"abc"), (BF_OK, b"abc".to_vec()));
+    }
+
+    #[test]
+    fn error_codes() {
+        assert_eq!(run(b"[", b"").0, BF_UNMATCHED_BEGIN);
+        assert_eq!(run(b"]", b"").0, BF_UNMATCHED_END);
+        assert_eq!(run(b"<", b"").0, BF_POINTER_UNDERFLOW);
+        assert_eq!(run(b"+[>+]", b"").0, BF_POINTER_OVERFLOW);
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        unsafe {
+            assert_eq!(
+                bf_compile(ptr::null(), 1, &mut ptr::null_mut()),
+                BF_INVALID_ARGUMENT
+            );
+            assert_eq!(
+                bf_run(ptr::null(), 100, None, None, ptr::null_mut()),
+                BF_INVALID_ARGUMENT
+            );
+            bf_free(ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,269 @@
+//! Reformats Brainfuck source.
+//!
+//! [`format`](fn.format.html) rewrites a program's source with just its commands, either
+//! indented by loop depth, with each `[` and `]` on a line of its own, or
+//! [minified](struct.FormatOptions.html#method.minify) to a single line. Anything that isn't a
+//! command is a comment, which is dropped unless
+//! [`comments`](struct.FormatOptions.html#structfield.comments) is set. A kept comment goes at
+//! the end of the line of code before it, with its whitespace tidied, so formatting never turns
+//! a comment into code.
+//!
+//! In `bfi`, run `bfi fmt` to format a program, and pass `--minify` to minify it instead.
+
+use crate::common::{BfResult, Error};
+
+/// How to lay out formatted source.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FormatOptions {
+    /// The number of spaces to indent each loop level, or `None` to write the program on a
+    /// single line.
+    pub indent: Option<usize>,
+    /// The longest a line of commands can be, counting its indentation, or 0 for no limit.
+    /// Comments can make a line longer.
+    pub width: usize,
+    /// Whether to keep comments.
+    pub comments: bool,
+    /// Whether `@` is a command, as in the [multi-tape
+    /// extension](../ast/struct.ParseOptions.html).
+    pub multi_tape: bool,
+}
+
+impl Default for FormatOptions {
+    /// Indents by two spaces, with lines of up to 80 characters, and drops comments.
+    fn default() -> Self {
+        FormatOptions {
+            indent: Some(2),
+            width: 80,
+            comments: false,
+            multi_tape: false,
+        }
+    }
+}
+
+impl FormatOptions {
+    /// Writes the commands on a single line, without comments.
+    pub fn minify() -> Self {
+        FormatOptions {
+            indent: None,
+            width: 0,
+            ..FormatOptions::default()
+        }
+    }
+}
+
+/// Formats Brainfuck source. The result ends with a newline, unless it is empty.
+///
+/// # Errors
+///
+/// Unmatched square brackets are errors, as when [parsing](../ast/fn.parse_program.html).
+pub fn format(src: &[u8], options: &FormatOptions) -> BfResult<Vec<u8>> {
+    let mut formatter = Formatter {
+        options,
+        result: Vec::new(),
+        line: Vec::new(),
+        depth: 0,
+    };
+
+    let mut rest = src;
+    while let Some(&byte) = rest.first() {
+        if is_command(byte, options) {
+            formatter.command(byte)?;
+            rest = &rest[1..];
+        } else {
+            let len = rest
+                .iter()
+                .position(|&byte| is_command(byte, options))
+                .unwrap_or(rest.len());
+            if options.comments {
+                formatter.comment(&rest[..len]);
+            }
+            rest = &rest[len..];
+        }
+    }
+
+    if formatter.depth > 0 {
+        return Err(Error::UnmatchedBegin);
+    }
+    formatter.end_line();
+    Ok(formatter.result)
+}
+
+/// The words of `text`, separated by single spaces.
+fn tidy(text: &[u8]) -> Vec<u8> {
+    let words: Vec<&[u8]> = text
+        .split(|byte| byte.is_ascii_whitespace())
+        .filter(|word| !word.is_empty())
+        .collect();
+    words.join(&b' ')
+}
+
+fn is_command(byte: u8, options: &FormatOptions) -> bool {
+    match byte {
+        b'>' | b'<' | b'+' | b'-' | b',' | b'.' | b'[' | b']' => true,
+        b'@' => options.multi_tape,
+        _ => false,
+    }
+}
+
+struct Formatter<'a> {
+    options: &'a FormatOptions,
+    result: Vec<u8>,
+    /// The line being written, without its indentation.
+    line: Vec<u8>,
+    depth: usize,
+}
+
+impl Formatter<'_> {
+    fn command(&mut self, byte: u8) -> BfResult<()> {
+        let indented = self.options.indent.is_some();
+
+        match byte {
+            b'[' if indented => {
+                self.end_line();
+                self.line.push(b'[');
+                self.end_line();
+                self.depth += 1;
+            }
+            b']' if indented => {
+                self.end_line();
+                self.depth = self.depth.checked_sub(1).ok_or(Error::UnmatchedEnd)?;
+                self.line.push(b']');
+                self.end_line();
+            }
+            b'[' => {
+                self.line.push(b'[');
+                self.depth += 1;
+            }
+            b']' => {
+                self.depth = self.depth.checked_sub(1).ok_or(Error::UnmatchedEnd)?;
+                self.line.push(b']');
+            }
+            _ => {
+                let width = self.options.width;
+                if width > 0
+                    && !self.line.is_empty()
+                    && self.indentation() + self.line.len() >= width
+                {
+                    self.end_line();
+                }
+                self.line.push(byte);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds a comment to the end of the line, with each run of whitespace replaced by a space. An
+    /// indented comment also ends the line, and one that spans lines ends as many.
+    fn comment(&mut self, text: &[u8]) {
+        if self.options.indent.is_none() {
+            let text = tidy(text);
+            if !text.is_empty() {
+                if !self.line.is_empty() {
+                    self.line.push(b' ');
+                }
+                self.line.extend_from_slice(&text);
+                self.line.push(b' ');
+            }
+            return;
+        }
+
+        for text in text.split(|&byte| byte == b'\n').map(tidy) {
+            if !text.is_empty() {
+                if !self.line.is_empty() {
+                    self.line.push(b' ');
+                }
+                self.line.extend_from_slice(&text);
+                self.end_line();
+            }
+        }
+    }
+
+    fn indentation(&self) -> usize {
+        self.options.indent.unwrap_or(0) * self.depth
+    }
+
+    fn end_line(&mut self) {
+        let line = self.line.trim_ascii_end();
+        if !line.is_empty() {
+            self.result
+                .extend(std::iter::repeat_n(b' ', self.indentation()));
+            self.result.extend_from_slice(line);
+            self.result.push(b'\n');
+        }
+        self.line.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn indents_loops() {
+        let result = format(b"++[>+ add\n<-]>.", &FormatOptions::default()).unwrap();
+        assert_eq!(result, b"++\n[\n  >+<-\n]\n>.\n");
+    }
+
+    #[test]
+    fn keeps_comments() {
+        let options = FormatOptions {
+            comments: true,
+            ..FormatOptions::default()
+        };
+        let result = format(b"+++  three\n  in all\n[-  clear ]", &options).unwrap();
+        assert_eq!(result, b"+++ three\nin all\n[\n  - clear\n]\n");
+    }
+
+    #[test]
+    fn wraps_long_lines() {
+        let options = FormatOptions {
+            width: 6,
+            ..FormatOptions::default()
+        };
+        let result = format(b"[++++++]", &options).unwrap();
+        assert_eq!(result, b"[\n  ++++\n  ++\n]\n");
+    }
+
+    #[test]
+    fn minifies() {
+        let result = format(b"+ one\n[ loop -]\n", &FormatOptions::minify()).unwrap();
+        assert_eq!(result, b"+[-]\n");
+
+        let options = FormatOptions {
+            comments: true,
+            ..FormatOptions::minify()
+        };
+        let result = format(b"+ one\n[ loop -]\n", &options).unwrap();
+        assert_eq!(result, b"+ one [ loop -]\n");
+    }
+
+    #[test]
+    fn formatted_programs_run_the_same() {
+        for options in [FormatOptions::default(), FormatOptions::minify()] {
+            let src = format(FACTOR_SRC, &options).unwrap();
+            let program = crate::ast::parse_program(&src).unwrap();
+            assert_interpret(&*program, b"100\n", b"100: 2 2 5 5\n");
+        }
+    }
+
+    #[test]
+    fn unmatched_brackets_are_errors() {
+        let options = FormatOptions::default();
+        assert_eq!(format(b"[[]", &options), Err(Error::UnmatchedBegin));
+        assert_eq!(format(b"[]]", &options), Err(Error::UnmatchedEnd));
+        assert_eq!(
+            format(b"]", &FormatOptions::minify()),
+            Err(Error::UnmatchedEnd)
+        );
+    }
+
+    #[test]
+    fn tape_switches_are_commands_only_if_enabled() {
+        let mut options = FormatOptions::minify();
+        assert_eq!(format(b"+@+", &options).unwrap(), b"++\n");
+        options.multi_tape = true;
+        assert_eq!(format(b"+@+", &options).unwrap(), b"+@+\n");
+    }
+}
@@ -0,0 +1,156 @@
+use super::*;
+use crate::bytecode;
+use crate::bytecode::usize_to_count;
+use crate::instrument;
+use crate::traits::{Compile, CompileTo, IntoUsize, Stage};
+
+/// Program forms that can be compiled to fused bytecode.
+pub trait FusedCompilable {
+    /// Compile the given program into bytecode to prepare for fusing.
+    fn with_bytecode<F, R>(&self, k: F) -> R
+    where
+        F: FnOnce(&bytecode::Program) -> R;
+
+    /// Compile the given program to fused bytecode.
+    fn fused_compile(&self) -> Box<Program> {
+        self.with_bytecode(compile)
+    }
+}
+
+/// Fuses pairs of bytecode instructions into superinstructions.
+pub fn compile(src: &bytecode::Program) -> Box<Program> {
+    instrument::pass("fused", src, fuse)
+}
+
+fn fuse(src: &bytecode::Program) -> Box<Program> {
+    use common::Instruction as Src;
+    use self::Instruction as Obj;
+
+    let mut instructions = Vec::with_capacity(src.len());
+    // For each source address, the address of the fused instruction that contains it.
+    let mut addresses = Vec::with_capacity(src.len());
+
+    let mut pc = 0;
+    while pc < src.len() {
+        let next = src.get(pc + 1);
+
+        let fused = match (&src[pc], next) {
+            (&Src::Add(amount), Some(&Src::Right(count))) => Some(Obj::AddRight(amount, count)),
+            (&Src::Add(amount), Some(&Src::Left(count))) => Some(Obj::AddLeft(amount, count)),
+            (&Src::Right(count), Some(&Src::Add(amount))) => Some(Obj::RightAdd(count, amount)),
+            (&Src::Left(count), Some(&Src::Add(amount))) => Some(Obj::LeftAdd(count, amount)),
+            (&Src::SetZero, Some(&Src::Right(count))) => Some(Obj::SetZeroRight(count)),
+            (&Src::SetZero, Some(&Src::Left(count))) => Some(Obj::SetZeroLeft(count)),
+            (&Src::Add(amount), Some(&Src::JumpNotZero(begin))) => {
+                Some(Obj::AddJumpNotZero(amount, begin))
+            }
+            (&Src::Right(count), Some(&Src::JumpNotZero(begin))) => {
+                Some(Obj::RightJumpNotZero(count, begin))
+            }
+            (&Src::Left(count), Some(&Src::JumpNotZero(begin))) => {
+                Some(Obj::LeftJumpNotZero(count, begin))
+            }
+            _ => None,
+        };
+
+        addresses.push(instructions.len());
+
+        if let Some(instruction) = fused {
+            addresses.push(instructions.len());
+            instructions.push(instruction);
+            pc += 2;
+        } else {
+            instructions.push(Obj::Op(src[pc].clone()));
+            pc += 1;
+        }
+    }
+
+    // Jump targets are still source addresses, so translate them.
+    let relocate = |address: Count| usize_to_count(addresses[address.into_usize()]);
+
+    for instruction in &mut instructions {
+        match instruction {
+            Obj::Op(Src::JumpZero(target))
+            | Obj::Op(Src::JumpNotZero(target))
+            | Obj::AddJumpNotZero(_, target)
+            | Obj::RightJumpNotZero(_, target)
+            | Obj::LeftJumpNotZero(_, target) => *target = relocate(*target),
+            _ => (),
+        }
+    }
+
+    instructions.into_boxed_slice()
+}
+
+impl FusedCompilable for bytecode::Program {
+    fn with_bytecode<F, R>(&self, k: F) -> R
+    where
+        F: FnOnce(&bytecode::Program) -> R,
+    {
+        k(self)
+    }
+}
+
+impl<T: bytecode::BytecodeCompilable + ?Sized> FusedCompilable for T {
+    fn with_bytecode<F, R>(&self, k: F) -> R
+    where
+        F: FnOnce(&bytecode::Program) -> R,
+    {
+        k(&self.bytecode_compile())
+    }
+}
+
+impl Stage for Program {
+    type Compiled = Box<Program>;
+}
+
+impl Compile for Program {}
+
+impl<T: FusedCompilable + ?Sized> CompileTo<Program> for T {
+    fn compile_to(&self) -> Box<Program> {
+        self.fused_compile()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Instruction::*;
+    use super::*;
+    use common::Instruction as Src;
+
+    #[test]
+    fn add_right_fuses() {
+        assert_compile(&[Src::Add(3), Src::Right(2)], &[AddRight(3, 2)]);
+    }
+
+    #[test]
+    fn unfusable_instructions_pass_through() {
+        assert_compile(&[Src::In, Src::Out], &[Op(Src::In), Op(Src::Out)]);
+    }
+
+    #[test]
+    fn jumps_are_relocated() {
+        assert_compile(
+            &[
+                Src::Add(1),
+                Src::Right(1),
+                Src::JumpZero(5),
+                Src::Out,
+                Src::Add(255),
+                Src::JumpNotZero(2),
+                Src::Out,
+            ],
+            &[
+                AddRight(1, 1),
+                Op(Src::JumpZero(3)),
+                Op(Src::Out),
+                AddJumpNotZero(255, 1),
+                Op(Src::Out),
+            ],
+        );
+    }
+
+    fn assert_compile(src: &[common::Instruction], expected: &[Instruction]) {
+        assert_eq!(&*compile(src), expected);
+    }
+}
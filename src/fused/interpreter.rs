@@ -0,0 +1,194 @@
+use std::io::{Read, Write};
+
+use super::*;
+use crate::state::State;
+use crate::traits::{Interpretable, IntoUsize};
+use common::BfResult;
+
+impl Interpretable for Program {
+    fn interpret_in<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        mut input: R,
+        mut output: W,
+    ) -> BfResult<()> {
+        interpret(self, state, &mut input, &mut output)
+    }
+}
+
+fn interpret<R, W>(
+    instructions: &Program,
+    state: &mut State,
+    input: &mut R,
+    output: &mut W,
+) -> BfResult<()>
+where
+    R: Read,
+    W: Write,
+{
+    use super::Instruction::*;
+    use common::Instruction::*;
+
+    let mut pc = 0;
+
+    while pc < instructions.len() {
+        match instructions[pc] {
+            Op(Left(count)) => state.left(count)?,
+            Op(Right(count)) => state.right(count)?,
+            Op(Add(count)) => state.add(count)?,
+            Op(In) => state.read(input),
+            Op(Out) => state.write(output),
+
+            Op(WriteStr(ref bytes)) => {
+                let _ = output.write_all(bytes);
+            }
+
+            Op(JumpZero(address)) => {
+                if state.load() == 0 {
+                    pc = address.into_usize();
+                }
+            }
+
+            Op(JumpNotZero(address)) => {
+                if state.load() != 0 {
+                    pc = address.into_usize();
+                }
+            }
+
+            Op(SetZero) => state.store(0),
+
+            Op(SetConst(value)) => state.store(value),
+
+            Op(OffsetAddRight(offset)) => {
+                if state.load() != 0 {
+                    let value = state.load();
+                    state.store(0);
+                    state.up_pos_offset(offset, value)?;
+                }
+            }
+
+            Op(OffsetAddLeft(offset)) => {
+                if state.load() != 0 {
+                    let value = state.load();
+                    state.store(0);
+                    state.up_neg_offset(offset, value)?;
+                }
+            }
+
+            Op(FindZeroRight(offset)) => state.find_zero_right(offset)?,
+
+            Op(FindZeroLeft(offset)) => state.find_zero_left(offset)?,
+
+            Op(MulAddRight(offset, factor)) => {
+                let value = state.load();
+                if value != 0 {
+                    state.up_pos_offset(offset, value.wrapping_mul(factor))?;
+                }
+            }
+
+            Op(MulAddLeft(offset, factor)) => {
+                let value = state.load();
+                if value != 0 {
+                    state.up_neg_offset(offset, value.wrapping_mul(factor))?;
+                }
+            }
+
+            Op(AddAt(offset, value)) => state.up_at(offset, value)?,
+
+            Op(SetAt(offset, value)) => state.store_at(offset, value)?,
+
+            Op(SwitchTape(count)) => state.switch_tape(count),
+
+            AddRight(amount, count) => {
+                state.add(amount)?;
+                state.right(count)?;
+            }
+
+            AddLeft(amount, count) => {
+                state.add(amount)?;
+                state.left(count)?;
+            }
+
+            RightAdd(count, amount) => {
+                state.right(count)?;
+                state.add(amount)?;
+            }
+
+            LeftAdd(count, amount) => {
+                state.left(count)?;
+                state.add(amount)?;
+            }
+
+            SetZeroRight(count) => {
+                state.store(0);
+                state.right(count)?;
+            }
+
+            SetZeroLeft(count) => {
+                state.store(0);
+                state.left(count)?;
+            }
+
+            AddJumpNotZero(amount, address) => {
+                state.add(amount)?;
+                if state.load() != 0 {
+                    pc = address.into_usize();
+                }
+            }
+
+            RightJumpNotZero(count, address) => {
+                state.right(count)?;
+                if state.load() != 0 {
+                    pc = address.into_usize();
+                }
+            }
+
+            LeftJumpNotZero(count, address) => {
+                state.left(count)?;
+                if state.load() != 0 {
+                    pc = address.into_usize();
+                }
+            }
+        }
+
+        pc += 1;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_helpers::*;
+
+    #[test]
+    fn hello_world() {
+        assert_parse_interpret(HELLO_WORLD_SRC, "", "Hello, World!");
+    }
+
+    #[test]
+    fn factoring() {
+        assert_parse_interpret(FACTOR_SRC, "2\n", "2: 2\n");
+        assert_parse_interpret(FACTOR_SRC, "3\n", "3: 3\n");
+        assert_parse_interpret(FACTOR_SRC, "6\n", "6: 2 3\n");
+        assert_parse_interpret(FACTOR_SRC, "100\n", "100: 2 2 5 5\n");
+    }
+
+    #[test]
+    fn multi_tape() {
+        let program = crate::rle::compile(&parse_multi_tape(MULTI_TAPE_SRC));
+        let program = crate::peephole::compile(&program);
+        let program = crate::bytecode::compile(&program);
+        let program = crate::fused::compile(&program);
+        assert_interpret_tapes(&*program, 2, b"", b"ABB");
+    }
+
+    fn assert_parse_interpret(program: &[u8], input: &str, output: &str) {
+        let program = crate::ast::parse_program(program).unwrap();
+        let program = crate::rle::compile(&program);
+        let program = crate::peephole::compile(&program);
+        let program = crate::bytecode::compile(&program);
+        let program = crate::fused::compile(&program);
+        assert_interpret(&*program, input.as_bytes(), output.as_bytes());
+    }
+}
@@ -0,0 +1,46 @@
+//! Bytecode with superinstructions.
+//!
+//! This pass takes [flat bytecode](../bytecode/index.html) and fuses common pairs of
+//! instructions, such as an `Add` followed by a move, into single superinstructions. This cuts
+//! the number of times the interpreter loop has to dispatch, which is most of the cost of
+//! interpreting bytecode. In `bfi`, pass the `--fused` flag to interpret the fused bytecode.
+//!
+//! Jump addresses refer to the fused program, so a loop that ends with a fused
+//! [`AddJumpNotZero`](enum.Instruction.html#variant.AddJumpNotZero) (for example) is the target
+//! of the matching `JumpZero`.
+
+use crate::common::{self, Count};
+
+mod compiler;
+mod interpreter;
+
+pub use self::compiler::{compile, FusedCompilable};
+
+/// A program is a sequence of possibly-fused instructions.
+pub type Program = [Instruction];
+
+/// Bytecode instructions, including superinstructions.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Instruction {
+    /// An ordinary bytecode instruction.
+    Op(common::Instruction),
+    /// `Add` then `Right`.
+    AddRight(u8, Count),
+    /// `Add` then `Left`.
+    AddLeft(u8, Count),
+    /// `Right` then `Add`.
+    RightAdd(Count, u8),
+    /// `Left` then `Add`.
+    LeftAdd(Count, u8),
+    /// `SetZero` then `Right`.
+    SetZeroRight(Count),
+    /// `SetZero` then `Left`.
+    SetZeroLeft(Count),
+    /// `Add` then `JumpNotZero`; the `Count` is the address of the matching `JumpZero`.
+    AddJumpNotZero(u8, Count),
+    /// `Right` then `JumpNotZero`; the second `Count` is the address of the matching `JumpZero`.
+    RightJumpNotZero(Count, Count),
+    /// `Left` then `JumpNotZero`; the second `Count` is the address of the matching `JumpZero`.
+    LeftJumpNotZero(Count, Count),
+}
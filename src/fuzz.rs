@@ -0,0 +1,98 @@
+//! Entry points for fuzzing.
+//!
+//! These functions panic when they find a bug, and are meant to be called from fuzz targets such
+//! as the `cargo-fuzz` harness in the `fuzz/` directory of the repository:
+//!
+//! ```text
+//! cargo +nightly fuzz run parse
+//! cargo +nightly fuzz run pipeline
+//! ```
+
+use crate::ast;
+use crate::bytecode;
+use crate::conformance;
+use crate::peephole;
+use crate::test_helpers::halts_within;
+use crate::traits::*;
+
+/// The memory size that `fuzz_pipeline` runs programs with.
+///
+/// This is small so that fuzzed programs reach the edges of memory often.
+pub const MEMORY_SIZE: usize = 256;
+
+/// The number of commands that `fuzz_pipeline` lets a program run before giving up on it.
+pub const FUEL: usize = 100_000;
+
+/// Parses arbitrary bytes and, if they are a valid program, compiles them through every pass,
+/// checking the invariants of each intermediate form.
+///
+/// # Panics
+///
+/// Panics if any pass panics or produces an invalid program.
+pub fn fuzz_parse(src: &[u8]) {
+    let program = match ast::parse_program(src) {
+        Ok(program) => program,
+        Err(_) => return,
+    };
+
+    let peephole = program.peephole_compile();
+    if let Err(message) = peephole::verify(&peephole) {
+        panic!("invalid peephole program: {}", message);
+    }
+
+    let bytecode = bytecode::compile(&peephole);
+    if let Err(message) = bytecode::verify(&bytecode) {
+        panic!("invalid bytecode program: {}", message);
+    }
+
+    // These panic on bad input.
+    bytecode::packed::compile(&bytecode).decode();
+    bytecode::threaded::compile(&bytecode);
+    bytecode.fused_compile();
+}
+
+/// Parses arbitrary bytes and, if they are a valid program that halts within
+/// [`FUEL`](constant.FUEL.html) commands, runs it on every backend with the given input.
+///
+/// # Panics
+///
+/// Panics if the backends disagree.
+pub fn fuzz_pipeline(src: &[u8], input: &[u8]) {
+    fuzz_parse(src);
+
+    let program = match ast::parse_program(src) {
+        Ok(program) => program,
+        Err(_) => return,
+    };
+
+    if !halts_within(&program, MEMORY_SIZE, input, FUEL) {
+        return;
+    }
+
+    if let Err(mismatch) = conformance::check(&program, Some(MEMORY_SIZE), input) {
+        panic!("{}", mismatch);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn accepts_invalid_programs() {
+        fuzz_parse(b"[[]");
+        fuzz_pipeline(b"]", b"");
+    }
+
+    #[test]
+    fn skips_programs_that_do_not_halt() {
+        fuzz_pipeline(b"+[]", b"");
+    }
+
+    #[test]
+    fn runs_valid_programs() {
+        fuzz_pipeline(FACTOR_SRC, b"12\n");
+        fuzz_pipeline(b"+[<+]", b"");
+    }
+}
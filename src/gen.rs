@@ -0,0 +1,174 @@
+//! Generating Brainfuck programs that print given text.
+//!
+//! [`print_text`](fn.print_text.html) writes a program that prints a byte string and stops. It
+//! starts with a loop that multiplies a counter into a few cells, one for each cluster of byte
+//! values in the text, and then prints each byte from whichever cell is cheapest to move to and
+//! adjust, leaving the cell at that byte for the next one. It tries several counters and numbers
+//! of cells, and keeps the shortest program. The result is plain Brainfuck, with no comments,
+//! that only uses cells to the right of the start, and it doesn't depend on the cell size or on
+//! wrapping.
+//!
+//! `gen` is a reserved word from the 2024 edition on, so the module is named `r#gen` in paths.
+//!
+//! In `bfi`, run `bfi gen --text TEXT` to print a program.
+
+/// The most cells that the multiplication loop sets up.
+const MAX_CELLS: usize = 6;
+
+/// The range of counters that the multiplication loop tries.
+const COUNTERS: std::ops::RangeInclusive<u8> = 2..=16;
+
+/// Writes a short Brainfuck program that prints `text`.
+///
+/// # Example
+///
+/// ```
+/// use bf::r#gen::print_text;
+///
+/// assert_eq!(print_text(b"AB"), "++++++++[>++++++++<-]>+.+.");
+/// ```
+pub fn print_text(text: &[u8]) -> String {
+    let mut best = print_from(text, &[]);
+    let mut values: Vec<u8> = text.to_vec();
+    values.sort_unstable();
+    values.dedup();
+
+    for cells in 1..=values.len().min(MAX_CELLS) {
+        let centers = centers(&values, cells);
+        for counter in COUNTERS {
+            let setup = multiply(counter, &centers);
+            if setup.len() >= best.len() {
+                continue;
+            }
+            let program = print_from(text, &setup_values(counter, &centers));
+            if setup.len() + program.len() < best.len() {
+                best = setup + &program;
+            }
+        }
+    }
+
+    best
+}
+
+/// The loop that sets cells 1 and up to the multiples of `counter` nearest `centers`, leaving
+/// the pointer at cell 0, which is zero.
+fn multiply(counter: u8, centers: &[u8]) -> String {
+    let mut result = "+".repeat(counter.into());
+    result.push('[');
+    for &center in centers {
+        result.push('>');
+        result.push_str(&"+".repeat(multiple(counter, center).into()));
+    }
+    result.push_str(&"<".repeat(centers.len()));
+    result.push_str("-]");
+    result
+}
+
+/// The values of cells 0 and up after [`multiply`](fn.multiply.html).
+fn setup_values(counter: u8, centers: &[u8]) -> Vec<u8> {
+    let mut result = vec![0];
+    result.extend(
+        centers
+            .iter()
+            .map(|&center| (multiple(counter, center) as u16 * counter as u16) as u8),
+    );
+    result
+}
+
+/// The number of times to add for the multiple of `counter` nearest `center`, without going
+/// past 255.
+fn multiple(counter: u8, center: u8) -> u8 {
+    let times = (center as u16 + counter as u16 / 2) / counter as u16;
+    times.min(255 / counter as u16) as u8
+}
+
+/// The median of each of `cells` runs of the sorted, distinct `values`.
+fn centers(values: &[u8], cells: usize) -> Vec<u8> {
+    (0..cells)
+        .map(|cell| {
+            let start = cell * values.len() / cells;
+            let end = (cell + 1) * values.len() / cells;
+            values[(start + end) / 2]
+        })
+        .collect()
+}
+
+/// The commands that print `text`, starting at cell 0 with the cells holding `cells`, or a
+/// single zero cell if `cells` is empty.
+fn print_from(text: &[u8], cells: &[u8]) -> String {
+    let mut cells = if cells.is_empty() {
+        vec![0]
+    } else {
+        cells.to_vec()
+    };
+    let mut pointer = 0;
+    let mut result = String::new();
+
+    for &byte in text {
+        let cost = |cell: usize, value: u8| cell.abs_diff(pointer) + value.abs_diff(byte) as usize;
+        let target = (0..cells.len())
+            .min_by_key(|&cell| cost(cell, cells[cell]))
+            .expect("there is at least one cell");
+
+        let (step, distance) = if target < pointer {
+            ('<', pointer - target)
+        } else {
+            ('>', target - pointer)
+        };
+        result.extend(std::iter::repeat_n(step, distance));
+        let (step, distance) = if byte < cells[target] {
+            ('-', cells[target] - byte)
+        } else {
+            ('+', byte - cells[target])
+        };
+        result.extend(std::iter::repeat_n(step, distance.into()));
+        result.push('.');
+
+        pointer = target;
+        cells[target] = byte;
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast;
+    use crate::test_helpers::*;
+    use crate::traits::{Interpretable, PeepholeCompilable};
+    use quickcheck::quickcheck;
+
+    fn output(src: &[u8]) -> Vec<u8> {
+        let program = ast::parse_program(src).unwrap();
+        program.interpret_memory(None, b"").unwrap()
+    }
+
+    #[test]
+    fn prints_text() {
+        assert_eq!(print_text(b""), "");
+        assert_eq!(print_text(b"\x03\x02"), "+++.-.");
+        for text in [&b"Hello, World!\n"[..], b"\xff\0\x80", b"aaaa"] {
+            assert_eq!(output(print_text(text).as_bytes()), text);
+        }
+    }
+
+    #[test]
+    fn is_short() {
+        let text = output(HELLO_WORLD_SRC);
+        let program = print_text(&text);
+        assert!(program.len() <= HELLO_WORLD_SRC.len(), "{}", program);
+    }
+
+    #[test]
+    fn prints_random_text() {
+        fn prop(text: Vec<u8>) -> bool {
+            let program = ast::parse_program(print_text(&text).as_bytes()).unwrap();
+            let optimized = program.peephole_compile();
+            program.interpret_memory(None, b"").unwrap() == text
+                && optimized.interpret_memory(None, b"").unwrap() == text
+        }
+
+        quickcheck(prop as fn(Vec<u8>) -> bool);
+    }
+}
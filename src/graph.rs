@@ -0,0 +1,621 @@
+//! Control-flow graphs of programs, for viewing the loop structure.
+//!
+//! Any program form implements [`ToGraph`](trait.ToGraph.html), which splits it into basic
+//! blocks: runs of instructions that always run together, and that end at a loop's test. A
+//! block's edges say where control goes next, and are labeled with the condition when the
+//! block ends with a test. A [`Graph`](struct.Graph.html) prints as
+//! [Graphviz](https://graphviz.org/) DOT or as JSON, and both include its
+//! [statistics](struct.Stats.html).
+//!
+//! In `bfi`, pass `--cfg dot` or `--cfg json` to `bfi compile` to print the graph of the form
+//! chosen by `--emit` instead of the form itself.
+
+use std::fmt::{self, Write};
+
+use crate::common::{self, Count};
+use crate::traits::IntoUsize;
+use crate::{ast, bytecode, fused, peephole, rle};
+
+/// Program forms that have a control-flow graph.
+pub trait ToGraph {
+    /// The control-flow graph of the program.
+    fn to_graph(&self) -> Graph;
+}
+
+/// A control-flow graph, whose entry is the first block.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Graph {
+    /// The basic blocks, in program order.
+    pub blocks: Vec<Block>,
+}
+
+/// A run of instructions with a single entry and a single exit.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Block {
+    /// The instructions, each printed in the form's usual notation, such as `+` for the AST.
+    pub instructions: Vec<String>,
+    /// The number of loops that the block is inside.
+    pub depth: usize,
+    /// Where control can go after the block.
+    pub edges: Vec<Edge>,
+}
+
+/// A way out of a block.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Edge {
+    pub target: Target,
+    /// The condition on the byte at the pointer, if the block ends with a test.
+    pub condition: Option<Condition>,
+}
+
+/// Where an edge goes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Target {
+    /// The block with the given index.
+    Block(usize),
+    /// The end of the program.
+    Exit,
+}
+
+/// The condition for taking an edge out of a test.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Condition {
+    Zero,
+    NonZero,
+}
+
+impl Condition {
+    fn name(self) -> &'static str {
+        match self {
+            Condition::Zero => "zero",
+            Condition::NonZero => "nonzero",
+        }
+    }
+}
+
+/// Counts that summarize a graph.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Stats {
+    pub blocks: usize,
+    pub edges: usize,
+    pub instructions: usize,
+    /// The number of instructions in the largest block.
+    pub largest_block: usize,
+    pub loops: usize,
+    /// The deepest that loops nest.
+    pub depth: usize,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} blocks, {} edges, {} instructions, largest block {}, {} loops, depth {}",
+            self.blocks, self.edges, self.instructions, self.largest_block, self.loops, self.depth
+        )
+    }
+}
+
+impl Graph {
+    /// Counts the parts of the graph.
+    pub fn stats(&self) -> Stats {
+        // Each loop has one edge back to its start.
+        let back_edges = |(index, block): (usize, &Block)| {
+            block
+                .edges
+                .iter()
+                .filter(|edge| matches!(edge.target, Target::Block(target) if target <= index))
+                .count()
+        };
+
+        Stats {
+            blocks: self.blocks.len(),
+            edges: self.blocks.iter().map(|block| block.edges.len()).sum(),
+            instructions: self
+                .blocks
+                .iter()
+                .map(|block| block.instructions.len())
+                .sum(),
+            largest_block: self
+                .blocks
+                .iter()
+                .map(|block| block.instructions.len())
+                .max()
+                .unwrap_or(0),
+            loops: self.blocks.iter().enumerate().map(back_edges).sum(),
+            depth: self
+                .blocks
+                .iter()
+                .map(|block| block.depth)
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
+    /// The graph in Graphviz DOT, with the statistics as its label.
+    pub fn to_dot(&self) -> String {
+        let mut result = String::new();
+        let _ = writeln!(result, "digraph cfg {{");
+        let _ = writeln!(result, "    label=\"{}\";", self.stats());
+        let _ = writeln!(result, "    node [shape=box, fontname=monospace];");
+        let _ = writeln!(result, "    entry [shape=point];");
+        let _ = writeln!(result, "    exit [shape=point];");
+
+        for (index, block) in self.blocks.iter().enumerate() {
+            let mut label = String::new();
+            for instruction in &block.instructions {
+                label.push_str(&escape_dot(instruction));
+                label.push_str("\\l");
+            }
+            let _ = writeln!(result, "    b{} [label=\"{}\"];", index, label);
+        }
+
+        let _ = writeln!(result, "    entry -> {};", dot_node(self.entry()));
+        for (index, block) in self.blocks.iter().enumerate() {
+            for edge in &block.edges {
+                let _ = write!(result, "    b{} -> {}", index, dot_node(edge.target));
+                if let Some(condition) = edge.condition {
+                    let _ = write!(result, " [label=\"{}\"]", condition.name());
+                }
+                let _ = writeln!(result, ";");
+            }
+        }
+
+        result.push_str("}\n");
+        result
+    }
+
+    /// The graph as a JSON object, with the blocks and the statistics.
+    pub fn to_json(&self) -> String {
+        let mut result = String::from("{\"blocks\":[");
+
+        for (index, block) in self.blocks.iter().enumerate() {
+            if index > 0 {
+                result.push(',');
+            }
+            let _ = write!(
+                result,
+                "{{\"id\":{},\"depth\":{},\"instructions\":[",
+                index, block.depth
+            );
+            for (i, instruction) in block.instructions.iter().enumerate() {
+                if i > 0 {
+                    result.push(',');
+                }
+                write_json_string(&mut result, instruction);
+            }
+            result.push_str("],\"edges\":[");
+            for (i, edge) in block.edges.iter().enumerate() {
+                if i > 0 {
+                    result.push(',');
+                }
+                match edge.target {
+                    Target::Block(target) => {
+                        let _ = write!(result, "{{\"target\":{}", target);
+                    }
+                    Target::Exit => result.push_str("{\"target\":\"exit\""),
+                }
+                if let Some(condition) = edge.condition {
+                    let _ = write!(result, ",\"condition\":\"{}\"", condition.name());
+                }
+                result.push('}');
+            }
+            result.push_str("]}");
+        }
+
+        let stats = self.stats();
+        let _ = write!(
+            result,
+            "],\"stats\":{{\"blocks\":{},\"edges\":{},\"instructions\":{},\"largest_block\":{},\
+             \"loops\":{},\"depth\":{}}}}}",
+            stats.blocks,
+            stats.edges,
+            stats.instructions,
+            stats.largest_block,
+            stats.loops,
+            stats.depth
+        );
+        result
+    }
+
+    fn entry(&self) -> Target {
+        if self.blocks.is_empty() {
+            Target::Exit
+        } else {
+            Target::Block(0)
+        }
+    }
+}
+
+fn dot_node(target: Target) -> String {
+    match target {
+        Target::Block(index) => format!("b{}", index),
+        Target::Exit => "exit".to_owned(),
+    }
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Writes `text` as a JSON string.
+pub fn write_json_string(out: &mut String, text: &str) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// An instruction of a flattened program.
+struct Op {
+    label: String,
+    depth: usize,
+    jump: Option<Jump>,
+}
+
+/// A test, with the address of the matching test.
+#[derive(Clone, Copy)]
+enum Jump {
+    /// Skips past the matching test if the byte is zero.
+    Zero(usize),
+    /// Goes back past the matching test if the byte isn't zero.
+    NonZero(usize),
+}
+
+/// Flattens the loops of a structured program into tests.
+#[derive(Default)]
+struct Flattener {
+    ops: Vec<Op>,
+    /// The addresses of the tests that start the open loops.
+    open: Vec<usize>,
+}
+
+impl Flattener {
+    fn instruction(&mut self, label: String) {
+        let depth = self.open.len();
+        self.ops.push(Op {
+            label,
+            depth,
+            jump: None,
+        });
+    }
+
+    fn begin(&mut self) {
+        let depth = self.open.len();
+        self.open.push(self.ops.len());
+        self.ops.push(Op {
+            label: "[".to_owned(),
+            depth,
+            jump: None,
+        });
+    }
+
+    fn end(&mut self) {
+        let begin = self.open.pop().expect("end without begin");
+        let end = self.ops.len();
+        self.ops[begin].jump = Some(Jump::Zero(end));
+        self.ops.push(Op {
+            label: "]".to_owned(),
+            depth: self.open.len() + 1,
+            jump: Some(Jump::NonZero(begin)),
+        });
+    }
+}
+
+/// Splits a flattened program into basic blocks.
+fn build(ops: &[Op]) -> Graph {
+    // Whether a block starts at each address, where the block at the end is the exit.
+    let mut leader = vec![false; ops.len() + 1];
+    leader[0] = true;
+    leader[ops.len()] = true;
+    for (pc, op) in ops.iter().enumerate() {
+        if let Some(Jump::Zero(target) | Jump::NonZero(target)) = op.jump {
+            leader[target + 1] = true;
+            leader[pc + 1] = true;
+        }
+    }
+
+    // The block that starts at each leader.
+    let mut block_at = vec![Target::Exit; ops.len() + 1];
+    let mut count = 0;
+    for pc in 0..ops.len() {
+        if leader[pc] {
+            block_at[pc] = Target::Block(count);
+            count += 1;
+        }
+    }
+
+    let mut blocks = Vec::with_capacity(count);
+    let mut start = 0;
+    for pc in 0..ops.len() {
+        if !leader[pc + 1] {
+            continue;
+        }
+
+        let next = block_at[pc + 1];
+        let edges = match ops[pc].jump {
+            Some(Jump::Zero(end)) => vec![
+                edge(next, Condition::NonZero),
+                edge(block_at[end + 1], Condition::Zero),
+            ],
+            Some(Jump::NonZero(begin)) => vec![
+                edge(block_at[begin + 1], Condition::NonZero),
+                edge(next, Condition::Zero),
+            ],
+            None => vec![Edge {
+                target: next,
+                condition: None,
+            }],
+        };
+
+        blocks.push(Block {
+            instructions: ops[start..=pc].iter().map(|op| op.label.clone()).collect(),
+            depth: ops[start].depth,
+            edges,
+        });
+        start = pc + 1;
+    }
+
+    Graph { blocks }
+}
+
+fn edge(target: Target, condition: Condition) -> Edge {
+    Edge {
+        target,
+        condition: Some(condition),
+    }
+}
+
+impl ToGraph for ast::Program {
+    fn to_graph(&self) -> Graph {
+        fn flatten(program: &ast::Program, flattener: &mut Flattener) {
+            for statement in program {
+                match *statement {
+                    ast::Statement::Cmd(command) => {
+                        flattener.instruction((command.symbol() as char).to_string())
+                    }
+                    ast::Statement::Loop(ref body) => {
+                        flattener.begin();
+                        flatten(body, flattener);
+                        flattener.end();
+                    }
+                }
+            }
+        }
+
+        let mut flattener = Flattener::default();
+        flatten(self, &mut flattener);
+        build(&flattener.ops)
+    }
+}
+
+impl ToGraph for rle::Program {
+    fn to_graph(&self) -> Graph {
+        fn flatten(program: &rle::Program, flattener: &mut Flattener) {
+            for statement in program {
+                match *statement {
+                    rle::Statement::Cmd(command, count) => {
+                        let symbol = command.symbol() as char;
+                        flattener.instruction(format!("{}{}", symbol, count))
+                    }
+                    rle::Statement::Loop(ref body) => {
+                        flattener.begin();
+                        flatten(body, flattener);
+                        flattener.end();
+                    }
+                }
+            }
+        }
+
+        let mut flattener = Flattener::default();
+        flatten(self, &mut flattener);
+        build(&flattener.ops)
+    }
+}
+
+impl ToGraph for peephole::Program {
+    fn to_graph(&self) -> Graph {
+        fn flatten(program: &peephole::Program, flattener: &mut Flattener) {
+            for statement in program {
+                match *statement {
+                    peephole::Statement::Instr(ref instruction) => {
+                        flattener.instruction(format!("{:?}", instruction))
+                    }
+                    peephole::Statement::Loop(ref body) => {
+                        flattener.begin();
+                        flatten(body, flattener);
+                        flattener.end();
+                    }
+                }
+            }
+        }
+
+        let mut flattener = Flattener::default();
+        flatten(self, &mut flattener);
+        build(&flattener.ops)
+    }
+}
+
+/// Flattens a program whose tests carry their targets, tracking the depth from the tests.
+fn flat_ops<I, F>(program: &[I], jump: F) -> Vec<Op>
+where
+    I: fmt::Debug,
+    F: Fn(&I) -> Option<Jump>,
+{
+    let mut depth: usize = 0;
+    program
+        .iter()
+        .map(|instruction| {
+            let jump = jump(instruction);
+            let op_depth = depth;
+            match jump {
+                Some(Jump::Zero(_)) => depth += 1,
+                Some(Jump::NonZero(_)) => depth = depth.saturating_sub(1),
+                None => (),
+            }
+            Op {
+                label: format!("{:?}", instruction),
+                depth: op_depth,
+                jump,
+            }
+        })
+        .collect()
+}
+
+fn address(count: Count) -> usize {
+    count.into_usize()
+}
+
+impl ToGraph for bytecode::Program {
+    /// # Panics
+    ///
+    /// Panics if the program's jumps don't [verify](../bytecode/fn.verify.html).
+    fn to_graph(&self) -> Graph {
+        assert_eq!(bytecode::verify(self), Ok(()));
+        build(&flat_ops(self, |instruction| match *instruction {
+            common::Instruction::JumpZero(end) => Some(Jump::Zero(address(end))),
+            common::Instruction::JumpNotZero(begin) => Some(Jump::NonZero(address(begin))),
+            _ => None,
+        }))
+    }
+}
+
+impl ToGraph for fused::Program {
+    fn to_graph(&self) -> Graph {
+        use fused::Instruction as Fused;
+
+        build(&flat_ops(self, |instruction| match *instruction {
+            Fused::Op(common::Instruction::JumpZero(end)) => Some(Jump::Zero(address(end))),
+            Fused::Op(common::Instruction::JumpNotZero(begin))
+            | Fused::AddJumpNotZero(_, begin)
+            | Fused::RightJumpNotZero(_, begin)
+            | Fused::LeftJumpNotZero(_, begin) => Some(Jump::NonZero(address(begin))),
+            _ => None,
+        }))
+    }
+}
+
+impl<P: ToGraph + ?Sized> ToGraph for Box<P> {
+    fn to_graph(&self) -> Graph {
+        (**self).to_graph()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+    use crate::traits::*;
+
+    #[test]
+    fn straight_line_is_one_block() {
+        let graph = ast::parse_program(b"+>.").unwrap().to_graph();
+        assert_eq!(graph.blocks.len(), 1);
+        assert_eq!(graph.blocks[0].instructions, ["+", ">", "."]);
+        assert_eq!(
+            graph.blocks[0].edges,
+            [Edge {
+                target: Target::Exit,
+                condition: None
+            }]
+        );
+    }
+
+    #[test]
+    fn loops_have_tests() {
+        let graph = ast::parse_program(b",[.,]+").unwrap().to_graph();
+        let blocks: Vec<_> = graph
+            .blocks
+            .iter()
+            .map(|block| (block.instructions.join(""), block.depth))
+            .collect();
+        assert_eq!(
+            blocks,
+            [
+                (",[".to_owned(), 0),
+                (".,]".to_owned(), 1),
+                ("+".to_owned(), 0)
+            ]
+        );
+        assert_eq!(
+            graph.blocks[0].edges,
+            [
+                edge(Target::Block(1), Condition::NonZero),
+                edge(Target::Block(2), Condition::Zero)
+            ]
+        );
+        assert_eq!(
+            graph.blocks[1].edges,
+            [
+                edge(Target::Block(1), Condition::NonZero),
+                edge(Target::Block(2), Condition::Zero)
+            ]
+        );
+    }
+
+    #[test]
+    fn every_form_has_the_same_shape() {
+        let program = ast::parse_program(FACTOR_SRC).unwrap();
+        let expected = program.to_graph().stats();
+        assert!(expected.loops > 10);
+
+        let shape = |graph: Graph| {
+            let stats = graph.stats();
+            (stats.blocks, stats.edges, stats.loops, stats.depth)
+        };
+        let expected = (
+            expected.blocks,
+            expected.edges,
+            expected.loops,
+            expected.depth,
+        );
+        let peephole = program.peephole_compile();
+        assert_eq!(shape(program.rle_compile().to_graph()), expected);
+
+        // The peephole compiler replaces some loops, but the later forms keep the rest.
+        let expected = shape(peephole.to_graph());
+        assert_eq!(shape(program.bytecode_compile().to_graph()), expected);
+        assert_eq!(shape(program.fused_compile().to_graph()), expected);
+    }
+
+    #[test]
+    fn prints_dot_and_json() {
+        let graph =
+            peephole::compile(&rle::compile(&ast::parse_program(b",[.,]").unwrap())).to_graph();
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph cfg {\n"));
+        assert!(dot.contains("    b0 -> b1 [label=\"nonzero\"];\n"));
+        assert!(dot.contains("    b1 -> exit [label=\"zero\"];\n"));
+
+        let json = graph.to_json();
+        assert!(
+            json.starts_with("{\"blocks\":[{\"id\":0,\"depth\":0,\"instructions\":[\"In\",\"[\"]")
+        );
+        assert!(json.ends_with("\"loops\":1,\"depth\":1}}"));
+
+        let empty = Graph::default();
+        assert!(empty.to_dot().contains("    entry -> exit;\n"));
+        assert_eq!(empty.stats(), Stats::default());
+    }
+
+    #[test]
+    fn escapes_labels() {
+        let graph = Graph {
+            blocks: vec![Block {
+                instructions: vec!["WriteStr([34, 92])\"\\\n".to_owned()],
+                depth: 0,
+                edges: Vec::new(),
+            }],
+        };
+        assert!(graph.to_dot().contains("\\\"\\\\\n"));
+        assert!(graph.to_json().contains("\\\"\\\\\\u000a"));
+    }
+}
@@ -0,0 +1,181 @@
+//! Counting how often each cell of the tape is read and written.
+//!
+//! A [`Heatmap`](struct.Heatmap.html) shows which part of the tape a program actually uses,
+//! which helps to pick a memory size, and where it spends its time, which helps to find a
+//! pointer that runs away. To record one, call
+//! [`State::track_heat`](../state/struct.State.html#method.track_heat) before running the
+//! program under the [debugger](../debugger/index.html); the other backends don't record it,
+//! so that they pay nothing for it. With several tapes, the counts for a cell are for that
+//! address on every tape.
+//!
+//! In `bfi`, pass `--heatmap` to print one after the run.
+
+use std::fmt::Write;
+
+/// The characters for increasing numbers of accesses, from none to the most.
+const RAMP: &[u8] = b" .:-=+*#%@";
+
+/// The most rows that [`render`](struct.Heatmap.html#method.render) draws.
+const MAX_ROWS: usize = 16;
+
+/// The number of reads and writes of each cell.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Heatmap {
+    reads: Vec<u64>,
+    writes: Vec<u64>,
+}
+
+impl Heatmap {
+    /// A heat map with no accesses.
+    pub fn new() -> Self {
+        Heatmap::default()
+    }
+
+    /// The number of times that the cell at the given address was read.
+    pub fn reads(&self, cell: usize) -> u64 {
+        self.reads.get(cell).copied().unwrap_or(0)
+    }
+
+    /// The number of times that the cell at the given address was written.
+    pub fn writes(&self, cell: usize) -> u64 {
+        self.writes.get(cell).copied().unwrap_or(0)
+    }
+
+    /// One past the highest address that was read or written.
+    pub fn len(&self) -> usize {
+        self.reads.len()
+    }
+
+    /// Whether no cell was read or written.
+    pub fn is_empty(&self) -> bool {
+        self.reads.is_empty()
+    }
+
+    #[cfg(feature = "tools")]
+    pub(crate) fn read(&mut self, cell: usize) {
+        self.grow(cell);
+        self.reads[cell] += 1;
+    }
+
+    #[cfg(feature = "tools")]
+    pub(crate) fn write(&mut self, cell: usize) {
+        self.grow(cell);
+        self.writes[cell] += 1;
+    }
+
+    #[cfg(feature = "tools")]
+    fn grow(&mut self, cell: usize) {
+        if cell >= self.reads.len() {
+            self.reads.resize(cell + 1, 0);
+            self.writes.resize(cell + 1, 0);
+        }
+    }
+
+    /// Draws the heat map as text of about the given width.
+    ///
+    /// After a summary line, each row starts with the address of its first cell, and each
+    /// character stands for a group of cells, from a space for none to `@` for the most
+    /// accesses, on a logarithmic scale. Each character is one cell unless that would take more
+    /// than 16 rows.
+    pub fn render(&self, width: usize) -> String {
+        let columns = width.saturating_sub(8).max(8);
+        let per_char = self.len().div_ceil(columns * MAX_ROWS).max(1);
+        let groups: Vec<u64> = (0..self.len())
+            .step_by(per_char)
+            .map(|start| {
+                (start..self.len().min(start + per_char))
+                    .map(|cell| self.reads[cell] + self.writes[cell])
+                    .sum()
+            })
+            .collect();
+        let busiest = groups.iter().copied().max().unwrap_or(0);
+
+        let mut result = format!(
+            "cells 0..{}, {} per character, {} reads, {} writes, at most {} accesses\n",
+            self.len(),
+            per_char,
+            self.reads.iter().sum::<u64>(),
+            self.writes.iter().sum::<u64>(),
+            busiest
+        );
+        for (row, groups) in groups.chunks(columns).enumerate() {
+            let _ = write!(result, "{:>7} ", row * columns * per_char);
+            result.extend(
+                groups
+                    .iter()
+                    .map(|&count| char::from(shade(count, busiest))),
+            );
+            result.push('\n');
+        }
+        result
+    }
+}
+
+/// The character for `count` accesses when the most is `busiest`.
+fn shade(count: u64, busiest: u64) -> u8 {
+    if count == 0 {
+        return RAMP[0];
+    }
+    let top = RAMP.len() - 1;
+    if busiest <= 1 {
+        return RAMP[top];
+    }
+    let level = (count as f64).ln() / (busiest as f64).ln() * (top - 1) as f64;
+    RAMP[1 + level.round() as usize]
+}
+
+#[cfg(all(test, feature = "tools"))]
+mod tests {
+    use super::*;
+    use crate::ast;
+    use crate::debugger::{Debugger, Stop};
+    use crate::state::State;
+    use crate::traits::*;
+
+    fn heatmap(src: &[u8]) -> Heatmap {
+        let program = ast::parse_program(src).unwrap().bytecode_compile();
+        let mut state = State::new();
+        state.track_heat();
+        let mut debugger = Debugger::new(program, state, &b""[..], Vec::new());
+        assert_eq!(debugger.run(), Stop::Halted);
+        debugger.state().heatmap().unwrap().clone()
+    }
+
+    #[test]
+    fn counts_reads_and_writes() {
+        // `Add(3)`, then the loop becomes `OffsetAddRight(2)`, and then `Right(2)`, `Out`.
+        let heatmap = heatmap(b"+++[->>+<<]>>.");
+        assert_eq!(heatmap.len(), 3);
+        assert_eq!((heatmap.reads(0), heatmap.writes(0)), (2, 2));
+        assert_eq!((heatmap.reads(1), heatmap.writes(1)), (0, 0));
+        assert_eq!((heatmap.reads(2), heatmap.writes(2)), (2, 1));
+        assert_eq!(heatmap.reads(3), 0);
+    }
+
+    #[test]
+    fn counts_searches() {
+        // `AddAt`s read cells 2 and 4, and then `FindZeroRight(2)` reads every other cell.
+        let heatmap = heatmap(b"+>>+>>+<<<<[>>]");
+        assert_eq!(heatmap.len(), 7);
+        assert_eq!(
+            (0..7).map(|cell| heatmap.reads(cell)).collect::<Vec<_>>(),
+            [2, 0, 2, 0, 2, 0, 1]
+        );
+    }
+
+    #[test]
+    fn renders_rows() {
+        let mut heatmap = Heatmap::new();
+        heatmap.write(0);
+        for _ in 0..100 {
+            heatmap.read(2);
+        }
+        heatmap.read(20);
+        assert_eq!(
+            heatmap.render(18),
+            "cells 0..21, 1 per character, 101 reads, 1 writes, at most 100 accesses\n      \
+             0 . @       \n     10           \n     20 .\n"
+        );
+        assert!(Heatmap::new().render(80).starts_with("cells 0..0,"));
+    }
+}
@@ -0,0 +1,231 @@
+//! Recording each phase of the pipeline with `tracing`, in the spans and events that the
+//! [crate documentation](../index.html) describes. Without the `tracing` feature, each phase is
+//! just a call.
+
+#[cfg(feature = "tracing")]
+use std::time::Instant;
+
+#[cfg(feature = "tracing")]
+use crate::{ast, bytecode, fused, peephole, rle};
+
+/// The target of the spans and events.
+#[cfg(feature = "tracing")]
+const TARGET: &str = "bf";
+
+/// Runs a compilation pass over `input`.
+#[cfg(feature = "tracing")]
+pub(crate) fn pass<I: Size, O: Size>(name: &str, input: I, run: impl FnOnce(I) -> O) -> O {
+    let _span = tracing::info_span!(target: TARGET, "pass", pass = name).entered();
+    let size_in = input.size();
+    let start = Instant::now();
+    let output = run(input);
+    let micros = start.elapsed().as_micros() as u64;
+    tracing::info!(
+        target: TARGET,
+        pass = name,
+        size_in,
+        size_out = output.size(),
+        micros
+    );
+    output
+}
+
+/// Runs a compilation pass over `input`.
+#[cfg(not(feature = "tracing"))]
+#[inline]
+pub(crate) fn pass<I, O>(_name: &str, input: I, run: impl FnOnce(I) -> O) -> O {
+    run(input)
+}
+
+/// Runs a program on the named backend.
+#[cfg(feature = "tracing")]
+pub(crate) fn run<T, E>(backend: &str, run: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+    let _span = tracing::info_span!(target: TARGET, "run", backend).entered();
+    let start = Instant::now();
+    let result = run();
+    let micros = start.elapsed().as_micros() as u64;
+    tracing::info!(target: TARGET, backend, micros, ok = result.is_ok());
+    result
+}
+
+/// Runs a program on the named backend.
+#[cfg(not(feature = "tracing"))]
+#[inline]
+pub(crate) fn run<T, E>(_backend: &str, run: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+    run()
+}
+
+/// The size of a program, as recorded for a pass.
+#[cfg(feature = "tracing")]
+pub(crate) trait Size {
+    fn size(&self) -> usize;
+}
+
+#[cfg(feature = "tracing")]
+impl<T: Size + ?Sized> Size for &T {
+    fn size(&self) -> usize {
+        (**self).size()
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl<T: Size + ?Sized> Size for Box<T> {
+    fn size(&self) -> usize {
+        (**self).size()
+    }
+}
+
+/// A program that failed to compile has no size.
+#[cfg(feature = "tracing")]
+impl<T: Size, E> Size for Result<T, E> {
+    fn size(&self) -> usize {
+        self.as_ref().map_or(0, Size::size)
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl Size for [u8] {
+    fn size(&self) -> usize {
+        self.len()
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl Size for ast::Program {
+    fn size(&self) -> usize {
+        self.iter()
+            .map(|statement| match *statement {
+                ast::Statement::Cmd(_) => 1,
+                ast::Statement::Loop(ref body) => 2 + body.size(),
+            })
+            .sum()
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl Size for rle::Program {
+    fn size(&self) -> usize {
+        self.iter()
+            .map(|statement| match *statement {
+                rle::Statement::Cmd(..) => 1,
+                rle::Statement::Loop(ref body) => 2 + body.size(),
+            })
+            .sum()
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl Size for peephole::Program {
+    fn size(&self) -> usize {
+        self.iter()
+            .map(|statement| match *statement {
+                peephole::Statement::Instr(_) => 1,
+                peephole::Statement::Loop(ref body) => 2 + body.size(),
+            })
+            .sum()
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl Size for bytecode::Program {
+    fn size(&self) -> usize {
+        self.len()
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl Size for fused::Program {
+    fn size(&self) -> usize {
+        self.len()
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use super::*;
+    use crate::pass::PassManager;
+    use crate::traits::*;
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span;
+
+    /// Collects the name of each pass that ends.
+    #[derive(Clone, Default)]
+    struct Passes(Arc<Mutex<Vec<String>>>);
+
+    impl tracing::Subscriber for Passes {
+        fn enabled(&self, metadata: &tracing::Metadata) -> bool {
+            metadata.target() == TARGET
+        }
+
+        fn new_span(&self, _: &span::Attributes) -> span::Id {
+            span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &span::Id, _: &span::Record) {}
+
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+        fn event(&self, event: &tracing::Event) {
+            event.record(&mut &*self);
+        }
+
+        fn enter(&self, _: &span::Id) {}
+
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    impl Visit for &Passes {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            if field.name() == "pass" {
+                self.0.lock().unwrap().push(value.to_owned());
+            }
+        }
+
+        fn record_debug(&mut self, _: &Field, _: &dyn fmt::Debug) {}
+    }
+
+    fn passes(compile: impl FnOnce()) -> Vec<String> {
+        let passes = Passes::default();
+        tracing::subscriber::with_default(passes.clone(), compile);
+        passes.0.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn records_each_pass() {
+        let names = passes(|| {
+            ast::parse_program(b"+[->+<]>.").unwrap().bytecode_compile();
+        });
+        assert_eq!(
+            names,
+            [
+                "parse",
+                "rle",
+                "peephole",
+                "set-const",
+                "dead-code",
+                "fold-moves",
+                "coalesce-output",
+                "bytecode"
+            ]
+        );
+
+        let manager = PassManager::standard();
+        let names = passes(|| {
+            let program = rle::compile(&ast::parse_program(b"+[-]").unwrap());
+            manager.compile(&program).unwrap();
+        });
+        assert_eq!(names[..4], ["parse", "rle", "lower", "set-zero"]);
+        assert_eq!(names.len(), 3 + manager.names().len());
+    }
+
+    #[test]
+    fn counts_loops_twice() {
+        let program = ast::parse_program(b"+[->+<]").unwrap();
+        assert_eq!(program.size(), 7);
+        assert_eq!(program.rle_compile().size(), 7);
+        assert_eq!(program.peephole_compile().size(), 2);
+        assert_eq!(program.bytecode_compile().size(), 2);
+    }
+}
@@ -2,11 +2,15 @@ use dynasm::dynasm;
 use dynasmrt::x64::Assembler;
 use dynasmrt::{DynasmApi, DynasmLabelApi};
 
-use super::analysis::{AbstractInterpreter, BoundsAnalysis, NoAnalysis};
 use super::*;
+use crate::analysis::{AbstractInterpreter, BoundsAnalysis, NoAnalysis};
 use crate::common::Count;
+use crate::counters::Kind;
+use crate::instrument;
 use crate::peephole;
-use rts;
+use crate::semantics::Overflow;
+use crate::traits::{CompileTo, Stage};
+use rts::{self, RtsState};
 
 /// Program forms that can be JIT compiled.
 pub trait JitCompilable {
@@ -19,6 +23,16 @@ pub trait JitCompilable {
     fn jit_compile(&self, checked: bool) -> Program {
         self.with_peephole(|ast| compile(ast, checked))
     }
+
+    /// JIT compile the given program, with code to count what it does.
+    fn jit_compile_counting(&self, checked: bool) -> Program {
+        self.with_peephole(|ast| compile_counting(ast, checked))
+    }
+
+    /// JIT compile the given program to run in a sandbox.
+    fn jit_compile_sandboxed(&self) -> Program {
+        self.with_peephole(compile_sandboxed)
+    }
 }
 
 dynasm!(asm
@@ -26,24 +40,101 @@ dynasm!(asm
     ; .alias mem_start, r13
     ; .alias mem_limit, r14
     ; .alias rts, r15
+    ; .alias cell, r11b
 );
 
 /// Compiles peephole-optimized AST to x64 machine code.
 ///
 /// Uses the `dynasmrt` assembler
 pub fn compile(program: &peephole::Program, checked: bool) -> Program {
-    if checked {
-        let mut compiler = Compiler::<AbstractInterpreter>::new(program, true);
-        compiler.compile(program);
-        compiler.into_program()
-    } else {
-        let mut compiler = Compiler::<NoAnalysis>::new(program, false);
-        compiler.compile(program);
-        compiler.into_program()
-    }
+    compile_with(program, checked, Modes::default())
+}
+
+/// Compiles peephole AST to x64 machine code for cells that overflow as `overflow` says, checking
+/// the carry flag after each `Add` when they don't wrap, or the overflow flag if they are
+/// `signed`.
+///
+/// The program should come from [`peephole::compile_for`](../peephole/fn.compile_for.html) with
+/// the same `overflow`, and the compiled program runs only against states with that
+/// [`overflow`](../state/struct.State.html#method.overflow), and, unless cells wrap, the same
+/// [signedness](../state/struct.State.html#method.signed).
+pub fn compile_for(
+    program: &peephole::Program,
+    checked: bool,
+    overflow: Overflow,
+    signed: bool,
+) -> Program {
+    let modes = Modes {
+        overflow,
+        signed,
+        ..Modes::default()
+    };
+    compile_with(program, checked, modes)
+}
+
+/// Compiles peephole-optimized AST to x64 machine code that
+/// [counts](../counters/index.html) what it does.
+///
+/// The counts agree with those of the peephole interpreter.
+pub fn compile_counting(program: &peephole::Program, checked: bool) -> Program {
+    let modes = Modes {
+        counting: true,
+        ..Modes::default()
+    };
+    compile_with(program, checked, modes)
+}
+
+/// Compiles peephole-optimized AST to bounds-checked x64 machine code that can run in a
+/// [sandbox](../sandbox/index.html), by calling the run-time system at the start of each loop
+/// body to check the limits.
+pub fn compile_sandboxed(program: &peephole::Program) -> Program {
+    let modes = Modes {
+        sandboxed: true,
+        ..Modes::default()
+    };
+    compile_with(program, true, modes)
+}
+
+/// Compiles peephole-optimized AST to x64 machine code that reads the time-stamp counter as each
+/// loop starts and ends, for the [profiler](../profile/index.html).
+#[cfg(feature = "tools")]
+pub(crate) fn compile_profiling(program: &peephole::Program, checked: bool) -> Program {
+    let modes = Modes {
+        profiling: true,
+        ..Modes::default()
+    };
+    compile_with(program, checked, modes)
+}
+
+fn compile_with(program: &peephole::Program, checked: bool, modes: Modes) -> Program {
+    instrument::pass("jit", program, |program| {
+        if checked {
+            let mut compiler = Compiler::<AbstractInterpreter>::new(program, true, modes);
+            compiler.compile(program);
+            compiler.into_program()
+        } else {
+            let mut compiler = Compiler::<NoAnalysis>::new(program, false, modes);
+            compiler.compile(program);
+            compiler.into_program()
+        }
+    })
+}
+
+/// The kinds of extra code to emit.
+#[derive(Clone, Copy, Default)]
+struct Modes {
+    counting: bool,
+    sandboxed: bool,
+    profiling: bool,
+    overflow: Overflow,
+    signed: bool,
 }
 
 /// The compiler state.
+///
+/// The value of the cell at the pointer is kept in `cell` across statements that use only that
+/// cell, and stored back to memory before the pointer moves, before calls, and before anything
+/// that can leave the generated code.
 struct Compiler<B: BoundsAnalysis> {
     /// The underlying assembler.
     asm: Assembler,
@@ -51,12 +142,41 @@ struct Compiler<B: BoundsAnalysis> {
     start: dynasmrt::AssemblyOffset,
     /// Whether we are emitting bounds checks.
     checked: bool,
+    /// Whether we are emitting code to update the counters.
+    counting: bool,
+    /// Whether we are emitting calls to check the sandbox's limits.
+    sandboxed: bool,
+    /// Whether we are emitting code to time each loop.
+    profiling: bool,
+    /// What an `Add` does to a cell that would go past its largest or smallest value.
+    overflow: Overflow,
+    /// Whether cells go from -128 to 127, which matters only if they don't wrap.
+    signed: bool,
+    /// The number of loops compiled so far, which is the index of the next one among the loops
+    /// in the order that they start.
+    loops: usize,
     /// Abstract interpreter for bounds checking analysis.
     interpreter: B,
+    /// Whether `cell` holds the value of the cell at the pointer.
+    cached: bool,
+    /// Whether `cell` holds a value that memory doesn't have yet.
+    dirty: bool,
+    /// Whether the flags were set by the last instruction, which changed `cell`, so that the
+    /// zero flag tells whether the cell is zero.
+    flags: bool,
+    /// The loop that the code being emitted belongs to, as the position of each enclosing loop
+    /// among its siblings, counting from 1.
+    loop_path: Vec<usize>,
+    /// The number of loops seen so far in the program and in each loop of `loop_path`.
+    loops_seen: Vec<usize>,
+    /// The offset where each run of code for one loop starts, and the name of the loop.
+    symbols: Vec<(usize, String)>,
+    /// The bytes of each `WriteStr`, which the code points at.
+    strings: Vec<Arc<[u8]>>,
 }
 
 impl<B: BoundsAnalysis> Compiler<B> {
-    fn new(program: &peephole::Program, checked: bool) -> Self {
+    fn new(program: &peephole::Program, checked: bool, modes: Modes) -> Self {
         let asm = Assembler::new().expect("Could not create assembler");
         let start = asm.offset();
 
@@ -64,9 +184,23 @@ impl<B: BoundsAnalysis> Compiler<B> {
             asm: asm,
             start: start,
             checked: checked,
+            counting: modes.counting,
+            sandboxed: modes.sandboxed,
+            profiling: modes.profiling,
+            overflow: modes.overflow,
+            signed: modes.signed,
+            loops: 0,
             interpreter: B::new(program),
+            cached: false,
+            dirty: false,
+            flags: false,
+            loop_path: Vec::new(),
+            loops_seen: vec![0],
+            symbols: Vec::new(),
+            strings: Vec::new(),
         };
 
+        result.start_symbol();
         result.emit_prologue();
 
         result
@@ -75,12 +209,62 @@ impl<B: BoundsAnalysis> Compiler<B> {
     fn into_program(mut self) -> Program {
         self.emit_epilogue();
 
+        let code = self.asm.finalize().unwrap();
+        let ends = self.symbols.iter().skip(1).map(|&(start, _)| start);
+        let symbols = self
+            .symbols
+            .iter()
+            .zip(ends.chain(Some(code.len())))
+            .filter(|&(&(start, _), end)| start < end)
+            .map(|(&(start, ref name), end)| Symbol {
+                start,
+                len: end - start,
+                name: name.clone(),
+            })
+            .collect();
+
         Program {
-            code: self.asm.finalize().unwrap(),
+            registration: None,
+            code,
             start: self.start,
+            counting: self.counting,
+            sandboxed: self.sandboxed,
+            #[cfg(feature = "tools")]
+            profiling: self.profiling,
+            overflow: self.overflow,
+            signed: self.signed,
+            symbols,
+            strings: self.strings,
         }
     }
 
+    /// Starts a run of code for the loop in `loop_path`, or for the program outside any loop.
+    fn start_symbol(&mut self) {
+        let name = if self.loop_path.is_empty() {
+            "bf::main".to_owned()
+        } else {
+            let path: Vec<String> = self.loop_path.iter().map(usize::to_string).collect();
+            format!("bf::loop_{}", path.join("_"))
+        };
+        self.symbols.push((self.asm.offset().0, name));
+    }
+
+    /// Starts the code for the body of the next loop.
+    fn enter_symbol(&mut self) {
+        let seen = self.loops_seen.last_mut().expect("no enclosing loop");
+        *seen += 1;
+        self.loop_path.push(*seen);
+        self.loops_seen.push(0);
+        self.start_symbol();
+    }
+
+    /// Goes back to the code of the enclosing loop, after the end of a loop.
+    fn leave_symbol(&mut self) {
+        self.loop_path.pop();
+        self.loops_seen.pop();
+        self.start_symbol();
+    }
+
     fn emit_prologue(&mut self) {
         dynasm!(self.asm
         ; .alias pointer, r12
@@ -96,15 +280,21 @@ impl<B: BoundsAnalysis> Compiler<B> {
                 ; mov mem_limit, rcx
                 ; add mem_limit, rdx    // second argument
                 ; mov rts, r8           // third argument
+                ; add pointer, QWORD [rts + RtsState::pointer_offset()]
             );
     }
 
     fn emit_epilogue(&mut self) {
+        self.flush_cell();
+
         dynasm!(self.asm
         ; .alias pointer, r12
         ; .alias mem_start, r13
         ; .alias mem_limit, r14
         ; .alias rts, r15
+                ; mov rcx, pointer
+                ; sub rcx, mem_start
+                ; mov QWORD [rts + RtsState::pointer_offset()], rcx
                 ; mov rax, rts::OKAY as i32
                 ; jmp ->finish
 
@@ -114,6 +304,20 @@ impl<B: BoundsAnalysis> Compiler<B> {
 
                 ; ->overflow:
                 ; mov rax, rts::OVERFLOW as i32
+                ; jmp ->finish
+
+                ; ->cell_overflow:
+                ; mov rcx, pointer
+                ; sub rcx, mem_start
+                ; mov QWORD [rts + RtsState::pointer_offset()], rcx
+                ; mov rax, rts::CELL_OVERFLOW as i32
+                ; jmp ->finish
+
+                ; ->limit:
+                ; mov rcx, pointer
+                ; sub rcx, mem_start
+                ; mov QWORD [rts + RtsState::pointer_offset()], rcx
+                ; mov rax, rts::LIMIT as i32
 
                 ; ->finish:
                 ; pop r15
@@ -125,18 +329,98 @@ impl<B: BoundsAnalysis> Compiler<B> {
     }
 
     fn compile(&mut self, program: &[peephole::Statement]) {
-        for stm in program {
+        let mut stms = program.iter().peekable();
+
+        while let Some(stm) = stms.next() {
+            // Fold a store followed by an add into a single store, unless counting needs to see
+            // both instructions or the add can overflow.
+            if !self.counting
+                && self.overflow == Overflow::Wrap
+                && let peephole::Statement::Instr(ref first) = *stm
+                && let Some(peephole::Statement::Instr(second)) = stms.peek()
+                && let Some(folded) = peephole::set_const_peephole(first, second)
+            {
+                self.compile_statement(&peephole::Statement::Instr(folded));
+                stms.next();
+                continue;
+            }
+
+            // Counting needs to see a run stop where it would have.
+            if self.checked && !self.counting {
+                self.merge_bounds_checks(stm, stms.clone());
+            }
+
             self.compile_statement(stm);
         }
     }
 
+    /// If `stm` starts a run of moves in one direction, checks the net distance of the whole run
+    /// before its first move, so that the moves in it need no checks of their own.
+    ///
+    /// The statements in the run write no output, and a program that fails the combined check
+    /// would have failed the same way by the end of the run. Unless cells wrap, only moves are
+    /// merged, because an add between them could fail first, with a cell overflow.
+    fn merge_bounds_checks<'a, I>(&mut self, stm: &peephole::Statement, rest: I)
+    where
+        I: Iterator<Item = &'a peephole::Statement>,
+    {
+        use crate::common::Instruction::*;
+        use peephole::Statement::*;
+
+        let (right, first) = match *stm {
+            Instr(Right(count)) => (true, count),
+            Instr(Left(count)) => (false, count),
+            _ => return,
+        };
+
+        let mut total = first;
+        for stm in rest {
+            match *stm {
+                Instr(Right(count)) if right => total = total.saturating_add(count),
+                Instr(Left(count)) if !right => total = total.saturating_add(count),
+                _ if self.overflow != Overflow::Wrap => break,
+                Instr(Add(_) | SetZero | SetConst(_)) => (),
+                Instr(OffsetAddRight(_) | MulAddRight(..)) if right => (),
+                Instr(OffsetAddLeft(_) | MulAddLeft(..)) if !right => (),
+                Instr(AddAt(offset, _) | SetAt(offset, _))
+                    if offset != 0 && (offset > 0) == right => {}
+                _ => break,
+            }
+        }
+
+        let proved = if right {
+            self.interpreter.check_right(total)
+        } else {
+            self.interpreter.check_left(total)
+        };
+        if total == first || proved {
+            return;
+        }
+
+        self.flush_cell();
+        if right {
+            self.load_pos_offset(total, false);
+            self.interpreter.assume_right(total);
+        } else {
+            self.load_neg_offset(total, false);
+            self.interpreter.assume_left(total);
+        }
+    }
+
     fn compile_statement(&mut self, stm: &peephole::Statement) {
         use crate::common::Instruction::*;
         use peephole::Statement::*;
 
+        self.flags = false;
+
+        if let Instr(ref instruction) = *stm {
+            self.count_instruction(Kind::of(instruction));
+        }
+
         match *stm {
             Instr(Right(count)) => {
                 let proved = self.interpreter.move_right(count);
+                self.spill_cell();
 
                 dynasm!(self.asm
                 ; .alias pointer, r12
@@ -146,10 +430,13 @@ impl<B: BoundsAnalysis> Compiler<B> {
                                 ;; self.load_pos_offset(count, proved)
                                 ; add pointer, rax
                             );
+
+                self.count_peak_pointer();
             }
 
             Instr(Left(count)) => {
                 let proved = self.interpreter.move_left(count);
+                self.spill_cell();
 
                 dynasm!(self.asm
                 ; .alias pointer, r12
@@ -161,51 +448,93 @@ impl<B: BoundsAnalysis> Compiler<B> {
                             );
             }
 
+            Instr(Add(count)) if self.overflow != Overflow::Wrap => self.add_checked(count),
+
             Instr(Add(count)) => {
+                self.load_cell();
+                self.dirty = true;
+
                 dynasm!(self.asm
-                ; .alias pointer, r12
-                ; .alias mem_start, r13
-                ; .alias mem_limit, r14
-                ; .alias rts, r15
-                                ; add [pointer], BYTE count as i8
+                ; .alias cell, r11b
+                                ; add cell, BYTE count as i8
                             );
+
+                self.flags = true;
             }
 
             Instr(In) => {
                 dynasm!(self.asm
-                ; .alias pointer, r12
-                ; .alias mem_start, r13
-                ; .alias mem_limit, r14
-                ; .alias rts, r15
+                ; .alias cell, r11b
+                                ;; self.load_cell()
+                                ;; self.flush_cell()
+                                ; movzx edx, cell
                                 ;; self.rts_call(rts::RtsState::read as _)
-                                ; mov [pointer], al
+                                ; mov cell, al
                             );
+
+                self.cached = true;
+                self.dirty = true;
             }
 
             Instr(Out) => {
+                dynasm!(self.asm
+                ; .alias cell, r11b
+                                ;; self.load_cell()
+                                ;; self.flush_cell()
+                                ; movzx edx, cell
+                                ;; self.rts_call(rts::RtsState::write as _)
+                            );
+            }
+
+            // The program keeps the bytes, so they outlive the compiled code.
+            Instr(WriteStr(ref bytes)) => {
+                self.flush_cell();
+                self.strings.push(bytes.clone());
+
                 dynasm!(self.asm
                 ; .alias pointer, r12
                 ; .alias mem_start, r13
                 ; .alias mem_limit, r14
                 ; .alias rts, r15
-                                ; xor rdx, rdx
-                                ; mov dl, [pointer]
-                                ;; self.rts_call(rts::RtsState::write as _)
+                                ; mov rdx, QWORD bytes.as_ptr() as i64
+                                ; mov r8, QWORD bytes.len() as i64
+                                ;; self.rts_call(rts::RtsState::write_str as *const u8 as i64)
                             );
             }
 
-            Instr(SetZero) => {
+            // Compiled code runs with a single tape, where switching does nothing.
+            Instr(SwitchTape(_)) => (),
+
+            Instr(SetZero) => self.set_cell(0),
+
+            Instr(SetConst(value)) => self.set_cell(value),
+
+            Instr(FindZeroRight(1)) => {
+                self.interpreter.find_zero_right(1);
+                self.spill_cell();
+
                 dynasm!(self.asm
                 ; .alias pointer, r12
                 ; .alias mem_start, r13
                 ; .alias mem_limit, r14
                 ; .alias rts, r15
-                                ; mov BYTE [pointer], 0
-                            )
+                                ; cmp BYTE [pointer], 0
+                                ; jz >skip
+                                ; mov rcx, pointer
+                                ; mov rdx, mem_limit
+                                ;; self.scan_call(rts::find_zero_right as *const u8 as i64)
+                                ; test rax, rax
+                                ; jz ->overflow
+                                ; mov pointer, rax
+                                ; skip:
+                            );
+
+                self.count_peak_pointer();
             }
 
             Instr(FindZeroRight(skip)) => {
-                self.interpreter.reset_right();
+                self.interpreter.find_zero_right(skip);
+                self.spill_cell();
 
                 dynasm!(self.asm
                 ; .alias pointer, r12
@@ -219,11 +548,35 @@ impl<B: BoundsAnalysis> Compiler<B> {
                                 ; end_loop:
                                 ; cmp BYTE [pointer], 0
                                 ; jnz <begin_loop
+                            );
+
+                self.count_peak_pointer();
+            }
+
+            Instr(FindZeroLeft(1)) => {
+                self.interpreter.find_zero_left(1);
+                self.spill_cell();
+
+                dynasm!(self.asm
+                ; .alias pointer, r12
+                ; .alias mem_start, r13
+                ; .alias mem_limit, r14
+                ; .alias rts, r15
+                                ; cmp BYTE [pointer], 0
+                                ; jz >skip
+                                ; mov rcx, mem_start
+                                ; mov rdx, pointer
+                                ;; self.scan_call(rts::find_zero_left as *const u8 as i64)
+                                ; test rax, rax
+                                ; jz ->underflow
+                                ; mov pointer, rax
+                                ; skip:
                             )
             }
 
             Instr(FindZeroLeft(skip)) => {
-                self.interpreter.reset_left();
+                self.interpreter.find_zero_left(skip);
+                self.spill_cell();
 
                 dynasm!(self.asm
                 ; .alias pointer, r12
@@ -242,6 +595,7 @@ impl<B: BoundsAnalysis> Compiler<B> {
 
             Instr(OffsetAddRight(offset)) => {
                 let proved = self.interpreter.check_right(offset);
+                self.spill_cell();
 
                 dynasm!(self.asm
                 ; .alias pointer, r12
@@ -254,12 +608,14 @@ impl<B: BoundsAnalysis> Compiler<B> {
                                 ; mov cl, BYTE [pointer]
                                 ; mov BYTE [pointer], 0
                                 ; add BYTE [pointer + rax], cl
+                                ;; self.count_peak_cell(offset as i64)
                                 ; skip:
                             );
             }
 
             Instr(OffsetAddLeft(offset)) => {
                 let proved = self.interpreter.check_left(offset);
+                self.spill_cell();
 
                 dynasm!(self.asm
                 ; .alias pointer, r12
@@ -277,6 +633,46 @@ impl<B: BoundsAnalysis> Compiler<B> {
                             );
             }
 
+            Instr(MulAddRight(offset, factor)) => self.compile_mul_add(offset as isize, factor),
+
+            Instr(MulAddLeft(offset, factor)) => self.compile_mul_add(-(offset as isize), factor),
+
+            Instr(AddAt(offset, value)) => {
+                self.flush_cell();
+                if offset == 0 {
+                    self.spill_cell();
+                }
+                match self.check_offset(offset) {
+                    Some(disp) => dynasm!(self.asm
+                    ; .alias pointer, r12
+                                    ; add BYTE [pointer + disp], value as i8
+                                ),
+                    None => dynasm!(self.asm
+                    ; .alias pointer, r12
+                                    ; add BYTE [pointer + rax], value as i8
+                                ),
+                }
+                self.count_peak_cell(offset as i64);
+            }
+
+            Instr(SetAt(offset, value)) => {
+                self.flush_cell();
+                if offset == 0 {
+                    self.spill_cell();
+                }
+                match self.check_offset(offset) {
+                    Some(disp) => dynasm!(self.asm
+                    ; .alias pointer, r12
+                                    ; mov BYTE [pointer + disp], value as i8
+                                ),
+                    None => dynasm!(self.asm
+                    ; .alias pointer, r12
+                                    ; mov BYTE [pointer + rax], value as i8
+                                ),
+                }
+                self.count_peak_cell(offset as i64);
+            }
+
             Instr(JumpZero(_)) | Instr(JumpNotZero(_)) => panic!("unexpected jump instruction"),
 
             Loop(ref body) => {
@@ -284,18 +680,27 @@ impl<B: BoundsAnalysis> Compiler<B> {
                 let end_label = self.asm.new_dynamic_label();
 
                 self.interpreter.enter_loop(body);
+                let index = self.loops;
+                self.loops += 1;
 
+                // Both ways into the jump at the end leave the cell in `cell` and in memory, and
+                // the flags set by it.
                 dynasm!(self.asm
                 ; .alias pointer, r12
-                ; .alias mem_start, r13
-                ; .alias mem_limit, r14
-                ; .alias rts, r15
+                                ;; self.time_loop(index, true)
+                                ;; self.test_cell()
                                 ; jmp =>end_label
+                                ;; self.enter_symbol()
                                 ; =>begin_label
+                                ;; self.flags = false
+                                ;; self.count_loop_iteration()
+                                ;; self.check_limits()
                                 ;; self.compile(body)
+                                ;; self.test_cell()
                                 ; =>end_label
-                                ; cmp BYTE [pointer], 0
                                 ; jnz =>begin_label
+                                ;; self.leave_symbol()
+                                ;; self.time_loop(index, false)
                             );
 
                 self.interpreter.leave_loop();
@@ -303,7 +708,175 @@ impl<B: BoundsAnalysis> Compiler<B> {
         }
     }
 
+    /// Adds the cell at the pointer times `factor` to the cell at `offset` from it, unless the
+    /// cell is zero. The cell stays cached, since the instruction doesn't change it.
+    fn compile_mul_add(&mut self, offset: isize, factor: u8) {
+        self.load_cell();
+        self.flush_cell();
+
+        dynasm!(self.asm
+        ; .alias cell, r11b
+                ; test cell, cell
+                ; jz >skip
+            );
+
+        let disp = self.check_offset(offset);
+
+        dynasm!(self.asm
+        ; .alias cell, r11b
+                ; movzx ecx, cell
+                ;; self.multiply_ecx(factor)
+            );
+
+        match disp {
+            Some(disp) => dynasm!(self.asm
+            ; .alias pointer, r12
+                    ; add BYTE [pointer + disp], cl
+                ),
+            None => dynasm!(self.asm
+            ; .alias pointer, r12
+                    ; add BYTE [pointer + rax], cl
+                ),
+        }
+
+        dynasm!(self.asm
+            ;; self.count_peak_cell(offset as i64)
+            ; skip:
+        );
+    }
+
+    /// Multiplies `ecx` by `factor`, modulo 256 in `cl`, with a shift, a negation, or an `lea`
+    /// where one will do.
+    fn multiply_ecx(&mut self, factor: u8) {
+        match factor {
+            1 => (),
+            255 => dynasm!(self.asm
+                ; neg ecx
+            ),
+            3 => dynasm!(self.asm
+                ; lea ecx, [rcx + rcx * 2]
+            ),
+            5 => dynasm!(self.asm
+                ; lea ecx, [rcx + rcx * 4]
+            ),
+            9 => dynasm!(self.asm
+                ; lea ecx, [rcx + rcx * 8]
+            ),
+            _ if factor.is_power_of_two() => dynasm!(self.asm
+                ; shl ecx, factor.trailing_zeros() as i8
+            ),
+            _ => dynasm!(self.asm
+                ; imul ecx, ecx, DWORD factor as i32
+            ),
+        }
+    }
+
+    /// Counts one instruction of the given kind, if we are counting.
+    fn count_instruction(&mut self, kind: Kind) {
+        if self.counting {
+            dynasm!(self.asm
+            ; .alias rts, r15
+                    ; add QWORD [rts + RtsState::count_offset(kind)], 1
+                );
+        }
+    }
+
+    /// Counts the start of a loop body, if we are counting.
+    fn count_loop_iteration(&mut self) {
+        if self.counting {
+            dynasm!(self.asm
+            ; .alias rts, r15
+                    ; add QWORD [rts + RtsState::loop_iterations_offset()], 1
+                );
+        }
+    }
+
+    /// Subtracts the time-stamp counter from the cycles of the loop with the given index as it
+    /// starts, counting the start, or adds it as the loop ends, if we are profiling. This leaves
+    /// `cell` alone, but not the flags.
+    fn time_loop(&mut self, index: usize, start: bool) {
+        if !self.profiling {
+            return;
+        }
+
+        let cycles = (index * 16) as i32;
+        dynasm!(self.asm
+        ; .alias rts, r15
+                ; rdtsc
+                ; shl rdx, 32
+                ; or rax, rdx
+                ; mov rcx, QWORD [rts + RtsState::profile_offset()]
+            );
+        if start {
+            dynasm!(self.asm
+                ; sub QWORD [rcx + cycles], rax
+                ; add QWORD [rcx + cycles + 8], 1
+            );
+        } else {
+            dynasm!(self.asm
+                ; add QWORD [rcx + cycles], rax
+            );
+        }
+        self.flags = false;
+    }
+
+    /// Counts a step against the sandbox, stopping the program at a limit, if we are sandboxed.
+    fn check_limits(&mut self) {
+        if self.sandboxed {
+            self.flush_cell();
+
+            dynasm!(self.asm
+                ;; self.rts_call(RtsState::step as *const u8 as i64)
+                ; test rax, rax
+                ; jnz ->limit
+            );
+        }
+    }
+
+    /// Records the pointer position if it's the highest so far, if we are counting.
+    fn count_peak_pointer(&mut self) {
+        if self.counting {
+            dynasm!(self.asm
+            ; .alias pointer, r12
+            ; .alias mem_start, r13
+            ; .alias rts, r15
+                    ; mov rax, pointer
+                    ; sub rax, mem_start
+                    ; cmp rax, QWORD [rts + RtsState::peak_pointer_offset()]
+                    ; jbe >not_peak
+                    ; mov QWORD [rts + RtsState::peak_pointer_offset()], rax
+                    ; not_peak:
+                );
+            self.count_peak_cell(0);
+        }
+    }
+
+    /// Records the address of the cell at `offset` from the pointer if it's the highest so far,
+    /// if we are counting. Only offsets to the right of the pointer can be the highest.
+    fn count_peak_cell(&mut self, offset: i64) {
+        if self.counting && offset >= 0 {
+            dynasm!(self.asm
+            ; .alias pointer, r12
+            ; .alias mem_start, r13
+            ; .alias rts, r15
+                    ; mov rax, pointer
+                    ; sub rax, mem_start
+                    ; mov rcx, QWORD offset
+                    ; add rax, rcx
+                    ; cmp rax, QWORD [rts + RtsState::peak_cell_offset()]
+                    ; jbe >not_peak
+                    ; mov QWORD [rts + RtsState::peak_cell_offset()], rax
+                    ; not_peak:
+                );
+        }
+    }
+
+    /// Calls a function of the run-time system, which may overwrite `cell`. The caller must
+    /// [flush](#method.flush_cell) the cell first.
     fn rts_call(&mut self, fun: i64) {
+        debug_assert!(!self.dirty, "calling the RTS with an unflushed cell");
+        self.cached = false;
+
         dynasm!(self.asm
         ; .alias pointer, r12
         ; .alias mem_start, r13
@@ -317,6 +890,137 @@ impl<B: BoundsAnalysis> Compiler<B> {
             );
     }
 
+    /// Calls one of the RTS memory scanning functions, whose arguments are already in `rcx` and
+    /// `rdx`.
+    fn scan_call(&mut self, fun: i64) {
+        debug_assert!(!self.cached, "scanning with a cached cell");
+
+        dynasm!(self.asm
+        ; .alias pointer, r12
+        ; .alias mem_start, r13
+        ; .alias mem_limit, r14
+        ; .alias rts, r15
+                ; mov rax, QWORD fun
+                ; sub rsp, BYTE 0x28
+                ; call rax
+                ; add rsp, BYTE 0x28
+            );
+    }
+
+    /// Loads the cell at the pointer into `cell`, unless it's already there.
+    fn load_cell(&mut self) {
+        if !self.cached {
+            dynasm!(self.asm
+            ; .alias pointer, r12
+            ; .alias cell, r11b
+                    ; mov cell, BYTE [pointer]
+                );
+            self.cached = true;
+        }
+    }
+
+    /// Stores `cell` to the cell at the pointer if memory doesn't have its value yet, and keeps
+    /// it cached. This must happen before anything that can leave the generated code.
+    fn flush_cell(&mut self) {
+        if self.dirty {
+            dynasm!(self.asm
+            ; .alias pointer, r12
+            ; .alias cell, r11b
+                    ; mov BYTE [pointer], cell
+                );
+            self.dirty = false;
+        }
+    }
+
+    /// Flushes `cell` and forgets it, before code that moves the pointer or uses the cell in
+    /// memory.
+    fn spill_cell(&mut self) {
+        self.flush_cell();
+        self.cached = false;
+    }
+
+    /// Loads and flushes `cell`, and sets the flags by it unless the instruction that changed it
+    /// last already did.
+    fn test_cell(&mut self) {
+        self.load_cell();
+        // Storing the cell leaves the flags alone.
+        self.flush_cell();
+
+        if !self.flags {
+            dynasm!(self.asm
+            ; .alias cell, r11b
+                    ; test cell, cell
+                );
+            self.flags = true;
+        }
+    }
+
+    /// Changes the cell at the pointer as `Add(amount)` does for cells that don't wrap, where 128
+    /// or more is a decrease, and checks the carry flag for overflow, or the overflow flag for
+    /// signed cells. A cell that traps is stored at its largest or smallest value before the
+    /// code stops, as the commands before the one that overflowed left it.
+    fn add_checked(&mut self, amount: u8) {
+        self.load_cell();
+        self.dirty = true;
+
+        let up = amount < 128;
+        let step = if up { amount } else { amount.wrapping_neg() } as i8;
+        if up {
+            dynasm!(self.asm
+            ; .alias cell, r11b
+                    ; add cell, BYTE step
+                );
+        } else {
+            dynasm!(self.asm
+            ; .alias cell, r11b
+                    ; sub cell, BYTE step
+                );
+        }
+
+        if self.signed {
+            dynasm!(self.asm
+                ; jno >ok
+            );
+        } else {
+            dynasm!(self.asm
+                ; jnc >ok
+            );
+        }
+        let limit: i8 = match (self.signed, up) {
+            (false, true) => -1,
+            (false, false) => 0,
+            (true, true) => i8::MAX,
+            (true, false) => i8::MIN,
+        };
+        if self.overflow == Overflow::Trap {
+            dynasm!(self.asm
+            ; .alias pointer, r12
+                    ; mov BYTE [pointer], BYTE limit
+                    ; jmp ->cell_overflow
+                    ; ok:
+                );
+            // The add set the flags when it didn't overflow.
+            self.flags = true;
+        } else {
+            dynasm!(self.asm
+            ; .alias cell, r11b
+                    ; mov cell, BYTE limit
+                    ; ok:
+                );
+            self.flags = false;
+        }
+    }
+
+    /// Sets the cell at the pointer to `value`, in `cell` only.
+    fn set_cell(&mut self, value: u8) {
+        dynasm!(self.asm
+        ; .alias cell, r11b
+                ; mov cell, BYTE value as i8
+            );
+        self.cached = true;
+        self.dirty = true;
+    }
+
     #[inline]
     fn load_constant(&mut self, count: Count) {
         if count as i32 as Count == count {
@@ -356,6 +1060,31 @@ impl<B: BoundsAnalysis> Compiler<B> {
         }
     }
 
+    /// Checks the offset of an `AddAt` or `SetAt`, if necessary. Returns the offset as a
+    /// displacement if it fits in one, and otherwise leaves it in `rax`.
+    fn check_offset(&mut self, offset: isize) -> Option<i32> {
+        let distance = offset.unsigned_abs() as Count;
+        let proved = if offset < 0 {
+            self.interpreter.check_left(distance)
+        } else {
+            self.interpreter.check_right(distance)
+        };
+        let disp = i32::try_from(offset).ok();
+
+        if disp.is_none() || (self.checked && !proved) {
+            if offset < 0 {
+                self.load_neg_offset(distance, proved);
+                dynasm!(self.asm
+                ; neg rax
+                );
+            } else {
+                self.load_pos_offset(distance, proved);
+            }
+        }
+
+        disp
+    }
+
     #[inline]
     fn load_neg_offset(&mut self, offset: Count, proved: bool) {
         self.load_constant(offset);
@@ -392,3 +1121,14 @@ impl<T: peephole::PeepholeCompilable + ?Sized> JitCompilable for T {
         k(&self.peephole_compile())
     }
 }
+
+impl Stage for Program {
+    type Compiled = Program;
+}
+
+/// Compiles with memory bounds checks, as `jit_compile(true)` does.
+impl<T: JitCompilable + ?Sized> CompileTo<Program> for T {
+    fn compile_to(&self) -> Program {
+        self.jit_compile(true)
+    }
+}
@@ -0,0 +1,342 @@
+//! Registering JIT-compiled code with GDB and LLDB.
+//!
+//! A debugger finds code that a JIT generates through the [GDB JIT interface]: the program keeps
+//! a list of in-memory object files describing its code, in a global that the debugger knows the
+//! name of, and calls a function that the debugger has a breakpoint on whenever the list changes.
+//! The object file here is an ELF file whose only contents are the code and a symbol for each run
+//! of code that belongs to one loop, with the names of the
+//! [perf map](../struct.Program.html#method.perf_map), so that a backtrace or a disassembly in the
+//! debugger shows which loop the code implements. There is no line information, because the
+//! peephole AST doesn't record where in the source each statement came from.
+//!
+//! [GDB JIT interface]: https://sourceware.org/gdb/current/onlinedocs/gdb.html/JIT-Interface.html
+
+use std::ptr;
+use std::sync::Mutex;
+
+use super::Symbol;
+
+/// That nothing changed.
+const JIT_NOACTION: u32 = 0;
+/// That `relevant_entry` was added to the list.
+const JIT_REGISTER_FN: u32 = 1;
+/// That `relevant_entry` was removed from the list.
+const JIT_UNREGISTER_FN: u32 = 2;
+
+/// An entry in the list of object files, as the debugger expects it.
+#[repr(C)]
+struct JitCodeEntry {
+    next_entry: *mut JitCodeEntry,
+    prev_entry: *mut JitCodeEntry,
+    symfile_addr: *const u8,
+    symfile_size: u64,
+}
+
+/// The list of object files, as the debugger expects it.
+#[repr(C)]
+pub struct JitDescriptor {
+    version: u32,
+    action_flag: u32,
+    relevant_entry: *mut JitCodeEntry,
+    first_entry: *mut JitCodeEntry,
+}
+
+/// The list of object files, which the debugger reads by name.
+#[allow(non_upper_case_globals)]
+#[unsafe(no_mangle)]
+pub static mut __jit_debug_descriptor: JitDescriptor = JitDescriptor {
+    version: 1,
+    action_flag: JIT_NOACTION,
+    relevant_entry: ptr::null_mut(),
+    first_entry: ptr::null_mut(),
+};
+
+/// The function that the debugger has a breakpoint on, to read the list after it changes.
+#[unsafe(no_mangle)]
+#[inline(never)]
+pub extern "C" fn __jit_debug_register_code() {
+    // Keeps the function from being merged with another one or optimized away.
+    unsafe { std::arch::asm!("", options(nomem, nostack, preserves_flags)) };
+}
+
+/// Serializes changes to the list, which is shared by the whole process.
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// An object file in the debugger's list, which stays there until this is dropped.
+pub(super) struct Registration {
+    entry: Box<JitCodeEntry>,
+    /// The object file, which the entry points to.
+    _image: Box<[u8]>,
+}
+
+// The entry is only touched while holding the lock.
+unsafe impl Send for Registration {}
+unsafe impl Sync for Registration {}
+
+impl Registration {
+    /// Adds an object file for `code`, which is loaded at `address`, to the debugger's list.
+    pub(super) fn new(code: &[u8], address: usize, symbols: &[Symbol]) -> Self {
+        let image = elf_image(code, address, symbols).into_boxed_slice();
+        let mut entry = Box::new(JitCodeEntry {
+            next_entry: ptr::null_mut(),
+            prev_entry: ptr::null_mut(),
+            symfile_addr: image.as_ptr(),
+            symfile_size: image.len() as u64,
+        });
+
+        let _guard = LOCK.lock().unwrap_or_else(|poison| poison.into_inner());
+        unsafe {
+            let descriptor = &raw mut __jit_debug_descriptor;
+            entry.next_entry = (*descriptor).first_entry;
+            if !entry.next_entry.is_null() {
+                (*entry.next_entry).prev_entry = &mut *entry;
+            }
+            (*descriptor).first_entry = &mut *entry;
+            (*descriptor).relevant_entry = &mut *entry;
+            (*descriptor).action_flag = JIT_REGISTER_FN;
+            __jit_debug_register_code();
+        }
+
+        Registration {
+            entry,
+            _image: image,
+        }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let _guard = LOCK.lock().unwrap_or_else(|poison| poison.into_inner());
+        unsafe {
+            let descriptor = &raw mut __jit_debug_descriptor;
+            let entry = &mut *self.entry;
+            if entry.prev_entry.is_null() {
+                (*descriptor).first_entry = entry.next_entry;
+            } else {
+                (*entry.prev_entry).next_entry = entry.next_entry;
+            }
+            if !entry.next_entry.is_null() {
+                (*entry.next_entry).prev_entry = entry.prev_entry;
+            }
+            (*descriptor).relevant_entry = entry;
+            (*descriptor).action_flag = JIT_UNREGISTER_FN;
+            __jit_debug_register_code();
+            (*descriptor).relevant_entry = ptr::null_mut();
+            (*descriptor).action_flag = JIT_NOACTION;
+        }
+    }
+}
+
+/// The size of an ELF header.
+const EHDR_SIZE: usize = 64;
+/// The size of a section header.
+const SHDR_SIZE: usize = 64;
+/// The size of a symbol table entry.
+const SYM_SIZE: usize = 24;
+
+/// Builds a relocatable ELF object whose `.text` section, at `address`, holds `code`, with a
+/// local function symbol for each of `symbols`.
+fn elf_image(code: &[u8], address: usize, symbols: &[Symbol]) -> Vec<u8> {
+    let shstrtab = b"\0.text\0.symtab\0.strtab\0.shstrtab\0";
+    let (text_name, symtab_name, strtab_name, shstrtab_name) = (1, 7, 15, 23);
+
+    let mut strtab = vec![0];
+    let mut symtab = vec![0; SYM_SIZE];
+    for symbol in symbols {
+        let name = strtab.len() as u32;
+        strtab.extend_from_slice(symbol.name.as_bytes());
+        strtab.push(0);
+
+        symtab.extend_from_slice(&name.to_le_bytes());
+        // A local function, in section 1.
+        symtab.push(2);
+        symtab.push(0);
+        symtab.extend_from_slice(&1u16.to_le_bytes());
+        symtab.extend_from_slice(&(symbol.start as u64).to_le_bytes());
+        symtab.extend_from_slice(&(symbol.len as u64).to_le_bytes());
+    }
+
+    let text_offset = EHDR_SIZE;
+    let symtab_offset = align(text_offset + code.len(), 8);
+    let strtab_offset = symtab_offset + symtab.len();
+    let shstrtab_offset = strtab_offset + strtab.len();
+    let shdrs_offset = align(shstrtab_offset + shstrtab.len(), 8);
+
+    let mut image = Vec::with_capacity(shdrs_offset + 5 * SHDR_SIZE);
+    // The identification: ELF, 64-bit, little-endian, version 1, System V.
+    image.extend_from_slice(b"\x7fELF\x02\x01\x01\0\0\0\0\0\0\0\0\0");
+    push_u16(&mut image, 1); // ET_REL
+    push_u16(&mut image, 62); // EM_X86_64
+    push_u32(&mut image, 1);
+    push_u64(&mut image, 0); // entry
+    push_u64(&mut image, 0); // program headers
+    push_u64(&mut image, shdrs_offset as u64);
+    push_u32(&mut image, 0); // flags
+    push_u16(&mut image, EHDR_SIZE as u16);
+    push_u16(&mut image, 0);
+    push_u16(&mut image, 0);
+    push_u16(&mut image, SHDR_SIZE as u16);
+    push_u16(&mut image, 5);
+    push_u16(&mut image, 4);
+
+    image.extend_from_slice(code);
+    image.resize(symtab_offset, 0);
+    image.extend_from_slice(&symtab);
+    image.extend_from_slice(&strtab);
+    image.extend_from_slice(shstrtab);
+    image.resize(shdrs_offset, 0);
+
+    let sections = [
+        SectionHeader::default(),
+        // PROGBITS, allocated and executable.
+        SectionHeader {
+            name: text_name,
+            kind: 1,
+            flags: 0x6,
+            addr: address as u64,
+            offset: text_offset as u64,
+            size: code.len() as u64,
+            align: 16,
+            ..SectionHeader::default()
+        },
+        // SYMTAB, linked to `.strtab`, where every symbol is local.
+        SectionHeader {
+            name: symtab_name,
+            kind: 2,
+            offset: symtab_offset as u64,
+            size: symtab.len() as u64,
+            link: 3,
+            info: symbols.len() as u32 + 1,
+            align: 8,
+            entsize: SYM_SIZE as u64,
+            ..SectionHeader::default()
+        },
+        // STRTAB, twice.
+        SectionHeader {
+            name: strtab_name,
+            kind: 3,
+            offset: strtab_offset as u64,
+            size: strtab.len() as u64,
+            align: 1,
+            ..SectionHeader::default()
+        },
+        SectionHeader {
+            name: shstrtab_name,
+            kind: 3,
+            offset: shstrtab_offset as u64,
+            size: shstrtab.len() as u64,
+            align: 1,
+            ..SectionHeader::default()
+        },
+    ];
+    for section in &sections {
+        section.write(&mut image);
+    }
+
+    image
+}
+
+/// The fields of an ELF section header.
+#[derive(Default)]
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    addr: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entsize: u64,
+}
+
+impl SectionHeader {
+    fn write(&self, image: &mut Vec<u8>) {
+        push_u32(image, self.name);
+        push_u32(image, self.kind);
+        push_u64(image, self.flags);
+        push_u64(image, self.addr);
+        push_u64(image, self.offset);
+        push_u64(image, self.size);
+        push_u32(image, self.link);
+        push_u32(image, self.info);
+        push_u64(image, self.align);
+        push_u64(image, self.entsize);
+    }
+}
+
+fn align(offset: usize, alignment: usize) -> usize {
+    offset.next_multiple_of(alignment)
+}
+
+fn push_u16(image: &mut Vec<u8>, value: u16) {
+    image.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(image: &mut Vec<u8>, value: u32) {
+    image.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u64(image: &mut Vec<u8>, value: u64) {
+    image.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(start: usize, len: usize, name: &str) -> Symbol {
+        Symbol {
+            start,
+            len,
+            name: name.to_owned(),
+        }
+    }
+
+    fn entries() -> Vec<*const u8> {
+        let _guard = LOCK.lock().unwrap();
+        let mut result = Vec::new();
+        unsafe {
+            let descriptor = &raw const __jit_debug_descriptor;
+            let mut entry = (*descriptor).first_entry;
+            while !entry.is_null() {
+                result.push((*entry).symfile_addr);
+                entry = (*entry).next_entry;
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn registers_until_dropped() {
+        let code = [0x90; 32];
+        let symbols = [symbol(0, 16, "bf::main"), symbol(16, 16, "bf::loop_1")];
+        let first = Registration::new(&code, 0x1000, &symbols);
+        let second = Registration::new(&code, 0x2000, &symbols[..1]);
+        let (a, b) = (first.entry.symfile_addr, second.entry.symfile_addr);
+        assert!(entries().starts_with(&[b, a]));
+
+        drop(first);
+        assert!(entries().contains(&b));
+        assert!(!entries().contains(&a));
+        drop(second);
+        assert!(!entries().contains(&b));
+    }
+
+    #[test]
+    fn builds_elf_with_symbols() {
+        let code = [0xc3; 10];
+        let image = elf_image(&code, 0x4000, &[symbol(2, 8, "bf::loop_1_2")]);
+        assert_eq!(&image[..4], b"\x7fELF");
+        assert_eq!(&image[EHDR_SIZE..EHDR_SIZE + 10], &code);
+
+        let shoff = u64::from_le_bytes(image[40..48].try_into().unwrap()) as usize;
+        assert_eq!(image.len(), shoff + 5 * SHDR_SIZE);
+        let text = &image[shoff + SHDR_SIZE..shoff + 2 * SHDR_SIZE];
+        assert_eq!(u64::from_le_bytes(text[16..24].try_into().unwrap()), 0x4000);
+
+        let name = b"\0bf::loop_1_2\0";
+        assert!(image.windows(name.len()).any(|window| window == name));
+    }
+}
@@ -9,19 +9,32 @@
 //! memory bounds checking in the generated code. Note that this runs Brainfuck in
 //! unsafe mode, which means that programs that move the pointer outside the allocated
 //! memory will access and possibly overwrite arbitrary memory locations.
+//!
+//! To profile JIT-compiled code with `perf`, pass `--perf-map`, which writes the addresses of
+//! the code for each loop where `perf report` finds them; see
+//! [`Program::perf_map`](struct.Program.html#method.perf_map).
+//! The [profiler](../profile/index.html) can also time each loop of JIT-compiled code, with
+//! `bfi profile --jit`.
 
-mod analysis;
 mod compiler;
-mod loop_balance;
+mod gdb;
 
-pub use self::compiler::{compile, JitCompilable};
-use std::io::{Read, Write};
+pub use self::compiler::{
+    JitCompilable, compile, compile_counting, compile_for, compile_sandboxed,
+};
+#[cfg(feature = "tools")]
+pub(crate) use self::compiler::compile_profiling;
+use std::io::{self, Read, Write};
 use std::mem;
+use std::sync::Arc;
 
 use dynasmrt;
 
 use crate::common::{BfResult, Error};
+use crate::counters::{self, CountingInterpretable, Counters};
 use crate::rts::{self, RtsState};
+use crate::sandbox::{self, SandboxConfig, SandboxInterpretable};
+use crate::semantics::Overflow;
 use crate::state::State;
 use crate::traits::Interpretable;
 
@@ -32,9 +45,34 @@ use crate::traits::Interpretable;
 /// is from [the `dynlib-rs` tutorial].
 ///
 /// [the `dynlib-rs` tutorial]: https://censoredusername.github.io/dynasm-rs/language/tutorial.html#advanced-usage
+///
+/// A `Program` is `Send` and `Sync`, so one compiled program can be shared between threads and
+/// run on all of them at once. The code buffer is read-only after compilation, and the generated
+/// code keeps no state of its own: each run gets its memory from the caller's
+/// [`State`](../state/struct.State.html), and its I/O channels from an
+/// [`RtsState`](../rts/struct.RtsState.html) that lives on that run's stack.
 pub struct Program {
+    /// Declared before the code, so that the debugger forgets the code before it goes away.
+    registration: Option<gdb::Registration>,
     code: dynasmrt::ExecutableBuffer,
     start: dynasmrt::AssemblyOffset,
+    counting: bool,
+    sandboxed: bool,
+    #[cfg(feature = "tools")]
+    profiling: bool,
+    overflow: Overflow,
+    signed: bool,
+    symbols: Vec<Symbol>,
+    /// The bytes that the code writes for `WriteStr`, which it points at.
+    #[allow(dead_code)]
+    strings: Vec<Arc<[u8]>>,
+}
+
+/// A run of generated code that belongs to one loop of the program, or to none.
+struct Symbol {
+    start: usize,
+    len: usize,
+    name: String,
 }
 
 /// The type of function that we will assemble and then call.
@@ -43,36 +81,223 @@ pub struct Program {
 ///
 /// `<'a>` – the lifetime of the channel references in the run-time system state.
 ///
-/// `memory` – the address of the beginning of memory (also where the pointer starts).
+/// `memory` – the address of the beginning of memory.
 ///
 /// `memory_size` – the amount of memory allocated, defaults to 30,000 bytes.
 ///
-/// `rts_state` – the state that the run-time system needs to do I/O.
+/// `rts_state` – the state that the run-time system needs to do I/O, which also holds where the
+/// pointer starts.
 type EntryFunction<'a> =
     extern "win64" fn(memory: *mut u8, memory_size: u64, rts_state: *mut RtsState<'a>) -> u64;
 
-impl Interpretable for Program {
-    fn interpret_state<R: Read, W: Write>(
-        &self,
-        mut state: State,
-        mut input: R,
-        mut output: W,
-    ) -> BfResult<()> {
-        let mut rts = RtsState::new(&mut input, &mut output);
+impl Program {
+    /// Whether the program was compiled with code to [count](../counters/index.html) what it
+    /// does, by [`compile_counting`](fn.compile_counting.html).
+    ///
+    /// Only such a program can be run with `interpret_counting`, which panics otherwise.
+    pub fn is_counting(&self) -> bool {
+        self.counting
+    }
+
+    /// Whether the program was compiled to run in a [sandbox](../sandbox/index.html), by
+    /// [`compile_sandboxed`](fn.compile_sandboxed.html).
+    ///
+    /// Only such a program can be run with `interpret_sandboxed`, which panics otherwise.
+    pub fn is_sandboxed(&self) -> bool {
+        self.sandboxed
+    }
+
+    /// What the program's `+` and `-` do to a cell that would go past its largest or smallest
+    /// value, which is to wrap unless it was compiled by [`compile_for`](fn.compile_for.html).
+    ///
+    /// The program runs only against states with the same
+    /// [`overflow`](../state/struct.State.html#method.overflow), and panics otherwise.
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+
+    /// Whether the program was compiled for cells that go from -128 to 127.
+    ///
+    /// Unless cells wrap, the program runs only against states that are as
+    /// [signed](../state/struct.State.html#method.signed), and panics otherwise.
+    pub fn is_signed(&self) -> bool {
+        self.signed
+    }
+
+    /// Lists the generated code in the format of a `perf` map file, one line for each run of
+    /// code that belongs to one loop: the address and the length in hex, and then a name.
+    ///
+    /// Code outside any loop is `bf::main`, and each loop is named by its position among its
+    /// siblings at each level of nesting, counting from 1, so that `bf::loop_2_1` is the first
+    /// loop in the body of the second top-level loop. The condition at the end of a loop belongs
+    /// to the loop.
+    pub fn perf_map(&self) -> String {
+        let base = self.code.ptr(dynasmrt::AssemblyOffset(0)) as usize;
+        self.symbols
+            .iter()
+            .map(|symbol| {
+                format!(
+                    "{:x} {:x} {}\n",
+                    base + symbol.start,
+                    symbol.len,
+                    symbol.name
+                )
+            })
+            .collect()
+    }
+
+    /// Tells GDB or LLDB about the generated code, through the [GDB JIT
+    /// interface](https://sourceware.org/gdb/current/onlinedocs/gdb.html/JIT-Interface.html),
+    /// until the program is dropped. A debugger then names the code for each loop as the
+    /// [`perf_map`](#method.perf_map) does.
+    pub fn register_with_debugger(&mut self) {
+        if self.registration.is_none() {
+            let address = self.code.ptr(dynasmrt::AssemblyOffset(0)) as usize;
+            self.registration = Some(gdb::Registration::new(&self.code, address, &self.symbols));
+        }
+    }
+
+    /// Appends the [`perf_map`](#method.perf_map) for this program to `/tmp/perf-PID.map`,
+    /// where `perf report` looks for the names of JIT-compiled code in the process `PID`.
+    ///
+    /// # Errors
+    ///
+    /// Any error opening or writing the file.
+    pub fn write_perf_map(&self) -> io::Result<()> {
+        let path = format!("/tmp/perf-{}.map", std::process::id());
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        file.write_all(self.perf_map().as_bytes())
+    }
+
+    fn run(&self, state: &mut State, rts: &mut RtsState) -> BfResult<()> {
+        assert_eq!(state.tapes(), 1, "JIT-compiled code supports only one tape");
+        assert_eq!(
+            state.overflow(),
+            self.overflow,
+            "JIT-compiled code supports only the overflow behavior it was compiled for"
+        );
+        assert!(
+            self.overflow == Overflow::Wrap || state.signed() == self.signed,
+            "JIT-compiled code supports only the signedness it was compiled for"
+        );
 
         let f: EntryFunction = unsafe { mem::transmute(self.code.ptr(self.start)) };
 
-        let result = f(state.as_mut_ptr(), state.capacity() as u64, &mut rts);
+        rts.set_pointer(state.pointer());
+        let result = f(state.as_mut_ptr(), state.capacity() as u64, rts);
 
         match result {
-            rts::OKAY => Ok(()),
+            rts::OKAY => state.set_pointer(rts.pointer()),
             rts::UNDERFLOW => Err(Error::PointerUnderflow),
             rts::OVERFLOW => Err(Error::PointerOverflow),
+            rts::LIMIT => {
+                state.set_pointer(rts.pointer())?;
+                Err(rts.limit().expect("no limit was reached"))
+            }
+            rts::CELL_OVERFLOW => {
+                state.set_pointer(rts.pointer())?;
+                Err(Error::CellOverflow)
+            }
             _ => panic!("{}", format!("Unknown result code: {}", result)),
         }
     }
 }
 
+/// The size of the machine code, in bytes.
+#[cfg(feature = "tracing")]
+impl crate::instrument::Size for Program {
+    fn size(&self) -> usize {
+        self.code.len()
+    }
+}
+
+#[cfg(feature = "tools")]
+impl Program {
+    /// Runs a program that was compiled for profiling, which adds the cycles of each loop and the
+    /// number of times that it started to `loops`, two numbers for each loop in the order that
+    /// they start. Returns the result and the cycles that the whole run took.
+    pub(crate) fn interpret_profiling<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        mut input: R,
+        mut output: W,
+        loops: &mut [u64],
+    ) -> (BfResult<()>, u64) {
+        use std::arch::x86_64::_rdtsc;
+
+        assert!(self.profiling, "program was not compiled for profiling");
+
+        let mut rts = RtsState::new(&mut input, &mut output);
+        rts.set_eof(state.eof());
+        rts.set_profile(loops);
+        let start = unsafe { _rdtsc() };
+        let result = self.run(state, &mut rts);
+        let cycles = unsafe { _rdtsc() } - start;
+        (result, cycles)
+    }
+}
+
+impl Interpretable for Program {
+    fn interpret_in<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        mut input: R,
+        mut output: W,
+    ) -> BfResult<()> {
+        let mut rts = RtsState::new(&mut input, &mut output);
+        rts.set_eof(state.eof());
+        self.run(state, &mut rts)
+    }
+}
+
+impl CountingInterpretable for Program {
+    fn interpret_counting<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        input: R,
+        output: W,
+        counters: &mut Counters,
+    ) -> BfResult<()> {
+        assert!(self.counting, "program was not compiled for counting");
+
+        counters::count_io(input, output, counters, |input, output, counters| {
+            let mut rts = RtsState::new(input, output);
+            rts.set_eof(state.eof());
+            let result = self.run(state, &mut rts);
+            counters.add(rts.counters());
+            result
+        })
+    }
+}
+
+impl SandboxInterpretable for Program {
+    fn interpret_sandboxed<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        input: R,
+        output: W,
+        config: &SandboxConfig,
+    ) -> BfResult<()> {
+        assert!(self.sandboxed, "program was not compiled for a sandbox");
+
+        sandbox::run(
+            state,
+            input,
+            output,
+            config,
+            |state, input, output, sandbox| {
+                let mut rts = RtsState::new(input, output);
+                rts.set_eof(state.eof());
+                rts.set_sandbox(sandbox);
+                self.run(state, &mut rts)
+            },
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::common::{BfResult, Error};
@@ -93,6 +318,16 @@ mod tests {
         assert_parse_interpret(b"+[>+]", "", Err(Error::PointerOverflow));
     }
 
+    #[test]
+    fn scan_left_underflows() {
+        assert_parse_interpret(b"+[<]", "", Err(Error::PointerUnderflow));
+    }
+
+    #[test]
+    fn scan_finds_zero() {
+        assert_parse_interpret(b"+>+>+<<[>]<.>>+[<]>.", "", Ok("\x01\x01"));
+    }
+
     #[test]
     fn echo_one_byte() {
         assert_parse_interpret(b",.", "A", Ok("A"));
@@ -116,6 +351,294 @@ mod tests {
         assert_parse_interpret(FACTOR_SRC, "100\n", Ok("100: 2 2 5 5\n"));
     }
 
+    #[test]
+    fn batch_factoring() {
+        use crate::traits::*;
+
+        let program = crate::ast::parse_program(FACTOR_SRC)
+            .unwrap()
+            .jit_compile(true);
+        let inputs = ["2\n", "6\n", "100\n"]
+            .iter()
+            .map(|s| s.as_bytes().to_vec());
+        let outputs: Vec<Vec<u8>> = program
+            .run_batch(inputs)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(outputs, [&b"2: 2\n"[..], b"6: 2 3\n", b"100: 2 2 5 5\n"]);
+    }
+
+    #[test]
+    fn set_const() {
+        assert_parse_interpret(b",[-]+++.>,[-]-[-]+.", "AB", Ok("\x03\x01"));
+    }
+
+    #[test]
+    fn folds_stores_from_unfolded_programs() {
+        let mut passes = crate::pass::PassManager::standard();
+        passes.remove("set-const");
+        let program = crate::ast::parse_program(b",[-]+++.").unwrap();
+        let program = passes.compile(&crate::rle::compile(&program)).unwrap();
+        let program = crate::jit::compile(&program, true);
+        assert_interpret_result(&program, b"A", Ok(b"\x03"));
+    }
+
+    #[test]
+    fn multiply() {
+        assert_parse_interpret(b"+++++[->+++>++<<]>.>.", "", Ok("\x0f\x0a"));
+        assert_parse_interpret(b">>++++[-<<+++>+>]<<.", "", Ok("\x0c"));
+        assert_parse_interpret(b"+[-<++>]", "", Err(Error::PointerUnderflow));
+    }
+
+    #[test]
+    fn write_str() {
+        assert_parse_interpret(b"+++..[-]++.,.", "!", Ok("\x03\x03\x02!"));
+    }
+
+    #[test]
+    fn offset_access() {
+        assert_parse_interpret(b">+>++<<+++>[-]+++++>.<.<.", "", Ok("\x02\x05\x03"));
+        assert_parse_interpret(b">>>-<<+.", "", Ok("\x01"));
+        assert_parse_interpret(b">+<<+", "", Err(Error::PointerUnderflow));
+    }
+
+    #[test]
+    fn loops_ending_in_adds() {
+        assert_parse_interpret(b"+++[.-]", "", Ok("\x03\x02\x01"));
+        assert_parse_interpret(b"++++[.--]", "", Ok("\x04\x02"));
+        assert_parse_interpret(b"++[>++[.-]<-]", "", Ok("\x02\x01\x02\x01"));
+        assert_parse_interpret(b"+[>,[.-]<-]", "\x02", Ok("\x02\x01"));
+    }
+
+    #[test]
+    fn multiply_by_each_kind_of_factor() {
+        use crate::traits::*;
+
+        for factor in [1, 2, 3, 5, 7, 8, 9, 64, 255] {
+            let adds = "+".repeat(factor);
+            let expected = [(5 * factor) as u8];
+            for src in [
+                format!("+++++[->>{}<<]>>.", adds),
+                format!(">>+++++[-<<{}>>]<<.", adds),
+            ] {
+                let program = crate::ast::parse_program(src.as_bytes())
+                    .unwrap()
+                    .jit_compile(true);
+                assert_eq!(program.interpret_memory(None, b""), Ok(expected.to_vec()));
+            }
+        }
+        assert_parse_interpret(b"+++[->+<]>.", "", Ok("\x03"));
+        assert_parse_interpret(b">+++[-<<++>>]", "", Err(Error::PointerUnderflow));
+    }
+
+    #[test]
+    fn cached_cells_reach_memory() {
+        use crate::state::State;
+        use crate::traits::*;
+
+        let run = |src: &[u8]| {
+            let program = crate::ast::parse_program(src).unwrap().jit_compile(true);
+            let mut state = State::with_capacity(4);
+            let result = program.interpret_in(&mut state, &b"\x07"[..], Vec::new());
+            (result, state.cells().to_vec())
+        };
+
+        assert_eq!(run(b"+++>+++++<-"), (Ok(()), vec![2, 5, 0, 0]));
+        assert_eq!(run(b"+++[>++<-]>,+"), (Ok(()), vec![0, 8, 0, 0]));
+        assert_eq!(
+            run(b"+++<"),
+            (Err(Error::PointerUnderflow), vec![3, 0, 0, 0])
+        );
+        assert_eq!(
+            run(b"+>++>,[->+<]>>"),
+            (Err(Error::PointerOverflow), vec![1, 2, 0, 7])
+        );
+    }
+
+    #[test]
+    fn merged_checks_fail_like_separate_ones() {
+        use crate::state::State;
+        use crate::traits::*;
+
+        let run = |src: &[u8]| {
+            let program = crate::ast::parse_program(src).unwrap().jit_compile(true);
+            let mut output = Vec::new();
+            let result = program.interpret_in(&mut State::with_capacity(8), &b""[..], &mut output);
+            (result, output)
+        };
+
+        assert_eq!(run(b">>+>>+>>+>"), (Ok(()), vec![]));
+        assert_eq!(
+            run(b"+.>+>+>+>+>+>+>+>+"),
+            (Err(Error::PointerOverflow), vec![1])
+        );
+        assert_eq!(
+            run(b">>>>+<<+<<+<<+<<+"),
+            (Err(Error::PointerUnderflow), vec![])
+        );
+        assert_eq!(
+            run(b">+>+<<<+>>>>>>>>>>"),
+            (Err(Error::PointerUnderflow), vec![])
+        );
+        assert_eq!(
+            run(b">>+<<<<+>>>>>>>>>>"),
+            (Err(Error::PointerUnderflow), vec![])
+        );
+        assert_eq!(
+            run(b"+>+++++.>>>>>>>"),
+            (Err(Error::PointerOverflow), vec![5])
+        );
+    }
+
+    #[test]
+    fn merged_checks_fail_like_separate_ones_when_cells_trap() {
+        use crate::semantics::Overflow;
+        use crate::state::State;
+        use crate::traits::*;
+
+        let program = crate::ast::parse_program(b">>>++++<<---<<<<").unwrap();
+        let program = crate::rle::compile_for(&program, Overflow::Trap);
+        let program = crate::jit::compile_for(
+            &crate::peephole::compile_for(&program, Overflow::Trap),
+            true,
+            Overflow::Trap,
+            false,
+        );
+        let mut state = State::with_capacity(40);
+        state.set_overflow(Overflow::Trap);
+        let result = program.interpret_in(&mut state, &b""[..], Vec::new());
+        assert_eq!(result, Err(Error::CellOverflow));
+    }
+
+    #[test]
+    fn perf_map_names_loops() {
+        use crate::traits::*;
+
+        let program = crate::ast::parse_program(b"+[.[.-]].+[.-]")
+            .unwrap()
+            .jit_compile(true);
+        let map = program.perf_map();
+        let entries: Vec<(usize, usize, &str)> = map
+            .lines()
+            .map(|line| {
+                let fields: Vec<&str> = line.split(' ').collect();
+                let number = |field: &str| usize::from_str_radix(field, 16).unwrap();
+                (number(fields[0]), number(fields[1]), fields[2])
+            })
+            .collect();
+
+        let names: Vec<&str> = entries.iter().map(|entry| entry.2).collect();
+        assert_eq!(
+            names,
+            [
+                "bf::main",
+                "bf::loop_1",
+                "bf::loop_1_1",
+                "bf::loop_1",
+                "bf::main",
+                "bf::loop_2",
+                "bf::main"
+            ]
+        );
+        for pair in entries.windows(2) {
+            assert_eq!(pair[0].0 + pair[0].1, pair[1].0);
+        }
+        let total: usize = entries.iter().map(|entry| entry.1).sum();
+        assert_eq!(total, program.code.len());
+    }
+
+    #[test]
+    fn program_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<crate::jit::Program>();
+    }
+
+    #[test]
+    fn concurrent_runs_have_independent_memory() {
+        use crate::traits::*;
+
+        let program = crate::ast::parse_program(b",[>+<-]>.")
+            .unwrap()
+            .jit_compile(true);
+
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (1..=8u8)
+                .map(|n| {
+                    let program = &program;
+                    scope.spawn(move || {
+                        (0..100)
+                            .map(|_| program.interpret_memory(Some(2), &[n]).unwrap())
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            for (n, handle) in (1..=8u8).zip(handles) {
+                assert!(handle.join().unwrap().iter().all(|output| output == &[n]));
+            }
+        });
+    }
+
+    #[test]
+    fn concurrent_runs_have_their_own_streams() {
+        use crate::counters::{Counters, CountingInterpretable};
+        use crate::sandbox::{SandboxConfig, SandboxInterpretable};
+        use crate::state::State;
+        use crate::traits::*;
+
+        let program = crate::ast::parse_program(b",[.,]").unwrap();
+        let counting = program.jit_compile_counting(true);
+        let sandboxed = program.jit_compile_sandboxed();
+
+        std::thread::scope(|scope| {
+            for n in 1..=8usize {
+                let (counting, sandboxed) = (&counting, &sandboxed);
+                scope.spawn(move || {
+                    let input = vec![n as u8; n * 10];
+
+                    let mut output = Vec::new();
+                    let mut counters = Counters::new();
+                    counting
+                        .interpret_counting(
+                            &mut State::new(),
+                            std::io::Cursor::new(&input),
+                            &mut output,
+                            &mut counters,
+                        )
+                        .unwrap();
+                    assert_eq!(output, input);
+                    assert_eq!(counters.bytes_read(), input.len() as u64);
+
+                    let config = SandboxConfig {
+                        max_output_bytes: Some(n as u64),
+                        ..SandboxConfig::default()
+                    };
+                    let mut output = Vec::new();
+                    let result = sandboxed.interpret_sandboxed(
+                        &mut State::new(),
+                        &input[..],
+                        &mut output,
+                        &config,
+                    );
+                    assert_eq!(result, Err(Error::OutputLimit));
+                    assert_eq!(output, &input[..n]);
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn compiles_from_any_earlier_form() {
+        use crate::traits::*;
+
+        let program = crate::ast::parse_program(FACTOR_SRC).unwrap();
+        let from_ast = program.compile::<crate::jit::Program>();
+        let from_peephole = program.peephole_compile().compile::<crate::jit::Program>();
+        assert_interpret(&from_ast, b"100\n", b"100: 2 2 5 5\n");
+        assert_interpret(&from_peephole, b"100\n", b"100: 2 2 5 5\n");
+    }
+
     fn assert_parse_interpret(program: &[u8], input: &str, output: BfResult<&str>) {
         let program = crate::ast::parse_program(program).unwrap();
         let program = crate::rle::compile(&program);
@@ -20,27 +20,86 @@
 //! This library implements a number of compilation passes:
 //!
 //!  - First, Brainfuck concrete syntax is parsed into
-//! [an abstract syntax tree](ast/index.html).
+//!    [an abstract syntax tree](ast/index.html). Programs in substitution dialects such as
+//!    Ook! are [translated to Brainfuck](dialects/index.html) first. The parser can also accept
+//!    the [multi-tape extension](ast/struct.ParseOptions.html), whose `@` command switches
+//!    between several tapes; only the interpreters run it with more than one tape.
 //!
 //!  - Then, repeated sequences of the same command are
-//! [run-length encoded](rle/index.html).
+//!    [run-length encoded](rle/index.html).
 //!
 //!  - Then, common loop forms are converted to new (non-Brainfuck)
-//! instructions by the [peephole optimizer](peephole/index.html).
+//!    instructions by the [peephole optimizer](peephole/index.html). The same rewrites are
+//!    available as [pluggable passes](pass/index.html), so that callers can add their own.
 //!
 //!  - The peephole output can be [flattened to bytecode](bytecode/index.html),
-//! which is then interpreted.
+//!    which is then interpreted. A Brainfuck file can also be compiled to bytecode while the
+//!    Rust program that embeds it compiles, with [`include_bf!`](macro.include_bf.html).
+//!
+//!  - The bytecode can have common pairs of instructions
+//!    [fused into superinstructions](fused/index.html), which cuts interpreter dispatch.
+//!
+//!  - The bytecode can also be run by a [direct-threaded
+//!    interpreter](bytecode/threaded/index.html), a faster option where the JIT isn't available.
 //!
 //!  - Or, if the `jit` feature is enabled (nightly only), the peephole output
-//! can be [just-in-time compiled to x64 machine code](jit/index.html).
+//!    can be [just-in-time compiled to x64 machine code](jit/index.html).
 //!
 //!  - Or, if the `llvm` feature is enabled (LLVM ≥ 3.8 must be in the PATH to build),
-//! the peephole output can be [JIT compiled using LLVM](llvm/index.html).
-//! (This is quite slow right now.)
+//!    the peephole output can be [JIT compiled using LLVM](llvm/index.html).
+//!    (This is quite slow right now.)
 //!
 //! Interpreters are provided for the intermediate forms as well. In particular,
 //! all representations of Brainfuck programs implement the
-//! [`Interpretable`](traits/trait.Interpretable.html) trait.
+//! [`Interpretable`](traits/trait.Interpretable.html) trait, and each can be compiled to any
+//! later form by naming it, as in `program.compile::<bytecode::Program>()`, with the
+//! [`Compile`](traits/trait.Compile.html) trait, or a [backend](backend/index.html) can be
+//! chosen by name at run time. Compiled bytecode can be kept in an on-disk
+//! [cache](cache/index.html), so that compiling an unchanged program again is a lookup, and
+//! programs can run as a [pipeline](pipeline/index.html) of filters, each on its own thread.
+//! Conventions that vary between Brainfuck implementations, such as what `,` does at the end of
+//! input and whether cells wrap, saturate, or trap when they overflow, are bundled as
+//! [semantics](semantics/index.html). Source can be [reformatted or minified](format/index.html),
+//! and any program form can be printed back as Brainfuck with
+//! [`ToSource`](traits/trait.ToSource.html) or drawn as a [control-flow graph](graph/index.html),
+//! and [statistics](stats/index.html) compare the size of each form. The
+//! [debugger](debugger/index.html) steps through bytecode, with breakpoints and watchpoints,
+//! and a run's input and output can be [recorded and replayed](replay/index.html), or its
+//! input can be [random](random/index.html) bytes that a seed chooses. A run can
+//! also be [traced](trace/index.html) as JSON Lines, one record per instruction, or summed up
+//! in a [heat map](heatmap/index.html) of how often each cell was read and written. Untrusted
+//! programs can run in a [sandbox](sandbox/index.html) that limits their steps, output, memory, and
+//! running time, and that another thread can interrupt. [Symbolic execution](symexec/index.html)
+//! runs a program on every input up to a length at once, which can prove that an optimization keeps
+//! what it does, and the [linter](lint/index.html) reports loops that never stop and other definite
+//! bugs, while [termination analysis](termination/index.html) finds the loops that provably do.
+//! Going the other way, a [generator](gen/index.html) writes short programs that print given
+//! text, and the [obfuscator](obfuscate/index.html) rewrites a program into an equivalent one
+//! that looks different, and the [assembly emitter](asm/index.html) renders the JIT's lowering
+//! as x86-64 assembly text for `as` or `nasm`, while the experimental [ELF](elf/index.html) and
+//! [PE](pe/index.html) writers encode it as a tiny Linux or Windows executable with no linker.
+//!
+//! Without the JIT features, the library also builds for `wasm32-unknown-unknown`, and the
+//! `wasm` feature adds [JavaScript bindings](wasm/index.html). The `ffi` feature adds a
+//! [C interface](ffi/index.html), and the `python` feature adds
+//! [Python bindings](python/index.html). The `serde` feature implements `serde::Serialize` for
+//! every program form, so that other tools can read the compiler's output as JSON.
+//!
+//! The `tracing` feature records each phase of the pipeline with the
+//! [`tracing`](https://docs.rs/tracing) crate, under the target `bf`. Each compilation pass, from
+//! parsing to the JIT and including each pass of a [`PassManager`](pass/struct.PassManager.html),
+//! runs in a `pass` span and ends with an event whose fields are the name of the `pass`, the
+//! program's `size_in` and `size_out` in instructions, counting a loop as two, or in bytes for
+//! the source and for machine code, and the `micros` that it took. Running a program with
+//! [`Backend::interpret_in`](backend/enum.Backend.html#method.interpret_in) is a `run` span, whose
+//! event gives the `backend`, the `micros`, and whether it was `ok`.
+//!
+//! The default `cli` feature builds `bfi` and brings in the `tools` feature, which adds the
+//! debugger, tracer, profiler, formatter, dialects, macro preprocessor, statistics, conformance
+//! checks, symbolic execution, linter, termination analysis, text-to-Brainfuck generator,
+//! obfuscator, assembly emitter, ELF and PE writers, and fuzzing entry points. A program that only
+//! embeds an interpreter can depend on this crate with `default-features = false`, which leaves the
+//! parser, the compilers, and the interpreters, with no dependency but `memchr`.
 
 #[cfg(feature = "jit")]
 extern crate dynasmrt;
@@ -48,13 +107,70 @@ extern crate dynasmrt;
 #[cfg(feature = "llvm")]
 extern crate llvm_sys;
 
+pub mod analysis;
+#[cfg(feature = "tools")]
+pub mod asm;
+pub mod backend;
+pub mod batch;
+pub mod cache;
 pub mod common;
+#[cfg(feature = "tools")]
+pub mod conformance;
+pub mod counters;
+#[cfg(feature = "tools")]
+pub mod debugger;
+#[cfg(feature = "tools")]
+pub mod dialects;
+#[cfg(feature = "tools")]
+pub mod elf;
+#[cfg(feature = "tools")]
+pub mod format;
+#[cfg(feature = "tools")]
+pub mod fuzz;
+#[cfg(feature = "tools")]
+pub mod r#gen;
+#[cfg(feature = "tools")]
+pub mod graph;
+pub mod heatmap;
+mod instrument;
+#[cfg(feature = "tools")]
+pub mod lint;
+pub mod machine;
+pub mod pass;
+#[cfg(feature = "tools")]
+pub mod pe;
+pub mod pipeline;
+#[cfg(feature = "tools")]
+pub mod obfuscate;
+#[cfg(feature = "tools")]
+pub mod preprocess;
+#[cfg(feature = "tools")]
+pub mod profile;
+#[cfg(feature = "tools")]
+pub mod random;
+#[cfg(feature = "tools")]
+pub mod replay;
+#[cfg(target_arch = "x86_64")]
 pub mod rts;
+pub mod sandbox;
+pub mod semantics;
 pub mod state;
+#[cfg(feature = "tools")]
+pub mod stats;
+#[cfg(feature = "tools")]
+pub mod symexec;
+mod tape;
+#[cfg(feature = "tools")]
+pub mod termination;
+#[cfg(feature = "tools")]
+pub mod trace;
 pub mod traits;
+#[cfg(feature = "tools")]
+mod x64;
 
 pub mod ast;
 pub mod bytecode;
+pub mod fused;
 pub mod peephole;
 pub mod rle;
 
@@ -67,4 +183,14 @@ pub mod cranelift;
 #[cfg(feature = "llvm")]
 pub mod llvm;
 
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+#[cfg(feature = "python")]
+pub mod python;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+#[cfg(any(test, feature = "tools"))]
 pub mod test_helpers;
@@ -0,0 +1,441 @@
+//! Finding definite bugs in Brainfuck source.
+//!
+//! [`lint`](fn.lint.html) reports code that can't do what its author meant, whatever the input:
+//!
+//!  - a loop whose body never changes the cell that it tests, so that once it starts it never
+//!    stops;
+//!  - a `<` that moves the pointer left of cell 0 whenever it runs;
+//!  - a `+` or `-` whose result nothing reads, because a `[-]` clears the cell first or the
+//!    program ends;
+//!  - code after a loop that is sure to start and never stops.
+//!
+//! It runs an abstract interpretation of the source, which tracks the pointer as an
+//! [`Interval`](../analysis/struct.Interval.html), as the [bounds
+//! analysis](../analysis/index.html) does, along with the values of the cells near the pointer
+//! where they are known. A loop that starts on a cell known to be zero never runs, so the
+//! comments in a loop at the start of a program aren't linted. Since `,` leaves the cell
+//! unchanged at the end of input under some [conventions](../semantics/index.html), it counts
+//! as reading the cell rather than overwriting it.
+//!
+//! In `bfi`, run `bfi lint` to lint a program.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+use crate::analysis::Interval;
+use crate::ast::{self, ParseError, ParseOptions, Position};
+use crate::common::Command;
+
+/// A kind of bug.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum LintKind {
+    /// A loop whose body never changes the cell that it tests. The position is its `[`.
+    InfiniteLoop,
+    /// A `<` that moves the pointer left of cell 0 whenever it runs.
+    Underflow,
+    /// A `+` or `-` that changes a cell that is cleared, or never used again, before anything
+    /// reads it. The position is the first of the changes.
+    DeadStore,
+    /// Code after a loop that is sure to start and never stops. The position is the first
+    /// command after the loop.
+    Unreachable,
+}
+
+impl fmt::Display for LintKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            LintKind::InfiniteLoop => {
+                "loop never stops once it starts, because its body never changes the cell it tests"
+            }
+            LintKind::Underflow => "pointer always moves left of cell 0 here",
+            LintKind::DeadStore => "cell is changed here, but nothing reads the change",
+            LintKind::Unreachable => "code never runs, because the loop before it never stops",
+        })
+    }
+}
+
+/// A bug, with where it is in the source.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Lint {
+    /// What is wrong.
+    pub kind: LintKind,
+    /// Where it is.
+    pub position: Position,
+}
+
+impl Lint {
+    /// Shows the line of `src` with the bug, which must be the source that was linted, and a
+    /// caret under it.
+    pub fn snippet(&self, src: &[u8]) -> String {
+        self.position.snippet(src)
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {}",
+            self.kind, self.position.line, self.position.column
+        )
+    }
+}
+
+/// Finds the definite bugs in a program, in the order they appear in the source.
+///
+/// # Errors
+///
+/// The same as [`parse_with_positions`](../ast/fn.parse_with_positions.html).
+pub fn lint(src: &[u8], options: &ParseOptions) -> Result<Vec<Lint>, ParseError> {
+    ast::parse_with_positions(src, options)?;
+    let program = scan(src, options);
+
+    let mut linter = Linter {
+        found: BTreeSet::new(),
+        invariants: HashMap::new(),
+    };
+    if let Some(end) = linter.sequence(&program, State::start(), true) {
+        for &offset in end.stores.values() {
+            linter.found.insert((offset, LintKind::DeadStore));
+        }
+    }
+
+    Ok(linter
+        .found
+        .into_iter()
+        .map(|(offset, kind)| Lint {
+            kind,
+            position: Position::new(src, offset),
+        })
+        .collect())
+}
+
+/// A command or a loop, with the offset in the source of the command or the `[`.
+#[derive(Debug)]
+pub(crate) enum Node {
+    Command(Command, usize),
+    Loop(Vec<Node>, usize),
+}
+
+impl Node {
+    pub(crate) fn offset(&self) -> usize {
+        match *self {
+            Node::Command(_, offset) | Node::Loop(_, offset) => offset,
+        }
+    }
+}
+
+/// Reads the commands of source that is known to parse.
+pub(crate) fn scan(src: &[u8], options: &ParseOptions) -> Vec<Node> {
+    use crate::common::Command::*;
+
+    let mut open = Vec::new();
+    let mut current = Vec::new();
+
+    for (offset, &byte) in src.iter().enumerate() {
+        let command = match byte {
+            b'<' => Left,
+            b'>' => Right,
+            b'+' => Up,
+            b'-' => Down,
+            b',' => In,
+            b'.' => Out,
+            b'@' if options.multi_tape => SwitchTape,
+            b'[' => {
+                open.push((offset, std::mem::take(&mut current)));
+                continue;
+            }
+            b']' => {
+                let (begin, outer) = open.pop().expect("the source parsed already");
+                let body = std::mem::replace(&mut current, outer);
+                current.push(Node::Loop(body, begin));
+                continue;
+            }
+            _ => continue,
+        };
+        current.push(Node::Command(command, offset));
+    }
+
+    current
+}
+
+/// What the linter knows at a point in the program.
+#[derive(Clone, Debug)]
+struct State {
+    pointer: Interval,
+    /// Where the pointer is, relative to where the knowledge of the cells starts.
+    here: isize,
+    /// The values of cells, by address relative to the start, where they are known or have
+    /// changed since the start.
+    cells: BTreeMap<isize, Option<u8>>,
+    /// The value of every other cell, if known.
+    rest: Option<u8>,
+    /// The first change to each cell since it was last read, with its offset in the source.
+    stores: BTreeMap<isize, usize>,
+}
+
+impl State {
+    /// The state at the start of the program, where every cell is zero.
+    fn start() -> Self {
+        State {
+            pointer: Interval::start(),
+            here: 0,
+            cells: BTreeMap::new(),
+            rest: Some(0),
+            stores: BTreeMap::new(),
+        }
+    }
+
+    /// The state at the start of a loop body, with the given pointer invariant.
+    fn loop_body(pointer: Interval) -> Self {
+        State {
+            pointer,
+            here: 0,
+            cells: BTreeMap::new(),
+            rest: None,
+            stores: BTreeMap::new(),
+        }
+    }
+
+    /// The state after a loop, where the cell at the pointer is zero.
+    fn after_loop(pointer: Interval) -> Self {
+        let mut result = State::loop_body(pointer);
+        result.cells.insert(0, Some(0));
+        result
+    }
+
+    /// The value of the cell at the pointer, if known.
+    fn value(&self) -> Option<u8> {
+        self.cells.get(&self.here).copied().unwrap_or(self.rest)
+    }
+
+    fn set(&mut self, value: Option<u8>) {
+        self.cells.insert(self.here, value);
+    }
+}
+
+struct Linter {
+    /// The offset and kind of each bug found.
+    found: BTreeSet<(usize, LintKind)>,
+    /// Cached loop invariants, by the offset of the loop and the pointer on entry.
+    invariants: HashMap<(usize, Interval), Interval>,
+}
+
+impl Linter {
+    /// Analyzes a sequence of nodes, starting from the given state, and recording bugs if
+    /// `report` is set.
+    ///
+    /// Returns the state after the sequence, or `None` if it never gets to the end.
+    fn sequence(&mut self, nodes: &[Node], mut state: State, report: bool) -> Option<State> {
+        use crate::common::Command::*;
+
+        for (index, node) in nodes.iter().enumerate() {
+            match *node {
+                Node::Command(command, offset) => match command {
+                    Right => {
+                        state.pointer.move_right(1);
+                        state.here += 1;
+                    }
+                    Left => {
+                        if state.pointer.disproves_left(1) {
+                            self.report(report, offset, LintKind::Underflow);
+                            return None;
+                        }
+                        state.pointer.move_left(1);
+                        state.here -= 1;
+                    }
+                    Up | Down => {
+                        let amount = if command == Up { 1 } else { u8::MAX };
+                        let value = state.value().map(|value| value.wrapping_add(amount));
+                        state.set(value);
+                        state.stores.entry(state.here).or_insert(offset);
+                    }
+                    In => {
+                        state.stores.remove(&state.here);
+                        state.set(None);
+                    }
+                    Out => {
+                        state.stores.remove(&state.here);
+                    }
+                    SwitchTape => {
+                        state.pointer.switch_tape();
+                        state = State::loop_body(state.pointer);
+                    }
+                    Begin | End => unreachable!("loops are nodes of their own"),
+                },
+
+                Node::Loop(ref body, offset) => {
+                    if is_clear_loop(body) {
+                        if let Some(store) = state.stores.remove(&state.here) {
+                            self.report(report, store, LintKind::DeadStore);
+                        }
+                        state.set(Some(0));
+                        continue;
+                    }
+
+                    state.stores.remove(&state.here);
+                    let entry = state.value();
+                    if entry == Some(0) {
+                        continue;
+                    }
+
+                    let invariant = self.invariant(body, offset, state.pointer);
+                    let finishes = self
+                        .sequence(body, State::loop_body(invariant), report)
+                        .is_some();
+
+                    if never_changes_cell(body) {
+                        self.report(report, offset, LintKind::InfiniteLoop);
+                        if entry.is_some() {
+                            if let Some(next) = nodes.get(index + 1) {
+                                self.report(report, next.offset(), LintKind::Unreachable);
+                            }
+                            return None;
+                        }
+                    }
+                    if !finishes && entry.is_some() {
+                        return None;
+                    }
+
+                    state = State::after_loop(invariant);
+                }
+            }
+        }
+
+        Some(state)
+    }
+
+    /// Computes the pointer invariant at the head of a loop, given the pointer on entry, by
+    /// iterating the analysis of the body, with widening, until it reaches a fixed point.
+    fn invariant(&mut self, body: &[Node], offset: usize, entry: Interval) -> Interval {
+        if let Some(&invariant) = self.invariants.get(&(offset, entry)) {
+            return invariant;
+        }
+
+        let mut invariant = entry;
+        loop {
+            let after = self
+                .sequence(body, State::loop_body(invariant), false)
+                .map_or(invariant, |state| state.pointer);
+            let next = invariant.widen(after);
+            if next == invariant {
+                break;
+            }
+            invariant = next;
+        }
+
+        self.invariants.insert((offset, entry), invariant);
+        invariant
+    }
+
+    fn report(&mut self, report: bool, offset: usize, kind: LintKind) {
+        if report {
+            self.found.insert((offset, kind));
+        }
+    }
+}
+
+/// Whether a loop body is a run of `+` and `-` that adds an odd amount, which clears the cell.
+fn is_clear_loop(body: &[Node]) -> bool {
+    let mut total = 0u8;
+    for node in body {
+        match *node {
+            Node::Command(Command::Up, _) => total = total.wrapping_add(1),
+            Node::Command(Command::Down, _) => total = total.wrapping_sub(1),
+            _ => return false,
+        }
+    }
+    total % 2 == 1
+}
+
+/// Whether a loop body always returns to where it began without changing the cell there.
+fn never_changes_cell(body: &[Node]) -> bool {
+    let mut writes = BTreeSet::new();
+    moves(body, 0, &mut writes) == Some(0) && !writes.contains(&0)
+}
+
+/// How far a loop body moves the pointer, if always the same distance, adding the addresses
+/// relative to where it began of the cells it may change, plus `start`, to `writes`.
+pub(crate) fn moves(body: &[Node], start: isize, writes: &mut BTreeSet<isize>) -> Option<isize> {
+    let mut here = start;
+
+    for node in body {
+        match *node {
+            Node::Command(Command::Right, _) => here += 1,
+            Node::Command(Command::Left, _) => here -= 1,
+            Node::Command(Command::Up | Command::Down | Command::In, _) => {
+                writes.insert(here);
+            }
+            Node::Command(Command::Out, _) => (),
+            Node::Command(..) => return None,
+            Node::Loop(ref body, _) => {
+                if moves(body, here, writes)? != 0 {
+                    return None;
+                }
+            }
+        }
+    }
+
+    Some(here - start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    fn lints(src: &[u8]) -> Vec<(LintKind, usize, usize)> {
+        lint(src, &ParseOptions::default())
+            .unwrap()
+            .into_iter()
+            .map(|lint| (lint.kind, lint.position.line, lint.position.column))
+            .collect()
+    }
+
+    #[test]
+    fn finds_infinite_loops() {
+        use self::LintKind::*;
+
+        assert_eq!(lints(b",[>+<.]"), [(InfiniteLoop, 1, 2)]);
+        assert_eq!(
+            lints(b"+[>[-]+<]\n>."),
+            [(InfiniteLoop, 1, 2), (Unreachable, 2, 1)]
+        );
+        assert_eq!(lints(b",[>[-<+>]<-]"), []);
+        // The loop at the start never runs, so it can hold comments.
+        assert_eq!(lints(b"[a comment.]"), []);
+    }
+
+    #[test]
+    fn finds_underflow() {
+        use self::LintKind::*;
+
+        assert_eq!(lints(b"+.>-.<<."), [(Underflow, 1, 7)]);
+        assert_eq!(lints(b"+[.<]"), [(Underflow, 1, 4)]);
+        assert_eq!(lints(b">+[.<]"), []);
+        assert_eq!(lints(b",[>,]<[.<]"), []);
+    }
+
+    #[test]
+    fn finds_dead_stores() {
+        use self::LintKind::*;
+
+        assert_eq!(lints(b"++>+.<[-]."), [(DeadStore, 1, 1)]);
+        assert_eq!(lints(b".>+++<."), [(DeadStore, 1, 3)]);
+        // The input can leave the cell unchanged.
+        assert_eq!(lints(b"-,."), []);
+        assert_eq!(lints(b",[->+<]>."), []);
+    }
+
+    #[test]
+    fn passes_real_programs() {
+        assert_eq!(lints(HELLO_WORLD_SRC), []);
+        assert_eq!(lints(FACTOR_SRC), []);
+    }
+
+    #[test]
+    fn reports_syntax_errors() {
+        let error = lint(b"+[", &ParseOptions::default()).unwrap_err();
+        assert_eq!(error.position.offset, 1);
+    }
+}
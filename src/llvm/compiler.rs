@@ -1,9 +1,10 @@
-use std::io;
+use std::io::{self, Read, Write};
 
 use crate::common::{BfResult, Count, Error};
 use crate::peephole;
 use crate::rts::{self, RtsState};
-use crate::state::DEFAULT_CAPACITY;
+use crate::semantics::Overflow;
+use crate::state::State;
 
 use super::wrapper::*;
 
@@ -16,10 +17,52 @@ pub trait LlvmCompilable {
 
     /// JIT compile and run the given program via LLVM.
     fn llvm_run(&self, memory_size: Option<usize>) -> BfResult<()> {
-        let mut stdin = io::stdin();
-        let mut stdout = io::stdout();
-        let rts_state = RtsState::new(&mut stdin, &mut stdout);
-        self.with_peephole(|ast| compile_and_run(ast, memory_size, false, rts_state))
+        self.llvm_run_with(memory_size, io::stdin(), io::stdout())
+    }
+
+    /// JIT compile and run the given program via LLVM, with the given input and output.
+    fn llvm_run_with<R: Read, W: Write>(
+        &self,
+        memory_size: Option<usize>,
+        input: R,
+        output: W,
+    ) -> BfResult<()> {
+        let mut state = memory_size.map(State::with_capacity).unwrap_or_default();
+        self.llvm_run_in(&mut state, input, output)
+    }
+
+    /// JIT compile and run the given program via LLVM against the given state, leaving the
+    /// final tape and pointer in place.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the state has more than one tape, or if its cells don't wrap.
+    fn llvm_run_in<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        mut input: R,
+        mut output: W,
+    ) -> BfResult<()> {
+        assert_eq!(
+            state.overflow(),
+            Overflow::Wrap,
+            "compiled code supports only wrapping cells"
+        );
+        let mut rts_state = RtsState::new(&mut input, &mut output);
+        rts_state.set_eof(state.eof());
+        self.with_peephole(|ast| compile_and_run(ast, state, false, rts_state))
+    }
+
+    /// JIT compile and run the given program via LLVM against the given state, returning the
+    /// final state.
+    fn llvm_run_into_state<R: Read, W: Write>(
+        &self,
+        mut state: State,
+        input: R,
+        output: W,
+    ) -> BfResult<State> {
+        self.llvm_run_in(&mut state, input, output)?;
+        Ok(state)
     }
 }
 
@@ -45,22 +88,30 @@ struct Compiler<'a> {
     read_function: Value<'a>,
     /// RtsSate::write_c
     write_function: Value<'a>,
+    /// RtsState::set_pointer_c
+    set_pointer_function: Value<'a>,
     /// The program’s memory (“tape”)
     memory: Value<'a>,
     /// The current offset into memory
     pointer: Value<'a>,
 }
 
-/// JIT compile and run the given program via LLVM.
+/// JIT compile and run the given program via LLVM, in the memory of the given state.
+///
+/// # Panics
+///
+/// Panics if the state has more than one tape.
 pub fn compile_and_run<'a>(
     program: &peephole::Program,
-    memory_size: Option<usize>,
+    state: &mut State,
     debug: bool,
     mut rts_state: RtsState<'a>,
 ) -> BfResult<()> {
+    assert_eq!(state.tapes(), 1, "compiled code supports only one tape");
+
     let context = Context::new();
 
-    let compiler = Compiler::prologue(&context, memory_size.unwrap_or(DEFAULT_CAPACITY) as u64);
+    let compiler = Compiler::prologue(&context, state.capacity() as u64, state.pointer() as u64);
     compiler.compile_block(program);
     compiler.epilogue();
 
@@ -79,17 +130,25 @@ pub fn compile_and_run<'a>(
                 "bfi_main",
                 |f: extern "C" fn(
                     rts_state: &mut RtsState<'a>,
-                    read: extern "C" fn(&mut RtsState<'a>) -> u8,
+                    read: extern "C" fn(&mut RtsState<'a>, u8) -> u8,
                     write: extern "C" fn(&mut RtsState<'a>, u8) -> (),
+                    set_pointer: extern "C" fn(&mut RtsState<'a>, u64) -> (),
+                    memory: *mut u8,
                 ) -> u64| {
-                    f(&mut rts_state, RtsState::read_c, RtsState::write_c)
+                    f(
+                        &mut rts_state,
+                        RtsState::read_c,
+                        RtsState::write_c,
+                        RtsState::set_pointer_c,
+                        state.as_mut_ptr(),
+                    )
                 },
             )
             .unwrap()
     };
 
     match result {
-        rts::OKAY => Ok(()),
+        rts::OKAY => state.set_pointer(rts_state.pointer()),
         rts::UNDERFLOW => Err(Error::PointerUnderflow),
         rts::OVERFLOW => Err(Error::PointerOverflow),
         _ => panic!("unrecognized error code"),
@@ -123,7 +182,8 @@ impl<'a> Compiler<'a> {
                 }
 
                 Instr(In) => {
-                    let result = builder.call(self.read_function, &[self.rts_state], "");
+                    let current = self.load_data("current");
+                    let result = builder.call(self.read_function, &[self.rts_state, current], "");
                     self.store_data(result);
                 }
 
@@ -132,10 +192,21 @@ impl<'a> Compiler<'a> {
                     builder.call(self.write_function, &[self.rts_state, argument], "");
                 }
 
+                Instr(WriteStr(ref bytes)) => {
+                    for &byte in bytes.iter() {
+                        let argument = Value::get_u8(self.context, byte);
+                        builder.call(self.write_function, &[self.rts_state, argument], "");
+                    }
+                }
+
                 Instr(SetZero) => {
                     self.store_data(Value::get_u8(self.context, 0));
                 }
 
+                Instr(SetConst(value)) => {
+                    self.store_data(Value::get_u8(self.context, value));
+                }
+
                 Instr(FindZeroRight(count)) => {
                     let instr = Loop(vec![Instr(Right(count))].into_boxed_slice());
                     self.compile_block(&[instr]);
@@ -182,6 +253,60 @@ impl<'a> Compiler<'a> {
                     builder.position_at_end(after);
                 }
 
+                Instr(MulAddRight(count, factor)) => {
+                    let do_it = self.main_function.append("do_it");
+                    let after = self.main_function.append("after");
+
+                    self.if_not0(do_it, after);
+
+                    builder.position_at_end(do_it);
+                    let pointer = self.load_pos_offset(count, "offset_ptr");
+                    let value = self.load_data("value");
+                    let factor = Value::get_u8(self.context, factor);
+                    let to_add = builder.mul(value, factor, "to_add");
+                    let add_to = self.load_data_at(pointer, "add_to");
+                    let sum = builder.add(to_add, add_to, "sum");
+                    self.store_data_at(pointer, sum);
+                    builder.br(after);
+
+                    builder.position_at_end(after);
+                }
+
+                Instr(MulAddLeft(count, factor)) => {
+                    let do_it = self.main_function.append("do_it");
+                    let after = self.main_function.append("after");
+
+                    self.if_not0(do_it, after);
+
+                    builder.position_at_end(do_it);
+                    let pointer = self.load_neg_offset(count, "offset_ptr");
+                    let value = self.load_data("value");
+                    let factor = Value::get_u8(self.context, factor);
+                    let to_add = builder.mul(value, factor, "to_add");
+                    let add_to = self.load_data_at(pointer, "add_to");
+                    let sum = builder.add(to_add, add_to, "sum");
+                    self.store_data_at(pointer, sum);
+                    builder.br(after);
+
+                    builder.position_at_end(after);
+                }
+
+                Instr(AddAt(offset, amount)) => {
+                    let pointer = self.load_offset(offset, "offset_ptr");
+                    let add_to = self.load_data_at(pointer, "add_to");
+                    let amount = Value::get_u8(self.context, amount);
+                    let sum = builder.add(add_to, amount, "sum");
+                    self.store_data_at(pointer, sum);
+                }
+
+                Instr(SetAt(offset, value)) => {
+                    let pointer = self.load_offset(offset, "offset_ptr");
+                    self.store_data_at(pointer, Value::get_u8(self.context, value));
+                }
+
+                // The compiled program has a single tape, where switching does nothing.
+                Instr(SwitchTape(_)) => (),
+
                 Instr(JumpZero(_)) | Instr(JumpNotZero(_)) => panic!("unexpected instruction"),
 
                 Loop(ref body) => {
@@ -205,14 +330,12 @@ impl<'a> Compiler<'a> {
     }
 
     /// Set up compilation.
-    fn prologue(context: &'a Context, memory_size: u64) -> Self {
+    fn prologue(context: &'a Context, memory_size: u64, pointer: u64) -> Self {
         let module = Module::new(context, "bfi_module");
 
         // Some useful types
         let i64_type = Type::get_i64(context);
-        let i32_type = Type::get_i32(context);
         let i8_type = Type::get_i8(context);
-        let bool_type = Type::get_bool(context);
         let void_type = Type::get_void(context);
         let char_ptr_type = Type::get_pointer(i8_type);
 
@@ -221,7 +344,8 @@ impl<'a> Compiler<'a> {
 
         let rts_state_type = Type::get_pointer(Type::get_void(context));
         let write_function_type = Type::get_function(&[rts_state_type, i8_type], void_type);
-        let read_function_type = Type::get_function(&[rts_state_type], i8_type);
+        let read_function_type = Type::get_function(&[rts_state_type, i8_type], i8_type);
+        let set_pointer_function_type = Type::get_function(&[rts_state_type, i64_type], void_type);
 
         // Create the main function, create an entry basic block, and position a builder at entry.
         let main_function_type = Type::get_function(
@@ -229,6 +353,8 @@ impl<'a> Compiler<'a> {
                 rts_state_type,
                 Type::get_pointer(read_function_type),
                 Type::get_pointer(write_function_type),
+                Type::get_pointer(set_pointer_function_type),
+                char_ptr_type,
             ],
             i64_type,
         );
@@ -247,40 +373,25 @@ impl<'a> Compiler<'a> {
             memory_size,
             main_function,
             pointer: builder.alloca(i64_type, "pointer"),
-            memory: builder.array_alloca(i8_type, memory_size, "memory"),
+            // The memory belongs to the state that the program runs in.
+            memory: main_function.get_fun_param(4),
             rts_state: main_function.get_fun_param(0),
             read_function: main_function.get_fun_param(1),
             write_function: main_function.get_fun_param(2),
+            set_pointer_function: main_function.get_fun_param(3),
         };
 
-        // Zero-initialize the memory
-        let memset_type = Type::get_function(
-            &[char_ptr_type, i8_type, i64_type, i32_type, bool_type],
-            void_type,
-        );
-        let memset = compiler
-            .module
-            .add_function("llvm.memset.p0i8.i64", memset_type);
-        builder.call(
-            memset,
-            &[
-                compiler.memory,
-                Value::get_u8(context, 0),
-                compiler.memory_size,
-                Value::get_u32(context, 0),
-                Value::get_bool(context, false),
-            ],
-            "",
-        );
-
-        // Start the data pointer at 0.
-        builder.store(Value::get_u64(context, 0), compiler.pointer);
+        // Start the data pointer where the state's is.
+        builder.store(Value::get_u64(context, pointer), compiler.pointer);
 
         compiler
     }
 
     /// Emit the returns for the successful path and both error paths.
     fn epilogue(&self) {
+        let pointer = self.builder.load(self.pointer, "final_pointer");
+        self.builder
+            .call(self.set_pointer_function, &[self.rts_state, pointer], "");
         self.builder.ret(Value::get_u64(self.context, rts::OKAY));
 
         self.builder.position_at_end(self.underflow);
@@ -341,6 +452,16 @@ impl<'a> Compiler<'a> {
     }
 
     /// Subtract the given offset from the data pointer, checking for underflow.
+    /// Add the given offset, which may be negative, to the data pointer, checking for overflow
+    /// and underflow.
+    fn load_offset(&self, offset: isize, name: &str) -> Value<'a> {
+        if offset < 0 {
+            self.load_neg_offset(offset.unsigned_abs() as Count, name)
+        } else {
+            self.load_pos_offset(offset as Count, name)
+        }
+    }
+
     fn load_neg_offset(&self, offset: Count, name: &str) -> Value<'a> {
         let success = self.main_function.append("left_success");
         let old_pointer = self.builder.load(self.pointer, "old_pointer");
@@ -180,18 +180,10 @@ impl<'a> Type<'a> {
         context.wrap_type(unsafe { LLVMInt64TypeInContext(context.context_ref) })
     }
 
-    pub fn get_i32(context: &'a Context) -> Self {
-        context.wrap_type(unsafe { LLVMInt32TypeInContext(context.context_ref) })
-    }
-
     pub fn get_i8(context: &'a Context) -> Self {
         context.wrap_type(unsafe { LLVMInt8TypeInContext(context.context_ref) })
     }
 
-    pub fn get_bool(context: &'a Context) -> Self {
-        context.wrap_type(unsafe { LLVMInt1TypeInContext(context.context_ref) })
-    }
-
     pub fn get_void(context: &'a Context) -> Self {
         context.wrap_type(unsafe { LLVMVoidTypeInContext(context.context_ref) })
     }
@@ -239,23 +231,11 @@ impl<'a> Value<'a> {
         })
     }
 
-    pub fn get_u32(context: &'a Context, value: u32) -> Self {
-        context.wrap_value(unsafe {
-            LLVMConstInt(Type::get_i32(context).type_ref, value as _, false as _)
-        })
-    }
-
     pub fn get_u8(context: &'a Context, value: u8) -> Self {
         context.wrap_value(unsafe {
             LLVMConstInt(Type::get_i8(context).type_ref, value as _, false as _)
         })
     }
-
-    pub fn get_bool(context: &'a Context, value: bool) -> Self {
-        context.wrap_value(unsafe {
-            LLVMConstInt(Type::get_bool(context).type_ref, value as _, false as _)
-        })
-    }
 }
 
 #[derive(Copy, Clone)]
@@ -296,13 +276,6 @@ impl<'a> Builder<'a> {
             .wrap_value(unsafe { LLVMBuildAlloca(self.builder_ref, ty.type_ref, name) })
     }
 
-    pub fn array_alloca(&self, ty: Type<'a>, size: Value<'a>, name: &str) -> Value<'a> {
-        let name = self.context.new_name(name);
-        self.context.wrap_value(unsafe {
-            LLVMBuildArrayAlloca(self.builder_ref, ty.type_ref, size.value_ref, name)
-        })
-    }
-
     pub fn br(&self, dst: BasicBlock<'a>) {
         unsafe {
             LLVMBuildBr(self.builder_ref, dst.bb_ref);
@@ -365,6 +338,12 @@ impl<'a> Builder<'a> {
             .wrap_value(unsafe { LLVMBuildLoad(self.builder_ref, ptr.value_ref, name) })
     }
 
+    pub fn mul(&self, v1: Value<'a>, v2: Value<'a>, name: &str) -> Value<'a> {
+        let name = self.context.new_name(name);
+        self.context
+            .wrap_value(unsafe { LLVMBuildMul(self.builder_ref, v1.value_ref, v2.value_ref, name) })
+    }
+
     pub fn ret(&self, value: Value<'a>) {
         unsafe {
             LLVMBuildRet(self.builder_ref, value.value_ref);
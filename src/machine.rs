@@ -0,0 +1,111 @@
+//! A compiled program together with its memory, for running many times.
+//!
+//! Each call to [`Interpretable::interpret`](../traits/trait.Interpretable.html#method.interpret)
+//! allocates fresh memory. A [`Machine`](struct.Machine.html) instead keeps one
+//! [`State`](../state/struct.State.html) and resets it before each run, so running the same
+//! program over and over neither recompiles it nor reallocates its memory.
+//!
+//! ```
+//! use bf::ast;
+//! use bf::machine::Machine;
+//! use bf::traits::*;
+//!
+//! let program = ast::parse_program(b",[.,]").unwrap().bytecode_compile();
+//! let mut machine = Machine::new(program);
+//!
+//! assert_eq!(machine.run(b"hello"), Ok(b"hello".to_vec()));
+//! assert_eq!(machine.run(b"world"), Ok(b"world".to_vec()));
+//! ```
+
+use std::io::{Read, Write};
+
+use crate::common::BfResult;
+use crate::state::{State, DEFAULT_CAPACITY};
+use crate::traits::Interpretable;
+
+/// A program and a reusable memory.
+#[derive(Clone, Debug)]
+pub struct Machine<P> {
+    program: P,
+    state: State,
+}
+
+impl<P: Interpretable> Machine<P> {
+    /// Creates a machine with the default memory size.
+    pub fn new(program: P) -> Self {
+        Machine::with_capacity(program, DEFAULT_CAPACITY)
+    }
+
+    /// Creates a machine with the given memory size.
+    pub fn with_capacity(program: P, memory_size: usize) -> Self {
+        Machine {
+            program,
+            state: State::with_capacity(memory_size),
+        }
+    }
+
+    /// The program.
+    pub fn program(&self) -> &P {
+        &self.program
+    }
+
+    /// The state at the end of the last run.
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Runs the program from a fresh state on the given input, returning its output.
+    pub fn run(&mut self, input: &[u8]) -> BfResult<Vec<u8>> {
+        let mut output = Vec::new();
+        self.run_with(input, &mut output)?;
+        Ok(output)
+    }
+
+    /// Runs the program from a fresh state with the given input and output.
+    pub fn run_with<R: Read, W: Write>(&mut self, input: R, output: W) -> BfResult<()> {
+        self.state.reset();
+        self.program.interpret_in(&mut self.state, input, output)
+    }
+
+    /// Extracts the program.
+    pub fn into_program(self) -> P {
+        self.program
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast;
+    use crate::common::Error;
+    use crate::test_helpers::*;
+    use crate::traits::*;
+
+    #[test]
+    fn reruns_from_fresh_state() {
+        let program = ast::parse_program(b"+++.>+.").unwrap().peephole_compile();
+        let mut machine = Machine::with_capacity(program, 10);
+
+        assert_eq!(machine.run(b""), Ok(b"\x03\x01".to_vec()));
+        assert_eq!(machine.run(b""), Ok(b"\x03\x01".to_vec()));
+        assert_eq!(machine.state().capacity(), 10);
+    }
+
+    #[test]
+    fn recovers_after_error() {
+        let program = ast::parse_program(b",[>,]").unwrap().rle_compile();
+        let mut machine = Machine::with_capacity(program, 3);
+
+        assert_eq!(machine.run(b"abcd"), Err(Error::PointerOverflow));
+        assert_eq!(machine.run(b"ab"), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn factoring() {
+        let program = ast::parse_program(FACTOR_SRC).unwrap().threaded_compile();
+        let mut machine = Machine::new(program);
+
+        assert_eq!(machine.run(b"6\n"), Ok(b"6: 2 3\n".to_vec()));
+        assert_eq!(machine.run(b"100\n"), Ok(b"100: 2 2 5 5\n".to_vec()));
+    }
+}
@@ -0,0 +1,165 @@
+//! Rewriting programs into equivalent ones that look different.
+//!
+//! [`obfuscate`](fn.obfuscate.html) makes random rewrites, chosen by a seed, that never change
+//! what a program does, including where it fails at the ends of memory:
+//!
+//!  - `+-` or `-+` goes between commands;
+//!  - `>` becomes `><>`, and `<` becomes `<><`, which only visit cells that the original move
+//!    reaches from where it starts;
+//!  - a loop `[A]` whose body has no loops of its own becomes `[A[A]]`, which runs the body just
+//!    as often, since the inner loop runs it until the cell is zero;
+//!  - after a loop, or at the start of the program, where the cell is zero, goes a loop that never
+//!    runs, with random commands or a copy of the loop before it.
+//!
+//! The same seed always gives the same result. The rewrites give the optimizer something to
+//! undo, which makes for stress tests, and they make short programs harder to read by eye.
+
+use crate::ast::{Program, Statement};
+use crate::random::SplitMix;
+
+/// The chance, out of 256, of making each rewrite where it can go.
+const RATE: u64 = 64;
+
+/// The most commands in a loop that never runs.
+const MAX_JUNK: u64 = 8;
+
+/// Rewrites a program into an equivalent one, with rewrites chosen by `seed`.
+pub fn obfuscate(program: &Program, seed: u64) -> Box<Program> {
+    let mut obfuscator = Obfuscator {
+        random: SplitMix::new(seed),
+    };
+    obfuscator.sequence(program, true).into_boxed_slice()
+}
+
+struct Obfuscator {
+    random: SplitMix,
+}
+
+impl Obfuscator {
+    /// Rewrites a sequence of statements, where `zero` says whether the cell at the pointer is
+    /// known to be zero at the start.
+    fn sequence(&mut self, program: &Program, mut zero: bool) -> Vec<Statement> {
+        use crate::common::Command::*;
+
+        let mut result = Vec::with_capacity(program.len());
+        for statement in program {
+            if zero && self.chance() {
+                let dead = self.dead_loop(result.last());
+                result.push(dead);
+            }
+            if self.chance() {
+                let pair = if self.random.below(2) == 0 {
+                    [Up, Down]
+                } else {
+                    [Down, Up]
+                };
+                result.extend(pair.map(Statement::Cmd));
+            }
+
+            match *statement {
+                Statement::Cmd(command @ (Left | Right)) if self.chance() => {
+                    let back = if command == Left { Right } else { Left };
+                    result.extend([command, back, command].map(Statement::Cmd));
+                    zero = false;
+                }
+                Statement::Cmd(command) => {
+                    result.push(Statement::Cmd(command));
+                    zero = zero && command == Out;
+                }
+                Statement::Loop(ref body) => {
+                    let mut body = self.sequence(body, false);
+                    let flat = body.iter().all(|inner| matches!(*inner, Statement::Cmd(_)));
+                    if flat && self.chance() {
+                        let inner = Statement::Loop(body.clone().into_boxed_slice());
+                        body.push(inner);
+                    }
+                    result.push(Statement::Loop(body.into_boxed_slice()));
+                    zero = true;
+                }
+            }
+        }
+
+        if zero && self.chance() {
+            let dead = self.dead_loop(result.last());
+            result.push(dead);
+        }
+        result
+    }
+
+    /// A loop that never runs: half the time a copy of `previous` if it is a loop,
+    /// and otherwise a few random commands.
+    fn dead_loop(&mut self, previous: Option<&Statement>) -> Statement {
+        use crate::common::Command::*;
+
+        if let Some(previous @ Statement::Loop(_)) = previous
+            && self.random.below(2) == 0
+        {
+            return previous.clone();
+        }
+
+        let commands = [Left, Right, Up, Down, In, Out];
+        let len = 1 + self.random.below(MAX_JUNK);
+        let body: Vec<Statement> = (0..len)
+            .map(|_| Statement::Cmd(commands[self.random.below(commands.len() as u64) as usize]))
+            .collect();
+        Statement::Loop(body.into_boxed_slice())
+    }
+
+    fn chance(&mut self) -> bool {
+        self.random.below(256) < RATE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast;
+    use crate::symexec::{self, Limits, Verdict};
+    use crate::test_helpers::*;
+    use crate::traits::*;
+    use quickcheck::quickcheck;
+
+    fn parse(src: &[u8]) -> Box<Program> {
+        ast::parse_program(src).unwrap()
+    }
+
+    #[test]
+    fn depends_on_the_seed() {
+        let program = parse(HELLO_WORLD_SRC);
+        let first = obfuscate(&program, 1);
+        assert_eq!(obfuscate(&program, 1), first);
+        assert_ne!(obfuscate(&program, 2), first);
+        assert_ne!(first, program);
+        assert!(first.to_source().len() > HELLO_WORLD_SRC.len());
+        assert_interpret(&*first, b"", b"Hello, World!");
+    }
+
+    #[test]
+    fn proves_equivalent() {
+        let limits = Limits::default();
+        for src in [&b",[->++<]>."[..], b",>,<[->+<]>.", b"+++[>+.<-]<"] {
+            let program = parse(src);
+            let expected = program.peephole_compile();
+            for seed in 0..8 {
+                let actual = obfuscate(&program, seed).peephole_compile();
+                let verdict = symexec::check_equivalent(&expected, &actual, &limits);
+                assert!(matches!(verdict, Verdict::Equivalent), "{:?}", verdict);
+            }
+        }
+    }
+
+    #[test]
+    fn matches_on_random_programs() {
+        fn prop(program: RandomProgram, seed: u64, input: Vec<u8>) -> bool {
+            let program = program.0;
+            if !halts_within(&program, 16, &input, 10_000) {
+                return true;
+            }
+            let obfuscated = obfuscate(&program, seed);
+            program.interpret_memory(Some(16), &input)
+                == obfuscated.interpret_memory(Some(16), &input)
+        }
+
+        quickcheck(prop as fn(RandomProgram, u64, Vec<u8>) -> bool);
+    }
+}
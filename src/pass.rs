@@ -0,0 +1,1014 @@
+//! Pluggable optimization passes over the peephole AST.
+//!
+//! The [peephole compiler](../peephole/fn.compile.html) recognizes its loop forms in one fixed
+//! traversal. A [`PassManager`](struct.PassManager.html) instead
+//! [lowers](../peephole/fn.lower.html) the run-length encoded program to the peephole AST without
+//! any rewrites, and then runs a list of [`Pass`](trait.Pass.html)es over it, in order. The
+//! [standard](struct.PassManager.html#method.standard) passes produce the same program as the
+//! peephole compiler, and callers can add, insert, or remove passes to run their own
+//! transformations. The last standard passes remove dead code, such as the comment loops that
+//! published programs often start with, keep the pointer still in straight-line code, and write
+//! runs of constant output all at once. Like the peephole compiler, the dead-code pass assumes that
+//! the program starts on zeroed memory; for a prefilled state, compile with
+//! [`peephole::compile_prefilled`](../peephole/fn.compile_prefilled.html) instead. A manager can
+//! also be given a verifier to check the program after lowering and after each pass.
+//!
+//! With the `tools` feature, a manager can also
+//! [validate](struct.PassManager.html#method.set_validation) each pass, for debug builds and
+//! tests: it checks that the pass didn't change what the program writes or how it stops, on
+//! random inputs and then on every short input with [symbolic execution](../symexec/index.html).
+//! When a pass does change it, the error names the pass and gives the smallest part of the
+//! program that still shows the change.
+//!
+//! [`PartialEval`](struct.PartialEval.html) is an optional pass that runs the program up to its
+//! first input at compile time, so that a program that only prints a banner becomes pure output.
+//! Insert it before `coalesce-output` to write that output all at once.
+//!
+//! [`Unroll`](struct.Unroll.html) is another optional pass, which replaces loops that run a small,
+//! known number of times with copies of their bodies. Insert it before `fold-moves`, so that the
+//! copies become straight-line code that keeps the pointer still.
+
+use std::error;
+use std::fmt;
+
+use crate::common::Instruction;
+use crate::instrument;
+use crate::peephole::{self, Program, Statement};
+use crate::rle;
+#[cfg(feature = "tools")]
+use crate::symexec::{self, Limits};
+
+/// A transformation from peephole AST to peephole AST.
+pub trait Pass {
+    /// A short name for the pass, which identifies it in a `PassManager` and in errors.
+    fn name(&self) -> &str;
+
+    /// Transforms the program.
+    fn run(&self, program: Box<Program>) -> Box<Program>;
+}
+
+/// Checks a program, returning a description of the problem if it isn't valid.
+pub type Verifier = fn(&Program) -> Result<(), String>;
+
+/// The error when the verifier rejects the output of a pass.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PassError {
+    /// The name of the pass whose output was rejected.
+    pub pass: String,
+    /// The verifier's description of the problem.
+    pub message: String,
+}
+
+impl fmt::Display for PassError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "after pass {}: {}", self.pass, self.message)
+    }
+}
+
+impl error::Error for PassError {}
+
+/// The name that verification errors use for the lowering step.
+pub const LOWER: &str = "lower";
+
+/// Runs a sequence of passes.
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+    verifier: Option<Verifier>,
+    #[cfg(feature = "tools")]
+    validation: Option<Limits>,
+}
+
+impl Default for PassManager {
+    fn default() -> Self {
+        PassManager::new()
+    }
+}
+
+impl PassManager {
+    /// Creates a pass manager with no passes.
+    ///
+    /// In debug builds, the manager starts out verifying with
+    /// [`peephole::verify`](../peephole/fn.verify.html).
+    pub fn new() -> Self {
+        PassManager {
+            passes: Vec::new(),
+            verifier: if cfg!(debug_assertions) {
+                Some(peephole::verify)
+            } else {
+                None
+            },
+            #[cfg(feature = "tools")]
+            validation: None,
+        }
+    }
+
+    /// Creates a pass manager with the passes that the peephole compiler uses.
+    pub fn standard() -> Self {
+        let mut result = PassManager::new();
+        result
+            .add(SetZero)
+            .add(FindZero)
+            .add(OffsetAdd)
+            .add(Multiply)
+            .add(SetConst)
+            .add(DeadCode)
+            .add(FoldMoves)
+            .add(CoalesceOutput);
+        result
+    }
+
+    /// Adds a pass to run after the current passes.
+    pub fn add<P: Pass + 'static>(&mut self, pass: P) -> &mut Self {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    /// Inserts a pass to run at the given position.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is greater than the number of passes.
+    pub fn insert<P: Pass + 'static>(&mut self, index: usize, pass: P) -> &mut Self {
+        self.passes.insert(index, Box::new(pass));
+        self
+    }
+
+    /// Removes the first pass with the given name, returning it if there was one.
+    pub fn remove(&mut self, name: &str) -> Option<Box<dyn Pass>> {
+        let index = self.position(name)?;
+        Some(self.passes.remove(index))
+    }
+
+    /// The position of the first pass with the given name.
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.passes.iter().position(|pass| pass.name() == name)
+    }
+
+    /// The names of the passes, in the order they run.
+    pub fn names(&self) -> Vec<&str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    /// Sets the verifier to run after lowering and after each pass, or disables verification.
+    pub fn set_verifier(&mut self, verifier: Option<Verifier>) -> &mut Self {
+        self.verifier = verifier;
+        self
+    }
+
+    /// Checks after each pass that it didn't change what the program does, within the given
+    /// limits, or stops checking.
+    ///
+    /// The check runs both programs on random inputs and then
+    /// [symbolically](../symexec/fn.find_difference.html), so it is slow, and meant for debug
+    /// builds and tests. A pass that fails it is an error, whose message gives an input that shows
+    /// the change and the smallest program, found by removing statements and unwrapping loops, on
+    /// which the pass still makes one.
+    #[cfg(feature = "tools")]
+    pub fn set_validation(&mut self, limits: Option<Limits>) -> &mut Self {
+        self.validation = limits;
+        self
+    }
+
+    /// Lowers a run-length encoded program and runs the passes over it.
+    pub fn compile(&self, src: &rle::Program) -> Result<Box<Program>, PassError> {
+        let program = instrument::pass(LOWER, src, peephole::lower);
+        self.verify(LOWER, &program)?;
+        self.run(program)
+    }
+
+    /// Runs the passes over a program.
+    pub fn run(&self, mut program: Box<Program>) -> Result<Box<Program>, PassError> {
+        for pass in &self.passes {
+            #[cfg(feature = "tools")]
+            let before = self.validation.map(|limits| (limits, program.clone()));
+            program = instrument::pass(pass.name(), program, |program| pass.run(program));
+            self.verify(pass.name(), &program)?;
+            #[cfg(feature = "tools")]
+            if let Some((limits, before)) = before
+                && before != program
+            {
+                validate(&**pass, &before, &program, &limits)?;
+            }
+        }
+
+        Ok(program)
+    }
+
+    fn verify(&self, pass: &str, program: &Program) -> Result<(), PassError> {
+        match self.verifier {
+            Some(verifier) => verifier(program).map_err(|message| PassError {
+                pass: pass.to_owned(),
+                message,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The most candidate programs that minimizing a counterexample tries.
+#[cfg(feature = "tools")]
+const MINIMIZE_ATTEMPTS: usize = 1_000;
+
+/// Checks that a pass turned `before` into a program that does the same thing.
+#[cfg(feature = "tools")]
+fn validate(
+    pass: &dyn Pass,
+    before: &Program,
+    after: &Program,
+    limits: &Limits,
+) -> Result<(), PassError> {
+    let counterexample = match symexec::find_difference(before, after, limits) {
+        Some(counterexample) => counterexample,
+        None => return Ok(()),
+    };
+
+    let changes = |program: &Program| {
+        symexec::find_difference(program, &pass.run(program.into()), limits).is_some()
+    };
+    Err(PassError {
+        pass: pass.name().to_owned(),
+        message: format!(
+            "changed what the program does: {}; the smallest program it changes is {:?}",
+            counterexample,
+            minimize(before, changes)
+        ),
+    })
+}
+
+/// Shrinks a program for which `fails` holds, one statement or loop at a time, while it still
+/// holds.
+#[cfg(feature = "tools")]
+fn minimize<F: Fn(&Program) -> bool>(program: &Program, fails: F) -> Box<Program> {
+    let mut program: Box<Program> = program.into();
+    let mut attempts = MINIMIZE_ATTEMPTS;
+
+    'shrink: loop {
+        for candidate in reductions(&program) {
+            if attempts == 0 {
+                break 'shrink;
+            }
+            attempts -= 1;
+            if fails(&candidate) {
+                program = candidate;
+                continue 'shrink;
+            }
+        }
+        break;
+    }
+
+    program
+}
+
+/// The programs that remove one statement, or replace one loop with its body, anywhere in
+/// `program`.
+#[cfg(feature = "tools")]
+fn reductions(program: &Program) -> Vec<Box<Program>> {
+    let mut result = Vec::new();
+
+    for (index, statement) in program.iter().enumerate() {
+        let mut removed = program.to_vec();
+        removed.remove(index);
+        result.push(removed.into_boxed_slice());
+
+        if let Statement::Loop(ref body) = *statement {
+            let mut unwrapped = program[..index].to_vec();
+            unwrapped.extend(body.iter().cloned());
+            unwrapped.extend(program[index + 1..].iter().cloned());
+            result.push(unwrapped.into_boxed_slice());
+
+            for smaller in reductions(body) {
+                let mut inner = program.to_vec();
+                inner[index] = Statement::Loop(smaller);
+                result.push(inner.into_boxed_slice());
+            }
+        }
+    }
+
+    result
+}
+
+/// Replaces `[-]` and `[+]` with `SetZero`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SetZero;
+
+/// Replaces loops that only move with `FindZeroRight` or `FindZeroLeft`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FindZero;
+
+/// Replaces loops like `[->+<]` with `OffsetAddRight` or `OffsetAddLeft`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OffsetAdd;
+
+/// Runs the start of the program at compile time, replacing it with its output and the memory
+/// it leaves behind.
+///
+/// This isn't one of the standard passes, because the result assumes that the program starts with
+/// zeroed memory. See [`peephole::partially_evaluate`](../peephole/fn.partially_evaluate.html).
+#[derive(Clone, Copy, Debug)]
+pub struct PartialEval {
+    /// The most instructions and loop iterations to run.
+    pub fuel: usize,
+}
+
+impl Default for PartialEval {
+    fn default() -> Self {
+        PartialEval { fuel: 1_000_000 }
+    }
+}
+
+/// Replaces loops that are known to run a few times with that many copies of their bodies.
+///
+/// This isn't one of the standard passes, because it trades code size for fewer branches. See
+/// [`peephole::unroll_loops`](../peephole/fn.unroll_loops.html).
+#[derive(Clone, Copy, Debug)]
+pub struct Unroll {
+    /// The most statements that the copies of one loop's body may come to.
+    pub budget: usize,
+}
+
+impl Default for Unroll {
+    fn default() -> Self {
+        Unroll { budget: 64 }
+    }
+}
+
+/// Replaces counting loops like `[->+++>++<<]` with `MulAddRight` or `MulAddLeft` for each
+/// offset, followed by `SetZero`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Multiply;
+
+/// Replaces `SetZero` followed by `Add` with `SetConst`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SetConst;
+
+/// Removes loops that can never run and code after loops that can never stop.
+///
+/// This assumes that the program starts on zeroed memory. See
+/// [`peephole::eliminate_dead_code`](../peephole/fn.eliminate_dead_code.html).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DeadCode;
+
+/// Replaces moves in straight-line code with `AddAt` and `SetAt`, which leave the pointer still.
+///
+/// See [`peephole::fold_moves`](../peephole/fn.fold_moves.html).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FoldMoves;
+
+/// Replaces runs of `Out` that write known bytes with a single `WriteStr`.
+///
+/// See [`peephole::coalesce_output`](../peephole/fn.coalesce_output.html).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CoalesceOutput;
+
+impl Pass for SetZero {
+    fn name(&self) -> &str {
+        "set-zero"
+    }
+
+    fn run(&self, program: Box<Program>) -> Box<Program> {
+        replace_loops(program, peephole::set_zero_peephole)
+    }
+}
+
+impl Pass for FindZero {
+    fn name(&self) -> &str {
+        "find-zero"
+    }
+
+    fn run(&self, program: Box<Program>) -> Box<Program> {
+        replace_loops(program, peephole::find_zero_peephole)
+    }
+}
+
+impl Pass for OffsetAdd {
+    fn name(&self) -> &str {
+        "offset-add"
+    }
+
+    fn run(&self, program: Box<Program>) -> Box<Program> {
+        replace_loops(program, peephole::offset_add_peephole)
+    }
+}
+
+impl Pass for Multiply {
+    fn name(&self) -> &str {
+        "multiply"
+    }
+
+    fn run(&self, program: Box<Program>) -> Box<Program> {
+        expand_loops(program, peephole::multiply_peephole)
+    }
+}
+
+impl Pass for PartialEval {
+    fn name(&self) -> &str {
+        "partial-eval"
+    }
+
+    fn run(&self, program: Box<Program>) -> Box<Program> {
+        peephole::partially_evaluate(program, self.fuel)
+    }
+}
+
+impl Pass for Unroll {
+    fn name(&self) -> &str {
+        "unroll"
+    }
+
+    fn run(&self, program: Box<Program>) -> Box<Program> {
+        peephole::unroll_loops(program, self.budget)
+    }
+}
+
+impl Pass for SetConst {
+    fn name(&self) -> &str {
+        "set-const"
+    }
+
+    fn run(&self, program: Box<Program>) -> Box<Program> {
+        peephole::fold_set_const(program)
+    }
+}
+
+impl Pass for DeadCode {
+    fn name(&self) -> &str {
+        "dead-code"
+    }
+
+    fn run(&self, program: Box<Program>) -> Box<Program> {
+        peephole::eliminate_dead_code(program)
+    }
+}
+
+impl Pass for FoldMoves {
+    fn name(&self) -> &str {
+        "fold-moves"
+    }
+
+    fn run(&self, program: Box<Program>) -> Box<Program> {
+        peephole::fold_moves(program)
+    }
+}
+
+impl Pass for CoalesceOutput {
+    fn name(&self) -> &str {
+        "coalesce-output"
+    }
+
+    fn run(&self, program: Box<Program>) -> Box<Program> {
+        peephole::coalesce_output(program)
+    }
+}
+
+/// Replaces each loop for which `peephole` returns an instruction with that instruction, working
+/// from the innermost loops out.
+pub fn replace_loops<F>(program: Box<Program>, peephole: F) -> Box<Program>
+where
+    F: Fn(&[Statement]) -> Option<Instruction> + Copy,
+{
+    expand_loops(program, move |body| {
+        peephole(body).map(|instruction| vec![instruction])
+    })
+}
+
+/// Replaces each loop for which `peephole` returns a sequence of instructions with those
+/// instructions, working from the innermost loops out.
+pub fn expand_loops<F>(program: Box<Program>, peephole: F) -> Box<Program>
+where
+    F: Fn(&[Statement]) -> Option<Vec<Instruction>> + Copy,
+{
+    let mut result = Vec::with_capacity(program.len());
+
+    for statement in program.into_vec() {
+        match statement {
+            Statement::Loop(body) => {
+                let body = expand_loops(body, peephole);
+                match peephole(&body) {
+                    Some(instructions) => {
+                        result.extend(instructions.into_iter().map(Statement::Instr))
+                    }
+                    None => result.push(Statement::Loop(body)),
+                }
+            }
+            statement => result.push(statement),
+        }
+    }
+
+    result.into_boxed_slice()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rle::RleCompilable;
+    use crate::test_helpers::*;
+    use crate::traits::Interpretable;
+    use quickcheck::quickcheck;
+
+    #[test]
+    fn standard_matches_peephole_compiler() {
+        for src in [HELLO_WORLD_SRC, FACTOR_SRC] {
+            let program = crate::ast::parse_program(src).unwrap().rle_compile();
+            let expected = peephole::compile(&program);
+            let actual = PassManager::standard().compile(&program).unwrap();
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn standard_matches_peephole_compiler_on_random_programs() {
+        fn prop(program: RandomProgram) -> bool {
+            let program = program.0.rle_compile();
+            PassManager::standard().compile(&program) == Ok(peephole::compile(&program))
+        }
+
+        quickcheck(prop as fn(RandomProgram) -> bool);
+    }
+
+    #[test]
+    fn empty_manager_only_lowers() {
+        let program = rle_compile(b"[-]");
+        let actual = PassManager::new().compile(&program).unwrap();
+        assert_eq!(&*actual, &*peephole::lower(&program));
+        assert_eq!(
+            &*actual,
+            &[Statement::Loop(Box::new([Statement::Instr(
+                Instruction::Add(255)
+            )]))]
+        );
+    }
+
+    #[test]
+    fn passes_can_be_removed_and_inserted() {
+        let mut passes = PassManager::standard();
+        assert!(passes.remove("set-zero").is_some());
+        assert!(passes.remove("set-zero").is_none());
+        assert_eq!(
+            passes.names(),
+            [
+                "find-zero",
+                "offset-add",
+                "multiply",
+                "set-const",
+                "dead-code",
+                "fold-moves",
+                "coalesce-output"
+            ]
+        );
+
+        passes.insert(0, ClearAll);
+        assert_eq!(passes.position("clear-all"), Some(0));
+
+        let actual = passes.compile(&rle_compile(b"+[-]>[>]")).unwrap();
+        assert_eq!(&*actual, &[]);
+    }
+
+    #[test]
+    fn verifier_names_failing_pass() {
+        let mut passes = PassManager::standard();
+        passes.set_verifier(Some(|program| {
+            if program.contains(&Statement::Instr(Instruction::SetZero)) {
+                Err("found SetZero".to_owned())
+            } else {
+                Ok(())
+            }
+        }));
+
+        assert!(passes.compile(&rle_compile(b"[>]")).is_ok());
+        assert_eq!(
+            passes.compile(&rle_compile(b"[-]")),
+            Err(PassError {
+                pass: "set-zero".to_owned(),
+                message: "found SetZero".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn dead_code_removes_comment_loops() {
+        let program = compile(b"[comment, with commas.]+[-][.]>[>]");
+        assert_eq!(
+            &*program,
+            &[
+                Statement::Instr(Instruction::Add(1)),
+                Statement::Instr(Instruction::SetZero),
+                Statement::Instr(Instruction::Right(1)),
+                Statement::Instr(Instruction::FindZeroRight(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn dead_code_keeps_loops_on_unknown_bytes() {
+        let program = compile(b"+>[.]<,[.]");
+        assert_eq!(program.len(), 6);
+    }
+
+    #[test]
+    fn prefilled_compile_assumes_nothing_at_the_start() {
+        use Instruction::*;
+
+        let program = peephole::compile_prefilled(&rle_compile(b"+.+."));
+        let expected = [Add(1), Out, Add(1), Out].map(Statement::Instr);
+        assert_eq!(&*program, &expected);
+
+        let program = peephole::compile_prefilled(&rle_compile(b"[.]+.+."));
+        assert_eq!(
+            &*program,
+            &[
+                Statement::Loop(Box::new([Statement::Instr(Out)])),
+                Statement::Instr(WriteStr(b"\x01\x02".as_slice().into())),
+                Statement::Instr(SetConst(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn dead_code_removes_code_after_infinite_loops() {
+        let program = compile(b"+[>+<].,");
+        assert_eq!(program.len(), 2);
+        let program = compile(b"+[>+<-].");
+        assert_eq!(program.len(), 4);
+        let program = compile(b",[>+<].");
+        assert_eq!(program.len(), 3);
+    }
+
+    #[test]
+    fn set_const_folds_stores() {
+        let program = compile(b",[-]+++.,[-]-.,[-]+-.");
+        assert_eq!(
+            &*program,
+            &[
+                Statement::Instr(Instruction::In),
+                Statement::Instr(Instruction::SetConst(3)),
+                Statement::Instr(Instruction::Out),
+                Statement::Instr(Instruction::In),
+                Statement::Instr(Instruction::SetConst(255)),
+                Statement::Instr(Instruction::Out),
+                Statement::Instr(Instruction::In),
+                Statement::Instr(Instruction::SetZero),
+                Statement::Instr(Instruction::Out),
+            ]
+        );
+    }
+
+    #[test]
+    fn multiply_replaces_counting_loops() {
+        let program = compile(b",[->+++>>-<<<<++>]");
+        assert_eq!(
+            &*program,
+            &[
+                Statement::Instr(Instruction::In),
+                Statement::Instr(Instruction::MulAddRight(1, 3)),
+                Statement::Instr(Instruction::MulAddRight(3, 255)),
+                Statement::Instr(Instruction::MulAddLeft(1, 2)),
+                Statement::Instr(Instruction::SetZero),
+            ]
+        );
+    }
+
+    #[test]
+    fn multiply_keeps_other_loops() {
+        // Each leaves something that the loop does out of the multiply-adds: a cell it passes
+        // without adding to, a counter that doesn't go down by one, and output.
+        for src in [
+            &b",[->>><+<<]"[..],
+            b",[-->+<]",
+            b",[->+<+>-<]",
+            b",[->+.<]",
+        ] {
+            let program = compile(src);
+            assert!(
+                matches!(program[1], Statement::Loop(_)),
+                "{}",
+                String::from_utf8_lossy(src)
+            );
+        }
+    }
+
+    #[test]
+    fn coalesce_output_writes_known_bytes() {
+        let program = compile(b"+++..[-]++.,.>[-]+.+.[-]");
+        assert_eq!(
+            &*program,
+            &[
+                Statement::Instr(Instruction::WriteStr(b"\x03\x03\x02".as_slice().into())),
+                Statement::Instr(Instruction::SetConst(2)),
+                Statement::Instr(Instruction::In),
+                Statement::Instr(Instruction::Out),
+                Statement::Instr(Instruction::Right(1)),
+                Statement::Instr(Instruction::WriteStr(b"\x01\x02".as_slice().into())),
+                Statement::Instr(Instruction::SetZero),
+            ]
+        );
+    }
+
+    #[test]
+    fn coalesce_output_keeps_single_bytes() {
+        let program = compile(b"+.,..");
+        assert_eq!(program.len(), 5);
+        assert!(
+            !program
+                .iter()
+                .any(|statement| matches!(statement, Statement::Instr(Instruction::WriteStr(_))))
+        );
+    }
+
+    #[test]
+    fn coalesce_output_frees_bytes_with_the_program() {
+        let program = compile(b"++.+.");
+        let Statement::Instr(Instruction::WriteStr(ref bytes)) = program[0] else {
+            panic!("no WriteStr in {:?}", program);
+        };
+        let bytes = std::sync::Arc::downgrade(bytes);
+        drop(program);
+        assert!(bytes.upgrade().is_none());
+    }
+
+    #[test]
+    fn fold_moves_keeps_pointer_still() {
+        let program = compile(b",>+>++<<-.<[-]+>>.");
+        assert_eq!(
+            &*program,
+            &[
+                Statement::Instr(Instruction::In),
+                Statement::Instr(Instruction::AddAt(1, 1)),
+                Statement::Instr(Instruction::AddAt(2, 2)),
+                Statement::Instr(Instruction::Add(255)),
+                Statement::Instr(Instruction::Out),
+                Statement::Instr(Instruction::SetAt(-1, 1)),
+                Statement::Instr(Instruction::Right(1)),
+                Statement::Instr(Instruction::Out),
+            ]
+        );
+    }
+
+    #[test]
+    fn fold_moves_checks_reach() {
+        let program = compile(b",>>><<+.");
+        assert_eq!(
+            &program[1..],
+            &[
+                Statement::Instr(Instruction::AddAt(1, 1)),
+                Statement::Instr(Instruction::AddAt(3, 0)),
+                Statement::Instr(Instruction::Right(1)),
+                Statement::Instr(Instruction::Out),
+            ]
+        );
+
+        // Going right first fails with an overflow, even though going left also fails.
+        for src in [&b",>>>+<<<<<<+>."[..], b",<<+>>>>+."] {
+            let program = compile(src);
+            let expected = crate::ast::parse_program(src)
+                .unwrap()
+                .interpret_memory(Some(3), b"");
+            assert_eq!(program.interpret_memory(Some(3), b""), expected);
+        }
+    }
+
+    #[test]
+    fn partial_eval_collapses_output() {
+        let program = compile_partial(HELLO_WORLD_SRC);
+        assert!(
+            program
+                .iter()
+                .all(|statement| matches!(statement, Statement::Instr(_)))
+        );
+        assert_interpret(&*program, b"", b"Hello, World!");
+    }
+
+    #[test]
+    fn partial_eval_stops_at_input() {
+        let program = compile_partial(b"+++.>++,[-<+>]<.");
+        assert_eq!(
+            &program[..5],
+            &[
+                Statement::Instr(Instruction::SetConst(3)),
+                Statement::Instr(Instruction::Out),
+                Statement::Instr(Instruction::Right(1)),
+                Statement::Instr(Instruction::SetConst(2)),
+                Statement::Instr(Instruction::In),
+            ]
+        );
+        assert_interpret(&*program, b"\x01", b"\x03\x04");
+    }
+
+    #[test]
+    fn partial_eval_runs_out_of_fuel() {
+        let program = compile_partial(b"+.[]");
+        assert_eq!(program.len(), 3);
+        assert!(matches!(program[2], Statement::Loop(_)));
+    }
+
+    #[test]
+    fn partial_eval_keeps_errors() {
+        let program = compile_partial(b"++.>>>>+.");
+        assert_eq!(
+            program.interpret_memory(Some(3), b""),
+            Err(crate::common::Error::PointerOverflow)
+        );
+        assert_eq!(program.interpret_memory(Some(5), b""), Ok(vec![2, 1]));
+
+        let program = compile_partial(b"+.<");
+        assert_eq!(program.len(), 3);
+    }
+
+    #[test]
+    fn partial_eval_matches_on_random_programs() {
+        fn prop(program: RandomProgram) -> bool {
+            let program = program.0;
+            if !halts_within(&program, 16, b"ab", 10_000) {
+                return true;
+            }
+
+            let rle = program.rle_compile();
+            let expected = peephole::compile(&rle);
+            let actual = compile_partial_rle(&rle);
+            expected.interpret_memory(Some(16), b"ab") == actual.interpret_memory(Some(16), b"ab")
+        }
+
+        quickcheck(prop as fn(RandomProgram) -> bool);
+    }
+
+    #[test]
+    fn unroll_copies_counted_loops() {
+        let program = compile_unrolled(b"+++[>+.<-]");
+        assert_eq!(
+            &*program,
+            &[
+                Statement::Instr(Instruction::Add(3)),
+                Statement::Instr(Instruction::Right(1)),
+                Statement::Instr(Instruction::Add(1)),
+                Statement::Instr(Instruction::Out),
+                Statement::Instr(Instruction::AddAt(-1, 255)),
+                Statement::Instr(Instruction::Add(1)),
+                Statement::Instr(Instruction::Out),
+                Statement::Instr(Instruction::AddAt(-1, 255)),
+                Statement::Instr(Instruction::Add(1)),
+                Statement::Instr(Instruction::Out),
+                Statement::Instr(Instruction::Left(1)),
+                Statement::Instr(Instruction::Add(255)),
+            ]
+        );
+        assert_interpret(&*program, b"", b"\x01\x02\x03");
+    }
+
+    #[test]
+    fn unroll_keeps_other_loops() {
+        // Too many copies, an unknown count, a count that never reaches zero, and a body that
+        // moves.
+        for src in [
+            &b"++++++++++++++++++++[>+.<-]"[..],
+            b",[>+.<-]",
+            b"+[>+.<--]",
+            b"+++[>+.<->]",
+        ] {
+            let program = compile_unrolled(src);
+            assert!(
+                program
+                    .iter()
+                    .any(|statement| matches!(statement, Statement::Loop(_))),
+                "{}",
+                String::from_utf8_lossy(src)
+            );
+        }
+    }
+
+    #[test]
+    fn unroll_matches_on_random_programs() {
+        fn prop(program: RandomProgram) -> bool {
+            let program = program.0;
+            if !halts_within(&program, 16, b"ab", 10_000) {
+                return true;
+            }
+
+            let rle = program.rle_compile();
+            let expected = peephole::compile(&rle);
+            let mut manager = PassManager::standard();
+            let index = manager.position("fold-moves").unwrap();
+            manager.insert(index, Unroll::default());
+            let actual = manager.compile(&rle).unwrap();
+            expected.interpret_memory(Some(16), b"ab") == actual.interpret_memory(Some(16), b"ab")
+        }
+
+        quickcheck(prop as fn(RandomProgram) -> bool);
+    }
+
+    #[test]
+    #[cfg(feature = "tools")]
+    fn validation_accepts_standard_passes() {
+        let mut manager = PassManager::standard();
+        manager.set_validation(Some(Limits::default()));
+        for src in [
+            &b",[->+<]>."[..],
+            b",>,<[->+<]>.",
+            b"+++[>+.<-]",
+            HELLO_WORLD_SRC,
+        ] {
+            assert!(manager.compile(&rle_compile(src)).is_ok());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "tools")]
+    fn validation_accepts_standard_passes_on_random_programs() {
+        fn prop(program: RandomProgram) -> bool {
+            let mut manager = PassManager::standard();
+            manager.set_validation(Some(Limits {
+                input: 1,
+                memory: 16,
+                steps: 1_000,
+                paths: 200,
+                ..Limits::default()
+            }));
+            manager.compile(&program.0.rle_compile()).is_ok()
+        }
+
+        quickcheck(prop as fn(RandomProgram) -> bool);
+    }
+
+    #[test]
+    #[cfg(feature = "tools")]
+    fn validation_minimizes_counterexamples() {
+        let mut manager = PassManager::standard();
+        let index = manager.position("set-const").unwrap();
+        manager
+            .insert(index, DoubleMultiplies)
+            .set_validation(Some(Limits::default()));
+
+        let error = manager
+            .compile(&rle_compile(b"+++.>,[->++<]>.<<++."))
+            .unwrap_err();
+        assert_eq!(error.pass, "double-multiplies");
+        assert!(
+            error.message.ends_with(
+                "the smallest program it changes is \
+                 [Instr(In), Instr(MulAddRight(1, 2)), Instr(Right(1)), Instr(Out)]"
+            ),
+            "{}",
+            error.message
+        );
+    }
+
+    /// Doubles every factor of a multiply, which changes what the program does.
+    #[cfg(feature = "tools")]
+    struct DoubleMultiplies;
+
+    #[cfg(feature = "tools")]
+    impl Pass for DoubleMultiplies {
+        fn name(&self) -> &str {
+            "double-multiplies"
+        }
+
+        fn run(&self, program: Box<Program>) -> Box<Program> {
+            program
+                .into_vec()
+                .into_iter()
+                .map(|statement| match statement {
+                    Statement::Instr(Instruction::MulAddRight(offset, factor)) => {
+                        Statement::Instr(Instruction::MulAddRight(offset, factor.wrapping_mul(2)))
+                    }
+                    statement => statement,
+                })
+                .collect()
+        }
+    }
+
+    struct ClearAll;
+
+    impl Pass for ClearAll {
+        fn name(&self) -> &str {
+            "clear-all"
+        }
+
+        fn run(&self, _program: Box<Program>) -> Box<Program> {
+            Box::new([])
+        }
+    }
+
+    fn compile(src: &[u8]) -> Box<Program> {
+        PassManager::standard().compile(&rle_compile(src)).unwrap()
+    }
+
+    fn compile_partial(src: &[u8]) -> Box<Program> {
+        compile_partial_rle(&rle_compile(src))
+    }
+
+    fn compile_partial_rle(src: &rle::Program) -> Box<Program> {
+        let mut manager = PassManager::standard();
+        let index = manager.position("coalesce-output").unwrap();
+        manager.insert(index, PartialEval { fuel: 10_000 });
+        manager.compile(src).unwrap()
+    }
+
+    fn compile_unrolled(src: &[u8]) -> Box<Program> {
+        let mut manager = PassManager::standard();
+        let index = manager.position("fold-moves").unwrap();
+        manager.insert(index, Unroll { budget: 32 });
+        manager.compile(&rle_compile(src)).unwrap()
+    }
+
+    fn rle_compile(src: &[u8]) -> Box<rle::Program> {
+        crate::ast::parse_program(src).unwrap().rle_compile()
+    }
+}
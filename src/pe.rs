@@ -0,0 +1,523 @@
+//! Tiny Windows executables, written directly as PE without an assembler or a linker.
+//!
+//! This backend is experimental, like the [ELF writer](../elf/index.html), whose machine code it
+//! shares. [`emit`](fn.emit.html) wraps the code in a PE32+ image for x86-64 Windows with three
+//! sections: the code and its strings, the imports, and the memory, which is zeroed by the
+//! loader and takes no room in the file. The runtime does the I/O with `ReadFile` and `WriteFile`
+//! on the standard handles, which it imports from `KERNEL32.dll` along with `GetStdHandle` and
+//! `ExitProcess`, so the program needs no C runtime. It stops with a message and status 3 if the
+//! pointer leaves the memory, and at the end of input, `,` stores 0.
+//!
+//! ```
+//! use bf::ast;
+//! use bf::pe;
+//! use bf::traits::*;
+//!
+//! let program = ast::parse_program(b",[.,]").unwrap().peephole_compile();
+//! let executable = pe::emit(&program, 30_000);
+//! assert_eq!(&executable[..2], b"MZ");
+//! assert!(executable.len() <= 2048);
+//! ```
+
+use crate::peephole;
+use crate::x64::{self, Code, Label, Runtime};
+
+/// The largest memory that an executable can have, 2 GiB, which leaves its image well within the
+/// 4 GiB that PE allows.
+pub const MAX_MEMORY_SIZE: usize = 1 << 31;
+
+/// Where the image asks to be loaded, the usual address for 64-bit executables.
+const IMAGE_BASE: u64 = 0x1_4000_0000;
+
+const SECTION_ALIGNMENT: u32 = 0x1000;
+
+const FILE_ALIGNMENT: u32 = 0x200;
+
+/// The offset of the PE signature, which follows the DOS header with no DOS program.
+const PE_OFFSET: usize = 0x40;
+
+const OPTIONAL_HEADER_SIZE: usize = 240;
+
+/// The size of the headers in the file, rounded up to the file alignment.
+const HEADERS_SIZE: u32 = 0x200;
+
+/// Where the code is once loaded, as an offset from the image base.
+const CODE_RVA: u32 = SECTION_ALIGNMENT;
+
+/// The functions that the runtime imports from `KERNEL32.dll`, in the order of their entries in
+/// the import address table.
+const IMPORTS: [&str; 4] = ["GetStdHandle", "ReadFile", "WriteFile", "ExitProcess"];
+
+/// The arguments of `GetStdHandle`.
+const STD_INPUT_HANDLE: i32 = -10;
+const STD_OUTPUT_HANDLE: i32 = -11;
+const STD_ERROR_HANDLE: i32 = -12;
+
+/// Renders the program as an executable for x86-64 Windows whose memory is `memory_size` cells.
+///
+/// # Panics
+///
+/// Panics if `memory_size` is more than [`MAX_MEMORY_SIZE`](constant.MAX_MEMORY_SIZE.html), or if
+/// the program contains jump instructions, which the peephole AST doesn't.
+pub fn emit(program: &peephole::Program, memory_size: usize) -> Vec<u8> {
+    assert!(
+        memory_size <= MAX_MEMORY_SIZE,
+        "memory too big for an executable"
+    );
+    let mut code = Code::new();
+    let runtime = Runtime::new(&mut code);
+    let imports = Imports::new(&mut code);
+    let memory = code.new_label();
+
+    code.jump(&[0x4C, 0x8D, 0x25], memory); // lea r12, [rel memory]
+    code.op(&[0x4D, 0x89, 0xE5]); // mov r13, r12
+    code.op(&[0x49, 0xBE]); // mov r14, memory_size
+    code.imm64(memory_size as u64);
+    code.op(&[0x4D, 0x01, 0xE6]); // add r14, r12
+
+    let strings = x64::compile(&mut code, &runtime, program);
+
+    // Exits with the status in `ecx`.
+    let exit = code.new_label();
+    code.op(&[0x31, 0xC9]); // xor ecx, ecx
+    code.bind(exit);
+    code.op(&[0x48, 0x83, 0xE4, 0xF0]); // and rsp, -16
+    code.op(&[0x48, 0x83, 0xEC, 0x20]); // sub rsp, 32
+    code.jump(&[0xFF, 0x15], imports.exit_process); // call [rel ExitProcess]
+
+    runtime.emit_moves(&mut code);
+    let write_handle = emit_io(&mut code, &runtime, &imports);
+    let fail = code.new_label();
+    runtime.emit_errors(&mut code, fail);
+    code.bind(fail);
+    load_ecx(&mut code, STD_ERROR_HANDLE);
+    code.jump(&[0xE8], write_handle); // call bf_write_handle
+    load_ecx(&mut code, 3);
+    code.jump(&[0xE9], exit); // jmp exit
+    for (label, bytes) in strings {
+        code.bind(label);
+        code.op(bytes);
+    }
+
+    // The imports and the memory follow the code, each in a section of its own.
+    let code_size = code.len() as u32;
+    let import_rva = CODE_RVA + align(code_size, SECTION_ALIGNMENT);
+    let (import_data, import_table) = import_section(import_rva);
+    let memory_rva = import_rva + align(import_data.len() as u32, SECTION_ALIGNMENT);
+    let image_size = memory_rva + align(memory_size as u32, SECTION_ALIGNMENT);
+    for (index, &label) in imports.labels().iter().enumerate() {
+        let entry = import_table.address_table + 8 * index as u32;
+        code.bind_at(label, (entry - CODE_RVA) as usize);
+    }
+    code.bind_at(memory, (memory_rva - CODE_RVA) as usize);
+    let bytes = code.finish();
+
+    let sections = [
+        Section {
+            name: *b".text\0\0\0",
+            virtual_size: code_size,
+            rva: CODE_RVA,
+            raw_size: align(code_size, FILE_ALIGNMENT),
+            raw_offset: HEADERS_SIZE,
+            characteristics: IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE | IMAGE_SCN_MEM_READ,
+        },
+        Section {
+            name: *b".idata\0\0",
+            virtual_size: import_data.len() as u32,
+            rva: import_rva,
+            raw_size: align(import_data.len() as u32, FILE_ALIGNMENT),
+            raw_offset: HEADERS_SIZE + align(code_size, FILE_ALIGNMENT),
+            // The loader writes the addresses of the imported functions here.
+            characteristics: IMAGE_SCN_CNT_INITIALIZED_DATA
+                | IMAGE_SCN_MEM_READ
+                | IMAGE_SCN_MEM_WRITE,
+        },
+        Section {
+            name: *b".bss\0\0\0\0",
+            virtual_size: memory_size as u32,
+            rva: memory_rva,
+            raw_size: 0,
+            raw_offset: 0,
+            characteristics: IMAGE_SCN_CNT_UNINITIALIZED_DATA
+                | IMAGE_SCN_MEM_READ
+                | IMAGE_SCN_MEM_WRITE,
+        },
+    ];
+
+    let mut file = Vec::new();
+    headers(&mut file, &sections, image_size, &import_table);
+    for (section, data) in sections.iter().zip([&bytes, &import_data]) {
+        file.resize(section.raw_offset as usize, 0);
+        file.extend_from_slice(data);
+        file.resize((section.raw_offset + section.raw_size) as usize, 0);
+    }
+    file
+}
+
+/// Emits the runtime's I/O, with `ReadFile` and `WriteFile`, and returns the label of a function
+/// that writes the `rdx` bytes at `rsi` to the standard handle that `ecx` chooses.
+///
+/// Each function that calls into Windows aligns the stack and leaves the 32 bytes of shadow space
+/// that the Windows x64 calling convention asks for, with room for a fifth argument and for the
+/// count of bytes read or written.
+fn emit_io(code: &mut Code, runtime: &Runtime, imports: &Imports) -> Label {
+    let done = code.new_label();
+    code.bind(runtime.read);
+    enter(code);
+    load_ecx(code, STD_INPUT_HANDLE);
+    code.jump(&[0xFF, 0x15], imports.get_std_handle); // call [rel GetStdHandle]
+    code.op(&[0x48, 0x89, 0xC1]); // mov rcx, rax
+    code.op(&[0x4C, 0x89, 0xE2]); // mov rdx, r12
+    code.op(&[0x41, 0xB8, 1, 0, 0, 0]); // mov r8d, 1
+    code.op(&[0x4C, 0x8D, 0x4C, 0x24, 0x28]); // lea r9, [rsp + 40]
+    code.op(&[0x48, 0xC7, 0x44, 0x24, 0x20, 0, 0, 0, 0]); // mov qword [rsp + 32], 0
+    code.op(&[0xC7, 0x44, 0x24, 0x28, 0, 0, 0, 0]); // mov dword [rsp + 40], 0
+    code.jump(&[0xFF, 0x15], imports.read_file); // call [rel ReadFile]
+    // A failed read, like the end of input, reads nothing.
+    code.op(&[0x83, 0x7C, 0x24, 0x28, 0x01]); // cmp dword [rsp + 40], 1
+    code.jump(&[0x0F, 0x84], done); // je done
+    code.op(&[0x41, 0xC6, 0x04, 0x24, 0]); // mov byte [r12], 0
+    code.bind(done);
+    leave(code);
+
+    // Output that can't be written is dropped.
+    let write_handle = code.new_label();
+    code.bind(runtime.out);
+    code.op(&[0x4C, 0x89, 0xE6]); // mov rsi, r12
+    code.op(&[0xBA, 1, 0, 0, 0]); // mov edx, 1
+    code.bind(runtime.write);
+    load_ecx(code, STD_OUTPUT_HANDLE);
+    code.bind(write_handle);
+    enter(code);
+    code.op(&[0x48, 0x89, 0xD7]); // mov rdi, rdx
+    code.jump(&[0xFF, 0x15], imports.get_std_handle); // call [rel GetStdHandle]
+    code.op(&[0x48, 0x89, 0xC1]); // mov rcx, rax
+    code.op(&[0x48, 0x89, 0xF2]); // mov rdx, rsi
+    code.op(&[0x49, 0x89, 0xF8]); // mov r8, rdi
+    code.op(&[0x4C, 0x8D, 0x4C, 0x24, 0x28]); // lea r9, [rsp + 40]
+    code.op(&[0x48, 0xC7, 0x44, 0x24, 0x20, 0, 0, 0, 0]); // mov qword [rsp + 32], 0
+    code.jump(&[0xFF, 0x15], imports.write_file); // call [rel WriteFile]
+    leave(code);
+    write_handle
+}
+
+/// Starts a function that calls into Windows.
+fn enter(code: &mut Code) {
+    code.op(&[0x55]); // push rbp
+    code.op(&[0x48, 0x89, 0xE5]); // mov rbp, rsp
+    code.op(&[0x48, 0x83, 0xE4, 0xF0]); // and rsp, -16
+    code.op(&[0x48, 0x83, 0xEC, 0x30]); // sub rsp, 48
+}
+
+/// Returns from a function that [`enter`](fn.enter.html) started.
+fn leave(code: &mut Code) {
+    code.op(&[0x48, 0x89, 0xEC]); // mov rsp, rbp
+    code.op(&[0x5D]); // pop rbp
+    code.op(&[0xC3]); // ret
+}
+
+fn load_ecx(code: &mut Code, value: i32) {
+    code.op(&[0xB9]); // mov ecx, value
+    code.imm32(value as u32);
+}
+
+/// The entries of the import address table, which the code calls through.
+struct Imports {
+    get_std_handle: Label,
+    read_file: Label,
+    write_file: Label,
+    exit_process: Label,
+}
+
+impl Imports {
+    fn new(code: &mut Code) -> Self {
+        Imports {
+            get_std_handle: code.new_label(),
+            read_file: code.new_label(),
+            write_file: code.new_label(),
+            exit_process: code.new_label(),
+        }
+    }
+
+    /// The labels in the order of `IMPORTS`.
+    fn labels(&self) -> [Label; 4] {
+        [
+            self.get_std_handle,
+            self.read_file,
+            self.write_file,
+            self.exit_process,
+        ]
+    }
+}
+
+/// Where the tables of the import section are, as RVAs, and how big they are.
+struct ImportTable {
+    directory: u32,
+    /// The size of the import directory, with its terminating entry.
+    directory_size: u32,
+    address_table: u32,
+    /// The size of the import address table, with its terminating entry.
+    address_table_size: u32,
+}
+
+/// Lays out the import section for `KERNEL32.dll` at `rva`. The tables refer to each other by RVA,
+/// so the section only works there.
+fn import_section(rva: u32) -> (Vec<u8>, ImportTable) {
+    const DLL: &[u8] = b"KERNEL32.dll\0";
+    let directory_size = 2 * 20;
+    let thunks_size = 8 * (IMPORTS.len() as u32 + 1);
+    let lookup_table = rva + directory_size;
+    let address_table = lookup_table + thunks_size;
+    let dll_name = address_table + thunks_size;
+
+    // Each import's hint, which is 0, and its name, padded to an even length.
+    let mut hint_names = Vec::new();
+    let mut names = Vec::new();
+    for name in IMPORTS {
+        hint_names.push(dll_name + align(DLL.len() as u32, 2) + names.len() as u32);
+        names.extend_from_slice(&[0, 0]);
+        names.extend_from_slice(name.as_bytes());
+        names.push(0);
+        names.resize(align(names.len() as u32, 2) as usize, 0);
+    }
+
+    let mut data = Vec::new();
+    // The directory entry for the DLL, and an entry of zeros to end the directory.
+    for value in [lookup_table, 0, 0, dll_name, address_table] {
+        push32(&mut data, value);
+    }
+    data.extend_from_slice(&[0; 20]);
+    // The lookup table and the address table start the same, each ending with a zero. The loader
+    // overwrites the address table with the addresses of the functions.
+    for _ in 0..2 {
+        for &hint_name in &hint_names {
+            push64(&mut data, hint_name.into());
+        }
+        push64(&mut data, 0);
+    }
+    data.extend_from_slice(DLL);
+    data.resize(align(data.len() as u32, 2) as usize, 0);
+    data.extend_from_slice(&names);
+
+    let table = ImportTable {
+        directory: rva,
+        directory_size,
+        address_table,
+        address_table_size: thunks_size,
+    };
+    (data, table)
+}
+
+/// Appends the DOS, COFF, and optional headers and the section headers.
+fn headers(file: &mut Vec<u8>, sections: &[Section], image_size: u32, imports: &ImportTable) {
+    // The DOS header, which only says where the PE header is.
+    file.extend_from_slice(b"MZ");
+    file.resize(0x3C, 0);
+    push32(file, PE_OFFSET as u32);
+    file.resize(PE_OFFSET, 0);
+    file.extend_from_slice(b"PE\0\0");
+
+    // The COFF header.
+    push16(file, 0x8664); // Machine: IMAGE_FILE_MACHINE_AMD64
+    push16(file, sections.len() as u16);
+    push32(file, 0); // TimeDateStamp
+    push32(file, 0); // PointerToSymbolTable
+    push32(file, 0); // NumberOfSymbols
+    push16(file, OPTIONAL_HEADER_SIZE as u16);
+    // IMAGE_FILE_RELOCS_STRIPPED | IMAGE_FILE_EXECUTABLE_IMAGE | IMAGE_FILE_LARGE_ADDRESS_AWARE
+    push16(file, 0x0001 | 0x0002 | 0x0020);
+
+    // The optional header, for PE32+.
+    let optional = file.len();
+    push16(file, 0x20B);
+    push16(file, 0); // MajorLinkerVersion, MinorLinkerVersion
+    push32(file, sections[0].raw_size); // SizeOfCode
+    push32(file, sections[1].raw_size); // SizeOfInitializedData
+    push32(file, align(sections[2].virtual_size, FILE_ALIGNMENT)); // SizeOfUninitializedData
+    push32(file, CODE_RVA); // AddressOfEntryPoint
+    push32(file, CODE_RVA); // BaseOfCode
+    push64(file, IMAGE_BASE);
+    push32(file, SECTION_ALIGNMENT);
+    push32(file, FILE_ALIGNMENT);
+    // Windows Vista or later.
+    for version in [6, 0, 0, 0, 6, 0] {
+        push16(file, version); // OperatingSystemVersion, ImageVersion, SubsystemVersion
+    }
+    push32(file, 0); // Win32VersionValue
+    push32(file, image_size);
+    push32(file, HEADERS_SIZE);
+    push32(file, 0); // CheckSum
+    push16(file, 3); // Subsystem: IMAGE_SUBSYSTEM_WINDOWS_CUI
+    // DllCharacteristics: IMAGE_DLLCHARACTERISTICS_NX_COMPAT | TERMINAL_SERVER_AWARE
+    push16(file, 0x0100 | 0x8000);
+    push64(file, 0x10_0000); // SizeOfStackReserve
+    push64(file, 0x1000); // SizeOfStackCommit
+    push64(file, 0x10_0000); // SizeOfHeapReserve
+    push64(file, 0x1000); // SizeOfHeapCommit
+    push32(file, 0); // LoaderFlags
+    push32(file, 16); // NumberOfRvaAndSizes
+    // Of the data directories, only the imports and their address table are used.
+    for index in 0..16 {
+        let (rva, size) = match index {
+            1 => (imports.directory, imports.directory_size),
+            12 => (imports.address_table, imports.address_table_size),
+            _ => (0, 0),
+        };
+        push32(file, rva);
+        push32(file, size);
+    }
+    debug_assert_eq!(file.len() - optional, OPTIONAL_HEADER_SIZE);
+
+    for section in sections {
+        file.extend_from_slice(&section.name);
+        push32(file, section.virtual_size);
+        push32(file, section.rva);
+        push32(file, section.raw_size);
+        push32(file, section.raw_offset);
+        file.extend_from_slice(&[0; 12]); // Relocations and line numbers
+        push32(file, section.characteristics);
+    }
+    debug_assert!(file.len() <= HEADERS_SIZE as usize);
+}
+
+fn push16(data: &mut Vec<u8>, value: u16) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push32(data: &mut Vec<u8>, value: u32) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push64(data: &mut Vec<u8>, value: u64) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Section flags.
+const IMAGE_SCN_CNT_CODE: u32 = 0x20;
+const IMAGE_SCN_CNT_INITIALIZED_DATA: u32 = 0x40;
+const IMAGE_SCN_CNT_UNINITIALIZED_DATA: u32 = 0x80;
+const IMAGE_SCN_MEM_EXECUTE: u32 = 0x2000_0000;
+const IMAGE_SCN_MEM_READ: u32 = 0x4000_0000;
+const IMAGE_SCN_MEM_WRITE: u32 = 0x8000_0000;
+
+struct Section {
+    name: [u8; 8],
+    virtual_size: u32,
+    rva: u32,
+    raw_size: u32,
+    raw_offset: u32,
+    characteristics: u32,
+}
+
+fn align(value: u32, alignment: u32) -> u32 {
+    value.div_ceil(alignment) * alignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast;
+    use crate::test_helpers::*;
+    use crate::traits::*;
+
+    fn emit_src(src: &[u8], memory_size: usize) -> Vec<u8> {
+        let program = ast::parse_program(src).unwrap().peephole_compile();
+        emit(&program, memory_size)
+    }
+
+    fn u16_at(bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    /// The offset of the optional header.
+    const OPTIONAL: usize = PE_OFFSET + 4 + 20;
+
+    /// The offset of the first section header.
+    const SECTION_HEADERS: usize = OPTIONAL + OPTIONAL_HEADER_SIZE;
+
+    /// The name, RVA, virtual size, raw size, and raw offset of each section.
+    fn sections(file: &[u8]) -> Vec<(&[u8], u32, u32, u32, u32)> {
+        (0..u16_at(file, PE_OFFSET + 6) as usize)
+            .map(|index| {
+                let header = SECTION_HEADERS + 40 * index;
+                let name = &file[header..header + 8];
+                let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(8)];
+                let field = |n: usize| u32_at(file, header + 8 + 4 * n);
+                (name, field(1), field(0), field(2), field(3))
+            })
+            .collect()
+    }
+
+    /// The bytes of the image at `rva`, which must be in a section that is in the file.
+    fn at_rva(file: &[u8], rva: u32) -> &[u8] {
+        let (_, start, _, size, offset) = sections(file)
+            .into_iter()
+            .find(|&(_, start, _, size, _)| (start..start + size).contains(&rva))
+            .expect("the RVA is in the file");
+        &file[(offset + rva - start) as usize..(offset + size) as usize]
+    }
+
+    #[test]
+    fn lays_out_an_image() {
+        let file = emit_src(HELLO_WORLD_SRC, 30_000);
+        assert_eq!(&file[..2], b"MZ");
+        assert_eq!(u32_at(&file, 0x3C) as usize, PE_OFFSET);
+        assert_eq!(&file[PE_OFFSET..PE_OFFSET + 4], b"PE\0\0");
+        assert_eq!(u16_at(&file, PE_OFFSET + 4), 0x8664);
+        assert_eq!(u16_at(&file, OPTIONAL), 0x20B);
+        assert_eq!(u32_at(&file, OPTIONAL + 16), CODE_RVA);
+
+        let sections = sections(&file);
+        let names: Vec<_> = sections.iter().map(|section| section.0).collect();
+        assert_eq!(names, [&b".text"[..], b".idata", b".bss"]);
+        // The memory is last, with nothing in the file.
+        let (_, memory_rva, memory_size, raw_size, _) = sections[2];
+        assert_eq!((memory_size, raw_size), (30_000, 0));
+        let image_size = u32_at(&file, OPTIONAL + 56);
+        assert_eq!(
+            image_size,
+            align(memory_rva + memory_size, SECTION_ALIGNMENT)
+        );
+        let (_, _, _, raw_size, raw_offset) = sections[1];
+        assert_eq!(file.len(), (raw_offset + raw_size) as usize);
+        assert!(file.len() <= 2048, "{} bytes", file.len());
+
+        // The program starts by loading the address of the memory.
+        let code = at_rva(&file, CODE_RVA);
+        assert_eq!(code[..3], [0x4C, 0x8D, 0x25]);
+        let displacement = u32_at(code, 3);
+        assert_eq!(
+            CODE_RVA.wrapping_add(7).wrapping_add(displacement),
+            memory_rva
+        );
+    }
+
+    #[test]
+    fn imports_what_the_runtime_calls() {
+        let file = emit_src(b",[.,]", 100);
+        let directory = u32_at(&file, OPTIONAL + 112 + 8);
+        let entry = at_rva(&file, directory);
+        let (lookup_table, dll_name, address_table) =
+            (u32_at(entry, 0), u32_at(entry, 12), u32_at(entry, 16));
+        assert!(entry[20..40].iter().all(|&b| b == 0));
+        assert!(at_rva(&file, dll_name).starts_with(b"KERNEL32.dll\0"));
+        assert_eq!(u32_at(&file, OPTIONAL + 112 + 8 * 12), address_table);
+
+        let lookup = at_rva(&file, lookup_table);
+        let addresses = at_rva(&file, address_table);
+        for (index, name) in IMPORTS.iter().enumerate() {
+            let hint_name = u32_at(lookup, 8 * index);
+            assert_eq!(u32_at(addresses, 8 * index), hint_name);
+            let hint_name = at_rva(&file, hint_name);
+            assert_eq!(
+                &hint_name[2..2 + name.len() + 1],
+                [name.as_bytes(), b"\0"].concat()
+            );
+        }
+        assert_eq!(u32_at(lookup, 8 * IMPORTS.len()), 0);
+    }
+}
@@ -1,5 +1,10 @@
 use super::*;
+use crate::common::Count;
+use crate::instrument;
+use crate::pass::{PassError, PassManager};
 use crate::rle;
+use crate::semantics::Overflow;
+use crate::traits::{Compile, CompileTo, IntoUsize, Stage};
 
 /// Program forms that can be compiled to the peephole AST.
 pub trait PeepholeCompilable {
@@ -12,19 +17,93 @@ pub trait PeepholeCompilable {
     fn peephole_compile(&self) -> Box<Program> {
         self.with_rle(compile)
     }
+
+    /// Lower the given program to the peephole AST and optimize it with the given passes.
+    fn peephole_compile_with(&self, passes: &PassManager) -> Result<Box<Program>, PassError> {
+        self.with_rle(|program| passes.compile(program))
+    }
 }
 
 /// Peephole-optimizes run-length encoded AST.
 ///
-/// See [`Instruction`](struct.Instruction.html) for descriptions of the peepholes.
+/// See [`Instruction`](struct.Instruction.html) for descriptions of the peepholes. Afterward,
+/// this [folds constant stores](fn.fold_set_const.html),
+/// [eliminates dead code](fn.eliminate_dead_code.html),
+/// [folds moves into offsets](fn.fold_moves.html), and
+/// [coalesces constant output](fn.coalesce_output.html).
 pub fn compile(src: &[rle::Statement]) -> Box<Program> {
+    let program = instrument::pass("peephole", src, compile_statements);
+    let program = instrument::pass("set-const", program, fold_set_const);
+    let program = instrument::pass("dead-code", program, eliminate_dead_code);
+    let program = instrument::pass("fold-moves", program, fold_moves);
+    let program = instrument::pass("coalesce-output", program, coalesce_output);
+    debug_assert_eq!(verify(&program), Ok(()));
+    program
+}
+
+/// Peephole-optimizes run-length encoded AST for a state whose memory may not start out zeroed,
+/// such as one filled in with [`State::from_cells`](../state/struct.State.html#method.from_cells).
+///
+/// [`compile`](fn.compile.html) assumes that the program starts on zeroed memory, which lets it
+/// remove the loops at the start and write the first output as constants. This runs the same
+/// passes without that assumption.
+pub fn compile_prefilled(src: &[rle::Statement]) -> Box<Program> {
+    let program = instrument::pass("peephole", src, compile_statements);
+    let program = instrument::pass("set-const", program, fold_set_const);
+    let program = instrument::pass("dead-code", program, |program| {
+        eliminate_dead_code_from(program, Knowledge::PREFILLED)
+    });
+    let program = instrument::pass("fold-moves", program, fold_moves);
+    let program = instrument::pass("coalesce-output", program, |program| {
+        coalesce_output_from(program, Knowledge::PREFILLED)
+    });
+    debug_assert_eq!(verify(&program), Ok(()));
+    program
+}
+
+fn compile_statements(src: &[rle::Statement]) -> Box<Program> {
     let mut compiler = Compiler::new();
     compiler.compile(src);
     compiler.into_program()
 }
 
+/// Translates run-length encoded AST to the peephole AST without replacing any loops.
+///
+/// This is the starting point for a [`PassManager`](../pass/struct.PassManager.html).
+pub fn lower(src: &[rle::Statement]) -> Box<Program> {
+    let mut compiler = Compiler::unoptimized();
+    compiler.compile(src);
+    compiler.into_program()
+}
+
+/// Compiles run-length encoded AST to the peephole AST for cells that overflow as `overflow`
+/// says.
+///
+/// The peepholes and the passes after them rewrite code with wrapping arithmetic, so only cells
+/// that wrap get the full [`compile`](fn.compile.html). For other cells, this
+/// [lowers](fn.lower.html) the program without replacing any loops, writing each `+` or `-`
+/// command as `Add`s of at most 127 in its direction, which is how
+/// [`State::add`](../state/struct.State.html#method.add) reads them. The program should come
+/// from [`rle::compile_for`](../rle/fn.compile_for.html), so that no changes were cancelled.
+pub fn compile_for(src: &[rle::Statement], overflow: Overflow) -> Box<Program> {
+    match overflow {
+        Overflow::Wrap => compile(src),
+        _ => instrument::pass("lower", src, |src| {
+            let mut compiler = Compiler {
+                wrapping: false,
+                ..Compiler::unoptimized()
+            };
+            compiler.compile(src);
+            compiler.into_program()
+        }),
+    }
+}
+
 pub struct Compiler {
     instructions: Vec<Statement>,
+    optimize: bool,
+    /// Whether cells wrap, so that an `Add` can change a cell by any amount.
+    wrapping: bool,
 }
 
 macro_rules! or_else {
@@ -36,6 +115,16 @@ impl Compiler {
     pub fn new() -> Self {
         Compiler {
             instructions: Vec::new(),
+            optimize: true,
+            wrapping: true,
+        }
+    }
+
+    pub fn unoptimized() -> Self {
+        Compiler {
+            instructions: Vec::new(),
+            optimize: false,
+            wrapping: true,
         }
     }
 
@@ -48,6 +137,8 @@ impl Compiler {
             match *instruction {
                 Cmd(Right, count) => self.push(Obj::Right(count)),
                 Cmd(Left, count) => self.push(Obj::Left(count)),
+                Cmd(Up, count) if !self.wrapping => self.push_changes(count, true),
+                Cmd(Down, count) if !self.wrapping => self.push_changes(count, false),
                 Cmd(Up, count) => {
                     let amount = (count % 256) as u8;
                     self.push(Obj::Add(amount));
@@ -66,10 +157,21 @@ impl Compiler {
                         self.push(Obj::Out);
                     }
                 }
+                Cmd(SwitchTape, count) => self.push(Obj::SwitchTape(count)),
                 Cmd(Begin, _) | Cmd(End, _) => panic!("bad opcode"),
 
+                Loop(ref body) if !self.optimize => {
+                    let mut compiler = Compiler {
+                        wrapping: self.wrapping,
+                        ..Compiler::unoptimized()
+                    };
+                    compiler.compile(body);
+                    self.instructions
+                        .push(Statement::Loop(compiler.into_program()))
+                }
+
                 Loop(ref body) => {
-                    let body = compile(body);
+                    let body = compile_statements(body);
 
                     let peephole = or_else!(
                         set_zero_peephole(&body),
@@ -79,6 +181,10 @@ impl Compiler {
 
                     if let Some(instr) = peephole {
                         self.push(instr);
+                    } else if let Some(instrs) = multiply_peephole(&body) {
+                        for instr in instrs {
+                            self.push(instr);
+                        }
                     } else {
                         self.instructions.push(Statement::Loop(body))
                     }
@@ -94,8 +200,444 @@ impl Compiler {
     fn push(&mut self, instr: common::Instruction) {
         self.instructions.push(Statement::Instr(instr));
     }
+
+    /// Pushes `Add`s that change the byte by `count` up or down, at most 127 at a time.
+    fn push_changes(&mut self, count: Count, up: bool) {
+        let mut count = count.into_usize();
+        while count > 0 {
+            let step = count.min(127) as u8;
+            let amount = if up { step } else { step.wrapping_neg() };
+            self.push(common::Instruction::Add(amount));
+            count -= step as usize;
+        }
+    }
+}
+
+/// Removes code that can never run.
+///
+/// This removes loops that start when the current byte is known to be zero, such as comment loops
+/// at the start of a program or right after another loop. It assumes that the program starts on
+/// zeroed memory, as [`compile_prefilled`](fn.compile_prefilled.html) does not. It also removes the
+/// code after a loop that is known to start and can never stop: one whose body does no input,
+/// returns to where it began, and leaves the byte there unchanged.
+pub fn eliminate_dead_code(program: Box<Program>) -> Box<Program> {
+    eliminate_dead_code_from(program, Knowledge::START)
+}
+
+/// What dead code elimination knows at a point in the program.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Knowledge {
+    /// The value of the byte at the pointer, if known.
+    pub(super) current: Option<u8>,
+    /// Whether every byte of memory is still zero.
+    pub(super) pristine: bool,
+}
+
+impl Knowledge {
+    /// Knowledge at the start of the program.
+    pub(super) const START: Knowledge = Knowledge {
+        current: Some(0),
+        pristine: true,
+    };
+
+    /// Knowledge at the start of a program whose memory may not be zeroed.
+    pub(super) const PREFILLED: Knowledge = Knowledge {
+        current: None,
+        pristine: false,
+    };
+
+    /// Knowledge at the start of a loop body, where the byte at the pointer isn't zero.
+    pub(super) const LOOP_BODY: Knowledge = Knowledge {
+        current: None,
+        pristine: false,
+    };
+
+    /// Knowledge right after a loop, where the byte at the pointer is zero.
+    pub(super) const AFTER_LOOP: Knowledge = Knowledge {
+        current: Some(0),
+        pristine: false,
+    };
+
+    /// Updates the knowledge for a single non-loop instruction.
+    pub(super) fn step(self, instruction: &common::Instruction) -> Self {
+        use common::Instruction::*;
+
+        let zeroed = Knowledge {
+            current: Some(0),
+            ..self
+        };
+
+        match *instruction {
+            // The multiplies change memory only if the current byte isn't zero.
+            Add(0) | AddAt(_, 0) | Out | WriteStr(_) | MulAddRight(..) | MulAddLeft(..) => self,
+            Add(amount) => Knowledge {
+                current: self.current.map(|value| value.wrapping_add(amount)),
+                pristine: false,
+            },
+            In => Knowledge::LOOP_BODY,
+            Left(_) | Right(_) | SwitchTape(_) if self.pristine => self,
+            Left(_) | Right(_) | SwitchTape(_) => Knowledge {
+                current: None,
+                ..self
+            },
+            // The offset adds change memory only if the current byte isn't zero.
+            SetZero | FindZeroRight(_) | FindZeroLeft(_) | OffsetAddRight(_) | OffsetAddLeft(_) => {
+                zeroed
+            }
+            SetConst(value) => Knowledge {
+                current: Some(value),
+                pristine: self.pristine && value == 0,
+            },
+            // These change only other bytes.
+            AddAt(..) | SetAt(..) => Knowledge {
+                pristine: self.pristine && matches!(instruction, SetAt(_, 0)),
+                ..self
+            },
+            JumpZero(_) | JumpNotZero(_) => panic!("unexpected jump instruction"),
+        }
+    }
+}
+
+fn eliminate_dead_code_from(program: Box<Program>, mut knowledge: Knowledge) -> Box<Program> {
+    let mut result = Vec::new();
+
+    for statement in program.into_vec() {
+        match statement {
+            Statement::Instr(ref instruction) => {
+                knowledge = knowledge.step(instruction);
+                result.push(statement);
+            }
+
+            Statement::Loop(_) if knowledge.current == Some(0) => (),
+
+            Statement::Loop(body) => {
+                let never_stops = knowledge.current.is_some() && is_idle_loop_body(&body);
+                let body = eliminate_dead_code_from(body, Knowledge::LOOP_BODY);
+                result.push(Statement::Loop(body));
+
+                if never_stops {
+                    break;
+                }
+
+                knowledge = Knowledge::AFTER_LOOP;
+            }
+        }
+    }
+
+    result.into_boxed_slice()
+}
+
+/// Whether a loop body does no input, returns to where it began, and leaves the byte there
+/// unchanged, so that a loop with this body never stops once it starts.
+fn is_idle_loop_body(body: &[Statement]) -> bool {
+    use common::Instruction::*;
+
+    let mut offset = 0isize;
+    let mut change = 0u8;
+
+    for statement in body {
+        let instruction = match *statement {
+            Statement::Instr(ref instruction) => instruction,
+            Statement::Loop(_) => return false,
+        };
+
+        match *instruction {
+            Right(count) => offset += count as isize,
+            Left(count) => offset -= count as isize,
+            Add(amount) if offset == 0 => change = change.wrapping_add(amount),
+            Add(_) | Out | WriteStr(_) => (),
+            SetZero | SetConst(_) if offset != 0 => (),
+            OffsetAddRight(target) if offset != 0 && offset + target as isize != 0 => {}
+            OffsetAddLeft(target) if offset != 0 && offset != target as isize => (),
+            MulAddRight(target, _) if offset + target as isize != 0 => (),
+            MulAddLeft(target, _) if offset != target as isize => (),
+            _ => return false,
+        }
+    }
+
+    offset == 0 && change == 0
+}
+
+/// Keeps the pointer still in straight-line code that only moves, adds, and stores, so that
+/// `>+>++<<-` becomes `AddAt(1, 1) AddAt(2, 2) Add(255)`.
+///
+/// Each such block becomes its adds and stores, at offsets from where the block starts, and
+/// then a single move to where it ends. The block checks every offset that the original reached
+/// before going the other way, in the same order, so that it fails with the same error. Where
+/// nothing else checks an offset, `AddAt` with a value of 0 does; so `>>><<+.` becomes
+/// `AddAt(1, 1) AddAt(3, 0) Right(1) Out`.
+pub fn fold_moves(program: Box<Program>) -> Box<Program> {
+    let mut result = Vec::new();
+    let mut block = MoveBlock::default();
+
+    for statement in program.into_vec() {
+        match statement {
+            Statement::Instr(ref instruction) if block.push(instruction) => (),
+            Statement::Instr(_) => {
+                block.flush(&mut result);
+                result.push(statement);
+            }
+            Statement::Loop(body) => {
+                block.flush(&mut result);
+                result.push(Statement::Loop(fold_moves(body)));
+            }
+        }
+    }
+
+    block.flush(&mut result);
+    result.into_boxed_slice()
+}
+
+/// A block of straight-line code that `fold_moves` may replace.
+#[derive(Default)]
+struct MoveBlock {
+    /// The block as it was.
+    original: Vec<common::Instruction>,
+    /// The adds and stores of the block, and the checks it needs, at their offsets.
+    accesses: Vec<common::Instruction>,
+    /// The offset of the pointer from where the block started.
+    offset: isize,
+    /// The lowest and highest offsets that are known to be in bounds.
+    low: isize,
+    high: isize,
+    /// An offset past `low` or `high` that the block has reached, which still needs checking.
+    unchecked: Option<isize>,
+}
+
+impl MoveBlock {
+    /// Adds an instruction to the block, returning whether it belongs in one.
+    fn push(&mut self, instruction: &common::Instruction) -> bool {
+        use common::Instruction::*;
+
+        match *instruction {
+            Right(count) => self.move_to(self.offset + count as isize),
+            Left(count) => self.move_to(self.offset - count as isize),
+            Add(amount) => self.access(AddAt(self.offset, amount)),
+            SetZero => self.access(SetAt(self.offset, 0)),
+            SetConst(value) => self.access(SetAt(self.offset, value)),
+            AddAt(offset, amount) => self.access(AddAt(self.offset + offset, amount)),
+            SetAt(offset, value) => self.access(SetAt(self.offset + offset, value)),
+            _ => return false,
+        }
+
+        self.original.push(instruction.clone());
+        true
+    }
+
+    fn move_to(&mut self, offset: isize) {
+        let (reached_low, reached_high) = match self.unchecked {
+            Some(unchecked) => (self.low.min(unchecked), self.high.max(unchecked)),
+            None => (self.low, self.high),
+        };
+
+        // Going further than before needs a check, but an unchecked offset in the other
+        // direction was reached first, so it needs its check now.
+        if offset > reached_high {
+            if let Some(unchecked) = self.unchecked.filter(|&unchecked| unchecked < self.low) {
+                self.check(unchecked);
+            }
+            self.unchecked = Some(offset);
+        } else if offset < reached_low {
+            if let Some(unchecked) = self.unchecked.filter(|&unchecked| unchecked > self.high) {
+                self.check(unchecked);
+            }
+            self.unchecked = Some(offset);
+        }
+
+        self.offset = offset;
+    }
+
+    /// Emits an explicit check of the given offset.
+    fn check(&mut self, offset: isize) {
+        self.access(common::Instruction::AddAt(offset, 0));
+    }
+
+    /// Adds an `AddAt` or `SetAt`, which also checks its offset.
+    fn access(&mut self, instruction: common::Instruction) {
+        let offset = access_offset(&instruction);
+        self.low = self.low.min(offset);
+        self.high = self.high.max(offset);
+        if self
+            .unchecked
+            .is_some_and(|unchecked| self.low <= unchecked && unchecked <= self.high)
+        {
+            self.unchecked = None;
+        }
+        self.accesses.push(instruction);
+    }
+
+    /// Ends the block.
+    fn flush(&mut self, result: &mut Vec<Statement>) {
+        use common::Instruction::*;
+
+        let offset = std::mem::take(&mut self.offset);
+        // The final move checks where the block ends.
+        if let Some(unchecked) = self.unchecked.take()
+            && unchecked != offset
+        {
+            self.check(unchecked);
+        }
+        (self.low, self.high) = (0, 0);
+        let original = std::mem::take(&mut self.original);
+        let mut accesses = std::mem::take(&mut self.accesses);
+
+        // The accesses where the block ends can come after the move, so that later passes see
+        // them at the pointer.
+        let at_end = accesses
+            .iter()
+            .rev()
+            .take_while(|access| access_offset(access) == offset)
+            .count();
+        let after: Vec<_> = accesses
+            .drain(accesses.len() - at_end..)
+            .map(|access| rebase(access, offset))
+            .filter(|access| *access != Add(0))
+            .collect();
+        let before: Vec<_> = accesses
+            .into_iter()
+            .map(|access| rebase(access, 0))
+            .collect();
+
+        if !before
+            .iter()
+            .any(|access| matches!(access, AddAt(..) | SetAt(..)))
+        {
+            result.extend(original.into_iter().map(Statement::Instr));
+            return;
+        }
+
+        result.extend(before.into_iter().map(Statement::Instr));
+        if offset > 0 {
+            result.push(Statement::Instr(Right(offset as Count)));
+        } else if offset < 0 {
+            result.push(Statement::Instr(Left(offset.unsigned_abs() as Count)));
+        }
+        result.extend(after.into_iter().map(Statement::Instr));
+    }
+}
+
+fn access_offset(instruction: &common::Instruction) -> isize {
+    match *instruction {
+        common::Instruction::AddAt(offset, _) | common::Instruction::SetAt(offset, _) => offset,
+        _ => unreachable!("not an offset access: {:?}", instruction),
+    }
+}
+
+/// Makes an `AddAt` or `SetAt` relative to the given offset, using the plain instruction if
+/// that's where it is.
+fn rebase(instruction: common::Instruction, base: isize) -> common::Instruction {
+    use common::Instruction::*;
+
+    match instruction {
+        AddAt(offset, amount) if offset == base => Add(amount),
+        SetAt(offset, 0) if offset == base => SetZero,
+        SetAt(offset, value) if offset == base => SetConst(value),
+        AddAt(offset, amount) => AddAt(offset - base, amount),
+        SetAt(offset, value) => SetAt(offset - base, value),
+        _ => unreachable!("not an offset access: {:?}", instruction),
+    }
+}
+
+/// Replaces runs of `Out` that write bytes known at compile time with a single `WriteStr`.
+///
+/// A run can also change the byte at the pointer between the writes, with `Add`, `SetZero`, and
+/// `SetConst`, as long as its value stays known. Those become a single store after the
+/// `WriteStr`, so that `SetConst(104) Out Add(1) Out` becomes `WriteStr(b"hi") SetConst(105)`.
+/// Runs with fewer than two bytes stay as they are. Like
+/// [`eliminate_dead_code`](fn.eliminate_dead_code.html), this assumes that the program starts
+/// on zeroed memory.
+pub fn coalesce_output(program: Box<Program>) -> Box<Program> {
+    coalesce_output_from(program, Knowledge::START)
+}
+
+fn coalesce_output_from(program: Box<Program>, mut knowledge: Knowledge) -> Box<Program> {
+    use common::Instruction::*;
+
+    let mut result = Vec::new();
+    let mut run = OutputRun::default();
+
+    for statement in program.into_vec() {
+        match statement {
+            Statement::Instr(ref instruction) => {
+                let extends = match *instruction {
+                    Out | Add(_) => knowledge.current.is_some(),
+                    WriteStr(_) | SetZero | SetConst(_) => true,
+                    _ => false,
+                };
+
+                if !extends {
+                    run.flush(&mut result, knowledge.current);
+                }
+
+                knowledge = knowledge.step(instruction);
+                if extends {
+                    run.push(instruction.clone(), knowledge.current);
+                } else {
+                    result.push(statement);
+                }
+            }
+
+            Statement::Loop(body) => {
+                run.flush(&mut result, knowledge.current);
+                result.push(Statement::Loop(coalesce_output_from(
+                    body,
+                    Knowledge::LOOP_BODY,
+                )));
+                knowledge = Knowledge::AFTER_LOOP;
+            }
+        }
+    }
+
+    run.flush(&mut result, knowledge.current);
+    result.into_boxed_slice()
+}
+
+/// The instructions of a run that `coalesce_output` may replace.
+#[derive(Default)]
+struct OutputRun {
+    instructions: Vec<common::Instruction>,
+    bytes: Vec<u8>,
+}
+
+impl OutputRun {
+    /// Adds an instruction to the run, given the value of the byte at the pointer, which is known
+    /// during a run.
+    fn push(&mut self, instruction: common::Instruction, current: Option<u8>) {
+        use common::Instruction::*;
+
+        match instruction {
+            Out => self.bytes.extend(current),
+            WriteStr(ref bytes) => self.bytes.extend_from_slice(bytes),
+            _ => (),
+        }
+        self.instructions.push(instruction);
+    }
+
+    /// Ends the run, given the value of the byte at the pointer at its end.
+    fn flush(&mut self, result: &mut Vec<Statement>, current: Option<u8>) {
+        use common::Instruction::*;
+
+        let instructions = std::mem::take(&mut self.instructions);
+        let bytes = std::mem::take(&mut self.bytes);
+
+        if bytes.len() < 2 {
+            result.extend(instructions.into_iter().map(Statement::Instr));
+            return;
+        }
+
+        result.push(Statement::Instr(WriteStr(bytes.into())));
+
+        let stores = instructions
+            .iter()
+            .any(|instruction| !matches!(instruction, Out | WriteStr(_)));
+        if stores && let Some(value) = current {
+            let store = if value == 0 { SetZero } else { SetConst(value) };
+            result.push(Statement::Instr(store));
+        }
+    }
 }
 
+/// Recognizes `[-]` and `[+]`.
 pub fn set_zero_peephole(body: &[Statement]) -> Option<common::Instruction> {
     use self::Statement::*;
     use common::Instruction::*;
@@ -107,6 +649,46 @@ pub fn set_zero_peephole(body: &[Statement]) -> Option<common::Instruction> {
     }
 }
 
+/// Recognizes `SetZero` or `SetConst` followed by `Add`, which together store a constant.
+pub fn set_const_peephole(
+    first: &common::Instruction,
+    second: &common::Instruction,
+) -> Option<common::Instruction> {
+    use common::Instruction::*;
+
+    let value = match (first, second) {
+        (&SetZero, &Add(amount)) => amount,
+        (&SetConst(value), &Add(amount)) => value.wrapping_add(amount),
+        _ => return None,
+    };
+
+    Some(if value == 0 { SetZero } else { SetConst(value) })
+}
+
+/// Replaces each `SetZero` or `SetConst` followed by an `Add` with a single store, so that
+/// `[-]+++` becomes `SetConst(3)`.
+pub fn fold_set_const(program: Box<Program>) -> Box<Program> {
+    let mut result: Vec<Statement> = Vec::new();
+
+    for statement in program.into_vec() {
+        match statement {
+            Statement::Instr(ref instruction) => {
+                if let Some(Statement::Instr(previous)) = result.last_mut()
+                    && let Some(folded) = set_const_peephole(previous, instruction)
+                {
+                    *previous = folded;
+                    continue;
+                }
+                result.push(statement);
+            }
+            Statement::Loop(body) => result.push(Statement::Loop(fold_set_const(body))),
+        }
+    }
+
+    result.into_boxed_slice()
+}
+
+/// Recognizes loops that only move, such as `[>]` and `[<<]`.
 pub fn find_zero_peephole(body: &[Statement]) -> Option<common::Instruction> {
     use self::Statement::*;
     use common::Instruction::*;
@@ -122,6 +704,7 @@ pub fn find_zero_peephole(body: &[Statement]) -> Option<common::Instruction> {
     }
 }
 
+/// Recognizes loops that move the current byte to an offset, such as `[->+<]`.
 pub fn offset_add_peephole(body: &[Statement]) -> Option<common::Instruction> {
     use self::Statement::*;
     use common::Instruction::*;
@@ -147,6 +730,60 @@ pub fn offset_add_peephole(body: &[Statement]) -> Option<common::Instruction> {
     }
 }
 
+/// Recognizes counting loops, which decrement the current byte once and otherwise only add
+/// constants at other offsets, such as `[->+++>++<<]`.
+///
+/// The loop runs as many times as the current byte says, so it becomes a multiply-add for each
+/// offset, in the order that the loop first reaches them, and then a `SetZero`. The loop must
+/// add something everywhere it stops, so that the multiply-adds go past the ends of memory
+/// exactly when the loop would.
+pub fn multiply_peephole(body: &[Statement]) -> Option<Vec<common::Instruction>> {
+    use self::Statement::*;
+    use common::Instruction::*;
+
+    let mut offset = 0isize;
+    let mut counter = 0u8;
+    // The total added at each offset, in the order that the loop first reaches them.
+    let mut factors: Vec<(isize, u8)> = Vec::new();
+
+    for statement in body {
+        match *statement {
+            Instr(Right(count)) => offset += count as isize,
+            Instr(Left(count)) => offset -= count as isize,
+            Instr(Add(amount)) if offset == 0 => counter = counter.wrapping_add(amount),
+            Instr(Add(amount)) => match factors.iter_mut().find(|entry| entry.0 == offset) {
+                Some(entry) => entry.1 = entry.1.wrapping_add(amount),
+                None => factors.push((offset, amount)),
+            },
+            _ => return None,
+        }
+
+        if offset != 0 && !factors.iter().any(|entry| entry.0 == offset) {
+            factors.push((offset, 0));
+        }
+    }
+
+    if offset != 0 || counter != 255 || factors.is_empty() {
+        return None;
+    }
+
+    let mut result = Vec::with_capacity(factors.len() + 1);
+    for (offset, factor) in factors {
+        if factor == 0 || offset.unsigned_abs() > Count::MAX.into_usize() {
+            return None;
+        }
+
+        result.push(if offset > 0 {
+            MulAddRight(offset.unsigned_abs() as Count, factor)
+        } else {
+            MulAddLeft(offset.unsigned_abs() as Count, factor)
+        });
+    }
+    result.push(SetZero);
+
+    Some(result)
+}
+
 impl PeepholeCompilable for rle::Program {
     fn with_rle<F, R>(&self, k: F) -> R
     where
@@ -164,3 +801,15 @@ impl<T: rle::RleCompilable + ?Sized> PeepholeCompilable for T {
         k(&self.rle_compile())
     }
 }
+
+impl Stage for Program {
+    type Compiled = Box<Program>;
+}
+
+impl Compile for Program {}
+
+impl<T: PeepholeCompilable + ?Sized> CompileTo<Program> for T {
+    fn compile_to(&self) -> Box<Program> {
+        self.peephole_compile()
+    }
+}
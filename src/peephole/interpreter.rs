@@ -1,69 +1,223 @@
 use std::io::{Read, Write};
 
 use super::*;
+use crate::analysis::{self, Proof};
+use crate::counters::{self, CountingInterpretable, Counters, Hooks, Kind};
+use crate::sandbox::{self, SandboxConfig, SandboxInterpretable};
 use crate::state::State;
-use crate::traits::Interpretable;
+use crate::traits::{Interpretable, IntoUsize};
 use common::BfResult;
 
 impl Interpretable for Program {
-    fn interpret_state<R: Read, W: Write>(
+    fn interpret_in<R: Read, W: Write>(
         &self,
-        mut state: State,
+        state: &mut State,
         mut input: R,
         mut output: W,
     ) -> BfResult<()> {
-        interpret(self, &mut state, &mut input, &mut output)
+        let proofs = analysis::prove(self);
+        specialize(self, &proofs, state, &mut input, &mut output, &mut ())
     }
 }
 
-fn interpret<R, W>(
+impl CountingInterpretable for Program {
+    fn interpret_counting<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        input: R,
+        output: W,
+        counters: &mut Counters,
+    ) -> BfResult<()> {
+        let proofs = analysis::prove(self);
+        counters::count_io(input, output, counters, |input, output, counters| {
+            specialize(self, &proofs, state, input, output, counters)
+        })
+    }
+}
+
+impl SandboxInterpretable for Program {
+    fn interpret_sandboxed<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        input: R,
+        output: W,
+        config: &SandboxConfig,
+    ) -> BfResult<()> {
+        let proofs = analysis::prove(self);
+        sandbox::run(
+            state,
+            input,
+            output,
+            config,
+            |state, input, output, hooks| specialize(self, &proofs, state, input, output, hooks),
+        )
+    }
+}
+
+/// Runs `interpret` specialized for how the state's cells overflow, so that the loop doesn't
+/// check that for every instruction. As in the bytecode interpreter, that's the only setting
+/// worth a parameter: the proofs decide the bounds checks, and only `In` looks at the EOF
+/// policy.
+fn specialize<R, W, H>(
     instructions: &[Statement],
+    proofs: &[Proof],
     state: &mut State,
     input: &mut R,
     output: &mut W,
+    hooks: &mut H,
 ) -> BfResult<()>
 where
     R: Read,
     W: Write,
+    H: Hooks,
 {
-    for instruction in instructions {
-        interpret_instruction(instruction, state, input, output)?;
+    if state.wraps() {
+        interpret::<_, _, _, true>(instructions, proofs, state, input, output, hooks)
+    } else {
+        interpret::<_, _, _, false>(instructions, proofs, state, input, output, hooks)
     }
+}
 
-    Ok(())
+fn interpret<R, W, H, const WRAP: bool>(
+    instructions: &[Statement],
+    proofs: &[Proof],
+    state: &mut State,
+    input: &mut R,
+    output: &mut W,
+    hooks: &mut H,
+) -> BfResult<()>
+where
+    R: Read,
+    W: Write,
+    H: Hooks,
+{
+    // The loops being run, innermost last, each as its body, its proofs, and where to carry on
+    // in the statements around it. Keeping them here rather than on the call stack saves a call
+    // for every iteration.
+    let mut stack = Vec::new();
+    let mut statements = instructions.iter().zip(proofs);
+
+    loop {
+        match statements.next() {
+            Some((Statement::Loop(body), proof)) => {
+                let proofs = match *proof {
+                    Proof::Loop(ref proofs) => proofs,
+                    Proof::Instr(_) => panic!("proofs do not match program"),
+                };
+
+                if state.load() != 0 {
+                    hooks.loop_iteration()?;
+                    let outer = std::mem::replace(&mut statements, body.iter().zip(proofs));
+                    stack.push((body, proofs, outer));
+                } else {
+                    hooks.pointer(state.pointer());
+                }
+            }
+
+            Some((statement, proof)) => {
+                interpret_instruction::<_, _, _, WRAP>(
+                    statement, proof, state, input, output, hooks,
+                )?;
+            }
+
+            None => {
+                let Some(&(body, proofs, _)) = stack.last() else {
+                    return Ok(());
+                };
+
+                if state.load() != 0 {
+                    hooks.loop_iteration()?;
+                    statements = body.iter().zip(proofs);
+                } else if let Some((_, _, outer)) = stack.pop() {
+                    statements = outer;
+                    hooks.pointer(state.pointer());
+                }
+            }
+        }
+    }
 }
 
-fn interpret_instruction<R, W>(
+/// Runs one statement other than a loop, which only `interpret` runs. `WRAP` says that the
+/// state's cells wrap, letting `Add` skip the overflow check; callers that don't know can pass
+/// `false`.
+#[inline(always)]
+pub(crate) fn interpret_instruction<R, W, H, const WRAP: bool>(
     instructions: &Statement,
+    proof: &Proof,
     state: &mut State,
     input: &mut R,
     output: &mut W,
+    hooks: &mut H,
 ) -> BfResult<()>
 where
     R: Read,
     W: Write,
+    H: Hooks,
 {
     use super::Statement::*;
     use common::Instruction::*;
 
+    let proved = *proof == Proof::Instr(true);
+
+    if let Instr(ref instruction) = *instructions {
+        hooks.instruction(Kind::of(instruction));
+    }
+
     match *instructions {
+        Instr(Left(count)) if proved => state.left_unchecked(count),
+
         Instr(Left(count)) => state.left(count)?,
 
+        Instr(Right(count)) if proved => state.right_unchecked(count),
+
         Instr(Right(count)) => state.right(count)?,
 
-        Instr(Add(amount)) => state.up(amount),
+        Instr(Add(amount)) if WRAP => state.add_wrapping(amount),
+
+        Instr(Add(amount)) => state.add(amount)?,
 
         Instr(In) => state.read(input),
 
         Instr(Out) => state.write(output),
 
+        Instr(WriteStr(ref bytes)) => {
+            let _ = output.write_all(bytes);
+        }
+
         Instr(SetZero) => state.store(0),
 
+        Instr(SetConst(value)) => state.store(value),
+
+        Instr(SwitchTape(count)) => state.switch_tape(count),
+
+        Instr(AddAt(offset, value)) => {
+            if proved {
+                state.up_at_unchecked(offset, value);
+            } else {
+                state.up_at(offset, value)?;
+            }
+            hooks.cell(state.pointer().wrapping_add_signed(offset));
+        }
+
+        Instr(SetAt(offset, value)) => {
+            if proved {
+                state.store_at_unchecked(offset, value);
+            } else {
+                state.store_at(offset, value)?;
+            }
+            hooks.cell(state.pointer().wrapping_add_signed(offset));
+        }
+
         Instr(OffsetAddRight(offset)) => {
             let value = state.load();
             if value != 0 {
                 state.store(0);
-                state.up_pos_offset(offset, value)?;
+                if proved {
+                    state.up_pos_offset_unchecked(offset, value);
+                } else {
+                    state.up_pos_offset(offset, value)?;
+                }
+                hooks.cell(state.pointer() + offset.into_usize());
             }
         }
 
@@ -71,31 +225,49 @@ where
             let value = state.load();
             if value != 0 {
                 state.store(0);
-                state.up_neg_offset(offset, value)?;
+                if proved {
+                    state.up_neg_offset_unchecked(offset, value);
+                } else {
+                    state.up_neg_offset(offset, value)?;
+                }
             }
         }
 
-        Instr(FindZeroRight(skip)) => {
-            while state.load() != 0 {
-                state.right(skip)?;
+        Instr(MulAddRight(offset, factor)) => {
+            let value = state.load();
+            if value != 0 {
+                let value = value.wrapping_mul(factor);
+                if proved {
+                    state.up_pos_offset_unchecked(offset, value);
+                } else {
+                    state.up_pos_offset(offset, value)?;
+                }
+                hooks.cell(state.pointer() + offset.into_usize());
             }
         }
 
-        Instr(FindZeroLeft(skip)) => {
-            while state.load() != 0 {
-                state.left(skip)?;
+        Instr(MulAddLeft(offset, factor)) => {
+            let value = state.load();
+            if value != 0 {
+                let value = value.wrapping_mul(factor);
+                if proved {
+                    state.up_neg_offset_unchecked(offset, value);
+                } else {
+                    state.up_neg_offset(offset, value)?;
+                }
             }
         }
 
+        Instr(FindZeroRight(skip)) => state.find_zero_right(skip)?,
+
+        Instr(FindZeroLeft(skip)) => state.find_zero_left(skip)?,
+
         Instr(JumpZero(_)) | Instr(JumpNotZero(_)) => panic!("unexpected jump instruction"),
 
-        Loop(ref body) => {
-            while state.load() != 0 {
-                interpret(body, state, input, output)?;
-            }
-        }
+        Loop(_) => panic!("unexpected loop"),
     }
 
+    hooks.pointer(state.pointer());
     Ok(())
 }
 
@@ -116,6 +288,13 @@ mod tests {
         assert_parse_interpret(FACTOR_SRC, "100\n", "100: 2 2 5 5\n");
     }
 
+    #[test]
+    fn multi_tape() {
+        let program = crate::rle::compile(&parse_multi_tape(MULTI_TAPE_SRC));
+        let program = crate::peephole::compile(&program);
+        assert_interpret_tapes(&*program, 2, b"", b"ABB");
+    }
+
     fn assert_parse_interpret(program: &[u8], input: &str, output: &str) {
         let program = crate::ast::parse_program(program).unwrap();
         let program = crate::rle::compile(&program);
@@ -7,15 +7,40 @@
 //!
 //! For example, we detect the pattern `[-]`, which decrements the current byte until it reaches
 //! zero, and replaces it with the [`SetZero`](../../src/bf/peephole/mod.rs.html#21-22)
-//! instruction. See the [`common::Instruction`](../common/enum.Instruction.html) enum for a list of
-//! the instructions produced by the [peephole compiler](fn.compile.html).
+//! instruction. A `[-]` followed by `+++` becomes a single store,
+//! [`SetConst(3)`](../common/enum.Instruction.html#variant.SetConst). See the
+//! [`common::Instruction`](../common/enum.Instruction.html) enum for a list of the instructions
+//! produced by the [peephole compiler](fn.compile.html). In debug builds, the compiler checks its
+//! output with [`verify`](fn.verify.html).
+//!
+//! Custom passes can traverse a program with a [`Visitor`](trait.Visitor.html) and rewrite it
+//! with a [`Folder`](trait.Folder.html), overriding only the cases they care about.
+//!
+//! [`ToSource`](../traits/trait.ToSource.html) prints an optimized program back as plain
+//! Brainfuck, expanding each instruction to the loop or sequence that it replaced.
 
 use crate::common;
 
 mod compiler;
 mod interpreter;
+mod partial;
+mod source;
+mod unroll;
+mod verifier;
+mod visit;
+
+pub use self::compiler::{
+    coalesce_output, compile, compile_for, compile_prefilled, eliminate_dead_code,
+    find_zero_peephole, fold_moves, fold_set_const, lower, multiply_peephole, offset_add_peephole,
+    set_const_peephole, set_zero_peephole, PeepholeCompilable,
+};
+pub use self::partial::partially_evaluate;
+pub use self::unroll::unroll_loops;
+pub use self::verifier::verify;
+pub use self::visit::{fold_program, fold_statement, walk_program, walk_statement, Folder, Visitor};
 
-pub use self::compiler::{compile, PeepholeCompilable};
+#[cfg(feature = "tools")]
+pub(crate) use self::interpreter::interpret_instruction;
 
 /// At this level, a program is a rose tree of statements.
 ///
@@ -24,6 +49,7 @@ pub type Program = [Statement];
 
 /// Instructions as output by the peephole optimizer.
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Statement {
     /// A bytecode instruction, which does not contain any loops.
     ///
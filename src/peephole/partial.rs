@@ -0,0 +1,221 @@
+use super::*;
+use crate::common::{Count, Instruction};
+use crate::state::State;
+use crate::traits::IntoUsize;
+
+/// Runs the start of a program at compile time, replacing it with what it did.
+///
+/// Top-level statements run in order until one could read input or switch tapes, runs out of
+/// `fuel`, or moves past the ends of memory. The statements that ran become direct output of the
+/// bytes they wrote, followed by stores of the memory they left behind, so a program that only
+/// prints a banner becomes pure output.
+///
+/// The result assumes that the program starts with zeroed memory and the pointer at the start.
+/// It also moves as far right as the original did, before each output, so that it fails at the
+/// end of a smaller memory just as the original would.
+pub fn partially_evaluate(program: Box<Program>, fuel: usize) -> Box<Program> {
+    let mut evaluator = Evaluator {
+        state: State::new(),
+        output: Vec::new(),
+        high: 0,
+        fuel,
+    };
+
+    let done = program
+        .iter()
+        .take_while(|statement| evaluator.try_run(statement))
+        .count();
+
+    if done == 0 {
+        return program;
+    }
+
+    let mut result = evaluator.residual();
+    result.extend(program.into_vec().into_iter().skip(done));
+    result.into_boxed_slice()
+}
+
+/// The reason that evaluation stopped early.
+struct Stop;
+
+struct Evaluator {
+    state: State,
+    /// Each byte written, with the highest position that the pointer had reached before.
+    output: Vec<(usize, u8)>,
+    /// The highest position reached so far.
+    high: usize,
+    fuel: usize,
+}
+
+impl Evaluator {
+    /// Runs a statement, returning whether it finished. If it didn't, the evaluator is unchanged.
+    fn try_run(&mut self, statement: &Statement) -> bool {
+        if !is_self_contained(statement) {
+            return false;
+        }
+
+        let saved = (self.state.clone(), self.output.len(), self.high, self.fuel);
+
+        match self.run(statement) {
+            Ok(()) => true,
+            Err(Stop) => {
+                let (state, output_len, high, fuel) = saved;
+                self.state = state;
+                self.output.truncate(output_len);
+                self.high = high;
+                self.fuel = fuel;
+                false
+            }
+        }
+    }
+
+    fn run(&mut self, statement: &Statement) -> Result<(), Stop> {
+        match *statement {
+            Statement::Instr(ref instruction) => {
+                self.spend()?;
+                self.step(instruction)?;
+            }
+
+            Statement::Loop(ref body) => {
+                while self.state.load() != 0 {
+                    self.spend()?;
+                    for statement in body.iter() {
+                        self.run(statement)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn spend(&mut self) -> Result<(), Stop> {
+        self.fuel = self.fuel.checked_sub(1).ok_or(Stop)?;
+        Ok(())
+    }
+
+    fn step(&mut self, instruction: &Instruction) -> Result<(), Stop> {
+        use common::Instruction::*;
+
+        let state = &mut self.state;
+        let value = state.load();
+        let result = match *instruction {
+            Left(count) => state.left(count),
+            Right(count) => state.right(count),
+            Add(amount) => state.add(amount),
+            Out => {
+                self.output.push((self.high, value));
+                Ok(())
+            }
+            WriteStr(ref bytes) => {
+                let high = self.high;
+                self.output.extend(bytes.iter().map(|&byte| (high, byte)));
+                Ok(())
+            }
+            SetZero => {
+                state.store(0);
+                Ok(())
+            }
+            SetConst(value) => {
+                state.store(value);
+                Ok(())
+            }
+            FindZeroRight(skip) => state.find_zero_right(skip),
+            FindZeroLeft(skip) => state.find_zero_left(skip),
+            OffsetAddRight(offset) if value != 0 => {
+                self.high = self.high.max(state.pointer() + offset.into_usize());
+                state.store(0);
+                state.up_pos_offset(offset, value)
+            }
+            OffsetAddLeft(offset) if value != 0 => {
+                state.store(0);
+                state.up_neg_offset(offset, value)
+            }
+            MulAddRight(offset, factor) if value != 0 => {
+                self.high = self.high.max(state.pointer() + offset.into_usize());
+                state.up_pos_offset(offset, value.wrapping_mul(factor))
+            }
+            MulAddLeft(offset, factor) if value != 0 => {
+                state.up_neg_offset(offset, value.wrapping_mul(factor))
+            }
+            OffsetAddRight(_) | OffsetAddLeft(_) | MulAddRight(..) | MulAddLeft(..) => Ok(()),
+            AddAt(offset, amount) => {
+                self.high = self.high.max(state.pointer().saturating_add_signed(offset));
+                state.up_at(offset, amount)
+            }
+            SetAt(offset, value) => {
+                self.high = self.high.max(state.pointer().saturating_add_signed(offset));
+                state.store_at(offset, value)
+            }
+            In | SwitchTape(_) | JumpZero(_) | JumpNotZero(_) => return Err(Stop),
+        };
+
+        self.high = self.high.max(self.state.pointer());
+        result.map_err(|_| Stop)
+    }
+
+    /// The statements that do what the evaluated statements did.
+    fn residual(&self) -> Vec<Statement> {
+        let mut result = Vec::new();
+        let mut reached = 0;
+
+        // Write from the first cell, which gets its final value below.
+        for &(high, value) in &self.output {
+            if high > reached {
+                result.push(Statement::Instr(Instruction::Right(high as Count)));
+                result.push(Statement::Instr(Instruction::Left(high as Count)));
+                reached = high;
+            }
+            result.push(store(value));
+            result.push(Statement::Instr(Instruction::Out));
+        }
+
+        let memory = &self.state.as_bytes()[..=self.high];
+        let first = self.output.last().map_or(0, |&(_, value)| value);
+        let mut position = 0;
+        for (address, &value) in memory.iter().enumerate() {
+            let before = if address == 0 { first } else { 0 };
+            if value != before {
+                move_to(&mut result, &mut position, address);
+                result.push(store(value));
+            }
+        }
+
+        if self.high > reached {
+            move_to(&mut result, &mut position, self.high);
+        }
+        move_to(&mut result, &mut position, self.state.pointer());
+
+        result
+    }
+}
+
+/// Whether a statement runs without reading input or switching tapes.
+fn is_self_contained(statement: &Statement) -> bool {
+    match *statement {
+        Statement::Instr(Instruction::In) | Statement::Instr(Instruction::SwitchTape(_)) => false,
+        Statement::Instr(_) => true,
+        Statement::Loop(ref body) => body.iter().all(is_self_contained),
+    }
+}
+
+fn store(value: u8) -> Statement {
+    Statement::Instr(if value == 0 {
+        Instruction::SetZero
+    } else {
+        Instruction::SetConst(value)
+    })
+}
+
+fn move_to(result: &mut Vec<Statement>, position: &mut usize, target: usize) {
+    if target > *position {
+        result.push(Statement::Instr(Instruction::Right(
+            (target - *position) as Count,
+        )));
+    } else if target < *position {
+        result.push(Statement::Instr(Instruction::Left(
+            (*position - target) as Count,
+        )));
+    }
+    *position = target;
+}
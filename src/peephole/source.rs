@@ -0,0 +1,305 @@
+use std::iter;
+
+use super::compiler::Knowledge;
+use super::*;
+use crate::common::{Count, Instruction};
+use crate::traits::{IntoUsize, ToSource};
+
+/// Prints the program as Brainfuck, expanding each instruction to the loop or sequence that it
+/// replaced, so that `MulAddRight(2, 3) SetZero` becomes `[->>+++<<]`.
+///
+/// Two instructions have no expansion on their own. The multiplies leave the byte at the
+/// pointer alone, but the loop that does them zeroes it, so a run of them must be followed by
+/// a store to that byte. `WriteStr` must know the byte at the pointer, so that it can put it
+/// back after using it to write, or it too must be followed by a store. The peephole compiler
+/// only produces them that way.
+///
+/// # Panics
+///
+/// Panics if a multiply or `WriteStr` is used in some other way, or the program contains a jump.
+impl ToSource for Program {
+    fn write_source(&self, out: &mut String) {
+        write_statements(self, Knowledge::START, true, out);
+    }
+}
+
+fn write_statements(program: &Program, mut knowledge: Knowledge, top: bool, out: &mut String) {
+    use common::Instruction::*;
+
+    let mut index = 0;
+    while index < program.len() {
+        match program[index] {
+            Statement::Loop(ref body) => {
+                out.push('[');
+                write_statements(body, Knowledge::LOOP_BODY, false, out);
+                out.push(']');
+                knowledge = Knowledge::AFTER_LOOP;
+                index += 1;
+            }
+
+            Statement::Instr(MulAddRight(..)) | Statement::Instr(MulAddLeft(..)) => {
+                let end = index
+                    + program[index..]
+                        .iter()
+                        .take_while(|statement| is_multiply(statement))
+                        .count();
+                // If the byte is zero, the multiplies do nothing.
+                if knowledge.current != Some(0) {
+                    assert!(
+                        is_overwritten(&program[end..], top),
+                        "multiply without a store after it"
+                    );
+                    write_multiply(&program[index..end], out);
+                    knowledge.current = Some(0);
+                }
+                index = end;
+            }
+
+            Statement::Instr(WriteStr(ref bytes)) => {
+                let start = knowledge.current.unwrap_or_else(|| {
+                    assert!(
+                        is_overwritten(&program[index + 1..], top),
+                        "WriteStr of an unknown byte without a store after it"
+                    );
+                    out.push_str("[-]");
+                    0
+                });
+                let mut current = start;
+                for &byte in bytes.iter() {
+                    write_add(byte.wrapping_sub(current), out);
+                    out.push('.');
+                    current = byte;
+                }
+                match knowledge.current {
+                    Some(_) => write_add(start.wrapping_sub(current), out),
+                    None => knowledge.current = Some(current),
+                }
+                index += 1;
+            }
+
+            Statement::Instr(ref instruction) => {
+                match (instruction, knowledge.current) {
+                    // A store to a known byte needs only the difference.
+                    (SetZero, Some(current)) => write_add(current.wrapping_neg(), out),
+                    (SetConst(value), Some(current)) => write_add(value.wrapping_sub(current), out),
+                    _ => write_instruction(instruction, out),
+                }
+                knowledge = knowledge.step(instruction);
+                index += 1;
+            }
+        }
+    }
+}
+
+fn is_multiply(statement: &Statement) -> bool {
+    matches!(
+        *statement,
+        Statement::Instr(Instruction::MulAddRight(..))
+            | Statement::Instr(Instruction::MulAddLeft(..))
+    )
+}
+
+/// Whether the byte at the pointer is stored to before anything reads it, or before the program
+/// ends if `top` is set.
+fn is_overwritten(rest: &[Statement], top: bool) -> bool {
+    use common::Instruction::*;
+
+    for statement in rest {
+        match *statement {
+            Statement::Instr(SetZero) | Statement::Instr(SetConst(_)) => return true,
+            Statement::Instr(WriteStr(_)) => (),
+            Statement::Instr(AddAt(offset, _)) | Statement::Instr(SetAt(offset, _))
+                if offset != 0 => {}
+            _ => return false,
+        }
+    }
+
+    top
+}
+
+/// Writes the counting loop for a run of multiplies.
+fn write_multiply(group: &[Statement], out: &mut String) {
+    use common::Instruction::*;
+
+    out.push_str("[-");
+    let mut position = 0;
+    for statement in group {
+        let (offset, factor) = match *statement {
+            Statement::Instr(MulAddRight(offset, factor)) => (offset.into_usize() as isize, factor),
+            Statement::Instr(MulAddLeft(offset, factor)) => {
+                (-(offset.into_usize() as isize), factor)
+            }
+            _ => unreachable!("not a multiply: {:?}", statement),
+        };
+        write_move(offset - position, out);
+        write_add(factor, out);
+        position = offset;
+    }
+    write_move(-position, out);
+    out.push(']');
+}
+
+fn write_instruction(instruction: &Instruction, out: &mut String) {
+    use common::Instruction::*;
+
+    match *instruction {
+        Left(count) => write_repeat('<', count, out),
+        Right(count) => write_repeat('>', count, out),
+        Add(amount) => write_add(amount, out),
+        In => out.push(','),
+        Out => out.push('.'),
+        SetZero => out.push_str("[-]"),
+        SetConst(value) => {
+            out.push_str("[-]");
+            write_add(value, out);
+        }
+        OffsetAddRight(offset) => {
+            out.push_str("[-");
+            write_repeat('>', offset, out);
+            out.push('+');
+            write_repeat('<', offset, out);
+            out.push(']');
+        }
+        OffsetAddLeft(offset) => {
+            out.push_str("[-");
+            write_repeat('<', offset, out);
+            out.push('+');
+            write_repeat('>', offset, out);
+            out.push(']');
+        }
+        FindZeroRight(skip) => {
+            out.push('[');
+            write_repeat('>', skip, out);
+            out.push(']');
+        }
+        FindZeroLeft(skip) => {
+            out.push('[');
+            write_repeat('<', skip, out);
+            out.push(']');
+        }
+        AddAt(offset, amount) => {
+            write_move(offset, out);
+            write_add(amount, out);
+            write_move(-offset, out);
+        }
+        SetAt(offset, value) => {
+            write_move(offset, out);
+            out.push_str("[-]");
+            write_add(value, out);
+            write_move(-offset, out);
+        }
+        SwitchTape(count) => write_repeat('@', count, out),
+        MulAddRight(..) | MulAddLeft(..) | WriteStr(_) => {
+            unreachable!("{:?} needs its context", instruction)
+        }
+        JumpZero(_) | JumpNotZero(_) => panic!("unexpected jump instruction"),
+    }
+}
+
+fn write_repeat(symbol: char, count: Count, out: &mut String) {
+    out.extend(iter::repeat_n(symbol, count.into_usize()));
+}
+
+/// Writes `+` or `-`, whichever is shorter.
+fn write_add(amount: u8, out: &mut String) {
+    if amount <= 128 {
+        out.extend(iter::repeat_n('+', amount.into_usize()));
+    } else {
+        out.extend(iter::repeat_n('-', 256 - amount.into_usize()));
+    }
+}
+
+fn write_move(offset: isize, out: &mut String) {
+    let symbol = if offset < 0 { '<' } else { '>' };
+    out.extend(iter::repeat_n(symbol, offset.unsigned_abs()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Instruction::*;
+    use crate::test_helpers::*;
+    use crate::traits::*;
+    use quickcheck::quickcheck;
+
+    #[test]
+    fn expands_instructions() {
+        let program = [
+            Statement::Instr(In),
+            Statement::Instr(MulAddRight(2, 3)),
+            Statement::Instr(MulAddLeft(1, 255)),
+            Statement::Instr(SetConst(2)),
+            Statement::Instr(AddAt(-1, 1)),
+            Statement::Instr(SetAt(2, 0)),
+            Statement::Instr(Add(253)),
+            Statement::Loop(Box::new([Statement::Instr(OffsetAddRight(1))])),
+            Statement::Instr(FindZeroLeft(2)),
+        ];
+        assert_eq!(
+            program.to_source(),
+            ",[->>+++<<<->]++<+>>>[-]<<---[[->+<]][<<]"
+        );
+    }
+
+    #[test]
+    fn writes_strings_from_known_bytes() {
+        let program = [
+            Statement::Instr(SetConst(104)),
+            Statement::Instr(WriteStr(b"hi".as_slice().into())),
+            Statement::Instr(Out),
+        ];
+        let expected = format!("{}.+.-.", "+".repeat(104));
+        assert_eq!(program.to_source(), expected);
+    }
+
+    #[test]
+    fn writes_strings_before_stores() {
+        let program = [
+            Statement::Instr(In),
+            Statement::Instr(WriteStr(b"\x02\x01".as_slice().into())),
+            Statement::Instr(SetConst(5)),
+            Statement::Instr(Out),
+        ];
+        assert_eq!(program.to_source(), ",[-]++.-.++++.");
+    }
+
+    #[test]
+    #[should_panic(expected = "without a store")]
+    fn rejects_multiply_without_store() {
+        let program = [
+            Statement::Instr(In),
+            Statement::Instr(MulAddRight(1, 2)),
+            Statement::Instr(Out),
+        ];
+        program.to_source();
+    }
+
+    #[test]
+    fn compiled_programs_round_trip() {
+        let program = crate::ast::parse_program(FACTOR_SRC).unwrap();
+        let source = program.peephole_compile().to_source();
+        let program = crate::ast::parse_program(source.as_bytes()).unwrap();
+        assert_interpret(&*program, b"100\n", b"100: 2 2 5 5\n");
+
+        let program = crate::ast::parse_program(HELLO_WORLD_SRC).unwrap();
+        let source = program.peephole_compile().to_source();
+        let program = crate::ast::parse_program(source.as_bytes()).unwrap();
+        assert_interpret(&*program, b"", b"Hello, World!");
+    }
+
+    #[test]
+    fn round_trips_on_random_programs() {
+        fn prop(program: RandomProgram) -> bool {
+            let program = program.0;
+            if !halts_within(&program, 16, b"ab", 10_000) {
+                return true;
+            }
+
+            let source = program.peephole_compile().to_source();
+            let actual = crate::ast::parse_program(source.as_bytes()).unwrap();
+            program.interpret_memory(Some(16), b"ab") == actual.interpret_memory(Some(16), b"ab")
+        }
+
+        quickcheck(prop as fn(RandomProgram) -> bool);
+    }
+}
@@ -0,0 +1,103 @@
+use super::compiler::Knowledge;
+use super::*;
+
+/// Unrolls loops that are known to run a small, fixed number of times.
+///
+/// A loop qualifies when the byte at the pointer is known where it starts, as it is right after
+/// a `SetConst`, and its body is straight-line code that does no input, returns to where it
+/// began, and changes that byte only by adding to it. Then the loop runs until as many adds as it
+/// takes bring the byte to zero, so it becomes that many copies of its body, as long as they come
+/// to at most `budget` statements in all. Loops that would never stop stay as they are.
+///
+/// Inner loops are unrolled first, so that a loop whose inner loops all unroll can unroll too.
+/// The copies do the same moves in the same order as the loop did, so they fail at the ends of
+/// memory just as it would.
+pub fn unroll_loops(program: Box<Program>, budget: usize) -> Box<Program> {
+    unroll_loops_from(program, Knowledge::START, budget)
+}
+
+fn unroll_loops_from(
+    program: Box<Program>,
+    mut knowledge: Knowledge,
+    budget: usize,
+) -> Box<Program> {
+    let mut result = Vec::with_capacity(program.len());
+
+    for statement in program.into_vec() {
+        match statement {
+            Statement::Instr(ref instruction) => {
+                knowledge = knowledge.step(instruction);
+                result.push(statement);
+            }
+
+            Statement::Loop(body) => {
+                let body = unroll_loops_from(body, Knowledge::LOOP_BODY, budget);
+                let trips = knowledge
+                    .current
+                    .zip(counter_change(&body))
+                    .and_then(|(start, change)| trip_count(start, change))
+                    .filter(|&trips| trips * body.len() <= budget);
+
+                match trips {
+                    Some(trips) => {
+                        for _ in 0..trips {
+                            result.extend(body.iter().cloned());
+                        }
+                    }
+                    None => result.push(Statement::Loop(body)),
+                }
+
+                knowledge = Knowledge::AFTER_LOOP;
+            }
+        }
+    }
+
+    result.into_boxed_slice()
+}
+
+/// How much one pass through a loop body adds to the byte where it starts, if the body is
+/// straight-line code that does no input, returns to where it began, and changes that byte only
+/// by adding to it.
+fn counter_change(body: &[Statement]) -> Option<u8> {
+    use common::Instruction::*;
+
+    let mut offset = 0isize;
+    let mut change = 0u8;
+
+    for statement in body {
+        let instruction = match *statement {
+            Statement::Instr(ref instruction) => instruction,
+            Statement::Loop(_) => return None,
+        };
+
+        match *instruction {
+            Right(count) => offset += count as isize,
+            Left(count) => offset -= count as isize,
+            Add(amount) if offset == 0 => change = change.wrapping_add(amount),
+            AddAt(target, amount) if offset + target == 0 => change = change.wrapping_add(amount),
+            Add(_) | AddAt(..) | Out | WriteStr(_) => (),
+            SetZero | SetConst(_) if offset != 0 => (),
+            SetAt(target, _) if offset + target != 0 => (),
+            OffsetAddRight(target) if offset != 0 && offset + target as isize != 0 => (),
+            OffsetAddLeft(target) if offset != 0 && offset != target as isize => (),
+            MulAddRight(target, _) if offset + target as isize != 0 => (),
+            MulAddLeft(target, _) if offset != target as isize => (),
+            _ => return None,
+        }
+    }
+
+    if offset == 0 { Some(change) } else { None }
+}
+
+/// The number of times that adding `change` to `start` repeats before the byte is zero, or
+/// `None` if it never is.
+fn trip_count(start: u8, change: u8) -> Option<usize> {
+    let mut value = start;
+    for trips in 0..256 {
+        if value == 0 {
+            return Some(trips);
+        }
+        value = value.wrapping_add(change);
+    }
+    None
+}
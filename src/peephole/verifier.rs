@@ -0,0 +1,61 @@
+use super::*;
+
+/// Checks the invariants of a peephole program.
+///
+/// A peephole program must not contain `JumpZero` or `JumpNotZero` instructions, since loops
+/// are represented by `Loop` statements, and its moves, offsets, and find-zero skips must be
+/// non-zero. Positions in the error message are paths of statement indices through the loops.
+pub fn verify(program: &Program) -> Result<(), String> {
+    verify_at(program, "")
+}
+
+fn verify_at(program: &Program, path: &str) -> Result<(), String> {
+    use common::Instruction::*;
+
+    for (index, statement) in program.iter().enumerate() {
+        let position = format!("{}{}", path, index);
+
+        match *statement {
+            Statement::Instr(JumpZero(_)) | Statement::Instr(JumpNotZero(_)) => {
+                return Err(format!("jump instruction at {}", position));
+            }
+            Statement::Instr(ref instruction) => instruction
+                .check_count()
+                .map_err(|message| format!("{} at {}", message, position))?,
+            Statement::Loop(ref body) => verify_at(body, &format!("{}.", position))?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use self::Statement::*;
+    use super::*;
+    use common::Instruction::*;
+
+    #[test]
+    fn accepts_compiler_output() {
+        let program = crate::ast::parse_program(crate::test_helpers::FACTOR_SRC).unwrap();
+        assert_eq!(verify(&program.peephole_compile()), Ok(()));
+    }
+
+    #[test]
+    fn rejects_jumps() {
+        let program = [
+            Instr(Out),
+            Loop(Box::new([Instr(Add(1)), Instr(JumpZero(0))])),
+        ];
+        assert_eq!(verify(&program), Err("jump instruction at 1.1".to_owned()));
+    }
+
+    #[test]
+    fn rejects_zero_counts() {
+        let program = [Loop(Box::new([Instr(FindZeroRight(0))]))];
+        assert_eq!(
+            verify(&program),
+            Err("FindZeroRight(0) has a zero count at 0.0".to_owned())
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use super::*;
+use crate::common::Instruction;
+
+/// Traverses a peephole AST without changing it.
+///
+/// Every method has a default that visits the children, so an implementation only overrides
+/// the methods for what it looks at. An override that still wants the children visited calls
+/// [`walk_program`](fn.walk_program.html) or [`walk_statement`](fn.walk_statement.html).
+pub trait Visitor {
+    /// Visits each statement of a program or loop body, in order.
+    fn visit_program(&mut self, program: &Program) {
+        walk_program(self, program)
+    }
+
+    /// Visits a statement, dispatching on its form.
+    fn visit_statement(&mut self, statement: &Statement) {
+        walk_statement(self, statement)
+    }
+
+    /// Visits a non-loop instruction.
+    fn visit_instruction(&mut self, _instruction: &Instruction) {}
+
+    /// Visits a loop, whose body is visited by default.
+    fn visit_loop(&mut self, body: &Program) {
+        self.visit_program(body)
+    }
+}
+
+/// Visits each statement of `program` with `visitor`.
+pub fn walk_program<V: Visitor + ?Sized>(visitor: &mut V, program: &Program) {
+    for statement in program {
+        visitor.visit_statement(statement);
+    }
+}
+
+/// Passes `statement` to the `visitor` method for its form.
+pub fn walk_statement<V: Visitor + ?Sized>(visitor: &mut V, statement: &Statement) {
+    match *statement {
+        Statement::Instr(ref instruction) => visitor.visit_instruction(instruction),
+        Statement::Loop(ref body) => visitor.visit_loop(body),
+    }
+}
+
+/// Rewrites a peephole AST, statement by statement.
+///
+/// Every method has a default that rebuilds the statement from its rewritten children, so an
+/// implementation only overrides the methods for what it changes. A statement that folds to
+/// `None` is removed. An override that still wants the children rewritten calls
+/// [`fold_program`](fn.fold_program.html) or [`fold_statement`](fn.fold_statement.html).
+///
+/// A folder makes a simple [`Pass`](../pass/trait.Pass.html): its `run` folds the program.
+pub trait Folder {
+    /// Rewrites each statement of a program or loop body, in order.
+    fn fold_program(&mut self, program: Box<Program>) -> Box<Program> {
+        fold_program(self, program)
+    }
+
+    /// Rewrites a statement, dispatching on its form.
+    fn fold_statement(&mut self, statement: Statement) -> Option<Statement> {
+        fold_statement(self, statement)
+    }
+
+    /// Rewrites a non-loop instruction.
+    fn fold_instruction(&mut self, instruction: Instruction) -> Option<Statement> {
+        Some(Statement::Instr(instruction))
+    }
+
+    /// Rewrites a loop, whose body is rewritten by default.
+    fn fold_loop(&mut self, body: Box<Program>) -> Option<Statement> {
+        Some(Statement::Loop(self.fold_program(body)))
+    }
+}
+
+/// Rewrites each statement of `program` with `folder`, dropping those that fold to `None`.
+pub fn fold_program<F: Folder + ?Sized>(folder: &mut F, program: Box<Program>) -> Box<Program> {
+    program
+        .into_vec()
+        .into_iter()
+        .filter_map(|statement| folder.fold_statement(statement))
+        .collect()
+}
+
+/// Passes `statement` to the `folder` method for its form.
+pub fn fold_statement<F: Folder + ?Sized>(
+    folder: &mut F,
+    statement: Statement,
+) -> Option<Statement> {
+    match statement {
+        Statement::Instr(instruction) => folder.fold_instruction(instruction),
+        Statement::Loop(body) => folder.fold_loop(body),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Instruction::*;
+    use crate::test_helpers::*;
+
+    #[derive(Default)]
+    struct Outputs(usize);
+
+    impl Visitor for Outputs {
+        fn visit_instruction(&mut self, instruction: &Instruction) {
+            if let Out | WriteStr(_) = instruction {
+                self.0 += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn visits_nested_instructions() {
+        let program = crate::ast::parse_program(b",[.>,[.,]<]").unwrap();
+        let program = program.peephole_compile();
+        let mut outputs = Outputs::default();
+        outputs.visit_program(&program);
+        assert_eq!(outputs.0, 2);
+    }
+
+    /// Replaces `[-]` with a loop that counts up instead, leaving other loops alone.
+    struct CountUp;
+
+    impl Folder for CountUp {
+        fn fold_instruction(&mut self, instruction: Instruction) -> Option<Statement> {
+            match instruction {
+                SetZero => Some(Statement::Loop(Box::new([Statement::Instr(Add(1))]))),
+                _ => Some(Statement::Instr(instruction)),
+            }
+        }
+    }
+
+    #[test]
+    fn folds_nested_instructions() {
+        let program = Box::new([
+            Statement::Instr(In),
+            Statement::Loop(Box::new([Statement::Instr(SetZero)])),
+        ]);
+        let program = CountUp.fold_program(program);
+        assert_eq!(
+            &*program,
+            &[
+                Statement::Instr(In),
+                Statement::Loop(Box::new([Statement::Loop(Box::new([Statement::Instr(
+                    Add(1)
+                )]))])),
+            ]
+        );
+
+        let program = crate::ast::parse_program(FACTOR_SRC).unwrap();
+        let program = program.peephole_compile();
+        assert_interpret(&*CountUp.fold_program(program), b"100\n", b"100: 2 2 5 5\n");
+    }
+}
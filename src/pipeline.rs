@@ -0,0 +1,149 @@
+//! Running programs as a pipeline of filters.
+//!
+//! [`run`](fn.run.html) starts each program on its own thread, with its own copy of the state,
+//! and streams each program's output into the next one's input through a pipe, as a shell
+//! pipeline does, so that Brainfuck programs compose like Unix filters. A program's input ends
+//! when the program before it stops. Output between programs is flushed at each newline and when
+//! a program stops, so a line reaches the next program as soon as it is written.
+//!
+//! A program that stops early doesn't stop the program before it, whose output is then
+//! discarded, since writing output never fails in Brainfuck.
+//!
+//! ```
+//! use bf::ast;
+//! use bf::backend::Backend;
+//! use bf::pipeline;
+//! use bf::state::State;
+//!
+//! // Adds one to each byte, and then copies every other byte.
+//! let succ = ast::parse_program(b",[+.,]").unwrap();
+//! let odd = ast::parse_program(b",[.,[-],]").unwrap();
+//! let mut output = Vec::new();
+//! let results =
+//!     pipeline::run(Backend::Peephole, &[&*succ, &*odd], &State::new(), &b"HAL9"[..], &mut output)
+//!         .unwrap();
+//! assert_eq!(results, [Ok(()), Ok(())]);
+//! assert_eq!(output, b"IM");
+//! ```
+
+use std::io::{self, BufReader, LineWriter, Read, Write};
+use std::panic;
+use std::thread;
+
+use crate::ast;
+use crate::backend::Backend;
+use crate::common::BfResult;
+use crate::state::State;
+
+/// Runs `programs` in order on `backend`, each on its own thread against its own copy of
+/// `state`, with `input` going to the first program, each program's output going to the next,
+/// and the last program's output going to `output`. Returns how each program stopped, once they
+/// all have. With no programs, nothing runs.
+///
+/// # Errors
+///
+/// Any error creating the pipes between the programs.
+///
+/// # Panics
+///
+/// Panics if the backend [is native](../backend/enum.Backend.html#method.is_native) and the
+/// state has more than one tape.
+pub fn run<R, W>(
+    backend: Backend,
+    programs: &[&ast::Program],
+    state: &State,
+    input: R,
+    output: W,
+) -> io::Result<Vec<BfResult<()>>>
+where
+    R: Read + Send,
+    W: Write + Send,
+{
+    if programs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut inputs: Vec<Box<dyn Read + Send + '_>> = vec![Box::new(input)];
+    let mut outputs: Vec<Box<dyn Write + Send + '_>> = Vec::new();
+    for _ in 1..programs.len() {
+        let (reader, writer) = io::pipe()?;
+        outputs.push(Box::new(LineWriter::new(writer)));
+        inputs.push(Box::new(BufReader::new(reader)));
+    }
+    outputs.push(Box::new(output));
+
+    thread::scope(|scope| {
+        let stages: Vec<_> = programs
+            .iter()
+            .zip(inputs)
+            .zip(outputs)
+            .map(|((&program, input), mut output)| {
+                let mut state = state.clone();
+                scope.spawn(move || {
+                    let result = backend.interpret_in(program, &mut state, input, &mut output);
+                    let _ = output.flush();
+                    result
+                })
+            })
+            .collect();
+        Ok(stages
+            .into_iter()
+            .map(|stage| stage.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+            .collect())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Error;
+    use crate::test_helpers::*;
+
+    fn parse(src: &[u8]) -> Box<ast::Program> {
+        ast::parse_program(src).unwrap()
+    }
+
+    fn pipe(backend: Backend, srcs: &[&[u8]], input: &[u8]) -> (Vec<BfResult<()>>, Vec<u8>) {
+        let programs: Vec<_> = srcs.iter().map(|src| parse(src)).collect();
+        let programs: Vec<_> = programs.iter().map(|program| &**program).collect();
+        let mut output = Vec::new();
+        let results = run(backend, &programs, &State::new(), input, &mut output).unwrap();
+        (results, output)
+    }
+
+    #[test]
+    fn streams_each_output_to_the_next_program() {
+        let (succ, pred) = (&b",[+.,]"[..], &b",[-.,]"[..]);
+        for backend in Backend::all() {
+            if backend.name() == "llvm" {
+                continue;
+            }
+            let (results, output) = pipe(backend, &[succ, succ, pred, pred], b"Hello\n");
+            assert_eq!(results, [Ok(()); 4], "{}", backend);
+            assert_eq!(output, b"Hello\n", "{}", backend);
+        }
+    }
+
+    #[test]
+    fn runs_each_program_on_its_own_tape() {
+        // The second program starts with its own cell 0 still zero.
+        let (results, output) = pipe(
+            Backend::Bytecode(crate::bytecode::Encoding::Boxed),
+            &[b"+++.", b".,."],
+            b"",
+        );
+        assert_eq!(results, [Ok(()), Ok(())]);
+        assert_eq!(output, b"\x00\x03");
+    }
+
+    #[test]
+    fn reports_how_each_program_stopped() {
+        // The second program fails before reading the output of the first, which still finishes.
+        let (results, output) = pipe(Backend::Peephole, &[HELLO_WORLD_SRC, b"<", b",[.,]"], b"");
+        assert_eq!(results, [Ok(()), Err(Error::PointerUnderflow), Ok(())]);
+        assert_eq!(output, b"");
+
+        let (results, output) = pipe(Backend::Ast, &[], b"unread");
+        assert_eq!((results, output), (vec![], vec![]));
+    }
+}
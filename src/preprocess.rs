@@ -0,0 +1,657 @@
+//! An optional macro preprocessor for Brainfuck source.
+//!
+//! Hand-written Brainfuck repeats itself: forty `+`s to make a space, the same loop to copy a
+//! cell in several places. The preprocessor [`expand`](fn.expand.html)s a few shorthands into
+//! plain Brainfuck before parsing:
+//!
+//!  - `{N}` after a command or a macro use repeats it N times, so `+{40}` is forty `+`s.
+//!  - A line `%define NAME BODY` defines the macro NAME as the rest of the line, and `$NAME`
+//!    anywhere after it expands to the body, which may use other macros and repetitions.
+//!  - A line `%include "FILE"` stands for the expanded contents of FILE, whose macros are then
+//!    defined for the rest of the source. A relative FILE is found in the directory of the file
+//!    that includes it.
+//!
+//! Everything else passes through unchanged, so comments are still comments, except that `{`
+//! must start a repetition and a `%` at the start of a line must start a directive. The
+//! [`Expansion`](struct.Expansion.html) maps each byte of the result back to the file and the
+//! place in it that the byte came from, so that a syntax error in the expanded program points
+//! into the file that was written; a byte from a macro maps to where the macro was used.
+//!
+//! ```
+//! use bf::ast;
+//! use bf::preprocess;
+//!
+//! let src = b"%define copy [->+>+<<]\n+{3} $copy";
+//! let expansion = preprocess::expand(src).unwrap();
+//! assert_eq!(expansion.source(), b"\n+++ [->+>+<<]");
+//! assert!(ast::parse_program(expansion.source()).is_ok());
+//! ```
+//!
+//! In `bfi`, pass `--macros` to expand a program before running it.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::ast::{ParseError, Position};
+
+/// The most macros that can be expanding inside one another.
+const MAX_NESTING: usize = 64;
+
+/// What went wrong while preprocessing.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PreprocessErrorKind {
+    /// A `$` names a macro that isn't defined.
+    UndefinedMacro(String),
+    /// A macro uses itself, directly or through other macros.
+    RecursiveMacro(String),
+    /// A `%define` has no valid name.
+    BadDefinition,
+    /// A line starts with `%` and a word that isn't a directive.
+    UnknownDirective(String),
+    /// A `{` isn't a count followed by `}`, or doesn't follow a command or macro use.
+    BadRepetition,
+    /// An `%include` has no quoted file name.
+    BadInclude,
+    /// The named file can't be read, for the given reason.
+    IncludeFailed(String, String),
+    /// A file includes itself, directly or through other files.
+    RecursiveInclude(String),
+}
+
+impl fmt::Display for PreprocessErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::PreprocessErrorKind::*;
+
+        match *self {
+            UndefinedMacro(ref name) => write!(f, "undefined macro ‘{}’", name),
+            RecursiveMacro(ref name) => write!(f, "macro ‘{}’ uses itself", name),
+            BadDefinition => write!(f, "‘%define’ needs a macro name"),
+            UnknownDirective(ref name) => write!(f, "unknown directive ‘%{}’", name),
+            BadRepetition => write!(f, "‘{{’ must follow a command and hold a count"),
+            BadInclude => write!(f, "‘%include’ needs a quoted file name"),
+            IncludeFailed(ref name, ref reason) => {
+                write!(f, "cannot include ‘{}’: {}", name, reason)
+            }
+            RecursiveInclude(ref name) => write!(f, "file ‘{}’ includes itself", name),
+        }
+    }
+}
+
+/// A preprocessing error, with where it is in the original source or an included file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PreprocessError {
+    /// What is wrong.
+    pub kind: PreprocessErrorKind,
+    /// The included file that the error is in, or `None` if it is in the source.
+    pub file: Option<SourceFile>,
+    /// Where it is in that file.
+    pub position: Position,
+}
+
+impl PreprocessError {
+    /// Shows the line with the error and a caret under it. `src` must be the source that was
+    /// preprocessed, and is used only if the error isn't in an included file.
+    pub fn snippet(&self, src: &[u8]) -> String {
+        match self.file {
+            Some(ref file) => self.position.snippet(&file.text),
+            None => self.position.snippet(src),
+        }
+    }
+}
+
+impl fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {}",
+            self.kind, self.position.line, self.position.column
+        )
+    }
+}
+
+/// A file that the source includes.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SourceFile {
+    /// The path that the file was read from.
+    pub path: PathBuf,
+    /// The contents of the file, before expansion.
+    pub text: Vec<u8>,
+}
+
+/// Where a byte of the expanded source came from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Origin {
+    /// The index of the included file in
+    /// [`Expansion::included`](struct.Expansion.html#method.included), or `None` for the source
+    /// itself.
+    pub file: Option<usize>,
+    /// The offset in that file.
+    pub offset: usize,
+}
+
+/// Expanded source, with where each of its bytes came from.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Expansion {
+    source: Vec<u8>,
+    /// Where each byte of `source` came from.
+    origins: Vec<Origin>,
+    /// The length of the original source.
+    original_len: usize,
+    included: Vec<SourceFile>,
+}
+
+impl Expansion {
+    /// The expanded source, which is plain Brainfuck.
+    pub fn source(&self) -> &[u8] {
+        &self.source
+    }
+
+    /// The files that the source included, each once, in the order in which they were first
+    /// included.
+    pub fn included(&self) -> &[SourceFile] {
+        &self.included
+    }
+
+    /// Where the byte at `offset` in the expanded source came from. The end of the expanded
+    /// source maps to the end of the original.
+    pub fn origin(&self, offset: usize) -> Origin {
+        self.origins.get(offset).copied().unwrap_or(Origin {
+            file: None,
+            offset: self.original_len,
+        })
+    }
+
+    /// Moves a syntax error in the expanded source to where it came from, in `original`, which
+    /// must be the source that was expanded, or in the included file that
+    /// [`origin`](#method.origin) gives for it.
+    pub fn original_error(&self, original: &[u8], error: ParseError) -> ParseError {
+        let origin = self.origin(error.position.offset);
+        let text = match origin.file {
+            Some(file) => &self.included[file].text,
+            None => original,
+        };
+        ParseError {
+            position: Position::new(text, origin.offset),
+            ..error
+        }
+    }
+}
+
+/// Expands the macros, repetitions, and includes in `src`, finding relative includes in the
+/// current directory.
+///
+/// # Errors
+///
+/// Returns the first [`PreprocessError`](struct.PreprocessError.html), positioned in `src` or in
+/// the included file that it is in.
+pub fn expand(src: &[u8]) -> Result<Expansion, PreprocessError> {
+    expand_in(src, Path::new(""))
+}
+
+/// Expands `src` as [`expand`](fn.expand.html) does, but finds relative includes in `dir`, as
+/// for a source that was read from a file there.
+pub fn expand_in(src: &[u8], dir: &Path) -> Result<Expansion, PreprocessError> {
+    let mut expander = Expander {
+        src: Rc::from(src),
+        dir,
+        texts: Vec::new(),
+        canonical: Vec::new(),
+        macros: HashMap::new(),
+        active: Vec::new(),
+        including: Vec::new(),
+        expansion: Expansion {
+            original_len: src.len(),
+            ..Expansion::default()
+        },
+    };
+    expander.expand(None, 0..src.len(), None)?;
+    Ok(expander.expansion)
+}
+
+struct Expander<'a> {
+    src: Rc<[u8]>,
+    /// Where relative includes in the source are found.
+    dir: &'a Path,
+    /// The text of each included file, as in `expansion.included`.
+    texts: Vec<Rc<[u8]>>,
+    /// The canonical path of each included file, to tell when a file is included again.
+    canonical: Vec<PathBuf>,
+    /// The file and range of the body of each macro.
+    macros: HashMap<Vec<u8>, (Option<usize>, Range<usize>)>,
+    /// The macros that are expanding, innermost last.
+    active: Vec<Vec<u8>>,
+    /// The included files that are expanding, innermost last.
+    including: Vec<usize>,
+    expansion: Expansion,
+}
+
+impl Expander<'_> {
+    /// Expands `range` of a file, which is all of it or a macro body. The output maps to `site`,
+    /// where the macro was used, or else to where it came from.
+    fn expand(
+        &mut self,
+        file: Option<usize>,
+        range: Range<usize>,
+        site: Option<Origin>,
+    ) -> Result<(), PreprocessError> {
+        let src = self.text(file);
+        let mut offset = range.start;
+        // Where the output of the last command or macro use starts, if it can be repeated.
+        let mut unit = None;
+
+        while offset < range.end {
+            let byte = src[offset];
+            let at = site.unwrap_or(Origin { file, offset });
+
+            if site.is_none() && is_line_start(&src, offset) && byte == b'%' {
+                offset = self.directive(file, offset)?;
+                unit = None;
+                continue;
+            }
+
+            match byte {
+                b'$' if name_len(&src[offset + 1..range.end]) > 0 => {
+                    let start = offset + 1;
+                    let end = start + name_len(&src[start..range.end]);
+                    unit = Some(self.expansion.source.len());
+                    self.use_macro(file, start..end, at)?;
+                    offset = end;
+                }
+                b'{' => {
+                    let (start, (count, len)) = unit
+                        .take()
+                        .zip(repetition(&src[offset..range.end]))
+                        .ok_or_else(|| {
+                            self.error(PreprocessErrorKind::BadRepetition, file, offset)
+                        })?;
+                    self.repeat(start, count);
+                    offset += len;
+                }
+                b'<' | b'>' | b'+' | b'-' | b',' | b'.' | b'[' | b']' | b'@' => {
+                    unit = Some(self.expansion.source.len());
+                    self.push(byte, at);
+                    offset += 1;
+                }
+                _ => {
+                    unit = None;
+                    self.push(byte, at);
+                    offset += 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handles the directive on the line at `offset` in a file, returning the offset of the
+    /// newline that ends it.
+    fn directive(&mut self, file: Option<usize>, offset: usize) -> Result<usize, PreprocessError> {
+        let src = self.text(file);
+        let end = src[offset..]
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .map_or(src.len(), |len| offset + len);
+        let content_end = if src[..end].ends_with(b"\r") {
+            end - 1
+        } else {
+            end
+        };
+        let line = &src[offset + 1..end];
+        let word = name_len(line);
+        let rest = offset + 1 + word + whitespace_len(&line[word..]);
+
+        match &line[..word] {
+            b"define" => {
+                let name_end = rest + name_len(&src[rest..end]);
+                if name_end == rest || rest == offset + 1 + word {
+                    return Err(self.error(PreprocessErrorKind::BadDefinition, file, offset));
+                }
+                let body_start = name_end + whitespace_len(&src[name_end..end]);
+                self.macros.insert(
+                    src[rest..name_end].to_vec(),
+                    (file, body_start..content_end.max(body_start)),
+                );
+                Ok(end)
+            }
+            b"include" => {
+                let quoted = src[rest..content_end.max(rest)].trim_ascii_end();
+                match quoted {
+                    [b'"', name @ .., b'"'] if !name.is_empty() && !name.contains(&b'"') => {
+                        self.include(file, name, offset)?;
+                        Ok(end)
+                    }
+                    _ => Err(self.error(PreprocessErrorKind::BadInclude, file, offset)),
+                }
+            }
+            name => Err(self.error(
+                PreprocessErrorKind::UnknownDirective(String::from_utf8_lossy(name).into_owned()),
+                file,
+                offset,
+            )),
+        }
+    }
+
+    /// Expands the file named `name`, which the directive at `offset` in `file` includes.
+    fn include(
+        &mut self,
+        file: Option<usize>,
+        name: &[u8],
+        offset: usize,
+    ) -> Result<(), PreprocessError> {
+        let display = String::from_utf8_lossy(name).into_owned();
+        let dir = match file {
+            Some(file) => self.expansion.included[file]
+                .path
+                .parent()
+                .unwrap_or(Path::new("")),
+            None => self.dir,
+        };
+        let path = dir.join(&display);
+        let failed = |error: std::io::Error| {
+            let kind = PreprocessErrorKind::IncludeFailed(display.clone(), error.to_string());
+            self.error(kind, file, offset)
+        };
+
+        let canonical = fs::canonicalize(&path).map_err(failed)?;
+        let index = match self.canonical.iter().position(|other| *other == canonical) {
+            Some(index) => index,
+            None => {
+                let text = fs::read(&path).map_err(failed)?;
+                self.texts.push(Rc::from(&text[..]));
+                self.canonical.push(canonical);
+                self.expansion.included.push(SourceFile { path, text });
+                self.texts.len() - 1
+            }
+        };
+        if self.including.contains(&index) {
+            return Err(self.error(PreprocessErrorKind::RecursiveInclude(display), file, offset));
+        }
+
+        self.including.push(index);
+        let len = self.texts[index].len();
+        let result = self.expand(Some(index), 0..len, None);
+        self.including.pop();
+        result
+    }
+
+    /// Expands the macro whose name is at `name` in a file.
+    fn use_macro(
+        &mut self,
+        file: Option<usize>,
+        name: Range<usize>,
+        site: Origin,
+    ) -> Result<(), PreprocessError> {
+        let key = self.text(file)[name.clone()].to_vec();
+        let display = || String::from_utf8_lossy(&key).into_owned();
+        let (body_file, body) = match self.macros.get(&key) {
+            Some(body) => body.clone(),
+            None => {
+                return Err(self.error(
+                    PreprocessErrorKind::UndefinedMacro(display()),
+                    file,
+                    name.start - 1,
+                ));
+            }
+        };
+        if self.active.contains(&key) || self.active.len() >= MAX_NESTING {
+            return Err(self.error(
+                PreprocessErrorKind::RecursiveMacro(display()),
+                file,
+                name.start - 1,
+            ));
+        }
+
+        self.active.push(key);
+        let result = self.expand(body_file, body, Some(site));
+        self.active.pop();
+        result
+    }
+
+    /// Repeats the output from `start` on so that it appears `count` times.
+    fn repeat(&mut self, start: usize, count: usize) {
+        let expansion = &mut self.expansion;
+        if count == 0 {
+            expansion.source.truncate(start);
+            expansion.origins.truncate(start);
+            return;
+        }
+        let end = expansion.source.len();
+        for _ in 1..count {
+            expansion.source.extend_from_within(start..end);
+            expansion.origins.extend_from_within(start..end);
+        }
+    }
+
+    fn push(&mut self, byte: u8, origin: Origin) {
+        self.expansion.source.push(byte);
+        self.expansion.origins.push(origin);
+    }
+
+    /// The text of an included file, or of the source for `None`.
+    fn text(&self, file: Option<usize>) -> Rc<[u8]> {
+        match file {
+            Some(file) => Rc::clone(&self.texts[file]),
+            None => Rc::clone(&self.src),
+        }
+    }
+
+    fn error(
+        &self,
+        kind: PreprocessErrorKind,
+        file: Option<usize>,
+        offset: usize,
+    ) -> PreprocessError {
+        PreprocessError {
+            kind,
+            file: file.map(|file| self.expansion.included[file].clone()),
+            position: Position::new(&self.text(file), offset),
+        }
+    }
+}
+
+fn is_line_start(src: &[u8], offset: usize) -> bool {
+    offset == 0 || src[offset - 1] == b'\n'
+}
+
+/// The length of the macro name at the start of `bytes`, which may be zero.
+fn name_len(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .position(|&byte| !(byte.is_ascii_alphanumeric() || byte == b'_'))
+        .unwrap_or(bytes.len())
+}
+
+fn whitespace_len(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .position(|&byte| byte != b' ' && byte != b'\t')
+        .unwrap_or(bytes.len())
+}
+
+/// The count of the repetition `{N}` at the start of `bytes`, and its length.
+fn repetition(bytes: &[u8]) -> Option<(usize, usize)> {
+    let close = bytes.iter().position(|&byte| byte == b'}')?;
+    let digits = &bytes[1..close];
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let count = std::str::from_utf8(digits).ok()?.parse().ok()?;
+    Some((count, close + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast;
+    use crate::common::Error;
+
+    fn expanded(src: &[u8]) -> Vec<u8> {
+        expand(src).unwrap().source
+    }
+
+    fn error(src: &[u8]) -> (PreprocessErrorKind, usize, usize) {
+        let error = expand(src).unwrap_err();
+        (error.kind, error.position.line, error.position.column)
+    }
+
+    #[test]
+    fn repeats_commands_and_macros() {
+        assert_eq!(expanded(b"+{5}>{2}"), b"+++++>>");
+        assert_eq!(expanded(b"$ and 5$"), b"$ and 5$");
+        assert_eq!(expanded(b"+{0}-"), b"-");
+        assert_eq!(
+            expanded(b"%define inc +>\n%define twice $inc{2}\n$twice{2}<"),
+            b"\n\n+>+>+>+><"
+        );
+    }
+
+    #[test]
+    fn maps_offsets_back() {
+        let src = b"%define clear [-]\n+{3}\n$clear]";
+        let expansion = expand(src).unwrap();
+        assert_eq!(expansion.source(), b"\n+++\n[-]]");
+        // The `+`s come from offset 18, the macro's output from the `$` at 23.
+        assert_eq!(
+            (0..expansion.source().len())
+                .map(|offset| expansion.origin(offset).offset)
+                .collect::<Vec<_>>(),
+            [17, 18, 18, 18, 22, 23, 23, 23, 29]
+        );
+        assert_eq!(expansion.origin(9).offset, src.len());
+
+        let errors =
+            ast::parse_program_recovering(expansion.source(), &Default::default()).unwrap_err();
+        let error = expansion.original_error(src, errors[0]);
+        assert_eq!(error.error, Error::UnmatchedEnd);
+        assert_eq!((error.position.line, error.position.column), (3, 7));
+    }
+
+    #[test]
+    fn reports_errors_where_they_are() {
+        assert_eq!(
+            error(b"+\n $nope"),
+            (PreprocessErrorKind::UndefinedMacro("nope".to_owned()), 2, 2)
+        );
+        assert_eq!(
+            error(b"%define a $b\n%define b $a\n$a"),
+            (PreprocessErrorKind::RecursiveMacro("a".to_owned()), 2, 11)
+        );
+        assert_eq!(
+            error(b"%define\n"),
+            (PreprocessErrorKind::BadDefinition, 1, 1)
+        );
+        assert_eq!(
+            error(b"+\n%pragma x"),
+            (
+                PreprocessErrorKind::UnknownDirective("pragma".to_owned()),
+                2,
+                1
+            )
+        );
+        assert_eq!(error(b"+{x}"), (PreprocessErrorKind::BadRepetition, 1, 2));
+        assert_eq!(error(b" {3}"), (PreprocessErrorKind::BadRepetition, 1, 2));
+        let error = expand(b"+\n $nope").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "undefined macro ‘nope’ at line 2, column 2"
+        );
+        assert_eq!(error.snippet(b"+\n $nope"), " $nope\n ^");
+    }
+
+    /// A new directory holding the given files.
+    fn directory(name: &str, files: &[(&str, &[u8])]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bf-{}-{}", name, std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        for &(file, text) in files {
+            fs::write(dir.join(file), text).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn includes_files() {
+        let dir = directory(
+            "includes",
+            &[
+                (
+                    "lib/copy.bf",
+                    b"%include \"clear.bf\"\n%define copy [->+>+<<]\n",
+                ),
+                ("lib/clear.bf", b"%define clear [-]\n+]"),
+            ],
+        );
+        let src = b"%include \"lib/copy.bf\"\n$copy $clear";
+        let expansion = expand_in(src, &dir).unwrap();
+        assert_eq!(expansion.source(), b"\n+]\n\n\n[->+>+<<] [-]");
+        assert_eq!(
+            expansion
+                .included()
+                .iter()
+                .map(|file| file.path.clone())
+                .collect::<Vec<_>>(),
+            [dir.join("lib/copy.bf"), dir.join("lib/clear.bf")]
+        );
+        assert_eq!(
+            expansion.origin(2),
+            Origin {
+                file: Some(1),
+                offset: 19
+            }
+        );
+        assert_eq!(
+            expansion.origin(6),
+            Origin {
+                file: None,
+                offset: 23
+            }
+        );
+
+        let errors =
+            ast::parse_program_recovering(expansion.source(), &Default::default()).unwrap_err();
+        let error = expansion.original_error(src, errors[0]);
+        assert_eq!(error.error, Error::UnmatchedEnd);
+        assert_eq!((error.position.line, error.position.column), (2, 2));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reports_errors_in_included_files() {
+        let dir = directory(
+            "include-errors",
+            &[
+                ("a.bf", b"%include \"lib/b.bf\""),
+                ("lib/b.bf", b"%include \"../a.bf\""),
+                ("bad.bf", b"+\n$nope"),
+            ],
+        );
+        let error = expand_in(b"%include \"a.bf\"", &dir).unwrap_err();
+        assert_eq!(
+            error.kind,
+            PreprocessErrorKind::RecursiveInclude("../a.bf".to_owned())
+        );
+        assert_eq!(error.file.unwrap().path, dir.join("lib/b.bf"));
+
+        let error = expand_in(b"\n%include \"bad.bf\"", &dir).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "undefined macro ‘nope’ at line 2, column 1"
+        );
+        assert_eq!(error.snippet(b""), "$nope\n^");
+
+        let error = expand_in(b"\n%include \"none.bf\"", &dir).unwrap_err();
+        assert!(matches!(
+            error.kind,
+            PreprocessErrorKind::IncludeFailed(ref name, _) if name == "none.bf"
+        ));
+        assert_eq!((error.file, error.position.line), (None, 2));
+        assert_eq!(
+            expand(b"%include lib.bf").unwrap_err().kind,
+            PreprocessErrorKind::BadInclude
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,446 @@
+//! Attributing a program's running time to its peephole statements.
+//!
+//! [Counting](../counters/index.html) how often each instruction runs doesn't say where the time
+//! goes: a `FindZeroRight` that scans a long way costs more than an `Add`, and a loop costs the
+//! time of everything inside it. [`profile`](fn.profile.html) runs a peephole program and times
+//! its statements, so that the [`Profile`](struct.Profile.html) says how long each statement took
+//! in all, and how much of that was its own rather than that of the statements inside it, which
+//! tells whether a scan loop or a multiply loop is the bottleneck.
+//!
+//! Reading the clock around every statement would take longer than most statements do, so the
+//! profiler times only one in every [`SAMPLE_EVERY`](constant.SAMPLE_EVERY.html) runs of each
+//! statement, and scales the time by the number of runs. It measures how long reading the clock
+//! takes, and subtracts that from each timed run and from the loops around it. The times of
+//! single instructions are rough, since they are close to the resolution of the clock; the times
+//! of loops are not.
+//!
+//! With the `jit` feature, [`profile_jit`](fn.profile_jit.html) instead runs JIT-compiled code
+//! that reads the time-stamp counter with `rdtsc` as each loop starts and ends, and turns cycles
+//! into time by the length of the whole run. It times only the loops, so the instructions in a
+//! loop count toward the loop's own time.
+//!
+//! In `bfi`, run `bfi profile` to profile a program.
+
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+use crate::analysis::{self, Proof};
+use crate::common::BfResult;
+use crate::peephole::{self, Program, Statement};
+use crate::state::State;
+use crate::traits::ToSource;
+
+/// How often the profiler times a statement: once in this many runs.
+pub const SAMPLE_EVERY: u64 = 16;
+
+/// The longest label, in characters, that a loop gets before it is cut short.
+const LABEL_WIDTH: usize = 40;
+
+/// What the profiler found about one statement.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entry {
+    /// How many loops the statement is inside.
+    pub depth: usize,
+    /// The instruction, or the loop as Brainfuck.
+    pub label: String,
+    /// How many times the statement ran. A loop runs once each time it is reached, however many
+    /// times its body runs.
+    pub hits: u64,
+    /// The time of all the statement's runs, including the statements inside it.
+    pub time: Duration,
+    /// `time`, less the time of the statements inside it.
+    pub self_time: Duration,
+}
+
+/// Where the time went in the runs of a program, one entry for each statement.
+#[derive(Clone, Debug)]
+pub struct Profile {
+    /// The statements in the order that they start in the program.
+    entries: Vec<Entry>,
+    /// The index of the loop around each statement.
+    parents: Vec<Option<usize>>,
+    /// The index of the statement after each one and everything inside it.
+    next: Vec<usize>,
+    /// The indices of the loops.
+    loops: Vec<usize>,
+    /// The number of timed runs of each statement, and their total time in nanoseconds.
+    samples: Vec<(u64, u64)>,
+}
+
+impl Profile {
+    /// Creates an empty profile for a program.
+    pub fn new(program: &Program) -> Self {
+        let mut profile = Profile {
+            entries: Vec::new(),
+            parents: Vec::new(),
+            next: Vec::new(),
+            loops: Vec::new(),
+            samples: Vec::new(),
+        };
+        profile.add(program, None, 0);
+        profile
+    }
+
+    fn add(&mut self, program: &[Statement], parent: Option<usize>, depth: usize) {
+        for statement in program {
+            let index = self.entries.len();
+            let label = match *statement {
+                Statement::Instr(ref instruction) => format!("{:?}", instruction),
+                Statement::Loop(_) => {
+                    let source = std::slice::from_ref(statement).to_source();
+                    if source.chars().count() > LABEL_WIDTH {
+                        let cut: String = source.chars().take(LABEL_WIDTH - 3).collect();
+                        cut + "..."
+                    } else {
+                        source
+                    }
+                }
+            };
+            self.entries.push(Entry {
+                depth,
+                label,
+                hits: 0,
+                time: Duration::ZERO,
+                self_time: Duration::ZERO,
+            });
+            self.parents.push(parent);
+            self.next.push(index + 1);
+            self.samples.push((0, 0));
+
+            if let Statement::Loop(ref body) = *statement {
+                self.loops.push(index);
+                self.add(body, Some(index), depth + 1);
+                self.next[index] = self.entries.len();
+            }
+        }
+    }
+
+    /// The statements, in the order that they start in the program.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The index of the loop that the statement at `index` is directly inside, if any.
+    pub fn parent(&self, index: usize) -> Option<usize> {
+        self.parents[index]
+    }
+
+    /// The time of the statements outside every loop, which is the time of the whole program.
+    pub fn total(&self) -> Duration {
+        self.entries
+            .iter()
+            .filter(|entry| entry.depth == 0)
+            .map(|entry| entry.time)
+            .sum()
+    }
+
+    /// The indices of the statements that took the most time of their own, most first, leaving
+    /// out those that never ran.
+    pub fn hottest(&self) -> Vec<usize> {
+        let mut result: Vec<usize> = (0..self.entries.len())
+            .filter(|&index| self.entries[index].hits > 0)
+            .collect();
+        result.sort_by_key(|&index| std::cmp::Reverse(self.entries[index].self_time));
+        result
+    }
+
+    /// Works out the times of the entries from the timed runs.
+    fn estimate(&mut self) {
+        for (entry, &(samples, nanos)) in self.entries.iter_mut().zip(&self.samples) {
+            if samples > 0 {
+                let nanos = nanos as u128 * entry.hits as u128 / samples as u128;
+                entry.time = Duration::from_nanos(nanos.min(u64::MAX as u128) as u64);
+            }
+        }
+        self.own_times();
+    }
+
+    /// Works out each entry's own time from the times of the entries.
+    fn own_times(&mut self) {
+        let mut inner = vec![Duration::ZERO; self.entries.len()];
+        for (index, parent) in self.parents.iter().enumerate() {
+            if let Some(parent) = *parent {
+                inner[parent] += self.entries[index].time;
+            }
+        }
+        for (entry, inner) in self.entries.iter_mut().zip(inner) {
+            entry.self_time = entry.time.saturating_sub(inner);
+        }
+    }
+}
+
+/// Runs a peephole program against the given state, adding where its time went to `profile`,
+/// which must have been created for the same program.
+///
+/// # Errors
+///
+/// The errors of the program itself. The profile covers what ran before the error.
+pub fn profile<R: Read, W: Write>(
+    program: &Program,
+    state: &mut State,
+    mut input: R,
+    mut output: W,
+    profile: &mut Profile,
+) -> BfResult<()> {
+    let proofs = analysis::prove(program);
+    let mut profiler = Profiler {
+        profile,
+        overhead: clock_overhead(),
+        timed: 0,
+    };
+    let result = profiler.run(program, &proofs, 0, state, &mut input, &mut output);
+    profiler.profile.estimate();
+    result
+}
+
+struct Profiler<'a> {
+    profile: &'a mut Profile,
+    /// How long it takes to read the clock twice, in nanoseconds.
+    overhead: u64,
+    /// The number of runs timed so far.
+    timed: u64,
+}
+
+impl Profiler<'_> {
+    /// Runs a sequence of statements, the first of which has the given index.
+    fn run<R: Read, W: Write>(
+        &mut self,
+        program: &[Statement],
+        proofs: &[Proof],
+        mut index: usize,
+        state: &mut State,
+        input: &mut R,
+        output: &mut W,
+    ) -> BfResult<()> {
+        for (statement, proof) in program.iter().zip(proofs) {
+            let hits = self.profile.entries[index].hits;
+            self.profile.entries[index].hits += 1;
+
+            if hits.is_multiple_of(SAMPLE_EVERY) {
+                let timed = self.timed;
+                let start = Instant::now();
+                self.execute(statement, proof, index, state, input, output)?;
+                let elapsed = start.elapsed().as_nanos() as u64;
+                // Each timed run inside this one, and this one, read the clock twice.
+                let clock = self.overhead * (self.timed - timed + 1);
+                self.timed += 1;
+
+                let samples = &mut self.profile.samples[index];
+                samples.0 += 1;
+                samples.1 += elapsed.saturating_sub(clock);
+            } else {
+                self.execute(statement, proof, index, state, input, output)?;
+            }
+
+            index = self.profile.next[index];
+        }
+
+        Ok(())
+    }
+
+    fn execute<R: Read, W: Write>(
+        &mut self,
+        statement: &Statement,
+        proof: &Proof,
+        index: usize,
+        state: &mut State,
+        input: &mut R,
+        output: &mut W,
+    ) -> BfResult<()> {
+        match *statement {
+            Statement::Instr(_) => peephole::interpret_instruction::<_, _, _, false>(
+                statement,
+                proof,
+                state,
+                input,
+                output,
+                &mut (),
+            ),
+            Statement::Loop(ref body) => {
+                let proofs = match *proof {
+                    Proof::Loop(ref proofs) => proofs,
+                    Proof::Instr(_) => panic!("proofs do not match program"),
+                };
+                while state.load() != 0 {
+                    self.run(body, proofs, index + 1, state, input, output)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// How long it takes to read the clock twice, in nanoseconds, at best.
+fn clock_overhead() -> u64 {
+    (0..1000)
+        .map(|_| {
+            let start = Instant::now();
+            start.elapsed().as_nanos() as u64
+        })
+        .min()
+        .unwrap_or(0)
+}
+
+/// Runs a peephole program as JIT-compiled code that times its loops, adding where its time went
+/// to `profile`, which must have been created for the same program. Only the loops get times;
+/// every statement gets its hits only if it is a loop.
+///
+/// # Errors
+///
+/// The errors of the program itself. The profile covers what ran before the error.
+///
+/// # Panics
+///
+/// Panics if the state has more than one tape.
+#[cfg(feature = "jit")]
+pub fn profile_jit<R: Read, W: Write>(
+    program: &Program,
+    state: &mut State,
+    input: R,
+    output: W,
+    profile: &mut Profile,
+) -> BfResult<()> {
+    let compiled = crate::jit::compile_profiling(program, true);
+    let mut table = vec![0; 2 * profile.loops.len()];
+
+    let start = Instant::now();
+    let (result, cycles) = compiled.interpret_profiling(state, input, output, &mut table);
+    let nanos_per_cycle = start.elapsed().as_nanos() as f64 / cycles.max(1) as f64;
+
+    for (&index, times) in profile.loops.iter().zip(table.chunks(2)) {
+        let entry = &mut profile.entries[index];
+        entry.time += Duration::from_nanos((times[0] as f64 * nanos_per_cycle) as u64);
+        entry.hits += times[1];
+    }
+    profile.own_times();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast;
+    use crate::test_helpers::*;
+    use crate::traits::PeepholeCompilable;
+    use std::io::Cursor;
+
+    fn run(src: &[u8], input: &[u8]) -> (Profile, Vec<u8>) {
+        let program = ast::parse_program(src).unwrap().peephole_compile();
+        let mut profile = Profile::new(&program);
+        let mut output = Vec::new();
+        super::profile(
+            &program,
+            &mut State::new(),
+            Cursor::new(input),
+            &mut output,
+            &mut profile,
+        )
+        .unwrap();
+        (profile, output)
+    }
+
+    #[test]
+    fn counts_hits() {
+        let (profile, output) = run(b"+++[>,.<-]", b"abc");
+        assert_eq!(output, b"abc");
+        let hits: Vec<(usize, &str, u64)> = profile
+            .entries()
+            .iter()
+            .map(|entry| (entry.depth, &*entry.label, entry.hits))
+            .collect();
+        assert_eq!(
+            hits,
+            [
+                (0, "Add(3)", 1),
+                (0, "[>,.<-]", 1),
+                (1, "Right(1)", 3),
+                (1, "In", 3),
+                (1, "Out", 3),
+                (1, "Left(1)", 3),
+                (1, "Add(255)", 3),
+            ]
+        );
+        assert_eq!(profile.parent(0), None);
+        assert_eq!(profile.parent(4), Some(1));
+    }
+
+    #[test]
+    fn attributes_time_to_loops() {
+        // Each scan runs over 5,000 cells, which takes far longer than the rest of the loop.
+        let mut src = b"++++++++++++++++>>".to_vec();
+        src.extend_from_slice(&b"+>".repeat(5_000));
+        src.extend_from_slice(b"<[<]<[>>[>]<[<]<-]");
+        let (profile, _) = run(&src, b"");
+        assert!(profile.total() > Duration::ZERO);
+
+        let entries = profile.entries();
+        let hottest = &entries[profile.hottest()[0]];
+        assert!(hottest.label.starts_with("FindZero"), "{:?}", hottest);
+        assert_eq!(hottest.depth, 1);
+        for entry in entries {
+            assert!(entry.self_time <= entry.time);
+        }
+    }
+
+    #[test]
+    fn profiles_factor() {
+        let program = ast::parse_program(FACTOR_SRC).unwrap().peephole_compile();
+        let mut profile = Profile::new(&program);
+        let mut output = Vec::new();
+        let result = super::profile(
+            &program,
+            &mut State::new(),
+            Cursor::new(b"100\n"),
+            &mut output,
+            &mut profile,
+        );
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, b"100: 2 2 5 5\n");
+        assert_eq!(profile.entries().len(), program_len(&program));
+    }
+
+    fn program_len(program: &[Statement]) -> usize {
+        program
+            .iter()
+            .map(|statement| match *statement {
+                Statement::Instr(_) => 1,
+                Statement::Loop(ref body) => 1 + program_len(body),
+            })
+            .sum()
+    }
+
+    #[cfg(feature = "jit")]
+    #[test]
+    fn profiles_jit_loops() {
+        let program = ast::parse_program(FACTOR_SRC).unwrap().peephole_compile();
+        let mut interpreted = Profile::new(&program);
+        super::profile(
+            &program,
+            &mut State::new(),
+            Cursor::new(b"360\n"),
+            std::io::sink(),
+            &mut interpreted,
+        )
+        .unwrap();
+
+        let mut jitted = Profile::new(&program);
+        let mut output = Vec::new();
+        let result = profile_jit(
+            &program,
+            &mut State::new(),
+            Cursor::new(b"360\n"),
+            &mut output,
+            &mut jitted,
+        );
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, b"360: 2 2 2 3 3 5\n");
+        for (interpreted, jitted) in interpreted.entries().iter().zip(jitted.entries()) {
+            if jitted.label.starts_with('[') {
+                assert_eq!(interpreted.hits, jitted.hits, "{}", jitted.label);
+            } else {
+                assert_eq!(jitted.hits, 0);
+            }
+        }
+        assert!(jitted.total() > Duration::ZERO);
+    }
+}
@@ -0,0 +1,155 @@
+//! Python bindings.
+//!
+//! Enabled with `--features=python`. To build an extension module that Python can import as
+//! `bf`, build a shared library with the `extension-module` feature of PyO3 and rename it:
+//!
+//! ```text
+//! cargo rustc --lib --release --features python,pyo3/extension-module --crate-type cdylib
+//! cp target/release/libbf.so bf.so
+//! ```
+//!
+//! Then, from Python:
+//!
+//! ```text
+//! >>> import bf
+//! >>> program = bf.compile(",[.,]", backend="bytecode")
+//! >>> program.run(b"hello")
+//! b'hello'
+//! ```
+//!
+//! The available backends are listed in [`BACKENDS`](constant.BACKENDS.html), and the default is
+//! `peephole`.
+
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::ast;
+use crate::bytecode::{self, Encoding};
+use crate::fused;
+use crate::peephole;
+use crate::rle;
+use crate::traits::*;
+
+/// A program compiled for some backend.
+enum Compiled {
+    Ast(Box<ast::Program>),
+    Rle(Box<rle::Program>),
+    Peephole(Box<peephole::Program>),
+    Bytecode(bytecode::Encoded),
+    Fused(Box<fused::Program>),
+    #[cfg(feature = "jit")]
+    Jit(crate::jit::Program),
+}
+
+/// The names of the backends that `compile` accepts.
+pub const BACKENDS: &[&str] = &[
+    "ast",
+    "rle",
+    "peephole",
+    "bytecode",
+    "packed",
+    "threaded",
+    "fused",
+    #[cfg(feature = "jit")]
+    "jit",
+];
+
+impl Compiled {
+    fn new(program: &ast::Program, backend: &str) -> Option<Self> {
+        Some(match backend {
+            "ast" => Compiled::Ast(program.into()),
+            "rle" => Compiled::Rle(program.rle_compile()),
+            "peephole" => Compiled::Peephole(program.peephole_compile()),
+            "bytecode" => Compiled::Bytecode(program.bytecode_compile_with(Encoding::Boxed)),
+            "packed" => Compiled::Bytecode(program.bytecode_compile_with(Encoding::Packed)),
+            "threaded" => Compiled::Bytecode(program.bytecode_compile_with(Encoding::Threaded)),
+            "fused" => Compiled::Fused(program.fused_compile()),
+            #[cfg(feature = "jit")]
+            "jit" => Compiled::Jit(program.jit_compile(true)),
+            _ => return None,
+        })
+    }
+
+    fn run(&self, memory: usize, input: &[u8]) -> crate::common::BfResult<Vec<u8>> {
+        let memory = Some(memory);
+        match *self {
+            Compiled::Ast(ref program) => program.interpret_memory(memory, input),
+            Compiled::Rle(ref program) => program.interpret_memory(memory, input),
+            Compiled::Peephole(ref program) => program.interpret_memory(memory, input),
+            Compiled::Bytecode(ref program) => program.interpret_memory(memory, input),
+            Compiled::Fused(ref program) => program.interpret_memory(memory, input),
+            #[cfg(feature = "jit")]
+            Compiled::Jit(ref program) => program.interpret_memory(memory, input),
+        }
+    }
+}
+
+/// A compiled Brainfuck program.
+#[pyclass(name = "Program", module = "bf", frozen)]
+pub struct Program {
+    compiled: Compiled,
+    backend: String,
+}
+
+#[pymethods]
+impl Program {
+    /// Runs the program on the given input, returning its output.
+    #[pyo3(signature = (input = None, memory = 30_000))]
+    fn run<'py>(
+        &self,
+        py: Python<'py>,
+        input: Option<&[u8]>,
+        memory: usize,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        if memory == 0 {
+            return Err(PyValueError::new_err("memory size must be at least 1"));
+        }
+
+        let output = self
+            .compiled
+            .run(memory, input.unwrap_or(&[]))
+            .map_err(|e| PyRuntimeError::new_err(format!("runtime error: {}", e)))?;
+        Ok(PyBytes::new(py, &output))
+    }
+
+    /// The name of the backend the program was compiled for.
+    #[getter]
+    fn backend(&self) -> &str {
+        &self.backend
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<bf.Program backend={:?}>", self.backend)
+    }
+}
+
+/// Compiles Brainfuck source for the given backend.
+#[pyfunction]
+#[pyo3(signature = (source, backend = "peephole"))]
+fn compile(source: &str, backend: &str) -> PyResult<Program> {
+    let program = ast::parse_program(source.as_bytes())
+        .map_err(|e| PyValueError::new_err(format!("syntax error: {}", e)))?;
+
+    let compiled = Compiled::new(&program, backend).ok_or_else(|| {
+        PyValueError::new_err(format!(
+            "unknown backend {:?}; expected one of {}",
+            backend,
+            BACKENDS.join(", ")
+        ))
+    })?;
+
+    Ok(Program {
+        compiled,
+        backend: backend.to_owned(),
+    })
+}
+
+/// The `bf` Python module.
+#[pymodule]
+fn bf(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Program>()?;
+    m.add_function(wrap_pyfunction!(compile, m)?)?;
+    m.add("BACKENDS", BACKENDS.to_vec())?;
+    Ok(())
+}
@@ -0,0 +1,120 @@
+//! Random input that is the same on every run.
+//!
+//! Some Brainfuck programs play games or make choices at random, taking their randomness from
+//! `,`. Reading from a [`RandomInput`](struct.RandomInput.html) instead of from a person gives
+//! such a program an endless stream of pseudorandom bytes, chosen by a seed, so that a run can
+//! be repeated exactly by giving the same seed again. It is not for cryptography.
+//!
+//! In `bfi`, pass `--random-input=SEED` to make `,` read from a `RandomInput`.
+//!
+//! ```
+//! use bf::ast;
+//! use bf::random::RandomInput;
+//! use bf::traits::*;
+//!
+//! // Prints four random bytes.
+//! let program = ast::parse_program(b",.,.,.,.").unwrap();
+//! let (mut first, mut again) = (Vec::new(), Vec::new());
+//! program.interpret(None, RandomInput::new(7), &mut first).unwrap();
+//! program.interpret(None, RandomInput::new(7), &mut again).unwrap();
+//! assert_eq!(first.len(), 4);
+//! assert_eq!(first, again);
+//! ```
+
+use std::io::{self, Read};
+
+/// An endless input of pseudorandom bytes, which is the same for the same seed.
+#[derive(Clone, Debug)]
+pub struct RandomInput {
+    random: SplitMix,
+}
+
+impl RandomInput {
+    /// The bytes chosen by `seed`.
+    pub fn new(seed: u64) -> Self {
+        RandomInput {
+            random: SplitMix::new(seed),
+        }
+    }
+}
+
+impl Read for RandomInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        for byte in buf.iter_mut() {
+            *byte = (self.random.next() >> 56) as u8;
+        }
+        Ok(buf.len())
+    }
+}
+
+/// A small pseudorandom generator, which gives a different sequence for every seed.
+#[derive(Clone, Debug)]
+pub(crate) struct SplitMix(u64);
+
+impl SplitMix {
+    pub(crate) fn new(seed: u64) -> Self {
+        SplitMix(seed)
+    }
+
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number less than `bound`.
+    pub(crate) fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast;
+    use crate::conformance;
+    use crate::state::State;
+    use crate::traits::*;
+
+    fn bytes(seed: u64, len: usize) -> Vec<u8> {
+        let mut result = vec![0; len];
+        RandomInput::new(seed).read_exact(&mut result).unwrap();
+        result
+    }
+
+    #[test]
+    fn depends_on_the_seed() {
+        assert_eq!(bytes(1, 64), bytes(1, 64));
+        assert_ne!(bytes(1, 64), bytes(2, 64));
+        // Reading in pieces gives the same bytes as reading all at once.
+        let mut input = RandomInput::new(1);
+        let mut pieces = vec![0; 64];
+        for piece in pieces.chunks_mut(5) {
+            input.read_exact(piece).unwrap();
+        }
+        assert_eq!(pieces, bytes(1, 64));
+    }
+
+    #[test]
+    fn covers_every_byte() {
+        let mut seen = [false; 256];
+        for byte in bytes(0, 4096) {
+            seen[byte as usize] = true;
+        }
+        assert!(seen.iter().all(|&seen| seen));
+    }
+
+    #[test]
+    fn every_backend_reads_the_same_bytes() {
+        // Prints 100 random bytes.
+        let program = ast::parse_program(b"++++++++++[>++++++++++<-]>[<,.>-]").unwrap();
+        let outcome = conformance::check_in(&program, &State::new(), &bytes(3, 100)).unwrap();
+        let mut output = Vec::new();
+        program
+            .interpret(None, RandomInput::new(3), &mut output)
+            .unwrap();
+        assert_eq!(output, outcome.output);
+    }
+}
@@ -0,0 +1,222 @@
+//! Recording the input and output of a run, and replaying it.
+//!
+//! An interactive program is hard to debug when a bug shows up only for input that someone
+//! typed. Wrapping the program's input and output in [`Record`](struct.Record.html) captures
+//! every byte that it reads and writes, and a [`Recording`](struct.Recording.html) saves them
+//! to a replay file. Replaying feeds the recorded input to the program again, which then does
+//! exactly what it did the first time, because interpretation depends on nothing else; the
+//! recorded output shows where a change made it do something different.
+//!
+//! A replay file is a header line, `bf-replay 1`, and then the input and the output, each as a
+//! line `input N` or `output N` followed by the N bytes and a newline.
+//!
+//! In `bfi`, pass `--record FILE` to record a run, and `--replay FILE` to replay one.
+//!
+//! ```
+//! use bf::ast;
+//! use bf::replay::{Record, Recording};
+//! use bf::traits::*;
+//!
+//! let program = ast::parse_program(b",[.,]").unwrap();
+//! let mut input = Record::new(&b"hi"[..]);
+//! let mut output = Record::new(Vec::new());
+//! program.interpret(None, &mut input, &mut output).unwrap();
+//!
+//! let recording = Recording::new(input.into_bytes(), output.into_bytes());
+//! let mut file = Vec::new();
+//! recording.write_to(&mut file).unwrap();
+//!
+//! let recording = Recording::read_from(&file[..]).unwrap();
+//! let output = program.interpret_memory(None, &recording.input).unwrap();
+//! assert_eq!(recording.first_difference(&output), None);
+//! ```
+
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+
+/// The first line of a replay file.
+const HEADER: &str = "bf-replay 1";
+
+/// Passes reads or writes through, keeping a copy of every byte.
+#[derive(Debug)]
+pub struct Record<T> {
+    inner: T,
+    bytes: Vec<u8>,
+}
+
+impl<T> Record<T> {
+    /// Records the bytes read from or written to `inner`.
+    pub fn new(inner: T) -> Self {
+        Record {
+            inner,
+            bytes: Vec::new(),
+        }
+    }
+
+    /// The bytes so far.
+    pub fn recorded(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The bytes, discarding the reader or writer.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+impl<R: Read> Read for Record<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.bytes.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+}
+
+impl<W: Write> Write for Record<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.bytes.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The input and output of a run.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Recording {
+    /// Everything that the program read.
+    pub input: Vec<u8>,
+    /// Everything that the program wrote.
+    pub output: Vec<u8>,
+}
+
+impl Recording {
+    /// A recording of the given input and output.
+    pub fn new(input: Vec<u8>, output: Vec<u8>) -> Self {
+        Recording { input, output }
+    }
+
+    /// Saves the recording as a replay file.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{}", HEADER)?;
+        for (name, bytes) in [("input", &self.input), ("output", &self.output)] {
+            writeln!(writer, "{} {}", name, bytes.len())?;
+            writer.write_all(bytes)?;
+            writeln!(writer)?;
+        }
+        writer.flush()
+    }
+
+    /// Loads a recording from a replay file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidData` if the file isn't a replay file.
+    pub fn read_from<R: Read>(reader: R) -> io::Result<Self> {
+        let mut reader = BufReader::new(reader);
+        if read_line(&mut reader)? != HEADER {
+            return Err(invalid("not a replay file"));
+        }
+        let input = read_section(&mut reader, "input")?;
+        let output = read_section(&mut reader, "output")?;
+        Ok(Recording { input, output })
+    }
+
+    /// The offset of the first byte where `output` is different from the recorded output, or
+    /// where one of them ends before the other, or `None` if they are the same.
+    pub fn first_difference(&self, output: &[u8]) -> Option<usize> {
+        let common = self
+            .output
+            .iter()
+            .zip(output)
+            .position(|(recorded, actual)| recorded != actual);
+        match common {
+            Some(offset) => Some(offset),
+            None if self.output.len() == output.len() => None,
+            None => Some(self.output.len().min(output.len())),
+        }
+    }
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.ends_with('\n') {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    line.pop();
+    Ok(line)
+}
+
+fn read_section<R: BufRead>(reader: &mut R, name: &str) -> io::Result<Vec<u8>> {
+    let line = read_line(reader)?;
+    let len = line
+        .strip_prefix(name)
+        .and_then(|rest| rest.strip_prefix(' '))
+        .and_then(|len| len.parse().ok())
+        .ok_or_else(|| invalid(&format!("expected the {} length", name)))?;
+
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    let mut newline = [0];
+    reader.read_exact(&mut newline)?;
+    if newline != *b"\n" {
+        return Err(invalid(&format!("expected a newline after the {}", name)));
+    }
+    Ok(bytes)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast;
+    use crate::test_helpers::*;
+    use crate::traits::*;
+
+    #[test]
+    fn records_and_replays() {
+        let program = ast::parse_program(FACTOR_SRC).unwrap();
+        let mut input = Record::new(&b"100\n200\n"[..]);
+        let mut output = Record::new(Vec::new());
+        program.interpret(None, &mut input, &mut output).unwrap();
+        // The program reads only the first line, so only that is recorded.
+        assert_eq!(input.recorded(), b"100\n");
+        assert_eq!(output.recorded(), b"100: 2 2 5 5\n");
+
+        let recording = Recording::new(input.into_bytes(), output.into_bytes());
+        let mut file = Vec::new();
+        recording.write_to(&mut file).unwrap();
+        assert!(file.starts_with(b"bf-replay 1\ninput 4\n100\n\noutput 13\n"));
+        assert_eq!(Recording::read_from(&file[..]).unwrap(), recording);
+
+        let replayed = program.interpret_memory(None, &recording.input).unwrap();
+        assert_eq!(recording.first_difference(&replayed), None);
+    }
+
+    #[test]
+    fn finds_differences() {
+        let recording = Recording::new(Vec::new(), b"abc".to_vec());
+        assert_eq!(recording.first_difference(b"abc"), None);
+        assert_eq!(recording.first_difference(b"abd"), Some(2));
+        assert_eq!(recording.first_difference(b"ab"), Some(2));
+        assert_eq!(recording.first_difference(b"abcd"), Some(3));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let error = |file: &[u8]| Recording::read_from(file).unwrap_err().kind();
+        assert_eq!(error(b"+[-]\n"), ErrorKind::InvalidData);
+        assert_eq!(error(b"bf-replay 1\ninput x\n"), ErrorKind::InvalidData);
+        assert_eq!(error(b"bf-replay 1\ninput 3\nab"), ErrorKind::UnexpectedEof);
+        assert_eq!(
+            error(b"bf-replay 1\ninput 1\nab\noutput 0\n\n"),
+            ErrorKind::InvalidData
+        );
+    }
+}
@@ -1,5 +1,12 @@
+use std::cmp;
+use std::iter;
+use std::mem;
+
 use super::*;
 use crate::ast;
+use crate::instrument;
+use crate::semantics::Overflow;
+use crate::traits::{Compile, CompileTo, IntoUsize, Stage, ToSource};
 
 /// Program forms that can be compiled to the RLE AST.
 pub trait RleCompilable {
@@ -16,6 +23,24 @@ pub trait RleCompilable {
 
 /// Compiles an unoptimized [`ast`](../ast/index.html) program to a run-length encoded program.
 pub fn compile(program: &ast::Program) -> Box<Program> {
+    instrument::pass("rle", program, compile_statements)
+}
+
+/// Compiles an unoptimized program to a run-length encoded program for cells that overflow as
+/// `overflow` says.
+///
+/// Only cells that wrap let a `+` and a `-` in a run cancel, since otherwise going past 255 or
+/// below 0 on the way matters, so for other cells each stretch of `+` or of `-` becomes its own
+/// command.
+pub fn compile_for(program: &ast::Program, overflow: Overflow) -> Box<Program> {
+    instrument::pass("rle", program, |program| {
+        let mut compiler = Compiler::for_overflow(overflow);
+        compiler.compile(program);
+        compiler.into_program()
+    })
+}
+
+fn compile_statements(program: &ast::Program) -> Box<Program> {
     let mut compiler = Compiler::new();
     compiler.compile(program);
     compiler.into_program()
@@ -24,17 +49,96 @@ pub fn compile(program: &ast::Program) -> Box<Program> {
 /// Represents the state of an RLE compiler from `ast::Instruction` to `Instruction`.
 pub struct Compiler {
     instructions: Vec<Statement>,
-    last_command: Command,
-    last_repeat: Count,
+    run: Run,
+    /// Whether cells wrap, so that a `+` and a `-` cancel.
+    wrapping: bool,
+}
+
+/// The run of commands that the compiler has seen but not yet issued.
+enum Run {
+    /// No commands.
+    Empty,
+    /// `+` and `-` commands, with their net change.
+    Add(isize),
+    /// `<` and `>` commands.
+    Move(Moves),
+    /// Repetitions of any other command.
+    Repeat(Command, Count),
+}
+
+/// A run of `<` and `>` commands.
+///
+/// Cancelling opposite moves must not hide a move past either end of memory, so the run
+/// remembers how far it reached in each direction. `turns` holds the offsets where it first went
+/// further left or right than before, keeping only the furthest of each stretch in one
+/// direction. Moving through the turns in order and then to the final `offset` errors exactly
+/// when, and the same way as, the original commands would.
+struct Moves {
+    offset: isize,
+    low: isize,
+    high: isize,
+    turns: Vec<isize>,
+}
+
+impl Moves {
+    fn new() -> Self {
+        Moves {
+            offset: 0,
+            low: 0,
+            high: 0,
+            turns: Vec::new(),
+        }
+    }
+
+    fn step(&mut self, delta: isize) {
+        self.offset += delta;
+
+        let record = if self.offset < self.low {
+            self.low = self.offset;
+            true
+        } else if self.offset > self.high {
+            self.high = self.offset;
+            true
+        } else {
+            false
+        };
+
+        if record {
+            match self.turns.last_mut() {
+                Some(turn) if turn.signum() == self.offset.signum() => *turn = self.offset,
+                _ => self.turns.push(self.offset),
+            }
+        }
+    }
+
+    /// The moves to issue, as signed distances.
+    fn distances(&self) -> Vec<isize> {
+        let mut result = Vec::new();
+        let mut position = 0;
+
+        for &target in self.turns.iter().chain(Some(&self.offset)) {
+            if target != position {
+                result.push(target - position);
+                position = target;
+            }
+        }
+
+        result
+    }
 }
 
 impl Compiler {
     /// Creates a new RLE compiler.
     pub fn new() -> Self {
+        Self::for_overflow(Overflow::Wrap)
+    }
+
+    /// Creates a new RLE compiler for cells that overflow as `overflow` says.
+    pub fn for_overflow(overflow: Overflow) -> Self {
         Compiler {
             instructions: Vec::new(),
-            last_command: Command::Right,
-            last_repeat: 0,
+            run: Run::Empty,
+            wrapping: overflow == Overflow::Wrap,
         }
     }
 
@@ -43,7 +147,14 @@ impl Compiler {
         for instruction in program {
             match *instruction {
                 ast::Statement::Cmd(op_code) => self.issue_op(op_code),
-                ast::Statement::Loop(ref body) => self.issue_loop(compile(body)),
+                ast::Statement::Loop(ref body) => {
+                    let mut compiler = Compiler {
+                        wrapping: self.wrapping,
+                        ..Compiler::new()
+                    };
+                    compiler.compile(body);
+                    self.issue_loop(compiler.into_program())
+                }
             }
         }
     }
@@ -55,30 +166,72 @@ impl Compiler {
     }
 
     fn push_op(&mut self) {
-        if self.last_repeat > 0 {
+        match mem::replace(&mut self.run, Run::Empty) {
+            Run::Empty => (),
+            // Adding wraps around, so only the net change modulo 256 matters.
+            Run::Add(net) if self.wrapping && net % 256 > 0 => {
+                self.push_repeated(Command::Up, net % 256)
+            }
+            Run::Add(net) if self.wrapping && net % 256 < 0 => {
+                self.push_repeated(Command::Down, -(net % 256))
+            }
+            Run::Add(_) if self.wrapping => (),
+            Run::Add(net) if net > 0 => self.push_repeated(Command::Up, net),
+            Run::Add(net) if net < 0 => self.push_repeated(Command::Down, -net),
+            Run::Add(_) => (),
+            Run::Move(moves) => {
+                for distance in moves.distances() {
+                    if distance > 0 {
+                        self.push_repeated(Command::Right, distance);
+                    } else {
+                        self.push_repeated(Command::Left, -distance);
+                    }
+                }
+            }
+            Run::Repeat(command, repeat) => {
+                self.instructions.push(Statement::Cmd(command, repeat));
+            }
+        }
+    }
+
+    /// Pushes `count` repetitions of the command, split up if they don't fit in one `Count`.
+    fn push_repeated(&mut self, command: Command, count: isize) {
+        let mut count = count as usize;
+
+        while count > 0 {
+            let repeat = cmp::min(count, Count::MAX.into_usize());
             self.instructions
-                .push(Statement::Cmd(self.last_command, self.last_repeat));
-            self.last_command = Command::Right;
-            self.last_repeat = 0;
+                .push(Statement::Cmd(command, repeat as Count));
+            count -= repeat;
         }
     }
 
-    fn issue_op(&mut self, cmd: Command) {
-        if cmd == self.last_command {
-            if let Some(last_repeat) = self.last_repeat.checked_add(1) {
-                self.last_repeat = last_repeat;
-            } else {
+    pub(super) fn issue_op(&mut self, cmd: Command) {
+        match (&mut self.run, cmd) {
+            (Run::Add(net), Command::Up) if self.wrapping || *net > 0 => *net += 1,
+            (Run::Add(net), Command::Down) if self.wrapping || *net < 0 => *net -= 1,
+            (Run::Move(moves), Command::Right) => moves.step(1),
+            (Run::Move(moves), Command::Left) => moves.step(-1),
+            (Run::Repeat(command, repeat), _) if *command == cmd && *repeat < Count::MAX => {
+                *repeat += 1
+            }
+            _ => {
                 self.push_op();
-                self.last_repeat = 1;
+                self.run = match cmd {
+                    Command::Up => Run::Add(1),
+                    Command::Down => Run::Add(-1),
+                    Command::Right | Command::Left => {
+                        let mut moves = Moves::new();
+                        moves.step(if cmd == Command::Right { 1 } else { -1 });
+                        Run::Move(moves)
+                    }
+                    _ => Run::Repeat(cmd, 1),
+                };
             }
-        } else {
-            self.push_op();
-            self.last_command = cmd;
-            self.last_repeat = 1;
         }
     }
 
-    fn issue_loop(&mut self, body: Box<Program>) {
+    pub(super) fn issue_loop(&mut self, body: Box<Program>) {
         self.push_op();
         self.instructions.push(Statement::Loop(body));
     }
@@ -93,6 +246,35 @@ impl RleCompilable for ast::Program {
     }
 }
 
+impl Stage for Program {
+    type Compiled = Box<Program>;
+}
+
+impl Compile for Program {}
+
+impl<T: RleCompilable + ?Sized> CompileTo<Program> for T {
+    fn compile_to(&self) -> Box<Program> {
+        self.rle_compile()
+    }
+}
+
+impl ToSource for Program {
+    fn write_source(&self, out: &mut String) {
+        for statement in self {
+            match *statement {
+                Statement::Cmd(command, count) => {
+                    out.extend(iter::repeat_n(command.symbol() as char, count.into_usize()))
+                }
+                Statement::Loop(ref body) => {
+                    out.push('[');
+                    body.write_source(out);
+                    out.push(']');
+                }
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::Command::*;
@@ -100,6 +282,22 @@ mod tests {
     use super::*;
     use ast::Statement as Src;
 
+    #[test]
+    fn source_repeats_commands() {
+        let program = compile(&ast::parse_program(b"+++[->>+-+<<]").unwrap());
+        assert_eq!(program.to_source(), "+++[->>+<<]");
+    }
+
+    #[test]
+    fn changes_cancel_only_when_cells_wrap() {
+        let program = ast::parse_program(b"++-[->>+-+<<]><").unwrap();
+        for overflow in [Overflow::Saturate, Overflow::Trap] {
+            let compiled = compile_for(&program, overflow);
+            assert_eq!(compiled.to_source(), "++-[->>+-+<<]><");
+        }
+        assert_eq!(compile_for(&program, Overflow::Wrap), compile(&program));
+    }
+
     #[test]
     fn right_compiles() {
         assert_compile(&[Src::Cmd(Right)], &[Obj::Cmd(Right, 1)]);
@@ -137,6 +335,65 @@ mod tests {
         );
     }
 
+    #[test]
+    fn opposite_adds_cancel() {
+        assert_compile(&parse(b"++-+--"), &[]);
+        assert_compile(&parse(b"+-+++-"), &[Obj::Cmd(Up, 2)]);
+        assert_compile(
+            &parse(b"-+--.+"),
+            &[Obj::Cmd(Down, 2), Obj::Cmd(Out, 1), Obj::Cmd(Up, 1)],
+        );
+    }
+
+    #[test]
+    fn adds_wrap_around() {
+        assert_compile(&parse(&[b'+'; 258]), &[Obj::Cmd(Up, 2)]);
+        assert_compile(&parse(&[b'-'; 256]), &[]);
+    }
+
+    #[test]
+    fn opposite_moves_cancel() {
+        assert_compile(&parse(b">><>"), &[Obj::Cmd(Right, 2)]);
+        assert_compile(&parse(b">><><>>"), &[Obj::Cmd(Right, 3)]);
+        assert_compile(&parse(b"><><><"), &[Obj::Cmd(Right, 1), Obj::Cmd(Left, 1)]);
+    }
+
+    #[test]
+    fn moves_keep_their_reach() {
+        assert_compile(&parse(b">><"), &[Obj::Cmd(Right, 2), Obj::Cmd(Left, 1)]);
+        assert_compile(
+            &parse(b"><<><>>"),
+            &[Obj::Cmd(Right, 1), Obj::Cmd(Left, 2), Obj::Cmd(Right, 2)],
+        );
+        assert_compile(
+            &parse(b">>>><<<<<<<"),
+            &[Obj::Cmd(Right, 4), Obj::Cmd(Left, 7)],
+        );
+    }
+
+    #[test]
+    fn cancelling_keeps_errors() {
+        use crate::common::Error;
+        use crate::state::State;
+        use crate::traits::Interpretable;
+
+        for (src, result) in [
+            (&b"<>"[..], Err(Error::PointerUnderflow)),
+            (b">>>>>>><<<<<<<", Err(Error::PointerOverflow)),
+            (b">><<<", Err(Error::PointerUnderflow)),
+            (b">><<", Ok(())),
+        ] {
+            let program = compile(&parse(src));
+            let mut state = State::with_capacity(4);
+            let actual = program.interpret_in(&mut state, &b""[..], Vec::new());
+            assert_eq!(actual, result, "{}", String::from_utf8_lossy(src));
+        }
+    }
+
+    fn parse(src: &[u8]) -> Box<ast::Program> {
+        ast::parse_program(src).unwrap()
+    }
+
     fn assert_compile(src: &[ast::Statement], expected: &[Statement]) {
         let actual = compile(src);
         assert_eq!(&*actual, expected);
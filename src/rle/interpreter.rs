@@ -2,56 +2,104 @@ use std::io::{Read, Write};
 
 use super::*;
 use crate::common::BfResult;
+use crate::counters::{self, CountingInterpretable, Counters, Hooks, Kind};
+use crate::sandbox::{self, SandboxConfig, SandboxInterpretable};
 use crate::state::State;
 use crate::traits::Interpretable;
 
 impl Interpretable for Program {
-    fn interpret_state<R: Read, W: Write>(
+    fn interpret_in<R: Read, W: Write>(
         &self,
-        mut state: State,
+        state: &mut State,
         mut input: R,
         mut output: W,
     ) -> BfResult<()> {
-        interpret(self, &mut state, &mut input, &mut output)
+        interpret(self, state, &mut input, &mut output, &mut ())
     }
 }
 
-fn interpret<R, W>(
+impl CountingInterpretable for Program {
+    fn interpret_counting<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        input: R,
+        output: W,
+        counters: &mut Counters,
+    ) -> BfResult<()> {
+        counters::count_io(input, output, counters, |input, output, counters| {
+            interpret(self, state, input, output, counters)
+        })
+    }
+}
+
+impl SandboxInterpretable for Program {
+    fn interpret_sandboxed<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        input: R,
+        output: W,
+        config: &SandboxConfig,
+    ) -> BfResult<()> {
+        sandbox::run(
+            state,
+            input,
+            output,
+            config,
+            |state, input, output, hooks| interpret(self, state, input, output, hooks),
+        )
+    }
+}
+
+fn interpret<R, W, H>(
     instructions: &Program,
     state: &mut State,
     input: &mut R,
     output: &mut W,
+    hooks: &mut H,
 ) -> BfResult<()>
 where
     R: Read,
     W: Write,
+    H: Hooks,
 {
     for instruction in instructions {
-        interpret_instruction(instruction, state, input, output)?;
+        interpret_instruction(instruction, state, input, output, hooks)?;
     }
 
     Ok(())
 }
 
 #[inline]
-fn interpret_instruction<R, W>(
+fn interpret_instruction<R, W, H>(
     instruction: &Statement,
     state: &mut State,
     input: &mut R,
     output: &mut W,
+    hooks: &mut H,
 ) -> BfResult<()>
 where
     R: Read,
     W: Write,
+    H: Hooks,
 {
     use self::Statement::*;
     use crate::common::Command::*;
 
+    match *instruction {
+        Cmd(Left, _) => hooks.instruction(Kind::Left),
+        Cmd(Right, _) => hooks.instruction(Kind::Right),
+        Cmd(Up, _) | Cmd(Down, _) => hooks.instruction(Kind::Add),
+        Cmd(In, _) => hooks.instruction(Kind::In),
+        Cmd(Out, _) => hooks.instruction(Kind::Out),
+        Cmd(SwitchTape, _) => hooks.instruction(Kind::SwitchTape),
+        Cmd(Begin, _) | Cmd(End, _) | Loop(_) => (),
+    }
+
     match *instruction {
         Cmd(Left, count) => state.left(count)?,
         Cmd(Right, count) => state.right(count)?,
-        Cmd(Up, count) => state.up(count as u8),
-        Cmd(Down, count) => state.down(count as u8),
+        Cmd(Up, count) => state.up(count)?,
+        Cmd(Down, count) => state.down(count)?,
         Cmd(In, count) => {
             for _ in 0..count {
                 state.read(input);
@@ -62,14 +110,17 @@ where
                 state.write(output);
             }
         }
+        Cmd(SwitchTape, count) => state.switch_tape(count),
         Cmd(Begin, _) | Cmd(End, _) => panic!("Invalid opcode"),
         Loop(ref program) => {
             while state.load() != 0 {
-                interpret(program, state, input, output)?;
+                hooks.loop_iteration()?;
+                interpret(program, state, input, output, hooks)?;
             }
         }
     }
 
+    hooks.pointer(state.pointer());
     Ok(())
 }
 
@@ -89,6 +140,12 @@ mod tests {
         assert_parse_interpret(FACTOR_SRC, "100\n", "100: 2 2 5 5\n");
     }
 
+    #[test]
+    fn multi_tape() {
+        let program = crate::rle::compile(&parse_multi_tape(MULTI_TAPE_SRC));
+        assert_interpret_tapes(&*program, 2, b"", b"ABB");
+    }
+
     fn assert_parse_interpret(program: &[u8], input: &str, output: &str) {
         let program = crate::ast::parse_program(program).unwrap();
         let program = crate::rle::compile(&program);
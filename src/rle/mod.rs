@@ -5,12 +5,22 @@
 //! flag.
 //!
 //! This module takes an [unoptimized Brainfuck AST](../ast/index.html) and replaces repeated runs
-//! of the same command with a run-length encoded instruction.
+//! of the same command with a run-length encoded instruction. Opposite commands in a run cancel:
+//! `++-` becomes a single `+`, and `><><><` becomes `><`. A run of moves still goes as far in
+//! each direction as before, in the same order, so that it fails at the ends of memory just as
+//! the original commands would. For cells that don't [wrap](../semantics/enum.Overflow.html),
+//! [`compile_for`](fn.compile_for.html) leaves `+` and `-` uncancelled.
+//!
+//! [`parse_program`](fn.parse_program.html) parses source straight to run-length encoded form,
+//! skipping the AST, and [`parse_program_from`](fn.parse_program_from.html) does the same from a
+//! reader, without holding the source in memory either, for programs too big to parse whole.
 
 mod compiler;
 mod interpreter;
+mod parser;
 
-pub use self::compiler::{compile, RleCompilable};
+pub use self::compiler::{compile, compile_for, RleCompilable};
+pub use self::parser::{parse_program, parse_program_from, parse_program_with};
 
 use crate::common::{Command, Count};
 
@@ -19,6 +29,7 @@ pub type Program = [Statement];
 
 /// A run-length encoded BF instruction.
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Statement {
     /// Repeats the given command the given number of times.
     ///
@@ -0,0 +1,208 @@
+use std::io::{self, ErrorKind, Read};
+
+use super::compiler::Compiler;
+use super::*;
+use crate::ast::ParseOptions;
+use crate::common::{BfResult, Error};
+
+/// How many bytes to read at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Parses Brainfuck concrete syntax straight to a run-length encoded program.
+///
+/// This gives the same result as parsing with [`ast::parse_program`](../ast/fn.parse_program.html)
+/// and then [compiling](fn.compile.html), but without building the AST, which for a large
+/// program is most of the work.
+///
+/// # Errors
+///
+/// The same as [`ast::parse_program`](../ast/fn.parse_program.html).
+pub fn parse_program(input: &[u8]) -> BfResult<Box<Program>> {
+    parse_program_with(input, &ParseOptions::default())
+}
+
+/// Parses Brainfuck concrete syntax, with the given extensions, straight to a run-length
+/// encoded program.
+///
+/// # Errors
+///
+/// The same as [`ast::parse_program_with`](../ast/fn.parse_program_with.html).
+pub fn parse_program_with(input: &[u8], options: &ParseOptions) -> BfResult<Box<Program>> {
+    let mut parser = Parser::new(options);
+    parser.feed(input)?;
+    parser.finish()
+}
+
+/// Parses Brainfuck concrete syntax from a reader straight to a run-length encoded program.
+///
+/// The source is read a chunk at a time and encoded as it is parsed, so neither the whole
+/// source nor an unoptimized AST is ever held in memory. The result is the same as parsing
+/// with [`ast::parse_program_with`](../ast/fn.parse_program_with.html) and then
+/// [compiling](fn.compile.html).
+///
+/// # Errors
+///
+/// Returns `Err` if reading fails, or else `Ok` of the result of parsing, which has the same
+/// errors as [`ast::parse_program_with`](../ast/fn.parse_program_with.html).
+pub fn parse_program_from<R: Read>(
+    mut reader: R,
+    options: &ParseOptions,
+) -> io::Result<BfResult<Box<Program>>> {
+    let mut parser = Parser::new(options);
+    let mut buffer = vec![0; CHUNK_SIZE];
+
+    loop {
+        let len = match reader.read(&mut buffer) {
+            Ok(0) => return Ok(parser.finish()),
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if let Err(e) = parser.feed(&buffer[..len]) {
+            return Ok(Err(e));
+        }
+    }
+}
+
+/// Parses source that arrives in pieces, encoding each loop body as it goes.
+struct Parser<'a> {
+    options: &'a ParseOptions,
+    /// The compilers for the enclosing loop bodies, outermost first.
+    open: Vec<Compiler>,
+    current: Compiler,
+}
+
+impl<'a> Parser<'a> {
+    fn new(options: &'a ParseOptions) -> Self {
+        Parser {
+            options,
+            open: Vec::new(),
+            current: Compiler::new(),
+        }
+    }
+
+    fn feed(&mut self, input: &[u8]) -> BfResult<()> {
+        use crate::common::Command::*;
+
+        for &byte in input {
+            let command = match byte {
+                b'<' => Left,
+                b'>' => Right,
+                b'+' => Up,
+                b'-' => Down,
+                b',' => In,
+                b'.' => Out,
+                b'@' if self.options.multi_tape => SwitchTape,
+                b'[' => {
+                    if self
+                        .options
+                        .max_depth
+                        .is_some_and(|max| self.open.len() >= max)
+                    {
+                        return Err(Error::NestingTooDeep);
+                    }
+                    let outer = std::mem::replace(&mut self.current, Compiler::new());
+                    self.open.push(outer);
+                    continue;
+                }
+                b']' => {
+                    let outer = self.open.pop().ok_or(Error::UnmatchedEnd)?;
+                    let body = std::mem::replace(&mut self.current, outer).into_program();
+                    self.current.issue_loop(body);
+                    continue;
+                }
+                _ => continue,
+            };
+            self.current.issue_op(command);
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> BfResult<Box<Program>> {
+        if self.open.is_empty() {
+            Ok(self.current.into_program())
+        } else {
+            Err(Error::UnmatchedBegin)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast;
+    use crate::test_helpers::*;
+    use crate::traits::*;
+    use quickcheck::quickcheck;
+
+    /// Reads one byte at a time, so that every byte starts a new chunk.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.split_first() {
+                Some((&byte, rest)) if !buf.is_empty() => {
+                    buf[0] = byte;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    fn parse(src: &[u8]) -> BfResult<Box<Program>> {
+        parse_program_from(Trickle(src), &ParseOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn matches_parsing_then_encoding() {
+        let expected = compile(&ast::parse_program(FACTOR_SRC).unwrap());
+        assert_eq!(parse(FACTOR_SRC), Ok(expected.clone()));
+        let options = ParseOptions::default();
+        assert_eq!(
+            parse_program_from(FACTOR_SRC, &options).unwrap(),
+            Ok(expected)
+        );
+    }
+
+    #[test]
+    fn parses_whole_sources() {
+        assert_eq!(
+            parse_program(HELLO_WORLD_SRC),
+            Ok(compile(&ast::parse_program(HELLO_WORLD_SRC).unwrap()))
+        );
+        assert_eq!(parse_program(b"[+]]"), Err(Error::UnmatchedEnd));
+
+        let options = ParseOptions {
+            multi_tape: true,
+            ..ParseOptions::default()
+        };
+        let expected = compile(&ast::parse_program_with(b"@@+", &options).unwrap());
+        assert_eq!(parse_program_with(b"@@+", &options), Ok(expected));
+    }
+
+    #[test]
+    fn reports_syntax_errors() {
+        assert_eq!(parse(b"+[[-]"), Err(Error::UnmatchedBegin));
+        assert_eq!(parse(b"+[-]]["), Err(Error::UnmatchedEnd));
+
+        let options = ParseOptions {
+            max_depth: Some(1),
+            ..ParseOptions::default()
+        };
+        let result = parse_program_from(&b"[][[]]"[..], &options).unwrap();
+        assert_eq!(result, Err(Error::NestingTooDeep));
+    }
+
+    #[test]
+    fn matches_on_random_programs() {
+        fn prop(program: RandomProgram) -> bool {
+            let src = program.0.to_source();
+            parse(src.as_bytes()) == Ok(program.0.rle_compile())
+        }
+
+        quickcheck(prop as fn(RandomProgram) -> bool);
+    }
+}
@@ -10,6 +10,12 @@
 //! [the `dynlib-rs` tutorial]:(https://censoredusername.github.io/dynasm-rs/language/tutorial.html#advanced-usage)
 
 use std::io::{Read, Write};
+use std::{ptr, slice};
+
+use crate::common::Error;
+use crate::counters::Counters;
+use crate::sandbox::Sandbox;
+use crate::semantics::Eof;
 
 /// The object code terminated successfully.
 pub const OKAY: u64 = 0;
@@ -20,38 +26,215 @@ pub const UNDERFLOW: u64 = 1;
 /// The pointer would have pointed above the allocated buffer had the program continued.
 pub const OVERFLOW: u64 = 2;
 
+/// The program reached a [sandbox](../sandbox/index.html) limit, which the `RtsState` records.
+pub const LIMIT: u64 = 3;
+
+/// A cell would have gone past 255 or below 0 in code compiled for cells that trap, which stores
+/// the pointer.
+pub const CELL_OVERFLOW: u64 = 4;
+
 /// Minimal state for our minimal run-time system.
 ///
-/// Trait objects providing channels for standard input and output.
+/// Trait objects providing channels for standard input and output, the
+/// [counters](../counters/index.html) that counting JIT code updates, and the limits that
+/// sandboxed JIT code checks.
+///
+/// Nothing here is fixed when a program compiles: the generated code gets a pointer to an
+/// `RtsState` as an argument, and each run makes its own from the reader and writer that it was
+/// given. So one compiled program can run on any number of threads at once, each with its own
+/// streams, counters, and sandbox.
 pub struct RtsState<'a> {
     /// Input channel for the `,` operation.
     input: &'a mut dyn Read,
     /// Output channel for the `.` operation.
     output: &'a mut dyn Write,
+    /// Updated in place by generated code that was compiled for counting.
+    counters: Counters,
+    /// What the `,` operation stores at the end of input.
+    eof: Eof,
+    /// Where the pointer is, as an offset from the start of memory, which generated code loads
+    /// when it starts and stores when it finishes successfully or stops at a sandbox limit.
+    pointer: u64,
+    /// The limits that sandboxed generated code checks at each step.
+    sandbox: Option<&'a mut Sandbox>,
+    /// The limit that stopped the program, if one did.
+    limit: Option<Error>,
+    /// The cycles and starts of each loop, which generated code that was compiled for profiling
+    /// updates in place.
+    #[cfg(feature = "jit")]
+    profile: *mut u64,
 }
 
 impl<'a> RtsState<'a> {
     pub fn new<R: Read, W: Write>(input: &'a mut R, output: &'a mut W) -> Self {
-        RtsState { input, output }
+        RtsState {
+            input,
+            output,
+            counters: Counters::new(),
+            eof: Eof::default(),
+            pointer: 0,
+            sandbox: None,
+            limit: None,
+            #[cfg(feature = "jit")]
+            profile: ptr::null_mut(),
+        }
+    }
+
+    /// Sets what the `,` operation stores at the end of input.
+    pub fn set_eof(&mut self, eof: Eof) {
+        self.eof = eof;
+    }
+
+    /// Where the pointer ended up after a successful run, or one that stopped at a sandbox limit.
+    pub fn pointer(&self) -> usize {
+        self.pointer as usize
+    }
+
+    /// Sets where the pointer starts, which is 0 unless this is called.
+    pub fn set_pointer(&mut self, pointer: usize) {
+        self.pointer = pointer as u64;
+    }
+
+    /// Enforces the limits of `sandbox` on generated code that was compiled for a sandbox.
+    #[cfg(feature = "jit")]
+    pub(crate) fn set_sandbox(&mut self, sandbox: &'a mut Sandbox) {
+        self.sandbox = Some(sandbox);
+    }
+
+    /// Gives generated code that was compiled for profiling somewhere to keep the cycles and
+    /// starts of each loop.
+    #[cfg(all(feature = "jit", feature = "tools"))]
+    pub(crate) fn set_profile(&mut self, loops: &'a mut [u64]) {
+        self.profile = loops.as_mut_ptr();
+    }
+
+    /// The limit that stopped the program, after it returned `LIMIT`.
+    #[cfg(feature = "jit")]
+    pub(crate) fn limit(&self) -> Option<Error> {
+        self.limit
+    }
+
+    /// The counters, as updated by the generated code.
+    pub fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    /// The offset from the start of an `RtsState` of the count for the given kind.
+    #[cfg(feature = "jit")]
+    pub(crate) fn count_offset(kind: crate::counters::Kind) -> i32 {
+        (std::mem::offset_of!(RtsState, counters)
+            + std::mem::offset_of!(Counters, instructions)
+            + kind as usize * std::mem::size_of::<u64>()) as i32
+    }
+
+    /// The offset from the start of an `RtsState` of the loop iteration count.
+    #[cfg(feature = "jit")]
+    pub(crate) fn loop_iterations_offset() -> i32 {
+        (std::mem::offset_of!(RtsState, counters)
+            + std::mem::offset_of!(Counters, loop_iterations)) as i32
+    }
+
+    /// The offset from the start of an `RtsState` of the peak pointer position.
+    #[cfg(feature = "jit")]
+    pub(crate) fn peak_pointer_offset() -> i32 {
+        (std::mem::offset_of!(RtsState, counters) + std::mem::offset_of!(Counters, peak_pointer))
+            as i32
     }
 
-    pub extern "win64" fn read(&mut self) -> u8 {
-        let mut buf = [0];
-        let _ = self.input.read_exact(&mut buf);
-        buf[0]
+    /// The offset from the start of an `RtsState` of the peak cell address.
+    #[cfg(feature = "jit")]
+    pub(crate) fn peak_cell_offset() -> i32 {
+        (std::mem::offset_of!(RtsState, counters) + std::mem::offset_of!(Counters, peak_cell))
+            as i32
+    }
+
+    /// The offset from the start of an `RtsState` of the pointer to the loop profile.
+    #[cfg(feature = "jit")]
+    pub(crate) fn profile_offset() -> i32 {
+        std::mem::offset_of!(RtsState, profile) as i32
+    }
+
+    /// The offset from the start of an `RtsState` of the starting and final pointer position.
+    #[cfg(any(feature = "jit", feature = "cranelift"))]
+    pub(crate) fn pointer_offset() -> i32 {
+        std::mem::offset_of!(RtsState, pointer) as i32
+    }
+
+    /// Reads a byte, given the byte at the pointer in case the input has ended.
+    pub extern "win64" fn read(&mut self, current: u8) -> u8 {
+        self.eof.read(self.input, current)
     }
 
     pub extern "win64" fn write(&mut self, byte: u8) {
         let _ = self.output.write_all(&[byte]);
     }
 
-    pub extern "C" fn read_c(&mut self) -> u8 {
-        let mut buf = [0];
-        let _ = self.input.read_exact(&mut buf);
-        buf[0]
+    /// Writes the `len` bytes at `bytes`, for `WriteStr`.
+    ///
+    /// # Safety
+    ///
+    /// `bytes` must point to `len` readable bytes.
+    pub unsafe extern "win64" fn write_str(&mut self, bytes: *const u8, len: usize) {
+        let _ = self
+            .output
+            .write_all(unsafe { slice::from_raw_parts(bytes, len) });
+    }
+
+    /// Counts a step against the sandbox, if there is one. Returns non-zero if the program must
+    /// stop, which sandboxed generated code does by returning `LIMIT`.
+    pub extern "win64" fn step(&mut self) -> u64 {
+        match self.sandbox.as_deref_mut().map(Sandbox::step) {
+            Some(Err(error)) => {
+                self.limit = Some(error);
+                1
+            }
+            _ => 0,
+        }
+    }
+
+    /// Reads a byte, given the byte at the pointer in case the input has ended.
+    pub extern "C" fn read_c(&mut self, current: u8) -> u8 {
+        self.eof.read(self.input, current)
     }
 
     pub extern "C" fn write_c(&mut self, byte: u8) {
         let _ = self.output.write_all(&[byte]);
     }
+
+    /// Records where the pointer ended up.
+    pub extern "C" fn set_pointer_c(&mut self, pointer: u64) {
+        self.pointer = pointer;
+    }
+}
+
+/// Scans memory from `pointer` up to (but not including) `limit` for a zero byte.
+///
+/// Returns the address of the zero, or null if there isn’t one. Generated code calls this to
+/// implement `FindZeroRight(1)`.
+///
+/// # Safety
+///
+/// `pointer` and `limit` must bound a single allocated region, with `pointer <= limit`.
+pub unsafe extern "win64" fn find_zero_right(pointer: *const u8, limit: *const u8) -> *const u8 {
+    let haystack = unsafe { slice::from_raw_parts(pointer, limit as usize - pointer as usize) };
+    match memchr::memchr(0, haystack) {
+        Some(offset) => pointer.wrapping_add(offset),
+        None => ptr::null(),
+    }
+}
+
+/// Scans memory from `pointer` down to `start` (inclusive) for a zero byte.
+///
+/// Returns the address of the zero, or null if there isn’t one. Generated code calls this to
+/// implement `FindZeroLeft(1)`.
+///
+/// # Safety
+///
+/// `start` and `pointer` must point into a single allocated region, with `start <= pointer`.
+pub unsafe extern "win64" fn find_zero_left(start: *const u8, pointer: *const u8) -> *const u8 {
+    let haystack = unsafe { slice::from_raw_parts(start, pointer as usize - start as usize + 1) };
+    match memchr::memrchr(0, haystack) {
+        Some(offset) => start.wrapping_add(offset),
+        None => ptr::null(),
+    }
 }
@@ -0,0 +1,481 @@
+//! Running untrusted programs within limits.
+//!
+//! A Brainfuck program from someone else can loop forever, write output until the host runs out
+//! of space, or move the pointer until a growing tape takes all the memory there is. A
+//! [`SandboxConfig`](struct.SandboxConfig.html) bounds each of these, and
+//! [`SandboxInterpretable::interpret_sandboxed`](trait.SandboxInterpretable.html) runs a
+//! program within the bounds, stopping it with an [error](../common/enum.Error.html) of its own
+//! kind for each limit that it reaches.
+//!
+//! A step is the start of a loop body, which every backend counts the same way, so that a
+//! program stops after the same number of steps whichever backend runs it; code outside loops
+//! runs only once, so the steps bound the running time. Output past the limit is never written,
+//! and the program stops at its next step. Memory is counted in cells over all the tapes, and the
+//! time is checked only every so many steps.
+//!
+//! An [`Interrupt`](struct.Interrupt.html) stops a program from outside: another thread or a
+//! signal handler raises it, and the program stops with `Interrupted` at its next step, leaving
+//! the state as it was there, so that the caller can look at the pointer and the tape.
+//!
+//! The AST, RLE, peephole, and bytecode interpreters run sandboxed without being compiled
+//! differently. The JIT compiles sandboxed programs with a call to its
+//! [run-time system](../rts/index.html) at each step, by
+//! [`compile_sandboxed`](../jit/fn.compile_sandboxed.html).
+//!
+//! ```
+//! use bf::ast;
+//! use bf::common::Error;
+//! use bf::sandbox::SandboxConfig;
+//! use bf::state::State;
+//! use bf::traits::*;
+//!
+//! let program = ast::parse_program(b"+[.]").unwrap();
+//! let config = SandboxConfig {
+//!     max_output_bytes: Some(1000),
+//!     ..SandboxConfig::default()
+//! };
+//! let mut output = Vec::new();
+//! let result = program.interpret_sandboxed(&mut State::new(), &b""[..], &mut output, &config);
+//! assert_eq!(result, Err(Error::OutputLimit));
+//! assert_eq!(output.len(), 1000);
+//! ```
+
+use std::cell::Cell;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use std::{fmt, ptr};
+
+use crate::common::{BfResult, Error};
+use crate::counters::{Hooks, Kind};
+use crate::state::State;
+use crate::traits::Interpretable;
+
+/// How many steps run between checks of the time.
+const TIME_CHECK_INTERVAL: u64 = 1024;
+
+/// The limits for a sandboxed run. `None` means no limit.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SandboxConfig {
+    /// The most loop iterations that the program may start.
+    pub max_steps: Option<u64>,
+    /// The most bytes that the program may write.
+    pub max_output_bytes: Option<u64>,
+    /// The most cells that the tapes may take, counting every tape. A
+    /// [growing](../state/struct.State.html#method.growing) state grows only up to this.
+    pub max_memory: Option<usize>,
+    /// The longest that the program may run.
+    pub wall_clock_limit: Option<Duration>,
+    /// A flag that stops the program at its next step once it is raised.
+    pub interrupt: Option<Interrupt>,
+}
+
+/// A flag that stops a running program at its next step once it is raised, from another thread
+/// or a signal handler. Copies of an interrupt all raise and check the same flag.
+///
+/// ```
+/// use std::sync::atomic::AtomicBool;
+/// use bf::ast;
+/// use bf::common::Error;
+/// use bf::sandbox::{Interrupt, SandboxConfig};
+/// use bf::state::State;
+/// use bf::traits::*;
+///
+/// static STOP: AtomicBool = AtomicBool::new(false);
+///
+/// let interrupt = Interrupt::new(&STOP);
+/// let config = SandboxConfig {
+///     interrupt: Some(interrupt),
+///     ..SandboxConfig::default()
+/// };
+/// interrupt.raise();
+/// let program = ast::parse_program(b"+[]").unwrap();
+/// let result = program.interpret_sandboxed(&mut State::new(), &b""[..], Vec::new(), &config);
+/// assert_eq!(result, Err(Error::Interrupted));
+/// ```
+#[derive(Clone, Copy)]
+pub struct Interrupt(&'static AtomicBool);
+
+impl Interrupt {
+    /// An interrupt that raises and checks `flag`.
+    pub const fn new(flag: &'static AtomicBool) -> Self {
+        Interrupt(flag)
+    }
+
+    /// Raises the flag, which is safe to do from a signal handler.
+    pub fn raise(self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Lowers the flag, so that the next run isn't stopped by an earlier interrupt.
+    pub fn clear(self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    /// Whether the flag is raised.
+    pub fn is_raised(self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Fails with `Interrupted` if the flag is raised.
+    #[inline]
+    pub(crate) fn check(self) -> BfResult<()> {
+        if self.is_raised() {
+            Err(Error::Interrupted)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl fmt::Debug for Interrupt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Interrupt").field(&self.is_raised()).finish()
+    }
+}
+
+/// Interrupts are equal if they raise the same flag.
+impl PartialEq for Interrupt {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self.0, other.0)
+    }
+}
+
+impl Eq for Interrupt {}
+
+/// Program forms that can be interpreted within the limits of a sandbox.
+pub trait SandboxInterpretable: Interpretable {
+    /// Interprets a program against the given state, stopping it when it reaches a limit.
+    ///
+    /// # Errors
+    ///
+    /// The errors of the program itself, `StepLimit`, `OutputLimit`, `MemoryLimit`, or `TimeLimit`
+    /// for the limit that stopped it, or `Interrupted` if the interrupt did. A state that already
+    /// has more memory than `max_memory` is a `MemoryLimit` error before the program starts.
+    fn interpret_sandboxed<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        input: R,
+        output: W,
+        config: &SandboxConfig,
+    ) -> BfResult<()>;
+}
+
+impl<P: SandboxInterpretable + ?Sized> SandboxInterpretable for Box<P> {
+    fn interpret_sandboxed<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        input: R,
+        output: W,
+        config: &SandboxConfig,
+    ) -> BfResult<()> {
+        (**self).interpret_sandboxed(state, input, output, config)
+    }
+}
+
+/// The hooks that enforce the limits on steps, output, and time as a program runs.
+pub(crate) struct Sandbox {
+    max_steps: Option<u64>,
+    steps: u64,
+    max_output_bytes: Option<u64>,
+    written: Rc<Cell<u64>>,
+    deadline: Option<Instant>,
+    interrupt: Option<Interrupt>,
+}
+
+impl Sandbox {
+    /// Counts a step, failing if that or the output so far is over a limit.
+    pub(crate) fn step(&mut self) -> BfResult<()> {
+        self.steps += 1;
+        if self.max_steps.is_some_and(|max| self.steps > max) {
+            return Err(Error::StepLimit);
+        }
+        if let Some(interrupt) = self.interrupt {
+            interrupt.check()?;
+        }
+        self.check_output()?;
+        if let Some(deadline) = self.deadline
+            && self.steps.is_multiple_of(TIME_CHECK_INTERVAL)
+            && Instant::now() >= deadline
+        {
+            return Err(Error::TimeLimit);
+        }
+        Ok(())
+    }
+
+    fn check_output(&self) -> BfResult<()> {
+        if self
+            .max_output_bytes
+            .is_some_and(|max| self.written.get() > max)
+        {
+            return Err(Error::OutputLimit);
+        }
+        Ok(())
+    }
+}
+
+impl Hooks for Sandbox {
+    #[inline(always)]
+    fn instruction(&mut self, _kind: Kind) {}
+
+    #[inline(always)]
+    fn pointer(&mut self, _pointer: usize) {}
+
+    #[inline(always)]
+    fn cell(&mut self, _address: usize) {}
+
+    #[inline]
+    fn loop_iteration(&mut self) -> BfResult<()> {
+        self.step()
+    }
+}
+
+/// Passes writes through up to the output limit, and counts every byte that the program tries
+/// to write, so that the sandbox can stop it.
+pub(crate) struct Limited<W> {
+    inner: W,
+    max: Option<u64>,
+    written: Rc<Cell<u64>>,
+}
+
+impl<W: Write> Write for Limited<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let before = self.written.get();
+        self.written.set(before + buf.len() as u64);
+        let allowed = self.max.map_or(buf.len(), |max| {
+            max.saturating_sub(before).min(buf.len() as u64) as usize
+        });
+        self.inner.write_all(&buf[..allowed])?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Runs a program within the limits of `config`, given a function that runs it with the
+/// sandbox as its hooks.
+pub(crate) fn run<R, W, F>(
+    state: &mut State,
+    mut input: R,
+    output: W,
+    config: &SandboxConfig,
+    run: F,
+) -> BfResult<()>
+where
+    R: Read,
+    W: Write,
+    F: FnOnce(&mut State, &mut R, &mut Limited<W>, &mut Sandbox) -> BfResult<()>,
+{
+    let tapes = state.tapes();
+    if config
+        .max_memory
+        .is_some_and(|max| state.capacity().saturating_mul(tapes) > max)
+    {
+        return Err(Error::MemoryLimit);
+    }
+    let limit = state.limit();
+    let capped = config
+        .max_memory
+        .map_or(limit, |max| limit.min(max / tapes));
+    state.set_limit(capped);
+
+    let written = Rc::new(Cell::new(0));
+    let mut output = Limited {
+        inner: output,
+        max: config.max_output_bytes,
+        written: Rc::clone(&written),
+    };
+    let mut sandbox = Sandbox {
+        max_steps: config.max_steps,
+        steps: 0,
+        max_output_bytes: config.max_output_bytes,
+        written,
+        deadline: config.wall_clock_limit.map(|limit| Instant::now() + limit),
+        interrupt: config.interrupt,
+    };
+
+    let result = run(state, &mut input, &mut output, &mut sandbox);
+    // The pointer ran off the end of a tape that could have grown if not for the sandbox.
+    let capped_growth = capped < limit && state.capacity() == capped;
+    state.set_limit(limit);
+
+    match result {
+        Err(Error::PointerOverflow) if capped_growth => Err(Error::MemoryLimit),
+        Err(error) => Err(error),
+        Ok(()) => sandbox.check_output(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast;
+    use crate::test_helpers::*;
+    use crate::traits::*;
+
+    fn run_all(src: &[u8], state: &State, config: &SandboxConfig) -> Vec<(BfResult<()>, usize)> {
+        let program = ast::parse_program(src).unwrap();
+        let mut results = Vec::new();
+        let mut run = |program: &dyn Fn(&mut State, &mut Vec<u8>) -> BfResult<()>| {
+            let mut output = Vec::new();
+            let result = program(&mut state.clone(), &mut output);
+            results.push((result, output.len()));
+        };
+        run(&|state, output| program.interpret_sandboxed(state, &b""[..], output, config));
+        let rle = program.rle_compile();
+        run(&|state, output| rle.interpret_sandboxed(state, &b""[..], output, config));
+        let peephole = program.peephole_compile();
+        run(&|state, output| peephole.interpret_sandboxed(state, &b""[..], output, config));
+        let bytecode = program.bytecode_compile();
+        run(&|state, output| bytecode.interpret_sandboxed(state, &b""[..], output, config));
+        #[cfg(feature = "jit")]
+        {
+            let jit = program.jit_compile_sandboxed();
+            run(&|state, output| jit.interpret_sandboxed(state, &b""[..], output, config));
+        }
+        results
+    }
+
+    fn assert_all(
+        src: &[u8],
+        state: &State,
+        config: &SandboxConfig,
+        expected: (BfResult<()>, usize),
+    ) {
+        for result in run_all(src, state, config) {
+            assert_eq!(result, expected);
+        }
+    }
+
+    #[test]
+    fn stops_after_max_steps() {
+        let config = SandboxConfig {
+            max_steps: Some(100),
+            ..SandboxConfig::default()
+        };
+        assert_all(b"+[]", &State::new(), &config, (Err(Error::StepLimit), 0));
+        // Ten iterations of the outer loop and ten of the inner loop each time make 110 steps.
+        let src = b"++++++++++[>++++++++++[-.]<-]";
+        assert_all(src, &State::new(), &config, (Err(Error::StepLimit), 90));
+        assert_all(&src[1..], &State::new(), &config, (Ok(()), 90));
+    }
+
+    #[test]
+    fn truncates_output() {
+        let config = SandboxConfig {
+            max_output_bytes: Some(5),
+            ..SandboxConfig::default()
+        };
+        assert_all(
+            b"+[.]",
+            &State::new(),
+            &config,
+            (Err(Error::OutputLimit), 5),
+        );
+        assert_all(
+            b"+......",
+            &State::new(),
+            &config,
+            (Err(Error::OutputLimit), 5),
+        );
+        assert_all(b"+.....", &State::new(), &config, (Ok(()), 5));
+    }
+
+    #[test]
+    fn limits_memory() {
+        let config = SandboxConfig {
+            max_memory: Some(1000),
+            ..SandboxConfig::default()
+        };
+        let program = ast::parse_program(b"+[>+]").unwrap();
+        let mut state = State::growing(10_000);
+        let result = program.interpret_sandboxed(&mut state, &b""[..], io::sink(), &config);
+        assert_eq!(result, Err(Error::MemoryLimit));
+        assert_eq!((state.capacity(), state.limit()), (1000, 10_000));
+
+        assert_all(b"+", &State::new(), &config, (Err(Error::MemoryLimit), 0));
+        // A tape that doesn't grow fails as it would outside the sandbox.
+        let full = State::with_capacity(1000);
+        assert_all(b"+[>+]", &full, &config, (Err(Error::PointerOverflow), 0));
+    }
+
+    #[test]
+    fn stops_after_wall_clock_limit() {
+        let config = SandboxConfig {
+            wall_clock_limit: Some(Duration::from_millis(10)),
+            ..SandboxConfig::default()
+        };
+        assert_all(b"+[]", &State::new(), &config, (Err(Error::TimeLimit), 0));
+    }
+
+    #[test]
+    fn stops_when_interrupted() {
+        static FLAG: AtomicBool = AtomicBool::new(false);
+        let interrupt = Interrupt::new(&FLAG);
+        let config = SandboxConfig {
+            interrupt: Some(interrupt),
+            ..SandboxConfig::default()
+        };
+        // Code outside loops runs to the end, since the flag is only checked at each step.
+        assert_all(b"+.", &State::new(), &config, (Ok(()), 1));
+        interrupt.raise();
+        assert_all(b"+.", &State::new(), &config, (Ok(()), 1));
+        assert_all(
+            b">+.[.]",
+            &State::new(),
+            &config,
+            (Err(Error::Interrupted), 1),
+        );
+
+        // The state is left as it was at the step that stopped.
+        let program = ast::parse_program(b">>+++[-.]").unwrap();
+        let mut state = State::new();
+        let result = program.bytecode_compile().interpret_sandboxed(
+            &mut state,
+            &b""[..],
+            io::sink(),
+            &config,
+        );
+        assert_eq!(
+            (result, state.pointer(), state.cells()[2]),
+            (Err(Error::Interrupted), 2, 3)
+        );
+        #[cfg(feature = "jit")]
+        {
+            let mut state = State::new();
+            let result = program.jit_compile_sandboxed().interpret_sandboxed(
+                &mut state,
+                &b""[..],
+                io::sink(),
+                &config,
+            );
+            assert_eq!(
+                (result, state.pointer(), state.cells()[2]),
+                (Err(Error::Interrupted), 2, 3)
+            );
+        }
+        interrupt.clear();
+        assert!(!interrupt.is_raised());
+        assert_all(b"+[-]", &State::new(), &config, (Ok(()), 0));
+    }
+
+    #[test]
+    fn runs_within_limits_like_unsandboxed() {
+        let config = SandboxConfig {
+            max_steps: Some(1_000_000),
+            max_output_bytes: Some(100),
+            max_memory: Some(30_000),
+            wall_clock_limit: Some(Duration::from_secs(60)),
+            interrupt: None,
+        };
+        let program = ast::parse_program(FACTOR_SRC).unwrap();
+        let mut output = Vec::new();
+        program
+            .interpret_sandboxed(&mut State::new(), &b"100\n"[..], &mut output, &config)
+            .unwrap();
+        assert_eq!(output, b"100: 2 2 5 5\n");
+    }
+}
@@ -0,0 +1,415 @@
+//! Conventions that differ between Brainfuck implementations.
+//!
+//! Brainfuck leaves a few things up to the implementation: how big a cell is, whether cells wrap
+//! around, what `,` does at the end of input, and where a program's input comes from. Programs
+//! written for other implementations, such as Daniel B. Cristofani's self-interpreter
+//! [dbfi](http://www.hevanet.com/cristofd/brainfuck/dbfi.b), depend on particular choices. A
+//! [`Semantics`](struct.Semantics.html) bundles those choices, and the presets are available by
+//! name as profiles.
+//!
+//! Cells in this crate are always 8 bits, so the choices that vary are the
+//! [end-of-input behavior](enum.Eof.html) and what [overflowing](enum.Overflow.html) a cell
+//! does, which [`State`](../state/struct.State.html) carries, and the `!`-input convention,
+//! which is up to the caller.
+//!
+//! Cells wrap by default. They can instead saturate at 0 and 255, or trap, stopping the program
+//! with an error, as some dialects and teaching settings require. The optimizing compilers rewrite
+//! loops with wrapping arithmetic, so a program for cells that don't wrap has to be compiled for
+//! them, as [`Backend::interpret_in`](../backend/enum.Backend.html#method.interpret_in) does for
+//! the state it is given.
+//!
+//! Cells can also be signed, holding -128 to 127. The bytes are the same as for unsigned cells,
+//! so a signed cell only overflows in a different place, between 127 and -128, and is shown as
+//! a negative number by the debugger and in traces.
+//!
+//! In `bfi`, pass `--profile dbfi` to run a program with dbfi's conventions, `--eof` to
+//! choose the end-of-input behavior alone, `--overflow` to choose what overflowing does, or
+//! `--signed` to make cells signed.
+
+use std::fmt;
+use std::io::Read;
+use std::str::FromStr;
+
+use crate::common::{BfResult, Error};
+use crate::state::{DEFAULT_CAPACITY, State};
+
+/// What `,` stores when there is no more input.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub enum Eof {
+    /// Store 0.
+    #[default]
+    Zero,
+    /// Leave the cell unchanged.
+    Unchanged,
+    /// Store -1, which is 255 in an 8-bit cell.
+    MinusOne,
+}
+
+impl Eof {
+    /// All the end-of-input behaviors.
+    pub const ALL: [Eof; 3] = [Eof::Zero, Eof::Unchanged, Eof::MinusOne];
+
+    /// The name of the behavior, as accepted by `from_str`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Eof::Zero => "zero",
+            Eof::Unchanged => "unchanged",
+            Eof::MinusOne => "minus-one",
+        }
+    }
+
+    /// Reads a byte for `,`, where `current` is the byte at the pointer.
+    #[inline]
+    pub fn read<R: Read + ?Sized>(self, input: &mut R, current: u8) -> u8 {
+        let mut byte = [0];
+        match input.read_exact(&mut byte) {
+            Ok(()) => byte[0],
+            Err(_) => match self {
+                Eof::Zero => 0,
+                Eof::Unchanged => current,
+                Eof::MinusOne => 255,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Eof {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Eof {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Eof::ALL
+            .iter()
+            .copied()
+            .find(|eof| eof.name() == s)
+            .ok_or_else(|| format!("unknown end-of-input behavior: {}", s))
+    }
+}
+
+/// What `+` and `-` do to a cell that would go past its largest or smallest value.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub enum Overflow {
+    /// Wrap around modulo 256.
+    #[default]
+    Wrap,
+    /// Stay at the largest or smallest value.
+    Saturate,
+    /// Stop the program with [`CellOverflow`](../common/enum.Error.html#variant.CellOverflow)
+    /// at the command that would overflow, which leaves the cell at its largest or smallest
+    /// value.
+    Trap,
+}
+
+impl Overflow {
+    /// All the overflow behaviors.
+    pub const ALL: [Overflow; 3] = [Overflow::Wrap, Overflow::Saturate, Overflow::Trap];
+
+    /// The name of the behavior, as accepted by `from_str`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Overflow::Wrap => "wrap",
+            Overflow::Saturate => "saturate",
+            Overflow::Trap => "trap",
+        }
+    }
+
+    /// What `count` `+` commands make of `cell`, which goes from 0 to 255. The state counts
+    /// [signed](../state/struct.State.html#method.signed) cells from -128 to use this.
+    ///
+    /// # Errors
+    ///
+    /// `CellOverflow` if cells trap and the sum is more than 255.
+    #[inline]
+    pub fn up(self, cell: u8, count: usize) -> BfResult<u8> {
+        let sum = (cell as usize).saturating_add(count);
+        match self {
+            Overflow::Wrap => Ok(sum as u8),
+            Overflow::Saturate => Ok(sum.min(255) as u8),
+            Overflow::Trap => u8::try_from(sum).map_err(|_| Error::CellOverflow),
+        }
+    }
+
+    /// What `count` `-` commands make of `cell`.
+    ///
+    /// # Errors
+    ///
+    /// `CellOverflow` if cells trap and `count` is more than `cell`.
+    #[inline]
+    pub fn down(self, cell: u8, count: usize) -> BfResult<u8> {
+        match (self, (cell as usize).checked_sub(count)) {
+            (_, Some(difference)) => Ok(difference as u8),
+            (Overflow::Wrap, None) => Ok(cell.wrapping_sub(count as u8)),
+            (Overflow::Saturate, None) => Ok(0),
+            (Overflow::Trap, None) => Err(Error::CellOverflow),
+        }
+    }
+}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Overflow::ALL
+            .iter()
+            .copied()
+            .find(|overflow| overflow.name() == s)
+            .ok_or_else(|| format!("unknown overflow behavior: {}", s))
+    }
+}
+
+/// A bundle of implementation-defined conventions.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct Semantics {
+    /// The number of bits in a cell. Only 8 is supported.
+    pub cell_bits: u32,
+    /// What `+` and `-` do to a cell that would go past its largest or smallest value.
+    pub overflow: Overflow,
+    /// Whether cells hold -128 to 127 rather than 0 to 255.
+    pub signed: bool,
+    /// What `,` stores when there is no more input.
+    pub eof: Eof,
+    /// Whether a `!` in the source ends the program, with the rest of the source as the start
+    /// of its input.
+    pub bang_input: bool,
+}
+
+impl Semantics {
+    /// The names of the profiles, as accepted by [`by_name`](struct.Semantics.html#method.by_name).
+    pub const PROFILES: [&'static str; 2] = ["default", "dbfi"];
+
+    /// Looks up a profile by name.
+    pub fn by_name(name: &str) -> Option<Semantics> {
+        match name {
+            "default" => Some(Semantics::default()),
+            "dbfi" => Some(Semantics::dbfi()),
+            _ => None,
+        }
+    }
+
+    /// The conventions of dbfi and similar self-interpreters.
+    ///
+    /// They read the program to interpret, followed by `!`, and then that program's input. dbfi
+    /// works with an end of input that stores 0 or leaves the cell unchanged; this profile leaves
+    /// it unchanged, which is what most self-interpreters expect.
+    pub fn dbfi() -> Self {
+        Semantics {
+            eof: Eof::Unchanged,
+            bang_input: true,
+            ..Semantics::default()
+        }
+    }
+
+    /// Checks that this crate can run programs with these conventions.
+    pub fn check(&self) -> Result<(), String> {
+        if self.cell_bits != 8 {
+            Err(format!("{}-bit cells are not supported", self.cell_bits))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Splits source into the program and the start of its input.
+    ///
+    /// With the `!`-input convention, the program ends at the first `!`, and the rest of the
+    /// source is input. Otherwise, the whole source is the program.
+    pub fn split_source<'a>(&self, src: &'a [u8]) -> (&'a [u8], &'a [u8]) {
+        match src.iter().position(|&c| c == b'!') {
+            Some(bang) if self.bang_input => (&src[..bang], &src[bang + 1..]),
+            _ => (src, &[]),
+        }
+    }
+
+    /// Creates a machine state with these conventions and the given memory size, or
+    /// [`DEFAULT_CAPACITY`](../state/constant.DEFAULT_CAPACITY.html).
+    pub fn state(&self, memory_size: Option<usize>) -> State {
+        let mut state = State::with_capacity(memory_size.unwrap_or(DEFAULT_CAPACITY));
+        state.set_eof(self.eof);
+        state.set_overflow(self.overflow);
+        state.set_signed(self.signed);
+        state
+    }
+}
+
+impl Default for Semantics {
+    fn default() -> Self {
+        Semantics {
+            cell_bits: 8,
+            overflow: Overflow::default(),
+            signed: false,
+            eof: Eof::default(),
+            bang_input: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Backend;
+
+    #[test]
+    fn names_round_trip() {
+        for eof in Eof::ALL {
+            assert_eq!(eof.name().parse(), Ok(eof));
+        }
+        assert!("error".parse::<Eof>().is_err());
+        for overflow in Overflow::ALL {
+            assert_eq!(overflow.name().parse(), Ok(overflow));
+        }
+        assert!("clamp".parse::<Overflow>().is_err());
+
+        for name in Semantics::PROFILES {
+            assert!(Semantics::by_name(name).is_some());
+        }
+        assert_eq!(Semantics::by_name("dbfi"), Some(Semantics::dbfi()));
+    }
+
+    #[test]
+    fn eof_behaviors() {
+        let program = crate::ast::parse_program(b"+++,.").unwrap();
+        for (eof, expected) in [(Eof::Zero, 0), (Eof::Unchanged, 3), (Eof::MinusOne, 255)] {
+            let mut state = State::new();
+            state.set_eof(eof);
+            for backend in Backend::all() {
+                let mut output = Vec::new();
+                let result =
+                    backend.interpret_in(&program, &mut state.clone(), &b""[..], &mut output);
+                assert_eq!(result, Ok(()), "{} on {}", eof, backend);
+                assert_eq!(output, [expected], "{} on {}", eof, backend);
+            }
+        }
+    }
+
+    #[test]
+    fn overflow_behaviors() {
+        // Goes below 0 and then past 255, printing the cell after each.
+        let mut src = b"-.".to_vec();
+        src.extend_from_slice(&[b'+'; 256]);
+        src.push(b'.');
+        let program = crate::ast::parse_program(&src).unwrap();
+        for (overflow, expected, stopped) in [
+            (Overflow::Wrap, &[255, 255][..], Ok(())),
+            (Overflow::Saturate, &[0, 255][..], Ok(())),
+            (Overflow::Trap, &[][..], Err(Error::CellOverflow)),
+        ] {
+            let mut state = State::new();
+            state.set_overflow(overflow);
+            for backend in Backend::all() {
+                if !backend.supports_overflow(overflow) {
+                    continue;
+                }
+                let mut output = Vec::new();
+                let result =
+                    backend.interpret_in(&program, &mut state.clone(), &b""[..], &mut output);
+                assert_eq!(result, stopped, "{} on {}", overflow, backend);
+                assert_eq!(output, expected, "{} on {}", overflow, backend);
+            }
+        }
+    }
+
+    #[test]
+    fn overflow_checks_each_command() {
+        assert_eq!(Overflow::Saturate.up(250, 10), Ok(255));
+        assert_eq!(Overflow::Saturate.down(3, 10), Ok(0));
+        assert_eq!(Overflow::Trap.up(250, 5), Ok(255));
+        assert_eq!(Overflow::Trap.up(250, 6), Err(Error::CellOverflow));
+        assert_eq!(Overflow::Trap.down(0, 1), Err(Error::CellOverflow));
+        assert_eq!(Overflow::Wrap.up(250, 262), Ok(0));
+
+        // Cancelling `+-` at 255 would hide the overflow, and so would a loop that the optimizer
+        // turns into arithmetic.
+        for (src, cells) in [(&b"+-"[..], [255, 0]), (b">[-<+++>]", [250, 3])] {
+            let program = crate::ast::parse_program(src).unwrap();
+            let mut state = State::from_cells(cells);
+            state.set_overflow(Overflow::Trap);
+            for backend in Backend::all() {
+                if !backend.supports_overflow(Overflow::Trap) {
+                    continue;
+                }
+                let result =
+                    backend.interpret_in(&program, &mut state.clone(), &b""[..], Vec::new());
+                assert_eq!(result, Err(Error::CellOverflow), "{:?} on {}", src, backend);
+            }
+        }
+    }
+
+    #[test]
+    fn trapping_stops_at_the_command_that_overflows() {
+        let program = crate::ast::parse_program(b">+-++").unwrap();
+        for backend in Backend::all() {
+            if !backend.supports_overflow(Overflow::Trap) {
+                continue;
+            }
+            let mut state = State::from_cells(vec![0, 254]);
+            state.set_overflow(Overflow::Trap);
+            let result = backend.interpret_in(&program, &mut state, &b""[..], Vec::new());
+            assert_eq!(result, Err(Error::CellOverflow), "{}", backend);
+            assert_eq!(state.cells(), [0, 255], "{}", backend);
+            assert_eq!(state.pointer(), 1, "{}", backend);
+        }
+    }
+
+    #[test]
+    fn signed_cells_overflow_between_127_and_minus_128() {
+        // Goes below 0, which signed cells do without overflowing, and then past 127.
+        let mut src = b"-.".to_vec();
+        src.extend_from_slice(&[b'+'; 129]);
+        src.extend_from_slice(b".>>[-<+++>]");
+        let program = crate::ast::parse_program(&src).unwrap();
+        for (overflow, expected, stopped, last) in [
+            (Overflow::Wrap, &[255, 128][..], Ok(()), 129),
+            (Overflow::Saturate, &[255, 127][..], Ok(()), 127),
+            (Overflow::Trap, &[255][..], Err(Error::CellOverflow), 120),
+        ] {
+            let mut state = State::from_cells(vec![0, 120, 3]);
+            state.set_overflow(overflow);
+            state.set_signed(true);
+            for backend in Backend::all() {
+                if !backend.supports_overflow(overflow) {
+                    continue;
+                }
+                let mut state = state.clone();
+                let mut output = Vec::new();
+                let result = backend.interpret_in(&program, &mut state, &b""[..], &mut output);
+                assert_eq!(result, stopped, "{} on {}", overflow, backend);
+                assert_eq!(output, expected, "{} on {}", overflow, backend);
+                assert_eq!(state.cells()[1], last, "{} on {}", overflow, backend);
+            }
+        }
+    }
+
+    #[test]
+    fn bang_splits_source() {
+        let dbfi = Semantics::dbfi();
+        assert_eq!(dbfi.split_source(b",.!ab!c"), (&b",."[..], &b"ab!c"[..]));
+        assert_eq!(dbfi.split_source(b",."), (&b",."[..], &b""[..]));
+        let plain = Semantics::default();
+        assert_eq!(plain.split_source(b",.!ab"), (&b",.!ab"[..], &b""[..]));
+    }
+
+    #[test]
+    fn unsupported_conventions() {
+        assert_eq!(Semantics::dbfi().check(), Ok(()));
+        let wide = Semantics {
+            cell_bits: 16,
+            ..Semantics::default()
+        };
+        assert!(wide.check().is_err());
+        let saturating = Semantics {
+            overflow: Overflow::Saturate,
+            ..Semantics::default()
+        };
+        assert_eq!(saturating.check(), Ok(()));
+    }
+}
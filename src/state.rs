@@ -2,23 +2,130 @@
 //!
 //! Useful for creating initial states for testing, and also the interface used by the
 //! interpreters to access the state.
+//!
+//! A host program can fill in the memory before a run, with
+//! [`State::from_cells`](struct.State.html#method.from_cells) or
+//! [`cells_mut`](struct.State.html#method.cells_mut), run a program in the state with
+//! [`interpret_in`](../traits/trait.Interpretable.html#tymethod.interpret_in), and then read the
+//! results off the tape.
+//!
+//! The optimizing compilers assume that memory starts out zeroed, and remove the loops at the
+//! start of a program, so a program for a filled-in state should be compiled with
+//! [`peephole::compile_prefilled`](../peephole/fn.compile_prefilled.html) or run with
+//! [`Backend::interpret_in`](../backend/enum.Backend.html#method.interpret_in), which checks:
+//!
+//! ```
+//! use bf::state::State;
+//! use bf::traits::*;
+//! use bf::{ast, peephole, rle};
+//!
+//! // Adds the first cell to the second.
+//! let program = ast::parse_program(b"[->+<]").unwrap();
+//! let program = peephole::compile_prefilled(&rle::compile(&program));
+//! let mut state = State::from_cells(vec![3, 4]);
+//! program.interpret_in(&mut state, &b""[..], Vec::new()).unwrap();
+//! assert_eq!(state.cells(), [0, 7]);
+//! assert_eq!(state.pointer(), 0);
+//! ```
+//!
+//! A very large tape can be allocated [lazily](enum.TapeAlloc.html), with
+//! [`State::with_tape_alloc`](struct.State.html#method.with_tape_alloc), so that the memory the
+//! program never touches costs nothing.
 
+use std::collections::VecDeque;
 use std::default::Default;
+use std::fmt;
 use std::io::{Read, Write};
+use std::mem;
 use std::num::Wrapping;
+use std::str::FromStr;
 
 use crate::common::{BfResult, Error};
+use crate::heatmap::Heatmap;
+use crate::semantics::{Eof, Overflow};
+use crate::tape::Tape;
 use crate::traits::IntoUsize;
 
 /// (`== 30_000`) The default number of 8-bit memory cells, as used by
 /// [`State::new`](struct.State.html#method.new).
 pub const DEFAULT_CAPACITY: usize = 30_000;
 
+/// The size that a [growing](struct.State.html#method.growing) state starts at.
+pub const INITIAL_GROWING_CAPACITY: usize = 256;
+
+/// How a [`State`](struct.State.html) allocates its tapes.
+///
+/// The cells start at zero either way. A tape on the heap may be zeroed, and so committed, all
+/// at once, which for a tape of a gigabyte is slow and takes all of that memory even if the
+/// program uses a little of it. A lazy tape is mapped from the operating system with `mmap`,
+/// which reserves the memory and commits each page only when the program first touches it.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub enum TapeAlloc {
+    /// Allocate each tape on the heap.
+    #[default]
+    Heap,
+    /// Map each tape with `mmap`, committing it a page at a time. Where there is no `mmap`, or
+    /// it fails, the tape goes on the heap instead.
+    Lazy,
+    /// Map each tape as for `Lazy`, and ask for it to be backed by huge pages, which a program
+    /// that ranges over a large tape misses the TLB less with. Only Linux takes the hint.
+    HugePages,
+}
+
+impl TapeAlloc {
+    /// All the allocation strategies.
+    pub const ALL: [TapeAlloc; 3] = [TapeAlloc::Heap, TapeAlloc::Lazy, TapeAlloc::HugePages];
+
+    /// The name of the strategy, as accepted by `from_str`.
+    pub fn name(self) -> &'static str {
+        match self {
+            TapeAlloc::Heap => "heap",
+            TapeAlloc::Lazy => "lazy",
+            TapeAlloc::HugePages => "huge-pages",
+        }
+    }
+}
+
+impl fmt::Display for TapeAlloc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for TapeAlloc {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TapeAlloc::ALL
+            .iter()
+            .copied()
+            .find(|alloc| alloc.name() == s)
+            .ok_or_else(|| format!("unknown tape allocation: {}", s))
+    }
+}
+
 /// The Brainfuck machine state.
+///
+/// For the multi-tape extension, a state can have several tapes, each with its own pointer. The
+/// operations all act on the current tape.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct State {
-    memory: Box<[Wrapping<u8>]>,
+    memory: Tape,
     pointer: usize,
+    /// The other tapes and their pointers, in the order that they will become current.
+    parked: VecDeque<(Tape, usize)>,
+    /// The index of the current tape.
+    tape: usize,
+    /// What `read` stores at the end of input.
+    eof: Eof,
+    /// What `up` and `down` do to a cell that would go past its largest or smallest value.
+    overflow: Overflow,
+    /// Whether cells hold -128 to 127 rather than 0 to 255.
+    signed: bool,
+    /// The reads and writes of each cell, if they are being counted.
+    heat: Option<Box<Heatmap>>,
+    /// The size that the tapes can grow to, which is their size if they don't grow.
+    limit: usize,
 }
 
 impl State {
@@ -30,12 +137,123 @@ impl State {
 
     /// Creates a new BF machine state with the given memory capacity.
     pub fn with_capacity(memory_size: usize) -> Self {
+        Self::with_tapes(memory_size, 1)
+    }
+
+    /// Creates a new BF machine state with the given number of tapes, each with the given memory
+    /// capacity.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `tapes` is zero.
+    pub fn with_tapes(memory_size: usize, tapes: usize) -> Self {
+        Self::with_tape_alloc(memory_size, tapes, TapeAlloc::Heap)
+    }
+
+    /// Creates a new BF machine state with the given number of tapes, each with the given memory
+    /// capacity and allocated as `alloc` says.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `tapes` is zero.
+    pub fn with_tape_alloc(memory_size: usize, tapes: usize, alloc: TapeAlloc) -> Self {
+        assert!(tapes > 0, "a state needs at least one tape");
+        let tape = || Tape::new(memory_size, alloc);
         State {
-            memory: vec![Wrapping(0); memory_size].into_boxed_slice(),
+            memory: tape(),
             pointer: 0,
+            parked: (1..tapes).map(|_| (tape(), 0)).collect(),
+            tape: 0,
+            eof: Eof::default(),
+            overflow: Overflow::default(),
+            signed: false,
+            heat: None,
+            limit: memory_size,
         }
     }
 
+    /// Creates a new BF machine state whose memory is the given cells, with the pointer at the
+    /// first.
+    ///
+    /// Programs from the optimizing compilers assume zeroed memory, so unless the cells are all
+    /// zero, compile with [`peephole::compile_prefilled`](../peephole/fn.compile_prefilled.html)
+    /// to run in this state.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no cells.
+    pub fn from_cells<T: Into<Vec<u8>>>(cells: T) -> Self {
+        let cells = cells.into();
+        assert!(!cells.is_empty(), "a state needs at least one cell");
+        let mut state = Self::with_capacity(0);
+        state.memory = cells.into();
+        state.limit = state.memory.len();
+        state
+    }
+
+    /// Creates a new BF machine state whose memory starts small and grows as the pointer moves
+    /// right, up to `limit` cells, so that a program needs no more memory than it uses.
+    ///
+    /// Only the interpreters grow the memory; compiled code sees the memory as it is when it
+    /// starts, and fails when it reaches the end.
+    pub fn growing(limit: usize) -> Self {
+        let mut state = Self::with_capacity(INITIAL_GROWING_CAPACITY.min(limit));
+        state.set_limit(limit);
+        state
+    }
+
+    /// The size that the memory can grow to, or its capacity if it doesn't grow.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Lets the memory grow up to `limit` cells, as for [`growing`](#method.growing). A limit
+    /// below the capacity stops the memory from growing.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit.max(self.capacity());
+    }
+
+    /// Zeroes the memory and moves the pointer back to the start, keeping the allocation, or
+    /// for a [lazy](enum.TapeAlloc.html#variant.Lazy) tape, mapping it afresh.
+    ///
+    /// With several tapes, this resets every tape and makes the first tape current again.
+    pub fn reset(&mut self) {
+        self.switch_tape(self.tapes() - self.tape);
+        self.memory.zero();
+        self.pointer = 0;
+        for (memory, pointer) in self.parked.iter_mut() {
+            memory.zero();
+            *pointer = 0;
+        }
+        if let Some(heat) = self.heat.as_mut() {
+            **heat = Heatmap::new();
+        }
+    }
+
+    /// Switches forward by `count` tapes, wrapping around to the first after the last.
+    ///
+    /// Each tape keeps its own pointer. With one tape, this does nothing.
+    #[inline]
+    pub fn switch_tape<C: IntoUsize>(&mut self, count: C) {
+        for _ in 0..count.into_usize() % self.tapes() {
+            let (memory, pointer) = self.parked.pop_front().expect("tapes() > 1");
+            let memory = mem::replace(&mut self.memory, memory);
+            let pointer = mem::replace(&mut self.pointer, pointer);
+            self.parked.push_back((memory, pointer));
+            self.tape = (self.tape + 1) % self.tapes();
+        }
+    }
+
+    /// The number of tapes.
+    pub fn tapes(&self) -> usize {
+        self.parked.len() + 1
+    }
+
+    /// The index of the current tape.
+    pub fn tape(&self) -> usize {
+        self.tape
+    }
+
     /// Decrements/decreases the pointer.
     ///
     /// # Errors
@@ -58,13 +276,60 @@ impl State {
         Ok(())
     }
 
+    /// Decreases the pointer without checking for underflow.
+    ///
+    /// This is for moves that a [bounds analysis](../analysis/index.html) has proved safe. If the
+    /// move does underflow, a later memory access will panic.
     #[inline]
-    fn pos_offset<C: IntoUsize>(&self, offset: C) -> BfResult<usize> {
-        let offset = offset.into_usize();
-        if self.pointer + offset < self.memory.len() {
-            Ok(self.pointer + offset)
+    pub fn left_unchecked<C: IntoUsize>(&mut self, count: C) {
+        let count = count.into_usize();
+        debug_assert!(self.pointer >= count, "unchecked move underflowed");
+        self.pointer = self.pointer.wrapping_sub(count);
+    }
+
+    /// Increases the pointer without checking for overflow.
+    ///
+    /// This is for moves that a [bounds analysis](../analysis/index.html) has proved safe. If the
+    /// move does overflow, a later memory access will panic.
+    #[inline]
+    pub fn right_unchecked<C: IntoUsize>(&mut self, count: C) {
+        let count = count.into_usize();
+        debug_assert!(
+            self.pointer + count < self.memory.len(),
+            "unchecked move overflowed"
+        );
+        self.pointer = self.pointer.wrapping_add(count);
+    }
+
+    #[inline]
+    fn pos_offset<C: IntoUsize>(&mut self, offset: C) -> BfResult<usize> {
+        let address = self.pointer + offset.into_usize();
+        if address >= self.memory.len() {
+            self.grow(address)?;
+        }
+        Ok(address)
+    }
+
+    /// Grows every tape so that `address` is in range, if the limit allows.
+    #[cold]
+    fn grow(&mut self, address: usize) -> BfResult<()> {
+        if address >= self.limit {
+            return Err(Error::PointerOverflow);
+        }
+        let size = (address + 1).max(2 * self.memory.len()).min(self.limit);
+        self.memory.resize(size);
+        for (memory, _) in self.parked.iter_mut() {
+            memory.resize(size);
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn offset(&mut self, offset: isize) -> BfResult<usize> {
+        if offset < 0 {
+            self.neg_offset(offset.unsigned_abs())
         } else {
-            Err(Error::PointerOverflow)
+            self.pos_offset(offset.unsigned_abs())
         }
     }
 
@@ -80,18 +345,158 @@ impl State {
 
     /// Increments/increases the byte at the pointer.
     ///
-    /// Wraps around modulo 256.
+    /// Wraps around modulo 256, unless the state's [`overflow`](#method.overflow) says
+    /// otherwise.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if cells trap and the byte would go past its largest value, 255 or 127 if
+    /// cells are [signed](#method.signed), leaving it there.
     #[inline]
-    pub fn up(&mut self, count: u8) {
-        self.memory[self.pointer] += Wrapping(count);
+    pub fn up<C: IntoUsize>(&mut self, count: C) -> BfResult<()> {
+        let bias = self.bias();
+        let cell = &mut self.memory[self.pointer];
+        match self.overflow {
+            Overflow::Wrap => *cell += Wrapping(count.into_usize() as u8),
+            overflow => {
+                // The commands before the one that traps take the cell to its largest value.
+                cell.0 = overflow
+                    .up(cell.0 ^ bias, count.into_usize())
+                    .inspect_err(|_| cell.0 = 255 ^ bias)?
+                    ^ bias
+            }
+        }
+        Ok(())
     }
 
     /// Decrements/decreases the byte at the pointer.
     ///
-    /// Wraps around modulo 256.
+    /// Wraps around modulo 256, unless the state's [`overflow`](#method.overflow) says
+    /// otherwise.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if cells trap and the byte would go below its smallest value, 0 or -128 if
+    /// cells are [signed](#method.signed), leaving it there.
+    #[inline]
+    pub fn down<C: IntoUsize>(&mut self, count: C) -> BfResult<()> {
+        let bias = self.bias();
+        let cell = &mut self.memory[self.pointer];
+        match self.overflow {
+            Overflow::Wrap => *cell -= Wrapping(count.into_usize() as u8),
+            overflow => {
+                cell.0 = overflow
+                    .down(cell.0 ^ bias, count.into_usize())
+                    .inspect_err(|_| cell.0 = bias)?
+                    ^ bias
+            }
+        }
+        Ok(())
+    }
+
+    /// What to flip in a byte to make its value as a cell count up from 0: the sign bit for
+    /// signed cells, so that -128 is 0 and 127 is 255.
+    #[inline]
+    fn bias(&self) -> u8 {
+        if self.signed { 0x80 } else { 0 }
+    }
+
+    /// Changes the byte at the pointer as an `Add(amount)` instruction does.
+    ///
+    /// When cells don't wrap, an `amount` of 128 or more is a decrease by `256 - amount`, which
+    /// is how the compilers write a run of `-` for such cells.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if cells trap and the byte would go past its largest or smallest value,
+    /// leaving it there.
+    #[inline]
+    pub fn add(&mut self, amount: u8) -> BfResult<()> {
+        if self.overflow == Overflow::Wrap || amount < 128 {
+            self.up(amount)
+        } else {
+            self.down(amount.wrapping_neg())
+        }
+    }
+
+    /// Adds `amount` to the byte at the pointer modulo 256, which is what
+    /// [`add`](#method.add) does when cells wrap.
+    ///
+    /// The interpreters call this from loops that they specialize for wrapping cells, so that
+    /// the overflow mode isn't checked for every instruction.
+    #[inline]
+    pub(crate) fn add_wrapping(&mut self, amount: u8) {
+        self.memory[self.pointer] += Wrapping(amount);
+    }
+
+    /// Whether cells wrap, so that [`add_wrapping`](#method.add_wrapping) can stand in for
+    /// [`add`](#method.add).
+    #[inline]
+    pub(crate) fn wraps(&self) -> bool {
+        self.overflow == Overflow::Wrap
+    }
+
+    /// Moves the pointer right by steps of `skip` until it reaches a zero byte.
+    ///
+    /// Equivalent to the Brainfuck loop `[>]` (for `skip == 1`) or `[>>>]` (for `skip == 3`).
+    /// Single steps are done with a fast (`memchr`) scan over the memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the pointer would go past the end of memory before finding a zero.
+    #[inline]
+    pub fn find_zero_right<C: IntoUsize>(&mut self, skip: C) -> BfResult<()> {
+        let skip = skip.into_usize();
+
+        if skip == 1 {
+            match memchr::memchr(0, &self.as_bytes()[self.pointer..]) {
+                Some(offset) => self.pointer += offset,
+                None => {
+                    // The cells that growing adds are zero, so the first of them is the zero.
+                    let end = self.memory.len();
+                    if let Err(error) = self.grow(end) {
+                        self.pointer = end - 1;
+                        return Err(error);
+                    }
+                    self.pointer = end;
+                }
+            }
+        } else {
+            while self.load() != 0 {
+                self.right(skip)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves the pointer left by steps of `skip` until it reaches a zero byte.
+    ///
+    /// Equivalent to the Brainfuck loop `[<]` (for `skip == 1`) or `[<<<]` (for `skip == 3`).
+    /// Single steps are done with a fast (`memrchr`) scan over the memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the pointer would go below 0 before finding a zero.
     #[inline]
-    pub fn down(&mut self, count: u8) {
-        self.memory[self.pointer] -= Wrapping(count);
+    pub fn find_zero_left<C: IntoUsize>(&mut self, skip: C) -> BfResult<()> {
+        let skip = skip.into_usize();
+
+        if skip == 1 {
+            match memchr::memrchr(0, &self.as_bytes()[..=self.pointer]) {
+                Some(address) => self.pointer = address,
+                None => {
+                    self.pointer = 0;
+                    return Err(Error::PointerUnderflow);
+                }
+            }
+        } else {
+            while self.load() != 0 {
+                self.left(skip)?;
+            }
+        }
+
+        Ok(())
     }
 
     /// Gets the value of the byte at the pointer.
@@ -106,6 +511,38 @@ impl State {
         self.memory[self.pointer] = Wrapping(value);
     }
 
+    /// Adds the given value at the given offset from the pointer, which may be negative.
+    #[inline]
+    pub fn up_at(&mut self, offset: isize, value: u8) -> BfResult<()> {
+        let address = self.offset(offset)?;
+        self.memory[address] += Wrapping(value);
+        Ok(())
+    }
+
+    /// Stores the given value at the given offset from the pointer, which may be negative.
+    #[inline]
+    pub fn store_at(&mut self, offset: isize, value: u8) -> BfResult<()> {
+        let address = self.offset(offset)?;
+        self.memory[address] = Wrapping(value);
+        Ok(())
+    }
+
+    /// Adds the given value at the given offset from the pointer, which a bounds analysis has
+    /// proved to be in range.
+    #[inline]
+    pub fn up_at_unchecked(&mut self, offset: isize, value: u8) {
+        let address = self.pointer.wrapping_add_signed(offset);
+        self.memory[address] += Wrapping(value);
+    }
+
+    /// Stores the given value at the given offset from the pointer, which a bounds analysis has
+    /// proved to be in range.
+    #[inline]
+    pub fn store_at_unchecked(&mut self, offset: isize, value: u8) {
+        let address = self.pointer.wrapping_add_signed(offset);
+        self.memory[address] = Wrapping(value);
+    }
+
     /// Adds the given value at the given positive offset from the pointer.
     #[inline]
     pub fn up_pos_offset<C: IntoUsize>(&mut self, offset: C, value: u8) -> BfResult<()> {
@@ -122,12 +559,73 @@ impl State {
         Ok(())
     }
 
+    /// Adds the given value at the given positive offset from the pointer, which a bounds
+    /// analysis has proved to be in range.
+    #[inline]
+    pub fn up_pos_offset_unchecked<C: IntoUsize>(&mut self, offset: C, value: u8) {
+        let address = self.pointer.wrapping_add(offset.into_usize());
+        self.memory[address] += Wrapping(value);
+    }
+
+    /// Adds the given value at the given negative offset from the pointer, which a bounds
+    /// analysis has proved to be in range.
+    #[inline]
+    pub fn up_neg_offset_unchecked<C: IntoUsize>(&mut self, offset: C, value: u8) {
+        let address = self.pointer.wrapping_sub(offset.into_usize());
+        self.memory[address] += Wrapping(value);
+    }
+
     /// Reads from a `Read` into the byte at the pointer.
+    ///
+    /// At the end of input, this does what the state's [`eof`](#method.eof) says.
     #[inline]
     pub fn read<R: Read>(&mut self, input: &mut R) {
-        let mut byte = [0];
-        let _ = input.read_exact(&mut byte);
-        self.store(byte[0]);
+        let byte = self.eof.read(input, self.load());
+        self.store(byte);
+    }
+
+    /// What [`read`](#method.read) does at the end of input, which is to store 0 by default.
+    pub fn eof(&self) -> Eof {
+        self.eof
+    }
+
+    /// Sets what [`read`](#method.read) does at the end of input.
+    pub fn set_eof(&mut self, eof: Eof) {
+        self.eof = eof;
+    }
+
+    /// What [`up`](#method.up) and [`down`](#method.down) do to a cell that would go past its
+    /// largest or smallest value, which is to wrap around by default.
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+
+    /// Sets what [`up`](#method.up) and [`down`](#method.down) do to a cell that would go past
+    /// its largest or smallest value.
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        self.overflow = overflow;
+    }
+
+    /// Whether cells hold -128 to 127, as an `i8` reads their bytes, rather than 0 to 255. This
+    /// changes only where cells that don't wrap overflow, and how cells are shown, since the
+    /// bytes are the same either way.
+    pub fn signed(&self) -> bool {
+        self.signed
+    }
+
+    /// Sets whether cells hold -128 to 127 rather than 0 to 255.
+    pub fn set_signed(&mut self, signed: bool) {
+        self.signed = signed;
+    }
+
+    /// The number that a cell holding `byte` stands for, which is negative for a byte of 128
+    /// or more if cells are [signed](#method.signed).
+    pub fn cell_value(&self, byte: u8) -> i16 {
+        if self.signed {
+            i16::from(byte as i8)
+        } else {
+            i16::from(byte)
+        }
     }
 
     /// Writes to a `Write` from the byte at the pointer.
@@ -136,11 +634,91 @@ impl State {
         let _ = output.write_all(&[self.load()]);
     }
 
+    /// The position of the pointer on the current tape.
+    pub fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    /// Moves the pointer to the given position on the current tape, growing the memory if it
+    /// can.
+    ///
+    /// Every backend starts the program where the pointer is, and the
+    /// [bounds analysis](../analysis/index.html) that lets them skip checks holds wherever that is.
+    /// The compilers do assume that memory is zeroed, so a state that has run a program before
+    /// needs a [reset](#method.reset) or
+    /// [`peephole::compile_prefilled`](../peephole/fn.compile_prefilled.html) whatever the
+    /// pointer.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the position is past the end of the memory.
+    pub fn set_pointer(&mut self, pointer: usize) -> BfResult<()> {
+        if pointer >= self.memory.len() {
+            self.grow(pointer)?;
+        }
+        self.pointer = pointer;
+        Ok(())
+    }
+
+    /// The cells of the current tape.
+    pub fn cells(&self) -> &[u8] {
+        self.as_bytes()
+    }
+
+    /// The cells of the current tape, for changing them.
+    ///
+    /// The [heat map](#method.heatmap) doesn't count changes made this way. As with
+    /// [`from_cells`](#method.from_cells), a program for memory changed this way should be
+    /// compiled with [`peephole::compile_prefilled`](../peephole/fn.compile_prefilled.html).
+    pub fn cells_mut(&mut self) -> &mut [u8] {
+        // Assumes that Wrapping<u8> == u8, as for `as_bytes`:
+        unsafe {
+            std::slice::from_raw_parts_mut(self.memory.as_mut_ptr() as *mut u8, self.memory.len())
+        }
+    }
+
+    /// Starts counting the reads and writes of each cell in a [`Heatmap`](../heatmap/index.html),
+    /// which only the [debugger](../debugger/index.html) does.
+    pub fn track_heat(&mut self) {
+        self.heat.get_or_insert_with(Box::default);
+    }
+
+    /// The reads and writes of each cell, if [`track_heat`](#method.track_heat) was called.
+    ///
+    /// [Resetting](#method.reset) the state clears the counts.
+    pub fn heatmap(&self) -> Option<&Heatmap> {
+        self.heat.as_deref()
+    }
+
+    #[cfg(feature = "tools")]
+    pub(crate) fn heatmap_mut(&mut self) -> Option<&mut Heatmap> {
+        self.heat.as_deref_mut()
+    }
+
+    /// How the tapes were allocated, which is on the heap unless the state was made by
+    /// [`with_tape_alloc`](#method.with_tape_alloc) and mapping the tapes worked.
+    pub fn tape_alloc(&self) -> TapeAlloc {
+        self.memory.alloc()
+    }
+
     /// The memory capacity.
     pub fn capacity(&self) -> usize {
         self.memory.len()
     }
 
+    /// Whether every cell of every tape is zero, as the optimizing compilers assume at the start.
+    pub(crate) fn is_zeroed(&self) -> bool {
+        let zero = |tape: &Tape| tape.iter().all(|cell| cell.0 == 0);
+        zero(&self.memory) && self.parked.iter().all(|(tape, _)| zero(tape))
+    }
+
+    /// Views the memory as a slice of bytes.
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        // Assumes that Wrapping<u8> == u8, which holds because `Wrapping` is
+        // `repr(transparent)`:
+        unsafe { std::slice::from_raw_parts(self.memory.as_ptr() as *const u8, self.memory.len()) }
+    }
+
     /// Gets a mutable, raw pointer to the start of memory.
     ///
     /// This is used by the JIT RTS to pass the memory pointer to the generated code.
@@ -170,6 +748,45 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn reset_zeroes() {
+        let mut actual = make(&[1, 2, 3], 2);
+        actual.reset();
+        assert_eq!(actual, make(&[0, 0, 0], 0));
+    }
+
+    #[test]
+    fn tapes_keep_their_pointers() {
+        let mut state = State::with_tapes(3, 2);
+        state.right(2usize).unwrap();
+        state.up(1u8).unwrap();
+        state.switch_tape(1usize);
+        assert_eq!((state.tape(), state.pointer(), state.load()), (1, 0, 0));
+        state.up(2u8).unwrap();
+        state.switch_tape(3usize);
+        assert_eq!((state.tape(), state.pointer(), state.load()), (0, 2, 1));
+        state.switch_tape(1usize);
+        assert_eq!(state.load(), 2);
+    }
+
+    #[test]
+    fn switch_with_one_tape_does_nothing() {
+        let mut state = make(&[1, 2, 3], 1);
+        state.switch_tape(5usize);
+        assert_eq!(state, make(&[1, 2, 3], 1));
+    }
+
+    #[test]
+    fn reset_returns_to_first_tape() {
+        let mut state = State::with_tapes(2, 3);
+        state.up(1u8).unwrap();
+        state.switch_tape(2usize);
+        state.right(1usize).unwrap();
+        state.up(1u8).unwrap();
+        state.reset();
+        assert_eq!(state, State::with_tapes(2, 3));
+    }
+
     #[test]
     fn right_then_left_restores() {
         let mut actual = make(&[0, 0, 0], 0);
@@ -184,17 +801,57 @@ mod tests {
     #[test]
     fn up_goes_to_1() {
         let mut actual = make(&[0, 0, 0], 0);
-        actual.up(1);
+        actual.up(1u8).unwrap();
         assert_eq!(actual, make(&[1, 0, 0], 0))
     }
 
     #[test]
     fn down_goes_to_255() {
         let mut actual = make(&[0, 0, 0], 0);
-        actual.down(1);
+        actual.down(1u8).unwrap();
         assert_eq!(actual, make(&[255, 0, 0], 0))
     }
 
+    #[test]
+    fn cells_that_dont_wrap() {
+        let mut actual = make(&[250, 3], 0);
+        actual.set_overflow(Overflow::Saturate);
+        actual.up(10u8).unwrap();
+        actual.right(1usize).unwrap();
+        actual.add(251).unwrap();
+        assert_eq!(actual.cells(), [255, 0]);
+
+        actual.set_overflow(Overflow::Trap);
+        actual.add(127).unwrap();
+        assert_eq!(actual.down(128u8), Err(Error::CellOverflow));
+        actual.left(1usize).unwrap();
+        assert_eq!(actual.add(1), Err(Error::CellOverflow));
+        assert_eq!(actual.cells(), [255, 0]);
+    }
+
+    #[test]
+    fn signed_cells() {
+        let mut actual = make(&[120, 0x85], 0);
+        actual.set_signed(true);
+        assert_eq!(actual.cell_value(actual.cells()[1]), -123);
+        actual.set_overflow(Overflow::Saturate);
+        actual.up(10u8).unwrap();
+        actual.right(1usize).unwrap();
+        actual.add(246).unwrap();
+        assert_eq!(actual.cells(), [127, 0x80]);
+
+        // Signed cells cross zero without overflowing, and overflow between 127 and -128.
+        actual.set_overflow(Overflow::Trap);
+        actual.up(200u8).unwrap();
+        assert_eq!(actual.cell_value(actual.load()), 72);
+        assert_eq!(actual.up(56u8), Err(Error::CellOverflow));
+        assert_eq!(actual.cells(), [127, 127]);
+        actual.left(1usize).unwrap();
+        assert_eq!(actual.down(255u8), Ok(()));
+        assert_eq!(actual.down(1u8), Err(Error::CellOverflow));
+        assert_eq!(actual.cells(), [0x80, 127]);
+    }
+
     #[test]
     fn load_reads() {
         assert_eq!(make(&[0, 0, 0], 0).load(), 0);
@@ -215,15 +872,15 @@ mod tests {
     #[test]
     fn longer_sequence_of_actions() {
         let mut actual = make(&[0, 0, 0], 0);
-        actual.up(1);
+        actual.up(1u8).unwrap();
         assert_eq!(actual, make(&[1, 0, 0], 0));
-        actual.up(1);
+        actual.up(1u8).unwrap();
         assert_eq!(actual, make(&[2, 0, 0], 0));
         actual.right(1usize).unwrap();
         assert_eq!(actual, make(&[2, 0, 0], 1));
-        actual.down(1);
+        actual.down(1u8).unwrap();
         assert_eq!(actual, make(&[2, 255, 0], 1));
-        actual.down(1);
+        actual.down(1u8).unwrap();
         assert_eq!(actual, make(&[2, 254, 0], 1));
         actual.right(1usize).unwrap();
         assert_eq!(actual, make(&[2, 254, 0], 2));
@@ -255,14 +912,148 @@ mod tests {
         machine.left(1usize).unwrap();
     }
 
+    #[test]
+    fn find_zero_right_finds_zero() {
+        let mut actual = make(&[1, 2, 0, 3, 0], 0);
+        actual.find_zero_right(1usize).unwrap();
+        assert_eq!(actual, make(&[1, 2, 0, 3, 0], 2));
+        actual.find_zero_right(1usize).unwrap();
+        assert_eq!(actual, make(&[1, 2, 0, 3, 0], 2));
+    }
+
+    #[test]
+    fn find_zero_right_with_skip() {
+        let mut actual = make(&[1, 0, 2, 0, 0], 0);
+        actual.find_zero_right(2usize).unwrap();
+        assert_eq!(actual, make(&[1, 0, 2, 0, 0], 4));
+    }
+
+    #[test]
+    fn find_zero_right_past_edge_is_error() {
+        let mut actual = make(&[0, 1, 1], 1);
+        assert_eq!(actual.find_zero_right(1usize), Err(Error::PointerOverflow));
+    }
+
+    #[test]
+    fn find_zero_left_finds_zero() {
+        let mut actual = make(&[0, 2, 0, 3, 4], 4);
+        actual.find_zero_left(1usize).unwrap();
+        assert_eq!(actual, make(&[0, 2, 0, 3, 4], 2));
+    }
+
+    #[test]
+    fn find_zero_left_past_edge_is_error() {
+        let mut actual = make(&[1, 1, 0], 1);
+        assert_eq!(actual.find_zero_left(1usize), Err(Error::PointerUnderflow));
+    }
+
+    #[test]
+    fn from_cells_starts_at_the_first() {
+        assert_eq!(State::from_cells(&[1, 2, 3][..]), make(&[1, 2, 3], 0));
+    }
+
+    #[test]
+    fn cells_can_be_changed() {
+        let mut state = State::with_capacity(4);
+        state.cells_mut()[2] = 9;
+        state.set_pointer(2).unwrap();
+        assert_eq!(state.load(), 9);
+        assert_eq!(state.cells(), [0, 0, 9, 0]);
+        assert_eq!(state.set_pointer(4), Err(Error::PointerOverflow));
+        assert_eq!(state.pointer(), 2);
+
+        let mut state = State::growing(1000);
+        state.set_pointer(999).unwrap();
+        assert_eq!(state.capacity(), 1000);
+    }
+
+    #[test]
+    fn lazy_tapes_are_like_heap_tapes() {
+        for alloc in TapeAlloc::ALL {
+            assert_eq!(alloc.name().parse(), Ok(alloc));
+
+            let mut state = State::with_tape_alloc(5000, 2, alloc);
+            if cfg!(unix) {
+                assert_eq!(state.tape_alloc(), alloc);
+            }
+            state.right(4999usize).unwrap();
+            state.up(7u8).unwrap();
+            state.switch_tape(1u8);
+            state.store(3);
+            state.set_limit(6000);
+            state.set_pointer(5999).unwrap();
+            let copy = state.clone();
+            assert_eq!(copy.tape_alloc(), state.tape_alloc());
+            assert_eq!(copy, state);
+            state.reset();
+            assert_eq!(state, State::with_tapes(6000, 2), "{}", alloc);
+            assert_eq!((copy.cells()[0], copy.tape()), (3, 1));
+        }
+        assert!("mmap".parse::<TapeAlloc>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn lazy_tapes_can_be_bigger_than_what_is_touched() {
+        // The pages of a gigabyte tape are committed only as they are touched.
+        let mut state = State::with_tape_alloc(1 << 30, 1, TapeAlloc::Lazy);
+        state.set_pointer((1 << 30) - 1).unwrap();
+        state.up(1u8).unwrap();
+        assert_eq!(state.as_bytes()[(1 << 30) - 1], 1);
+        state.reset();
+        assert_eq!(state.load(), 0);
+    }
+
+    #[test]
+    fn growing_state_grows_to_its_limit() {
+        let mut state = State::growing(1000);
+        assert_eq!(
+            (state.capacity(), state.limit()),
+            (INITIAL_GROWING_CAPACITY, 1000)
+        );
+        state.right(300usize).unwrap();
+        state.up(1u8).unwrap();
+        assert_eq!(state.capacity(), 512);
+        state.up_at(300, 1).unwrap();
+        assert_eq!(state.capacity(), 1000);
+        assert_eq!(state.right(700usize), Err(Error::PointerOverflow));
+        assert_eq!(state.pointer(), 300);
+    }
+
+    #[test]
+    fn growing_state_finds_zero_past_the_end() {
+        let full = |limit| {
+            let mut state = State::growing(limit);
+            state.store(1);
+            for _ in 1..INITIAL_GROWING_CAPACITY {
+                state.right(1usize).unwrap();
+                state.store(1);
+            }
+            state.left(INITIAL_GROWING_CAPACITY - 1).unwrap();
+            assert_eq!(state.capacity(), INITIAL_GROWING_CAPACITY);
+            state
+        };
+
+        let mut state = full(300);
+        state.find_zero_right(1usize).unwrap();
+        assert_eq!(state.pointer(), INITIAL_GROWING_CAPACITY);
+        assert_eq!(state.capacity(), 300);
+
+        let mut state = full(INITIAL_GROWING_CAPACITY);
+        assert_eq!(state.find_zero_right(1usize), Err(Error::PointerOverflow));
+    }
+
     fn make(memory: &[u8], pointer: usize) -> State {
         State {
-            memory: memory
-                .iter()
-                .map(|&b| Wrapping(b))
-                .collect::<Vec<_>>()
-                .into_boxed_slice(),
+            memory: memory.to_vec().into(),
             pointer,
+            parked: VecDeque::new(),
+            tape: 0,
+            eof: Eof::default(),
+            overflow: Overflow::default(),
+            signed: false,
+            heat: None,
+            limit: memory.len(),
         }
     }
 }
@@ -0,0 +1,425 @@
+//! Static statistics about a program, for comparing its forms.
+//!
+//! Where [counters](../counters/index.html) record what a program does when it runs, a
+//! [`Stats`](struct.Stats.html) describes its code: how many instructions of each kind it has,
+//! how big it is, how deeply its loops nest, and how much of the tape it can reach. Every
+//! program form converts to `Stats` with `From`, so comparing the statistics of each form shows
+//! how much each pass shrinks a program.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::common::{Count, Instruction};
+use crate::counters::Kind;
+use crate::traits::IntoUsize;
+use crate::{ast, bytecode, fused, peephole, rle};
+
+/// Counts that describe a program's code.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Stats {
+    /// The number of instructions of each kind, by name, leaving out kinds that don't appear.
+    /// Loops in the tree-shaped forms count as `Loop`.
+    pub kinds: BTreeMap<String, usize>,
+    /// The size of the code in instructions, where a loop in the tree-shaped forms counts as two,
+    /// for its start and end, as in bytecode. For the AST, this is the number of commands.
+    pub size: usize,
+    /// The number of loops.
+    pub loops: usize,
+    /// The deepest that loops nest.
+    pub depth: usize,
+    /// The number of cells that the program can reach on a tape, if that is known without
+    /// running it. It isn't known if a loop can end somewhere other than where it started.
+    pub tape_span: Option<usize>,
+}
+
+impl Stats {
+    /// The total number of instructions, not counting loops.
+    pub fn instructions(&self) -> usize {
+        self.kinds
+            .iter()
+            .filter(|&(name, _)| name != "Loop")
+            .map(|(_, &count)| count)
+            .sum()
+    }
+
+    /// The statistics as a JSON object.
+    pub fn to_json(&self) -> String {
+        let mut result = String::from("{\"kinds\":{");
+        for (index, (name, count)) in self.kinds.iter().enumerate() {
+            if index > 0 {
+                result.push(',');
+            }
+            let _ = write!(result, "\"{}\":{}", name, count);
+        }
+        let _ = write!(
+            result,
+            "}},\"size\":{},\"loops\":{},\"depth\":{},\"tape_span\":",
+            self.size, self.loops, self.depth
+        );
+        match self.tape_span {
+            Some(span) => {
+                let _ = write!(result, "{}}}", span);
+            }
+            None => result.push_str("null}"),
+        }
+        result
+    }
+}
+
+/// What an instruction does to the pointer, for estimating the tape span.
+enum Reach {
+    /// Moves the pointer by the given distance.
+    Move(isize),
+    /// Uses the cell at the given offset from the pointer.
+    Offset(isize),
+    /// Moves the pointer an unknown distance.
+    Unknown,
+}
+
+/// Collects statistics, with loops as a begin and end.
+struct Collector {
+    stats: Stats,
+    /// The positions of the pointer at the start of each open loop.
+    open: Vec<isize>,
+    /// The position of the pointer relative to the start, and the least and greatest positions
+    /// reached, unless a move was unknown.
+    reach: Option<(isize, isize, isize)>,
+}
+
+impl Collector {
+    fn new() -> Self {
+        Collector {
+            stats: Stats::default(),
+            open: Vec::new(),
+            reach: Some((0, 0, 0)),
+        }
+    }
+
+    fn instruction(&mut self, name: &str, reach: Reach) {
+        *self.stats.kinds.entry(name.to_owned()).or_insert(0) += 1;
+        self.stats.size += 1;
+        self.reach(reach);
+    }
+
+    fn reach(&mut self, reach: Reach) {
+        if let Some((ref mut position, ref mut low, ref mut high)) = self.reach {
+            let reached = match reach {
+                Reach::Move(distance) => {
+                    *position += distance;
+                    *position
+                }
+                Reach::Offset(offset) => *position + offset,
+                Reach::Unknown => {
+                    self.reach = None;
+                    return;
+                }
+            };
+            *low = (*low).min(reached);
+            *high = (*high).max(reached);
+        }
+    }
+
+    fn begin(&mut self) {
+        self.stats.size += 1;
+        self.stats.loops += 1;
+        self.open.push(self.position());
+        self.stats.depth = self.stats.depth.max(self.open.len());
+    }
+
+    fn end(&mut self) {
+        self.stats.size += 1;
+        let start = self.open.pop().unwrap_or(0);
+        if self.position() != start {
+            self.reach = None;
+        }
+    }
+
+    fn position(&self) -> isize {
+        self.reach.map_or(0, |(position, _, _)| position)
+    }
+
+    fn finish(mut self) -> Stats {
+        self.stats.tape_span = self.reach.map(|(_, low, high)| (high - low) as usize + 1);
+        self.stats
+    }
+}
+
+fn distance(count: Count) -> isize {
+    count.into_usize() as isize
+}
+
+/// What a bytecode instruction does to the pointer.
+fn instruction_reach(instruction: &Instruction) -> Reach {
+    use crate::common::Instruction::*;
+
+    match *instruction {
+        Left(count) => Reach::Move(-distance(count)),
+        Right(count) => Reach::Move(distance(count)),
+        OffsetAddRight(offset) | MulAddRight(offset, _) => Reach::Offset(distance(offset)),
+        OffsetAddLeft(offset) | MulAddLeft(offset, _) => Reach::Offset(-distance(offset)),
+        AddAt(offset, _) | SetAt(offset, _) => Reach::Offset(offset),
+        FindZeroRight(_) | FindZeroLeft(_) => Reach::Unknown,
+        Add(_) | In | Out | JumpZero(_) | JumpNotZero(_) | SetZero | SetConst(_) | WriteStr(_)
+        | SwitchTape(_) => Reach::Offset(0),
+    }
+}
+
+/// Adds a bytecode instruction, treating its jumps as loops.
+fn collect_instruction(collector: &mut Collector, instruction: &Instruction) {
+    match *instruction {
+        Instruction::JumpZero(_) => collector.begin(),
+        Instruction::JumpNotZero(_) => collector.end(),
+        _ => collector.instruction(Kind::of(instruction).name(), instruction_reach(instruction)),
+    }
+}
+
+impl From<&ast::Program> for Stats {
+    fn from(program: &ast::Program) -> Self {
+        fn collect(program: &ast::Program, collector: &mut Collector) {
+            use crate::common::Command::*;
+
+            for statement in program {
+                match *statement {
+                    ast::Statement::Cmd(command) => {
+                        let reach = match command {
+                            Left => Reach::Move(-1),
+                            Right => Reach::Move(1),
+                            _ => Reach::Offset(0),
+                        };
+                        collector.instruction(&format!("{:?}", command), reach);
+                    }
+                    ast::Statement::Loop(ref body) => {
+                        collector.begin();
+                        collect(body, collector);
+                        collector.end();
+                    }
+                }
+            }
+        }
+
+        let mut collector = Collector::new();
+        collect(program, &mut collector);
+        with_loops(collector.finish())
+    }
+}
+
+impl From<&rle::Program> for Stats {
+    fn from(program: &rle::Program) -> Self {
+        fn collect(program: &rle::Program, collector: &mut Collector) {
+            use crate::common::Command::*;
+
+            for statement in program {
+                match *statement {
+                    rle::Statement::Cmd(command, count) => {
+                        let reach = match command {
+                            Left => Reach::Move(-distance(count)),
+                            Right => Reach::Move(distance(count)),
+                            _ => Reach::Offset(0),
+                        };
+                        collector.instruction(&format!("{:?}", command), reach);
+                    }
+                    rle::Statement::Loop(ref body) => {
+                        collector.begin();
+                        collect(body, collector);
+                        collector.end();
+                    }
+                }
+            }
+        }
+
+        let mut collector = Collector::new();
+        collect(program, &mut collector);
+        with_loops(collector.finish())
+    }
+}
+
+impl From<&peephole::Program> for Stats {
+    fn from(program: &peephole::Program) -> Self {
+        fn collect(program: &peephole::Program, collector: &mut Collector) {
+            for statement in program {
+                match *statement {
+                    peephole::Statement::Instr(ref instruction) => {
+                        collect_instruction(collector, instruction)
+                    }
+                    peephole::Statement::Loop(ref body) => {
+                        collector.begin();
+                        collect(body, collector);
+                        collector.end();
+                    }
+                }
+            }
+        }
+
+        let mut collector = Collector::new();
+        collect(program, &mut collector);
+        with_loops(collector.finish())
+    }
+}
+
+impl From<&bytecode::Program> for Stats {
+    fn from(program: &bytecode::Program) -> Self {
+        let mut collector = Collector::new();
+        for instruction in program {
+            collect_instruction(&mut collector, instruction);
+        }
+        let mut result = collector.finish();
+        for (name, count) in [("JumpZero", result.loops), ("JumpNotZero", result.loops)] {
+            if count > 0 {
+                result.kinds.insert(name.to_owned(), count);
+            }
+        }
+        result
+    }
+}
+
+impl From<&fused::Program> for Stats {
+    fn from(program: &fused::Program) -> Self {
+        use fused::Instruction as Fused;
+
+        let mut collector = Collector::new();
+        let mut ends = 0;
+        for instruction in program {
+            let (name, first) = match *instruction {
+                Fused::Op(Instruction::JumpZero(_)) => {
+                    collector.begin();
+                    continue;
+                }
+                Fused::Op(Instruction::JumpNotZero(_)) => {
+                    collector.end();
+                    ends += 1;
+                    continue;
+                }
+                Fused::Op(ref instruction) => {
+                    let reach = instruction_reach(instruction);
+                    collector.instruction(Kind::of(instruction).name(), reach);
+                    continue;
+                }
+                Fused::AddRight(_, count) => ("AddRight", Reach::Move(distance(count))),
+                Fused::AddLeft(_, count) => ("AddLeft", Reach::Move(-distance(count))),
+                Fused::RightAdd(count, _) => ("RightAdd", Reach::Move(distance(count))),
+                Fused::LeftAdd(count, _) => ("LeftAdd", Reach::Move(-distance(count))),
+                Fused::SetZeroRight(count) => ("SetZeroRight", Reach::Move(distance(count))),
+                Fused::SetZeroLeft(count) => ("SetZeroLeft", Reach::Move(-distance(count))),
+                Fused::AddJumpNotZero(..) => ("AddJumpNotZero", Reach::Offset(0)),
+                Fused::RightJumpNotZero(count, _) => {
+                    ("RightJumpNotZero", Reach::Move(distance(count)))
+                }
+                Fused::LeftJumpNotZero(count, _) => {
+                    ("LeftJumpNotZero", Reach::Move(-distance(count)))
+                }
+            };
+
+            collector.instruction(name, first);
+            if name.ends_with("JumpNotZero") {
+                // The end of the loop is part of the superinstruction.
+                collector.stats.size -= 1;
+                collector.end();
+            }
+        }
+
+        let mut result = collector.finish();
+        result.kinds.insert("JumpZero".to_owned(), result.loops);
+        if ends > 0 {
+            result.kinds.insert("JumpNotZero".to_owned(), ends);
+        }
+        result.kinds.retain(|_, &mut count| count > 0);
+        result
+    }
+}
+
+/// Counts the loops in the tree-shaped forms as a kind.
+fn with_loops(mut stats: Stats) -> Stats {
+    if stats.loops > 0 {
+        stats.kinds.insert("Loop".to_owned(), stats.loops);
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+    use crate::traits::*;
+
+    #[test]
+    fn counts_kinds() {
+        let program = ast::parse_program(b"++[->+<]>.").unwrap();
+        let stats = Stats::from(&*program);
+        let kinds: Vec<_> = stats
+            .kinds
+            .iter()
+            .map(|(name, &count)| (&**name, count))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("Down", 1),
+                ("Left", 1),
+                ("Loop", 1),
+                ("Out", 1),
+                ("Right", 2),
+                ("Up", 3)
+            ]
+        );
+        assert_eq!(stats.instructions(), 8);
+        assert_eq!((stats.size, stats.loops, stats.depth), (10, 1, 1));
+        assert_eq!(stats.tape_span, Some(2));
+
+        let stats = Stats::from(&*program.peephole_compile());
+        assert_eq!(stats.size, 4);
+        assert_eq!(stats.tape_span, Some(2));
+    }
+
+    #[test]
+    fn span_is_unknown_after_unbalanced_loops() {
+        let program = ast::parse_program(b"<<+>>>[>]").unwrap();
+        assert_eq!(Stats::from(&*program).tape_span, None);
+        let program = ast::parse_program(b"<<+>>>[>+<]").unwrap();
+        assert_eq!(Stats::from(&*program).tape_span, Some(5));
+        assert_eq!(Stats::from(&*program.peephole_compile()).tape_span, Some(5));
+    }
+
+    #[test]
+    fn passes_shrink_programs() {
+        let program = ast::parse_program(FACTOR_SRC).unwrap();
+        let sizes = [
+            Stats::from(&*program).size,
+            Stats::from(&*program.rle_compile()).size,
+            Stats::from(&*program.peephole_compile()).size,
+            Stats::from(&*program.fused_compile()).size,
+        ];
+        assert!(
+            sizes.windows(2).all(|pair| pair[0] > pair[1]),
+            "{:?}",
+            sizes
+        );
+
+        let peephole = Stats::from(&*program.peephole_compile());
+        let bytecode = Stats::from(&*program.bytecode_compile());
+        assert_eq!(peephole.size, bytecode.size);
+        assert_eq!(
+            peephole.instructions(),
+            bytecode.instructions() - 2 * bytecode.loops
+        );
+        assert_eq!(peephole.depth, bytecode.depth);
+    }
+
+    #[test]
+    fn fused_loops_match() {
+        let program = ast::parse_program(b"+[>+<-]>[-<+>]+[.>]").unwrap();
+        let bytecode = Stats::from(&*program.bytecode_compile());
+        let fused = Stats::from(&*program.fused_compile());
+        assert_eq!((fused.loops, fused.depth), (bytecode.loops, bytecode.depth));
+        assert_eq!(fused.kinds["JumpZero"], bytecode.kinds["JumpZero"]);
+    }
+
+    #[test]
+    fn prints_json() {
+        let program = ast::parse_program(b"+[>]").unwrap();
+        assert_eq!(
+            Stats::from(&*program).to_json(),
+            "{\"kinds\":{\"Loop\":1,\"Right\":1,\"Up\":1},\"size\":4,\"loops\":1,\"depth\":1,\
+             \"tape_span\":null}"
+        );
+    }
+}
@@ -0,0 +1,914 @@
+//! Symbolic execution, which runs a program on every input at once.
+//!
+//! Instead of bytes, the cells here hold [`Value`](struct.Value.html)s: a constant plus a
+//! multiple of each input byte, modulo 256. Adds, stores, and multiply-adds keep a value in
+//! that form, so a program that only moves its input around runs once for all inputs. Where a
+//! loop or a scan needs to know whether a value is zero, the run splits into a path for each
+//! way that it could go, with what each path assumes about the input; when the value depends on
+//! more than one input byte, the first of them becomes concrete instead, with a path for each of
+//! its values. [`explore`](fn.explore.html) lists the [`Path`](struct.Path.html)s of a program, for
+//! inputs up to a length, within [`Limits`](struct.Limits.html) on how much work to do.
+//!
+//! [`check_equivalent`](fn.check_equivalent.html) runs two programs down the same paths and
+//! compares what they write and how they stop, so that it can prove that an optimization doesn't
+//! change what a program does on any input up to the length, or find an input where it does.
+//! The programs are in the peephole AST, which any program can be lowered to without optimizing
+//! it, with [`peephole::lower`](../peephole/fn.lower.html). Only single-tape programs are
+//! supported. [`find_difference`](fn.find_difference.html) tries some longer random inputs
+//! first, and is what a [`PassManager`](../pass/struct.PassManager.html#method.set_validation)
+//! uses to validate each pass.
+//!
+//! ```
+//! use bf::ast;
+//! use bf::peephole;
+//! use bf::rle;
+//! use bf::symexec::{self, Limits, Verdict};
+//!
+//! let program = rle::compile(&ast::parse_program(b",[->++<]>.").unwrap());
+//! let verdict = symexec::check_equivalent(
+//!     &peephole::lower(&program),
+//!     &peephole::compile(&program),
+//!     &Limits::default(),
+//! );
+//! assert_eq!(verdict, Verdict::Equivalent);
+//! ```
+
+use std::fmt;
+use std::io::Cursor;
+
+use crate::common::{BfResult, Error, Instruction};
+use crate::conformance::Outcome;
+use crate::peephole::{Program, Statement};
+use crate::sandbox::{SandboxConfig, SandboxInterpretable};
+use crate::semantics::Eof;
+use crate::state::State;
+use crate::traits::IntoUsize;
+
+/// A byte in terms of the input: a constant plus a multiple of each input byte, modulo 256.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Value {
+    constant: u8,
+    /// The input bytes that the value depends on, in increasing order, with their nonzero
+    /// coefficients.
+    terms: Vec<(usize, u8)>,
+}
+
+impl Value {
+    /// A value that doesn't depend on the input.
+    pub fn constant(value: u8) -> Self {
+        Value {
+            constant: value,
+            terms: Vec::new(),
+        }
+    }
+
+    /// The input byte at the given index.
+    pub fn input(index: usize) -> Self {
+        Value {
+            constant: 0,
+            terms: vec![(index, 1)],
+        }
+    }
+
+    /// The value, if it doesn't depend on the input.
+    pub fn as_constant(&self) -> Option<u8> {
+        if self.terms.is_empty() {
+            Some(self.constant)
+        } else {
+            None
+        }
+    }
+
+    /// How many times the value counts the input byte at the given index.
+    pub fn coefficient(&self, index: usize) -> u8 {
+        self.terms
+            .iter()
+            .find(|&&(term, _)| term == index)
+            .map_or(0, |&(_, coefficient)| coefficient)
+    }
+
+    /// The value for the given input.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value depends on a byte past the end of `input`.
+    pub fn evaluate(&self, input: &[u8]) -> u8 {
+        self.terms
+            .iter()
+            .fold(self.constant, |sum, &(index, coefficient)| {
+                sum.wrapping_add(coefficient.wrapping_mul(input[index]))
+            })
+    }
+
+    fn add_constant(&mut self, amount: u8) {
+        self.constant = self.constant.wrapping_add(amount);
+    }
+
+    /// Adds `factor` times `other`.
+    fn add_scaled(&mut self, other: &Value, factor: u8) {
+        self.add_constant(other.constant.wrapping_mul(factor));
+        for &(index, coefficient) in &other.terms {
+            let coefficient = coefficient.wrapping_mul(factor);
+            match self.terms.binary_search_by_key(&index, |&(term, _)| term) {
+                Ok(position) => {
+                    let sum = self.terms[position].1.wrapping_add(coefficient);
+                    if sum == 0 {
+                        self.terms.remove(position);
+                    } else {
+                        self.terms[position].1 = sum;
+                    }
+                }
+                Err(position) if coefficient != 0 => {
+                    self.terms.insert(position, (index, coefficient));
+                }
+                Err(_) => (),
+            }
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, &(index, coefficient)) in self.terms.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" + ")?;
+            }
+            if coefficient != 1 {
+                write!(f, "{}*", coefficient)?;
+            }
+            write!(f, "in{}", index)?;
+        }
+
+        match (self.terms.is_empty(), self.constant) {
+            (false, 0) => Ok(()),
+            (false, constant) => write!(f, " + {}", constant),
+            (true, constant) => write!(f, "{}", constant),
+        }
+    }
+}
+
+/// How much of the program's behavior to explore.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Limits {
+    /// The longest input to consider. Every length up to this one is tried.
+    pub input: usize,
+    /// The number of cells of memory, which doesn't grow.
+    pub memory: usize,
+    /// What `,` stores at the end of input.
+    pub eof: Eof,
+    /// The most instructions and loop iterations to run on any path.
+    pub steps: usize,
+    /// The most paths to run. Each time a run splits counts as another.
+    pub paths: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            input: 2,
+            memory: 32,
+            eof: Eof::Zero,
+            steps: 10_000,
+            paths: 10_000,
+        }
+    }
+}
+
+/// Why symbolic execution couldn't finish.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Inconclusive {
+    /// A path ran more steps than the limit.
+    Steps,
+    /// There were more paths than the limit.
+    Paths,
+    /// The program switches tapes.
+    Tapes,
+    /// Two programs do different things on some path, symbolically, but no input that takes the
+    /// path showed the difference.
+    Unconfirmed,
+}
+
+impl fmt::Display for Inconclusive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Inconclusive::Steps => write!(f, "a path ran too many steps"),
+            Inconclusive::Paths => write!(f, "there were too many paths"),
+            Inconclusive::Tapes => write!(f, "the program switches tapes"),
+            Inconclusive::Unconfirmed => write!(f, "a difference could not be confirmed"),
+        }
+    }
+}
+
+/// The values that one byte of input can have on a path, as a bit for each.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Domain([u64; 4]);
+
+impl Domain {
+    /// Any value at all.
+    const ANY: Domain = Domain([u64::MAX; 4]);
+
+    /// Only the given value.
+    fn only(byte: u8) -> Self {
+        let mut result = Domain([0; 4]);
+        result.0[usize::from(byte / 64)] = 1 << (byte % 64);
+        result
+    }
+
+    fn admits(self, byte: u8) -> bool {
+        self.0[usize::from(byte / 64)] >> (byte % 64) & 1 != 0
+    }
+
+    fn remove(&mut self, byte: u8) {
+        self.0[usize::from(byte / 64)] &= !(1 << (byte % 64));
+    }
+
+    fn len(self) -> usize {
+        self.0.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    /// The value, if there is only one.
+    fn known(self) -> Option<u8> {
+        if self.len() == 1 {
+            self.values().next()
+        } else {
+            None
+        }
+    }
+
+    fn values(self) -> impl Iterator<Item = u8> {
+        (0..=255).filter(move |&byte| self.admits(byte))
+    }
+}
+
+/// One way that a program can run: the inputs that take it, and what it does on them.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Path {
+    domains: Vec<Domain>,
+    /// The bytes that the program writes, in terms of the input.
+    pub output: Vec<Value>,
+    /// Whether the program finishes or stops with an error.
+    pub result: BfResult<()>,
+}
+
+impl Path {
+    /// The length of the inputs that take this path.
+    pub fn input_len(&self) -> usize {
+        self.domains.len()
+    }
+
+    /// Whether the program takes this path on the given input.
+    pub fn admits(&self, input: &[u8]) -> bool {
+        input.len() == self.domains.len()
+            && self
+                .domains
+                .iter()
+                .zip(input)
+                .all(|(domain, &byte)| domain.admits(byte))
+    }
+
+    /// The first input, in order, that takes this path.
+    pub fn example(&self) -> Vec<u8> {
+        example(&self.domains)
+    }
+
+    /// The output for an input that takes this path.
+    pub fn output_for(&self, input: &[u8]) -> Vec<u8> {
+        self.output
+            .iter()
+            .map(|value| value.evaluate(input))
+            .collect()
+    }
+}
+
+/// Lists the ways that a program can run on inputs up to `limits.input` bytes long, with the
+/// memory starting zeroed.
+///
+/// # Errors
+///
+/// Why the limits stopped the exploration before it finished.
+pub fn explore(program: &Program, limits: &Limits) -> Result<Vec<Path>, Inconclusive> {
+    let mut work = Worklist::new(limits);
+    let mut paths = Vec::new();
+
+    while let Some(domains) = work.next()? {
+        match execute(program, &domains, limits) {
+            Ok((output, result)) => paths.push(Path {
+                domains,
+                output,
+                result,
+            }),
+            Err(Stop::Split(index, values)) => work.split(domains, index, values),
+            Err(Stop::GiveUp(reason)) => return Err(reason),
+            Err(Stop::Error(_)) => unreachable!("errors end the path"),
+        }
+    }
+
+    Ok(paths)
+}
+
+/// The result of comparing two programs.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Verdict {
+    /// The programs write the same bytes and stop the same way on every input up to the length.
+    Equivalent,
+    /// An input on which the programs differ.
+    Differ(Counterexample),
+    /// Why the programs couldn't be compared.
+    Inconclusive(Inconclusive),
+}
+
+/// An input on which two programs differ.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Counterexample {
+    /// The input.
+    pub input: Vec<u8>,
+    /// What the first program did.
+    pub expected: Outcome,
+    /// What the second program did.
+    pub actual: Outcome,
+}
+
+impl fmt::Display for Counterexample {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "on input {:?}, expected {:?} and {:?}, got {:?} and {:?}",
+            self.input,
+            self.expected.output,
+            self.expected.result,
+            self.actual.output,
+            self.actual.result
+        )
+    }
+}
+
+/// Checks whether two programs write the same bytes and stop the same way on every input up to
+/// `limits.input` bytes long.
+///
+/// A difference is always confirmed by running both programs on a concrete input, which becomes
+/// the counterexample.
+pub fn check_equivalent(expected: &Program, actual: &Program, limits: &Limits) -> Verdict {
+    let mut work = Worklist::new(limits);
+    let mut unconfirmed = false;
+
+    loop {
+        let domains = match work.next() {
+            Ok(Some(domains)) => domains,
+            Ok(None) => break,
+            Err(reason) => return Verdict::Inconclusive(reason),
+        };
+
+        let mut runs = Vec::with_capacity(2);
+        for program in [expected, actual] {
+            match execute(program, &domains, limits) {
+                Ok(run) => runs.push(run),
+                Err(Stop::Split(index, values)) => {
+                    work.split(domains.clone(), index, values);
+                    break;
+                }
+                Err(Stop::GiveUp(reason)) => return Verdict::Inconclusive(reason),
+                Err(Stop::Error(_)) => unreachable!("errors end the path"),
+            }
+        }
+
+        if runs.len() == 2 && !same_runs(&runs[0], &runs[1], &domains) {
+            match witness(expected, actual, &domains, limits) {
+                Some(counterexample) => return Verdict::Differ(counterexample),
+                None => unconfirmed = true,
+            }
+        }
+    }
+
+    if unconfirmed {
+        Verdict::Inconclusive(Inconclusive::Unconfirmed)
+    } else {
+        Verdict::Equivalent
+    }
+}
+
+/// Whether two runs do the same thing on every input that takes their path.
+fn same_runs(
+    (expected_output, expected_result): &(Vec<Value>, BfResult<()>),
+    (actual_output, actual_result): &(Vec<Value>, BfResult<()>),
+    domains: &[Domain],
+) -> bool {
+    expected_result == actual_result
+        && expected_output.len() == actual_output.len()
+        && expected_output
+            .iter()
+            .zip(actual_output)
+            .all(|(expected, actual)| agree(expected, actual, domains))
+}
+
+/// Whether two values are equal on every input allowed by `domains`. Values that differ in more
+/// than one input byte count as different, for the counterexample search to settle.
+fn agree(expected: &Value, actual: &Value, domains: &[Domain]) -> bool {
+    let mut difference = expected.clone();
+    difference.add_scaled(actual, 255);
+    match *difference.terms.as_slice() {
+        [] => difference.constant == 0,
+        [(index, coefficient)] => domains[index].values().all(|byte| {
+            difference
+                .constant
+                .wrapping_add(coefficient.wrapping_mul(byte))
+                == 0
+        }),
+        _ => false,
+    }
+}
+
+/// The number of random inputs that [`find_difference`](fn.find_difference.html) tries.
+const RANDOM_INPUTS: usize = 32;
+
+/// Looks for an input on which two programs differ: first among random inputs of up to four
+/// times `limits.input` bytes, and then among all inputs up to `limits.input` bytes, with
+/// [`check_equivalent`](fn.check_equivalent.html). Returns `None` if there is none, or if the
+/// limits stop the search before it finds one.
+///
+/// The random inputs are the same on every call. A run that reaches the step limit on one of them
+/// doesn't count, since an optimized program may take fewer steps.
+pub fn find_difference(
+    expected: &Program,
+    actual: &Program,
+    limits: &Limits,
+) -> Option<Counterexample> {
+    let mut random = XorShift(0x2545_f491_4f6c_dd1d);
+    for _ in 0..RANDOM_INPUTS {
+        let len = random.next() as usize % (4 * limits.input + 1);
+        let input: Vec<u8> = (0..len).map(|_| random.next() as u8).collect();
+        let domains: Vec<Domain> = input.iter().map(|&byte| Domain::only(byte)).collect();
+        // With every input byte known, a run doesn't split, and it runs faster here than in the
+        // interpreters, which analyze the program each time.
+        if let (Ok(expected_run), Ok(actual_run)) = (
+            execute(expected, &domains, limits),
+            execute(actual, &domains, limits),
+        ) && expected_run != actual_run
+            && let Some(counterexample) = confirm(expected, actual, input, limits)
+        {
+            return Some(counterexample);
+        }
+    }
+
+    match check_equivalent(expected, actual, limits) {
+        Verdict::Differ(counterexample) => Some(counterexample),
+        Verdict::Equivalent | Verdict::Inconclusive(_) => None,
+    }
+}
+
+/// A small pseudorandom generator, for inputs that are the same on every run.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// Runs a program on a concrete input, within the same limits.
+pub fn run_concrete(program: &Program, input: &[u8], limits: &Limits) -> Outcome {
+    let mut state = State::with_capacity(limits.memory);
+    state.set_eof(limits.eof);
+    let config = SandboxConfig {
+        max_steps: Some(limits.steps as u64),
+        ..SandboxConfig::default()
+    };
+    let mut output = Vec::new();
+    let result = program.interpret_sandboxed(&mut state, Cursor::new(input), &mut output, &config);
+    Outcome { output, result }
+}
+
+/// Looks for an input allowed by `domains` on which the programs differ, trying the first
+/// allowed input and then every allowed value of each byte in turn.
+fn witness(
+    expected: &Program,
+    actual: &Program,
+    domains: &[Domain],
+    limits: &Limits,
+) -> Option<Counterexample> {
+    let base = example(domains);
+    let variations = domains.iter().enumerate().flat_map(|(index, domain)| {
+        let base = base.clone();
+        domain.values().map(move |byte| {
+            let mut input = base.clone();
+            input[index] = byte;
+            input
+        })
+    });
+
+    std::iter::once(base.clone())
+        .chain(variations)
+        .find_map(|input| confirm(expected, actual, input, limits))
+}
+
+/// Runs both programs on `input` to check that they differ on it.
+fn confirm(
+    expected: &Program,
+    actual: &Program,
+    input: Vec<u8>,
+    limits: &Limits,
+) -> Option<Counterexample> {
+    let expected = run_concrete(expected, &input, limits);
+    let actual = run_concrete(actual, &input, limits);
+    if expected == actual {
+        None
+    } else {
+        Some(Counterexample {
+            input,
+            expected,
+            actual,
+        })
+    }
+}
+
+fn example(domains: &[Domain]) -> Vec<u8> {
+    domains
+        .iter()
+        .map(|domain| domain.values().next().expect("a domain admits some byte"))
+        .collect()
+}
+
+/// The assumptions about the input still to run, within the limit on paths.
+struct Worklist {
+    pending: Vec<Vec<Domain>>,
+    remaining: usize,
+}
+
+impl Worklist {
+    /// Starts with an input of each length, about which nothing is known.
+    fn new(limits: &Limits) -> Self {
+        Worklist {
+            pending: (0..=limits.input)
+                .rev()
+                .map(|len| vec![Domain::ANY; len])
+                .collect(),
+            remaining: limits.paths,
+        }
+    }
+
+    fn next(&mut self) -> Result<Option<Vec<Domain>>, Inconclusive> {
+        if self.pending.is_empty() {
+            return Ok(None);
+        }
+        self.remaining = self.remaining.checked_sub(1).ok_or(Inconclusive::Paths)?;
+        Ok(self.pending.pop())
+    }
+
+    /// Replaces a run with one for each of the given values of an input byte, and one for the
+    /// rest of its values, if there are any.
+    fn split(&mut self, domains: Vec<Domain>, index: usize, values: Vec<u8>) {
+        let mut rest = domains[index];
+        for &value in &values {
+            rest.remove(value);
+        }
+        if rest.len() > 0 {
+            let mut domains = domains.clone();
+            domains[index] = rest;
+            self.pending.push(domains);
+        }
+
+        for &value in values.iter().rev() {
+            let mut known = domains.clone();
+            known[index] = Domain::only(value);
+            self.pending.push(known);
+        }
+    }
+}
+
+/// Why a run stopped before the end of the program.
+enum Stop {
+    /// The program stopped with an error, which ends the path.
+    Error(Error),
+    /// The run needs a path for each of these values of the input byte at the index.
+    Split(usize, Vec<u8>),
+    GiveUp(Inconclusive),
+}
+
+impl From<Error> for Stop {
+    fn from(error: Error) -> Self {
+        Stop::Error(error)
+    }
+}
+
+/// Runs a program down the path that `domains` picks, returning what it wrote and how it
+/// stopped, or why the path has to split.
+fn execute(
+    program: &Program,
+    domains: &[Domain],
+    limits: &Limits,
+) -> Result<(Vec<Value>, BfResult<()>), Stop> {
+    let mut run = Run {
+        domains,
+        memory: vec![Value::default(); limits.memory],
+        pointer: 0,
+        read: 0,
+        output: Vec::new(),
+        steps: limits.steps,
+        eof: limits.eof,
+    };
+
+    let result = match run.run(program) {
+        Ok(()) => Ok(()),
+        Err(Stop::Error(error)) => Err(error),
+        Err(stop) => return Err(stop),
+    };
+    Ok((run.output, result))
+}
+
+struct Run<'a> {
+    domains: &'a [Domain],
+    memory: Vec<Value>,
+    pointer: usize,
+    /// The number of input bytes read.
+    read: usize,
+    output: Vec<Value>,
+    /// The steps left.
+    steps: usize,
+    eof: Eof,
+}
+
+impl Run<'_> {
+    fn run(&mut self, program: &Program) -> Result<(), Stop> {
+        for statement in program {
+            self.spend()?;
+            match *statement {
+                Statement::Instr(ref instruction) => self.step(instruction)?,
+                Statement::Loop(ref body) => {
+                    while !self.is_zero(self.pointer)? {
+                        self.spend()?;
+                        self.run(body)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn spend(&mut self) -> Result<(), Stop> {
+        self.steps = self
+            .steps
+            .checked_sub(1)
+            .ok_or(Stop::GiveUp(Inconclusive::Steps))?;
+        Ok(())
+    }
+
+    fn step(&mut self, instruction: &Instruction) -> Result<(), Stop> {
+        use crate::common::Instruction::*;
+
+        match *instruction {
+            Left(count) => self.pointer = self.address(-(count.into_usize() as isize))?,
+            Right(count) => self.pointer = self.address(count.into_usize() as isize)?,
+            Add(amount) => self.memory[self.pointer].add_constant(amount),
+            In => {
+                if self.read < self.domains.len() {
+                    // A byte with only one value is a constant, which keeps what uses it constant.
+                    self.memory[self.pointer] = match self.domains[self.read].known() {
+                        Some(value) => Value::constant(value),
+                        None => Value::input(self.read),
+                    };
+                    self.read += 1;
+                } else {
+                    match self.eof {
+                        Eof::Zero => self.memory[self.pointer] = Value::constant(0),
+                        Eof::Unchanged => (),
+                        Eof::MinusOne => self.memory[self.pointer] = Value::constant(255),
+                    }
+                }
+            }
+            Out => self.output.push(self.memory[self.pointer].clone()),
+            WriteStr(ref bytes) => self
+                .output
+                .extend(bytes.iter().map(|&byte| Value::constant(byte))),
+            SetZero => self.memory[self.pointer] = Value::constant(0),
+            SetConst(value) => self.memory[self.pointer] = Value::constant(value),
+            OffsetAddRight(offset) => self.move_to(offset.into_usize() as isize, 1, true)?,
+            OffsetAddLeft(offset) => self.move_to(-(offset.into_usize() as isize), 1, true)?,
+            MulAddRight(offset, factor) => {
+                self.move_to(offset.into_usize() as isize, factor, false)?
+            }
+            MulAddLeft(offset, factor) => {
+                self.move_to(-(offset.into_usize() as isize), factor, false)?
+            }
+            AddAt(offset, amount) => {
+                let address = self.address(offset)?;
+                self.memory[address].add_constant(amount);
+            }
+            SetAt(offset, value) => {
+                let address = self.address(offset)?;
+                self.memory[address] = Value::constant(value);
+            }
+            FindZeroRight(skip) => {
+                while !self.is_zero(self.pointer)? {
+                    self.spend()?;
+                    self.pointer = self.address(skip.into_usize() as isize)?;
+                }
+            }
+            FindZeroLeft(skip) => {
+                while !self.is_zero(self.pointer)? {
+                    self.spend()?;
+                    self.pointer = self.address(-(skip.into_usize() as isize))?;
+                }
+            }
+            SwitchTape(_) => return Err(Stop::GiveUp(Inconclusive::Tapes)),
+            JumpZero(_) | JumpNotZero(_) => panic!("unexpected jump instruction"),
+        }
+
+        Ok(())
+    }
+
+    /// Adds `factor` times the current byte at `offset`, clearing the current byte if `clear`.
+    /// Like the interpreters, this checks the offset only if the current byte isn't zero.
+    fn move_to(&mut self, offset: isize, factor: u8, clear: bool) -> Result<(), Stop> {
+        let address = match self.address(offset) {
+            Ok(address) => address,
+            Err(_) if self.is_zero(self.pointer)? => return Ok(()),
+            Err(stop) => return Err(stop),
+        };
+
+        let value = self.memory[self.pointer].clone();
+        if clear {
+            self.memory[self.pointer] = Value::constant(0);
+        }
+        self.memory[address].add_scaled(&value, factor);
+        Ok(())
+    }
+
+    /// The address at `offset` from the pointer, if it's in memory.
+    fn address(&self, offset: isize) -> Result<usize, Stop> {
+        match self.pointer.checked_add_signed(offset) {
+            Some(address) if address < self.memory.len() => Ok(address),
+            Some(_) => Err(Stop::Error(Error::PointerOverflow)),
+            None => Err(Stop::Error(Error::PointerUnderflow)),
+        }
+    }
+
+    /// Whether the byte at `address` is zero on this path, or how the path has to split to
+    /// know.
+    fn is_zero(&self, address: usize) -> Result<bool, Stop> {
+        let value = &self.memory[address];
+        let (index, coefficient) = match *value.terms.as_slice() {
+            [] => return Ok(value.constant == 0),
+            [term] => term,
+            // Make the first byte concrete, since the zeros of a sum aren't one byte's values.
+            [(index, _), ..] => {
+                return Err(Stop::Split(index, self.domains[index].values().collect()));
+            }
+        };
+
+        let domain = &self.domains[index];
+        let zeros: Vec<u8> = domain
+            .values()
+            .filter(|&byte| value.constant.wrapping_add(coefficient.wrapping_mul(byte)) == 0)
+            .collect();
+
+        if zeros.is_empty() {
+            Ok(false)
+        } else if zeros.len() == domain.len() {
+            Ok(true)
+        } else {
+            Err(Stop::Split(index, zeros))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peephole;
+    use crate::rle;
+    use crate::test_helpers::*;
+    use quickcheck::quickcheck;
+
+    #[test]
+    fn values_stay_in_terms_of_input() {
+        let paths = explore(&optimized(b",>,<[->++<]>+."), &Limits::default()).unwrap();
+        assert_eq!(paths.len(), 3);
+        let output: Vec<String> = paths[2].output.iter().map(Value::to_string).collect();
+        assert_eq!(output, ["2*in0 + in1 + 1"]);
+        assert_eq!(paths[2].output_for(b"\x03\x04"), [11]);
+        assert_eq!(paths[1].output[0].coefficient(0), 2);
+        assert_eq!(paths[0].output[0].as_constant(), Some(1));
+    }
+
+    #[test]
+    fn loops_split_paths() {
+        let paths = explore(&optimized(b",[.[-]]"), &limits(1)).unwrap();
+        assert_eq!(paths.len(), 3);
+        assert_eq!(paths[0].input_len(), 0);
+        assert!(paths[1].admits(b"\x00"));
+        assert!(!paths[1].admits(b"\x01"));
+        assert_eq!(paths[2].example(), b"\x01");
+        assert_eq!(paths[2].output, [Value::input(0)]);
+    }
+
+    #[test]
+    fn errors_end_paths() {
+        let limits = Limits {
+            memory: 3,
+            ..limits(1)
+        };
+        let paths = explore(&lowered(b",[>>>]<"), &limits).unwrap();
+        let results: Vec<_> = paths.iter().map(|path| path.result).collect();
+        assert_eq!(
+            results,
+            [
+                Err(Error::PointerUnderflow),
+                Err(Error::PointerUnderflow),
+                Err(Error::PointerOverflow),
+            ]
+        );
+    }
+
+    #[test]
+    fn limits_make_it_inconclusive() {
+        assert_eq!(
+            explore(&lowered(b"+[]"), &Limits::default()),
+            Err(Inconclusive::Steps)
+        );
+        let limits = Limits {
+            paths: 10,
+            ..Limits::default()
+        };
+        assert_eq!(
+            explore(&lowered(b",[-]"), &limits),
+            Err(Inconclusive::Paths)
+        );
+    }
+
+    #[test]
+    fn optimizer_is_equivalent() {
+        for src in [
+            &b",[->+<]>."[..],
+            b",>,<[->+>++<<]>.>.",
+            b",[.,]",
+            b",[>]<.",
+            b"+++[>+.<-]",
+            HELLO_WORLD_SRC,
+        ] {
+            let verdict = check_equivalent(&lowered(src), &optimized(src), &Limits::default());
+            assert_eq!(
+                verdict,
+                Verdict::Equivalent,
+                "{}",
+                String::from_utf8_lossy(src)
+            );
+        }
+    }
+
+    #[test]
+    fn finds_counterexamples() {
+        let verdict = check_equivalent(
+            &optimized(b",[->+<]>."),
+            &optimized(b",[->++<]>."),
+            &Limits::default(),
+        );
+        match verdict {
+            Verdict::Differ(counterexample) => {
+                assert_eq!(counterexample.input.len(), 1);
+                assert_ne!(counterexample.expected, counterexample.actual);
+            }
+            verdict => panic!("expected a counterexample, got {:?}", verdict),
+        }
+
+        let verdict = check_equivalent(&lowered(b",[-]"), &lowered(b",[-]."), &limits(1));
+        assert!(matches!(verdict, Verdict::Differ(ref c) if c.input.is_empty()));
+    }
+
+    #[test]
+    fn optimizer_is_equivalent_on_random_programs() {
+        fn prop(program: RandomProgram) -> bool {
+            let program = rle::compile(&program.0);
+            let limits = Limits {
+                memory: 16,
+                paths: 2_000,
+                ..limits(1)
+            };
+            let verdict = check_equivalent(
+                &peephole::lower(&program),
+                &peephole::compile(&program),
+                &limits,
+            );
+            !matches!(verdict, Verdict::Differ(_))
+        }
+
+        quickcheck(prop as fn(RandomProgram) -> bool);
+    }
+
+    fn limits(input: usize) -> Limits {
+        Limits {
+            input,
+            ..Limits::default()
+        }
+    }
+
+    fn lowered(src: &[u8]) -> Box<Program> {
+        peephole::lower(&rle::compile(&crate::ast::parse_program(src).unwrap()))
+    }
+
+    fn optimized(src: &[u8]) -> Box<Program> {
+        peephole::compile(&rle::compile(&crate::ast::parse_program(src).unwrap()))
+    }
+}
@@ -0,0 +1,185 @@
+//! The memory of one tape, on the heap or mapped from the operating system as
+//! [`TapeAlloc`](../state/enum.TapeAlloc.html) chooses.
+
+use std::fmt;
+use std::num::Wrapping;
+use std::ops::{Deref, DerefMut};
+
+use crate::state::TapeAlloc;
+
+/// The cells of a tape, which start at zero.
+pub(crate) struct Tape(Cells);
+
+enum Cells {
+    Heap(Box<[Wrapping<u8>]>),
+    #[cfg(unix)]
+    Mapped(Mapping),
+}
+
+impl Tape {
+    /// A tape of `len` zero cells, mapped if `alloc` asks for that and mapping works, and on the
+    /// heap otherwise.
+    pub(crate) fn new(len: usize, alloc: TapeAlloc) -> Self {
+        #[cfg(unix)]
+        if alloc != TapeAlloc::Heap
+            && let Some(mapping) = Mapping::new(len, alloc == TapeAlloc::HugePages)
+        {
+            return Tape(Cells::Mapped(mapping));
+        }
+        #[cfg(not(unix))]
+        let _ = alloc;
+        Tape(Cells::Heap(vec![Wrapping(0); len].into_boxed_slice()))
+    }
+
+    /// How the tape was allocated.
+    pub(crate) fn alloc(&self) -> TapeAlloc {
+        match self.0 {
+            Cells::Heap(_) => TapeAlloc::Heap,
+            #[cfg(unix)]
+            Cells::Mapped(ref mapping) if mapping.huge => TapeAlloc::HugePages,
+            #[cfg(unix)]
+            Cells::Mapped(_) => TapeAlloc::Lazy,
+        }
+    }
+
+    /// Sets every cell to zero. A mapped tape is mapped afresh, which gives back the pages that
+    /// the program touched instead of touching the rest.
+    pub(crate) fn zero(&mut self) {
+        #[cfg(unix)]
+        if let Cells::Mapped(_) = self.0 {
+            let fresh = Tape::new(self.len(), self.alloc());
+            if let Cells::Mapped(_) = fresh.0 {
+                *self = fresh;
+                return;
+            }
+        }
+        self.fill(Wrapping(0));
+    }
+
+    /// Makes the tape `len` cells long, keeping the cells it has and adding zeros.
+    pub(crate) fn resize(&mut self, len: usize) {
+        match self.0 {
+            Cells::Heap(ref mut cells) => {
+                let mut vec = std::mem::take(cells).into_vec();
+                vec.resize(len, Wrapping(0));
+                *cells = vec.into_boxed_slice();
+            }
+            #[cfg(unix)]
+            Cells::Mapped(_) => {
+                let mut resized = Tape::new(len, self.alloc());
+                let kept = len.min(self.len());
+                resized[..kept].copy_from_slice(&self[..kept]);
+                *self = resized;
+            }
+        }
+    }
+}
+
+impl From<Vec<u8>> for Tape {
+    fn from(cells: Vec<u8>) -> Self {
+        Tape(Cells::Heap(cells.into_iter().map(Wrapping).collect()))
+    }
+}
+
+impl Deref for Tape {
+    type Target = [Wrapping<u8>];
+
+    fn deref(&self) -> &Self::Target {
+        match self.0 {
+            Cells::Heap(ref cells) => cells,
+            #[cfg(unix)]
+            Cells::Mapped(ref mapping) => mapping.cells(),
+        }
+    }
+}
+
+impl DerefMut for Tape {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self.0 {
+            Cells::Heap(ref mut cells) => cells,
+            #[cfg(unix)]
+            Cells::Mapped(ref mut mapping) => mapping.cells_mut(),
+        }
+    }
+}
+
+impl Clone for Tape {
+    fn clone(&self) -> Self {
+        let mut result = Tape::new(self.len(), self.alloc());
+        result.copy_from_slice(self);
+        result
+    }
+}
+
+impl PartialEq for Tape {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for Tape {}
+
+impl fmt::Debug for Tape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+/// Anonymous memory from `mmap`, which the system commits a page at a time as it is touched.
+#[cfg(unix)]
+struct Mapping {
+    start: std::ptr::NonNull<Wrapping<u8>>,
+    len: usize,
+    /// Whether the system was asked for huge pages.
+    huge: bool,
+}
+
+// The mapping belongs to the tape alone, as a `Box` would.
+#[cfg(unix)]
+unsafe impl Send for Mapping {}
+#[cfg(unix)]
+unsafe impl Sync for Mapping {}
+
+#[cfg(unix)]
+impl Mapping {
+    /// Maps `len` bytes, or returns `None` if the system won't.
+    fn new(len: usize, huge: bool) -> Option<Self> {
+        if len == 0 {
+            return None;
+        }
+        let flags = libc::MAP_PRIVATE | libc::MAP_ANON;
+        // Reserving no swap lets a tape be bigger than the memory that is free.
+        #[cfg(target_os = "linux")]
+        let flags = flags | libc::MAP_NORESERVE;
+        let protection = libc::PROT_READ | libc::PROT_WRITE;
+        let start = unsafe { libc::mmap(std::ptr::null_mut(), len, protection, flags, -1, 0) };
+        if start == libc::MAP_FAILED {
+            return None;
+        }
+        // Huge pages are only a hint, which the system may ignore.
+        #[cfg(target_os = "linux")]
+        if huge {
+            unsafe { libc::madvise(start, len, libc::MADV_HUGEPAGE) };
+        }
+        Some(Mapping {
+            start: std::ptr::NonNull::new(start as *mut Wrapping<u8>)?,
+            len,
+            huge,
+        })
+    }
+
+    fn cells(&self) -> &[Wrapping<u8>] {
+        unsafe { std::slice::from_raw_parts(self.start.as_ptr(), self.len) }
+    }
+
+    fn cells_mut(&mut self) -> &mut [Wrapping<u8>] {
+        unsafe { std::slice::from_raw_parts_mut(self.start.as_ptr(), self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.start.as_ptr() as *mut libc::c_void, self.len) };
+    }
+}
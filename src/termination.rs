@@ -0,0 +1,346 @@
+//! Estimating whether a program stops, and how much memory it uses.
+//!
+//! [`analyze`](fn.analyze.html) sorts the loops of a program by whether they can be shown to
+//! stop, and bounds the number of cells that the program can use, which helps to pick the limits
+//! for running untrusted code in a [sandbox](../sandbox/index.html). A loop provably stops if it
+//! never runs, because the cell it tests is known to be zero, or if it is a scan such as `[>]`,
+//! which stops at a zero or at the end of memory, or if each pass through its body returns to
+//! where it began and adds the same odd amount to the cell there, so that the cell reaches zero
+//! within 256 passes. A loop whose body instead ends by reading input into that cell, as in
+//! `,[.,]`, is bounded by the input: it stops once the input runs out, as long as `,` stores zero
+//! at the end of input. A loop is only as sure to stop as the loops inside it.
+//!
+//! The bound on memory comes from the [bounds analysis](../analysis/index.html), which can't
+//! bound a program whose pointer moves by an amount that depends on the data, as a scan's does.
+//!
+//! In `bfi`, run `bfi analyze` to analyze a program.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use crate::analysis::Interval;
+use crate::ast::{ParseError, ParseOptions, Position};
+use crate::lint::{self, Node};
+
+/// Whether a loop can be shown to stop, from surest to least sure.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Termination {
+    /// The loop stops, whatever the input.
+    Terminates,
+    /// The loop stops once the input runs out, if `,` stores zero at the end of input.
+    BoundedByInput,
+    /// The analysis can't tell whether the loop stops.
+    Unknown,
+}
+
+impl fmt::Display for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Termination::Terminates => "provably terminating",
+            Termination::BoundedByInput => "bounded by input",
+            Termination::Unknown => "unknown",
+        })
+    }
+}
+
+/// A loop, with whether it can be shown to stop.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LoopReport {
+    /// The loop's `[`.
+    pub position: Position,
+    /// Whether it stops.
+    pub termination: Termination,
+}
+
+/// What the analysis found about a program.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Report {
+    /// Every loop, in the order they start in the source.
+    pub loops: Vec<LoopReport>,
+    /// The most cells that the program can use, if bounded.
+    pub memory: Option<usize>,
+}
+
+impl Report {
+    /// Whether the whole program can be shown to stop: the least sure of its loops, or
+    /// `Terminates` if it has none.
+    pub fn termination(&self) -> Termination {
+        self.loops
+            .iter()
+            .map(|report| report.termination)
+            .max()
+            .unwrap_or(Termination::Terminates)
+    }
+
+    /// The number of loops with the given classification.
+    pub fn count(&self, termination: Termination) -> usize {
+        self.loops
+            .iter()
+            .filter(|report| report.termination == termination)
+            .count()
+    }
+}
+
+/// Analyzes whether a program stops, and how much memory it uses.
+///
+/// # Errors
+///
+/// The same as [`parse_with_positions`](../ast/fn.parse_with_positions.html).
+pub fn analyze(src: &[u8], options: &ParseOptions) -> Result<Report, ParseError> {
+    crate::ast::parse_with_positions(src, options)?;
+    let program = lint::scan(src, options);
+
+    let mut analyzer = Analyzer {
+        loops: Vec::new(),
+        peak: Some(1),
+        invariants: HashMap::new(),
+    };
+    analyzer.sequence(&program, Interval::start(), Cells::START, true);
+
+    Ok(Report {
+        loops: analyzer
+            .loops
+            .into_iter()
+            .map(|(offset, termination)| LoopReport {
+                position: Position::new(src, offset),
+                termination,
+            })
+            .collect(),
+        memory: analyzer.peak,
+    })
+}
+
+/// What the analysis knows about the cells.
+#[derive(Clone, Copy, Debug)]
+struct Cells {
+    /// Whether the cell at the pointer is zero.
+    zero: bool,
+    /// Whether every cell is still zero.
+    pristine: bool,
+}
+
+impl Cells {
+    const START: Cells = Cells {
+        zero: true,
+        pristine: true,
+    };
+
+    const LOOP_BODY: Cells = Cells {
+        zero: false,
+        pristine: false,
+    };
+
+    const AFTER_LOOP: Cells = Cells {
+        zero: true,
+        pristine: false,
+    };
+}
+
+struct Analyzer {
+    /// The offset of each loop's `[` and its classification, in source order.
+    loops: Vec<(usize, Termination)>,
+    /// One more than the greatest address the pointer may have, or `None` if unbounded.
+    peak: Option<usize>,
+    /// Cached loop invariants, by the offset of the loop and the pointer on entry.
+    invariants: HashMap<(usize, Interval), Interval>,
+}
+
+impl Analyzer {
+    /// Analyzes a sequence of nodes, starting from the given pointer, and classifying its loops
+    /// if `report` is set. Returns the pointer after the sequence.
+    fn sequence(
+        &mut self,
+        nodes: &[Node],
+        mut pointer: Interval,
+        mut cells: Cells,
+        report: bool,
+    ) -> Interval {
+        use crate::common::Command::*;
+
+        for node in nodes {
+            match *node {
+                Node::Command(command, _) => {
+                    match command {
+                        Right => {
+                            pointer.move_right(1);
+                            cells.zero = cells.pristine;
+                        }
+                        Left => {
+                            pointer.move_left(1);
+                            cells.zero = cells.pristine;
+                        }
+                        SwitchTape => {
+                            pointer.switch_tape();
+                            cells.zero = cells.pristine;
+                        }
+                        Up | Down | In => cells = Cells::LOOP_BODY,
+                        Out => (),
+                        Begin | End => unreachable!("loops are nodes of their own"),
+                    }
+                    self.peak = self
+                        .peak
+                        .zip(pointer.high())
+                        .map(|(peak, high)| peak.max(high + 1));
+                }
+
+                Node::Loop(ref body, offset) => {
+                    if cells.zero {
+                        if report {
+                            self.never_runs(body, offset);
+                        }
+                        continue;
+                    }
+
+                    let invariant = self.invariant(body, offset, pointer);
+                    if report {
+                        let index = self.loops.len();
+                        self.loops.push((offset, Termination::Unknown));
+                        self.sequence(body, invariant, Cells::LOOP_BODY, true);
+                        let inner = self.loops[index + 1..].iter().map(|&(_, inner)| inner);
+                        self.loops[index].1 = inner.fold(classify(body), Ord::max);
+                    }
+
+                    pointer = invariant;
+                    cells = Cells::AFTER_LOOP;
+                }
+            }
+        }
+
+        pointer
+    }
+
+    /// Computes the pointer invariant at the head of a loop, given the pointer on entry, by
+    /// iterating the analysis of the body, with widening, until it reaches a fixed point.
+    fn invariant(&mut self, body: &[Node], offset: usize, entry: Interval) -> Interval {
+        if let Some(&invariant) = self.invariants.get(&(offset, entry)) {
+            return invariant;
+        }
+
+        let mut invariant = entry;
+        loop {
+            let after = self.sequence(body, invariant, Cells::LOOP_BODY, false);
+            let next = invariant.widen(after);
+            if next == invariant {
+                break;
+            }
+            invariant = next;
+        }
+
+        self.invariants.insert((offset, entry), invariant);
+        invariant
+    }
+
+    /// Records a loop that never runs, and the loops inside it, as terminating.
+    fn never_runs(&mut self, body: &[Node], offset: usize) {
+        self.loops.push((offset, Termination::Terminates));
+        for node in body {
+            if let Node::Loop(ref body, offset) = *node {
+                self.never_runs(body, offset);
+            }
+        }
+    }
+}
+
+/// Whether a loop stops, by the form of its body, assuming that the loops inside it stop.
+fn classify(body: &[Node]) -> Termination {
+    use crate::common::Command::*;
+
+    let is_move = |node: &Node| matches!(*node, Node::Command(Left | Right, _));
+    if body.iter().all(is_move) && lint::moves(body, 0, &mut BTreeSet::new()) != Some(0) {
+        return Termination::Terminates;
+    }
+
+    /// The writes to the cell that the loop tests.
+    enum Counter {
+        /// Only adds, of this much in all.
+        Adds(u8),
+        /// A read of input, with no add after it.
+        Input,
+        /// Anything else.
+        Mixed,
+    }
+
+    let mut here = 0isize;
+    let mut counter = Counter::Adds(0);
+    for node in body {
+        match *node {
+            Node::Command(Right, _) => here += 1,
+            Node::Command(Left, _) => here -= 1,
+            Node::Command(command @ (Up | Down), _) if here == 0 => {
+                let amount = if command == Up { 1 } else { u8::MAX };
+                counter = match counter {
+                    Counter::Adds(total) => Counter::Adds(total.wrapping_add(amount)),
+                    Counter::Input | Counter::Mixed => Counter::Mixed,
+                };
+            }
+            Node::Command(In, _) if here == 0 => counter = Counter::Input,
+            Node::Command(SwitchTape, _) => return Termination::Unknown,
+            Node::Command(..) => (),
+            Node::Loop(ref inner, _) => {
+                let mut writes = BTreeSet::new();
+                if lint::moves(inner, here, &mut writes) != Some(0) {
+                    return Termination::Unknown;
+                }
+                if writes.contains(&0) {
+                    counter = Counter::Mixed;
+                }
+            }
+        }
+    }
+
+    match counter {
+        _ if here != 0 => Termination::Unknown,
+        Counter::Adds(total) if total % 2 == 1 => Termination::Terminates,
+        Counter::Input => Termination::BoundedByInput,
+        _ => Termination::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    fn analyze(src: &[u8]) -> Report {
+        super::analyze(src, &ParseOptions::default()).unwrap()
+    }
+
+    fn classes(src: &[u8]) -> Vec<Termination> {
+        analyze(src)
+            .loops
+            .into_iter()
+            .map(|report| report.termination)
+            .collect()
+    }
+
+    #[test]
+    fn classifies_loops() {
+        use self::Termination::*;
+
+        assert_eq!(classes(b"+++[->++<]>[<+>---]"), [Terminates, Terminates]);
+        assert_eq!(classes(b",[.,]"), [BoundedByInput]);
+        assert_eq!(classes(b",[>[-]<[>+<--]]"), [Unknown, Terminates, Unknown]);
+        assert_eq!(classes(b",[[->+<],]"), [BoundedByInput, Terminates]);
+        assert_eq!(classes(b",[>]<[<]"), [Terminates, Terminates]);
+        assert_eq!(classes(b"+[>+<]"), [Unknown]);
+        // Loops that never run stop, whatever is in them.
+        assert_eq!(classes(b"[[]]+[-][]"), [Terminates; 4]);
+    }
+
+    #[test]
+    fn bounds_memory() {
+        assert_eq!(analyze(b"").memory, Some(1));
+        assert_eq!(analyze(b">>+<[->>+<<]>>.").memory, Some(4));
+        assert_eq!(analyze(b"+[>+]").memory, None);
+        assert_eq!(analyze(HELLO_WORLD_SRC).memory, Some(6));
+    }
+
+    #[test]
+    fn summarizes() {
+        let report = analyze(b",[.,]+++[-]");
+        assert_eq!(report.termination(), Termination::BoundedByInput);
+        assert_eq!(report.count(Termination::Terminates), 1);
+        assert_eq!(report.loops[1].position.column, 9);
+        assert_eq!(analyze(FACTOR_SRC).termination(), Termination::Unknown);
+    }
+}
@@ -1,13 +1,30 @@
 //! Helper definitions for testing both inside and outside (e.g., benches) the crate.
+//!
+//! Longer programs with expected output, which run on every backend, live in the corpus under
+//! `tests/corpus/`.
 
+use std::io::{self, Cursor};
 use std::str;
 
-use crate::common::BfResult;
+use crate::ast::{self, Statement};
+use crate::common::{BfResult, Command};
+use crate::state::State;
 use crate::traits::Interpretable;
 
 /// Source of the factoring program from `../bf/factor.bf`.
 pub const FACTOR_SRC: &[u8] = include_bytes!("../bf/factor.bf");
 
+/// Source of the Mandelbrot set renderer from `../bf/mandelbrot.bf`.
+pub const MANDELBROT_SRC: &[u8] = include_bytes!("../bf/mandelbrot.bf");
+
+/// Source of Daniel B. Cristofani’s Brainfuck self-interpreter, dbfi, from `../bf/dbfi.b`.
+///
+/// Its input is a program, then `!`, then the program’s input.
+pub const DBFI_SRC: &[u8] = include_bytes!("../bf/dbfi.b");
+
+/// Source of a quine from `../bf/quine.b`, which prints its own source, with nothing around it.
+pub const QUINE_SRC: &[u8] = include_bytes!("../bf/quine.b");
+
 /// Source of a “hello world” program.
 pub const HELLO_WORLD_SRC: &[u8] = b"++++++[>++++++++++++<-]>.\
       >++++++++++[>++++++++++<-]>+.\
@@ -15,6 +32,34 @@ pub const HELLO_WORLD_SRC: &[u8] = b"++++++[>++++++++++++<-]>.\
       <+++[>----<-]>.<<<<<+++[>+++++<-]>.\
       >>.+++.------.--------.>>+.";
 
+/// Source of a program for the multi-tape extension, which prints `ABB` when run with two tapes.
+///
+/// It leaves `A` on the first tape and `B` on the second, each one cell to the right, and then
+/// prints from the first tape, the second, and after two switches, the second again.
+pub const MULTI_TAPE_SRC: &[u8] = b"++++++++[>++++++++<-]>+\
+      @++++++++[>++++++++<-]>++\
+      @.@.@@.";
+
+/// Parses `src` with the multi-tape extension enabled.
+pub fn parse_multi_tape(src: &[u8]) -> Box<ast::Program> {
+    let options = ast::ParseOptions {
+        multi_tape: true,
+        ..ast::ParseOptions::default()
+    };
+    ast::parse_program_with(src, &options).unwrap()
+}
+
+/// Interprets `program` on a state with `tapes` tapes, asserting that its output is `output`.
+pub fn assert_interpret_tapes<I>(program: &I, tapes: usize, input: &[u8], output: &[u8])
+where
+    I: Interpretable + ?Sized,
+{
+    let mut state = State::with_tapes(100, tapes);
+    let mut actual = Vec::new();
+    program.interpret_in(&mut state, input, &mut actual).unwrap();
+    assert_eq!(actual, output);
+}
+
 /// Interprets `program`, giving it input `input`, and asserting that its output is `output`.
 pub fn assert_interpret<I: Interpretable + ?Sized>(program: &I, input: &[u8], output: &[u8]) {
     assert_interpret_result(program, input, Ok(output));
@@ -31,3 +76,158 @@ where
 
     assert_eq!(actual, expected);
 }
+
+/// Runs `program` for at most `fuel` commands, returning whether it stopped, either by finishing
+/// or with an error.
+///
+/// Property tests use this to skip random programs that loop forever.
+pub fn halts_within(program: &ast::Program, size: usize, input: &[u8], fuel: usize) -> bool {
+    let mut state = State::with_capacity(size);
+    let mut input = Cursor::new(input);
+    let mut fuel = fuel;
+    halts(program, &mut state, &mut input, &mut fuel)
+}
+
+fn halts(
+    program: &ast::Program,
+    state: &mut State,
+    input: &mut Cursor<&[u8]>,
+    fuel: &mut usize,
+) -> bool {
+    for statement in program {
+        if *fuel == 0 {
+            return false;
+        }
+        *fuel -= 1;
+
+        let result = match *statement {
+            Statement::Cmd(Command::Left) => state.left(1usize),
+            Statement::Cmd(Command::Right) => state.right(1usize),
+            Statement::Cmd(Command::Up) => state.up(1u8),
+            Statement::Cmd(Command::Down) => state.down(1u8),
+            Statement::Cmd(Command::In) => {
+                state.read(input);
+                Ok(())
+            }
+            Statement::Cmd(Command::Out) => {
+                state.write(&mut io::sink());
+                Ok(())
+            }
+            Statement::Cmd(Command::SwitchTape) => {
+                state.switch_tape(1usize);
+                Ok(())
+            }
+            Statement::Cmd(Command::Begin) | Statement::Cmd(Command::End) => {
+                panic!("Invalid instruction: Begin or End")
+            }
+            Statement::Loop(ref body) => {
+                while state.load() != 0 {
+                    if !halts(body, state, input, fuel) {
+                        return false;
+                    }
+                    if *fuel == 0 {
+                        return false;
+                    }
+                    *fuel -= 1;
+                }
+                Ok(())
+            }
+        };
+
+        // An error stops the program, which counts as halting.
+        if result.is_err() {
+            return true;
+        }
+    }
+
+    true
+}
+
+/// A random, well-bracketed Brainfuck program, for property tests.
+///
+/// The generated programs favor the loop shapes that the peephole optimizer recognizes, and
+/// shrink by deleting statements and by replacing loops with their bodies.
+#[cfg(test)]
+#[derive(Clone, Debug)]
+pub struct RandomProgram(pub Box<ast::Program>);
+
+#[cfg(test)]
+mod random {
+    use quickcheck::{Arbitrary, Gen};
+
+    use super::*;
+
+    const MAX_DEPTH: usize = 3;
+
+    const COMMANDS: &[Command] = &[
+        Command::Left,
+        Command::Right,
+        Command::Up,
+        Command::Down,
+        Command::In,
+        Command::Out,
+    ];
+
+    /// Loop bodies that the peephole optimizer replaces.
+    const PEEPHOLES: &[&[u8]] = &[
+        b"-",
+        b"+",
+        b">",
+        b"<<",
+        b"->+<",
+        b"-<<+>>",
+        b"->>>-<<<",
+        b"->++>>+++<<<",
+        b"<++>->>-<",
+    ];
+
+    impl Arbitrary for RandomProgram {
+        fn arbitrary(g: &mut Gen) -> Self {
+            RandomProgram(program(g, 0))
+        }
+
+        fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+            Box::new(shrink(&self.0).into_iter().map(RandomProgram))
+        }
+    }
+
+    fn program(g: &mut Gen, depth: usize) -> Box<ast::Program> {
+        let len = usize::arbitrary(g) % (g.size() / (depth + 1) + 1);
+        (0..len).map(|_| statement(g, depth)).collect()
+    }
+
+    fn statement(g: &mut Gen, depth: usize) -> Statement {
+        match u8::arbitrary(g) % 16 {
+            0 | 1 if depth < MAX_DEPTH => Statement::Loop(program(g, depth + 1)),
+            2 => {
+                let body = g.choose(PEEPHOLES).unwrap();
+                Statement::Loop(ast::parse_program(body).unwrap())
+            }
+            _ => Statement::Cmd(*g.choose(COMMANDS).unwrap()),
+        }
+    }
+
+    fn shrink(program: &ast::Program) -> Vec<Box<ast::Program>> {
+        let mut result = Vec::new();
+
+        for (i, statement) in program.iter().enumerate() {
+            let mut without = program.to_vec();
+            without.remove(i);
+            result.push(without.clone().into_boxed_slice());
+
+            if let Statement::Loop(ref body) = *statement {
+                let mut spliced = without.clone();
+                spliced.splice(i..i, body.iter().cloned());
+                result.push(spliced.into_boxed_slice());
+
+                for smaller in shrink(body) {
+                    let mut replaced = program.to_vec();
+                    replaced[i] = Statement::Loop(smaller);
+                    result.push(replaced.into_boxed_slice());
+                }
+            }
+        }
+
+        result
+    }
+}
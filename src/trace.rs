@@ -0,0 +1,213 @@
+//! Tracing what a program does, one instruction at a time.
+//!
+//! [`trace`](fn.trace.html) runs a bytecode program and writes an [`Event`](struct.Event.html)
+//! for each instruction to a sink, in [JSON Lines](https://jsonlines.org/) format, so that
+//! other tools can draw or analyze what the program did. Each line is an object such as
+//!
+//! ```text
+//! {"step":12,"pc":4,"op":"Add(3)","ptr":1,"before":0,"after":3}
+//! ```
+//!
+//! which says that the thirteenth instruction to run was `Add(3)` at address 4, with the pointer
+//! at cell 1, and that it changed the cell from 0 to 3. `before` and `after` are always the cell
+//! that the pointer was at before the instruction, even if it moves the pointer or writes some
+//! other cell. They are negative for a byte of 128 or more if the state's cells are
+//! [signed](../state/struct.State.html#method.signed).
+//!
+//! A long run produces a great many events, so [`TraceOptions`](struct.TraceOptions.html) can
+//! keep only every so many, or stop after a limit.
+//!
+//! In `bfi`, pass `--trace FILE` to trace a run.
+
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+
+use crate::bytecode;
+use crate::common::{BfResult, Instruction};
+use crate::debugger::{Debugger, Stop};
+use crate::graph::write_json_string;
+use crate::state::State;
+
+/// Which instructions to trace.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TraceOptions {
+    /// Trace only the instructions whose step numbers are multiples of this, which must not be
+    /// zero. The default of 1 traces every instruction.
+    pub every: u64,
+    /// The most events to write, after which the program runs on untraced.
+    pub limit: Option<u64>,
+}
+
+impl Default for TraceOptions {
+    fn default() -> Self {
+        TraceOptions {
+            every: 1,
+            limit: None,
+        }
+    }
+}
+
+/// An instruction that ran.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Event {
+    /// How many instructions ran before this one.
+    pub step: u64,
+    /// The address of the instruction.
+    pub pc: usize,
+    /// The instruction.
+    pub instruction: Instruction,
+    /// The position of the pointer before the instruction.
+    pub pointer: usize,
+    /// The value of the cell at `pointer` before the instruction, as the state's
+    /// [`cell_value`](../state/struct.State.html#method.cell_value) reads it.
+    pub before: i16,
+    /// The value of the cell at `pointer` after the instruction.
+    pub after: i16,
+}
+
+impl Event {
+    /// The event as a JSON object on one line.
+    pub fn to_json(&self) -> String {
+        let mut result = format!("{{\"step\":{},\"pc\":{},\"op\":", self.step, self.pc);
+        write_json_string(&mut result, &format!("{:?}", self.instruction));
+        let _ = write!(
+            result,
+            ",\"ptr\":{},\"before\":{},\"after\":{}}}",
+            self.pointer, self.before, self.after
+        );
+        result
+    }
+}
+
+/// Runs a bytecode program, writing an event for each traced instruction to `sink`, one per
+/// line.
+///
+/// # Errors
+///
+/// Returns `Err` if writing to the sink fails, or else `Ok` of the result of the run.
+///
+/// # Panics
+///
+/// Panics if `options.every` is zero, or if the program doesn't
+/// [verify](../bytecode/fn.verify.html).
+pub fn trace<R, W, T>(
+    program: Box<bytecode::Program>,
+    state: State,
+    input: R,
+    output: W,
+    mut sink: T,
+    options: &TraceOptions,
+) -> io::Result<BfResult<()>>
+where
+    R: Read,
+    W: Write,
+    T: Write,
+{
+    assert_ne!(options.every, 0, "cannot trace every 0th instruction");
+
+    let mut debugger = Debugger::new(program, state, input, output);
+    let mut events = 0;
+
+    loop {
+        let step = debugger.steps();
+        let traced =
+            step.is_multiple_of(options.every) && options.limit.is_none_or(|limit| events < limit);
+        let pc = debugger.pc();
+        let pointer = debugger.state().pointer();
+        let before = debugger.state().cell_value(debugger.state().load());
+        let instruction = debugger.program().get(pc).cloned();
+
+        match debugger.step() {
+            Some(Stop::Halted) => break,
+            Some(Stop::Error(error)) => {
+                sink.flush()?;
+                return Ok(Err(error));
+            }
+            // There are no breakpoints or watchpoints.
+            _ => {}
+        }
+
+        if traced && let Some(instruction) = instruction {
+            let event = Event {
+                step,
+                pc,
+                instruction,
+                pointer,
+                before,
+                after: debugger
+                    .state()
+                    .cell_value(debugger.state().as_bytes()[pointer]),
+            };
+            writeln!(sink, "{}", event.to_json())?;
+            events += 1;
+        }
+    }
+
+    sink.flush()?;
+    Ok(Ok(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast;
+    use crate::common::Error;
+    use crate::traits::*;
+
+    fn run(src: &[u8], options: &TraceOptions) -> (BfResult<()>, Vec<String>) {
+        run_in(src, State::new(), options)
+    }
+
+    fn run_in(src: &[u8], state: State, options: &TraceOptions) -> (BfResult<()>, Vec<String>) {
+        let program = ast::parse_program(src).unwrap().bytecode_compile();
+        let mut sink = Vec::new();
+        let result = trace(program, state, &b""[..], io::sink(), &mut sink, options);
+        let lines = String::from_utf8(sink).unwrap();
+        (result.unwrap(), lines.lines().map(str::to_owned).collect())
+    }
+
+    #[test]
+    fn writes_an_event_for_each_instruction() {
+        let (result, lines) = run(b"+++>+<.", &TraceOptions::default());
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            lines,
+            [
+                r#"{"step":0,"pc":0,"op":"Add(3)","ptr":0,"before":0,"after":3}"#,
+                r#"{"step":1,"pc":1,"op":"AddAt(1, 1)","ptr":0,"before":3,"after":3}"#,
+                r#"{"step":2,"pc":2,"op":"Out","ptr":0,"before":3,"after":3}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn shows_signed_cells_as_negative() {
+        let mut state = State::new();
+        state.set_signed(true);
+        let (result, lines) = run_in(b"-.", state, &TraceOptions::default());
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            lines[0],
+            r#"{"step":0,"pc":0,"op":"Add(255)","ptr":0,"before":0,"after":-1}"#
+        );
+    }
+
+    #[test]
+    fn samples_and_limits() {
+        let options = TraceOptions {
+            every: 100,
+            limit: Some(3),
+        };
+        let (result, lines) = run(b"+[>+<+]", &options);
+        assert_eq!(result, Ok(()));
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with(r#"{"step":100,"#), "{}", lines[1]);
+    }
+
+    #[test]
+    fn stops_at_errors() {
+        let (result, lines) = run(b"+<", &TraceOptions::default());
+        assert_eq!(result, Err(Error::PointerUnderflow));
+        assert_eq!(lines.len(), 1);
+    }
+}
@@ -1,26 +1,64 @@
 //! Contains the Interpretable trait, which provides a common interface for running a Brainfuck
-//! program.
+//! program, and its object-safe form DynProgram, the ToSource trait, which prints a program back
+//! as Brainfuck, and the CompileTo trait, which compiles a program to any later form.
 
 use std::io::{Cursor, Read, Write, stdin, stdout};
 
 use crate::common::BfResult;
 use crate::state::State;
 
+pub use crate::batch::RunBatch;
 pub use crate::bytecode::BytecodeCompilable;
+pub use crate::bytecode::packed::PackedCompilable;
+pub use crate::bytecode::threaded::ThreadedCompilable;
+pub use crate::counters::CountingInterpretable;
 #[cfg(feature = "cranelift")]
 pub use crate::cranelift::CraneliftCompilable;
+pub use crate::fused::FusedCompilable;
 #[cfg(feature = "jit")]
 pub use crate::jit::JitCompilable;
 #[cfg(feature = "llvm")]
 pub use crate::llvm::LlvmCompilable;
 pub use crate::peephole::PeepholeCompilable;
 pub use crate::rle::RleCompilable;
+pub use crate::sandbox::SandboxInterpretable;
 
 /// Program forms that can be interpreted.
 pub trait Interpretable {
+    /// Interprets a program against the given state, leaving the final state in place.
+    ///
+    /// A state can be reused across runs by [resetting](../state/struct.State.html#method.reset)
+    /// it in between, which avoids allocating new memory each time.
+    fn interpret_in<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        input: R,
+        output: W,
+    ) -> BfResult<()>;
+
     /// Interprets a program against the given state.
-    fn interpret_state<R: Read, W: Write>(&self, state: State, input: R, output: W)
-    -> BfResult<()>;
+    fn interpret_state<R: Read, W: Write>(
+        &self,
+        mut state: State,
+        input: R,
+        output: W,
+    ) -> BfResult<()> {
+        self.interpret_in(&mut state, input, output)
+    }
+
+    /// Interprets a program against the given state, returning the final state, so that the
+    /// tape can serve as the program's result.
+    ///
+    /// Compiled code copies its final pointer back into the state, too.
+    fn interpret_into_state<R: Read, W: Write>(
+        &self,
+        mut state: State,
+        input: R,
+        output: W,
+    ) -> BfResult<State> {
+        self.interpret_in(&mut state, input, output)?;
+        Ok(state)
+    }
 
     /// Interprets a program. If the given `size` is `None`, the default memory size.
     fn interpret<R: Read, W: Write>(
@@ -48,6 +86,145 @@ pub trait Interpretable {
     }
 }
 
+impl<P: Interpretable + ?Sized> Interpretable for Box<P> {
+    fn interpret_in<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        input: R,
+        output: W,
+    ) -> BfResult<()> {
+        (**self).interpret_in(state, input, output)
+    }
+}
+
+/// Programs that can be run through a trait object.
+///
+/// [`Interpretable`](trait.Interpretable.html) is generic over the input and output, so its
+/// programs can't be held as `dyn Interpretable`. Every interpretable program is a
+/// `DynProgram`, though, so programs of different forms can be held together as
+/// `Box<dyn DynProgram>`, which is itself interpretable.
+///
+/// ```
+/// use bf::ast;
+/// use bf::traits::*;
+///
+/// let program = ast::parse_program(b"++++++[>++++++++<-]>+.").unwrap();
+/// let programs: Vec<Box<dyn DynProgram>> = vec![
+///     Box::new(program.rle_compile()),
+///     Box::new(program.bytecode_compile()),
+/// ];
+/// for program in &programs {
+///     let mut output = Vec::new();
+///     program.run(&mut &b""[..], &mut output).unwrap();
+///     assert_eq!(output, b"1");
+/// }
+/// ```
+pub trait DynProgram {
+    /// Runs the program against the given state, leaving the final state in place.
+    fn run_in(
+        &self,
+        state: &mut State,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> BfResult<()>;
+
+    /// Runs the program with a new state of the default memory size.
+    fn run(&self, input: &mut dyn Read, output: &mut dyn Write) -> BfResult<()> {
+        self.run_in(&mut State::new(), input, output)
+    }
+}
+
+impl<P: Interpretable + ?Sized> DynProgram for P {
+    fn run_in(
+        &self,
+        state: &mut State,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> BfResult<()> {
+        self.interpret_in(state, input, output)
+    }
+}
+
+impl Interpretable for Box<dyn DynProgram> {
+    fn interpret_in<R: Read, W: Write>(
+        &self,
+        state: &mut State,
+        mut input: R,
+        mut output: W,
+    ) -> BfResult<()> {
+        (**self).run_in(state, &mut input, &mut output)
+    }
+}
+
+/// Program forms that can be printed back as Brainfuck source.
+pub trait ToSource {
+    /// Appends the Brainfuck source for the program to `out`.
+    fn write_source(&self, out: &mut String);
+
+    /// The Brainfuck source for the program.
+    ///
+    /// Instructions that aren't Brainfuck are expanded to commands that do the same thing, so
+    /// the source of an optimized program runs on any Brainfuck implementation.
+    fn to_source(&self) -> String {
+        let mut result = String::new();
+        self.write_source(&mut result);
+        result
+    }
+}
+
+impl<P: ToSource + ?Sized> ToSource for Box<P> {
+    fn write_source(&self, out: &mut String) {
+        (**self).write_source(out)
+    }
+}
+
+/// A program form that other forms compile to, such as `rle::Program` or `jit::Program`.
+pub trait Stage {
+    /// What holds a compiled program of this form, which is a `Box` for the forms that are
+    /// slices.
+    type Compiled;
+}
+
+/// Program forms that can be compiled to the form `Target`, through whichever stages come in
+/// between.
+///
+/// Each stage implements this for every form that its own compiler trait accepts, so a new
+/// backend slots in with a [`Stage`](trait.Stage.html) and one implementation of `CompileTo`,
+/// and every earlier form then compiles to it with [`compile`](trait.Compile.html#method.compile).
+///
+/// ```
+/// use bf::{ast, bytecode, fused, rle};
+/// use bf::traits::*;
+///
+/// let program = ast::parse_program(b"++[->+<]").unwrap();
+/// let rle = program.compile::<rle::Program>();
+/// let bytecode = rle.compile::<bytecode::Program>();
+/// assert_eq!(bytecode, program.bytecode_compile());
+/// assert_eq!(
+///     program.compile::<fused::Program>().interpret_memory(None, b""),
+///     Ok(vec![])
+/// );
+/// ```
+pub trait CompileTo<Target: Stage + ?Sized> {
+    /// Compile the given program to `Target`.
+    fn compile_to(&self) -> Target::Compiled;
+}
+
+/// Compiles a program to the form named by its type parameter, as
+/// `program.compile::<jit::Program>()`.
+///
+/// Each form implements this for its own program type, so that a boxed program compiles like the
+/// program in the box.
+pub trait Compile {
+    /// Compile the given program to `Target`.
+    fn compile<Target: Stage + ?Sized>(&self) -> Target::Compiled
+    where
+        Self: CompileTo<Target>,
+    {
+        self.compile_to()
+    }
+}
+
 /// For converting smaller numeric types into `usize`.
 pub trait IntoUsize {
     fn into_usize(self) -> usize;
@@ -0,0 +1,72 @@
+//! JavaScript bindings, for running Brainfuck in the browser.
+//!
+//! Enabled with `--features=wasm`. The JIT backends don't target WebAssembly, so machines run
+//! the [peephole-optimized AST](../peephole/index.html). To build a module for
+//! [`wasm-bindgen`](https://rustwasm.github.io/docs/wasm-bindgen/):
+//!
+//! ```text
+//! cargo rustc --lib --release --target wasm32-unknown-unknown --features wasm --crate-type cdylib
+//! wasm-bindgen --target web target/wasm32-unknown-unknown/release/bf.wasm --out-dir pkg
+//! ```
+//!
+//! Then, from JavaScript:
+//!
+//! ```text
+//! const machine = new BfMachine(",[.,]");
+//! machine.run(new TextEncoder().encode("hello"));
+//! ```
+
+use wasm_bindgen::prelude::*;
+
+use crate::peephole;
+use crate::rle;
+use crate::traits::*;
+
+/// A compiled Brainfuck program.
+#[wasm_bindgen]
+pub struct BfMachine {
+    program: Box<peephole::Program>,
+    memory_size: usize,
+}
+
+#[wasm_bindgen]
+impl BfMachine {
+    /// Parses and compiles a program, throwing if it doesn't parse.
+    #[wasm_bindgen(constructor)]
+    pub fn new(src: &str) -> Result<BfMachine, JsError> {
+        let program = rle::parse_program(src.as_bytes())
+            .map_err(|e| JsError::new(&format!("syntax error: {}", e)))?;
+
+        Ok(BfMachine {
+            program: program.peephole_compile(),
+            memory_size: 30_000,
+        })
+    }
+
+    /// The memory size, in bytes, for subsequent runs.
+    #[wasm_bindgen(getter = memorySize)]
+    pub fn memory_size(&self) -> usize {
+        self.memory_size
+    }
+
+    /// Sets the memory size, in bytes, for subsequent runs.
+    #[wasm_bindgen(setter = memorySize)]
+    pub fn set_memory_size(&mut self, memory_size: usize) {
+        self.memory_size = memory_size.max(1);
+    }
+
+    /// Runs the program on the given input, returning its output and throwing on a run-time
+    /// error.
+    pub fn run(&self, input: &[u8]) -> Result<Vec<u8>, JsError> {
+        self.program
+            .interpret_memory(Some(self.memory_size), input)
+            .map_err(|e| JsError::new(&format!("runtime error: {}", e)))
+    }
+
+    /// Runs the program on a string, returning its output as a string.
+    #[wasm_bindgen(js_name = runString)]
+    pub fn run_string(&self, input: &str) -> Result<String, JsError> {
+        let output = self.run(input.as_bytes())?;
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+}
@@ -0,0 +1,330 @@
+//! x86-64 machine code for the executable writers, [ELF](../elf/index.html) and
+//! [PE](../pe/index.html).
+//!
+//! [`compile`](fn.compile.html) lowers the peephole AST to code that keeps the pointer in `r12`
+//! and the ends of the memory in `r13` and `r14`, and calls a [`Runtime`](struct.Runtime.html)
+//! for each move and for I/O. The moves are the same on every system; each writer supplies the
+//! I/O, the start of the program, and how it exits.
+
+use crate::common::Error;
+use crate::peephole;
+
+/// A place in the code, which may not be known yet.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Label(usize);
+
+/// Machine code as it is encoded, with the jumps to labels that aren't bound yet.
+pub(crate) struct Code {
+    bytes: Vec<u8>,
+    /// Where each label is, once it is bound.
+    labels: Vec<Option<usize>>,
+    /// The 32-bit displacements to fill in, and the labels they are to.
+    fixups: Vec<(usize, Label)>,
+}
+
+impl Code {
+    pub(crate) fn new() -> Self {
+        Code {
+            bytes: Vec::new(),
+            labels: Vec::new(),
+            fixups: Vec::new(),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub(crate) fn op(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub(crate) fn imm32(&mut self, value: u32) {
+        self.op(&value.to_le_bytes());
+    }
+
+    /// Appends a 64-bit immediate, and returns where it is.
+    pub(crate) fn imm64(&mut self, value: u64) -> usize {
+        self.op(&value.to_le_bytes());
+        self.bytes.len() - 8
+    }
+
+    pub(crate) fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// Binds `label` to the end of the code so far.
+    pub(crate) fn bind(&mut self, label: Label) {
+        let at = self.bytes.len();
+        self.bind_at(label, at);
+    }
+
+    /// Binds `label` to `at` bytes from the start of the code, which may be past its end, for
+    /// data that is loaded after the code.
+    pub(crate) fn bind_at(&mut self, label: Label, at: usize) {
+        debug_assert!(self.labels[label.0].is_none(), "label bound twice");
+        self.labels[label.0] = Some(at);
+    }
+
+    /// Appends `opcode` and a 32-bit displacement from the end of the instruction to `label`,
+    /// for a jump, a call, or a RIP-relative address.
+    pub(crate) fn jump(&mut self, opcode: &[u8], label: Label) {
+        self.op(opcode);
+        self.fixups.push((self.bytes.len(), label));
+        self.imm32(0);
+    }
+
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        for (at, label) in self.fixups {
+            let target = self.labels[label.0].expect("every label is bound");
+            let displacement = target as i64 - (at as i64 + 4);
+            let displacement = i32::try_from(displacement).expect("code is smaller than 2 GiB");
+            self.bytes[at..at + 4].copy_from_slice(&displacement.to_le_bytes());
+        }
+        self.bytes
+    }
+}
+
+/// The functions that the program calls. Each keeps `r12` to `r14` as it finds them, except those
+/// that move the pointer, and keeps `rcx` except those that do I/O.
+pub(crate) struct Runtime {
+    /// Moves the pointer right by `rax`.
+    right: Label,
+    /// Moves the pointer left by `rax`.
+    left: Label,
+    /// Checks that the pointer can move right by `rax`.
+    check_right: Label,
+    /// Checks that the pointer can move left by `rax`, and negates `rax`.
+    check_left: Label,
+    /// Reads a byte into the cell, or stores 0 at the end of input.
+    pub(crate) read: Label,
+    /// Writes the cell.
+    pub(crate) out: Label,
+    /// Writes the `rdx` bytes at `rsi`.
+    pub(crate) write: Label,
+    underflow: Label,
+    overflow: Label,
+}
+
+impl Runtime {
+    pub(crate) fn new(code: &mut Code) -> Self {
+        Runtime {
+            right: code.new_label(),
+            left: code.new_label(),
+            check_right: code.new_label(),
+            check_left: code.new_label(),
+            read: code.new_label(),
+            out: code.new_label(),
+            write: code.new_label(),
+            underflow: code.new_label(),
+            overflow: code.new_label(),
+        }
+    }
+
+    /// Emits the functions that move the pointer and check the moves. `read`, `out`, and `write`
+    /// are the writer's to emit.
+    pub(crate) fn emit_moves(&self, code: &mut Code) {
+        code.bind(self.right);
+        code.jump(&[0xE8], self.check_right); // call bf_check_right
+        code.op(&[0x49, 0x01, 0xC4]); // add r12, rax
+        code.op(&[0xC3]); // ret
+
+        code.bind(self.left);
+        code.jump(&[0xE8], self.check_left); // call bf_check_left
+        code.op(&[0x49, 0x01, 0xC4]); // add r12, rax
+        code.op(&[0xC3]); // ret
+
+        code.bind(self.check_right);
+        code.op(&[0x4C, 0x89, 0xF2]); // mov rdx, r14
+        code.op(&[0x4C, 0x29, 0xE2]); // sub rdx, r12
+        code.op(&[0x48, 0x39, 0xC2]); // cmp rdx, rax
+        code.jump(&[0x0F, 0x8E], self.overflow); // jle bf_overflow
+        code.op(&[0xC3]); // ret
+
+        code.bind(self.check_left);
+        code.op(&[0x4C, 0x89, 0xE2]); // mov rdx, r12
+        code.op(&[0x4C, 0x29, 0xEA]); // sub rdx, r13
+        code.op(&[0x48, 0x39, 0xC2]); // cmp rdx, rax
+        code.jump(&[0x0F, 0x8C], self.underflow); // jl bf_underflow
+        code.op(&[0x48, 0xF7, 0xD8]); // neg rax
+        code.op(&[0xC3]); // ret
+    }
+
+    /// Emits what a failed check jumps to, which loads the message for the error into `rsi` and
+    /// its length into `rdx`, and jumps to `fail`.
+    pub(crate) fn emit_errors(&self, code: &mut Code, fail: Label) {
+        let mut messages = Vec::new();
+        for (label, error) in [
+            (self.underflow, Error::PointerUnderflow),
+            (self.overflow, Error::PointerOverflow),
+        ] {
+            let message = format!("runtime error: {}.\n", error).into_bytes();
+            let text = code.new_label();
+            code.bind(label);
+            code.jump(&[0x48, 0x8D, 0x35], text); // lea rsi, [rel message]
+            code.op(&[0xBA]); // mov edx, len
+            code.imm32(message.len() as u32);
+            code.jump(&[0xE9], fail); // jmp fail
+            messages.push((text, message));
+        }
+        for (label, message) in messages {
+            code.bind(label);
+            code.op(&message);
+        }
+    }
+}
+
+/// Emits the program, and returns the strings that it writes, each with the label that it needs
+/// to be bound to.
+pub(crate) fn compile<'a>(
+    code: &mut Code,
+    runtime: &Runtime,
+    program: &'a peephole::Program,
+) -> Vec<(Label, &'a [u8])> {
+    let mut strings = Vec::new();
+    compile_into(code, runtime, &mut strings, program);
+    strings
+}
+
+fn compile_into<'a>(
+    code: &mut Code,
+    runtime: &Runtime,
+    strings: &mut Vec<(Label, &'a [u8])>,
+    program: &'a [peephole::Statement],
+) {
+    use crate::common::Instruction::*;
+    use peephole::Statement::*;
+
+    for stm in program {
+        let instruction = match *stm {
+            Instr(ref instruction) => instruction,
+            Loop(ref body) => {
+                let (begin, end) = (code.new_label(), code.new_label());
+                code.jump(&[0xE9], end); // jmp end
+                code.bind(begin);
+                compile_into(code, runtime, strings, body);
+                code.bind(end);
+                code.op(&[0x41, 0x80, 0x3C, 0x24, 0x00]); // cmp byte [r12], 0
+                code.jump(&[0x0F, 0x85], begin); // jnz begin
+                continue;
+            }
+        };
+
+        match *instruction {
+            Right(count) => {
+                load_rax(code, count as u64);
+                code.jump(&[0xE8], runtime.right); // call bf_right
+            }
+
+            Left(count) => {
+                load_rax(code, count as u64);
+                code.jump(&[0xE8], runtime.left); // call bf_left
+            }
+
+            Add(count) => code.op(&[0x41, 0x80, 0x04, 0x24, count]), // add byte [r12], count
+
+            In => code.jump(&[0xE8], runtime.read), // call bf_read
+
+            Out => code.jump(&[0xE8], runtime.out), // call bf_out
+
+            WriteStr(ref bytes) => {
+                let label = code.new_label();
+                strings.push((label, bytes));
+                code.jump(&[0x48, 0x8D, 0x35], label); // lea rsi, [rel label]
+                code.op(&[0xBA]); // mov edx, len
+                code.imm32(bytes.len() as u32);
+                code.jump(&[0xE8], runtime.write); // call bf_write
+            }
+
+            // The program runs with a single tape, where switching does nothing.
+            SwitchTape(_) => (),
+
+            SetZero => code.op(&[0x41, 0xC6, 0x04, 0x24, 0]), // mov byte [r12], 0
+
+            SetConst(value) => code.op(&[0x41, 0xC6, 0x04, 0x24, value]), // mov byte [r12], value
+
+            FindZeroRight(skip) => find_zero(code, skip as u64, runtime.right),
+
+            FindZeroLeft(skip) => find_zero(code, skip as u64, runtime.left),
+
+            OffsetAddRight(offset) => {
+                mul_add(code, runtime, offset as isize, 1);
+                code.op(&[0x41, 0xC6, 0x04, 0x24, 0]); // mov byte [r12], 0
+            }
+
+            OffsetAddLeft(offset) => {
+                mul_add(code, runtime, -(offset as isize), 1);
+                code.op(&[0x41, 0xC6, 0x04, 0x24, 0]); // mov byte [r12], 0
+            }
+
+            MulAddRight(offset, factor) => mul_add(code, runtime, offset as isize, factor),
+
+            MulAddLeft(offset, factor) => mul_add(code, runtime, -(offset as isize), factor),
+
+            AddAt(offset, value) => {
+                check_offset(code, runtime, offset);
+                code.op(&[0x41, 0x80, 0x04, 0x04, value]); // add byte [r12 + rax], value
+            }
+
+            SetAt(offset, value) => {
+                check_offset(code, runtime, offset);
+                code.op(&[0x41, 0xC6, 0x04, 0x04, value]); // mov byte [r12 + rax], value
+            }
+
+            JumpZero(_) | JumpNotZero(_) => panic!("unexpected jump instruction"),
+        }
+    }
+}
+
+/// Moves by steps of `skip` with `step`, `bf_right` or `bf_left`, until the cell is zero.
+fn find_zero(code: &mut Code, skip: u64, step: Label) {
+    let (begin, end) = (code.new_label(), code.new_label());
+    code.jump(&[0xE9], end); // jmp end
+    code.bind(begin);
+    load_rax(code, skip);
+    code.jump(&[0xE8], step); // call step
+    code.bind(end);
+    code.op(&[0x41, 0x80, 0x3C, 0x24, 0x00]); // cmp byte [r12], 0
+    code.jump(&[0x0F, 0x85], begin); // jnz begin
+}
+
+/// Adds the cell at the pointer times `factor` to the cell at `offset` from it, unless the cell
+/// is zero.
+fn mul_add(code: &mut Code, runtime: &Runtime, offset: isize, factor: u8) {
+    let skip = code.new_label();
+    code.op(&[0x41, 0x0F, 0xB6, 0x0C, 0x24]); // movzx ecx, byte [r12]
+    code.op(&[0x85, 0xC9]); // test ecx, ecx
+    code.jump(&[0x0F, 0x84], skip); // jz skip
+    check_offset(code, runtime, offset);
+    if factor != 1 {
+        code.op(&[0x69, 0xC9]); // imul ecx, ecx, factor
+        code.imm32(factor.into());
+    }
+    code.op(&[0x41, 0x00, 0x0C, 0x04]); // add byte [r12 + rax], cl
+    code.bind(skip);
+}
+
+/// Checks that the pointer has a cell at `offset` from it, and leaves the offset in `rax`.
+fn check_offset(code: &mut Code, runtime: &Runtime, offset: isize) {
+    load_rax(code, offset.unsigned_abs() as u64);
+    if offset < 0 {
+        code.jump(&[0xE8], runtime.check_left); // call bf_check_left
+    } else {
+        code.jump(&[0xE8], runtime.check_right); // call bf_check_right
+    }
+}
+
+/// Loads `value` into `rax`, in five bytes if it fits in 32 bits.
+fn load_rax(code: &mut Code, value: u64) {
+    match u32::try_from(value) {
+        Ok(value) => {
+            code.op(&[0xB8]); // mov eax, value
+            code.imm32(value);
+        }
+        Err(_) => {
+            code.op(&[0x48, 0xB8]); // mov rax, value
+            code.imm64(value);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! Runs the programs in `corpus/` on every backend and compares their output to what is expected.
+//!
+//! Each program `name.b` comes with `name.out`, its expected output, and, if it reads input,
+//! `name.in`. The comment at the top of each program says what it does and what it needs, such as
+//! what `,` must store at the end of input.
+
+extern crate bf;
+
+use bf::ast;
+use bf::conformance;
+use bf::semantics::Eof;
+use bf::state::State;
+use bf::test_helpers::*;
+
+/// Runs a program with the given input and end-of-input behavior on every backend, and checks
+/// that they agree and print the expected output.
+fn assert_corpus(src: &[u8], input: &[u8], eof: Eof, expected: &[u8]) {
+    let program = ast::parse_program(src).unwrap();
+    let mut state = State::default();
+    state.set_eof(eof);
+
+    let outcome =
+        conformance::check_in(&program, &state, input).unwrap_or_else(|m| panic!("{}", m));
+    assert_eq!(outcome.result, Ok(()));
+    assert!(
+        outcome.output == expected,
+        "expected:\n{}\nactual:\n{}",
+        String::from_utf8_lossy(expected),
+        String::from_utf8_lossy(&outcome.output)
+    );
+}
+
+macro_rules! corpus_test {
+    ($test:ident, $name:literal) => {
+        corpus_test!($test, $name, b"", Eof::Zero);
+    };
+    ($test:ident, $name:literal, input, $eof:expr) => {
+        corpus_test!(
+            $test,
+            $name,
+            include_bytes!(concat!("corpus/", $name, ".in")),
+            $eof
+        );
+    };
+    ($test:ident, $name:literal, $input:expr, $eof:expr) => {
+        #[test]
+        fn $test() {
+            assert_corpus(
+                include_bytes!(concat!("corpus/", $name, ".b")),
+                $input,
+                $eof,
+                include_bytes!(concat!("corpus/", $name, ".out")),
+            );
+        }
+    };
+}
+
+corpus_test!(hanoi, "hanoi");
+corpus_test!(sierpinski, "sierpinski");
+corpus_test!(rot13, "rot13", input, Eof::Unchanged);
+corpus_test!(rot13_minus_one, "rot13", input, Eof::MinusOne);
+corpus_test!(wc, "wc", input, Eof::Zero);
+
+#[test]
+#[ignore = "takes minutes in a debug build; run with `cargo test --release -- --ignored`"]
+fn mandelbrot() {
+    assert_corpus(
+        MANDELBROT_SRC,
+        b"",
+        Eof::Zero,
+        include_bytes!("../bf/mandelbrot.out"),
+    );
+}
@@ -0,0 +1,96 @@
+Prints the moves that solve the Towers of Hanoi with five disks
+moving them all from peg A to peg C
+
++++++++++++++++++++++++++++++++[>[->>>>>>>>>>+>>+<<<<<<<<<<<<]>>>>>>>>>>
+>>[-<<<<<<<<<<<<+>>>>>>>>>>>>]<+<[[-]>-<<<<<<<<<<<[-]>[->>>>>>>>>>>+>>+<
+<<<<<<<<<<<<]>>>>>>>>>>>>>[-<<<<<<<<<<<<<+>>>>>>>>>>>>>]<+<[[-]>-<<<<<<<
+<<<<<[-]>[->>>>>>>>>>>>+>>+<<<<<<<<<<<<<<]>>>>>>>>>>>>>>[-<<<<<<<<<<<<<<
++>>>>>>>>>>>>>>]<+<[[-]>-<<<<<<<<<<<<<[-]>[->>>>>>>>>>>>>+>>+<<<<<<<<<<<
+<<<<]>>>>>>>>>>>>>>>[-<<<<<<<<<<<<<<<+>>>>>>>>>>>>>>>]<+<[[-]>-<<<<<<<<<
+<<<<<[-]>+>>>>>>>>>>>>>>++++++++++++++++++++++++++++++++++++++++++++++++
++++++++++++++++++++++++++++++.++++++++++++++++++++++++++++++++++.+++++++
+.-----------------.-----------------------------------------------------
+----------------.+++++++++++++++++++++++++++++++++++++++++++++++++++++++
++++++++++++++.+++++.++++++++++.--------.--------------------------------
+-------------------------------------------.+++++++++++++++++++++.------
+---------------.++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++.++++++++++++.---.--.-------------------------------------
+----------------------------------------.[-]<<<<<<<<<[->>>>>>>>>+>+<<<<<
+<<<<<]>>>>>>>>>>[-<<<<<<<<<<+>>>>>>>>>>]<+++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++.[-]<<<<<<<<<[->>>>>>>>>+>>+<<<<<<<<<<
+<]>>>>>>>>>>>[-<<<<<<<<<<<+>>>>>>>>>>>]<<>+<[[-]>-<]>[-<<<<<<<<<<+++>>>>
+>>>>>>]<<<<<<<<<<->>>>>>>>>++++++++++++++++++++++++++++++++.++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+.-----.-----------------------------------------------------------------
+--------------.[-]<<<<<<<<<[->>>>>>>>>+>+<<<<<<<<<<]>>>>>>>>>>[-<<<<<<<<
+<<+>>>>>>>>>>]<+++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++.[-]++++++++++.[-]<<]>[-<<<<<<<<<<<<<<+>>>>>>>>>>>>>>>++++++++++
++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++.++++
+++++++++++++++++++++++++++++++.+++++++.-----------------.---------------
+------------------------------------------------------.+++++++++++++++++
++++++++++++++++++++++++++++++++++++++++++++++++++++.+++++.++++++++++.---
+-----.------------------------------------------------------------------
+---------.++++++++++++++++++++.--------------------.++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++.++++++++++++.---.--.-
+------------------------------------------------------------------------
+----.[-]<<<<<<<<<<[->>>>>>>>>>+>+<<<<<<<<<<<]>>>>>>>>>>>[-<<<<<<<<<<<+>>
+>>>>>>>>>]<+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++.[-]<<<<<<<<<<+[->>>>>>>>>>+>>+<<<<<<<<<<<<]>>>>>>>>>>>>[-<<<<<<<<<<
+<<+>>>>>>>>>>>>]<<--->+<[[-]>-<]>[-<<<<<<<<<<<[-]>>>>>>>>>>>]<++++++++++
+++++++++++++++++++++++.+++++++++++++++++++++++++++++++++++++++++++++++++
++++++++++++++++++++++++++++++++++++.-----.------------------------------
+-------------------------------------------------.[-]<<<<<<<<<<[->>>>>>>
+>>>+>+<<<<<<<<<<<]>>>>>>>>>>>[-<<<<<<<<<<<+>>>>>>>>>>>]<++++++++++++++++
++++++++++++++++++++++++++++++++++++++++++++++++++.[-]++++++++++.[-]<]<<<
+]>[-<<<<<<<<<<<<<+>>>>>>>>>>>>>>++++++++++++++++++++++++++++++++++++++++
++++++++++++++++++++++++++++++++++++++.++++++++++++++++++++++++++++++++++
+.+++++++.-----------------.---------------------------------------------
+------------------------.+++++++++++++++++++++++++++++++++++++++++++++++
++++++++++++++++++++++.+++++.++++++++++.--------.------------------------
+---------------------------------------------------.+++++++++++++++++++.
+-------------------.++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++.++++++++++++.---.--.---------------------------------
+--------------------------------------------.[-]<<<<<<<<<[->>>>>>>>>+>+<
+<<<<<<<<<]>>>>>>>>>>[-<<<<<<<<<<+>>>>>>>>>>]<+++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++.[-]<<<<<<<<<[->>>>>>>>>+>>+<<<<<<
+<<<<<]>>>>>>>>>>>[-<<<<<<<<<<<+>>>>>>>>>>>]<<>+<[[-]>-<]>[-<<<<<<<<<<+++
+>>>>>>>>>>]<<<<<<<<<<->>>>>>>>>++++++++++++++++++++++++++++++++.++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++.-----.-------------------------------------------------------------
+------------------.[-]<<<<<<<<<[->>>>>>>>>+>+<<<<<<<<<<]>>>>>>>>>>[-<<<<
+<<<<<<+>>>>>>>>>>]<+++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++.[-]++++++++++.[-]<]<<<]>[-<<<<<<<<<<<<+>>>>>>>>>>>>>+++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++.+
++++++++++++++++++++++++++++++++++.+++++++.-----------------.------------
+---------------------------------------------------------.++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++.+++++.++++++++++.
+--------.---------------------------------------------------------------
+------------.++++++++++++++++++.------------------.+++++++++++++++++++++
++++++++++++++++++++++++++++++++++++++++++++++++++.++++++++++++.---.--.--
+------------------------------------------------------------------------
+---.[-]<<<<<<<<[->>>>>>>>+>+<<<<<<<<<]>>>>>>>>>[-<<<<<<<<<+>>>>>>>>>]<++
++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++.[-]<<<<<
+<<<+[->>>>>>>>+>>+<<<<<<<<<<]>>>>>>>>>>[-<<<<<<<<<<+>>>>>>>>>>]<<--->+<[
+[-]>-<]>[-<<<<<<<<<[-]>>>>>>>>>]<++++++++++++++++++++++++++++++++.++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++.-----.-----------------------------------------------------------
+--------------------.[-]<<<<<<<<[->>>>>>>>+>+<<<<<<<<<]>>>>>>>>>[-<<<<<<
+<<<+>>>>>>>>>]<+++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++.[-]++++++++++.[-]<]<<<]>[-<<<<<<<<<<<+>>>>>>>>>>>>+++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++.+++++++
++++++++++++++++++++++++++++.+++++++.-----------------.------------------
+---------------------------------------------------.++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++.+++++.++++++++++.------
+--.---------------------------------------------------------------------
+------.+++++++++++++++++.-----------------.+++++++++++++++++++++++++++++
++++++++++++++++++++++++++++++++++++++++++.++++++++++++.---.--.----------
+-------------------------------------------------------------------.[-]<
+<<<<<<[->>>>>>>+>+<<<<<<<<]>>>>>>>>[-<<<<<<<<+>>>>>>>>]<++++++++++++++++
++++++++++++++++++++++++++++++++++++++++++++++++++.[-]<<<<<<<[->>>>>>>+>>
++<<<<<<<<<]>>>>>>>>>[-<<<<<<<<<+>>>>>>>>>]<<>+<[[-]>-<]>[-<<<<<<<<+++>>>
+>>>>>]<<<<<<<<->>>>>>>++++++++++++++++++++++++++++++++.+++++++++++++++++
++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++.----
+-.----------------------------------------------------------------------
+---------.[-]<<<<<<<[->>>>>>>+>+<<<<<<<<]>>>>>>>>[-<<<<<<<<+>>>>>>>>]<++
++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++.[-]+++++
++++++.[-]<]<<<<<<<<<<<<-]
@@ -0,0 +1,7 @@
+Applies ROT13 to its input
+which must not contain a zero byte
+until the end of input
+which must leave the cell unchanged or store 255
+from the Brainfuck article on Wikipedia
+
+-,+[-[>>++++[>++++++++<-]<+<-[>+>+>-[>>>]<[[>+<-]>>+>]<<<<<-]]>>>[-]+>--[-[<->+++[-]]]<[++++++++++++<[>-[>+>>]>[+[<+>-]>+>>]<<<<<-]>>[<+>-]>[-[-<<[-]>>]<<[<<->>-]>>]<<[<<+>>-]]<[-]<.[-]<-,+]
@@ -0,0 +1,4 @@
+Prints a Sierpinski triangle of 32 rows
+by Daniel B Cristofani
+
+++++++++[>+>++++<<-]>++>>+<[-[>>+<<-]+>>]>+[-<<<[->[+[-]+>++>>>-<<]<[<]>>++++++[<<+++++>>-]+<<++.[-]<<]>.>+[>>]>+]
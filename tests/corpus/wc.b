@@ -0,0 +1,47 @@
+Counts the lines and words and bytes of its input and prints them in decimal
+separated by spaces as wc does but without padding
+A word is a run of characters other than space and tab and newline
+The input must not contain a zero byte and each count must be at most 999
+
+,[>>>>>>>>>+[->>>+>>+<<<<<]>>>>>[-<<<<<+>>>>>]<<---------->+<[[-]>-<]>[-
+<<<<[-]>+[->>>>+>>+<<<<<<]>>>>>>[-<<<<<<+>>>>>>]<<---------->+<[[-]>-<]>
+[-<<<<<[-]>+>>>>]<<]<<<<<<<<<<<<<[->>>>>>>>>>>>+>>+<<<<<<<<<<<<<<]>>>>>>
+>>>>>>>>[-<<<<<<<<<<<<<<+>>>>>>>>>>>>>>]<<---------->+<[[-]>-<]>[-<<<<<<
+<<<<<+>+[->>>>>>>>>>>+>>+<<<<<<<<<<<<<]>>>>>>>>>>>>>[-<<<<<<<<<<<<<+>>>>
+>>>>>>>>>]<<---------->+<[[-]>-<]>[-<<<<<<<<<<<<[-]>+[->>>>>>>>>>>>+>>+<
+<<<<<<<<<<<<<]>>>>>>>>>>>>>>[-<<<<<<<<<<<<<<+>>>>>>>>>>>>>>]<<----------
+>+<[[-]>-<]>[-<<<<<<<<<<<<<[-]>+>>>>>>>>>>>>]<<]<<]<<<<<<<<<<<<<[->>>>>>
+>>>>>>+>>+<<<<<<<<<<<<<<]>>>>>>>>>>>>>>[-<<<<<<<<<<<<<<+>>>>>>>>>>>>>>]<
+<-------------------------------->+<[[-]>-<]>[-<<<<<<<<<<<+>>>>>>>>>>>]<
+<<<<<<<<<<<<[->>>>>>>>>>>>+>>+<<<<<<<<<<<<<<]>>>>>>>>>>>>>>[-<<<<<<<<<<<
+<<<+>>>>>>>>>>>>>>]<<--------->+<[[-]>-<]>[-<<<<<<<<<<<+>>>>>>>>>>>]<<<<
+<<<<<<<[->>>>>>>>>>+>>+<<<<<<<<<<<<]>>>>>>>>>>>>[-<<<<<<<<<<<<+>>>>>>>>>
+>>>]<+<[[-]>-<<<<<<<<<<<<[-]>>>>>>>>>>>]>[-<<<<<<<<<<<<[->>>>>>>>>>>>>+>
+>+<<<<<<<<<<<<<<<]>>>>>>>>>>>>>>>[-<<<<<<<<<<<<<<<+>>>>>>>>>>>>>>>]<+<[[
+-]>-<]>[-<<<<<<<<<+[->>>>>>>>>>+>>+<<<<<<<<<<<<]>>>>>>>>>>>>[-<<<<<<<<<<
+<<+>>>>>>>>>>>>]<<---------->+<[[-]>-<]>[-<<<<<<<<<<<[-]>+[->>>>>>>>>>>+
+>>+<<<<<<<<<<<<<]>>>>>>>>>>>>>[-<<<<<<<<<<<<<+>>>>>>>>>>>>>]<<----------
+>+<[[-]>-<]>[-<<<<<<<<<<<<[-]>+>>>>>>>>>>>]<<]<<]<<<<<<<<<<<<<<[-]+>>>>>
+>>>>>>>]<<<<<<<<<<<[-]<<[-],]>>>>>[->>>>>>>+>+<<<<<<<<]>>>>>>>>[-<<<<<<<
+<+>>>>>>>>]<[[-]<<<<<<<[->>>>>>>>+>+<<<<<<<<<]>>>>>>>>>[-<<<<<<<<<+>>>>>
+>>>>]<++++++++++++++++++++++++++++++++++++++++++++++++.[-]<]<<<<<<<[->>>
+>>>>>+>+<<<<<<<<<]>>>>>>>>>[-<<<<<<<<<+>>>>>>>>>]<[[-]<[-]+>]<<<<<<<<<[-
+>>>>>>>>>+>+<<<<<<<<<<]>>>>>>>>>>[-<<<<<<<<<<+>>>>>>>>>>]<[[-]<[-]+>]<[-
+>+>+<<]>>[-<<+>>]<[[-]<<<<<<<<<[->>>>>>>>>>+>+<<<<<<<<<<<]>>>>>>>>>>>[-<
+<<<<<<<<<<+>>>>>>>>>>>]<++++++++++++++++++++++++++++++++++++++++++++++++
+.[-]<]<[-]<<<<<<<<<[->>>>>>>>>+>+<<<<<<<<<<]>>>>>>>>>>[-<<<<<<<<<<+>>>>>
+>>>>>]<++++++++++++++++++++++++++++++++++++++++++++++++.[-]+++++++++++++
++++++++++++++++++++.[-]<<<<[->>>>+>+<<<<<]>>>>>[-<<<<<+>>>>>]<[[-]<<<<[-
+>>>>>+>+<<<<<<]>>>>>>[-<<<<<<+>>>>>>]<++++++++++++++++++++++++++++++++++
+++++++++++++++.[-]<]<<<<[->>>>>+>+<<<<<<]>>>>>>[-<<<<<<+>>>>>>]<[[-]<[-]
++>]<<<<<<[->>>>>>+>+<<<<<<<]>>>>>>>[-<<<<<<<+>>>>>>>]<[[-]<[-]+>]<[->+>+
+<<]>>[-<<+>>]<[[-]<<<<<<[->>>>>>>+>+<<<<<<<<]>>>>>>>>[-<<<<<<<<+>>>>>>>>
+]<++++++++++++++++++++++++++++++++++++++++++++++++.[-]<]<[-]<<<<<<[->>>>
+>>+>+<<<<<<<]>>>>>>>[-<<<<<<<+>>>>>>>]<+++++++++++++++++++++++++++++++++
++++++++++++++++.[-]++++++++++++++++++++++++++++++++.[-]<[->+>+<<]>>[-<<+
+>>]<[[-]<[->>+>+<<<]>>>[-<<<+>>>]<++++++++++++++++++++++++++++++++++++++
+++++++++++.[-]<]<[->>+>+<<<]>>>[-<<<+>>>]<[[-]<[-]+>]<<<[->>>+>+<<<<]>>>
+>[-<<<<+>>>>]<[[-]<[-]+>]<[->+>+<<]>>[-<<+>>]<[[-]<<<[->>>>+>+<<<<<]>>>>
+>[-<<<<<+>>>>>]<++++++++++++++++++++++++++++++++++++++++++++++++.[-]<]<[
+-]<<<[->>>+>+<<<<]>>>>[-<<<<+>>>>]<+++++++++++++++++++++++++++++++++++++
++++++++++++.[-]++++++++++.[-]
@@ -0,0 +1,38 @@
+//! Runs the dbfi self-interpreter, which depends on the conventions of
+//! [`Semantics::dbfi`](../bf/semantics/struct.Semantics.html#method.dbfi), on every backend.
+
+extern crate bf;
+
+use bf::ast;
+use bf::conformance::{self, Backend};
+use bf::semantics::Semantics;
+use bf::test_helpers::*;
+
+#[test]
+fn dbfi_hello_world_on_every_backend() {
+    let semantics = Semantics::dbfi();
+    let program = ast::parse_program(DBFI_SRC).unwrap();
+    let mut input = HELLO_WORLD_SRC.to_vec();
+    input.push(b'!');
+
+    for backend in Backend::all() {
+        let outcome = backend.run_in(&program, &mut semantics.state(None), &input);
+        assert_eq!(outcome.result, Ok(()), "{}", backend);
+        assert_eq!(outcome.output, b"Hello, World!", "{}", backend);
+    }
+}
+
+#[test]
+fn dbfi_passes_input_through() {
+    // The interpreted program is `,.,.,.`, which copies three bytes of input. The source follows
+    // the `!`-input convention, so it carries both the program and its input after dbfi itself.
+    let mut src = DBFI_SRC.to_vec();
+    src.extend_from_slice(b"!,.,.,.!abc");
+    let semantics = Semantics::dbfi();
+    let (src, input) = semantics.split_source(&src);
+    let program = ast::parse_program(src).unwrap();
+
+    let outcome = conformance::check_in(&program, &semantics.state(None), input)
+        .unwrap_or_else(|m| panic!("{}", m));
+    assert_eq!(outcome.output, b"abc");
+}
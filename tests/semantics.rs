@@ -0,0 +1,137 @@
+//! Checks the documented behavior of each [`Semantics`](../bf/semantics/struct.Semantics.html)
+//! option on every backend: what `,` stores at the end of input, which programs that copy input
+//! stop under which convention, and which cell sizes are supported.
+
+extern crate bf;
+
+use bf::ast;
+use bf::common::Error;
+use bf::conformance;
+use bf::sandbox::{SandboxConfig, SandboxInterpretable};
+use bf::semantics::{Eof, Overflow, Semantics};
+use bf::test_helpers::*;
+
+/// The input for the programs that copy or transform it.
+const INPUT: &[u8] = b"Hello, World!\nABCXYZ abcxyz 0123\n";
+
+/// Steps after which a program that copies input is taken not to stop.
+const MAX_STEPS: u64 = 100_000;
+
+/// Runs a program under the given end-of-input behavior, checking that every backend stops with
+/// the given output, or that the reference doesn't stop within `MAX_STEPS` if `expected` is
+/// `None`.
+fn assert_eof(src: &[u8], eof: Eof, input: &[u8], expected: Option<&[u8]>) {
+    let program = ast::parse_program(src).unwrap();
+    let state = Semantics {
+        eof,
+        ..Semantics::default()
+    }
+    .state(None);
+
+    match expected {
+        Some(expected) => {
+            let outcome = conformance::check_in(&program, &state, input)
+                .unwrap_or_else(|m| panic!("eof {}: {}", eof, m));
+            assert_eq!(outcome.result, Ok(()), "eof {}", eof);
+            assert_eq!(outcome.output, expected, "eof {}", eof);
+        }
+        None => {
+            let config = SandboxConfig {
+                max_steps: Some(MAX_STEPS),
+                ..SandboxConfig::default()
+            };
+            let result =
+                program.interpret_sandboxed(&mut state.clone(), input, Vec::new(), &config);
+            assert_eq!(result, Err(Error::StepLimit), "eof {}", eof);
+        }
+    }
+}
+
+fn rot13(input: &[u8]) -> Vec<u8> {
+    input
+        .iter()
+        .map(|&byte| match byte {
+            b'a'..=b'z' => (byte - b'a' + 13) % 26 + b'a',
+            b'A'..=b'Z' => (byte - b'A' + 13) % 26 + b'A',
+            _ => byte,
+        })
+        .collect()
+}
+
+#[test]
+fn eof_stores_what_it_says() {
+    for (eof, expected) in [(Eof::Zero, 0), (Eof::Unchanged, 3), (Eof::MinusOne, 255)] {
+        assert_eof(b"+++,.,.", eof, b"", Some(&[expected, expected]));
+        // Input that hasn't run out is read the same way under every convention.
+        assert_eof(b"+++,.", eof, b"a", Some(b"a"));
+    }
+}
+
+#[test]
+fn cat_stops_under_its_convention() {
+    use Eof::*;
+
+    // Each program copies its input under the conventions listed with it, and under any other
+    // keeps going once the input runs out.
+    let cases: [(&[u8], &[Eof]); 3] = [
+        (b",[.,]", &[Zero]),
+        (b",+[-.,+]", &[MinusOne]),
+        (b"[-],[.[-],]", &[Zero, Unchanged]),
+    ];
+    for (src, stops) in cases {
+        for eof in Eof::ALL {
+            let expected = if stops.contains(&eof) {
+                Some(INPUT)
+            } else {
+                None
+            };
+            assert_eof(src, eof, INPUT, expected);
+        }
+    }
+}
+
+#[test]
+fn rot13_stops_unless_eof_is_zero() {
+    let src = include_bytes!("corpus/rot13.b");
+    let expected = rot13(INPUT);
+    assert_eof(src, Eof::Zero, INPUT, None);
+    assert_eof(src, Eof::Unchanged, INPUT, Some(&expected));
+    assert_eof(src, Eof::MinusOne, INPUT, Some(&expected));
+}
+
+#[test]
+fn quine_prints_itself_under_every_convention() {
+    for eof in Eof::ALL {
+        assert_eof(QUINE_SRC, eof, b"", Some(QUINE_SRC));
+    }
+}
+
+#[test]
+fn only_8_bit_cells_are_supported() {
+    for cell_bits in [1, 7, 16, 32] {
+        let semantics = Semantics {
+            cell_bits,
+            ..Semantics::default()
+        };
+        assert_eq!(
+            semantics.check(),
+            Err(format!("{}-bit cells are not supported", cell_bits))
+        );
+    }
+    let saturating = Semantics {
+        overflow: Overflow::Saturate,
+        ..Semantics::default()
+    };
+    assert_eq!(saturating.check(), Ok(()));
+    for profile in Semantics::PROFILES {
+        assert_eq!(Semantics::by_name(profile).unwrap().check(), Ok(()));
+    }
+
+    // With 8-bit wrapping cells, 0 - 1 is 255, and 256 additions come back to 0.
+    let mut src = b"-.+.".to_vec();
+    src.extend_from_slice(&[b'+'; 256]);
+    src.push(b'.');
+    for eof in Eof::ALL {
+        assert_eof(&src, eof, b"", Some(&[255, 0, 0]));
+    }
+}
@@ -0,0 +1,72 @@
+//! Runs `bfi serve` against clients that misbehave, checking that the server keeps serving the
+//! ones that don't.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+/// A running `bfi serve`, which is killed when the test ends, even if it fails.
+struct Server {
+    child: Child,
+    address: String,
+}
+
+impl Server {
+    /// Starts serving `src` on a free port, with the given options.
+    fn start(src: &str, options: &[&str]) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_bfi"))
+            .args(["serve", "--listen", "127.0.0.1:0", "-e", src])
+            .args(options)
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(child.stderr.as_mut().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let address = line
+            .trim_end()
+            .strip_prefix("bfi: listening on ")
+            .and_then(|rest| rest.strip_suffix('.'))
+            .unwrap_or_else(|| panic!("unexpected first line: {:?}", line))
+            .to_owned();
+        Server { child, address }
+    }
+
+    /// Connects, sends `input`, and returns everything that the server sends back.
+    fn request(&self, input: &[u8]) -> Vec<u8> {
+        let mut stream = TcpStream::connect(&self.address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(30)))
+            .unwrap();
+        stream.write_all(input).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut output = Vec::new();
+        stream.read_to_end(&mut output).unwrap();
+        output
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn serves_the_next_client_after_one_stops_reading() {
+    // Prints `A`, and then the first byte of input forever, unless there is none.
+    let server = Server::start(
+        "++++++++[>++++++++<-]>+.,[.]",
+        &["--workers", "1", "--timeout", "1"],
+    );
+
+    // The only worker runs this client's program, which fills the socket while it isn't read.
+    let mut stuck = TcpStream::connect(&server.address).unwrap();
+    stuck.write_all(b"x").unwrap();
+
+    assert_eq!(server.request(b""), b"A");
+    drop(stuck);
+}